@@ -0,0 +1,51 @@
+//! How the time of a loop over texts grows with the number of texts alive. Every instruction that touches a text looks
+//! it up in the special storage, so with a lookup that depends on the number of items the time would grow
+//! quadratically. Run with `cargo bench`
+
+extern crate birl;
+
+use birl::context::Context;
+
+use std::time::Instant;
+
+/// Split a text with `count` parts, keeping them all alive, and go through them making new texts
+fn run(count : usize) -> f64 {
+    let parts = vec!["BIRL"; count].join(",");
+
+    let src = format!("JAULA SHOW\n\
+                       É HORA DO: DIVIDE TEXTO, \"{}\", \",\"\n\
+                       VEM: PARTES, TREZE\n\
+                       VEM: TOTAL, \"\"\n\
+                       PRA CADA: PARTE, PARTES\n\
+                       BORA: TOTAL, PARTE + \"!\"\n\
+                       FIM\n\
+                       SAINDO DA JAULA", parts);
+
+    let mut ctx = Context::new();
+
+    let result = ctx.call_function_by_id(0, vec![])
+        .and_then(|_| ctx.add_standard_library())
+        .and_then(|_| ctx.add_source_string(src));
+
+    if let Err(e) = result {
+        panic!("{}", e);
+    }
+
+    let start = Instant::now();
+
+    if let Err(e) = ctx.start_program() {
+        panic!("{}", e);
+    }
+
+    start.elapsed().as_secs_f64() * 1000.0
+}
+
+fn main() {
+    println!("{:>8} {:>12} {:>16}", "textos", "tempo (ms)", "por texto (µs)");
+
+    for &count in &[2_000, 4_000, 8_000, 16_000, 32_000] {
+        let ms = run(count);
+
+        println!("{:>8} {:>12.2} {:>16.3}", count, ms, ms * 1000.0 / count as f64);
+    }
+}
@@ -0,0 +1,12 @@
+//! Any bytes as the source, line by line through the parser. It can give errors, but never panic
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate birl;
+
+use birl::parser::parse_bytes;
+
+fuzz_target!(|data : &[u8]| {
+    let _ = parse_bytes(data);
+});
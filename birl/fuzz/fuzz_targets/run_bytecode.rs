@@ -0,0 +1,16 @@
+//! Any bytes as a .birlc file, loaded and run in the playground without the standard library. The bytecode isn't
+//! checked like the source is, so this is what reaches the VM with instructions the compiler would never write. A
+//! corpus that gets past the header comes from programs compiled with `--compila --sem-padrão`
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate birl;
+
+use birl::playground::{ run_bytecode, Limits };
+
+fuzz_target!(|data : &[u8]| {
+    let limits = Limits { fuel : 10_000, time : None, max_output : 4096, standard_library : false, .. Limits::default() };
+
+    let _ = run_bytecode(data, "BIRL\n13\n", &limits);
+});
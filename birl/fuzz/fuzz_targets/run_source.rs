@@ -0,0 +1,15 @@
+//! Any bytes as the source of a program, compiled and run in the playground with few instructions, so the compiler
+//! and the VM get whatever the parser lets through
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate birl;
+
+use birl::playground::{ run, Limits };
+
+fuzz_target!(|data : &[u8]| {
+    let limits = Limits { fuel : 10_000, time : None, max_output : 4096, .. Limits::default() };
+
+    let _ = run(&String::from_utf8_lossy(data), "BIRL\n13\n", &limits);
+});
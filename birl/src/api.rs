@@ -0,0 +1,46 @@
+//! What programs that embed BIRL should use. Everything here follows the version of the crate : it only changes in a
+//! way that breaks code when the major version changes. The rest of the crate (the registers and special storage of the
+//! VM, the instructions, the bytecode, the parser and compiler) is public for the shell and the tools that come with
+//! it, and can change in any version, so the VM can be redesigned without breaking anyone :
+//!
+//! ```
+//! use birl::api::{ Context, DynamicValue, PluginContext, VirtualMachine, CaptureIo, TypeKind };
+//!
+//! fn double(arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+//!     let mut ctx = PluginContext::new(arguments, vm);
+//!     let text = ctx.arg_str(0)?;
+//!
+//!     Ok(Some(ctx.make_text(&text.repeat(2))?))
+//! }
+//!
+//! let io = CaptureIo::new("");
+//! let output = io.output();
+//!
+//! let mut ctx = Context::new();
+//! let _ = ctx.set_io(Box::new(io));
+//!
+//! ctx.call_function_by_id(birl::api::BIRL_GLOBAL_FUNCTION_ID, vec![]).unwrap();
+//! ctx.add_standard_library().unwrap();
+//! ctx.add_plugin("DOBRA".to_owned(), vec![TypeKind::Text], double).unwrap();
+//! ctx.add_source_string("JAULA SHOW\nÉ HORA DO: DOBRA, \"BIRL\"\nCE QUER VER ISSO: TREZE\nSAINDO DA JAULA".to_owned())
+//!     .unwrap();
+//!
+//! ctx.start_program().unwrap();
+//!
+//! assert_eq!(*output.borrow(), "BIRLBIRL\n");
+//! ```
+
+pub use context::{ Context, RawValue, CompileError, BIRL_VERSION, BIRL_COPYRIGHT, BIRL_GLOBAL_FUNCTION_ID,
+                   BIRL_MAIN_FUNCTION_ID, enabled_features };
+pub use program::{ ProgramCompiler, Program, Environment };
+pub use vm::{ VirtualMachine, VirtualMachineBuilder, DynamicValue, PluginFunction, ExecutionStatus, SpecialRef, Steps,
+              HeapItem, IntegerWidth, OverflowPolicy, DecimalSeparator, INSTRUCTION_SET_VERSION };
+pub use plugin::PluginContext;
+// The functions written in BIRL in a module are commands from the parser, so SourceFunction isn't here
+pub use modules::{ Module, Plugin, GlobalVariable, Method };
+pub use vm_io::{ VmIo, StdIo, CaptureIo, NullIo };
+pub use parser::{ TypeKind, IntegerType };
+pub use compiler::{ SymbolTable, FunctionSymbol, FunctionKind, GlobalSymbol, MethodSymbol, Warning };
+pub use diagnostic::{ Diagnostic, Severity, Span };
+pub use error::{ BirlError, ErrorLocation, TraceEntry };
+pub use testing::AssertionFailure;
@@ -0,0 +1,209 @@
+//! Helper for chat bots (Discord, Telegram, etc). Takes the text of a message, and if it's a command to run code, runs
+//! it in the playground with strict limits and gives back the text of the reply. Sending and receiving the messages is
+//! up to the bot
+
+use playground::{ run, Limits, RunResult };
+use diagnostic::Severity;
+
+use std::time::Duration;
+
+/// Prefix of the command used when none is given
+pub const BOT_DEFAULT_PREFIX : &str = "!birl";
+/// Discord doesn't accept messages longer than this (in characters), so it's the default for replies
+pub const BOT_DEFAULT_MAX_REPLY : usize = 2000;
+
+const CODE_FENCE : &str = "```";
+
+/// Limits for code coming from a chat, stricter than the playground defaults since anyone can send it
+pub fn bot_limits() -> Limits {
+    Limits {
+        fuel : 1_000_000,
+        time : Some(Duration::from_secs(2)),
+        max_output : 4 * 1024,
+        max_text_size : 64 * 1024,
+        max_list_size : 4 * 1024,
+        max_special_items : 1024,
+        stack_size : 128,
+        max_call_depth : 256,
+        standard_library : true,
+    }
+}
+
+/// Source and input given in a command
+#[derive(Debug, Clone, PartialEq)]
+pub struct BotCommand {
+    pub source : String,
+    pub input : String,
+}
+
+pub struct BotHandler {
+    prefix : String,
+    limits : Limits,
+    max_reply : usize,
+}
+
+impl BotHandler {
+    pub fn new() -> BotHandler {
+        BotHandler {
+            prefix : BOT_DEFAULT_PREFIX.to_owned(),
+            limits : bot_limits(),
+            max_reply : BOT_DEFAULT_MAX_REPLY,
+        }
+    }
+
+    pub fn set_prefix(&mut self, prefix : String) {
+        self.prefix = prefix;
+    }
+
+    pub fn set_limits(&mut self, limits : Limits) {
+        self.limits = limits;
+    }
+
+    /// Maximum size of a reply, in characters
+    pub fn set_max_reply(&mut self, max : usize) {
+        self.max_reply = max;
+    }
+
+    /// Get the command in a message, if it has one. The code comes after the prefix, either as is or in a code block
+    /// (```), in which case anything after the block is given to the program as input
+    pub fn parse_command(&self, message : &str) -> Option<BotCommand> {
+        let message = message.trim_start();
+
+        if ! message.starts_with(self.prefix.as_str()) {
+            return None;
+        }
+
+        let rest = &message[self.prefix.len()..];
+
+        // "!birlscript" isn't "!birl"
+        if rest.chars().next().map(|c| ! c.is_whitespace()).unwrap_or(false) {
+            return None;
+        }
+
+        let rest = rest.trim();
+
+        if ! rest.starts_with(CODE_FENCE) {
+            return Some(BotCommand { source : rest.to_owned(), input : String::new() });
+        }
+
+        let block = &rest[CODE_FENCE.len()..];
+
+        let (source, input) = match block.find(CODE_FENCE) {
+            Some(end) => (&block[..end], block[end + CODE_FENCE.len()..].trim()),
+            None => (block, ""),
+        };
+
+        // The first line of the block can name the language, like ```birl
+        let source = match source.find('\n') {
+            Some(newline) if ! source[..newline].trim().contains(' ') => &source[newline + 1..],
+            _ => source,
+        };
+
+        Some(BotCommand { source : source.to_owned(), input : input.to_owned() })
+    }
+
+    /// Run the command in the message, if there is one, and give the reply
+    pub fn handle_message(&self, message : &str) -> Option<String> {
+        let command = self.parse_command(message)?;
+
+        if command.source.trim().is_empty() {
+            return Some(format!("Cadê o código, cumpade? Usa assim: {} ```código```", self.prefix));
+        }
+
+        Some(self.format_reply(&run(&command.source, &command.input, &self.limits)))
+    }
+
+    /// Text of the reply for the result of running some code : the output in a code block, followed by the errors
+    pub fn format_reply(&self, result : &RunResult) -> String {
+        let mut reply = String::new();
+
+        if ! result.output.is_empty() {
+            reply.push_str(CODE_FENCE);
+            reply.push('\n');
+            // A fence in the output would end the block early, so it's broken up with a zero width space
+            reply.push_str(&result.output.replace(CODE_FENCE, "`\u{200B}``"));
+
+            if ! result.output.ends_with('\n') {
+                reply.push('\n');
+            }
+
+            reply.push_str(CODE_FENCE);
+            reply.push('\n');
+        }
+
+        for diagnostic in &result.diagnostics {
+            let label = match diagnostic.severity {
+                Severity::Error => "Erro",
+                Severity::Warning => "Aviso",
+            };
+
+            reply.push_str(&format!("**{}** : {}\n", label, diagnostic.message));
+        }
+
+        if reply.is_empty() {
+            reply.push_str("(O programa não imprimiu nada)");
+        }
+
+        self.truncate(reply.trim_end())
+    }
+
+    fn truncate(&self, reply : &str) -> String {
+        if reply.chars().count() <= self.max_reply {
+            return reply.to_owned();
+        }
+
+        const CUT_MARKER : &str = "\n(...)";
+        let keep = self.max_reply.saturating_sub(CUT_MARKER.chars().count());
+
+        let mut truncated : String = reply.chars().take(keep).collect();
+
+        // Don't leave a code block open
+        if truncated.matches(CODE_FENCE).count() % 2 == 1 {
+            let keep = keep.saturating_sub(CODE_FENCE.len() + 1);
+            truncated = reply.chars().take(keep).collect();
+            truncated.push('\n');
+            truncated.push_str(CODE_FENCE);
+        }
+
+        truncated.push_str(CUT_MARKER);
+
+        truncated
+    }
+}
+
+impl Default for BotHandler {
+    fn default() -> BotHandler {
+        BotHandler::new()
+    }
+}
+
+mod tests {
+    #[test]
+    fn bot_commands() {
+        use bot::{ BotHandler, BotCommand };
+
+        let mut bot = BotHandler::new();
+
+        assert_eq!(bot.parse_command("BIRL"), None);
+        assert_eq!(bot.parse_command("!birlscript CE QUER VER: 1"), None);
+        assert_eq!(bot.parse_command("!birl CE QUER VER: 1"),
+                   Some(BotCommand { source : "CE QUER VER: 1".to_owned(), input : String::new() }));
+        assert_eq!(bot.parse_command("!birl ```birl\nFALA AÍ: X\n``` BAMBAM"),
+                   Some(BotCommand { source : "FALA AÍ: X\n".to_owned(), input : "BAMBAM".to_owned() }));
+
+        assert_eq!(bot.handle_message("bora").as_ref(), None);
+        assert_eq!(bot.handle_message("!birl ```\nJAULA SHOW\nCE QUER VER ISSO: \"BIRL\"\nSAINDO DA JAULA\n```").unwrap(),
+                   "```\nBIRL\n```");
+
+        let reply = bot.handle_message("!birl CE QUER VER ISSO: NADA_AQUI").unwrap();
+
+        assert!(reply.starts_with("**Erro** : "));
+
+        bot.set_max_reply(20);
+
+        let reply = bot.handle_message("!birl ```\nJAULA SHOW\nENQUANTO É MEMO: 1, 1\nCE QUER VER ISSO: \"BIRL\"\nFIM\nSAINDO DA JAULA\n```").unwrap();
+
+        assert!(reply.chars().count() <= 20);
+        assert!(reply.ends_with("```\n(...)"));
+    }
+}
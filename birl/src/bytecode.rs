@@ -0,0 +1,735 @@
+//! The `.birlc` files : a program already compiled, so it can be run without parsing and compiling the source again.
+//!
+//! The file starts with a header (the magic bytes, the version of the format and the version of the instruction set),
+//! so a file from another version is refused with a proper error instead of running garbage. Everything after it is
+//! little endian : numbers have a fixed size (integers are always written with 128 bits, so a file works with any
+//! integer width), texts and lists are prefixed by their length and the enums by a byte saying the variant
+
+use vm::{ Instruction, ComparisionRequest, VirtualMachine, INSTRUCTION_SET_VERSION };
+use parser::{ TypeKind, IntegerType };
+use context::RawValue;
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+/// The first bytes of every `.birlc` file
+pub const BYTECODE_MAGIC : &[u8; 5] = b"BIRLC";
+
+/// Version of the layout of the file. Changes when the way things are written changes, while the version of the
+/// instruction set changes when what the instructions mean changes
+pub const BYTECODE_FORMAT_VERSION : u32 = 1;
+
+/// Extension of the compiled files
+pub const BYTECODE_EXTENSION : &str = "birlc";
+
+/// A function of the program, with everything the VM needs to run it and to say where errors happened
+#[derive(Debug, Clone)]
+pub struct CompiledFunction {
+    /// None for the global code and for the main function when it wasn't declared
+    pub name : Option<String>,
+    pub parameters : Vec<TypeKind>,
+    pub code : Vec<Instruction>,
+    /// Variables of the function scope and their addresses
+    pub variables : HashMap<String, usize>,
+    /// The first instruction of each line of the source and the line, as given to `add_source_line`
+    pub source_lines : Vec<(usize, usize)>,
+}
+
+/// A whole program, as stored in a `.birlc` file. The functions are in order of ID (the global code first, then the
+/// main function), since the instructions refer to them by it
+#[derive(Debug, Clone)]
+pub struct CompiledProgram {
+    /// File the source came from, so errors can still point to it
+    pub source_file : Option<String>,
+    /// Names of the plugins the code calls, in order of ID. The program can only run where the same plugins were added
+    /// in the same order
+    pub plugins : Vec<String>,
+    /// Name, address and whether they can be changed, for the global variables known when it was compiled
+    pub globals : Vec<(String, usize, bool)>,
+    pub functions : Vec<CompiledFunction>,
+}
+
+impl CompiledProgram {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer { bytes : BYTECODE_MAGIC.to_vec() };
+
+        writer.u32(BYTECODE_FORMAT_VERSION);
+        writer.u32(INSTRUCTION_SET_VERSION);
+
+        writer.optional_text(&self.source_file);
+
+        writer.usize(self.plugins.len());
+
+        for plugin in &self.plugins {
+            writer.text(plugin);
+        }
+
+        writer.usize(self.globals.len());
+
+        for &(ref name, address, writeable) in &self.globals {
+            writer.text(name);
+            writer.usize(address);
+            writer.bool(writeable);
+        }
+
+        writer.usize(self.functions.len());
+
+        for function in &self.functions {
+            writer.optional_text(&function.name);
+
+            writer.usize(function.parameters.len());
+
+            for &kind in &function.parameters {
+                writer.kind(kind);
+            }
+
+            // Sorted, so the same program always gives the same file
+            let mut variables = function.variables.iter().collect::<Vec<(&String, &usize)>>();
+            variables.sort_by_key(|&(name, &address)| (address, name.clone()));
+
+            writer.usize(variables.len());
+
+            for (name, &address) in variables {
+                writer.text(name);
+                writer.usize(address);
+            }
+
+            writer.usize(function.source_lines.len());
+
+            for &(first, line) in &function.source_lines {
+                writer.usize(first);
+                writer.usize(line);
+            }
+
+            writer.usize(function.code.len());
+
+            for instruction in &function.code {
+                writer.instruction(instruction);
+            }
+        }
+
+        writer.bytes
+    }
+
+    /// Read a program from the contents of a `.birlc` file, refusing files of other versions
+    pub fn from_bytes(bytes : &[u8]) -> Result<CompiledProgram, String> {
+        if ! bytes.starts_with(BYTECODE_MAGIC) {
+            return Err("Isso não é um arquivo .birlc, cumpade".to_owned());
+        }
+
+        let mut reader = Reader { bytes, position : BYTECODE_MAGIC.len() };
+
+        let format_version = reader.u32()?;
+
+        if format_version != BYTECODE_FORMAT_VERSION {
+            return Err(format!("O arquivo foi gerado na versão {} do formato .birlc, mas essa versão do BIRL só lê a versão {}. \
+                                Compila de novo, cumpade", format_version, BYTECODE_FORMAT_VERSION));
+        }
+
+        VirtualMachine::check_instruction_set_version(reader.u32()?)?;
+
+        let source_file = reader.optional_text()?;
+
+        let mut plugins = vec![];
+
+        for _ in 0..reader.usize()? {
+            plugins.push(reader.text()?);
+        }
+
+        let mut globals = vec![];
+
+        for _ in 0..reader.usize()? {
+            globals.push((reader.text()?, reader.usize()?, reader.bool()?));
+        }
+
+        let mut functions = vec![];
+
+        for _ in 0..reader.usize()? {
+            let name = reader.optional_text()?;
+
+            let mut parameters = vec![];
+
+            for _ in 0..reader.usize()? {
+                parameters.push(reader.kind()?);
+            }
+
+            let mut variables = HashMap::new();
+
+            for _ in 0..reader.usize()? {
+                let name = reader.text()?;
+                variables.insert(name, reader.usize()?);
+            }
+
+            let mut source_lines = vec![];
+
+            for _ in 0..reader.usize()? {
+                source_lines.push((reader.usize()?, reader.usize()?));
+            }
+
+            let mut code = vec![];
+
+            for _ in 0..reader.usize()? {
+                code.push(reader.instruction()?);
+            }
+
+            functions.push(CompiledFunction { name, parameters, code, variables, source_lines });
+        }
+
+        if reader.position != bytes.len() {
+            return Err(format!("O arquivo .birlc tem {} bytes sobrando no final", bytes.len() - reader.position));
+        }
+
+        Ok(CompiledProgram { source_file, plugins, globals, functions })
+    }
+}
+
+struct Writer {
+    bytes : Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value : u8) {
+        self.bytes.push(value);
+    }
+
+    fn u32(&mut self, value : u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn usize(&mut self, value : usize) {
+        self.bytes.extend_from_slice(&(value as u64).to_le_bytes());
+    }
+
+    fn bool(&mut self, value : bool) {
+        self.u8(value as u8);
+    }
+
+    fn text(&mut self, value : &str) {
+        self.usize(value.len());
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    fn optional_text(&mut self, value : &Option<String>) {
+        match *value {
+            Some(ref text) => {
+                self.bool(true);
+                self.text(text);
+            }
+            None => self.bool(false),
+        }
+    }
+
+    fn kind(&mut self, kind : TypeKind) {
+        self.u8(match kind {
+            TypeKind::Integer => 0,
+            TypeKind::Number => 1,
+            TypeKind::Text => 2,
+            TypeKind::List => 3,
+            TypeKind::Sequence => 4,
+            TypeKind::File => 5,
+            TypeKind::Database => 6,
+            TypeKind::Bool => 7,
+            TypeKind::Map => 8,
+            TypeKind::Null => 9,
+        });
+    }
+
+    fn comparision(&mut self, request : &ComparisionRequest) {
+        self.u8(match *request {
+            ComparisionRequest::Equal => 0,
+            ComparisionRequest::NotEqual => 1,
+            ComparisionRequest::Less => 2,
+            ComparisionRequest::LessOrEqual => 3,
+            ComparisionRequest::More => 4,
+            ComparisionRequest::MoreOrEqual => 5,
+        });
+    }
+
+    fn value(&mut self, value : &RawValue) {
+        match *value {
+            RawValue::Text(ref text) => {
+                self.u8(0);
+                self.text(text);
+            }
+            RawValue::Integer(i) => {
+                self.u8(1);
+                self.bytes.extend_from_slice(&i128::from(i).to_le_bytes());
+            }
+            RawValue::Number(n) => {
+                self.u8(2);
+                self.bytes.extend_from_slice(&n.to_bits().to_le_bytes());
+            }
+            RawValue::Bool(b) => {
+                self.u8(3);
+                self.bool(b);
+            }
+            RawValue::Null => self.u8(4),
+        }
+    }
+
+    fn instruction(&mut self, instruction : &Instruction) {
+        match *instruction {
+            Instruction::PrintMathB => self.u8(0),
+            Instruction::PrintMathBDebug => self.u8(1),
+            Instruction::PrintNewLine => self.u8(2),
+            Instruction::FlushStdout => self.u8(3),
+            Instruction::Quit => self.u8(4),
+            Instruction::Compare => self.u8(5),
+            Instruction::Return => self.u8(6),
+            Instruction::Jump(target) => {
+                self.u8(7);
+                self.usize(target);
+            }
+            Instruction::JumpIfNot(ref request, target) => {
+                self.u8(8);
+                self.comparision(request);
+                self.usize(target);
+            }
+            Instruction::MakeNewFrame(id) => {
+                self.u8(9);
+                self.usize(id);
+            }
+            Instruction::SetLastFrameReady => self.u8(10),
+            Instruction::AssertMathBCompatible(kind) => {
+                self.u8(11);
+                self.kind(kind);
+            }
+            Instruction::ReadInput => self.u8(12),
+            Instruction::ConvertToString => self.u8(13),
+            Instruction::ConvertToNum => self.u8(14),
+            Instruction::ConvertToInt => self.u8(15),
+            Instruction::PushValMathA(ref value) => {
+                self.u8(16);
+                self.value(value);
+            }
+            Instruction::PushValMathB(ref value) => {
+                self.u8(17);
+                self.value(value);
+            }
+            Instruction::PushIntermediateToA => self.u8(18),
+            Instruction::PushIntermediateToB => self.u8(19),
+            Instruction::PushMathBToSeconday => self.u8(20),
+            Instruction::ClearSecondary => self.u8(21),
+            Instruction::ReadGlobalVarFrom(address) => {
+                self.u8(22);
+                self.usize(address);
+            }
+            Instruction::WriteGlobalVarTo(address) => {
+                self.u8(23);
+                self.usize(address);
+            }
+            Instruction::ReadVarFrom(address) => {
+                self.u8(24);
+                self.usize(address);
+            }
+            Instruction::ReadVarFromFrame(address, ref function) => {
+                self.u8(25);
+                self.usize(address);
+                self.text(function);
+            }
+            Instruction::WriteVarTo(address) => {
+                self.u8(26);
+                self.usize(address);
+            }
+            Instruction::WriteVarToLast(address) => {
+                self.u8(27);
+                self.usize(address);
+            }
+            Instruction::SwapMath => self.u8(28),
+            Instruction::ClearMath => self.u8(29),
+            Instruction::Add => self.u8(30),
+            Instruction::Mul => self.u8(31),
+            Instruction::Div => self.u8(32),
+            Instruction::Mod => self.u8(33),
+            Instruction::Pow => self.u8(34),
+            Instruction::Sub => self.u8(35),
+            Instruction::AddLoopLabel => self.u8(36),
+            Instruction::RestoreLoopLabel => self.u8(37),
+            Instruction::PopLoopLabel => self.u8(38),
+            Instruction::RegisterIncrementOnRestore(address) => {
+                self.u8(39);
+                self.usize(address);
+            }
+            Instruction::MakeNewList => self.u8(40),
+            Instruction::IndexList => self.u8(41),
+            Instruction::AddToListAtIndex => self.u8(42),
+            Instruction::RemoveFromListAtIndex => self.u8(43),
+            Instruction::QueryListSize => self.u8(44),
+            Instruction::MakeNewMap => self.u8(45),
+            Instruction::SetMapEntry => self.u8(46),
+            Instruction::IndexMap => self.u8(47),
+            Instruction::RemoveFromMap => self.u8(48),
+            Instruction::QueryMapSize => self.u8(49),
+            Instruction::ListMapKeys => self.u8(50),
+            Instruction::CallPlugin(address, arguments) => {
+                self.u8(51);
+                self.usize(address);
+                self.usize(arguments);
+            }
+            Instruction::PushMathBPluginArgument => self.u8(52),
+            Instruction::Halt => self.u8(54),
+            Instruction::TryDecrementRefAt(address) => {
+                self.u8(55);
+                self.usize(address);
+            }
+            Instruction::PushMathBToStack => self.u8(56),
+            Instruction::PopStackToMathA => self.u8(57),
+            Instruction::CompareToMathB(ref request) => {
+                self.u8(58);
+                self.comparision(request);
+            }
+            Instruction::Not => self.u8(59),
+            Instruction::CompareTruthiness => self.u8(60),
+            Instruction::SkipIfMathBFalsy(count) => {
+                self.u8(61);
+                self.usize(count);
+            }
+            Instruction::SkipIfMathBTruthy(count) => {
+                self.u8(62);
+                self.usize(count);
+            }
+            Instruction::AssertMathEqual => self.u8(63),
+            Instruction::DispatchMethod(ref method, ref candidates) => {
+                self.u8(64);
+                self.text(method);
+                self.usize(candidates.len());
+
+                for &(kind, id) in candidates {
+                    self.kind(kind);
+                    self.usize(id);
+                }
+            }
+            Instruction::Skip(count) => {
+                self.u8(65);
+                self.usize(count);
+            }
+            Instruction::IterateMathB => self.u8(66),
+            Instruction::NextFromIterator => self.u8(67),
+            Instruction::CompareLoopIndex => self.u8(68),
+            Instruction::PushReturnValue => self.u8(69),
+            Instruction::ReadReturnValue(index) => {
+                self.u8(70);
+                self.usize(index);
+            }
+            Instruction::SortList => self.u8(71),
+            Instruction::TailCall(id) => {
+                self.u8(72);
+                self.usize(id);
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes : &'a [u8],
+    position : usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count : usize) -> Result<&'a [u8], String> {
+        match self.bytes.get(self.position..self.position.saturating_add(count)) {
+            Some(taken) => {
+                self.position += count;
+                Ok(taken)
+            }
+            None => Err("O arquivo .birlc acabou no meio, deve estar cortado".to_owned()),
+        }
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(self.take(4)?);
+
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.take(8)?);
+
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn usize(&mut self) -> Result<usize, String> {
+        let value = self.u64()?;
+
+        usize::try_from(value).map_err(|_| format!("O número {} do arquivo .birlc é grande demais pra esse computador", value))
+    }
+
+    fn bool(&mut self) -> Result<bool, String> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            other => Err(format!("Valor inválido pra um booleano no arquivo .birlc : {}", other)),
+        }
+    }
+
+    fn text(&mut self) -> Result<String, String> {
+        let size = self.usize()?;
+        let bytes = self.take(size)?;
+
+        String::from_utf8(bytes.to_vec()).map_err(|_| "O arquivo .birlc tem um texto que não é UTF-8 válido".to_owned())
+    }
+
+    fn optional_text(&mut self) -> Result<Option<String>, String> {
+        if self.bool()? {
+            Ok(Some(self.text()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn kind(&mut self) -> Result<TypeKind, String> {
+        match self.u8()? {
+            0 => Ok(TypeKind::Integer),
+            1 => Ok(TypeKind::Number),
+            2 => Ok(TypeKind::Text),
+            3 => Ok(TypeKind::List),
+            4 => Ok(TypeKind::Sequence),
+            5 => Ok(TypeKind::File),
+            6 => Ok(TypeKind::Database),
+            7 => Ok(TypeKind::Bool),
+            8 => Ok(TypeKind::Map),
+            9 => Ok(TypeKind::Null),
+            other => Err(format!("Tipo desconhecido no arquivo .birlc : {}", other)),
+        }
+    }
+
+    fn comparision(&mut self) -> Result<ComparisionRequest, String> {
+        match self.u8()? {
+            0 => Ok(ComparisionRequest::Equal),
+            1 => Ok(ComparisionRequest::NotEqual),
+            2 => Ok(ComparisionRequest::Less),
+            3 => Ok(ComparisionRequest::LessOrEqual),
+            4 => Ok(ComparisionRequest::More),
+            5 => Ok(ComparisionRequest::MoreOrEqual),
+            other => Err(format!("Comparação desconhecida no arquivo .birlc : {}", other)),
+        }
+    }
+
+    fn value(&mut self) -> Result<RawValue, String> {
+        match self.u8()? {
+            0 => Ok(RawValue::Text(self.text()?)),
+            1 => {
+                let mut bytes = [0u8; 16];
+                bytes.copy_from_slice(self.take(16)?);
+
+                let value = i128::from_le_bytes(bytes);
+
+                match IntegerType::try_from(value) {
+                    Ok(i) => Ok(RawValue::Integer(i)),
+                    Err(_) => Err(format!("O inteiro {} do arquivo .birlc não cabe nos inteiros dessa versão do BIRL", value)),
+                }
+            }
+            2 => Ok(RawValue::Number(f64::from_bits(self.u64()?))),
+            3 => Ok(RawValue::Bool(self.bool()?)),
+            4 => Ok(RawValue::Null),
+            other => Err(format!("Valor desconhecido no arquivo .birlc : {}", other)),
+        }
+    }
+
+    fn instruction(&mut self) -> Result<Instruction, String> {
+        let instruction = match self.u8()? {
+            0 => Instruction::PrintMathB,
+            1 => Instruction::PrintMathBDebug,
+            2 => Instruction::PrintNewLine,
+            3 => Instruction::FlushStdout,
+            4 => Instruction::Quit,
+            5 => Instruction::Compare,
+            6 => Instruction::Return,
+            7 => Instruction::Jump(self.usize()?),
+            8 => Instruction::JumpIfNot(self.comparision()?, self.usize()?),
+            9 => Instruction::MakeNewFrame(self.usize()?),
+            10 => Instruction::SetLastFrameReady,
+            11 => Instruction::AssertMathBCompatible(self.kind()?),
+            12 => Instruction::ReadInput,
+            13 => Instruction::ConvertToString,
+            14 => Instruction::ConvertToNum,
+            15 => Instruction::ConvertToInt,
+            16 => Instruction::PushValMathA(self.value()?),
+            17 => Instruction::PushValMathB(self.value()?),
+            18 => Instruction::PushIntermediateToA,
+            19 => Instruction::PushIntermediateToB,
+            20 => Instruction::PushMathBToSeconday,
+            21 => Instruction::ClearSecondary,
+            22 => Instruction::ReadGlobalVarFrom(self.usize()?),
+            23 => Instruction::WriteGlobalVarTo(self.usize()?),
+            24 => Instruction::ReadVarFrom(self.usize()?),
+            25 => {
+                let address = self.usize()?;
+                Instruction::ReadVarFromFrame(address, self.text()?)
+            }
+            26 => Instruction::WriteVarTo(self.usize()?),
+            27 => Instruction::WriteVarToLast(self.usize()?),
+            28 => Instruction::SwapMath,
+            29 => Instruction::ClearMath,
+            30 => Instruction::Add,
+            31 => Instruction::Mul,
+            32 => Instruction::Div,
+            33 => Instruction::Mod,
+            34 => Instruction::Pow,
+            35 => Instruction::Sub,
+            36 => Instruction::AddLoopLabel,
+            37 => Instruction::RestoreLoopLabel,
+            38 => Instruction::PopLoopLabel,
+            39 => Instruction::RegisterIncrementOnRestore(self.usize()?),
+            40 => Instruction::MakeNewList,
+            41 => Instruction::IndexList,
+            42 => Instruction::AddToListAtIndex,
+            43 => Instruction::RemoveFromListAtIndex,
+            44 => Instruction::QueryListSize,
+            45 => Instruction::MakeNewMap,
+            46 => Instruction::SetMapEntry,
+            47 => Instruction::IndexMap,
+            48 => Instruction::RemoveFromMap,
+            49 => Instruction::QueryMapSize,
+            50 => Instruction::ListMapKeys,
+            51 => {
+                let address = self.usize()?;
+                Instruction::CallPlugin(address, self.usize()?)
+            }
+            52 => Instruction::PushMathBPluginArgument,
+            54 => Instruction::Halt,
+            55 => Instruction::TryDecrementRefAt(self.usize()?),
+            56 => Instruction::PushMathBToStack,
+            57 => Instruction::PopStackToMathA,
+            58 => Instruction::CompareToMathB(self.comparision()?),
+            59 => Instruction::Not,
+            60 => Instruction::CompareTruthiness,
+            61 => Instruction::SkipIfMathBFalsy(self.usize()?),
+            62 => Instruction::SkipIfMathBTruthy(self.usize()?),
+            63 => Instruction::AssertMathEqual,
+            64 => {
+                let method = self.text()?;
+                let mut candidates = vec![];
+
+                for _ in 0..self.usize()? {
+                    let kind = self.kind()?;
+                    candidates.push((kind, self.usize()?));
+                }
+
+                Instruction::DispatchMethod(method, candidates)
+            }
+            65 => Instruction::Skip(self.usize()?),
+            66 => Instruction::IterateMathB,
+            67 => Instruction::NextFromIterator,
+            68 => Instruction::CompareLoopIndex,
+            69 => Instruction::PushReturnValue,
+            70 => Instruction::ReadReturnValue(self.usize()?),
+            71 => Instruction::SortList,
+            72 => Instruction::TailCall(self.usize()?),
+            other => return Err(format!("Instrução desconhecida no arquivo .birlc : {}", other)),
+        };
+
+        Ok(instruction)
+    }
+}
+
+mod tests {
+    #[test]
+    fn compiled_programs() {
+        use bytecode::{ CompiledProgram, BYTECODE_MAGIC, BYTECODE_FORMAT_VERSION };
+        use context::Context;
+        use vm::INSTRUCTION_SET_VERSION;
+        use vm_io::CaptureIo;
+
+        let src = "VEM: G, 10\n\
+                   JAULA DOBRO (N : TRAPÉZIO DESCENDENTE)\n\
+                   BIRL: N * 2\n\
+                   SAINDO DA JAULA\n\
+                   JAULA SHOW\n\
+                   É HORA DO: DOBRO, G\n\
+                   CE QUER VER ISSO: TREZE, \" \", -1.5, \" \", VERDADE\n\
+                   É HORA DO: TAMANHO DO TEXTO, \"BIRL\"\n\
+                   CE QUER VER ISSO: TREZE\n\
+                   VEM: X, 1 / 0\n\
+                   SAINDO DA JAULA";
+
+        let new_context = |io : CaptureIo| {
+            let mut ctx = Context::new();
+            let _ = ctx.set_io(Box::new(io));
+
+            match ctx.call_function_by_id(0, vec![]).and_then(|_| ctx.add_standard_library()) {
+                Ok(_) => ctx,
+                Err(e) => panic!("{}", e),
+            }
+        };
+
+        let io = CaptureIo::new("");
+        let output = io.output();
+        let mut ctx = new_context(io);
+
+        if let Err(e) = ctx.add_source_string(src.to_owned()) {
+            panic!("{}", e);
+        }
+
+        let bytes = ctx.compiled_program().to_bytes();
+
+        // The same program always gives the same file
+        assert_eq!(ctx.compiled_program().to_bytes(), bytes);
+
+        let source_result = ctx.start_program();
+        let source_output = output.borrow().clone();
+
+        let program = match CompiledProgram::from_bytes(&bytes) {
+            Ok(p) => p,
+            Err(e) => panic!("{}", e),
+        };
+
+        assert_eq!(program.to_bytes(), bytes);
+
+        let io = CaptureIo::new("");
+        let output = io.output();
+        let mut loaded = new_context(io);
+
+        if let Err(e) = loaded.load_program(program.clone()) {
+            panic!("{}", e);
+        }
+
+        assert!(loaded.has_main());
+
+        let loaded_result = loaded.start_program();
+
+        assert_eq!(*output.borrow(), source_output);
+        assert_eq!(source_output, "20 -1.5 VERDADE\n4\n");
+        assert_eq!(loaded_result, source_result);
+        assert!(loaded_result.unwrap_err().message().contains("linha 10, na JAULA SHOW"));
+
+        // Without the standard library, the plugins aren't the same
+        let mut bare = Context::new();
+
+        match bare.load_program(program.clone()) {
+            Ok(_) => panic!("Carregou o programa sem os plugins"),
+            Err(e) => assert!(e.contains("outros plugins"), "{}", e),
+        }
+
+        // Only in a context without code
+        match loaded.load_program(program) {
+            Ok(_) => panic!("Carregou o programa duas vezes"),
+            Err(e) => assert!(e.contains("ainda não tem código"), "{}", e),
+        }
+
+        // Files of other versions, cut or that aren't .birlc at all are refused
+        let with_header = |format : u32, instructions : u32| {
+            let mut changed = BYTECODE_MAGIC.to_vec();
+            changed.extend_from_slice(&format.to_le_bytes());
+            changed.extend_from_slice(&instructions.to_le_bytes());
+            changed.extend_from_slice(&bytes[BYTECODE_MAGIC.len() + 8..]);
+            changed
+        };
+
+        let error = |bytes : &[u8]| match CompiledProgram::from_bytes(bytes) {
+            Ok(_) => panic!("Leu um arquivo inválido"),
+            Err(e) => e,
+        };
+
+        assert!(error(&with_header(BYTECODE_FORMAT_VERSION + 1, INSTRUCTION_SET_VERSION)).contains("formato .birlc"));
+        assert!(error(&with_header(BYTECODE_FORMAT_VERSION, INSTRUCTION_SET_VERSION + 1)).contains("conjunto de instruções"));
+        assert!(error(&bytes[..bytes.len() - 1]).contains("cortado"));
+        assert!(error(b"JAULA SHOW").contains("não é um arquivo .birlc"));
+    }
+}
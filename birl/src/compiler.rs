@@ -1,15 +1,38 @@
 use std::collections::HashMap;
-use parser::{ Expression, ExpressionNode, FunctionParameter, Command, TypeKind, CommandArgument, MathOperator, CommandKind };
+use std::fmt;
+use parser::{ Expression, ExpressionNode, FunctionParameter, Command, TypeKind, CommandArgument, MathOperator, CommandKind,
+              Annotation };
 use vm::{ Instruction, ComparisionRequest };
-use context::RawValue;
+use context::{ RawValue, BIRL_MAIN_FUNCTION, BIRL_MAIN_FUNCTION_ID };
+use diagnostic::{ CODE_REDECLARATION, CODE_SHADOWING, CODE_UNASSIGNED };
+use standard_lib::edit_distance;
+use optimizer::{ fold_constants, inline_body, inline_call, InlineBody };
 
 #[derive(Debug)]
 enum SubScopeKind {
     Loop,
     ExecuteIf,
+    /// The SE NÃO of an if, which runs when no branch before it did
+    Else,
+    /// The scope of an ESCOLHE, which holds the value being compared and surrounds the cases
+    Switch(SwitchBlock),
+    /// A CASO, which only runs when the value matches
+    SwitchCase,
+    /// The QUALQUER OUTRO of a switch, which runs if no case matched
+    SwitchDefault,
     Regular,
 }
 
+/// What the compiler tracks of an ESCOLHE while its cases are compiled
+#[derive(Debug)]
+struct SwitchBlock {
+    /// Name of the hidden variable with the value being compared. It has a space, so it can't be typed in the code
+    subject : String,
+    /// The values of every case so far, to find repeated ones
+    values : Vec<RawValue>,
+    has_default : bool,
+}
+
 #[derive(Clone, Copy, PartialEq)]
 pub enum ScopeKind {
     Function,
@@ -21,11 +44,33 @@ struct SymbolEntry {
     address : usize,
     global : bool,
     writeable : bool,
+    /// Whether this is a parameter of the function being compiled
+    parameter : bool,
+    /// Whether this is a global reserved by the host, that the code can use but not declare again
+    reserved : bool,
+    /// Line where the symbol was declared, if it came from a source
+    line : Option<usize>,
 }
 
 impl SymbolEntry {
-    fn from(address : usize, global : bool, writeable : bool) -> SymbolEntry {
-        SymbolEntry { address, global, writeable }
+    fn from(address : usize, global : bool, writeable : bool, line : Option<usize>) -> SymbolEntry {
+        SymbolEntry { address, global, writeable, parameter : false, reserved : false, line }
+    }
+
+    /// Describe the declaration of the symbol, for diagnostics
+    fn describe(&self, name : &str) -> String {
+        let (kind, declared) = if self.parameter {
+            ("o parâmetro", "declarado")
+        } else if self.global {
+            ("a variável global", "declarada")
+        } else {
+            ("a variável", "declarada")
+        };
+
+        match self.line {
+            Some(l) => format!("{} {} {} na linha {}", kind, name, declared, l),
+            None => format!("{} {} {} fora do código fonte", kind, name, declared),
+        }
     }
 }
 
@@ -35,24 +80,52 @@ struct ScopeInfo {
     scope_kind : SubScopeKind,
     previous_next_var_address : usize,
     starting_var_address : usize,
+    /// Index of the JumpIfNot that goes past the block when its condition fails, set when the block ends
+    condition : Option<usize>,
+    /// Index of each Jump that leaves the block for its end, which is only known when the block ends. An if passes
+    /// them on to its else, so the whole chain jumps to the same place, and in a loop they go to where it's left
+    jumps : Vec<usize>,
 }
 
 impl ScopeInfo {
     fn new(scope_kind : SubScopeKind, previous_next_var_address : usize, is_global : bool) -> ScopeInfo {
         let mut symbol_table = HashMap::new();
-        symbol_table.insert("TREZE".to_owned(), SymbolEntry::from(0, is_global, false));
+        symbol_table.insert("TREZE".to_owned(), SymbolEntry::from(0, is_global, false, None));
 
         ScopeInfo {
             symbol_table,
             scope_kind,
             previous_next_var_address,
-            starting_var_address : previous_next_var_address
+            starting_var_address : previous_next_var_address,
+            condition : None,
+            jumps : vec![],
+        }
+    }
+}
+
+/// A variable declared without a value, to warn about it being read before it gets one
+#[derive(Debug)]
+struct UnassignedLocal {
+    /// Line of the declaration
+    line : Option<usize>,
+    /// Depth of the outermost scope in which it surely has a value, in the code being compiled. A block may not run, so
+    /// what's assigned inside it is forgotten when it ends
+    assigned_depth : Option<usize>,
+    /// Whether a read of it was already reported, so each variable gets only one warning
+    reported : bool,
+}
+
+impl UnassignedLocal {
+    fn assign(&mut self, depth : usize) {
+        if self.assigned_depth.is_none_or(|d| d > depth) {
+            self.assigned_depth = Some(depth);
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
-enum FunctionKind {
+/// Whether a function was written in BIRL or is a plugin
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FunctionKind {
     Plugin,
     Source,
 }
@@ -61,11 +134,136 @@ struct FunctionInfo {
     address : usize,
     arguments : Vec<TypeKind>,
     kind : FunctionKind,
+    annotations : Vec<Annotation>,
+    /// The code of the function, if it's small enough to take the place of the calls to it
+    inline_body : Option<InlineBody>,
 }
 
 impl FunctionInfo {
     fn from(address : usize, arguments : Vec<TypeKind>, kind : FunctionKind) -> FunctionInfo {
-        FunctionInfo { address, arguments, kind }
+        FunctionInfo { address, arguments, kind, annotations : vec![], inline_body : None }
+    }
+}
+
+/// A global variable declared somewhere in the source being compiled, found before the compilation starts.
+/// Used to give a proper error when a global is used before its declaration
+#[derive(Debug, Clone)]
+pub struct GlobalDeclaration {
+    pub line : usize,
+    /// Variables used by the initial value of the global
+    pub dependencies : Vec<String>,
+}
+
+impl GlobalDeclaration {
+    pub fn from(line : usize, dependencies : Vec<String>) -> GlobalDeclaration {
+        GlobalDeclaration { line, dependencies }
+    }
+}
+
+/// A function the code can call, as the compiler knows it
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionSymbol {
+    pub name : String,
+    pub kind : FunctionKind,
+    /// ID of the code, for functions written in BIRL, or of the plugin. The two are counted apart
+    pub id : usize,
+    pub parameters : Vec<TypeKind>,
+    /// What was written with FICHA above the function. Always empty for plugins
+    pub annotations : Vec<Annotation>,
+}
+
+impl FunctionSymbol {
+    /// Number of parameters
+    pub fn arity(&self) -> usize {
+        self.parameters.len()
+    }
+
+    pub fn has_annotation(&self, annotation : Annotation) -> bool {
+        self.annotations.contains(&annotation)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlobalSymbol {
+    pub name : String,
+    /// Where it is in the frame of the global code
+    pub address : usize,
+    pub writeable : bool,
+}
+
+/// A function bound as a method of a type, like TAMANHO for texts
+#[derive(Debug, Clone, PartialEq)]
+pub struct MethodSymbol {
+    pub receiver : TypeKind,
+    pub name : String,
+    pub function : String,
+}
+
+/// Every function, global variable and method known to the compiler at some point, for tools that look things up by
+/// name (completion in the console, reading a global from the host, a debugger, documentation). It's a copy, so it
+/// doesn't change when more code is compiled
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SymbolTable {
+    /// Functions written in BIRL in order of ID, then the plugins in order of ID
+    pub functions : Vec<FunctionSymbol>,
+    /// In order of address
+    pub globals : Vec<GlobalSymbol>,
+    /// In order of name, then of receiver
+    pub methods : Vec<MethodSymbol>,
+}
+
+impl SymbolTable {
+    pub fn function(&self, name : &str) -> Option<&FunctionSymbol> {
+        self.functions.iter().find(|f| f.name == name)
+    }
+
+    /// The function written in BIRL with the code ID
+    pub fn function_by_id(&self, id : usize) -> Option<&FunctionSymbol> {
+        self.functions.iter().find(|f| f.kind == FunctionKind::Source && f.id == id)
+    }
+
+    pub fn global(&self, name : &str) -> Option<&GlobalSymbol> {
+        self.globals.iter().find(|g| g.name == name)
+    }
+
+    /// The functions bound to a method name, for each type of receiver
+    pub fn methods_named(&self, name : &str) -> Vec<&MethodSymbol> {
+        self.methods.iter().filter(|m| m.name == name).collect()
+    }
+
+    /// Names of functions, globals and methods that start with `prefix`, sorted and without repetitions
+    pub fn complete(&self, prefix : &str) -> Vec<&str> {
+        let mut names : Vec<&str> = self.functions.iter().map(|f| f.name.as_str())
+            .chain(self.globals.iter().map(|g| g.name.as_str()))
+            .chain(self.methods.iter().map(|m| m.name.as_str()))
+            .filter(|name| name.starts_with(prefix))
+            .collect();
+
+        names.sort_unstable();
+        names.dedup();
+
+        names
+    }
+}
+
+/// Something that is probably wrong in the code, but isn't an error
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    /// Line of the source where it happened, if known
+    pub line : Option<usize>,
+    /// One of the warning codes in `diagnostic`
+    pub code : &'static str,
+    pub message : String,
+    /// The variable the warning is about, to point at it in the line
+    pub name : Option<String>,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(l) => write!(f, "Aviso (Linha {}) : {}", l, self.message),
+            None => write!(f, "Aviso : {}", self.message),
+        }
     }
 }
 
@@ -77,8 +275,29 @@ pub enum CompilerHint {
 pub struct Compiler {
     scopes : Vec<ScopeInfo>,
     functions : HashMap<String, FunctionInfo>,
+    /// Functions bound as methods : for each method name, the type of the receiver and the function called for it
+    methods : HashMap<String, Vec<(TypeKind, String)>>,
     next_var_address : usize,
     current_scope : ScopeKind,
+    global_declarations : HashMap<String, GlobalDeclaration>,
+    /// Line of the source being compiled, if known
+    current_line : Option<usize>,
+    warnings : Vec<Warning>,
+    /// In strict mode, warnings are reported as errors
+    strict : bool,
+    /// Annotations of the function being compiled
+    annotations : Vec<Annotation>,
+    /// If set, variables have to be declared with a value
+    require_initialization : bool,
+    /// Variables of the function being compiled that were declared without a value, by address
+    unassigned : HashMap<usize, UnassignedLocal>,
+    /// ID of the function being compiled, None in the global code
+    current_function : Option<usize>,
+    /// Where the last call of the function being compiled to itself ended, to turn it into a tail call if a BIRL:
+    /// TREZE comes right after it
+    self_call_end : Option<usize>,
+    /// Whether calls to small functions are replaced by their code
+    inlining : bool,
 }
 
 impl Compiler {
@@ -90,9 +309,270 @@ impl Compiler {
         Compiler {
             scopes : vec![ScopeInfo::new(SubScopeKind::Regular, 1, true)],
             functions : funcs,
+            methods : HashMap::new(),
             next_var_address : 1,
             current_scope : ScopeKind::Global,
+            global_declarations : HashMap::new(),
+            current_line : None,
+            warnings : vec![],
+            strict : false,
+            annotations : vec![],
+            require_initialization : false,
+            unassigned : HashMap::new(),
+            current_function : None,
+            self_call_end : None,
+            inlining : true,
+        }
+    }
+
+    pub fn set_current_line(&mut self, line : Option<usize>) {
+        self.current_line = line;
+    }
+
+    pub fn current_line(&self) -> Option<usize> {
+        self.current_line
+    }
+
+    pub fn set_strict_mode(&mut self, strict : bool) {
+        self.strict = strict;
+    }
+
+    /// Replace the calls to small functions compiled after this by their code (on by default). With it off, each call
+    /// runs in a frame of its own, so errors point to the line in the function
+    pub fn set_inlining(&mut self, inlining : bool) {
+        self.inlining = inlining;
+    }
+
+    /// Make declaring a variable without a value (`VEM: X`) an error
+    pub fn set_require_initialization(&mut self, require : bool) {
+        self.require_initialization = require;
+    }
+
+    /// Return the warnings emitted since the last call
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        ::std::mem::take(&mut self.warnings)
+    }
+
+    /// Emit a warning, or fail if in strict mode (and the function wasn't marked to be compiled without it)
+    fn warn(&mut self, code : &'static str, name : Option<&str>, message : String) -> Result<(), String> {
+        if self.strict && ! self.annotations.contains(&Annotation::NoStrict) {
+            return Err(message);
+        }
+
+        self.warnings.push(Warning { line : self.current_line, code, message, name : name.map(|n| n.to_owned()) });
+
+        Ok(())
+    }
+
+    /// Warn if declaring `name` hides a parameter, a global or another variable, or redeclares one in the same scope
+    fn check_shadowing(&mut self, name : &str) -> Result<(), String> {
+        let same_scope = match self.scopes.last() {
+            Some(s) => s.symbol_table.contains_key(name),
+            None => false,
+        };
+
+        let (code, message) = match self.find_symbol(name) {
+            Some(entry) if entry.reserved && self.current_scope == ScopeKind::Global =>
+                return Err(format!("{} é uma variável global reservada pelo programa que tá rodando o BIRL. Dá pra ler \
+                                    e mudar ela, mas não declarar de novo", name)),
+            Some(entry) if same_scope && ! entry.parameter => (CODE_REDECLARATION,
+                format!("{} foi declarada de novo, o que cria uma nova variável. A declaração anterior é {}",
+                        name, entry.describe(name))),
+            Some(entry) => (CODE_SHADOWING, format!("A declaração de {} esconde {}", name, entry.describe(name))),
+            None => return Ok(()),
+        };
+
+        self.warn(code, None, message)
+    }
+
+    /// Whether a command reads and whether it writes the variable named in its argument `index`
+    fn name_access(kind : CommandKind, index : usize) -> (bool, bool) {
+        match (kind, index) {
+            (CommandKind::Set, 0) | (CommandKind::GetStringInput, 0) | (CommandKind::GetNumberInput, 0) |
+            (CommandKind::GetIntegerInput, 0) | (CommandKind::ForEach, 0) |
+            (CommandKind::MakeNewList, 0) | (CommandKind::MakeNewMap, 0) | (CommandKind::QueryListSize, 1) |
+            (CommandKind::QueryMapSize, 1) | (CommandKind::ListMapKeys, 1) | (CommandKind::IndexList, 2) |
+            (CommandKind::IndexMap, 2) => (false, true),
+            (CommandKind::IntoString, 0) | (CommandKind::ConvertToNum, 0) | (CommandKind::ConvertToInt, 0) => (true, true),
+            // The variable being declared and the function being called
+            (CommandKind::Declare, _) | (CommandKind::RangeLoop, 0) | (CommandKind::Call, _) |
+            (CommandKind::CallUnpack, _) => (false, false),
+            _ => (true, false),
+        }
+    }
+
+    /// Warn if `name` is a variable that may not have a value where it's being read
+    fn check_assigned(&mut self, name : &str) -> Result<(), String> {
+        let address = match self.find_symbol(name) {
+            Some(entry) if ! entry.global => entry.address,
+            _ => return Ok(()),
+        };
+
+        let line = match self.unassigned.get_mut(&address) {
+            Some(local) if local.assigned_depth.is_none() && ! local.reported => {
+                local.reported = true;
+                local.line
+            }
+            _ => return Ok(()),
+        };
+
+        let declared = match line {
+            Some(l) => format!("na linha {}", l),
+            None => "fora do código fonte".to_owned(),
+        };
+
+        self.warn(CODE_UNASSIGNED, Some(name), format!("{} pode ser usada sem ter recebido um valor, e aí vale Null. Ela \
+                                                        foi declarada sem valor {}", name, declared))
+    }
+
+    /// Warn about the variables the command reads that may not have a value yet, then take note of the ones it assigns.
+    /// Globals are left out, since any function can assign them
+    fn check_initialization(&mut self, cmd : &Command) -> Result<(), String> {
+        if self.current_scope == ScopeKind::Global {
+            return Ok(());
+        }
+
+        let mut read = vec![];
+        let mut written = vec![];
+
+        for (index, argument) in cmd.arguments.iter().enumerate() {
+            match *argument {
+                CommandArgument::Expression(ref expr) => expr.root.collect_symbols(&mut read),
+                CommandArgument::Method(ref receiver, _) => read.push(receiver.clone()),
+                CommandArgument::Targets(ref names) => written.extend(names.iter().cloned()),
+                CommandArgument::Name(ref name) => {
+                    let (reads, writes) = Compiler::name_access(cmd.kind, index);
+
+                    if reads {
+                        read.push(name.clone());
+                    }
+
+                    if writes {
+                        written.push(name.clone());
+                    }
+                }
+            }
+        }
+
+        for name in read {
+            self.check_assigned(name.as_str())?;
+        }
+
+        let depth = self.scopes.len();
+
+        for name in written {
+            let address = match self.find_symbol(name.as_str()) {
+                Some(entry) if ! entry.global => entry.address,
+                _ => continue,
+            };
+
+            if let Some(local) = self.unassigned.get_mut(&address) {
+                local.assign(depth);
+            }
+        }
+
+        match cmd.kind {
+            // Nothing after these runs, so for the rest of the block it's as if everything had a value
+            CommandKind::Return | CommandKind::Quit | CommandKind::BreakScope | CommandKind::SkipNextIteration |
+            CommandKind::BreakLoop => {
+                for local in self.unassigned.values_mut() {
+                    local.assign(depth);
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Set the globals that are declared in the source about to be compiled
+    pub fn set_global_declarations(&mut self, declarations : HashMap<String, GlobalDeclaration>) {
+        self.global_declarations = declarations;
+    }
+
+    pub fn clear_global_declarations(&mut self) {
+        self.global_declarations.clear();
+    }
+
+    /// Search for a path of dependencies from `from` that ends in `target`
+    fn find_dependency_path(&self, from : &str, target : &str, path : &mut Vec<String>) -> bool {
+        if from == target {
+            path.push(from.to_owned());
+            return true;
+        }
+
+        if path.iter().any(|p| p == from) {
+            return false;
+        }
+
+        path.push(from.to_owned());
+
+        if let Some(decl) = self.global_declarations.get(from) {
+            for dep in &decl.dependencies {
+                if self.find_dependency_path(dep.as_str(), target, path) {
+                    return true;
+                }
+            }
+        }
+
+        path.pop();
+
+        false
+    }
+
+    /// The end of the error for a name that wasn't found, suggesting the closest ones among `candidates` (like
+    /// " Cê quis dizer FRANGO?"), or nothing if none of them is close enough. Case is ignored, and the longer the name,
+    /// the more typos it can have
+    fn suggestion<'a, I : IntoIterator<Item = &'a str>>(name : &str, candidates : I) -> String {
+        let name = name.to_uppercase();
+        let max_distance = name.chars().count().max(3) / 3;
+
+        let mut close = candidates.into_iter()
+            .map(|candidate| (edit_distance(&name, &candidate.to_uppercase()), candidate))
+            .filter(|&(distance, _)| distance <= max_distance)
+            .collect::<Vec<(usize, &str)>>();
+
+        close.sort();
+        close.dedup();
+        close.truncate(3);
+
+        let names = close.into_iter().map(|(_, candidate)| candidate).collect::<Vec<&str>>();
+
+        match names.split_last() {
+            None => String::new(),
+            Some((last, [])) => format!(". Cê quis dizer {}?", last),
+            Some((last, rest)) => format!(". Cê quis dizer {} ou {}?", rest.join(", "), last),
+        }
+    }
+
+    /// Every variable that can be used where the code is being compiled
+    fn visible_symbols(&self) -> impl Iterator<Item = &str> {
+        self.scopes.iter().flat_map(|scope| scope.symbol_table.keys().map(|name| name.as_str()))
+    }
+
+    /// Every function that can be called by name
+    fn callable_functions(&self) -> impl Iterator<Item = &str> {
+        self.functions.keys().map(|name| name.as_str()).filter(|&name| name != "__global__")
+    }
+
+    /// Error for a variable that wasn't found. If it's a global declared later in the source, explain that instead
+    /// (or the dependency cycle, if `declaring` is the global being declared and it's part of one)
+    fn symbol_not_found(&self, name : &str, declaring : Option<&str>) -> String {
+        let decl = match self.global_declarations.get(name) {
+            Some(d) => d,
+            None => return format!("Variável não encontrada : {}{}", name, Compiler::suggestion(name, self.visible_symbols())),
+        };
+
+        if let Some(declaring) = declaring {
+            let mut path = vec![declaring.to_owned()];
+
+            if self.find_dependency_path(name, declaring, &mut path) {
+                return format!("Dependência circular entre variáveis globais : {}", path.join(" -> "));
+            }
         }
+
+        format!("A variável global {} é usada antes de ser declarada (ela só é declarada na linha {}). \
+                 Variáveis globais são inicializadas na ordem em que aparecem", name, decl.line)
     }
 
     fn get_inst_for_op(op : MathOperator) -> Option<Instruction> {
@@ -101,62 +581,215 @@ impl Compiler {
             MathOperator::Minus => Some(Instruction::Sub),
             MathOperator::Division => Some(Instruction::Div),
             MathOperator::Multiplication => Some(Instruction::Mul),
+            MathOperator::Modulo => Some(Instruction::Mod),
+            MathOperator::Power => Some(Instruction::Pow),
+            MathOperator::Equal => Some(Instruction::CompareToMathB(ComparisionRequest::Equal)),
+            MathOperator::NotEqual => Some(Instruction::CompareToMathB(ComparisionRequest::NotEqual)),
+            MathOperator::Less => Some(Instruction::CompareToMathB(ComparisionRequest::Less)),
+            MathOperator::LessOrEqual => Some(Instruction::CompareToMathB(ComparisionRequest::LessOrEqual)),
+            MathOperator::More => Some(Instruction::CompareToMathB(ComparisionRequest::More)),
+            MathOperator::MoreOrEqual => Some(Instruction::CompareToMathB(ComparisionRequest::MoreOrEqual)),
             _ => None,
         }
     }
 
+    /// Compile the expression. The result is left in MathB
     pub fn compile_expression(&self, expr : Expression, inst : &mut Vec<Instruction>) -> Result<(), String> {
+        self.compile_node(fold_constants(expr.root), inst)
+    }
 
-        inst.push(Instruction::SetFirstExpressionOperation);
-
-        let mut is_a = expr.nodes.len() > 1;
+    /// Values and variables can be loaded straight into either math register without touching the other one
+    fn is_simple_node(node : &ExpressionNode) -> bool {
+        matches!(*node, ExpressionNode::Value(_) | ExpressionNode::Symbol(_) | ExpressionNode::CallerSymbol(_, _))
+    }
 
-        for node in expr.nodes {
-            match node {
-                ExpressionNode::Operator(MathOperator::ParenthesisLeft) |
-                ExpressionNode::Operator(MathOperator::ParenthesisRight) => unreachable!(),
-                ExpressionNode::Operator(o) => {
-                    let opi = match Compiler::get_inst_for_op(o) {
-                        Some(i) => i,
-                        None => unreachable!(),
-                    };
+    fn compile_simple_node(&self, node : ExpressionNode, to_a : bool, inst : &mut Vec<Instruction>) -> Result<(), String> {
+        match node {
+            ExpressionNode::Value(raw) => {
+                if to_a {
+                    inst.push(Instruction::PushValMathA(raw));
+                } else {
+                    inst.push(Instruction::PushValMathB(raw));
+                }
+            }
+            ExpressionNode::Symbol(s) => {
+                let info = match self.find_symbol(s.as_str()) {
+                    Some(i) => i,
+                    None => return Err(self.symbol_not_found(s.as_str(), None)),
+                };
 
-                    inst.push(opi);
+                if info.global {
+                    inst.push(Instruction::ReadGlobalVarFrom(info.address));
+                } else {
+                    inst.push(Instruction::ReadVarFrom(info.address));
+                }
 
-                    is_a = true;
+                if to_a {
+                    inst.push(Instruction::PushIntermediateToA);
+                } else {
+                    inst.push(Instruction::PushIntermediateToB);
+                }
+            }
+            ExpressionNode::CallerSymbol(depth, s) => {
+                if self.current_scope == ScopeKind::Global {
+                    return Err(format!("Variáveis de quem chamou a função (como @{}) só podem ser lidas dentro de funções", s));
                 }
-                ExpressionNode::Value(raw) => {
-                    if is_a {
-                        inst.push(Instruction::PushValMathA(raw));
-                    } else {
-                        inst.push(Instruction::PushValMathB(raw));
-                    }
 
-                    is_a = !is_a;
+                inst.push(Instruction::ReadVarFromFrame(depth, s));
+
+                if to_a {
+                    inst.push(Instruction::PushIntermediateToA);
+                } else {
+                    inst.push(Instruction::PushIntermediateToB);
                 }
-                ExpressionNode::Symbol(s) => {
-                    let info = match self.find_symbol(s.as_str()) {
-                        Some(i) => i,
-                        None => return Err(format!("Variável não encontrada : {}", s)),
-                    };
+            }
+            _ => return Err("Erro interno : compile_simple_node chamado com um nó composto".to_owned()),
+        }
 
-                    if info.global {
-                        inst.push(Instruction::ReadGlobalVarFrom(info.address));
-                    } else {
-                        inst.push(Instruction::ReadVarFrom(info.address));
+        Ok(())
+    }
+
+    fn compile_node(&self, node : ExpressionNode, inst : &mut Vec<Instruction>) -> Result<(), String> {
+        match node {
+            ExpressionNode::Value(_) | ExpressionNode::Symbol(_) |
+            ExpressionNode::CallerSymbol(_, _) => self.compile_simple_node(node, false, inst)?,
+            ExpressionNode::Unary(op, operand) => {
+                self.compile_node(*operand, inst)?;
+
+                match op {
+                    MathOperator::Minus => {
+                        // 0 - value
+                        inst.push(Instruction::PushValMathA(RawValue::Integer(0)));
+                        inst.push(Instruction::Sub);
                     }
+                    MathOperator::Not => inst.push(Instruction::Not),
+                    _ => return Err(format!("Erro interno : {:?} não é um operador unário", op)),
+                }
+            }
+            ExpressionNode::Binary(op, left, right) => {
+                let opi = match Compiler::get_inst_for_op(op) {
+                    Some(i) => i,
+                    None => return Err(format!("Erro interno : {:?} não é um operador binário", op)),
+                };
 
-                    if is_a {
-                        inst.push(Instruction::PushIntermediateToA);
+                // The operation is always MathB = MathA op MathB, so the left side must end up in A
+
+                if Compiler::is_simple_node(&right) {
+                    if Compiler::is_simple_node(&left) {
+                        self.compile_simple_node(*left, true, inst)?;
                     } else {
-                        inst.push(Instruction::PushIntermediateToB);
+                        self.compile_node(*left, inst)?;
+                        inst.push(Instruction::SwapMath);
                     }
 
-                    is_a = !is_a;
+                    self.compile_simple_node(*right, false, inst)?;
+                } else {
+                    // The right side may use both registers, so the left result has to be saved on the stack
+
+                    self.compile_node(*left, inst)?;
+                    inst.push(Instruction::PushMathBToStack);
+                    self.compile_node(*right, inst)?;
+                    inst.push(Instruction::PopStackToMathA);
                 }
+
+                inst.push(opi);
+            }
+            ExpressionNode::Logical(op, left, right) => self.compile_logical(op, *left, *right, inst)?,
+            ExpressionNode::Chain(ops, operands) => self.compile_chain(ops, operands, inst)?,
+            ExpressionNode::Map(entries) => self.compile_map(entries, inst)?,
+        }
+
+        Ok(())
+    }
+
+    fn compile_map(&self, entries : Vec<(ExpressionNode, ExpressionNode)>, inst : &mut Vec<Instruction>) -> Result<(), String> {
+        // The map stays on the stack while the entries are added, since the keys and values may use both registers (or
+        // be maps themselves). Each key goes on the stack too, while its value is evaluated
+
+        inst.push(Instruction::MakeNewMap);
+        inst.push(Instruction::PushMathBToStack);
+
+        for (key, value) in entries {
+            self.compile_node(key, inst)?;
+            inst.push(Instruction::PushMathBToStack);
+            self.compile_node(value, inst)?;
+            inst.push(Instruction::SetMapEntry);
+        }
+
+        inst.push(Instruction::PopStackToMathA);
+        inst.push(Instruction::SwapMath);
+
+        Ok(())
+    }
+
+    fn compile_logical(&self, op : MathOperator, left : ExpressionNode, right : ExpressionNode, inst : &mut Vec<Instruction>) -> Result<(), String> {
+        // The left side is evaluated into MathB. If it already decides the result, the instructions for the right side
+        // are skipped and the left value is the result. Otherwise, the right value is the result
+
+        self.compile_node(left, inst)?;
+
+        let mut right_inst = vec![];
+
+        self.compile_node(right, &mut right_inst)?;
+
+        match op {
+            MathOperator::And => inst.push(Instruction::SkipIfMathBFalsy(right_inst.len())),
+            MathOperator::Or => inst.push(Instruction::SkipIfMathBTruthy(right_inst.len())),
+            _ => return Err(format!("Erro interno : {:?} não é um operador lógico", op)),
+        }
+
+        inst.append(&mut right_inst);
+
+        Ok(())
+    }
+
+    fn compile_chain(&self, ops : Vec<MathOperator>, operands : Vec<ExpressionNode>, inst : &mut Vec<Instruction>) -> Result<(), String> {
+        // Every operand is evaluated only once. The last one evaluated stays on the stack, to be the left side of the next
+        // comparision. Each comparision is like a &&, so a false one skips all the others. Either way, one value is left
+        // on the stack at the end, and it's removed without touching the result in MathB
+
+        let mut operands = operands.into_iter();
+
+        match operands.next() {
+            Some(first) => self.compile_node(first, inst)?,
+            None => return Err("Erro interno : Comparação encadeada sem operandos".to_owned()),
+        }
+
+        inst.push(Instruction::PushMathBToStack);
+
+        let mut steps = vec![];
+
+        for (op, operand) in ops.into_iter().zip(operands) {
+            let opi = match Compiler::get_inst_for_op(op) {
+                Some(i) => i,
+                None => return Err(format!("Erro interno : {:?} não é um operador de comparação", op)),
+            };
+
+            let mut step = vec![];
+
+            self.compile_node(operand, &mut step)?;
+            step.push(Instruction::PopStackToMathA);
+            step.push(Instruction::PushMathBToStack);
+            step.push(opi);
+
+            steps.push(step);
+        }
+
+        // Built from the last step to the first, since each skip needs the size of everything after it
+        let mut rest : Vec<Instruction> = vec![];
+
+        for mut step in steps.into_iter().rev() {
+            if ! rest.is_empty() {
+                step.push(Instruction::SkipIfMathBFalsy(rest.len()));
+                step.append(&mut rest);
             }
+
+            rest = step;
         }
 
+        inst.append(&mut rest);
+        inst.push(Instruction::PopStackToMathA);
+
         Ok(())
     }
 
@@ -165,6 +798,18 @@ impl Compiler {
             instructions.push(Instruction::TryDecrementRefAt(sym.address));
         }
 
+        // The variables of the scope are gone (and their addresses will be reused), and the block may not have run
+        let depth = self.scopes.len();
+        let first_address = info.starting_var_address;
+
+        self.unassigned.retain(|&address, _| address < first_address);
+
+        for local in self.unassigned.values_mut() {
+            if local.assigned_depth.is_some_and(|d| d > depth) {
+                local.assigned_depth = None;
+            }
+        }
+
         self.next_var_address = info.previous_next_var_address;
     }
 
@@ -181,7 +826,7 @@ impl Compiler {
 
     fn add_symbol(&mut self, name : String, writeable : bool) -> Option<SymbolEntry> {
         let is_global = self.current_scope == ScopeKind::Global;
-        let entry = SymbolEntry::from(self.next_var_address, is_global, writeable);
+        let entry = SymbolEntry::from(self.next_var_address, is_global, writeable, self.current_line);
         self.next_var_address += 1;
 
         match self.scopes.last_mut() {
@@ -210,8 +855,9 @@ impl Compiler {
     }
 
     fn get_function_info(&self, id : usize) -> Option<&FunctionInfo> {
+        // Plugins have their own addresses, which can be the same as the ID of a function
         for (_, f) in &self.functions {
-            if f.address == id {
+            if f.address == id && f.kind == FunctionKind::Source {
                 return Some(f);
             }
         }
@@ -219,43 +865,423 @@ impl Compiler {
         None
     }
 
-    fn add_execute_while_boilerplate(&self, mut cmd : Command, instructions : &mut Vec<Instruction>) -> Result<(), String> {
-        instructions.push(Instruction::AddLoopLabel);
+    fn compile_call(&self, name : &str, arguments : Vec<Expression>, instructions : &mut Vec<Instruction>) -> Result<(), String> {
+        let info = match self.functions.get(name) {
+            Some(i) => i,
+            None => return Err(format!("Função {} não encontrada{}", name, Compiler::suggestion(name, self.callable_functions())))
+        };
 
-        if let CommandArgument::Expression(expr) = cmd.arguments.remove(0) {
-            self.compile_expression(expr, instructions)?;
-        } else {
-            return Err("Argumento 1 não é expressão".to_owned());
+        if info.kind == FunctionKind::Source {
+            instructions.push(Instruction::MakeNewFrame(info.address));
         }
 
-        // Move result to A
-        instructions.push(Instruction::SwapMath);
+        let num_args = arguments.len();
 
-        if let CommandArgument::Expression(expr) = cmd.arguments.remove(0) {
-            self.compile_expression(expr, instructions)?;
-        } else {
-            return Err("Argumento 2 não é expressão".to_owned());
+        if num_args > info.arguments.len() {
+            return Err(format!("A função {} espera {} argumentos, mas {} foram passados", name, info.arguments.len(), num_args));
         }
 
-        instructions.push(Instruction::Compare);
+        for (index, expr) in arguments.into_iter().enumerate() {
+            let expected_type = info.arguments[index];
+
+            // The parameter address is, in this case, index + 1 (because the address 0 is reserved to
+            // the return value)
+
+            match self.compile_expression(expr, instructions) {
+                Ok(_) => {}
+                Err(e) => return Err(e)
+            };
+
+            instructions.push(Instruction::AssertMathBCompatible(expected_type));
+
+            if info.kind == FunctionKind::Source {
+                instructions.push(Instruction::WriteVarToLast(index + 1));
+            } else {
+                instructions.push(Instruction::PushMathBPluginArgument);
+            }
+        }
+
+        if info.kind == FunctionKind::Source {
+            instructions.push(Instruction::SetLastFrameReady);
+        } else if info.kind == FunctionKind::Plugin {
+            instructions.push(Instruction::CallPlugin(info.address, num_args));
+        }
 
         Ok(())
     }
 
-    pub fn compile_command(&mut self, mut cmd : Command, instructions : &mut Vec<Instruction>)
-            -> Result<Option<CompilerHint>, String> {
-        match cmd.kind {
-            CommandKind::PrintDebug => {
-                // Evaluate the single argument and print-debug it
+    /// Put the code of a function in place of a call to it. The jumps in the code go to positions in `instructions`,
+    /// so it has to be the code of the function being compiled, not a part of it compiled apart
+    fn compile_inlined_call(&self, name : &str, body : &InlineBody, arguments : Vec<Expression>,
+                            instructions : &mut Vec<Instruction>) -> Result<(), String> {
+        let parameters = match self.functions.get(name) {
+            Some(f) => &f.arguments,
+            None => return Err(format!("Função {} não encontrada", name)),
+        };
 
-                if cmd.arguments.len() != 1 {
-                    return Err("Internal error : Debug print command has more than 1 argument (or less)".to_owned());
-                }
+        let num_args = arguments.len();
 
-                for arg in cmd.arguments {
-                    match arg {
-                        CommandArgument::Expression(expr) => {
-                            match self.compile_expression(expr, instructions) {
+        if num_args > parameters.len() {
+            return Err(format!("A função {} espera {} argumentos, mas {} foram passados", name, parameters.len(), num_args));
+        }
+
+        // The variables of the function go after the ones of the caller
+        let base = self.next_var_address;
+
+        for (index, expr) in arguments.into_iter().enumerate() {
+            self.compile_expression(expr, instructions)?;
+
+            instructions.push(Instruction::AssertMathBCompatible(parameters[index]));
+            instructions.push(Instruction::WriteVarTo(base + index + 1));
+        }
+
+        inline_call(body, base, num_args, instructions);
+
+        Ok(())
+    }
+
+    /// The code that can take the place of a call to the function, if inlining is on
+    fn inline_body_of(&self, name : &str) -> Option<&InlineBody> {
+        if ! self.inlining {
+            return None;
+        }
+
+        self.functions.get(name).and_then(|f| f.inline_body.as_ref())
+    }
+
+    /// The arguments of a É HORA DO : the function name, or the method of a variable, then the values passed to it.
+    /// If `inline`, a call by name to a small function is replaced by its code
+    fn compile_call_command(&self, mut arguments : Vec<CommandArgument>, inline : bool, instructions : &mut Vec<Instruction>)
+            -> Result<(), String> {
+        let target = arguments.remove(0);
+        let mut values = vec![];
+
+        for arg in arguments {
+            match arg {
+                CommandArgument::Expression(e) => values.push(e),
+                _ => return Err("Erro interno : Era esperado um valor como argumento \
+                                            pro comando.".to_owned()),
+            }
+        }
+
+        match target {
+            CommandArgument::Name(name) => match self.inline_body_of(&name) {
+                Some(body) if inline => self.compile_inlined_call(&name, body, values, instructions),
+                _ => self.compile_call(&name, values, instructions),
+            },
+            CommandArgument::Method(receiver, method) => self.compile_method_call(&receiver, &method, values, instructions),
+            _ => Err("É HORA DO espera um nome pra função".to_owned()),
+        }
+    }
+
+    /// Call a method of a variable, which is given as the first argument to the function. If functions were bound to
+    /// that method name, the one called depends on the type of the variable when the call happens. Otherwise, the
+    /// function with the same name as the method is called
+    fn compile_method_call(&self, receiver : &str, method : &str, arguments : Vec<Expression>,
+                           instructions : &mut Vec<Instruction>) -> Result<(), String> {
+        let receiver = Expression::from(ExpressionNode::Symbol(receiver.to_owned()));
+
+        let mut all_arguments = vec![receiver.clone()];
+        all_arguments.extend(arguments);
+
+        let candidates = match self.methods.get(method) {
+            Some(c) => c,
+            None => return match self.functions.get(method) {
+                Some(_) => self.compile_call(method, all_arguments, instructions),
+                None => {
+                    let names = self.methods.keys().map(|name| name.as_str()).chain(self.callable_functions());
+
+                    Err(format!("Método {} não encontrado{}", method, Compiler::suggestion(method, names)))
+                }
+            },
+        };
+
+        let mut branches = vec![];
+
+        for &(kind, ref function) in candidates {
+            let mut branch = vec![];
+
+            self.compile_call(function, all_arguments.clone(), &mut branch)?;
+            branches.push((kind, branch));
+        }
+
+        // After each branch but the last, skip all the ones after it
+        let mut table = vec![];
+        let mut offset = 0usize;
+
+        for (index, &(kind, ref branch)) in branches.iter().enumerate() {
+            table.push((kind, offset));
+            offset += branch.len();
+
+            if index + 1 < branches.len() {
+                offset += 1;
+            }
+        }
+
+        self.compile_expression(receiver, instructions)?;
+        instructions.push(Instruction::DispatchMethod(method.to_owned(), table));
+
+        let mut remaining = offset;
+
+        for (index, (_, mut branch)) in branches.into_iter().enumerate() {
+            remaining -= branch.len();
+            instructions.append(&mut branch);
+
+            if index + 1 < candidates.len() {
+                remaining -= 1;
+                instructions.push(Instruction::Skip(remaining));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compile the arguments of a comparision : with two values, they're compared to each other, and with only one, it's
+    /// compared to true (so É ELE MEMO runs if it's truthy and NUM É ELE if it's not)
+    fn add_comparision(&self, mut arguments : Vec<CommandArgument>, instructions : &mut Vec<Instruction>) -> Result<(), String> {
+        if let CommandArgument::Expression(expr) = arguments.remove(0) {
+            self.compile_expression(expr, instructions)?;
+        } else {
+            return Err("Argumento 1 não é expressão".to_owned());
+        }
+
+        if arguments.is_empty() {
+            instructions.push(Instruction::CompareTruthiness);
+
+            return Ok(());
+        }
+
+        let expr = match arguments.remove(0) {
+            CommandArgument::Expression(expr) => expr,
+            _ => return Err("Argumento 2 não é expressão".to_owned()),
+        };
+
+        // The first value has to end up in A. The second expression may use both registers, so unless it's a simple
+        // one, the first value waits on the stack
+
+        if Compiler::is_simple_node(&expr.root) {
+            instructions.push(Instruction::SwapMath);
+            self.compile_expression(expr, instructions)?;
+        } else {
+            instructions.push(Instruction::PushMathBToStack);
+            self.compile_expression(expr, instructions)?;
+            instructions.push(Instruction::PopStackToMathA);
+        }
+
+        instructions.push(Instruction::Compare);
+
+        Ok(())
+    }
+
+    /// A call of the function to itself followed by a BIRL: TREZE is a tail call : the function would just give back
+    /// what the call gave, so the call takes the place of the function running instead of going on top of it. The
+    /// return is still compiled after it, but it's never reached
+    fn make_tail_call(&self, returned : &CommandArgument, instructions : &mut [Instruction]) {
+        let returns_treze = match *returned {
+            CommandArgument::Expression(Expression { root : ExpressionNode::Symbol(ref name), .. }) => {
+                name == "TREZE" && self.find_symbol(name).map(|s| s.address == 0 && ! s.global).unwrap_or(false)
+            }
+            _ => false,
+        };
+
+        if ! returns_treze || self.self_call_end != Some(instructions.len()) {
+            return;
+        }
+
+        if let (Some(id), Some(last)) = (self.current_function, instructions.last_mut()) {
+            if let Instruction::SetLastFrameReady = *last {
+                *last = Instruction::TailCall(id);
+            }
+        }
+    }
+
+    /// Index of the scope of the innermost loop of the function, if the code being compiled is in one
+    fn innermost_loop(&self) -> Option<usize> {
+        for (index, scope) in self.scopes.iter().enumerate().rev() {
+            match scope.scope_kind {
+                SubScopeKind::Loop => return Some(index),
+                SubScopeKind::Regular => return None,
+                _ => {}
+            }
+        }
+
+        None
+    }
+
+    /// Release the variables of the blocks inside the scope at `scope`, for leaving them without reaching their ends
+    fn release_scopes_after(&self, scope : usize, instructions : &mut Vec<Instruction>) {
+        for info in &self.scopes[scope + 1..] {
+            for sym in info.symbol_table.values() {
+                instructions.push(Instruction::TryDecrementRefAt(sym.address));
+            }
+        }
+    }
+
+    /// Add the JumpIfNot that goes past the block of the current scope when the last comparision doesn't match `req`
+    fn add_condition(&mut self, req : ComparisionRequest, instructions : &mut Vec<Instruction>) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.condition = Some(instructions.len());
+        }
+
+        instructions.push(Instruction::JumpIfNot(req, 0));
+    }
+
+    /// Index of the scope of the switch whose cases are being compiled, if any
+    fn current_switch_scope(&self) -> Option<usize> {
+        match self.scopes.last().map(|s| &s.scope_kind) {
+            Some(&SubScopeKind::Switch(_)) => Some(self.scopes.len() - 1),
+            Some(&SubScopeKind::SwitchCase) | Some(&SubScopeKind::SwitchDefault) => Some(self.scopes.len() - 2),
+            _ => None,
+        }
+    }
+
+    /// The switch whose cases are being compiled, if any
+    fn current_switch(&mut self) -> Option<&mut SwitchBlock> {
+        let index = self.current_switch_scope()?;
+
+        match self.scopes[index].scope_kind {
+            SubScopeKind::Switch(ref mut block) => Some(block),
+            _ => None,
+        }
+    }
+
+    /// Add a Jump to the end of the block of the scope at `scope`, to be set when the block ends
+    fn add_jump_to_end(&mut self, scope : usize, instructions : &mut Vec<Instruction>) {
+        self.scopes[scope].jumps.push(instructions.len());
+        instructions.push(Instruction::Jump(0));
+    }
+
+    /// Point the jumps (and conditional jumps) at the indexes in `jumps` to `target`
+    fn set_jump_targets(jumps : &[usize], target : usize, instructions : &mut [Instruction]) {
+        for &index in jumps {
+            instructions[index] = match instructions[index] {
+                Instruction::JumpIfNot(req, _) => Instruction::JumpIfNot(req, target),
+                _ => Instruction::Jump(target),
+            };
+        }
+    }
+
+    /// End the case or default of the switch, if one is open. A case that ran jumps to the end of the switch, unless
+    /// it's the last one
+    fn end_switch_case(&mut self, last : bool, instructions : &mut Vec<Instruction>) {
+        let conditional = match self.scopes.last().map(|s| &s.scope_kind) {
+            Some(&SubScopeKind::SwitchCase) => true,
+            Some(&SubScopeKind::SwitchDefault) => false,
+            _ => return,
+        };
+
+        // The variables of the case are released before the jump, so only the case that ran does it
+
+        let condition = match self.scopes.pop() {
+            Some(case) => {
+                let condition = case.condition;
+                self.end_scope(case, instructions);
+
+                condition
+            }
+            None => return,
+        };
+
+        if conditional && ! last {
+            let switch = self.scopes.len() - 1;
+            self.add_jump_to_end(switch, instructions);
+        }
+
+        // A case that doesn't match goes on to check the next one
+        let next = instructions.len();
+        if let Some(index) = condition {
+            Compiler::set_jump_targets(&[index], next, instructions);
+        }
+    }
+
+    /// End the switch being compiled and its last case, and point every jump of it to the end
+    fn end_switch(&mut self, instructions : &mut Vec<Instruction>) {
+        self.end_switch_case(true, instructions);
+
+        if let Some(mut scope_info) = self.scopes.pop() {
+            // The jumps land on the release of the value being compared
+            let jumps = ::std::mem::take(&mut scope_info.jumps);
+            let end = instructions.len();
+
+            Compiler::set_jump_targets(&jumps, end, instructions);
+            self.end_scope(scope_info, instructions);
+        }
+    }
+
+    /// End the open branch of an if for the SE NÃO or OU SE FOR in `command`. The branch, when it runs, jumps over the
+    /// ones after it. Gives back the jumps of the chain so far, for the next branch to keep
+    fn end_if_branch(&mut self, command : &str, instructions : &mut Vec<Instruction>) -> Result<Vec<usize>, String> {
+        match self.scopes.last().map(|s| &s.scope_kind) {
+            Some(&SubScopeKind::ExecuteIf) => {}
+            Some(&SubScopeKind::Else) => return Err(format!("{} depois do SE NÃO. O SE NÃO tem que ser o último", command)),
+            _ => return Err(format!("{} sem um bloco condicional antes", command)),
+        }
+
+        let mut branch = match self.scopes.pop() {
+            Some(s) => s,
+            None => return Err(format!("{} fora de qualquer scope", command)),
+        };
+
+        let mut jumps = ::std::mem::take(&mut branch.jumps);
+        let condition = branch.condition;
+
+        self.end_scope(branch, instructions);
+
+        jumps.push(instructions.len());
+        instructions.push(Instruction::Jump(0));
+
+        // When the condition fails, the next branch is checked
+        let next = instructions.len();
+        if let Some(index) = condition {
+            Compiler::set_jump_targets(&[index], next, instructions);
+        }
+
+        Ok(jumps)
+    }
+
+    /// End the if being compiled, or its else, and point every jump of the chain to the end
+    fn end_if(&mut self, instructions : &mut Vec<Instruction>) {
+        if let Some(mut branch) = self.scopes.pop() {
+            let mut jumps = ::std::mem::take(&mut branch.jumps);
+            jumps.extend(branch.condition);
+
+            // The variables are released inside the block, so a branch that didn't run doesn't touch them
+            self.end_scope(branch, instructions);
+
+            let end = instructions.len();
+            Compiler::set_jump_targets(&jumps, end, instructions);
+        }
+    }
+
+    fn add_execute_while_boilerplate(&self, cmd : Command, instructions : &mut Vec<Instruction>) -> Result<(), String> {
+        instructions.push(Instruction::AddLoopLabel);
+
+        self.add_comparision(cmd.arguments, instructions)
+    }
+
+    pub fn compile_command(&mut self, mut cmd : Command, instructions : &mut Vec<Instruction>)
+            -> Result<Option<CompilerHint>, String> {
+        if let Some(&SubScopeKind::Switch(_)) = self.scopes.last().map(|s| &s.scope_kind) {
+            match cmd.kind {
+                CommandKind::SwitchCase | CommandKind::SwitchDefault | CommandKind::EndSubScope => {}
+                _ => return Err("Dentro de um ESCOLHE, os comandos têm que estar num CASO ou no QUALQUER OUTRO".to_owned()),
+            }
+        }
+
+        self.check_initialization(&cmd)?;
+
+        match cmd.kind {
+            CommandKind::PrintDebug => {
+                // Evaluate the single argument and print-debug it
+
+                if cmd.arguments.len() != 1 {
+                    return Err("Internal error : Debug print command has more than 1 argument (or less)".to_owned());
+                }
+
+                for arg in cmd.arguments {
+                    match arg {
+                        CommandArgument::Expression(expr) => {
+                            match self.compile_expression(expr, instructions) {
                                 Ok(_) => {},
                                 Err(e) => return Err(e),
                             };
@@ -280,8 +1306,6 @@ impl Compiler {
                         _ => return Err("Erro : Um argumento diferente de valor foi passado pra print. Erro interno.".to_owned()),
                     }
                 }
-
-                instructions.push(Instruction::FlushStdout);
             }
             CommandKind::PrintLn => {
                 for arg in cmd.arguments {
@@ -315,7 +1339,7 @@ impl Compiler {
 
                 let entry = match self.find_symbol(name.as_str()) {
                     Some(e) => e,
-                    None => return Err(format!("Variável {} não encontrada", name))
+                    None => return Err(self.symbol_not_found(name.as_str(), None))
                 };
 
                 if ! entry.writeable {
@@ -351,8 +1375,12 @@ impl Compiler {
                 };
 
                 let is_global = self.current_scope == ScopeKind::Global;
+                let without_value = cmd.arguments.is_empty();
 
-                if cmd.arguments.is_empty() {
+                if without_value && self.require_initialization {
+                    return Err(format!("{} foi declarada sem valor. Cê tem que dar um valor pra ela : VEM: {}, <valor>",
+                                       name, name));
+                } else if without_value {
                     // Set value to Null
                     // To achieve this, we set both Maths to null, then copy B to the var address
 
@@ -362,6 +1390,19 @@ impl Compiler {
 
                     match expr_arg {
                         CommandArgument::Expression(expr) => {
+                            if is_global {
+                                // Check for globals used before being declared first, for a better error message
+
+                                let mut symbols = vec![];
+                                expr.root.collect_symbols(&mut symbols);
+
+                                for sym in symbols {
+                                    if self.find_symbol(sym.as_str()).is_none() {
+                                        return Err(self.symbol_not_found(sym.as_str(), Some(name.as_str())));
+                                    }
+                                }
+                            }
+
                             match self.compile_expression(expr, instructions) {
                                 Ok(_) => {}
                                 Err(e) => return Err(e)
@@ -371,16 +1412,24 @@ impl Compiler {
                     }
                 }
 
+                self.check_shadowing(name.as_str())?;
+
                 // Add the variable after the expression is parsed, so we can't use the variable before a value is set
 
                 let address = self.next_var_address;
                 self.next_var_address += 1;
 
+                let line = self.current_line;
+
                 match self.scopes.last_mut() {
-                    Some(s) => s.symbol_table.insert(name, SymbolEntry::from(address, is_global, true)),
+                    Some(s) => s.symbol_table.insert(name, SymbolEntry::from(address, is_global, true, line)),
                     None => return Err(format!("Scopes é vazio"))
                 };
 
+                if without_value && ! is_global {
+                    self.unassigned.insert(address, UnassignedLocal { line, assigned_depth : None, reported : false });
+                }
+
                 if is_global {
                     instructions.push(Instruction::WriteGlobalVarTo(address));
                 } else {
@@ -390,9 +1439,22 @@ impl Compiler {
             CommandKind::Return => {
                 if cmd.arguments.is_empty() {
                     instructions.push(Instruction::ClearMath);
+                } else if cmd.arguments.len() > 1 {
+                    // Each value is kept in order, and the first one goes to TREZE
+                    for arg in cmd.arguments {
+                        match arg {
+                            CommandArgument::Expression(expr) => self.compile_expression(expr, instructions)?,
+                            other => return Err(format!("Esperado uma expressão como argumento pro comando Return, \
+                                                         encontrado {:?}", other)),
+                        }
+
+                        instructions.push(Instruction::PushReturnValue);
+                    }
                 } else {
                     let expr_arg = cmd.arguments.remove(0);
 
+                    self.make_tail_call(&expr_arg, instructions);
+
                     match expr_arg {
                         CommandArgument::Expression(expr) => {
                             match self.compile_expression(expr, instructions) {
@@ -406,35 +1468,7 @@ impl Compiler {
 
                 instructions.push(Instruction::Return);
             }
-            CommandKind::Compare => {
-                let left_expr_arg = cmd.arguments.remove(0);
-
-                match left_expr_arg {
-                    CommandArgument::Expression(expr) => {
-                        match self.compile_expression(expr, instructions) {
-                            Ok(_) => {}
-                            Err(e) => return Err(e)
-                        }
-                    }
-                    _ => return Err(format!("Esperado uma expressão como argumento pro comando Return, encontrado {:?}", left_expr_arg)),
-                }
-
-                instructions.push(Instruction::SwapMath);
-
-                let right_expr_arg = cmd.arguments.remove(0);
-
-                match right_expr_arg {
-                    CommandArgument::Expression(expr) => {
-                        match self.compile_expression(expr, instructions) {
-                            Ok(_) => {}
-                            Err(e) => return Err(e)
-                        }
-                    }
-                    _ => return Err(format!("Esperado uma expressão como argumento pro comando Return, encontrado {:?}", right_expr_arg)),
-                }
-
-                instructions.push(Instruction::Compare);
-            }
+            CommandKind::Compare => self.add_comparision(cmd.arguments, instructions)?,
             CommandKind::EndSubScope => {
                 let scope_info = match self.scopes.pop() {
                     Some(s) => s,
@@ -442,12 +1476,31 @@ impl Compiler {
                 };
 
                 match scope_info.scope_kind {
-                    SubScopeKind::ExecuteIf => instructions.push(Instruction::EndConditionalBlock),
+                    SubScopeKind::ExecuteIf | SubScopeKind::Else => {
+                        self.scopes.push(scope_info);
+                        self.end_if(instructions);
+
+                        return Ok(Some(CompilerHint::ScopeEnd));
+                    }
                     SubScopeKind::Loop => {
                         instructions.push(Instruction::RestoreLoopLabel);
-                        instructions.push(Instruction::EndConditionalBlock);
+
+                        // The loop is left when the condition fails or with a SAI DO LOOP, and either way the label
+                        // goes away
+                        let mut jumps = scope_info.jumps.clone();
+                        jumps.extend(scope_info.condition);
+
+                        let exit = instructions.len();
+                        Compiler::set_jump_targets(&jumps, exit, instructions);
+
                         instructions.push(Instruction::PopLoopLabel);
                     }
+                    SubScopeKind::Switch(_) | SubScopeKind::SwitchCase | SubScopeKind::SwitchDefault => {
+                        self.scopes.push(scope_info);
+                        self.end_switch(instructions);
+
+                        return Ok(Some(CompilerHint::ScopeEnd));
+                    }
                     SubScopeKind::Regular => {
                         self.scopes.push(scope_info);
 
@@ -463,7 +1516,7 @@ impl Compiler {
                 let is_global = self.current_scope == ScopeKind::Global;
                 self.scopes.push(ScopeInfo::new(SubScopeKind::ExecuteIf,
                                                 self.next_var_address, is_global));
-                instructions.push(Instruction::ExecuteIf(ComparisionRequest::Equal));
+                self.add_condition(ComparisionRequest::Equal, instructions);
 
                 return Ok(Some(CompilerHint::ScopeStart));
             },
@@ -471,7 +1524,7 @@ impl Compiler {
                 let is_global = self.current_scope == ScopeKind::Global;
                 self.scopes.push(ScopeInfo::new(SubScopeKind::ExecuteIf,
                                                 self.next_var_address, is_global));
-                instructions.push(Instruction::ExecuteIf(ComparisionRequest::NotEqual));
+                self.add_condition(ComparisionRequest::NotEqual, instructions);
 
                 return Ok(Some(CompilerHint::ScopeStart));
             },
@@ -479,7 +1532,7 @@ impl Compiler {
                 let is_global = self.current_scope == ScopeKind::Global;
                 self.scopes.push(ScopeInfo::new(SubScopeKind::ExecuteIf,
                                                 self.next_var_address, is_global));
-                instructions.push(Instruction::ExecuteIf(ComparisionRequest::MoreOrEqual));
+                self.add_condition(ComparisionRequest::MoreOrEqual, instructions);
 
                 return Ok(Some(CompilerHint::ScopeStart));
             },
@@ -487,7 +1540,7 @@ impl Compiler {
                 let is_global = self.current_scope == ScopeKind::Global;
                 self.scopes.push(ScopeInfo::new(SubScopeKind::ExecuteIf,
                                                 self.next_var_address, is_global));
-                instructions.push(Instruction::ExecuteIf(ComparisionRequest::More));
+                self.add_condition(ComparisionRequest::More, instructions);
 
                 return Ok(Some(CompilerHint::ScopeStart));
             },
@@ -495,7 +1548,7 @@ impl Compiler {
                 let is_global = self.current_scope == ScopeKind::Global;
                 self.scopes.push(ScopeInfo::new(SubScopeKind::ExecuteIf,
                                                 self.next_var_address, is_global));
-                instructions.push(Instruction::ExecuteIf(ComparisionRequest::LessOrEqual));
+                self.add_condition(ComparisionRequest::LessOrEqual, instructions);
 
                 return Ok(Some(CompilerHint::ScopeStart));
             },
@@ -503,61 +1556,47 @@ impl Compiler {
                 let is_global = self.current_scope == ScopeKind::Global;
                 self.scopes.push(ScopeInfo::new(SubScopeKind::ExecuteIf,
                                                 self.next_var_address, is_global));
-                instructions.push(Instruction::ExecuteIf(ComparisionRequest::Less));
+                self.add_condition(ComparisionRequest::Less, instructions);
 
                 return Ok(Some(CompilerHint::ScopeStart));
             },
             CommandKind::Call => {
-                // First argument is the function name
-
-                let info = if let CommandArgument::Name(name) = cmd.arguments.remove(0) {
-                    match self.functions.get(name.as_str()) {
-                        Some(i) => i,
-                        None => return Err(format!("Função {} não encontrada", name))
-                    }
-                } else {
-                    return Err("É HORA DO espera um nome pra função".to_owned());
+                let calls_itself = match cmd.arguments.first() {
+                    Some(CommandArgument::Name(name)) => self.functions.get(name)
+                        .map(|f| f.kind == FunctionKind::Source && Some(f.address) == self.current_function)
+                        .unwrap_or(false),
+                    _ => false,
                 };
 
-                if info.kind == FunctionKind::Source {
-                    instructions.push(Instruction::MakeNewFrame(info.address));
-                }
-
-                let mut index = 0usize;
-                let num_args = cmd.arguments.len();
-
-                for arg in cmd.arguments {
-                    let expr = match arg {
-                        CommandArgument::Expression(e) => e,
-                        _ => return Err("Erro interno : Era esperado um valor como argumento \
-                                                    pro comando.".to_owned()),
-                    };
+                self.compile_call_command(cmd.arguments, true, instructions)?;
 
-                    let expected_type = info.arguments[index];
+                if calls_itself {
+                    self.self_call_end = Some(instructions.len());
+                }
+            }
+            CommandKind::CallUnpack => {
+                // The variables, then the same as a call
+                let targets = match cmd.arguments.remove(0) {
+                    CommandArgument::Targets(names) => names,
+                    _ => return Err("Erro interno : Esperado as variáveis pro PEGA DA JAULA".to_owned()),
+                };
 
-                    // The parameter address is, in this case, index + 1 (because the address 0 is reserved to
-                    // the return value)
+                // The values are read from what the call returned, so it can't be inlined
+                self.compile_call_command(cmd.arguments, false, instructions)?;
 
-                    match self.compile_expression(expr, instructions) {
-                        Ok(_) => {}
-                        Err(e) => return Err(e)
+                for (index, name) in targets.iter().enumerate() {
+                    let entry = match self.find_or_add_symbol(name.as_str(), true) {
+                        Some(e) => e,
+                        None => return Err(format!("Variável {} não encontrada", name))
                     };
 
-                    instructions.push(Instruction::AssertMathBCompatible(expected_type));
+                    instructions.push(Instruction::ReadReturnValue(index));
 
-                    if info.kind == FunctionKind::Source {
-                        instructions.push(Instruction::WriteVarToLast(index + 1));
+                    if entry.global {
+                        instructions.push(Instruction::WriteGlobalVarTo(entry.address));
                     } else {
-                        instructions.push(Instruction::PushMathBPluginArgument);
+                        instructions.push(Instruction::WriteVarTo(entry.address));
                     }
-
-                    index += 1;
-                }
-
-                if info.kind == FunctionKind::Source {
-                    instructions.push(Instruction::SetLastFrameReady);
-                } else if info.kind == FunctionKind::Plugin {
-                    instructions.push(Instruction::CallPlugin(info.address, num_args));
                 }
             }
             CommandKind::GetStringInput => {
@@ -725,7 +1764,7 @@ impl Compiler {
 
                 self.add_execute_while_boilerplate(cmd, instructions)?;
 
-                instructions.push(Instruction::ExecuteIf(ComparisionRequest::Equal));
+                self.add_condition(ComparisionRequest::Equal, instructions);
                 return Ok(Some(CompilerHint::ScopeStart));
             }
             CommandKind::ExecuteWhileNotEqual => {
@@ -734,7 +1773,7 @@ impl Compiler {
 
                 self.add_execute_while_boilerplate(cmd, instructions)?;
 
-                instructions.push(Instruction::ExecuteIf(ComparisionRequest::NotEqual));
+                self.add_condition(ComparisionRequest::NotEqual, instructions);
                 return Ok(Some(CompilerHint::ScopeStart));
             }
             CommandKind::ExecuteWhileGreater => {
@@ -743,7 +1782,7 @@ impl Compiler {
 
                 self.add_execute_while_boilerplate(cmd, instructions)?;
 
-                instructions.push(Instruction::ExecuteIf(ComparisionRequest::More));
+                self.add_condition(ComparisionRequest::More, instructions);
                 return Ok(Some(CompilerHint::ScopeStart));
             }
             CommandKind::ExecuteWhileEqualOrGreater => {
@@ -752,7 +1791,7 @@ impl Compiler {
 
                 self.add_execute_while_boilerplate(cmd, instructions)?;
 
-                instructions.push(Instruction::ExecuteIf(ComparisionRequest::MoreOrEqual));
+                self.add_condition(ComparisionRequest::MoreOrEqual, instructions);
                 return Ok(Some(CompilerHint::ScopeStart));
             }
             CommandKind::ExecuteWhileLess => {
@@ -761,7 +1800,7 @@ impl Compiler {
 
                 self.add_execute_while_boilerplate(cmd, instructions)?;
 
-                instructions.push(Instruction::ExecuteIf(ComparisionRequest::Less));
+                self.add_condition(ComparisionRequest::Less, instructions);
                 return Ok(Some(CompilerHint::ScopeStart));
             }
             CommandKind::ExecuteWhileEqualOrLess => {
@@ -770,7 +1809,17 @@ impl Compiler {
 
                 self.add_execute_while_boilerplate(cmd, instructions)?;
 
-                instructions.push(Instruction::ExecuteIf(ComparisionRequest::LessOrEqual));
+                self.add_condition(ComparisionRequest::LessOrEqual, instructions);
+                return Ok(Some(CompilerHint::ScopeStart));
+            }
+            CommandKind::ExecuteWhile => {
+                let is_global = self.current_scope == ScopeKind::Global;
+                self.scopes.push(ScopeInfo::new(SubScopeKind::Loop, self.next_var_address, is_global));
+
+                // With a single value, the comparision is Equal when it's true
+                self.add_execute_while_boilerplate(cmd, instructions)?;
+
+                self.add_condition(ComparisionRequest::Equal, instructions);
                 return Ok(Some(CompilerHint::ScopeStart));
             }
             CommandKind::RangeLoop => {
@@ -783,25 +1832,29 @@ impl Compiler {
                     return Err("Esperado uma variável pro primeiro argumento do loop".to_owned());
                 };
 
-                let entry = match self.find_or_add_symbol(name.as_str(), true) {
-                    Some(e) => e,
-                    None => return Err(format!("Não foi possível adicionar nem encontrar a variável {}", name)),
-                };
-
-                // Initialize counter
+                // The start is evaluated before the counter exists, so it can use a variable with the same name
 
                 if let CommandArgument::Expression(expr) = cmd.arguments.remove(0) {
                     self.compile_expression(expr, instructions)?;
-
-                    if entry.global {
-                        instructions.push(Instruction::WriteGlobalVarTo(entry.address));
-                    } else {
-                        instructions.push(Instruction::WriteVarTo(entry.address));
-                    }
                 } else {
                     return Err("Era esperado uma expressão pro segundo argumento de RangedLoop".to_owned());
                 }
 
+                // The counter is always a new variable of the loop, so it's gone after the FIM
+
+                self.check_shadowing(name.as_str())?;
+
+                let entry = match self.add_symbol(name.clone(), true) {
+                    Some(e) => e,
+                    None => return Err(format!("Não foi possível declarar a variável {}", name)),
+                };
+
+                if entry.global {
+                    instructions.push(Instruction::WriteGlobalVarTo(entry.address));
+                } else {
+                    instructions.push(Instruction::WriteVarTo(entry.address));
+                }
+
                 let final_expr = if let CommandArgument::Expression(expr) = cmd.arguments.remove(0) {
                     expr
                 } else {
@@ -838,17 +1891,53 @@ impl Compiler {
 
                 instructions.push(Instruction::PushIntermediateToA);
 
-                instructions.push(Instruction::Compare);
+                instructions.push(Instruction::CompareLoopIndex);
 
-                instructions.push(Instruction::ExecuteIf(ComparisionRequest::NotEqual));
+                self.add_condition(ComparisionRequest::Equal, instructions);
 
                 return Ok(Some(CompilerHint::ScopeStart));
             }
-            CommandKind::MakeNewList => {
-                let name = if let CommandArgument::Name(name) = cmd.arguments.remove(0) {
-                    name
+            CommandKind::ForEach => {
+                let name = if let CommandArgument::Name(n) = cmd.arguments.remove(0) {
+                    n
                 } else {
-                    return Err("MakeNewList : Esperado um nome".to_owned());
+                    return Err("Esperado uma variável pro primeiro argumento do PRA CADA".to_owned());
+                };
+
+                if let CommandArgument::Expression(expr) = cmd.arguments.remove(0) {
+                    self.compile_expression(expr, instructions)?;
+                } else {
+                    return Err("Era esperado uma expressão pro segundo argumento do PRA CADA".to_owned());
+                }
+
+                let is_global = self.current_scope == ScopeKind::Global;
+                self.scopes.push(ScopeInfo::new(SubScopeKind::Loop, self.next_var_address, is_global));
+
+                let entry = match self.find_or_add_symbol(name.as_str(), true) {
+                    Some(e) => e,
+                    None => return Err(format!("Não foi possível adicionar nem encontrar a variável {}", name)),
+                };
+
+                // Every iteration starts by getting the next value, and the body only runs if there was one
+
+                instructions.push(Instruction::AddLoopLabel);
+                instructions.push(Instruction::IterateMathB);
+                instructions.push(Instruction::NextFromIterator);
+                self.add_condition(ComparisionRequest::Equal, instructions);
+
+                if entry.global {
+                    instructions.push(Instruction::WriteGlobalVarTo(entry.address));
+                } else {
+                    instructions.push(Instruction::WriteVarTo(entry.address));
+                }
+
+                return Ok(Some(CompilerHint::ScopeStart));
+            }
+            CommandKind::MakeNewList => {
+                let name = if let CommandArgument::Name(name) = cmd.arguments.remove(0) {
+                    name
+                } else {
+                    return Err("MakeNewList : Esperado um nome".to_owned());
                 };
 
                 let entry = match self.find_or_add_symbol(name.as_str(), true) {
@@ -929,11 +2018,11 @@ impl Compiler {
                     None => return Err(format!("Não foi possível encontrar a lista {}", list_name))
                 };
 
-                if list.global {
-                    instructions.push(Instruction::ReadGlobalVarFrom(list.address));
+                let read_list = if list.global {
+                    Instruction::ReadGlobalVarFrom(list.address)
                 } else {
-                    instructions.push(Instruction::ReadVarFrom(list.address));
-                }
+                    Instruction::ReadVarFrom(list.address)
+                };
 
                 if let Some(expr) = index {
                     self.compile_expression(expr, instructions)?;
@@ -945,6 +2034,8 @@ impl Compiler {
 
                 self.compile_expression(element, instructions)?;
 
+                // Reading variables in the expressions uses the intermediate register, so only read the list after them
+                instructions.push(read_list);
                 instructions.push(Instruction::AddToListAtIndex);
             }
             CommandKind::RemoveListElement => {
@@ -975,6 +2066,35 @@ impl Compiler {
 
                 instructions.push(Instruction::RemoveFromListAtIndex);
             }
+            CommandKind::SortList => {
+                let name = if let CommandArgument::Name(name) = cmd.arguments.remove(0) {
+                    name
+                } else {
+                    return Err("SortList : Esperado um nome".to_owned())
+                };
+
+                let list = match self.find_symbol(name.as_str()) {
+                    Some(e) => e,
+                    None => return Err(format!("Variável {} não encontrada", name))
+                };
+
+                if cmd.arguments.is_empty() {
+                    instructions.push(Instruction::PushValMathB(RawValue::Null));
+                } else if let CommandArgument::Expression(expr) = cmd.arguments.remove(0) {
+                    self.compile_expression(expr, instructions)?;
+                } else {
+                    return Err("SortList : Esperado uma expressão com o nome da função".to_owned())
+                }
+
+                // The expression goes through the intermediate register, so the list is only read after it
+                if list.global {
+                    instructions.push(Instruction::ReadGlobalVarFrom(list.address));
+                } else {
+                    instructions.push(Instruction::ReadVarFrom(list.address));
+                }
+
+                instructions.push(Instruction::SortList);
+            }
             CommandKind::IndexList => {
                 let name = if let CommandArgument::Name(name) = cmd.arguments.remove(0) {
                     name
@@ -1020,18 +2140,356 @@ impl Compiler {
                     instructions.push(Instruction::WriteVarTo(dest.address));
                 }
             }
+            CommandKind::MakeNewMap => {
+                let name = if let CommandArgument::Name(name) = cmd.arguments.remove(0) {
+                    name
+                } else {
+                    return Err("MakeNewMap : Esperado um nome".to_owned());
+                };
+
+                let entry = match self.find_or_add_symbol(name.as_str(), true) {
+                    Some(a) => a,
+                    None => return Err(format!("Não foi possível declarar a variável pro mapa {}", name))
+                };
+
+                instructions.push(Instruction::MakeNewMap);
+
+                if entry.global {
+                    instructions.push(Instruction::WriteGlobalVarTo(entry.address));
+                } else {
+                    instructions.push(Instruction::WriteVarTo(entry.address));
+                }
+            }
+            CommandKind::SetMapEntry => {
+                let name = if let CommandArgument::Name(name) = cmd.arguments.remove(0) {
+                    name
+                } else {
+                    return Err("SetMapEntry : Esperado um nome".to_owned())
+                };
+
+                let key = if let CommandArgument::Expression(expr) = cmd.arguments.remove(0) {
+                    expr
+                } else {
+                    return Err("SetMapEntry : Esperado uma expressão como chave".to_owned())
+                };
+
+                let value = if let CommandArgument::Expression(expr) = cmd.arguments.remove(0) {
+                    expr
+                } else {
+                    return Err("SetMapEntry : Esperado uma expressão como valor".to_owned())
+                };
+
+                let map = match self.find_symbol(name.as_str()) {
+                    Some(m) => m,
+                    None => return Err(format!("Não foi possível encontrar o mapa {}", name))
+                };
+
+                if map.global {
+                    instructions.push(Instruction::ReadGlobalVarFrom(map.address));
+                } else {
+                    instructions.push(Instruction::ReadVarFrom(map.address));
+                }
+
+                // Like in a map literal, the map and the key wait on the stack for the value
+                instructions.push(Instruction::PushIntermediateToB);
+                instructions.push(Instruction::PushMathBToStack);
+                self.compile_expression(key, instructions)?;
+                instructions.push(Instruction::PushMathBToStack);
+                self.compile_expression(value, instructions)?;
+                instructions.push(Instruction::SetMapEntry);
+                instructions.push(Instruction::PopStackToMathA);
+            }
+            CommandKind::IndexMap | CommandKind::RemoveMapEntry => {
+                let name = if let CommandArgument::Name(name) = cmd.arguments.remove(0) {
+                    name
+                } else {
+                    return Err(format!("{:?} : Esperado um nome", cmd.kind))
+                };
+
+                let key = if let CommandArgument::Expression(expr) = cmd.arguments.remove(0) {
+                    expr
+                } else {
+                    return Err(format!("{:?} : Esperado uma expressão como chave", cmd.kind))
+                };
+
+                let map = match self.find_symbol(name.as_str()) {
+                    Some(m) => m,
+                    None => return Err(format!("Não foi possível encontrar o mapa {}", name))
+                };
+
+                let read_map = if map.global {
+                    Instruction::ReadGlobalVarFrom(map.address)
+                } else {
+                    Instruction::ReadVarFrom(map.address)
+                };
+
+                self.compile_expression(key, instructions)?;
+
+                // Reading variables in the key uses the intermediate register, so only read the map after it
+                instructions.push(read_map);
+
+                if cmd.kind == CommandKind::RemoveMapEntry {
+                    instructions.push(Instruction::RemoveFromMap);
+                } else {
+                    let dest_name = if let CommandArgument::Name(name) = cmd.arguments.remove(0) {
+                        name
+                    } else {
+                        return Err("IndexMap : Esperado um nome".to_owned())
+                    };
+
+                    let dest = match self.find_or_add_symbol(dest_name.as_str(), true) {
+                        Some(e) => e,
+                        None => return Err(format!("Não foi possível encontrar ou declarar a variável {}", dest_name))
+                    };
+
+                    instructions.push(Instruction::IndexMap);
+
+                    if dest.global {
+                        instructions.push(Instruction::WriteGlobalVarTo(dest.address));
+                    } else {
+                        instructions.push(Instruction::WriteVarTo(dest.address));
+                    }
+                }
+            }
+            CommandKind::QueryMapSize | CommandKind::ListMapKeys => {
+                let name = if let CommandArgument::Name(name) = cmd.arguments.remove(0) {
+                    name
+                } else {
+                    return Err(format!("{:?} : Esperado um nome", cmd.kind))
+                };
+
+                let dest_name = if let CommandArgument::Name(name) = cmd.arguments.remove(0) {
+                    name
+                } else {
+                    return Err(format!("{:?} : Esperado um nome", cmd.kind))
+                };
+
+                let dest = match self.find_or_add_symbol(dest_name.as_str(), true) {
+                    Some(d) => d,
+                    None => return Err(format!("Não foi possível encontrar ou declarar a variável {}", dest_name))
+                };
+
+                let map = match self.find_symbol(name.as_str()) {
+                    Some(m) => m,
+                    None => return Err(format!("Não foi possível encontrar o mapa {}", name))
+                };
+
+                if map.global {
+                    instructions.push(Instruction::ReadGlobalVarFrom(map.address));
+                } else {
+                    instructions.push(Instruction::ReadVarFrom(map.address));
+                }
+
+                if cmd.kind == CommandKind::QueryMapSize {
+                    instructions.push(Instruction::QueryMapSize);
+                } else {
+                    instructions.push(Instruction::ListMapKeys);
+                }
+
+                if dest.global {
+                    instructions.push(Instruction::WriteGlobalVarTo(dest.address));
+                } else {
+                    instructions.push(Instruction::WriteVarTo(dest.address));
+                }
+            }
             CommandKind::BreakScope => {
-                instructions.push(Instruction::IncreaseSkippingLevel);
+                // Leaving a branch of an if or a case leaves the whole block, or the branches after it would still be
+                // checked
+
+                let last = self.scopes.len().saturating_sub(1);
+
+                // The variables of a loop are released after it's left, so only the ones of a branch are released here
+                let (target, release) = match self.scopes.last().map(|s| &s.scope_kind) {
+                    Some(&SubScopeKind::ExecuteIf) | Some(&SubScopeKind::Else) => (last, true),
+                    Some(&SubScopeKind::Loop) => (last, false),
+                    Some(&SubScopeKind::SwitchCase) | Some(&SubScopeKind::SwitchDefault) => (last - 1, true),
+                    _ => return Err("PARA AQUI fora de um bloco".to_owned()),
+                };
+
+                if release {
+                    self.release_scopes_after(last - 1, instructions);
+                }
+
+                self.add_jump_to_end(target, instructions);
             }
             CommandKind::SkipNextIteration => {
+                let target = match self.innermost_loop() {
+                    Some(t) => t,
+                    None => return Err("VAI PRO PRÓXIMO fora de um loop".to_owned()),
+                };
+
+                // The blocks inside the loop don't reach their ends
+                self.release_scopes_after(target, instructions);
                 instructions.push(Instruction::RestoreLoopLabel);
             }
+            CommandKind::BreakLoop => {
+                let target = match self.innermost_loop() {
+                    Some(t) => t,
+                    None => return Err("SAI DO LOOP fora de um loop".to_owned()),
+                };
+
+                self.release_scopes_after(target, instructions);
+                self.add_jump_to_end(target, instructions);
+            }
+            CommandKind::ElseIf => {
+                let jumps = self.end_if_branch("OU SE FOR", instructions)?;
+
+                match cmd.arguments.remove(0) {
+                    CommandArgument::Expression(expr) => self.compile_expression(expr, instructions)?,
+                    _ => return Err("Era esperado uma expressão pro OU SE FOR".to_owned()),
+                }
+
+                instructions.push(Instruction::CompareTruthiness);
+
+                let is_global = self.current_scope == ScopeKind::Global;
+                let mut branch = ScopeInfo::new(SubScopeKind::ExecuteIf, self.next_var_address, is_global);
+                branch.jumps = jumps;
+                self.scopes.push(branch);
+                self.add_condition(ComparisionRequest::Equal, instructions);
+            }
+            CommandKind::Else => {
+                let jumps = self.end_if_branch("SE NÃO", instructions)?;
+
+                // Every branch that ran jumped over this one already, so there's nothing to check
+
+                let is_global = self.current_scope == ScopeKind::Global;
+                let mut branch = ScopeInfo::new(SubScopeKind::Else, self.next_var_address, is_global);
+                branch.jumps = jumps;
+                self.scopes.push(branch);
+            }
+            CommandKind::Switch => {
+                match cmd.arguments.remove(0) {
+                    CommandArgument::Expression(expr) => self.compile_expression(expr, instructions)?,
+                    _ => return Err("Era esperado uma expressão pro ESCOLHE".to_owned()),
+                }
+
+                // The value is kept in a variable of the switch, so every case compares against the same one
+
+                let subject = format!(" ESCOLHE {}", self.scopes.len());
+                let block = SwitchBlock { subject : subject.clone(), values : vec![], has_default : false };
+                let is_global = self.current_scope == ScopeKind::Global;
+                self.scopes.push(ScopeInfo::new(SubScopeKind::Switch(block), self.next_var_address, is_global));
+
+                let entry = match self.add_symbol(subject, false) {
+                    Some(e) => e,
+                    None => return Err("Não foi possível adicionar a variável do ESCOLHE".to_owned()),
+                };
+
+                if entry.global {
+                    instructions.push(Instruction::WriteGlobalVarTo(entry.address));
+                } else {
+                    instructions.push(Instruction::WriteVarTo(entry.address));
+                }
+
+                return Ok(Some(CompilerHint::ScopeStart));
+            }
+            CommandKind::SwitchCase => {
+                let subject = {
+                    let block = match self.current_switch() {
+                        Some(b) => b,
+                        None => return Err("CASO fora de um ESCOLHE".to_owned()),
+                    };
+
+                    if block.has_default {
+                        return Err("CASO depois do QUALQUER OUTRO. O QUALQUER OUTRO tem que ser o último".to_owned());
+                    }
+
+                    for arg in &cmd.arguments {
+                        let expr = match *arg {
+                            CommandArgument::Expression(ref e) => e,
+                            _ => return Err("Era esperado um valor pro CASO".to_owned()),
+                        };
+
+                        if expr.has_symbols {
+                            return Err("Os valores de um CASO têm que ser constantes, sem variáveis".to_owned());
+                        }
+
+                        if let ExpressionNode::Value(ref value) = expr.root {
+                            if block.values.contains(value) {
+                                return Err(format!("O valor {:?} já tem um CASO nesse ESCOLHE", value));
+                            }
+
+                            block.values.push(value.clone());
+                        }
+                    }
+
+                    block.subject.clone()
+                };
+
+                self.end_switch_case(false, instructions);
+
+                // The case runs if the value is equal to any of the constants
+
+                let mut condition = None;
+
+                for arg in cmd.arguments {
+                    if let CommandArgument::Expression(expr) = arg {
+                        let compare = ExpressionNode::Binary(MathOperator::Equal,
+                                                             Box::new(ExpressionNode::Symbol(subject.clone())),
+                                                             Box::new(expr.root));
+
+                        condition = Some(match condition {
+                            Some(c) => ExpressionNode::Logical(MathOperator::Or, Box::new(c), Box::new(compare)),
+                            None => compare,
+                        });
+                    }
+                }
+
+                match condition {
+                    Some(c) => self.compile_expression(Expression::from(c), instructions)?,
+                    None => return Err("CASO sem nenhum valor".to_owned()),
+                }
+
+                instructions.push(Instruction::CompareTruthiness);
+
+                let is_global = self.current_scope == ScopeKind::Global;
+                self.scopes.push(ScopeInfo::new(SubScopeKind::SwitchCase, self.next_var_address, is_global));
+                self.add_condition(ComparisionRequest::Equal, instructions);
+            }
+            CommandKind::SwitchDefault => {
+                match self.current_switch() {
+                    Some(ref block) if block.has_default => return Err("Esse ESCOLHE já tem um QUALQUER OUTRO".to_owned()),
+                    Some(block) => block.has_default = true,
+                    None => return Err("QUALQUER OUTRO fora de um ESCOLHE".to_owned()),
+                }
+
+                self.end_switch_case(false, instructions);
+
+                // Every case that matched left the switch already, so nothing has to be checked
+
+                let is_global = self.current_scope == ScopeKind::Global;
+                self.scopes.push(ScopeInfo::new(SubScopeKind::SwitchDefault, self.next_var_address, is_global));
+            }
+            CommandKind::Assert => {
+                // The actual value goes to MathA and the expected one to MathB. The expected expression may use both
+                // registers, so the actual value waits on the stack
+
+                let actual = cmd.arguments.remove(0);
+
+                match actual {
+                    CommandArgument::Expression(expr) => self.compile_expression(expr, instructions)?,
+                    _ => return Err(format!("Esperado uma expressão como argumento pro comando Assert, encontrado {:?}", actual)),
+                }
+
+                instructions.push(Instruction::PushMathBToStack);
+
+                let expected = cmd.arguments.remove(0);
+
+                match expected {
+                    CommandArgument::Expression(expr) => self.compile_expression(expr, instructions)?,
+                    _ => return Err(format!("Esperado uma expressão como argumento pro comando Assert, encontrado {:?}", expected)),
+                }
+
+                instructions.push(Instruction::PopStackToMathA);
+                instructions.push(Instruction::AssertMathEqual);
+            }
         }
 
         Ok(None)
     }
 
-    pub fn begin_compiling_function(&mut self, address : usize, args : Vec<FunctionParameter>, name : String) -> Result<(), String> {
+    pub fn begin_compiling_function(&mut self, address : usize, args : Vec<FunctionParameter>, name : String,
+                                    annotations : Vec<Annotation>) -> Result<(), String> {
         let mut base_scope = ScopeInfo::new(SubScopeKind::Regular,
                                             self.next_var_address, false);
 
@@ -1042,17 +2500,122 @@ impl Compiler {
         for arg in args {
             args_kind.push(arg.kind);
 
-            base_scope.symbol_table.insert(arg.name, SymbolEntry::from(self.next_var_address, false, true));
+            let mut entry = SymbolEntry::from(self.next_var_address, false, true, self.current_line);
+            entry.parameter = true;
+
+            base_scope.symbol_table.insert(arg.name, entry);
             self.next_var_address += 1;
         }
 
         self.current_scope = ScopeKind::Function;
-        self.functions.insert(name, FunctionInfo::from(address, args_kind, FunctionKind::Source));
+        self.unassigned.clear();
+        self.current_function = Some(address);
+        self.self_call_end = None;
+
+        // A function marked as the entry point takes the place of SHOW
+        if address == BIRL_MAIN_FUNCTION_ID && name != BIRL_MAIN_FUNCTION {
+            self.functions.remove(BIRL_MAIN_FUNCTION);
+        }
+
+        let mut info = FunctionInfo::from(address, args_kind, FunctionKind::Source);
+        info.annotations = annotations.clone();
+
+        self.annotations = annotations;
+        self.functions.insert(name, info);
         self.scopes.push(base_scope);
 
         Ok(())
     }
 
+    /// Name and address of every function written in BIRL (not plugins), including the global one
+    pub fn source_functions(&self) -> Vec<(String, usize)> {
+        let mut functions : Vec<(String, usize)> = self.functions.iter()
+            .filter(|&(_, info)| info.kind == FunctionKind::Source)
+            .map(|(name, info)| (name.clone(), info.address))
+            .collect();
+
+        functions.sort_by_key(|&(_, address)| address);
+
+        functions
+    }
+
+    /// Every function, global variable and method known so far
+    pub fn symbol_table(&self) -> SymbolTable {
+        let mut functions : Vec<FunctionSymbol> = self.callable_functions()
+            .map(|name| {
+                let info = &self.functions[name];
+
+                FunctionSymbol { name : name.to_owned(), kind : info.kind, id : info.address, parameters : info.arguments.clone(),
+                                 annotations : info.annotations.clone() }
+            })
+            .collect();
+
+        functions.sort_by_key(|f| (f.kind == FunctionKind::Plugin, f.id));
+
+        let globals = self.global_variables().into_iter()
+            .map(|(name, address, writeable)| GlobalSymbol { name, address, writeable })
+            .collect();
+
+        let mut methods : Vec<MethodSymbol> = self.methods.iter()
+            .flat_map(|(name, candidates)| candidates.iter().map(move |&(receiver, ref function)| {
+                MethodSymbol { receiver, name : name.clone(), function : function.clone() }
+            }))
+            .collect();
+
+        methods.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.receiver.to_string().cmp(&b.receiver.to_string())));
+
+        SymbolTable { functions, globals, methods }
+    }
+
+    /// Whether there's a function (or plugin) with the given name
+    pub fn has_function(&self, name : &str) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    /// Name and address of every plugin, in order of address
+    pub fn plugin_functions(&self) -> Vec<(String, usize)> {
+        let mut plugins : Vec<(String, usize)> = self.functions.iter()
+            .filter(|&(_, info)| info.kind == FunctionKind::Plugin)
+            .map(|(name, info)| (name.clone(), info.address))
+            .collect();
+
+        plugins.sort_by_key(|&(_, address)| address);
+
+        plugins
+    }
+
+    /// Make a function already compiled (like one loaded from a `.birlc` file) known to the compiler, so it can be
+    /// called by ID and from code compiled later
+    pub fn add_source_function_definition(&mut self, address : usize, params : Vec<TypeKind>, name : String) {
+        self.functions.insert(name, FunctionInfo::from(address, params, FunctionKind::Source));
+    }
+
+    /// Name, address and whether they can be changed, for every global variable, in order of address
+    pub fn global_variables(&self) -> Vec<(String, usize, bool)> {
+        let mut globals : Vec<(String, usize, bool)> = match self.scopes.first() {
+            Some(scope) => scope.symbol_table.iter()
+                .map(|(name, entry)| (name.clone(), entry.address, entry.writeable))
+                .collect(),
+            None => vec![],
+        };
+
+        globals.sort_by_key(|&(_, address, _)| address);
+
+        globals
+    }
+
+    /// Make a global variable declared by code already compiled known to the compiler, at the address that code uses
+    pub fn add_global_symbol(&mut self, name : String, address : usize, writeable : bool) -> Result<(), String> {
+        if self.current_scope != ScopeKind::Global || self.scopes.len() != 1 {
+            return Err("Scope atual não é o global".to_owned());
+        }
+
+        self.scopes[0].symbol_table.insert(name, SymbolEntry::from(address, true, writeable, None));
+        self.next_var_address = self.next_var_address.max(address + 1);
+
+        Ok(())
+    }
+
     pub fn add_plugin_function_definition(&mut self, address : usize, params : Vec<TypeKind>, name : String) -> Result<(), String> {
         let info = FunctionInfo::from(address, params, FunctionKind::Plugin);
 
@@ -1062,6 +2625,28 @@ impl Compiler {
         }
     }
 
+    /// Bind a function as a method of a type : `X.METHOD, args` calls the function with X as the first argument when X
+    /// has that type
+    pub fn add_method(&mut self, receiver : TypeKind, method : String, function : String) -> Result<(), String> {
+        match self.functions.get(function.as_str()) {
+            Some(info) if info.arguments.first() == Some(&receiver) => {}
+            Some(_) => return Err(format!("A função {} precisa receber um {} como primeiro argumento pra ser método dele",
+                                          function, receiver)),
+            None => return Err(format!("Função {} não encontrada{}", function,
+                                       Compiler::suggestion(&function, self.callable_functions()))),
+        }
+
+        let candidates = self.methods.entry(method).or_default();
+
+        if candidates.iter().any(|&(kind, _)| kind == receiver) {
+            return Err(format!("{} já tem um método com esse nome", receiver));
+        }
+
+        candidates.push((receiver, function));
+
+        Ok(())
+    }
+
     pub fn compile_global_variable(&mut self, name : String, value : RawValue, writeable : bool, instructions : &mut Vec<Instruction>) -> Result<(), String> {
         if self.current_scope != ScopeKind::Global {
             return Err("Scope atual não é o global".to_owned());
@@ -1078,6 +2663,24 @@ impl Compiler {
         Ok(())
     }
 
+    /// Like compile_global_variable, for a global the host reserves : it can be changed, but code compiled after
+    /// can't declare a global with the same name
+    pub fn compile_reserved_global(&mut self, name : String, value : RawValue, instructions : &mut Vec<Instruction>)
+        -> Result<(), String>
+    {
+        if self.scopes.first().map(|s| s.symbol_table.contains_key(&name)).unwrap_or(false) {
+            return Err(format!("Já existe uma variável global chamada {}", name));
+        }
+
+        self.compile_global_variable(name.clone(), value, true, instructions)?;
+
+        if let Some(entry) = self.scopes[0].symbol_table.get_mut(&name) {
+            entry.reserved = true;
+        }
+
+        Ok(())
+    }
+
     pub fn compile_function_call(&self, id : usize, args : Vec<RawValue>, instructions : &mut Vec<Instruction>)
         -> Result<(), String>
     {
@@ -1093,9 +2696,7 @@ impl Compiler {
 
         instructions.push(Instruction::MakeNewFrame(id));
 
-        let mut index = 0usize;
-
-        for arg in args {
+        for (index, arg) in args.into_iter().enumerate() {
             let expected = info.arguments[index];
 
             match &arg {
@@ -1114,15 +2715,19 @@ impl Compiler {
                         return Err(format!("Tipo incompatível : Função espera {:?}, foi passado Texto", expected))
                     }
                 }
+                &RawValue::Bool(_) => {
+                    if expected != TypeKind::Bool {
+                        return Err(format!("Tipo incompatível : Função espera {:?}, foi passado Booleano", expected))
+                    }
+                }
                 &RawValue::Null => {
                     return Err(format!("Tipo incompatível : Passado Nulo como argumento"))
                 }
             }
 
-            index += 1;
-
+            // Address 0 is TREZE, so the parameters start at 1
             instructions.push(Instruction::PushValMathB(arg));
-            Instruction::WriteVarToLast(index + 1);
+            instructions.push(Instruction::WriteVarToLast(index + 1));
         }
 
         instructions.push(Instruction::SetLastFrameReady);
@@ -1130,11 +2735,17 @@ impl Compiler {
         Ok(())
     }
 
-    pub fn end_compiling_function(&mut self, instructions : &mut Vec<Instruction>) -> Result<(), String> {
-        // Push a return if the last instruction is not a return
+    /// Finish the function being compiled. Returns the variables declared in the function scope (including parameters)
+    /// and their addresses, so callees can read them
+    pub fn end_compiling_function(&mut self, instructions : &mut Vec<Instruction>) -> Result<HashMap<String, usize>, String> {
+        // Push a return if the last instruction is not a return. The function gives null when it reaches the end, not
+        // whatever was left in the register (which may even be a text that was freed already)
         match instructions.last() {
             Some(Instruction::Return) | None => {}
-            Some(_) => instructions.push(Instruction::Return)
+            Some(_) => {
+                instructions.push(Instruction::PushValMathB(RawValue::Null));
+                instructions.push(Instruction::Return);
+            }
         };
 
         match self.scopes.pop() {
@@ -1144,13 +2755,266 @@ impl Compiler {
                     _ => return Err("Fim da função encontrado, mas algum scope foi deixado aberto".to_owned()),
                 }
 
+                let variables = s.symbol_table.iter()
+                    .map(|(name, entry)| (name.clone(), entry.address))
+                    .collect();
+
+                // Without the release of the variables at the end, since the return already left
+                let requested = self.annotations.contains(&Annotation::Inline);
+                let current = self.current_function;
+
+                if let Some(info) = self.functions.values_mut().find(|f| f.kind == FunctionKind::Source && Some(f.address) == current) {
+                    info.inline_body = inline_body(instructions, info.arguments.len(), requested);
+                }
+
                 self.end_scope(s, instructions);
 
                 self.current_scope = ScopeKind::Global;
+                self.unassigned.clear();
+                self.current_function = None;
+                self.self_call_end = None;
+                self.annotations.clear();
 
-                Ok(())
+                Ok(variables)
             }
             None => Err("".to_owned())
         }
     }
 }
+
+mod tests {
+    #[test]
+    fn name_suggestions() {
+        use context::Context;
+
+        let compile = |src : &str| -> String {
+            let mut ctx = Context::new();
+
+            let result = ctx.call_function_by_id(0, vec![])
+                .and_then(|_| ctx.add_standard_library())
+                .and_then(|_| ctx.add_source_string(src.to_owned()));
+
+            match result {
+                Ok(_) => panic!("Compilou sem erro : {}", src),
+                Err(e) => e.into(),
+            }
+        };
+
+        let error = compile("JAULA SHOW\nVEM: BAMBAMZINHO, 1\nCE QUER VER ISSO: BAMBANZINHO\nSAINDO DA JAULA");
+        assert!(error.contains("Cê quis dizer BAMBAMZINHO?"), "{}", error);
+
+        let error = compile("É HORA DO: DISTANCIA DE EDICAO, \"A\", \"B\"");
+        assert!(error.contains("Função DISTANCIA DE EDICAO não encontrada. Cê quis dizer DISTÂNCIA DE EDIÇÃO?"), "{}", error);
+
+        // Case doesn't count as a typo
+        let error = compile("JAULA TESTE\nSAINDO DA JAULA\nÉ HORA DO: teste");
+        assert!(error.contains("Cê quis dizer TESTE?"), "{}", error);
+
+        let error = compile("VEM: FRANGU, 1\nVEM: FRANGA, 2\nVEM: FRANGOS, 3\nCE QUER VER ISSO: FRANG");
+        assert!(error.contains("Cê quis dizer FRANGA, FRANGO ou FRANGU?"), "{}", error);
+
+        let error = compile("VEM: T, \"BIRL\"\nÉ HORA DO: T.TAMANHA");
+        assert!(error.contains("Cê quis dizer TAMANHO?"), "{}", error);
+
+        // Nothing close enough, nothing suggested
+        let error = compile("CE QUER VER ISSO: BAMBAM");
+        assert!(! error.contains("quis dizer"), "{}", error);
+    }
+
+    #[test]
+    fn symbol_table() {
+        use context::Context;
+        use compiler::FunctionKind;
+        use parser::TypeKind;
+
+        let mut ctx = Context::new();
+
+        let result = ctx.call_function_by_id(0, vec![])
+            .and_then(|_| ctx.add_standard_library())
+            .and_then(|_| ctx.add_source_string("VEM: LIMITE, 10\n\
+                                                 JAULA DOBRO(X : BATATA DOCE)\n\
+                                                 BIRL: X * 2\n\
+                                                 SAINDO DA JAULA\n\
+                                                 JAULA SHOW\n\
+                                                 SAINDO DA JAULA".to_owned()))
+            .and_then(|_| ctx.add_method(TypeKind::Integer, "DOBRADO".to_owned(), "DOBRO".to_owned()));
+
+        assert_eq!(result, Ok(()));
+
+        let symbols = ctx.symbol_table();
+
+        let dobro = symbols.function("DOBRO").unwrap();
+        assert_eq!((dobro.kind, dobro.arity(), dobro.parameters.as_slice()),
+                   (FunctionKind::Source, 1, &[TypeKind::Integer][..]));
+        assert_eq!(symbols.function_by_id(dobro.id), Some(dobro));
+        assert_eq!(symbols.function("SHOW").unwrap().id, 1);
+        assert_eq!(symbols.function("TIRA ACENTOS").unwrap().kind, FunctionKind::Plugin);
+        assert!(symbols.function("__global__").is_none());
+
+        // The functions written in BIRL come first, in order of ID
+        let kinds = symbols.functions.iter().map(|f| f.kind).collect::<Vec<FunctionKind>>();
+        assert!(kinds.windows(2).all(|k| k[0] == k[1] || k[1] == FunctionKind::Plugin));
+
+        let limite = symbols.global("LIMITE").unwrap();
+        assert!(limite.writeable);
+        assert!(! symbols.global("UM").unwrap().writeable);
+        assert!(symbols.globals.windows(2).all(|g| g[0].address < g[1].address));
+
+        let methods = symbols.methods_named("DOBRADO");
+        assert_eq!((methods.len(), methods[0].receiver, methods[0].function.as_str()), (1, TypeKind::Integer, "DOBRO"));
+
+        assert_eq!(symbols.complete("DOB"), ["DOBRADO", "DOBRO"]);
+        assert_eq!(symbols.complete("LIM"), ["LIMITE"]);
+    }
+
+    #[test]
+    fn range_loop_counter() {
+        use context::Context;
+        use diagnostic::CODE_SHADOWING;
+        use error::BirlError;
+        use testing::run_in_context;
+
+        let run = |src : &str| {
+            let mut ctx = Context::new();
+            let (result, output) = run_in_context(&mut ctx, src);
+            let shadowing = ctx.take_warnings().into_iter().filter(|w| w.code == CODE_SHADOWING).count();
+
+            (result, output, shadowing)
+        };
+
+        let (result, output, shadowing) = run("JAULA SHOW\nREPETE: I, 0, 3\nCE QUER VER ISSO: I\nFIM\n\
+                                                REPETE: I, 3, 0, (-1)\nCE QUER VER ISSO: I\nFIM\nSAINDO DA JAULA");
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "0\n1\n2\n3\n2\n1\n");
+        assert_eq!(shadowing, 0);
+
+        // The counter is gone after the loop
+        let (result, _, _) = run("JAULA SHOW\nREPETE: I, 0, 3\nFIM\nCE QUER VER ISSO: I\nSAINDO DA JAULA");
+        assert!(matches!(result, Err(BirlError::Compile(ref message, _))
+                         if message.contains("Variável não encontrada : I")),
+                "{:?}", result);
+
+        // A variable with the same name is hidden during the loop and untouched by it, but can be the start
+        let (result, output, shadowing) = run("JAULA SHOW\nVEM: I, 10\nREPETE: I, I, 12\nCE QUER VER ISSO: I\nFIM\n\
+                                                CE QUER VER ISSO: I\nSAINDO DA JAULA");
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "10\n11\n10\n");
+        assert_eq!(shadowing, 1);
+    }
+
+    #[test]
+    fn unassigned_reads() {
+        use context::Context;
+        use diagnostic::CODE_UNASSIGNED;
+
+        // The lines of the warnings about variables read without a value
+        let warnings = |src : &str| -> Vec<Option<usize>> {
+            let mut ctx = Context::new();
+
+            if let Err(e) = ctx.add_source_string(src.to_owned()) {
+                panic!("{}", e);
+            }
+
+            ctx.take_warnings().into_iter().filter(|w| w.code == CODE_UNASSIGNED).map(|w| w.line).collect()
+        };
+
+        assert_eq!(warnings("JAULA SHOW\nVEM: X\nCE QUER VER ISSO: X + 1\nCE QUER VER ISSO: X\nSAINDO DA JAULA"),
+                   vec![Some(3)]);
+        assert_eq!(warnings("JAULA SHOW\nVEM: X\nBORA: X, 1\nCE QUER VER ISSO: X\nSAINDO DA JAULA"), vec![]);
+
+        // A block may not run, so what it assigns doesn't count after it
+        assert_eq!(warnings("JAULA SHOW\nVEM: X\nE ELE QUE A GENTE QUER: 1, 2\nE ELE MEMO\nBORA: X, 1\n\
+                             CE QUER VER ISSO: X\nFIM\nCE QUER VER ISSO: X\nSAINDO DA JAULA"), vec![Some(8)]);
+        assert_eq!(warnings("JAULA SHOW\nVEM: X\nBORA: X, 0\nE ELE QUE A GENTE QUER: 1, 2\nE ELE MEMO\nBORA: X, 1\n\
+                             FIM\nCE QUER VER ISSO: X\nSAINDO DA JAULA"), vec![]);
+
+        // In a loop, the first time through it has no value yet
+        assert_eq!(warnings("JAULA SHOW\nVEM: X\nREPETE: I, 0, 3\nCE QUER VER ISSO: X\nBORA: X, I\nFIM\nSAINDO DA JAULA"),
+                   vec![Some(4)]);
+
+        // Nothing after a return runs
+        assert_eq!(warnings("JAULA SHOW\nVEM: X\nE ELE QUE A GENTE QUER: 1, 2\nE ELE MEMO\nBIRL\nCE QUER VER ISSO: X\n\
+                             FIM\nSAINDO DA JAULA"), vec![]);
+
+        // Commands that use the variable, and the ones that give it a value
+        assert_eq!(warnings("JAULA SHOW\nVEM: L\nPÕE ISSO AQUI: L, 1\nSAINDO DA JAULA"), vec![Some(3)]);
+        assert_eq!(warnings("JAULA SHOW\nVEM: N\nMUDA PRA NUMERO: N\nSAINDO DA JAULA"), vec![Some(3)]);
+        assert_eq!(warnings("JAULA SHOW\nVEM: L\nFAZ UMA LISTA: L\nPÕE ISSO AQUI: L, 1\nSAINDO DA JAULA"), vec![]);
+
+        // Globals can be assigned by any function
+        assert_eq!(warnings("VEM: G\nJAULA SHOW\nCE QUER VER ISSO: G\nSAINDO DA JAULA"), vec![]);
+
+        let mut ctx = Context::new();
+        ctx.set_strict_mode(true);
+
+        match ctx.add_source_string("JAULA SHOW\nVEM: X\nCE QUER VER ISSO: X\nSAINDO DA JAULA".to_owned()) {
+            Ok(_) => panic!("Compilou sem erro"),
+            Err(e) => assert!(e.message().starts_with("X pode ser usada sem ter recebido um valor"), "{}", e),
+        }
+    }
+
+    #[test]
+    fn annotations() {
+        use context::{ Context, BIRL_MAIN_FUNCTION_ID };
+        use parser::Annotation;
+        use vm_io::CaptureIo;
+
+        let src = "FICHA: TESTE\n\
+                   JAULA CONFERE\n\
+                   SAINDO DA JAULA\n\
+                   # Comments and empty lines can come between them\n\
+                   FICHA: PRINCIPAL\n\
+                   \n\
+                   FICHA: SEM MODO ESTRITO, EM LINHA\n\
+                   JAULA COMEÇO\n\
+                   VEM: X\n\
+                   CE QUER VER ISSO: X\n\
+                   SAINDO DA JAULA";
+
+        let io = CaptureIo::new("");
+        let output = io.output();
+
+        let mut ctx = Context::new();
+        let _ = ctx.set_io(Box::new(io));
+        ctx.set_strict_mode(true);
+
+        ctx.call_function_by_id(0, vec![]).unwrap();
+
+        // The warning doesn't stop the compilation, since the function is marked without strict mode
+        if let Err(e) = ctx.add_source_string(src.to_owned()) {
+            panic!("{}", e);
+        }
+
+        assert_eq!(ctx.take_warnings().len(), 1);
+
+        let symbols = ctx.symbol_table();
+        assert_eq!(symbols.function("CONFERE").unwrap().annotations, vec![Annotation::Test]);
+
+        // The entry point runs in place of SHOW
+        let start = symbols.function("COMEÇO").unwrap();
+        assert_eq!(start.id, BIRL_MAIN_FUNCTION_ID);
+        assert_eq!(start.annotations, vec![Annotation::EntryPoint, Annotation::NoStrict, Annotation::Inline]);
+        assert!(symbols.function("SHOW").is_none());
+
+        assert_eq!(ctx.start_program(), Ok(()));
+        assert_eq!(*output.borrow(), "<Null>\n");
+
+        let errors = [
+            ("FICHA: TESTE\nVEM: X, 1\nJAULA F\nSAINDO DA JAULA", "logo antes de uma JAULA"),
+            ("JAULA F\nFICHA: TESTE\nSAINDO DA JAULA", "fora de outras funções"),
+            ("JAULA F\nSAINDO DA JAULA\nFICHA: TESTE", "sem uma JAULA depois"),
+            ("FICHA: RAPIDINHO\nJAULA F\nSAINDO DA JAULA", "não existe"),
+            ("FICHA: TESTE, TESTE\nJAULA F\nSAINDO DA JAULA", "repetida"),
+            ("FICHA: PRINCIPAL\nJAULA F\nSAINDO DA JAULA\nJAULA SHOW\nSAINDO DA JAULA", "Múltipla declaração"),
+            ("FICHA: PRINCIPAL\nJAULA F (A : FIBRA)\nSAINDO DA JAULA", "não deve pedir argumentos"),
+        ];
+
+        for &(src, message) in errors.iter() {
+            let mut ctx = Context::new();
+            ctx.call_function_by_id(0, vec![]).unwrap();
+
+            let error = ctx.add_source_string(src.to_owned()).unwrap_err();
+            assert!(error.message().contains(message), "{} : {}", src, error);
+        }
+    }
+}
@@ -1,13 +1,24 @@
 //! Hosts the runtime for the birlscript language
 
-use vm::{VirtualMachine, ExecutionStatus, PluginFunction, Instruction};
-use parser::{ parse_line, TypeKind, ParserResult, IntegerType, FunctionDeclaration };
-use compiler::{ Compiler, CompilerHint };
+use vm::{VirtualMachine, VirtualMachineBuilder, DecimalSeparator, OverflowPolicy, DynamicValue, Registers, ExecutionStatus, PluginFunction, Instruction, HeapItem, Steps, INSTRUCTION_SET_VERSION};
+use parser::{ parse_line, TypeKind, ParserResult, IntegerType, FunctionDeclaration, CommandKind, CommandArgument, Annotation };
+use compiler::{ Compiler, CompilerHint, GlobalDeclaration, Warning, SymbolTable };
 use modules::*;
 use standard_lib::module_standard_library;
 
-use std::io::{ BufRead, BufReader, Write };
-use std::fs::File;
+use vm_io::VmIo;
+use diagnostic::{ CODE_PARSE_ERROR, CODE_COMPILE_ERROR };
+use testing::AssertionFailure;
+use error::BirlError;
+use bytecode::{ CompiledProgram, CompiledFunction };
+use disassembler::{ disassemble, DisassembledFunction };
+use optimizer::{ peephole, move_source_lines };
+
+use std::io::{ BufRead, BufReader };
+use std::fs::{ self, File };
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
 
 pub const BIRL_COPYRIGHT : &'static str 
     = "© 2016 - 2019 Rafael Rodrigues Nakano";
@@ -20,11 +31,54 @@ pub const BIRL_MAIN_FUNCTION_ID     : usize = 1;
 pub const BIRL_GLOBAL_FUNCTION_ID   : usize = 0;
 pub const BIRL_RET_VAL_VAR_ADDRESS  : usize = 0;
 
+/// Comment that turns on the monster mode for the whole source it's in, written as `# USE MODO MONSTRO`
+pub const MONSTER_MODE_PRAGMA : &str = "USE MODO MONSTRO";
+
+/// Names of the optional features (as in Cargo.toml) this interpreter was built with
+pub fn enabled_features() -> Vec<&'static str> {
+    let mut features = vec![];
+
+    if cfg!(feature = "i128") {
+        features.push("i128");
+    }
+
+    if cfg!(feature = "bot") {
+        features.push("bot");
+    }
+
+    if cfg!(feature = "toml") {
+        features.push("toml");
+    }
+
+    if cfg!(feature = "yaml") {
+        features.push("yaml");
+    }
+
+    if cfg!(feature = "sqlite") {
+        features.push("sqlite");
+    }
+
+    if cfg!(feature = "clipboard") {
+        features.push("clipboard");
+    }
+
+    if cfg!(feature = "http") {
+        features.push("http");
+    }
+
+    if cfg!(feature = "wasm") {
+        features.push("wasm");
+    }
+
+    features
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum RawValue {
     Text(String),
     Integer(IntegerType),
     Number(f64),
+    Bool(bool),
     Null,
 }
 
@@ -34,47 +88,133 @@ impl RawValue {
             &RawValue::Integer(_) => TypeKind::Integer,
             &RawValue::Number(_) => TypeKind::Number,
             &RawValue::Text(_) => TypeKind::Text,
+            &RawValue::Bool(_) => TypeKind::Bool,
             &RawValue::Null => TypeKind::Null,
         }
     }
 }
 
+/// Where adding source code failed
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    pub line : usize,
+    /// CODE_PARSE_ERROR or CODE_COMPILE_ERROR, from `diagnostic`
+    pub code : &'static str,
+    pub message : String,
+}
+
 pub struct Context {
     vm : VirtualMachine,
     has_main : bool,
     compiler : Compiler,
     current_code_id : usize,
+    last_compile_error : Option<CompileError>,
+    /// File the source was last added from, to say where errors while running happened
+    source_file : Option<String>,
+    monster_mode : bool,
+    /// Annotations written with FICHA, waiting for the function declared next
+    pending_annotations : Vec<Annotation>,
+    /// Whether the code of each function goes through the peephole optimizer when it's done
+    peephole : bool,
 }
 
 impl Context {
-    /// Alias for vm.set_stdout().
-    pub fn set_stdout(&mut self, write: Option<Box<Write>>) -> Option<Box<Write>>{
-        self.vm.set_stdout(write)
+    /// Alias for vm.set_io().
+    pub fn set_io(&mut self, io : Box<dyn VmIo>) -> Box<dyn VmIo> {
+        self.vm.set_io(io)
+    }
+
+    /// Alias for vm.set_max_text_size().
+    pub fn set_max_text_size(&mut self, size : Option<usize>) {
+        self.vm.set_max_text_size(size)
+    }
+
+    /// Alias for vm.set_max_list_size().
+    pub fn set_max_list_size(&mut self, size : Option<usize>) {
+        self.vm.set_max_list_size(size)
     }
 
-    /// Alias for vm.set_stdin().
-    pub fn set_stdin(&mut self, read: Option<Box<BufRead>>) -> Option<Box<BufRead>>{
-        self.vm.set_stdin(read)
+    /// Alias for vm.set_max_special_items().
+    pub fn set_max_special_items(&mut self, count : Option<usize>) {
+        self.vm.set_max_special_items(count)
+    }
+
+    /// Alias for vm.set_max_call_depth().
+    pub fn set_max_call_depth(&mut self, depth : Option<usize>) {
+        self.vm.set_max_call_depth(depth)
+    }
+
+    /// Alias for vm.set_gc_threshold().
+    pub fn set_gc_threshold(&mut self, count : Option<usize>) {
+        self.vm.set_gc_threshold(count)
+    }
+
+    /// Alias for vm.set_random_seed().
+    pub fn set_random_seed(&mut self, seed : u64) {
+        self.vm.set_random_seed(seed)
+    }
+
+    /// Alias for vm.set_fuel().
+    pub fn set_fuel(&mut self, fuel : u64) {
+        self.vm.set_fuel(fuel)
+    }
+
+    /// Alias for vm.get_fuel().
+    pub fn get_fuel(&self) -> Option<u64> {
+        self.vm.get_fuel()
+    }
+
+    /// Alias for vm.set_decimal_separator().
+    pub fn set_decimal_separator(&mut self, separator : DecimalSeparator) {
+        self.vm.set_decimal_separator(separator)
+    }
+
+    /// Alias for vm.source_line_at().
+    pub fn source_line_at(&self, id : usize, instruction : usize) -> Option<usize> {
+        self.vm.source_line_at(id, instruction)
+    }
+
+    /// Alias for vm.set_auto_flush().
+    pub fn set_auto_flush(&mut self, auto_flush : Option<bool>) {
+        self.vm.set_auto_flush(auto_flush)
+    }
+
+    /// Alias for vm.flush_stdout().
+    pub fn flush_output(&mut self) {
+        self.vm.flush_stdout()
     }
 
     pub fn new() -> Context {
-        let mut vm = VirtualMachine::new();
+        match Context::with_vm(VirtualMachineBuilder::new()) {
+            Ok(c) => c,
+            Err(e) => unreachable!("A VM padrão sempre pode ser criada : {}", e),
+        }
+    }
+
+    /// Create a context with a VM configured by the builder
+    pub fn with_vm(builder : VirtualMachineBuilder) -> Result<Context, String> {
+        let mut vm = builder.build()?;
         let _ = vm.add_new_code(); // For global
         let _ = vm.add_new_code(); // For main
 
-        Context {
+        Ok(Context {
             vm,
             has_main : false,
             compiler : Compiler::new(),
             current_code_id : 0,
-        }
+            last_compile_error : None,
+            source_file : None,
+            monster_mode : false,
+            pending_annotations : vec![],
+            peephole : false,
+        })
     }
 
     fn add_function(&mut self, f : FunctionDeclaration) -> Result<(), String> {
-        let is_main = f.name == BIRL_MAIN_FUNCTION;
+        let is_main = f.name == BIRL_MAIN_FUNCTION || f.annotations.contains(&Annotation::EntryPoint);
         if is_main {
             if self.has_main {
-                return Err("Erro: Múltipla declaração da função principal".to_owned());
+                return Err("Erro: Múltipla declaração da função principal (a SHOW ou uma FICHA: PRINCIPAL)".to_owned());
             }
             if f.arguments.len() != 0 {
                 return Err("Erro : Declaração da função principal inválida : A função principal não deve pedir argumentos".to_owned());
@@ -88,23 +228,104 @@ impl Context {
             self.vm.add_new_code()
         };
 
-        self.compiler.begin_compiling_function(id, f.arguments, f.name)?;
+        self.vm.set_function(f.name.clone(), id, f.arguments.iter().map(|a| a.kind).collect());
+
+        self.compiler.begin_compiling_function(id, f.arguments, f.name, f.annotations)?;
 
         self.current_code_id = id;
 
         Ok(())
     }
 
+    /// Whether a main function (SHOW) was declared
+    pub fn has_main(&self) -> bool {
+        self.has_main
+    }
+
+    /// Report warnings (like variables hiding others) as errors
+    pub fn set_strict_mode(&mut self, strict : bool) {
+        self.compiler.set_strict_mode(strict);
+    }
+
+    /// Replace the calls to small functions by their code, which is on by default. Turning it off makes errors inside
+    /// them point to their lines, for debugging. Only changes the code compiled after it
+    pub fn set_inlining(&mut self, inlining : bool) {
+        self.compiler.set_inlining(inlining);
+    }
+
+    /// Pass the code of the functions compiled after this through the peephole optimizer (see `optimizer::peephole`),
+    /// which is off by default. The global code is left as it is, since it may be running while more of it is compiled
+    pub fn set_peephole(&mut self, peephole : bool) {
+        self.peephole = peephole;
+    }
+
+    /// Rewrite the code of a function that was just compiled, moving its lines along
+    fn optimize_function(&mut self, id : usize) -> Result<(), String> {
+        let moved = match self.vm.get_code_for(id) {
+            Some(code) => {
+                let (optimized, moved) = peephole(code);
+                *code = optimized;
+
+                moved
+            }
+            None => return Err(format!("Erro interno : Função {} não encontrada pra otimizar", id)),
+        };
+
+        let lines = self.vm.source_lines(id).map(|lines| move_source_lines(lines, &moved)).unwrap_or_default();
+
+        self.vm.set_source_lines(id, lines)
+    }
+
+    /// Turn on every check that makes mistakes errors instead of surprises, for classrooms : warnings are errors,
+    /// variables have to be declared with a value, comparing values of different types fails and integers that don't
+    /// fit fail instead of wrapping. Arithmetic with Null is always an error
+    pub fn enable_monster_mode(&mut self) {
+        self.monster_mode = true;
+        self.compiler.set_strict_mode(true);
+        self.compiler.set_require_initialization(true);
+        self.vm.set_strict_comparisons(true);
+        self.vm.set_overflow_policy(OverflowPolicy::Error);
+    }
+
+    /// Whether the monster mode was turned on, by the host or by the pragma in the source
+    pub fn is_monster_mode(&self) -> bool {
+        self.monster_mode
+    }
+
+    /// Whether one of the lines is the monster mode pragma
+    fn has_monster_mode_pragma(lines : &[String]) -> bool {
+        lines.iter().any(|line| match line.trim().strip_prefix('#') {
+            Some(comment) => comment.trim().to_uppercase() == MONSTER_MODE_PRAGMA,
+            None => false,
+        })
+    }
+
+    /// Alias for compiler.symbol_table().
+    pub fn symbol_table(&self) -> SymbolTable {
+        self.compiler.symbol_table()
+    }
+
+    /// Return the warnings emitted by the compiler since the last call
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        self.compiler.take_warnings()
+    }
+
     pub fn set_interactive_mode(&mut self) {
         self.vm.set_interactive_mode();
     }
     
     pub fn end_function(&mut self) -> Result<(), String>{
-        match self.vm.get_code_for(self.current_code_id) {
+        let variables = match self.vm.get_code_for(self.current_code_id) {
             Some(f) => self.compiler.end_compiling_function(f)?,
             None => return Err("Nenhuma função em compilação".to_owned())
         };
 
+        self.vm.set_variable_names(self.current_code_id, variables)?;
+
+        if self.peephole {
+            self.optimize_function(self.current_code_id)?;
+        }
+
         self.current_code_id = BIRL_GLOBAL_FUNCTION_ID;
 
         Ok(())
@@ -114,23 +335,57 @@ impl Context {
 
         let result = match parse_line(line) {
             Ok(r) => r,
-            Err(e) => return Err(e)
+            Err(e) => return Err(e.into())
         };
 
+        self.process_parsed(result)
+    }
+
+    /// Compile the result of parsing a line
+    fn process_parsed(&mut self, result : ParserResult) -> Result<Option<CompilerHint>, String> {
+        match result {
+            ParserResult::Annotations(_) | ParserResult::FunctionStart(_) | ParserResult::Nothing => {}
+            _ if ! self.pending_annotations.is_empty() => {
+                self.pending_annotations.clear();
+
+                return Err("A FICHA tem que vir logo antes de uma JAULA".to_owned());
+            }
+            _ => {}
+        }
+
         match result {
+            ParserResult::Annotations(annotations) => {
+                if self.current_code_id != BIRL_GLOBAL_FUNCTION_ID {
+                    return Err("A FICHA tem que vir antes de uma JAULA, fora de outras funções".to_owned());
+                }
+
+                for annotation in annotations {
+                    if ! self.pending_annotations.contains(&annotation) {
+                        self.pending_annotations.push(annotation);
+                    }
+                }
+
+                Ok(None)
+            }
             ParserResult::Command(cmd) => {
-                let hint = {
+                let (hint, first_instruction) = {
                     let instructions = match self.vm.get_code_for(self.current_code_id) {
                         Some(i) => i,
                         None => return Err(format!("Erro ao pegar o código para a função atual"))
                     };
 
+                    let first_instruction = instructions.len();
+
                     match self.compiler.compile_command(cmd, instructions) {
-                        Ok(hint) => hint,
+                        Ok(hint) => (hint, first_instruction),
                         Err(e) => return Err(e)
                     }
                 };
 
+                if let Some(line) = self.compiler.current_line() {
+                    self.vm.add_source_line(self.current_code_id, first_instruction, line)?;
+                }
+
                 Ok(hint)
             }
             ParserResult::FunctionEnd => {
@@ -138,7 +393,9 @@ impl Context {
 
                 Ok(Some(CompilerHint::ScopeEnd))
             },
-            ParserResult::FunctionStart(func) => {
+            ParserResult::FunctionStart(mut func) => {
+                func.annotations = ::std::mem::take(&mut self.pending_annotations);
+
                 self.add_function(func)?;
 
                 Ok(Some(CompilerHint::ScopeStart))
@@ -147,56 +404,334 @@ impl Context {
         }
     }
 
-    pub fn add_source_string(&mut self, string : String) -> Result<(), String> {
+    /// Find every global variable declared in the source (outside of functions), along with the line it's declared
+    /// and the variables used in its initial value. Lines that fail to parse are ignored, they're reported when compiled
+    fn scan_global_declarations(lines : &[String]) -> HashMap<String, GlobalDeclaration> {
+        let mut declarations = HashMap::new();
+        let mut in_function = false;
+
+        for (index, line) in lines.iter().enumerate() {
+            let cmd = match parse_line(line.as_str()) {
+                Ok(ParserResult::FunctionStart(_)) => {
+                    in_function = true;
+                    continue;
+                }
+                Ok(ParserResult::FunctionEnd) => {
+                    in_function = false;
+                    continue;
+                }
+                Ok(ParserResult::Command(cmd)) => cmd,
+                _ => continue,
+            };
+
+            if in_function || cmd.kind != CommandKind::Declare {
+                continue;
+            }
+
+            let name = match cmd.arguments.first() {
+                Some(CommandArgument::Name(n)) => n.clone(),
+                _ => continue,
+            };
+
+            let mut dependencies = vec![];
+
+            if let Some(CommandArgument::Expression(expr)) = cmd.arguments.get(1) {
+                expr.root.collect_symbols(&mut dependencies);
+            }
+
+            declarations.entry(name).or_insert_with(|| GlobalDeclaration::from(index + 1, dependencies));
+        }
+
+        declarations
+    }
+
+    /// Compile all lines of a source. Errors say the file and line they came from
+    fn add_source_lines(&mut self, lines : Vec<String>, file : Option<&str>) -> Result<(), BirlError> {
+        if Context::has_monster_mode_pragma(&lines) {
+            self.enable_monster_mode();
+        }
+
+        self.compiler.set_global_declarations(Context::scan_global_declarations(&lines));
+
+        let mut result = Ok(());
+        self.last_compile_error = None;
+        self.source_file = file.map(|f| f.to_owned());
+
+        for (index, line) in lines.iter().enumerate() {
+            self.compiler.set_current_line(Some(index + 1));
+
+            let processed = match parse_line(line.as_str()) {
+                Ok(parsed) => self.process_parsed(parsed)
+                    .map_err(|e| (CODE_COMPILE_ERROR, BirlError::Compile(e, Default::default()))),
+                Err(e) => Err((CODE_PARSE_ERROR, e)),
+            };
+
+            if let Err((code, error)) = processed {
+                let error = error.at_line(index + 1);
+
+                self.last_compile_error = Some(CompileError { line : index + 1, code, message : error.message().to_owned() });
+
+                result = Err(match file {
+                    Some(f) => error.in_file(f),
+                    None => error,
+                });
+
+                break;
+            }
+        }
+
+        // A FICHA in the last lines has no function to go with
+        if ! self.pending_annotations.is_empty() {
+            self.pending_annotations.clear();
+
+            if result.is_ok() {
+                result = Err(BirlError::Compile("A FICHA no fim do código ficou sem uma JAULA depois dela".to_owned(),
+                                                Default::default()));
+            }
+        }
+
+        self.compiler.clear_global_declarations();
+        self.compiler.set_current_line(None);
+
+        result
+    }
+
+    /// Line and message of the error from the last time source was added, if it failed
+    pub fn last_compile_error(&self) -> Option<&CompileError> {
+        self.last_compile_error.as_ref()
+    }
+
+    /// Put the file the source came from in an error from running the program
+    fn locate_error(&self, error : BirlError) -> BirlError {
+        match self.source_file {
+            Some(ref f) => error.in_file(f),
+            None => error,
+        }
+    }
+
+    /// ID, name and code of every function written in BIRL, in order of ID
+    pub fn compiled_functions(&self) -> Vec<(usize, String, &[Instruction])> {
+        let mut functions = vec![];
+
+        for (name, id) in self.compiler.source_functions() {
+            if let Some(code) = self.vm.get_code_ref(id) {
+                functions.push((id, name, code.as_slice()));
+            }
+        }
+
+        functions
+    }
+
+    /// Everything compiled so far (the code of every function, including the global one, and what's needed to run it),
+    /// to be saved in a `.birlc` file
+    pub fn compiled_program(&self) -> CompiledProgram {
+        let mut functions = vec![];
+
+        for id in 0..self.vm.get_next_code_id() {
+            let name = self.vm.function_name(id).map(|n| n.to_owned());
+            let parameters = name.as_ref()
+                .and_then(|n| self.vm.function_parameters(n))
+                .map(|p| p.to_vec())
+                .unwrap_or_default();
+
+            functions.push(CompiledFunction {
+                name,
+                parameters,
+                code : self.vm.get_code_ref(id).cloned().unwrap_or_default(),
+                variables : self.vm.variable_names(id).cloned().unwrap_or_default(),
+                source_lines : self.vm.source_lines(id).map(|l| l.to_vec()).unwrap_or_default(),
+            });
+        }
+
+        CompiledProgram {
+            source_file : self.source_file.clone(),
+            plugins : self.compiler.plugin_functions().into_iter().map(|(name, _)| name).collect(),
+            globals : self.compiler.global_variables(),
+            functions,
+        }
+    }
+
+    /// The listing of the code compiled so far, with the names and jumps the instructions refer to
+    pub fn disassemble(&self) -> Vec<DisassembledFunction> {
+        disassemble(&self.compiled_program())
+    }
+
+    /// Load a program compiled before, instead of compiling the source. The context can't have any code yet, and needs
+    /// the same plugins and global variables the program was compiled with (the standard library, if it was used, has
+    /// to be added before)
+    pub fn load_program(&mut self, program : CompiledProgram) -> Result<(), String> {
+        let has_code = |id| self.vm.get_code_ref(id).map(|c| ! c.is_empty()).unwrap_or(false);
+
+        if self.has_main || has_code(BIRL_GLOBAL_FUNCTION_ID) {
+            return Err("O programa compilado só pode ser carregado num contexto que ainda não tem código".to_owned());
+        }
+
+        if program.functions.len() <= BIRL_MAIN_FUNCTION_ID {
+            return Err("O programa compilado não tem o código global e o da função principal".to_owned());
+        }
+
+        // Everything is checked before changing anything, so a program that can't be loaded leaves the context as it was
+
+        let plugins = self.compiler.plugin_functions().into_iter().map(|(name, _)| name).collect::<Vec<String>>();
+
+        if plugins != program.plugins {
+            let index = plugins.iter().zip(&program.plugins).take_while(|&(a, b)| a == b).count();
+
+            let difference = match (program.plugins.get(index), plugins.get(index)) {
+                (Some(expected), Some(found)) => format!("o plugin {} devia ser {}, mas aqui é {}", index, expected, found),
+                (Some(expected), None) => format!("falta o plugin {}", expected),
+                (None, Some(found)) => format!("sobrou o plugin {}", found),
+                (None, None) => unreachable!(),
+            };
+
+            return Err(format!("O programa foi compilado com outros plugins ({}). Roda ele com as mesmas opções que \
+                                usou pra compilar, como --sem-padrão", difference));
+        }
+
+        let globals = self.compiler.global_variables().into_iter()
+            .map(|(name, address, _)| (name, address))
+            .collect::<HashMap<String, usize>>();
+
+        for &(ref name, address, _) in &program.globals {
+            match globals.get(name) {
+                Some(&current) if current != address => {
+                    return Err(format!("A variável global {} está no endereço {} no programa compilado, mas no {} aqui",
+                                       name, address, current));
+                }
+                _ => {}
+            }
+        }
+
+        let existing = self.vm.get_next_code_id();
+
+        if program.functions.len() < existing {
+            return Err(format!("O programa compilado tem {} funções, mas o contexto já tem {}", program.functions.len(), existing));
+        }
+
+        // Functions that already exist (from a module) have to be the same ones, in the same place
+        for (id, function) in program.functions.iter().enumerate().take(existing).skip(BIRL_MAIN_FUNCTION_ID + 1) {
+            if self.vm.function_name(id) != function.name.as_deref() {
+                return Err(format!("A função {} do programa compilado devia ser {}, mas aqui é {}", id,
+                                   function.name.as_deref().unwrap_or("<nenhuma>"),
+                                   self.vm.function_name(id).unwrap_or("<nenhuma>")));
+            }
+        }
+
+        for (name, address, writeable) in program.globals {
+            if ! globals.contains_key(&name) {
+                self.compiler.add_global_symbol(name, address, writeable)?;
+            }
+        }
+
+        for (id, function) in program.functions.into_iter().enumerate() {
+            if id >= existing {
+                let _ = self.vm.add_new_code();
+            }
+
+            match self.vm.get_code_for(id) {
+                Some(code) => *code = function.code,
+                None => return Err(format!("ID inválido pra código : {}", id)),
+            }
+
+            self.vm.set_variable_names(id, function.variables)?;
+            self.vm.set_source_lines(id, function.source_lines)?;
+
+            if let Some(name) = function.name {
+                if id == BIRL_MAIN_FUNCTION_ID {
+                    self.has_main = true;
+                }
+
+                self.vm.set_function(name.clone(), id, function.parameters.clone());
+                self.compiler.add_source_function_definition(id, function.parameters, name);
+            }
+        }
+
+        self.source_file = program.source_file;
+
+        Ok(())
+    }
+
+    /// Save everything compiled so far in a `.birlc` file
+    pub fn save_bytecode(&self, filename : &str) -> Result<(), String> {
+        match fs::write(filename, self.compiled_program().to_bytes()) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("Não deu pra escrever o arquivo {} : {}", filename, e)),
+        }
+    }
+
+    /// Load the program of a `.birlc` file, like `load_program`
+    pub fn add_bytecode_file(&mut self, filename : &str) -> Result<(), String> {
+        let bytes = match fs::read(filename) {
+            Ok(b) => b,
+            Err(e) => return Err(format!("Não deu pra ler o arquivo {} : {}", filename, e)),
+        };
+
+        self.load_program(CompiledProgram::from_bytes(&bytes)?)
+    }
+
+    fn split_lines(string : &str) -> Result<Vec<String>, String> {
         let reader = BufReader::new(string.as_bytes());
+        let mut lines = vec![];
 
         for line in reader.lines() {
             match line {
-                Ok(line) => {
-                    match self.process_line(line.as_str()) {
-                        Ok(_) => {}
-                        Err(e) => return Err(e)
-                    }
-                }
+                Ok(line) => lines.push(line),
                 Err(e) => return Err(format!("{:?}", e))
             }
         }
 
-        Ok(())
+        Ok(lines)
+    }
+
+    pub fn add_source_string(&mut self, string : String) -> Result<(), BirlError> {
+        let lines = Context::split_lines(&string)?;
+
+        self.add_source_lines(lines, None)
     }
 
-    pub fn add_file(&mut self, filename : &str) -> Result<(), String> {
+    /// Like add_source_string, but the errors say the source came from `file`, like with add_file
+    pub fn add_named_source(&mut self, file : &str, string : String) -> Result<(), BirlError> {
+        let lines = Context::split_lines(&string)?;
+
+        self.add_source_lines(lines, Some(file))
+    }
+
+    pub fn add_file(&mut self, filename : &str) -> Result<(), BirlError> {
         let file = match File::open(filename) {
             Ok(f) => f,
-            Err(e) => return Err(format!("{:?}", e)),
+            Err(e) => return Err(BirlError::Io(format!("{:?}", e), Default::default()).in_file(filename)),
         };
 
-        let mut line_num = 0usize;
-
         let reader = BufReader::new(file);
+        let mut lines = vec![];
 
         for line in reader.lines() {
-            line_num += 1;
             match line {
-                Ok(line) => {
-                    match self.process_line(line.as_str()) {
-                        Ok(_) => {}
-                        Err(e) => return Err(format!("(Linha {}) : {:?}", line_num, e))
-                    }
-                }
-                Err(e) => return Err(format!("(Linha {}) : {:?}", line_num, e))
+                Ok(line) => lines.push(line),
+                Err(e) => return Err(BirlError::Io(format!("{:?}", e), Default::default()).in_file(filename).at_line(lines.len() + 1))
             }
         }
 
-        Ok(())
+        self.add_source_lines(lines, Some(filename))
     }
 
-    pub fn add_plugin(&mut self, name : String, parameters : Vec<TypeKind>, code : PluginFunction) -> Result<(), String> {
-        let index = self.vm.add_new_plugin(code);
+    /// Add a plugin that the code can call by its name, like any function
+    pub fn add_plugin(&mut self, name : String, parameters : Vec<TypeKind>, code : PluginFunction) -> Result<(), BirlError> {
+        if self.compiler.has_function(&name) {
+            return Err(BirlError::Compile(format!("Erro adicionando plugin : Já existe uma função chamada {}", name),
+                                          Default::default()));
+        }
 
-        self.compiler.add_plugin_function_definition(index, parameters, name)?;
+        let index = self.vm.add_named_plugin(name.clone(), parameters.clone(), code)?;
 
-        Ok(())
+        self.compiler.add_plugin_function_definition(index, parameters, name)
+            .map_err(|e| BirlError::Compile(e, Default::default()))
+    }
+
+    /// Bind a function (plugin or written in BIRL) as a method of a type, so `X.METHOD, args` calls it with X as the
+    /// first argument when X has that type
+    pub fn add_method(&mut self, receiver : TypeKind, method : String, function : String) -> Result<(), BirlError> {
+        self.compiler.add_method(receiver, method, function).map_err(|e| BirlError::Compile(e, Default::default()))
     }
 
     pub fn add_global_variable(&mut self, name : String, value : RawValue, writeable : bool) -> Result<(), String> {
@@ -204,19 +739,63 @@ impl Context {
 
         self.compiler.compile_global_variable(name, value, writeable, &mut inst)?;
 
+        self.run_host_instructions(inst)
+    }
+
+    /// Reserve a global for the host to share data with the code, like the input of a request or a configuration. The
+    /// code can read and change it, but not declare it again, and the host can read it with `global_value` and change
+    /// it with `set_global` before each run. Reserve it before adding the code that uses it
+    pub fn reserve_global(&mut self, name : String, value : RawValue) -> Result<(), String> {
+        let mut inst = vec![];
+
+        self.compiler.compile_reserved_global(name, value, &mut inst)?;
+
+        self.run_host_instructions(inst)
+    }
+
+    /// Change the value of a global variable that can be changed, like one reserved with `reserve_global`
+    pub fn set_global(&mut self, name : &str, value : RawValue) -> Result<(), String> {
+        let address = match self.compiler.global_variables().into_iter().find(|g| g.0 == name) {
+            Some((_, address, true)) => address,
+            Some(_) => return Err(format!("A variável global {} não pode ser mudada", name)),
+            None => return Err(format!("Variável global não encontrada : {}", name)),
+        };
+
+        self.run_host_instructions(vec![Instruction::PushValMathB(value), Instruction::WriteGlobalVarTo(address)])
+    }
+
+    /// Value of a global variable. Texts, lists and maps are references, that `value_to_string` turns into text
+    pub fn global_value(&self, name : &str) -> Result<DynamicValue, String> {
+        let address = match self.compiler.global_variables().into_iter().find(|g| g.0 == name) {
+            Some((_, address, _)) => address,
+            None => return Err(format!("Variável global não encontrada : {}", name)),
+        };
+
+        match self.vm.global_variable(address) {
+            Some(value) => Ok(value),
+            None => Err(format!("A variável global {} ainda não tem valor", name)),
+        }
+    }
+
+    /// Run instructions made for the host, outside of any code, like the ones that write a global
+    fn run_host_instructions(&mut self, inst : Vec<Instruction>) -> Result<(), String> {
         for i in inst {
             match self.vm.run(i)? {
                 ExecutionStatus::Halt => break,
                 ExecutionStatus::Quit => return Err("VM Quitou enquanto adicionava var".to_owned()),
                 ExecutionStatus::Normal => {}
-                ExecutionStatus::Returned => return Err("VM Retornou enquanto adicionava var".to_owned())
+                ExecutionStatus::Returned => return Err("VM Retornou enquanto adicionava var".to_owned()),
+                status => return Err(format!("VM parou enquanto adicionava var : {:?}", status)),
             }
         }
 
         Ok(())
     }
 
-    pub fn add_module(&mut self, module : Module) -> Result<(), String> {
+    /// Add the globals, functions, plugins and methods of a module. An error in the code of its functions is a compile
+    /// error, without a location
+    pub fn add_module(&mut self, module : Module) -> Result<(), BirlError> {
+        let compile_error = |e : String| BirlError::Compile(e, Default::default());
 
         for var in module.global_variables {
             self.add_global_variable(var.name, var.value, var.writeable)?;
@@ -226,83 +805,209 @@ impl Context {
             let mut decl = FunctionDeclaration::from(src.name);
             decl.arguments = src.parameters;
 
-            self.add_function(decl)?;
+            self.add_function(decl).map_err(compile_error)?;
 
             for c in src.body {
                 let instructions = match self.vm.get_code_for(self.current_code_id) {
                     Some(i) => i,
-                    None => return Err(format!("Erro ao pegar o código para a função atual"))
+                    None => return Err(format!("Erro ao pegar o código para a função atual").into())
                 };
 
                 match self.compiler.compile_command(c, instructions) {
                     Ok(_) => {},
-                    Err(e) => return Err(e)
+                    Err(e) => return Err(compile_error(e))
                 }
             }
 
-            self.end_function()?;
+            self.end_function().map_err(compile_error)?;
         }
 
         for plg in module.plugin_functions {
             self.add_plugin(plg.name, plg.parameters, plg.func)?;
         }
 
+        for method in module.methods {
+            self.add_method(method.receiver, method.name, method.function)?;
+        }
+
         Ok(())
     }
 
     /// Prepares the context to begin executing interactive code again after an Halt
-    pub fn interactive_prepare_resume(&mut self) -> Result<(), String>
+    pub fn interactive_prepare_resume(&mut self) -> Result<(), BirlError>
     {
         // all this does is put an Halt in the end of the global function, so the program stops on its own
 
         match self.vm.get_code_for(BIRL_GLOBAL_FUNCTION_ID) {
             Some(c) => c.push(Instruction::Halt),
-            None => return Err("Contexto não foi iniciado corretamente".to_owned())
+            None => return Err("Contexto não foi iniciado corretamente".to_owned().into())
         }
 
         Ok(())
     }
 
-    pub fn add_standard_library(&mut self) -> Result<(), String> {
+    pub fn add_standard_library(&mut self) -> Result<(), BirlError> {
         let m = module_standard_library();
 
         self.add_module(m)
     }
 
-    pub fn call_function_by_id(&mut self, id : usize, args : Vec<RawValue>) -> Result<(), String> {
+    pub fn call_function_by_id(&mut self, id : usize, args : Vec<RawValue>) -> Result<(), BirlError> {
         let mut instructions = vec![];
 
         match self.compiler.compile_function_call(id, args, &mut instructions) {
             Ok(_) => {}
-            Err(e) => return Err(e),
+            Err(e) => return Err(BirlError::Compile(e, Default::default())),
         }
 
         for i in instructions {
             match self.vm.run(i) {
                 Ok(_) => {}
-                Err(e) => return Err(e)
+                Err(e) => return Err(self.locate_error(e))
             }
         }
 
         Ok(())
     }
 
-    pub fn execute_next_instruction(&mut self) -> Result<ExecutionStatus, String> {
+    /// Run one instruction, without the checks of `step` (breakpoints, input). Use `step` or `run_steps` instead
+    #[doc(hidden)]
+    pub fn execute_next_instruction(&mut self) -> Result<ExecutionStatus, BirlError> {
         self.vm.execute_next_instruction()
     }
 
-    pub fn start_program(&mut self) -> Result<(), String> {
-        // Global function is already running
+    /// Alias for vm.resume().
+    pub fn resume(&mut self) -> Result<ExecutionStatus, BirlError> {
+        self.vm.resume()
+    }
+
+    /// Alias for vm.step().
+    pub fn step(&mut self) -> Result<ExecutionStatus, BirlError> {
+        self.vm.step()
+    }
+
+    /// Alias for vm.continue_run().
+    pub fn continue_run(&mut self) -> Result<ExecutionStatus, BirlError> {
+        self.vm.continue_run()
+    }
+
+    /// Alias for vm.set_breakpoint().
+    pub fn set_breakpoint(&mut self, id : usize, instruction : usize) {
+        self.vm.set_breakpoint(id, instruction)
+    }
+
+    /// Alias for vm.remove_breakpoint().
+    pub fn remove_breakpoint(&mut self, id : usize, instruction : usize) -> bool {
+        self.vm.remove_breakpoint(id, instruction)
+    }
+
+    /// Alias for vm.clear_breakpoints().
+    pub fn clear_breakpoints(&mut self) {
+        self.vm.clear_breakpoints()
+    }
+
+    /// Alias for vm.current_location().
+    pub fn current_location(&self) -> Option<(usize, usize)> {
+        self.vm.current_location()
+    }
+
+    /// Alias for vm.current_frame_stack().
+    pub fn current_frame_stack(&self) -> Option<&[DynamicValue]> {
+        self.vm.current_frame_stack()
+    }
+
+    /// Alias for vm.current_frame_variables().
+    pub fn current_frame_variables(&self) -> Vec<(String, DynamicValue)> {
+        self.vm.current_frame_variables()
+    }
+
+    /// Alias for vm.get_registers().
+    #[doc(hidden)]
+    pub fn get_registers(&self) -> &Registers {
+        self.vm.get_registers()
+    }
+
+    /// Alias for vm.value_to_string().
+    pub fn value_to_string(&mut self, value : DynamicValue) -> Result<String, String> {
+        self.vm.value_to_string(value)
+    }
+
+    /// Alias for vm.run_steps().
+    pub fn run_steps(&mut self, count : usize) -> Result<ExecutionStatus, BirlError> {
+        self.vm.run_steps(count)
+    }
 
+    /// Alias for vm.run_for().
+    pub fn run_for(&mut self, count : usize) -> ExecutionStatus {
+        self.vm.run_for(count)
+    }
+
+    /// Alias for vm.steps().
+    pub fn steps(&mut self) -> Steps<'_> {
+        self.vm.steps()
+    }
+
+    /// Alias for vm.stop_reason().
+    pub fn stop_reason(&self) -> Option<ExecutionStatus> {
+        self.vm.stop_reason()
+    }
+
+    /// Alias for vm.set_await_input().
+    pub fn set_await_input(&mut self, await_input : bool) {
+        self.vm.set_await_input(await_input)
+    }
+
+    /// Alias for vm.provide_input().
+    pub fn provide_input(&mut self, line : String) {
+        self.vm.provide_input(line)
+    }
+
+    /// Alias for vm.dump_heap().
+    pub fn dump_heap(&self) -> String {
+        self.vm.dump_heap()
+    }
+
+    /// Alias for vm.collect_garbage().
+    pub fn collect_garbage(&mut self) -> Result<usize, String> {
+        self.vm.collect_garbage()
+    }
+
+    /// Alias for vm.find_leaks().
+    pub fn find_leaks(&self) -> Vec<HeapItem> {
+        self.vm.find_leaks()
+    }
+
+    /// Alias for vm.take_assertion_failure().
+    pub fn take_assertion_failure(&mut self) -> Option<AssertionFailure> {
+        self.vm.take_assertion_failure()
+    }
+
+    /// Alias for vm.interrupt_handle().
+    pub fn interrupt_handle(&self) -> Arc<AtomicBool> {
+        self.vm.interrupt_handle()
+    }
+
+    /// Resume until the code stops by itself (Halt or Quit). Breakpoints are ignored, and any other reason to stop is
+    /// an error, since there's no one to deal with it
+    fn run_until_stopped(&mut self) -> Result<ExecutionStatus, BirlError> {
         loop {
-            match self.execute_next_instruction() {
-                Ok(ExecutionStatus::Normal) => {}
-                Ok(ExecutionStatus::Returned) => {}
-                Ok(ExecutionStatus::Halt) => break,
-                Ok(ExecutionStatus::Quit) => break,
-                Err(e) => return Err(e)
+            match ExecutionStatus::from(self.vm.resume()) {
+                ExecutionStatus::Error(e) => return Err(self.locate_error(*e)),
+                ExecutionStatus::Breakpoint => {}
+                ExecutionStatus::Halt => return Ok(ExecutionStatus::Halt),
+                ExecutionStatus::Quit => return Ok(ExecutionStatus::Quit),
+                ExecutionStatus::Interrupted => return Err("Execução interrompida".to_owned().into()),
+                ExecutionStatus::OutOfFuel => return Err("Acabou o combustível da VM".to_owned().into()),
+                ExecutionStatus::AwaitingInput => return Err("O programa tá esperando input, mas nenhum foi dado".to_owned().into()),
+                ExecutionStatus::Normal | ExecutionStatus::Returned | ExecutionStatus::Paused => {}
             }
         }
+    }
+
+    pub fn start_program(&mut self) -> Result<(), BirlError> {
+        // Global function is already running
+
+        self.run_until_stopped()?;
 
         self.vm.unset_quit();
 
@@ -312,22 +1017,38 @@ impl Context {
                 Err(e) => return Err(e)
             }
 
-            loop {
-                match self.execute_next_instruction() {
-                    Ok(ExecutionStatus::Normal) => {}
-                    Ok(ExecutionStatus::Returned) => {}
-                    Ok(ExecutionStatus::Halt) => break,
-                    Ok(ExecutionStatus::Quit) => return Ok(()),
-                    Err(e) => return Err(e)
-                }
-            }
+            self.run_until_stopped()?;
         }
 
         Ok(())
     } 
     
+    /// Like start_program, but runs the function with the given id (which takes no arguments) instead of SHOW. Used
+    /// to run tests
+    pub fn start_function(&mut self, id : usize) -> Result<(), BirlError> {
+        self.run_function(id, vec![]).map(|_| ())
+    }
+
+    /// Finish what's running (the global code, the first time), then call the function with the given id and run it
+    /// until it ends. Can be called many times on the same VM, keeping the globals, like to handle each line of an
+    /// input, even after a call quits or fails. Gives Quit if the code quit, and Halt if not
+    pub fn run_function(&mut self, id : usize, args : Vec<RawValue>) -> Result<ExecutionStatus, BirlError> {
+        // After a quit or an error, what was running doesn't go on
+        match self.vm.stop_reason() {
+            Some(ExecutionStatus::Quit) | Some(ExecutionStatus::Error(_)) => self.vm.unwind_to_global()?,
+            _ => { self.run_until_stopped()?; }
+        }
+
+        self.vm.unset_quit();
+
+        self.call_function_by_id(id, args)?;
+
+        self.run_until_stopped()
+    }
+
     pub fn print_version() {
         println!("{}", BIRL_VERSION);
+        println!("Conjunto de instruções v{}", INSTRUCTION_SET_VERSION);
         println!("{}", BIRL_COPYRIGHT);
     }
 }
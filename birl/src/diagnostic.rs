@@ -0,0 +1,273 @@
+//! Errors and warnings in a form that tools (editors, the playground, etc) can use without reading the messages
+
+use compiler::Warning;
+use context::CompileError;
+use error::BirlError;
+
+/// The line is in a form the parser doesn't understand
+pub const CODE_PARSE_ERROR : &str = "E0001";
+/// The line was parsed but couldn't be compiled (unknown variable, wrong types, etc)
+pub const CODE_COMPILE_ERROR : &str = "E0002";
+/// An error while running the program
+pub const CODE_RUNTIME_ERROR : &str = "E0003";
+/// A variable was declared again in the same scope
+pub const CODE_REDECLARATION : &str = "W0001";
+/// A declaration hides a parameter, a global or a variable from an outer scope
+pub const CODE_SHADOWING : &str = "W0002";
+/// A variable declared without a value may be read before it gets one
+pub const CODE_UNASSIGNED : &str = "W0003";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// Where in the source something happened. Lines and columns start at 1, and the end column is exclusive
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub line : usize,
+    pub column_start : usize,
+    pub column_end : usize,
+}
+
+impl Span {
+    /// Span covering the whole line (without the whitespace around it)
+    pub fn whole_line(line : usize, source_line : &str) -> Span {
+        let indent = source_line.chars().take_while(|c| c.is_whitespace()).count();
+        let length = source_line.trim().chars().count();
+
+        Span {
+            line,
+            column_start : indent + 1,
+            column_end : indent + length + 1,
+        }
+    }
+
+    /// Span of the first time `name` is written as a whole word in the arguments of the line (after the `:` of the
+    /// command, and not inside a text), or None if it isn't there
+    pub fn of_name(line : usize, source_line : &str, name : &str) -> Option<Span> {
+        let chars = source_line.chars().collect::<Vec<char>>();
+        let name = name.chars().collect::<Vec<char>>();
+
+        let is_word = |c : char| c.is_alphanumeric() || c == '_';
+        let arguments = chars.iter().position(|&c| c == ':').map_or(0, |p| p + 1);
+
+        let mut in_text = false;
+        let mut escaped = false;
+
+        for start in arguments..chars.len() {
+            let c = chars[start];
+
+            if in_text {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    '"' => in_text = false,
+                    _ => {}
+                }
+
+                continue;
+            }
+
+            if c == '"' {
+                in_text = true;
+                continue;
+            }
+
+            let end = start + name.len();
+
+            if ! name.is_empty() && end <= chars.len() && chars[start..end] == name[..]
+                && (start == 0 || ! is_word(chars[start - 1])) && (end == chars.len() || ! is_word(chars[end])) {
+                return Some(Span { line, column_start : start + 1, column_end : end + 1 });
+            }
+        }
+
+        None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity : Severity,
+    /// One of the CODE_ constants, if it's known what kind of problem it is
+    pub code : Option<&'static str>,
+    pub message : String,
+    pub file : Option<String>,
+    pub span : Option<Span>,
+    /// The name the message is about, which `with_source` points at instead of the whole line
+    pub name : Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message : String) -> Diagnostic {
+        Diagnostic { severity : Severity::Error, code : None, message, file : None, span : None, name : None }
+    }
+
+    pub fn warning(message : String) -> Diagnostic {
+        Diagnostic { severity : Severity::Warning, code : None, message, file : None, span : None, name : None }
+    }
+
+    pub fn from_warning(warning : &Warning) -> Diagnostic {
+        let mut diagnostic = Diagnostic::warning(warning.message.clone());
+        diagnostic.code = Some(warning.code);
+        diagnostic.span = warning.line.map(|line| Span { line, column_start : 1, column_end : 1 });
+        diagnostic.name = warning.name.clone();
+
+        diagnostic
+    }
+
+    pub fn from_compile_error(error : &CompileError) -> Diagnostic {
+        let mut diagnostic = Diagnostic::error(error.message.clone());
+        diagnostic.code = Some(error.code);
+        diagnostic.span = Some(Span { line : error.line, column_start : 1, column_end : 1 });
+
+        diagnostic
+    }
+
+    /// An error from running the program, pointing to the line where it happened if it's known
+    pub fn from_runtime_error(error : &BirlError) -> Diagnostic {
+        let location = error.location();
+
+        let mut diagnostic = Diagnostic::error(error.message().to_owned());
+        diagnostic.code = Some(CODE_RUNTIME_ERROR);
+        diagnostic.file = location.file.clone();
+        diagnostic.span = location.line.map(|line| Span { line, column_start : 1, column_end : 1 });
+
+        diagnostic
+    }
+
+    pub fn with_code(mut self, code : &'static str) -> Diagnostic {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn with_file(mut self, file : &str) -> Diagnostic {
+        self.file = Some(file.to_owned());
+        self
+    }
+
+    /// Make the span cover the name the message is about, or the whole line it's in, given the source it came from
+    pub fn with_source(mut self, source : &str) -> Diagnostic {
+        if let Some(span) = self.span {
+            if let Some(line) = source.lines().nth(span.line.saturating_sub(1)) {
+                let of_name = self.name.as_ref().and_then(|name| Span::of_name(span.line, line, name));
+
+                self.span = Some(of_name.unwrap_or_else(|| Span::whole_line(span.line, line)));
+            }
+        }
+
+        self
+    }
+
+    /// A single line of JSON, like
+    /// `{"file":"a.birl","span":{"line":3,"column_start":5,"column_end":24},"severity":"error","code":"E0002","message":"..."}`
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+
+        json.push_str("{\"file\":");
+        match self.file {
+            Some(ref f) => push_json_string(&mut json, f),
+            None => json.push_str("null"),
+        }
+
+        json.push_str(",\"span\":");
+        match self.span {
+            Some(span) => json.push_str(&format!("{{\"line\":{},\"column_start\":{},\"column_end\":{}}}",
+                                                 span.line, span.column_start, span.column_end)),
+            None => json.push_str("null"),
+        }
+
+        json.push_str(",\"severity\":");
+        push_json_string(&mut json, self.severity.name());
+
+        json.push_str(",\"code\":");
+        match self.code {
+            Some(c) => push_json_string(&mut json, c),
+            None => json.push_str("null"),
+        }
+
+        json.push_str(",\"message\":");
+        push_json_string(&mut json, &self.message);
+        json.push('}');
+
+        json
+    }
+}
+
+/// Append `s` to `json` as a JSON string, with quotes and escapes
+pub fn push_json_string(json : &mut String, s : &str) {
+    json.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+
+    json.push('"');
+}
+
+mod tests {
+    #[test]
+    fn diagnostic_json() {
+        use diagnostic::{ Diagnostic, Span, CODE_SHADOWING, CODE_UNASSIGNED };
+        use context::Context;
+
+        let mut ctx = Context::new();
+
+        let src = "VEM: X, 1\nJAULA SHOW\n    VEM: X, \"BIRL\"\nSAINDO DA JAULA";
+
+        if let Err(e) = ctx.add_source_string(src.to_owned()) {
+            panic!("{}", e);
+        }
+
+        let warnings = ctx.take_warnings();
+
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].code, CODE_SHADOWING);
+
+        let diagnostic = Diagnostic::from_warning(&warnings[0]).with_file("a.birl").with_source(src);
+        let expected = format!("{{\"file\":\"a.birl\",\"span\":{{\"line\":3,\"column_start\":5,\"column_end\":19}},\
+                                \"severity\":\"warning\",\"code\":\"W0002\",\"message\":\"{}\"}}", warnings[0].message);
+
+        assert_eq!(diagnostic.to_json(), expected);
+
+        // Reads of variables that may not have a value point at the variable
+        let src = "JAULA OUTRA\nVEM: NOME\nE ELE QUE A GENTE QUER: 1, 2\nE ELE MEMO\nBORA: NOME, \"A\"\nFIM\n\
+                   CE QUER VER ISSO: \"NOME: \", NOME\nSAINDO DA JAULA";
+
+        if let Err(e) = ctx.add_source_string(src.to_owned()) {
+            panic!("{}", e);
+        }
+
+        let warnings = ctx.take_warnings();
+
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].code, CODE_UNASSIGNED);
+
+        let diagnostic = Diagnostic::from_warning(&warnings[0]).with_source(src);
+
+        assert_eq!(diagnostic.span, Some(Span { line : 7, column_start : 29, column_end : 33 }));
+
+        let error = Diagnostic::error("Deu \"ruim\"\n".to_owned());
+
+        assert_eq!(error.to_json(), "{\"file\":null,\"span\":null,\"severity\":\"error\",\"code\":null,\
+                                     \"message\":\"Deu \\\"ruim\\\"\\n\"}");
+    }
+}
@@ -0,0 +1,571 @@
+//! Differential testing : the same program is run in different ways that must give the same result, and any
+//! difference is a bug. Random programs (with integers, texts, conditionals and loops) are generated from a seed and
+//! run both by a reference evaluator, that just walks the generated program in the simplest way possible, and by the
+//! VM in each of its configurations (the default one, with the arena, collecting garbage after every instruction and
+//! loaded from bytecode). When the VM is redesigned (a new way to jump, to keep the values, to represent them), running
+//! a few thousand seeds catches the changes in what programs do :
+//!
+//! ```
+//! use birl::differential::check_generated;
+//!
+//! for seed in 0..20 {
+//!     let mismatches = check_generated(seed);
+//!     assert!(mismatches.is_empty(), "{:?}", mismatches);
+//! }
+//! ```
+
+use context::{ Context, BIRL_GLOBAL_FUNCTION_ID };
+use bytecode::CompiledProgram;
+use parser::IntegerType;
+use standard_lib::module_deterministic_standard_library;
+use vm::VirtualMachineBuilder;
+use vm_io::CaptureIo;
+
+/// Seed of the random numbers of the VMs, so the programs given to `check_source` can use them
+const RANDOM_SEED : u64 = 13;
+
+/// Maximum depth of the blocks (conditionals and loops) in a generated program
+const MAX_BLOCK_DEPTH : usize = 3;
+
+/// Maximum depth of a generated expression
+const MAX_EXPRESSION_DEPTH : usize = 3;
+
+/// A way of running a program in the VM
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Engine {
+    /// A VM with the default configuration
+    Default,
+    /// A VM that keeps texts and lists in an arena
+    Arena,
+    /// A VM that runs the garbage collector after every instruction
+    Collecting,
+    /// The program compiled in a context, saved as bytecode and loaded in another
+    Bytecode,
+}
+
+/// Every engine, in the order they're run
+pub const ENGINES : [Engine; 4] = [Engine::Default, Engine::Arena, Engine::Collecting, Engine::Bytecode];
+
+/// What a program did : what it printed and the error it stopped with, if any. The messages of the errors aren't
+/// compared, only whether there was one, since the reference evaluator doesn't have the same messages as the VM
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub output : String,
+    pub error : Option<String>,
+}
+
+impl Outcome {
+    pub fn same_as(&self, other : &Outcome) -> bool {
+        self.output == other.output && self.error.is_some() == other.error.is_some()
+    }
+}
+
+/// A run of a program that didn't do what the reference did. The reference is the evaluator for generated programs and
+/// the default engine for `check_source`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    pub source : String,
+    pub engine : Engine,
+    pub expected : Outcome,
+    pub found : Outcome,
+}
+
+fn builder_for(engine : Engine) -> VirtualMachineBuilder {
+    let builder = VirtualMachineBuilder::new().random_seed(Some(RANDOM_SEED));
+
+    match engine {
+        Engine::Arena => builder.arena_allocation(true),
+        Engine::Collecting => builder.gc_threshold(Some(1)),
+        Engine::Default | Engine::Bytecode => builder,
+    }
+}
+
+/// A context of the engine with the deterministic standard library, ready for a program
+fn context_for(engine : Engine, io : CaptureIo) -> Result<Context, String> {
+    let mut ctx = Context::with_vm(builder_for(engine))?;
+    let _ = ctx.set_io(Box::new(io));
+
+    ctx.call_function_by_id(BIRL_GLOBAL_FUNCTION_ID, vec![])?;
+    ctx.add_module(module_deterministic_standard_library())?;
+
+    Ok(ctx)
+}
+
+/// Run the source on an engine, with the given input. An error in the compilation is an Err, one while running is in
+/// the outcome
+pub fn run_on(engine : Engine, source : &str, input : &str) -> Result<Outcome, String> {
+    let io = CaptureIo::new(input);
+    let output = io.output();
+
+    let mut ctx = context_for(engine, io)?;
+
+    if engine == Engine::Bytecode {
+        let mut compiler = context_for(Engine::Default, CaptureIo::new(""))?;
+        compiler.add_source_string(source.to_owned())?;
+
+        let program = CompiledProgram::from_bytes(&compiler.compiled_program().to_bytes())?;
+        ctx.load_program(program)?;
+    } else {
+        ctx.add_source_string(source.to_owned())?;
+    }
+
+    let error = ctx.start_program().err().map(String::from);
+
+    drop(ctx);
+
+    let printed = output.borrow().clone();
+
+    Ok(Outcome { output : printed, error })
+}
+
+/// Run the source on every engine and compare them with the default one
+pub fn check_source(source : &str, input : &str) -> Result<Vec<Mismatch>, String> {
+    let expected = run_on(Engine::Default, source, input)?;
+    let mut mismatches = vec![];
+
+    for &engine in &ENGINES[1..] {
+        let found = run_on(engine, source, input)?;
+
+        if ! found.same_as(&expected) {
+            mismatches.push(Mismatch { source : source.to_owned(), engine, expected : expected.clone(), found });
+        }
+    }
+
+    Ok(mismatches)
+}
+
+/// Generate the program of the seed and compare what every engine does with what the reference evaluator does. A
+/// generated program always compiles, so failing to compile is a mismatch too
+pub fn check_generated(seed : u64) -> Vec<Mismatch> {
+    let program = GeneratedProgram::generate(seed);
+    let source = program.source();
+    let expected = program.evaluate();
+
+    let mut mismatches = vec![];
+
+    for &engine in &ENGINES {
+        let found = match run_on(engine, &source, "") {
+            Ok(outcome) => outcome,
+            Err(e) => Outcome { output : String::new(), error : Some(format!("Não compilou : {}", e)) },
+        };
+
+        if ! found.same_as(&expected) || found.error.as_ref().map(|e| e.starts_with("Não compilou")).unwrap_or(false) {
+            mismatches.push(Mismatch { source : source.clone(), engine, expected : expected.clone(), found });
+        }
+    }
+
+    mismatches
+}
+
+/// The random numbers of the generator (a SplitMix64, like the VM's), so a seed always gives the same program
+struct Generator {
+    state : u64,
+}
+
+impl Generator {
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+
+        z ^ (z >> 31)
+    }
+
+    /// A number from 0 to max - 1
+    fn below(&mut self, max : usize) -> usize {
+        (self.next() % max as u64) as usize
+    }
+
+    /// True one in `times` times
+    fn one_in(&mut self, times : usize) -> bool {
+        self.below(times) == 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+impl Operator {
+    fn symbol(&self) -> &'static str {
+        match *self {
+            Operator::Add => "+",
+            Operator::Sub => "-",
+            Operator::Mul => "*",
+            Operator::Div => "/",
+            Operator::Mod => "%",
+        }
+    }
+
+    /// The result, wrapped like the integers of the VM with the default configuration, or None if it's an error
+    fn apply(&self, left : IntegerType, right : IntegerType) -> Option<IntegerType> {
+        match *self {
+            Operator::Add => Some(left.wrapping_add(right)),
+            Operator::Sub => Some(left.wrapping_sub(right)),
+            Operator::Mul => Some(left.wrapping_mul(right)),
+            Operator::Div if right == 0 => None,
+            Operator::Div => Some(left.wrapping_div(right)),
+            Operator::Mod if right == 0 => None,
+            Operator::Mod => Some(left.wrapping_rem(right)),
+        }
+    }
+}
+
+/// An integer expression. The binary ones are always written between parenthesis, so the precedence of the operators
+/// doesn't matter
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeneratedExpression {
+    Integer(IntegerType),
+    Variable(String),
+    Binary(Box<GeneratedExpression>, Operator, Box<GeneratedExpression>),
+}
+
+/// The commands that come after a comparison, and what they accept
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Condition {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    EqualOrLess,
+    EqualOrGreater,
+}
+
+impl Condition {
+    fn command(&self) -> &'static str {
+        match *self {
+            Condition::Equal => "É ELE MEMO",
+            Condition::NotEqual => "NUM É ELE",
+            Condition::Less => "É MENOR",
+            Condition::Greater => "É MAIOR",
+            Condition::EqualOrLess => "MENOR OU É MEMO",
+            Condition::EqualOrGreater => "MAIOR OU É MEMO",
+        }
+    }
+
+    fn holds(&self, left : IntegerType, right : IntegerType) -> bool {
+        match *self {
+            Condition::Equal => left == right,
+            Condition::NotEqual => left != right,
+            Condition::Less => left < right,
+            Condition::Greater => left > right,
+            Condition::EqualOrLess => left <= right,
+            Condition::EqualOrGreater => left >= right,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeneratedCommand {
+    /// BORA in one of the integer variables
+    Assign(String, GeneratedExpression),
+    /// CE QUER VER ISSO, with a label before the value
+    Print(String, GeneratedExpression),
+    /// Add a piece to the end of the text variable
+    Append(String),
+    /// CE QUER VER ISSO of the text variable
+    PrintText,
+    /// A comparison and a block that runs depending on it
+    If(GeneratedExpression, GeneratedExpression, Condition, Vec<GeneratedCommand>),
+    /// REPETE from 0, with the loop variable and the number of times
+    Repeat(String, IntegerType, Vec<GeneratedCommand>),
+}
+
+/// Name of the variable with the text built by the program
+const TEXT_VARIABLE : &str = "TEXTO";
+
+/// A random program, in SHOW, that declares some integer variables and the text variable and then runs its commands
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratedProgram {
+    pub variables : Vec<(String, IntegerType)>,
+    pub commands : Vec<GeneratedCommand>,
+}
+
+impl GeneratedProgram {
+    pub fn generate(seed : u64) -> GeneratedProgram {
+        let mut generator = Generator { state : seed };
+
+        let variables = (0..1 + generator.below(4))
+            .map(|i| (format!("V{}", i), generator.below(20) as IntegerType))
+            .collect::<Vec<(String, IntegerType)>>();
+
+        let names = variables.iter().map(|v| v.0.clone()).collect::<Vec<String>>();
+        let commands = GeneratedProgram::generate_block(&mut generator, &names, &[], 0);
+
+        GeneratedProgram { variables, commands }
+    }
+
+    fn generate_block(generator : &mut Generator, variables : &[String], loop_variables : &[String], depth : usize)
+        -> Vec<GeneratedCommand> {
+        let count = 1 + generator.below(if depth == 0 { 8 } else { 4 });
+        let mut commands = vec![];
+
+        for _ in 0..count {
+            let readable = variables.iter().chain(loop_variables.iter()).cloned().collect::<Vec<String>>();
+
+            let kind = generator.below(if depth < MAX_BLOCK_DEPTH { 7 } else { 5 });
+
+            commands.push(match kind {
+                0 | 1 => GeneratedCommand::Assign(variables[generator.below(variables.len())].clone(),
+                                                  GeneratedProgram::generate_expression(generator, &readable, 0)),
+                2 => GeneratedCommand::Print(format!("{} : ", commands.len()),
+                                             GeneratedProgram::generate_expression(generator, &readable, 0)),
+                3 => GeneratedCommand::Append(((b'a' + generator.below(26) as u8) as char).to_string()),
+                4 => GeneratedCommand::PrintText,
+                5 => {
+                    let conditions = [Condition::Equal, Condition::NotEqual, Condition::Less, Condition::Greater,
+                                      Condition::EqualOrLess, Condition::EqualOrGreater];
+
+                    let left = GeneratedProgram::generate_expression(generator, &readable, 1);
+                    let right = GeneratedProgram::generate_expression(generator, &readable, 1);
+                    let condition = conditions[generator.below(conditions.len())];
+                    let body = GeneratedProgram::generate_block(generator, variables, loop_variables, depth + 1);
+
+                    GeneratedCommand::If(left, right, condition, body)
+                }
+                _ => {
+                    let name = format!("I{}", loop_variables.len());
+                    let times = generator.below(5) as IntegerType;
+
+                    let mut inner = loop_variables.to_vec();
+                    inner.push(name.clone());
+
+                    let body = GeneratedProgram::generate_block(generator, variables, &inner, depth + 1);
+
+                    GeneratedCommand::Repeat(name, times, body)
+                }
+            });
+        }
+
+        commands
+    }
+
+    fn generate_expression(generator : &mut Generator, variables : &[String], depth : usize) -> GeneratedExpression {
+        if depth >= MAX_EXPRESSION_DEPTH || generator.one_in(3) {
+            return if generator.one_in(2) {
+                GeneratedExpression::Variable(variables[generator.below(variables.len())].clone())
+            } else if generator.one_in(8) {
+                // Big enough to overflow when multiplied a few times
+                GeneratedExpression::Integer(1_000_000_007)
+            } else {
+                GeneratedExpression::Integer(generator.below(20) as IntegerType)
+            };
+        }
+
+        let operators = [Operator::Add, Operator::Sub, Operator::Mul, Operator::Div, Operator::Mod];
+        let operator = operators[generator.below(operators.len())];
+
+        GeneratedExpression::Binary(Box::new(GeneratedProgram::generate_expression(generator, variables, depth + 1)),
+                                    operator,
+                                    Box::new(GeneratedProgram::generate_expression(generator, variables, depth + 1)))
+    }
+
+    /// The program in BIRL
+    pub fn source(&self) -> String {
+        let mut lines = vec!["JAULA SHOW".to_owned()];
+
+        for &(ref name, value) in &self.variables {
+            lines.push(format!("VEM: {}, {}", name, value));
+        }
+
+        lines.push(format!("VEM: {}, \"\"", TEXT_VARIABLE));
+
+        GeneratedProgram::write_block(&self.commands, 1, &mut lines);
+
+        lines.push("SAINDO DA JAULA".to_owned());
+
+        lines.join("\n")
+    }
+
+    fn write_block(commands : &[GeneratedCommand], indentation : usize, lines : &mut Vec<String>) {
+        let indent = "    ".repeat(indentation);
+
+        for command in commands {
+            match *command {
+                GeneratedCommand::Assign(ref name, ref value) => {
+                    lines.push(format!("{}BORA: {}, {}", indent, name, GeneratedProgram::write_expression(value)));
+                }
+                GeneratedCommand::Print(ref label, ref value) => {
+                    lines.push(format!("{}CE QUER VER ISSO: \"{}\", {}", indent, label,
+                                       GeneratedProgram::write_expression(value)));
+                }
+                GeneratedCommand::Append(ref piece) => {
+                    lines.push(format!("{}BORA: {}, {} + \"{}\"", indent, TEXT_VARIABLE, TEXT_VARIABLE, piece));
+                }
+                GeneratedCommand::PrintText => lines.push(format!("{}CE QUER VER ISSO: {}", indent, TEXT_VARIABLE)),
+                GeneratedCommand::If(ref left, ref right, condition, ref body) => {
+                    lines.push(format!("{}É ELE QUE A GENTE QUER: {}, {}", indent,
+                                       GeneratedProgram::write_expression(left),
+                                       GeneratedProgram::write_expression(right)));
+                    lines.push(format!("{}{}:", indent, condition.command()));
+                    GeneratedProgram::write_block(body, indentation + 1, lines);
+                    lines.push(format!("{}FIM", indent));
+                }
+                GeneratedCommand::Repeat(ref name, times, ref body) => {
+                    lines.push(format!("{}REPETE: {}, 0, {}", indent, name, times));
+                    GeneratedProgram::write_block(body, indentation + 1, lines);
+                    lines.push(format!("{}FIM", indent));
+                }
+            }
+        }
+    }
+
+    fn write_expression(expression : &GeneratedExpression) -> String {
+        match *expression {
+            GeneratedExpression::Integer(i) => i.to_string(),
+            GeneratedExpression::Variable(ref name) => name.clone(),
+            GeneratedExpression::Binary(ref left, operator, ref right) => {
+                format!("({} {} {})", GeneratedProgram::write_expression(left), operator.symbol(),
+                        GeneratedProgram::write_expression(right))
+            }
+        }
+    }
+
+    /// What the program does, found by the reference evaluator : the commands are run one by one, with the variables
+    /// in a list of names and values, without going through the parser, the compiler or the VM
+    pub fn evaluate(&self) -> Outcome {
+        let mut state = Evaluation {
+            variables : self.variables.clone(),
+            text : String::new(),
+            output : String::new(),
+        };
+
+        let error = state.run_block(&self.commands).err();
+
+        Outcome { output : state.output, error }
+    }
+}
+
+struct Evaluation {
+    variables : Vec<(String, IntegerType)>,
+    text : String,
+    output : String,
+}
+
+impl Evaluation {
+    fn run_block(&mut self, commands : &[GeneratedCommand]) -> Result<(), String> {
+        for command in commands {
+            match *command {
+                GeneratedCommand::Assign(ref name, ref value) => {
+                    let value = self.evaluate(value)?;
+                    self.set(name, value);
+                }
+                GeneratedCommand::Print(ref label, ref value) => {
+                    // Each argument is printed as soon as it's evaluated, so the label comes out even on an error
+                    self.output.push_str(label);
+
+                    let value = self.evaluate(value)?;
+                    self.output.push_str(&format!("{}\n", value));
+                }
+                GeneratedCommand::Append(ref piece) => self.text.push_str(piece),
+                GeneratedCommand::PrintText => {
+                    self.output.push_str(&self.text);
+                    self.output.push('\n');
+                }
+                GeneratedCommand::If(ref left, ref right, condition, ref body) => {
+                    let left = self.evaluate(left)?;
+                    let right = self.evaluate(right)?;
+
+                    if condition.holds(left, right) {
+                        self.run_block(body)?;
+                    }
+                }
+                GeneratedCommand::Repeat(ref name, times, ref body) => {
+                    // The loop variable only exists inside the loop
+                    let count = self.variables.len();
+
+                    for i in 0..times {
+                        self.variables.truncate(count);
+                        self.variables.push((name.clone(), i));
+
+                        self.run_block(body)?;
+                    }
+
+                    self.variables.truncate(count);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn set(&mut self, name : &str, value : IntegerType) {
+        if let Some(variable) = self.variables.iter_mut().rev().find(|v| v.0 == name) {
+            variable.1 = value;
+        }
+    }
+
+    fn evaluate(&self, expression : &GeneratedExpression) -> Result<IntegerType, String> {
+        match *expression {
+            GeneratedExpression::Integer(i) => Ok(i),
+            GeneratedExpression::Variable(ref name) => match self.variables.iter().rev().find(|v| &v.0 == name) {
+                Some(variable) => Ok(variable.1),
+                None => Err(format!("Variável {} não existe", name)),
+            },
+            GeneratedExpression::Binary(ref left, operator, ref right) => {
+                let left = self.evaluate(left)?;
+                let right = self.evaluate(right)?;
+
+                operator.apply(left, right).ok_or_else(|| format!("{} {} {} não tem resultado", left,
+                                                                   operator.symbol(), right))
+            }
+        }
+    }
+}
+
+mod tests {
+    #[test]
+    fn generated_programs() {
+        use differential::{ check_generated, GeneratedProgram };
+
+        // The same seed always gives the same program
+        assert_eq!(GeneratedProgram::generate(7), GeneratedProgram::generate(7));
+
+        let mut failed = 0;
+
+        for seed in 0..300 {
+            let mismatches = check_generated(seed);
+            if let Some(mismatch) = mismatches.first() {
+                panic!("Semente {}, {:?} :\n{}\n\nEsperado {:?}\n\nEncontrado {:?}", seed, mismatch.engine,
+                       mismatch.source, mismatch.expected, mismatch.found);
+            }
+
+            if GeneratedProgram::generate(seed).evaluate().error.is_some() {
+                failed += 1;
+            }
+        }
+
+        // Some programs divide by zero, so the errors are compared too
+        assert!(failed > 0);
+    }
+
+    #[test]
+    fn engines() {
+        use differential::{ check_source, run_on, Engine };
+
+        let source = "JAULA SHOW\n\
+                      FAZ UMA LISTA: NOMES\n\
+                      VEM: N, 0\n\
+                      REPETE: I, 0, 50\n\
+                      BORA: N, I\n\
+                      MUDA PRA TEXTO: N\n\
+                      PÕE ISSO AQUI: NOMES, \"BIRL\" + N\n\
+                      FIM\n\
+                      VEM: ULTIMO, FRANGO\n\
+                      ME DÁ ESSE: NOMES, 49, ULTIMO\n\
+                      CE QUER VER ISSO: ULTIMO\n\
+                      SAINDO DA JAULA";
+
+        assert_eq!(check_source(source, ""), Ok(vec![]));
+        assert_eq!(run_on(Engine::Bytecode, source, "").map(|o| o.output), Ok("BIRL49\n".to_owned()));
+
+        assert!(run_on(Engine::Default, "JAULA SHOW\nBORA: X\nSAINDO DA JAULA", "").is_err());
+    }
+}
@@ -0,0 +1,213 @@
+//! Listing of the compiled code of a program, one instruction per line, with what the numbers in them mean : the names
+//! of the variables, functions and plugins they refer to and where the jumps go. For debugging the compiler and for
+//! seeing how BIRL is compiled
+
+use bytecode::{ CompiledProgram, CompiledFunction };
+use context::{ BIRL_GLOBAL_FUNCTION_ID, BIRL_MAIN_FUNCTION_ID, BIRL_MAIN_FUNCTION };
+use vm::Instruction;
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// An instruction and what it refers to
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisassembledInstruction {
+    pub index : usize,
+    /// The line of the source, when this is the first instruction that came from it
+    pub line : Option<usize>,
+    /// The instruction as it's written in the code of the VM
+    pub text : String,
+    /// Names and jump targets, like "G" or "-> 12", if there's anything to say
+    pub note : Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisassembledFunction {
+    pub id : usize,
+    pub name : String,
+    pub instructions : Vec<DisassembledInstruction>,
+}
+
+/// The same listing `--emit-bytecode` shows in the shell, without the colors
+impl fmt::Display for DisassembledFunction {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "== {} ({}) ==", self.name, self.id)?;
+
+        for instruction in &self.instructions {
+            if let Some(line) = instruction.line {
+                writeln!(f, "     ; linha {}", line)?;
+            }
+
+            match instruction.note {
+                Some(ref note) => writeln!(f, "{:>4} {:<32} ; {}", instruction.index, instruction.text, note)?,
+                None => writeln!(f, "{:>4} {}", instruction.index, instruction.text)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Names of the addresses, joining the ones that share an address (variables of different scopes can)
+fn names_by_address<'a, I : Iterator<Item = (&'a String, usize)>>(names : I) -> HashMap<usize, String> {
+    let mut sorted = names.collect::<Vec<(&String, usize)>>();
+    sorted.sort();
+
+    let mut result : HashMap<usize, String> = HashMap::new();
+
+    for (name, address) in sorted {
+        result.entry(address)
+            .and_modify(|names| { names.push('/'); names.push_str(name); })
+            .or_insert_with(|| name.clone());
+    }
+
+    result
+}
+
+fn function_name(program : &CompiledProgram, id : usize) -> String {
+    match program.functions.get(id).and_then(|f| f.name.as_ref()) {
+        Some(name) => name.clone(),
+        None if id == BIRL_GLOBAL_FUNCTION_ID => "__global__".to_owned(),
+        None if id == BIRL_MAIN_FUNCTION_ID => BIRL_MAIN_FUNCTION.to_owned(),
+        None => format!("<função {}>", id),
+    }
+}
+
+fn disassemble_function(program : &CompiledProgram, globals : &HashMap<usize, String>, id : usize,
+                        function : &CompiledFunction) -> DisassembledFunction {
+    let locals = names_by_address(function.variables.iter().map(|(name, &address)| (name, address)));
+    let variables_of = |id : usize| match program.functions.get(id) {
+        Some(f) => names_by_address(f.variables.iter().map(|(name, &address)| (name, address))),
+        None => HashMap::new(),
+    };
+
+    let local = |address : usize| locals.get(&address).cloned();
+    let global = |address : usize| globals.get(&address).cloned();
+
+    // Functions whose frame is being prepared (the arguments are written to the last one) and loops that were started
+    let mut frames = vec![];
+    let mut loops = vec![];
+    let mut instructions = vec![];
+    let mut last_line = None;
+
+    for (index, instruction) in function.code.iter().enumerate() {
+        let line = function.source_lines.iter().rev().find(|&&(first, _)| first <= index).map(|&(_, line)| line);
+
+        let note = match *instruction {
+            Instruction::ReadVarFrom(address) | Instruction::WriteVarTo(address) |
+            Instruction::TryDecrementRefAt(address) | Instruction::RegisterIncrementOnRestore(address) => local(address),
+            Instruction::ReadGlobalVarFrom(address) | Instruction::WriteGlobalVarTo(address) => global(address),
+            Instruction::ReadVarFromFrame(address, ref name) => {
+                let id = program.functions.iter().position(|f| f.name.as_ref() == Some(name));
+
+                id.and_then(|id| variables_of(id).remove(&address))
+            }
+            Instruction::MakeNewFrame(id) => {
+                frames.push(id);
+                Some(function_name(program, id))
+            }
+            Instruction::WriteVarToLast(address) => frames.last().and_then(|&id| variables_of(id).remove(&address)),
+            Instruction::SetLastFrameReady | Instruction::TailCall(_) => frames.pop().map(|id| function_name(program, id)),
+            Instruction::CallPlugin(id, _) => program.plugins.get(id).cloned(),
+            Instruction::Skip(count) | Instruction::SkipIfMathBFalsy(count) | Instruction::SkipIfMathBTruthy(count) => {
+                Some(format!("-> {}", index + 1 + count))
+            }
+            Instruction::DispatchMethod(_, ref table) => {
+                let targets = table.iter()
+                    .map(|&(kind, offset)| format!("{} -> {}", kind, index + 1 + offset))
+                    .collect::<Vec<String>>();
+
+                Some(targets.join(", "))
+            }
+            Instruction::Jump(target) => Some(format!("-> {}", target)),
+            Instruction::JumpIfNot(_, target) => Some(format!("se não, -> {}", target)),
+            Instruction::AddLoopLabel => {
+                loops.push(index + 1);
+                Some(format!("laço começa em {}", index + 1))
+            }
+            Instruction::RestoreLoopLabel => loops.last().map(|start| format!("-> {}", start)),
+            Instruction::PopLoopLabel => {
+                loops.pop();
+                None
+            }
+            _ => None,
+        };
+
+        instructions.push(DisassembledInstruction {
+            index,
+            line : if line != last_line { line } else { None },
+            text : format!("{:?}", instruction),
+            note,
+        });
+
+        last_line = line;
+    }
+
+    DisassembledFunction { id, name : function_name(program, id), instructions }
+}
+
+/// The listing of every function of the program, in order of ID
+pub fn disassemble(program : &CompiledProgram) -> Vec<DisassembledFunction> {
+    let globals = names_by_address(program.globals.iter().map(|&(ref name, address, _)| (name, address)));
+
+    program.functions.iter().enumerate()
+        .map(|(id, function)| disassemble_function(program, &globals, id, function))
+        .collect()
+}
+
+mod tests {
+    #[test]
+    fn disassembly() {
+        use context::Context;
+        use disassembler::disassemble;
+
+        let mut ctx = Context::new();
+        ctx.set_inlining(false);
+
+        let result = ctx.call_function_by_id(0, vec![])
+            .and_then(|_| ctx.add_standard_library())
+            .and_then(|_| ctx.add_source_string("VEM: G, 2\n\
+                                                 JAULA DOBRO (N : TRAPÉZIO DESCENDENTE)\n\
+                                                 BIRL: N * G\n\
+                                                 SAINDO DA JAULA\n\
+                                                 JAULA SHOW\n\
+                                                 REPETE: I, 0, 3\n\
+                                                 É HORA DO: DOBRO, I\n\
+                                                 FIM\n\
+                                                 É HORA DO: TAMANHO DO TEXTO, \"BIRL\"\n\
+                                                 SAINDO DA JAULA".to_owned()));
+
+        if let Err(e) = result {
+            panic!("{}", e);
+        }
+
+        let functions = disassemble(&ctx.compiled_program());
+
+        assert_eq!(functions[0].name, "__global__");
+        assert_eq!(functions[1].name, "SHOW");
+
+        let show = functions[1].to_string();
+        let dobro = match functions.iter().find(|f| f.name == "DOBRO") {
+            Some(f) => f.to_string(),
+            None => panic!("DOBRO não foi listada"),
+        };
+
+        assert!(show.starts_with("== SHOW (1) ==\n     ; linha 6\n"), "{}", show);
+        assert!(show.contains("; DOBRO\n"), "{}", show);
+        assert!(show.contains("; N\n"), "{}", show);
+        assert!(show.contains("; TAMANHO DO TEXTO\n"), "{}", show);
+        assert!(show.contains("; laço começa em"), "{}", show);
+        assert!(show.contains("; linha 9\n"), "{}", show);
+        assert!(dobro.contains("ReadGlobalVarFrom"), "{}", dobro);
+        assert!(dobro.contains("; G\n"), "{}", dobro);
+
+        // Jumps point to the instruction after the loop starts, which is the one the loop goes back to
+        let start = show.lines().find(|l| l.contains("AddLoopLabel")).and_then(|l| l.split_whitespace().next())
+            .and_then(|i| i.parse::<usize>().ok());
+
+        match start {
+            Some(start) => assert!(show.contains(&format!("RestoreLoopLabel                 ; -> {}", start + 1)), "{}", show),
+            None => panic!("{}", show),
+        }
+    }
+}
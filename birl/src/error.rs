@@ -0,0 +1,250 @@
+//! Errors with the kind of problem and where it happened, given by the entry points of the Context. They turn into
+//! (and come from) the `String` errors used in the rest of the code, so only the entry points have to deal with them
+
+use std::fmt;
+
+/// How many times the same function and line are shown in a row in the trace, before the rest is just counted
+const TRACE_REPEATS_SHOWN : usize = 3;
+
+/// Where an error happened. Everything is optional, since not every error knows all of it (code typed in the interactive
+/// console has no lines, a parse error has no instruction, etc). Lines and columns start at 1
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ErrorLocation {
+    pub file : Option<String>,
+    pub line : Option<usize>,
+    pub column : Option<usize>,
+    /// ID of the code of the function that failed, like in `Context::compiled_functions`
+    pub function_id : Option<usize>,
+    /// Index of the instruction that failed, in the code of the function it's in
+    pub instruction : Option<usize>,
+    /// The BIRL functions that were running when it happened, from the first one called to the one that failed. Empty
+    /// for errors that didn't happen while running
+    pub trace : Vec<TraceEntry>,
+}
+
+/// A function in the trace of an error, with the line it was in (the call to the next function, or the one that failed)
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEntry {
+    /// None for the global code, outside of any function
+    pub function : Option<String>,
+    pub line : Option<usize>,
+}
+
+impl ErrorLocation {
+    /// The trace like a traceback, with the most recent call last. Empty unless there was a function calling another,
+    /// since the message already says where the error happened
+    pub fn format_trace(&self) -> String {
+        if self.trace.len() < 2 {
+            return String::new();
+        }
+
+        let mut result = String::from("Rastro (a chamada mais recente por último) :");
+        let mut repeated = 0;
+
+        for (index, entry) in self.trace.iter().enumerate() {
+            // A recursion that went too deep repeats the same entry thousands of times, so only the first few are shown
+            if index >= TRACE_REPEATS_SHOWN && self.trace[index - TRACE_REPEATS_SHOWN..index].iter().all(|e| e == entry) {
+                repeated += 1;
+
+                if self.trace.get(index + 1) != Some(entry) {
+                    result.push_str(&format!("\n  [a mesma chamada se repete mais {} vez(es)]", repeated));
+                    repeated = 0;
+                }
+
+                continue;
+            }
+
+            result.push_str("\n  ");
+
+            match entry.function {
+                Some(ref name) => result.push_str(&format!("na JAULA {}", name)),
+                None => result.push_str("no código global"),
+            }
+
+            if let Some(line) = entry.line {
+                result.push_str(&format!(", linha {}", line));
+            }
+        }
+
+        result
+    }
+}
+
+/// The location is boxed, so a Result with a BirlError isn't much bigger than one with a String
+#[derive(Debug, Clone, PartialEq)]
+pub enum BirlError {
+    /// The line is in a form the parser doesn't understand
+    Parse(String, Box<ErrorLocation>),
+    /// The line was parsed but couldn't be compiled (unknown variable, wrong number of arguments, etc)
+    Compile(String, Box<ErrorLocation>),
+    /// A value of a type that can't be used there, like a text in a multiplication or a list passed as a number
+    Type(String, Box<ErrorLocation>),
+    /// Any other error while running
+    Runtime(String, Box<ErrorLocation>),
+    /// Reading input or writing output failed
+    Io(String, Box<ErrorLocation>),
+}
+
+impl BirlError {
+    pub fn message(&self) -> &str {
+        match *self {
+            BirlError::Parse(ref m, _) | BirlError::Compile(ref m, _) | BirlError::Type(ref m, _) |
+            BirlError::Runtime(ref m, _) | BirlError::Io(ref m, _) => m,
+        }
+    }
+
+    pub fn message_mut(&mut self) -> &mut String {
+        match *self {
+            BirlError::Parse(ref mut m, _) | BirlError::Compile(ref mut m, _) | BirlError::Type(ref mut m, _) |
+            BirlError::Runtime(ref mut m, _) | BirlError::Io(ref mut m, _) => m,
+        }
+    }
+
+    pub fn location(&self) -> &ErrorLocation {
+        match *self {
+            BirlError::Parse(_, ref l) | BirlError::Compile(_, ref l) | BirlError::Type(_, ref l) |
+            BirlError::Runtime(_, ref l) | BirlError::Io(_, ref l) => l,
+        }
+    }
+
+    pub fn location_mut(&mut self) -> &mut ErrorLocation {
+        match *self {
+            BirlError::Parse(_, ref mut l) | BirlError::Compile(_, ref mut l) | BirlError::Type(_, ref mut l) |
+            BirlError::Runtime(_, ref mut l) | BirlError::Io(_, ref mut l) => l,
+        }
+    }
+
+    /// Set the file, unless it's already known
+    pub fn in_file(mut self, file : &str) -> BirlError {
+        {
+            let location = self.location_mut();
+
+            if location.file.is_none() {
+                location.file = Some(file.to_owned());
+            }
+        }
+
+        self
+    }
+
+    /// Set the line, unless it's already known
+    pub fn at_line(mut self, line : usize) -> BirlError {
+        {
+            let location = self.location_mut();
+
+            if location.line.is_none() {
+                location.line = Some(line);
+            }
+        }
+
+        self
+    }
+}
+
+/// The message, with the line before it for errors in the code, like the warnings. Errors while running already say the
+/// line (and function) in the message
+impl fmt::Display for BirlError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BirlError::Parse(ref m, ref l) | BirlError::Compile(ref m, ref l) => match l.line {
+                Some(line) => write!(f, "(Linha {}) : {}", line, m),
+                None => f.write_str(m),
+            },
+            _ => f.write_str(self.message()),
+        }
+    }
+}
+
+/// Errors that come as a String have no kind, so they're runtime errors without a location
+impl From<String> for BirlError {
+    fn from(message : String) -> BirlError {
+        BirlError::Runtime(message, Box::default())
+    }
+}
+
+impl From<BirlError> for String {
+    fn from(error : BirlError) -> String {
+        match error {
+            BirlError::Parse(m, _) | BirlError::Compile(m, _) | BirlError::Type(m, _) |
+            BirlError::Runtime(m, _) | BirlError::Io(m, _) => m,
+        }
+    }
+}
+
+mod tests {
+    #[test]
+    fn error_kinds_and_locations() {
+        use context::Context;
+        use error::{ BirlError, ErrorLocation };
+        use std::env;
+        use std::fs;
+        use std::process;
+
+        let run = |src : &str| -> Option<BirlError> {
+            let mut ctx = Context::new();
+
+            ctx.add_source_string(src.to_owned())
+                .and_then(|_| ctx.call_function_by_id(0, vec![]))
+                .and_then(|_| ctx.start_program())
+                .err()
+        };
+
+        match run("JAULA SHOW\n  VEM: A, (1 +\nSAINDO DA JAULA") {
+            Some(BirlError::Parse(_, location)) => {
+                assert_eq!(location.line, Some(2));
+                assert!(location.column.is_some());
+            }
+            e => panic!("Era esperado um erro de parse, recebido {:?}", e),
+        }
+
+        match run("JAULA SHOW\nCE QUER VER ISSO: B\nSAINDO DA JAULA") {
+            Some(BirlError::Compile(_, location)) => assert_eq!(location.line, Some(2)),
+            e => panic!("Era esperado um erro de compilação, recebido {:?}", e),
+        }
+
+        // Shown with the line, since the message doesn't say it
+        let error = run("JAULA SHOW\nCE QUER VER ISSO: B\nSAINDO DA JAULA").unwrap();
+        assert_eq!(error.to_string(), format!("(Linha 2) : {}", error.message()));
+
+        match run("JAULA SHOW\nVEM: A, \"BIRL\"\nVEM: B, A * 2\nSAINDO DA JAULA") {
+            Some(BirlError::Type(_, location)) => {
+                assert_eq!(location.line, Some(3));
+                assert!(location.instruction.is_some());
+            }
+            e => panic!("Era esperado um erro de tipo, recebido {:?}", e),
+        }
+
+        match run("JAULA SHOW\nVEM: A, 0\nVEM: B, 1 / A\nSAINDO DA JAULA") {
+            Some(BirlError::Runtime(_, location)) => assert_eq!(location.line, Some(3)),
+            e => panic!("Era esperado um erro de execução, recebido {:?}", e),
+        }
+
+        assert_eq!(run("JAULA SHOW\nCE QUER VER ISSO: 1\nSAINDO DA JAULA"), None);
+
+        // Errors from a file say which file
+        let path = env::temp_dir().join(format!("birl_erro_{}.birl", process::id()));
+        fs::write(&path, "JAULA SHOW\nVEM: A, 1\nBORA: A, A - \"2\"\nSAINDO DA JAULA").unwrap();
+
+        let mut ctx = Context::new();
+        let filename = path.display().to_string();
+
+        let result = ctx.add_file(&filename)
+            .and_then(|_| ctx.call_function_by_id(0, vec![]))
+            .and_then(|_| ctx.start_program());
+
+        let _ = fs::remove_file(&path);
+
+        let error = match result {
+            Err(e) => e,
+            Ok(_) => panic!("Era esperado um erro"),
+        };
+
+        assert_eq!(error.location(), &ErrorLocation { file : Some(filename), line : Some(3), column : None,
+                                                      function_id : error.location().function_id,
+                                                      instruction : error.location().instruction,
+                                                      trace : error.location().trace.clone() });
+
+        // Turning it back into a String gives just the message
+        assert_eq!(String::from(error.clone()), error.message());
+    }
+}
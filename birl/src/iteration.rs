@@ -0,0 +1,298 @@
+//! The iteration protocol : every value that PRA CADA can go through is turned into a ValueIterator, which gives one
+//! value at a time until there are no more. A new kind of value only needs an iterator here (and a case in `iterate`)
+//! to work in loops, without new loop instructions
+
+use vm::{ VirtualMachine, DynamicValue, SpecialItemData, SpecialRef };
+use parser::IntegerType;
+
+use std::fmt::Debug;
+
+pub trait ValueIterator : Debug {
+    /// The next value, or None when there are no more
+    fn next(&mut self, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String>;
+}
+
+/// Goes through the elements of a list, in order. Elements added to the list during the loop are also visited
+#[derive(Debug)]
+pub struct ListIterator {
+    list : SpecialRef,
+    index : usize,
+}
+
+impl ValueIterator for ListIterator {
+    fn next(&mut self, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let element = match self.list.value() {
+            DynamicValue::List(id) => match vm.get_special_storage_ref().get_data_ref(id) {
+                Some(SpecialItemData::List(ref list)) => list.get(self.index).map(|e| **e),
+                _ => return Err("PRA CADA : A lista não existe mais".to_owned()),
+            },
+            _ => return Err("Erro interno : ListIterator sem uma lista".to_owned()),
+        };
+
+        self.index += 1;
+
+        Ok(element)
+    }
+}
+
+/// Goes through the characters of a text, giving each one as a new text
+#[derive(Debug)]
+pub struct TextIterator {
+    text : SpecialRef,
+    /// In bytes
+    offset : usize,
+}
+
+impl ValueIterator for TextIterator {
+    fn next(&mut self, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let character = match self.text.value() {
+            DynamicValue::Text(id) => match vm.get_special_storage_ref().get_data_ref(id) {
+                Some(SpecialItemData::Text(ref text)) => text.get(self.offset..).and_then(|rest| rest.chars().next()),
+                _ => return Err("PRA CADA : O texto não existe mais".to_owned()),
+            },
+            _ => return Err("Erro interno : TextIterator sem um texto".to_owned()),
+        };
+
+        match character {
+            Some(c) => {
+                self.offset += c.len_utf8();
+
+                let id = vm.get_special_storage_mut().add(SpecialItemData::Text(c.to_string()), 0);
+
+                Ok(Some(DynamicValue::Text(id)))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Counts from 0 up to the end (not including it)
+#[derive(Debug)]
+pub struct RangeIterator {
+    next : IntegerType,
+    end : IntegerType,
+}
+
+impl ValueIterator for RangeIterator {
+    fn next(&mut self, _ : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        if self.next >= self.end {
+            return Ok(None);
+        }
+
+        let value = self.next;
+        self.next += 1;
+
+        Ok(Some(DynamicValue::Integer(value)))
+    }
+}
+
+/// Goes through a sequence. Sequences are consumed as they're gone through, so two loops over the same sequence share
+/// the values instead of each one seeing all of them
+#[derive(Debug)]
+pub struct SequenceIterator {
+    sequence : SpecialRef,
+}
+
+impl ValueIterator for SequenceIterator {
+    fn next(&mut self, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        match self.sequence.value() {
+            DynamicValue::Sequence(id) => next_in_sequence(id, vm),
+            _ => Err("Erro interno : SequenceIterator sem uma sequência".to_owned()),
+        }
+    }
+}
+
+/// Take the next value out of the sequence with the given ID
+pub fn next_in_sequence(id : u64, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+    // The iterator is taken out of the storage while it runs, since it needs the VM (to call functions, for example)
+    let inner = match vm.get_special_storage_mut().get_data_mut(id) {
+        Some(SpecialItemData::Sequence(ref mut inner)) => inner.take(),
+        _ => return Err("A sequência não existe mais".to_owned()),
+    };
+
+    let mut inner = match inner {
+        Some(i) => i,
+        None => return Err("A sequência já está sendo percorrida".to_owned()),
+    };
+
+    let result = inner.next(vm);
+
+    if let Some(SpecialItemData::Sequence(ref mut slot)) = vm.get_special_storage_mut().get_data_mut(id) {
+        *slot = Some(inner);
+    }
+
+    result
+}
+
+/// Gives only the first values of another iterator
+#[derive(Debug)]
+pub struct TakeIterator {
+    source : Box<dyn ValueIterator>,
+    remaining : IntegerType,
+}
+
+impl TakeIterator {
+    pub fn new(source : Box<dyn ValueIterator>, count : IntegerType) -> TakeIterator {
+        TakeIterator { source, remaining : count }
+    }
+}
+
+impl ValueIterator for TakeIterator {
+    fn next(&mut self, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        if self.remaining <= 0 {
+            return Ok(None);
+        }
+
+        self.remaining -= 1;
+
+        self.source.next(vm)
+    }
+}
+
+/// Ignores the first values of another iterator, giving the rest
+#[derive(Debug)]
+pub struct SkipIterator {
+    source : Box<dyn ValueIterator>,
+    to_skip : IntegerType,
+}
+
+impl SkipIterator {
+    pub fn new(source : Box<dyn ValueIterator>, count : IntegerType) -> SkipIterator {
+        SkipIterator { source, to_skip : count }
+    }
+}
+
+impl ValueIterator for SkipIterator {
+    fn next(&mut self, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        while self.to_skip > 0 {
+            self.to_skip -= 1;
+
+            if self.source.next(vm)?.is_none() {
+                return Ok(None);
+            }
+        }
+
+        self.source.next(vm)
+    }
+}
+
+/// Gives what a function returns for each value of another iterator
+#[derive(Debug)]
+pub struct MapIterator {
+    source : Box<dyn ValueIterator>,
+    function : String,
+}
+
+impl MapIterator {
+    pub fn new(source : Box<dyn ValueIterator>, function : String) -> MapIterator {
+        MapIterator { source, function }
+    }
+}
+
+impl ValueIterator for MapIterator {
+    fn next(&mut self, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        match self.source.next(vm)? {
+            Some(value) => Ok(Some(vm.call_function(&self.function, vec![value])?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Gives only the values of another iterator for which a function returns something true (not zero, empty or null)
+#[derive(Debug)]
+pub struct FilterIterator {
+    source : Box<dyn ValueIterator>,
+    function : String,
+}
+
+impl FilterIterator {
+    pub fn new(source : Box<dyn ValueIterator>, function : String) -> FilterIterator {
+        FilterIterator { source, function }
+    }
+}
+
+impl ValueIterator for FilterIterator {
+    fn next(&mut self, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        while let Some(value) = self.source.next(vm)? {
+            let keep = vm.call_function(&self.function, vec![value])?;
+
+            if vm.is_truthy(keep)? {
+                return Ok(Some(value));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// Put an iterator in the special storage as a new sequence
+pub fn make_sequence(iterator : Box<dyn ValueIterator>, vm : &mut VirtualMachine) -> DynamicValue {
+    let id = vm.get_special_storage_mut().add(SpecialItemData::Sequence(Some(iterator)), 0);
+
+    DynamicValue::Sequence(id)
+}
+
+/// Make an iterator that goes through the value : the elements of a list, the characters of a text, the values of a
+/// sequence or, for an integer N, the numbers from 0 to N - 1
+pub fn iterate(value : DynamicValue, vm : &mut VirtualMachine) -> Result<Box<dyn ValueIterator>, String> {
+    match value {
+        DynamicValue::List(_) | DynamicValue::Text(_) | DynamicValue::Sequence(_) => {
+            // The iterator keeps the item alive until the loop ends, even if the variable changes
+            let item = match vm.retain(value)? {
+                Some(r) => r,
+                None => return Err("Erro interno : Não deu pra guardar o item a ser percorrido".to_owned()),
+            };
+
+            match value {
+                DynamicValue::List(_) => Ok(Box::new(ListIterator { list : item, index : 0 })),
+                DynamicValue::Sequence(_) => Ok(Box::new(SequenceIterator { sequence : item })),
+                _ => Ok(Box::new(TextIterator { text : item, offset : 0 })),
+            }
+        }
+        DynamicValue::Integer(end) => Ok(Box::new(RangeIterator { next : 0, end })),
+        _ => Err(format!("Não dá pra percorrer um {} com PRA CADA", value.get_kind())),
+    }
+}
+
+mod tests {
+    #[test]
+    fn for_each() {
+        use error::BirlError;
+        use testing::run_program;
+
+        let src = "JAULA SHOW\n\
+                   FAZ UMA LISTA: NOMES\n\
+                   PÕE ISSO AQUI: NOMES, \"A\"\n\
+                   PÕE ISSO AQUI: NOMES, 2\n\
+                   PÕE ISSO AQUI: NOMES, \"C\"\n\
+                   PRA CADA: NOME, NOMES\n\
+                   CE QUER VER ISSO: NOME\n\
+                   FIM\n\
+                   PRA CADA: LETRA, \"BIRLÉ\"\n\
+                   CE QUER VER: LETRA, \"-\"\n\
+                   FIM\n\
+                   CE QUER VER ISSO\n\
+                   PRA CADA: I, 5\n\
+                   É ELE QUE A GENTE QUER: I, 1\n\
+                   É ELE MEMO:\n\
+                   VAI PRO PRÓXIMO\n\
+                   FIM\n\
+                   CE QUER VER ISSO: I\n\
+                   FIM\n\
+                   SAINDO DA JAULA";
+
+        let (result, output) = run_program(src);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "A\n2\nC\nB-I-R-L-É-\n0\n2\n3\n4\n");
+
+        let (result, _) = run_program("JAULA SHOW\nPRA CADA: X, 1.5\nFIM\nSAINDO DA JAULA");
+
+        match result {
+            Ok(_) => panic!("Percorreu um número"),
+            Err(BirlError::Runtime(message, _)) =>
+                assert!(message.contains("Não dá pra percorrer um Número"), "{}", message),
+            Err(e) => panic!("{:?}", e),
+        }
+    }
+}
@@ -1,6 +1,37 @@
+extern crate ryu;
+#[cfg(feature = "toml")]
+extern crate toml;
+#[cfg(feature = "yaml")]
+extern crate yaml_rust;
+#[cfg(feature = "sqlite")]
+extern crate rusqlite;
+#[cfg(feature = "clipboard")]
+extern crate arboard;
+#[cfg(feature = "wasm")]
+extern crate wasm_bindgen;
+#[cfg(feature = "wasm")]
+extern crate js_sys;
+
+pub mod api;
 pub mod parser;
 pub mod context;
 pub mod vm;
+pub mod bytecode;
+pub mod disassembler;
+pub mod plugin;
+pub mod vm_io;
 pub mod compiler;
+pub mod optimizer;
+pub mod program;
 pub mod modules;
 pub mod standard_lib;
+pub mod diagnostic;
+pub mod error;
+pub mod playground;
+pub mod testing;
+pub mod differential;
+pub mod iteration;
+#[cfg(feature = "bot")]
+pub mod bot;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -50,10 +50,28 @@ impl SourceFunction {
     }
 }
 
+/// A function of the module bound as a method of a type, like TAMANHO for texts
+pub struct Method {
+    pub receiver : TypeKind,
+    pub name : String,
+    pub function : String,
+}
+
+impl Method {
+    pub fn new(receiver : TypeKind, name : String, function : String) -> Method {
+        Method {
+            receiver,
+            name,
+            function
+        }
+    }
+}
+
 pub struct Module {
     pub global_variables : Vec<GlobalVariable>,
     pub plugin_functions : Vec<Plugin>,
     pub source_functions : Vec<SourceFunction>,
+    pub methods : Vec<Method>,
     pub name : String,
 }
 
@@ -63,6 +81,7 @@ impl Module {
             global_variables : vec![],
             plugin_functions : vec![],
             source_functions : vec![],
+            methods : vec![],
             name,
         }
     }
@@ -0,0 +1,679 @@
+//! Optimizations done while compiling, before the code goes into the VM. Everything here has to give the same results
+//! (and the same errors) as running the code without it, except for where an error says it happened : one in the code
+//! of an inlined function has the line of the call and the function that called it, and the inlined one isn't in the
+//! trace
+
+use std::collections::HashSet;
+use parser::{ ExpressionNode, MathOperator, IntegerType };
+use context::RawValue;
+use vm::{ checked_pow, Instruction };
+
+/// Most instructions a function can have to be inlined. Bigger functions don't gain much from it, since the call is a
+/// small part of what they do, and each call would get a copy of all their code
+pub const INLINE_BUDGET : usize = 24;
+
+/// The same, for functions with FICHA: EM LINHA
+pub const REQUESTED_INLINE_BUDGET : usize = 256;
+
+/// Integers are only folded while they fit in 32 bits, the smallest width of the VM's integers. Bigger ones may wrap or
+/// overflow depending on how the VM is set up, so they're left for it
+fn fits(i : IntegerType) -> bool {
+    i as i32 as IntegerType == i
+}
+
+fn integer_operation(op : MathOperator, left : IntegerType, right : IntegerType) -> Option<RawValue> {
+    if ! fits(left) || ! fits(right) {
+        return None;
+    }
+
+    let checked : fn(IntegerType, IntegerType) -> Option<IntegerType> = match op {
+        // An integer to a negative power is a fraction
+        MathOperator::Power if right < 0 => return Some(RawValue::Number((left as f64).powf(right as f64))),
+        MathOperator::Plus => IntegerType::checked_add,
+        MathOperator::Minus => IntegerType::checked_sub,
+        MathOperator::Multiplication => IntegerType::checked_mul,
+        // Division by zero gives None, so the error stays for when the code runs
+        MathOperator::Division => IntegerType::checked_div,
+        MathOperator::Modulo => IntegerType::checked_rem,
+        MathOperator::Power => checked_pow,
+        _ => return None,
+    };
+
+    match checked(left, right) {
+        Some(result) if fits(result) => Some(RawValue::Integer(result)),
+        _ => None,
+    }
+}
+
+fn number_operation(op : MathOperator, left : f64, right : f64) -> Option<RawValue> {
+    let result = match op {
+        MathOperator::Plus => left + right,
+        MathOperator::Minus => left - right,
+        MathOperator::Multiplication => left * right,
+        MathOperator::Division => left / right,
+        MathOperator::Modulo => left % right,
+        MathOperator::Power => left.powf(right),
+        _ => return None,
+    };
+
+    Some(RawValue::Number(result))
+}
+
+/// The result of an operation between two values, if it's the same no matter how the VM is set up. None leaves the
+/// operation to be done when the code runs
+fn fold_operation(op : MathOperator, left : &RawValue, right : &RawValue) -> Option<RawValue> {
+    match (left, right) {
+        (RawValue::Integer(l), RawValue::Integer(r)) => integer_operation(op, *l, *r),
+        (RawValue::Integer(l), RawValue::Number(r)) if fits(*l) => number_operation(op, *l as f64, *r),
+        (RawValue::Number(l), RawValue::Integer(r)) if fits(*r) => number_operation(op, *l, *r as f64),
+        (RawValue::Number(l), RawValue::Number(r)) => number_operation(op, *l, *r),
+        (RawValue::Text(l), RawValue::Text(r)) if op == MathOperator::Plus => Some(RawValue::Text(format!("{}{}", l, r))),
+        _ => None,
+    }
+}
+
+/// Replace the arithmetic and the joining of texts done only on values (like `2 * 3 + 1`) with their results, so they
+/// aren't calculated every time the code runs
+pub fn fold_constants(node : ExpressionNode) -> ExpressionNode {
+    match node {
+        ExpressionNode::Unary(op, operand) => {
+            let operand = fold_constants(*operand);
+
+            // The VM does 0 - value
+            if let (MathOperator::Minus, ExpressionNode::Value(value)) = (op, &operand) {
+                if let Some(result) = fold_operation(op, &RawValue::Integer(0), value) {
+                    return ExpressionNode::Value(result);
+                }
+            }
+
+            ExpressionNode::Unary(op, Box::new(operand))
+        }
+        ExpressionNode::Binary(op, left, right) => {
+            let (left, right) = (fold_constants(*left), fold_constants(*right));
+
+            if let (ExpressionNode::Value(l), ExpressionNode::Value(r)) = (&left, &right) {
+                if let Some(result) = fold_operation(op, l, r) {
+                    return ExpressionNode::Value(result);
+                }
+            }
+
+            ExpressionNode::Binary(op, Box::new(left), Box::new(right))
+        }
+        ExpressionNode::Logical(op, left, right) =>
+            ExpressionNode::Logical(op, Box::new(fold_constants(*left)), Box::new(fold_constants(*right))),
+        ExpressionNode::Chain(ops, operands) =>
+            ExpressionNode::Chain(ops, operands.into_iter().map(fold_constants).collect()),
+        ExpressionNode::Map(entries) =>
+            ExpressionNode::Map(entries.into_iter().map(|(k, v)| (fold_constants(k), fold_constants(v))).collect()),
+        other => other,
+    }
+}
+
+/// Code of a function that can take the place of the calls to it, running in the frame of the caller
+#[derive(Debug, Clone)]
+pub struct InlineBody {
+    /// Everything the function does, ending with a return
+    instructions : Vec<Instruction>,
+    parameters : usize,
+    /// How many addresses the variables of the function take, its TREZE and parameters included
+    slots : usize,
+    reads_treze : bool,
+}
+
+/// Instructions that only work in a frame of their own : calls, loops and the values kept for PEGA DA JAULA. A plugin
+/// gives its result in the TREZE of the last frame, which for inlined code is the one of the caller
+fn needs_own_frame(instruction : &Instruction) -> bool {
+    matches!(*instruction,
+        Instruction::MakeNewFrame(_) | Instruction::SetLastFrameReady | Instruction::TailCall(_) |
+        Instruction::CallPlugin(_, _) | Instruction::WriteVarToLast(_) | Instruction::ReadVarFromFrame(_, _) |
+        Instruction::AddLoopLabel | Instruction::RestoreLoopLabel | Instruction::PopLoopLabel |
+        Instruction::RegisterIncrementOnRestore(_) | Instruction::IterateMathB | Instruction::NextFromIterator |
+        Instruction::CompareLoopIndex | Instruction::PushReturnValue | Instruction::ReadReturnValue(_))
+}
+
+fn local_address(instruction : &Instruction) -> Option<usize> {
+    match *instruction {
+        Instruction::ReadVarFrom(address) | Instruction::WriteVarTo(address) |
+        Instruction::TryDecrementRefAt(address) => Some(address),
+        _ => None,
+    }
+}
+
+/// The code of a function that can be inlined : a small one (up to `INLINE_BUDGET` instructions, or
+/// `REQUESTED_INLINE_BUDGET` if `requested`) that doesn't call other functions or plugins nor has loops. None for the
+/// others
+pub fn inline_body(instructions : &[Instruction], parameters : usize, requested : bool) -> Option<InlineBody> {
+    let budget = if requested { REQUESTED_INLINE_BUDGET } else { INLINE_BUDGET };
+
+    if instructions.len() > budget || instructions.iter().any(needs_own_frame) {
+        return None;
+    }
+
+    match instructions.last() {
+        Some(Instruction::Return) => {}
+        _ => return None,
+    }
+
+    let slots = instructions.iter()
+        .filter_map(local_address)
+        .map(|address| address + 1)
+        .fold(parameters + 1, usize::max);
+
+    let reads_treze = instructions.iter().any(|i| matches!(*i, Instruction::ReadVarFrom(0)));
+
+    Some(InlineBody { instructions : instructions.to_vec(), parameters, slots, reads_treze })
+}
+
+/// Add the code of the function in place of a call, after the values of the first `arguments` parameters were written
+/// to their addresses. The variables of the function go from the address `base` of the caller, moved as a whole, and
+/// the jumps are moved to where the code goes. A return goes to the end, where what it gave is written to the TREZE of
+/// the caller and the variables of the function are released (as the return would do)
+pub fn inline_call(body : &InlineBody, base : usize, arguments : usize, instructions : &mut Vec<Instruction>) {
+    // The addresses are reused by the caller, so the ones the function reads before writing may have old values
+    let mut cleared : Vec<usize> = (arguments + 1 .. body.parameters + 1).collect();
+
+    if body.reads_treze {
+        cleared.push(0);
+    }
+
+    for address in cleared {
+        instructions.push(Instruction::ClearMath);
+        instructions.push(Instruction::WriteVarTo(base + address));
+    }
+
+    let start = instructions.len();
+    // The last return is left out, so the function ends right where it was
+    let length = body.instructions.len() - 1;
+    let end = start + length;
+    let target = |t : usize| start + t.min(length);
+
+    for instruction in &body.instructions[.. length] {
+        let moved = match *instruction {
+            Instruction::ReadVarFrom(address) => Instruction::ReadVarFrom(base + address),
+            Instruction::WriteVarTo(address) => Instruction::WriteVarTo(base + address),
+            Instruction::TryDecrementRefAt(address) => Instruction::TryDecrementRefAt(base + address),
+            Instruction::Jump(t) => Instruction::Jump(target(t)),
+            Instruction::JumpIfNot(request, t) => Instruction::JumpIfNot(request, target(t)),
+            Instruction::Return => Instruction::Jump(end),
+            ref other => other.clone(),
+        };
+
+        instructions.push(moved);
+    }
+
+    instructions.push(Instruction::WriteVarTo(0));
+
+    for address in 0..body.slots {
+        instructions.push(Instruction::TryDecrementRefAt(base + address));
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Register {
+    MathA,
+    MathB,
+    Intermediate,
+}
+
+/// What an instruction does with a register : whether it reads it and whether it writes it. None for the instructions
+/// that do more than moving values between the registers and the variables, which the peephole optimizer doesn't
+/// look past
+fn register_use(instruction : &Instruction, register : Register) -> Option<(bool, bool)> {
+    let (a, b, intermediate) = match *instruction {
+        Instruction::PushValMathA(_) => ((false, true), (false, false), (false, false)),
+        Instruction::PushValMathB(_) => ((false, false), (false, true), (false, false)),
+        Instruction::ReadVarFrom(_) | Instruction::ReadGlobalVarFrom(_) => ((false, false), (false, false), (false, true)),
+        Instruction::PushIntermediateToA => ((false, true), (false, false), (true, false)),
+        Instruction::PushIntermediateToB => ((false, false), (false, true), (true, false)),
+        Instruction::SwapMath => ((true, true), (true, true), (false, false)),
+        Instruction::ClearMath => ((false, true), (false, true), (false, true)),
+        Instruction::WriteVarTo(_) | Instruction::WriteGlobalVarTo(_) | Instruction::AssertMathBCompatible(_) |
+        Instruction::PrintMathB => ((false, false), (true, false), (false, false)),
+        Instruction::PrintNewLine | Instruction::TryDecrementRefAt(_) => ((false, false), (false, false), (false, false)),
+        _ => return None,
+    };
+
+    Some(match register {
+        Register::MathA => a,
+        Register::MathB => b,
+        Register::Intermediate => intermediate,
+    })
+}
+
+/// Whether the instruction leaves every register as it was
+fn keeps_registers(instruction : &Instruction) -> bool {
+    [Register::MathA, Register::MathB, Register::Intermediate].iter()
+        .all(|&r| matches!(register_use(instruction, r), Some((_, false))))
+}
+
+/// Whether the value in the register is replaced, from the instruction `start` on, before anything reads it
+fn overwritten_before_read(code : &[Instruction], removed : &[bool], start : usize, register : Register) -> bool {
+    for (instruction, _) in code.iter().zip(removed).skip(start).filter(|&(_, &r)| ! r) {
+        match register_use(instruction, register) {
+            Some((false, true)) => return true,
+            Some((false, false)) => {}
+            _ => return false,
+        }
+    }
+
+    false
+}
+
+/// The instructions that are reached by something other than the one before them
+fn jump_targets(code : &[Instruction]) -> HashSet<usize> {
+    let mut targets = HashSet::new();
+
+    for (index, instruction) in code.iter().enumerate() {
+        match *instruction {
+            Instruction::Jump(target) | Instruction::JumpIfNot(_, target) => { targets.insert(target); }
+            Instruction::Skip(count) | Instruction::SkipIfMathBFalsy(count) | Instruction::SkipIfMathBTruthy(count) => {
+                targets.insert(index + 1 + count);
+            }
+            Instruction::DispatchMethod(_, ref table) => targets.extend(table.iter().map(|&(_, offset)| index + 1 + offset)),
+            // Each RestoreLoopLabel goes back to the instruction after it
+            Instruction::AddLoopLabel => { targets.insert(index + 1); }
+            _ => {}
+        }
+    }
+
+    targets
+}
+
+fn same_variable(write : &Instruction, read : &Instruction) -> bool {
+    match (write, read) {
+        (Instruction::WriteVarTo(w), Instruction::ReadVarFrom(r)) => w == r,
+        (Instruction::WriteGlobalVarTo(w), Instruction::ReadGlobalVarFrom(r)) => w == r,
+        _ => false,
+    }
+}
+
+/// The instruction at `index` with its jumps and skips changed to the new positions. `moved` has the new position of
+/// each old one
+fn relocate(instruction : Instruction, index : usize, new_index : usize, moved : &[usize]) -> Instruction {
+    let relative = |count : usize| moved[index + 1 + count] - new_index - 1;
+
+    match instruction {
+        Instruction::Jump(target) => Instruction::Jump(moved[target]),
+        Instruction::JumpIfNot(request, target) => Instruction::JumpIfNot(request, moved[target]),
+        Instruction::Skip(count) => Instruction::Skip(relative(count)),
+        Instruction::SkipIfMathBFalsy(count) => Instruction::SkipIfMathBFalsy(relative(count)),
+        Instruction::SkipIfMathBTruthy(count) => Instruction::SkipIfMathBTruthy(relative(count)),
+        Instruction::DispatchMethod(method, table) => {
+            let table = table.into_iter().map(|(kind, offset)| (kind, relative(offset))).collect();
+
+            Instruction::DispatchMethod(method, table)
+        }
+        other => other,
+    }
+}
+
+/// Rewrite the sequences of instructions of a function that do more than they need to :
+///
+/// * `PushIntermediateToB, SwapMath` becomes `PushIntermediateToA` when MathB is replaced right after, like when the
+///   first value of a comparison is a variable
+/// * A `ClearMath` when the registers were cleared already and nothing was put in them is removed
+/// * Reading a variable into MathB right after writing MathB to it (`WriteVarTo(1), ReadVarFrom(1),
+///   PushIntermediateToB`) is removed, as long as the intermediate register is replaced before being read
+///
+/// Nothing that a jump goes to is removed. Returns the new code and, for each old position (and the end), the new one
+pub fn peephole(code : &[Instruction]) -> (Vec<Instruction>, Vec<usize>) {
+    let targets = jump_targets(code);
+    let mut code = code.to_vec();
+    let mut removed = vec![false; code.len()];
+    let mut cleared = false;
+
+    for index in 0..code.len() {
+        if removed[index] {
+            continue;
+        }
+
+        let free = |offset : usize| index + offset < code.len() && ! targets.contains(&(index + offset));
+
+        // Another path may get here with something in the registers
+        if targets.contains(&index) {
+            cleared = false;
+        }
+
+        match code[index] {
+            Instruction::PushIntermediateToB if free(1) && matches!(code[index + 1], Instruction::SwapMath) &&
+                                                overwritten_before_read(&code, &removed, index + 2, Register::MathB) => {
+                code[index] = Instruction::PushIntermediateToA;
+                removed[index + 1] = true;
+            }
+            Instruction::ClearMath if cleared => removed[index] = true,
+            Instruction::WriteVarTo(_) | Instruction::WriteGlobalVarTo(_) if free(1) && free(2) &&
+                    same_variable(&code[index], &code[index + 1]) &&
+                    matches!(code[index + 2], Instruction::PushIntermediateToB) &&
+                    overwritten_before_read(&code, &removed, index + 3, Register::Intermediate) => {
+                removed[index + 1] = true;
+                removed[index + 2] = true;
+            }
+            _ => {}
+        }
+
+        if ! removed[index] {
+            cleared = match code[index] {
+                Instruction::ClearMath => true,
+                ref other => cleared && keeps_registers(other),
+            };
+        }
+    }
+
+    let mut moved = Vec::with_capacity(code.len() + 1);
+    let mut kept = 0;
+
+    for &r in &removed {
+        moved.push(kept);
+
+        if ! r {
+            kept += 1;
+        }
+    }
+
+    moved.push(kept);
+
+    let optimized = code.into_iter().enumerate()
+        .filter(|&(index, _)| ! removed[index])
+        .map(|(index, instruction)| relocate(instruction, index, moved[index], &moved))
+        .collect();
+
+    (optimized, moved)
+}
+
+/// The lines of the source of each instruction (the first instruction of each line) after the code was changed by
+/// `peephole`. A line whose instructions were all removed is left out
+pub fn move_source_lines(lines : &[(usize, usize)], moved : &[usize]) -> Vec<(usize, usize)> {
+    let mut result : Vec<(usize, usize)> = vec![];
+
+    for &(first, line) in lines {
+        let first = moved[first.min(moved.len() - 1)];
+
+        match result.last_mut() {
+            Some(last) if last.0 == first => last.1 = line,
+            _ => result.push((first, line)),
+        }
+    }
+
+    result
+}
+
+mod tests {
+    #[test]
+    fn constant_folding() {
+        use context::{ Context, RawValue };
+        use vm::Instruction;
+        use vm_io::CaptureIo;
+
+        let src = "JAULA SHOW\n\
+                   CE QUER VER ISSO: 2 * 3 + 1\n\
+                   CE QUER VER ISSO: \"BORA\" + \", \" + \"CUMPADE\"\n\
+                   CE QUER VER ISSO: -(2 ^ 3) + 0.5\n\
+                   CE QUER VER ISSO: 2 ^ -1\n\
+                   VEM: X, 10\n\
+                   CE QUER VER ISSO: X * (4 - 1)\n\
+                   SAINDO DA JAULA";
+
+        let io = CaptureIo::new("");
+        let output = io.output();
+
+        let mut ctx = Context::new();
+        let _ = ctx.set_io(Box::new(io));
+
+        ctx.call_function_by_id(0, vec![]).unwrap();
+
+        if let Err(e) = ctx.add_source_string(src.to_owned()) {
+            panic!("{}", e);
+        }
+
+        {
+            let functions = ctx.compiled_functions();
+            let show = match functions.iter().find(|f| f.1 == "SHOW") {
+                Some(f) => f.2,
+                None => panic!("SHOW não foi compilada"),
+            };
+
+            for value in &[RawValue::Integer(7), RawValue::Text("BORA, CUMPADE".to_owned()), RawValue::Number(-7.5),
+                           RawValue::Number(0.5), RawValue::Integer(3)] {
+                let pushed = show.iter().any(|i| match *i {
+                    Instruction::PushValMathB(ref v) => v == value,
+                    _ => false,
+                });
+
+                assert!(pushed, "{:?} : {:?}", value, show);
+            }
+
+            // Only the operation with the variable is left
+            let operations = show.iter().filter(|i| matches!(**i, Instruction::Add | Instruction::Sub | Instruction::Mul | Instruction::Pow));
+
+            assert_eq!(operations.count(), 1, "{:?}", show);
+        }
+
+        assert_eq!(ctx.start_program(), Ok(()));
+        assert_eq!(*output.borrow(), "7\nBORA, CUMPADE\n-7.5\n0.5\n30\n");
+    }
+
+    #[test]
+    fn operations_left_for_the_vm() {
+        use optimizer::fold_constants;
+        use parser::{ ExpressionNode, MathOperator };
+        use context::RawValue;
+
+        let binary = |op, l, r| ExpressionNode::Binary(op, Box::new(ExpressionNode::Value(l)),
+                                                      Box::new(ExpressionNode::Value(r)));
+
+        // Errors, results that depend on the width of the integers and values that aren't numbers or texts
+        let kept = vec![
+            binary(MathOperator::Division, RawValue::Integer(1), RawValue::Integer(0)),
+            binary(MathOperator::Modulo, RawValue::Integer(1), RawValue::Integer(0)),
+            binary(MathOperator::Multiplication, RawValue::Integer(1 << 20), RawValue::Integer(1 << 20)),
+            binary(MathOperator::Plus, RawValue::Text("A".to_owned()), RawValue::Integer(1)),
+            binary(MathOperator::Minus, RawValue::Text("A".to_owned()), RawValue::Text("B".to_owned())),
+            binary(MathOperator::Plus, RawValue::Null, RawValue::Integer(1)),
+            binary(MathOperator::Equal, RawValue::Integer(1), RawValue::Integer(1)),
+        ];
+
+        for node in kept {
+            assert_eq!(fold_constants(node.clone()), node);
+        }
+    }
+
+    #[test]
+    fn inlining() {
+        use context::Context;
+        use vm::Instruction;
+        use vm_io::CaptureIo;
+
+        let run = |src : &str, inlining : bool| -> (String, Vec<Instruction>) {
+            let io = CaptureIo::new("");
+            let output = io.output();
+
+            let mut ctx = Context::new();
+            let _ = ctx.set_io(Box::new(io));
+            ctx.set_inlining(inlining);
+
+            let result = ctx.call_function_by_id(0, vec![])
+                .and_then(|_| ctx.add_standard_library())
+                .and_then(|_| ctx.add_source_string(src.to_owned()));
+
+            if let Err(e) = result {
+                panic!("{}", e);
+            }
+
+            let show = match ctx.compiled_functions().into_iter().find(|f| f.1 == "SHOW") {
+                Some(f) => f.2.to_vec(),
+                None => panic!("SHOW não foi compilada"),
+            };
+
+            assert_eq!(ctx.start_program(), Ok(()));
+
+            let output = output.borrow().clone();
+
+            (output, show)
+        };
+
+        let calls = |code : &[Instruction]| code.iter().filter(|i| matches!(**i, Instruction::MakeNewFrame(_))).count();
+
+        // Returns from inside blocks, parameters left out, texts and the variables of a loop that called it before
+        let src = "JAULA SINAL(N : BATATA DOCE)\n\
+                   É ELE QUE A GENTE QUER: N, 0\n\
+                   É MENOR\n\
+                   BIRL: -1\n\
+                   FIM\n\
+                   É ELE MEMO\n\
+                   BIRL: 0\n\
+                   FIM\n\
+                   BIRL: 1\n\
+                   SAINDO DA JAULA\n\
+                   JAULA SAUDA(NOME : FIBRA, FECHO : FIBRA)\n\
+                   VEM: T, \"OLÁ, \" + NOME\n\
+                   É ELE QUE A GENTE QUER: FECHO\n\
+                   É ELE MEMO\n\
+                   BORA: T, T + FECHO\n\
+                   FIM\n\
+                   BIRL: T\n\
+                   SAINDO DA JAULA\n\
+                   JAULA SHOW\n\
+                   REPETE: I, -1, 2\n\
+                   É HORA DO: SINAL, I\n\
+                   VEM: S, TREZE\n\
+                   É HORA DO: SAUDA, \"BAMBAM\"\n\
+                   VEM: X, TREZE\n\
+                   CE QUER VER ISSO: S, \" \", X\n\
+                   FIM\n\
+                   É HORA DO: SAUDA, \"JORGE\", \"!\"\n\
+                   CE QUER VER ISSO: TREZE\n\
+                   SAINDO DA JAULA";
+
+        let (inlined, show) = run(src, true);
+        let expected = "-1 OLÁ, BAMBAM\n0 OLÁ, BAMBAM\n1 OLÁ, BAMBAM\nOLÁ, JORGE!\n";
+
+        assert_eq!(inlined, expected);
+        assert_eq!(calls(&show), 0, "{:?}", show);
+
+        let (called, show) = run(src, false);
+
+        assert_eq!(called, expected);
+        assert_eq!(calls(&show), 3, "{:?}", show);
+
+        // Loops, calls to other functions and to plugins (which give their result in the TREZE of the frame) and
+        // PEGA DA JAULA need a frame
+        let src = "JAULA CONTA(N : BATATA DOCE)\n\
+                   REPETE: I, 0, N\n\
+                   CE QUER VER ISSO: I\n\
+                   FIM\n\
+                   SAINDO DA JAULA\n\
+                   JAULA DOIS()\n\
+                   BIRL: 1, 2\n\
+                   SAINDO DA JAULA\n\
+                   JAULA CHAMA()\n\
+                   É HORA DO: CONTA, 1\n\
+                   SAINDO DA JAULA\n\
+                   JAULA SOMA(A : BATATA DOCE, B : BATATA DOCE)\n\
+                   BIRL: A + B\n\
+                   SAINDO DA JAULA\n\
+                   JAULA TAM(X : FIBRA)\n\
+                   É HORA DO: TAMANHO DO TEXTO, X\n\
+                   BIRL: TREZE + 1\n\
+                   SAINDO DA JAULA\n\
+                   JAULA SHOW\n\
+                   É HORA DO: CONTA, 2\n\
+                   É HORA DO: DOIS\n\
+                   É HORA DO: CHAMA\n\
+                   PEGA DA JAULA: A : SOMA, 1, 2\n\
+                   CE QUER VER ISSO: A\n\
+                   É HORA DO: TAM, \"BIRL\"\n\
+                   CE QUER VER ISSO: TREZE\n\
+                   SAINDO DA JAULA";
+
+        let (output, show) = run(src, true);
+
+        assert_eq!(output, "0\n1\n0\n3\n5\n");
+        assert_eq!(calls(&show), 5, "{:?}", show);
+    }
+
+    #[test]
+    fn peephole() {
+        use context::Context;
+        use disassembler::{ disassemble, DisassembledFunction };
+        use vm_io::CaptureIo;
+
+        let src = "JAULA SHOW\n\
+                   VEM: X, 1\n\
+                   BORA: X, X + 1\n\
+                   CE QUER VER ISSO: X\n\
+                   VEM: A\n\
+                   VEM: B\n\
+                   É ELE QUE A GENTE QUER: X, 2\n\
+                   É ELE MEMO\n\
+                   VEM: C\n\
+                   FIM\n\
+                   VEM: D\n\
+                   CE QUER VER ISSO: A, B, D\n\
+                   SAINDO DA JAULA";
+
+        let run = |optimize : bool| -> (String, DisassembledFunction) {
+            let io = CaptureIo::new("");
+            let output = io.output();
+
+            let mut ctx = Context::new();
+            let _ = ctx.set_io(Box::new(io));
+            ctx.set_peephole(optimize);
+
+            let result = ctx.call_function_by_id(0, vec![])
+                .and_then(|_| ctx.add_source_string(src.to_owned()));
+
+            if let Err(e) = result {
+                panic!("{}", e);
+            }
+
+            let show = disassemble(&ctx.compiled_program()).remove(1);
+
+            assert_eq!(ctx.start_program(), Ok(()));
+
+            let output = output.borrow().clone();
+
+            (output, show)
+        };
+
+        let count = |function : &DisassembledFunction, text : &str| {
+            function.instructions.iter().filter(|i| i.text == text).count()
+        };
+
+        let (before_output, before) = run(false);
+        let (after_output, after) = run(true);
+
+        assert_eq!(before_output, "2\n<Null><Null><Null>\n");
+        assert_eq!(after_output, before_output);
+
+        // The comparison reads X straight into MathA
+        assert_eq!(count(&before, "SwapMath"), 1, "{}", before);
+        assert_eq!(count(&after, "SwapMath"), 0, "{}", after);
+        assert_eq!(count(&after, "PushIntermediateToA"), count(&before, "PushIntermediateToA") + 1, "{}", after);
+
+        // X is printed from what was just written to it
+        assert_eq!(count(&before, "ReadVarFrom(1)"), 3, "{}", before);
+        assert_eq!(count(&after, "ReadVarFrom(1)"), 2, "{}", after);
+
+        // VEM: B doesn't need to clear the registers again, but VEM: D is where the comparison jumps to
+        assert_eq!(count(&before, "ClearMath"), 4, "{}", before);
+        assert_eq!(count(&after, "ClearMath"), 3, "{}", after);
+
+        assert_eq!(after.instructions.len() + 4, before.instructions.len());
+
+        // The jump goes to the same instruction, moved, and every line still starts somewhere
+        for function in &[&before, &after] {
+            let target = function.instructions.iter()
+                .find(|i| i.text.starts_with("JumpIfNot"))
+                .and_then(|i| i.note.clone())
+                .and_then(|note| note.trim_start_matches("se não, -> ").parse::<usize>().ok());
+
+            match target {
+                Some(t) => assert_eq!(function.instructions[t].text, "ClearMath", "{}", function),
+                None => panic!("Sem o pulo do É ELE MEMO : {}", function),
+            }
+
+            let lines = function.instructions.iter().filter_map(|i| i.line).collect::<Vec<usize>>();
+            assert_eq!(lines, (2..13).collect::<Vec<usize>>(), "{}", function);
+        }
+    }
+}
@@ -1,17 +1,26 @@
 use context::RawValue;
+use error::{ BirlError, ErrorLocation };
 
-#[cfg(target_pointer_width = "64")]
+use std::fmt;
+
+#[cfg(feature = "i128")]
+pub type IntegerType = i128;
+
+#[cfg(all(not(feature = "i128"), target_pointer_width = "64"))]
 pub type IntegerType = i64;
 
-#[cfg(target_pointer_width = "32")]
+#[cfg(all(not(feature = "i128"), target_pointer_width = "32"))]
 pub type IntegerType = i32;
 
 const COMMENT_CHARACTER : char = '#';
+const CALLER_FRAME_CHARACTER : char = '@';
+const METHOD_CHARACTER : char = '.';
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum KeyPhrase {
     FunctionStart,
     FunctionEnd,
+    Annotation,
     PrintLn,
     Print,
     PrintDebug,
@@ -33,8 +42,11 @@ pub enum KeyPhrase {
     ExecuteWhileLess,
     ExecuteWhileGreater,
     ExecuteWhileEqualOrGreater,
+    ExecuteWhile,
     RangeLoop,
+    ForEach,
     Call,
+    CallUnpack,
     GetStringInput,
     GetNumberInput,
     GetIntegerInput,
@@ -45,13 +57,29 @@ pub enum KeyPhrase {
     TypeNum,
     TypeStr,
     TypeList,
+    TypeBool,
+    TypeMap,
     MakeNewList,
     QueryListSize,
     AddListElement,
     RemoveListElement,
     IndexList,
+    SortList,
+    MakeNewMap,
+    SetMapEntry,
+    IndexMap,
+    RemoveMapEntry,
+    QueryMapSize,
+    ListMapKeys,
     BreakScope,
     SkipNextIteration,
+    BreakLoop,
+    Assert,
+    Switch,
+    SwitchCase,
+    SwitchDefault,
+    ElseIf,
+    Else,
 }
 
 impl KeyPhrase {
@@ -59,6 +87,7 @@ impl KeyPhrase {
         match src {
             "JAULA" => Some(KeyPhrase::FunctionStart),
             "SAINDO DA JAULA" => Some(KeyPhrase::FunctionEnd),
+            "FICHA" => Some(KeyPhrase::Annotation),
             "BIRL" => Some(KeyPhrase::Return),
             "NUM VAI DA NAO" |
             "NUM VAI DÁ NAO" |
@@ -74,10 +103,13 @@ impl KeyPhrase {
             "FIBRA" => Some(KeyPhrase::TypeStr),
             "BATATA DOCE" => Some(KeyPhrase::TypeInt),
             "LISTA" => Some(KeyPhrase::TypeList),
+            "SIM OU NÃO" | "SIM OU NAO" => Some(KeyPhrase::TypeBool),
+            "MAPA" => Some(KeyPhrase::TypeMap),
             "E ELE QUE A GENTE QUER" |
             "É ELE QUE A GENTE QUER" => Some(KeyPhrase::Compare),
             "FIM" => Some(KeyPhrase::EndSubScope),
             "E HORA DO" | "É HORA DO" => Some(KeyPhrase::Call),
+            "PEGA DA JAULA" => Some(KeyPhrase::CallUnpack),
             "E ELE MEMO" | "É ELE MEMO" => Some(KeyPhrase::ExecuteIfEqual),
             "NUM E ELE" | "NUM É ELE" => Some(KeyPhrase::ExecuteIfNotEqual),
             "E MAIOR" | "É MAIOR" => Some(KeyPhrase::ExecuteIfGreater),
@@ -96,14 +128,30 @@ impl KeyPhrase {
             "ENQUANTO MENOR OU E MEMO" | "ENQUANTO MENOR OU É MEMO" => Some(KeyPhrase::ExecuteWhileEqualOrLess),
             "ENQUANTO E MAIOR" | "ENQUANTO É MAIOR" => Some(KeyPhrase::ExecuteWhileGreater),
             "ENQUANTO MAIOR OU E MEMO" | "ENQUANTO MAIOR OU É MEMO" => Some(KeyPhrase::ExecuteWhileEqualOrGreater),
+            "ENQUANTO FOR" => Some(KeyPhrase::ExecuteWhile),
             "REPETE" => Some(KeyPhrase::RangeLoop),
+            "PRA CADA" => Some(KeyPhrase::ForEach),
             "FAZ UMA LISTA" => Some(KeyPhrase::MakeNewList),
             "FALA O TAMANHO" => Some(KeyPhrase::QueryListSize),
             "POE ISSO AQUI" | "PÕE ISSO AQUI" => Some(KeyPhrase::AddListElement),
             "TIRA ESSE" => Some(KeyPhrase::RemoveListElement),
             "ME DA ESSE" | "ME DÁ ESSE" => Some(KeyPhrase::IndexList),
+            "ORDENA" => Some(KeyPhrase::SortList),
+            "FAZ UM MAPA" => Some(KeyPhrase::MakeNewMap),
+            "BOTA NO MAPA" => Some(KeyPhrase::SetMapEntry),
+            "PEGA DO MAPA" => Some(KeyPhrase::IndexMap),
+            "TIRA DO MAPA" => Some(KeyPhrase::RemoveMapEntry),
+            "FALA O TAMANHO DO MAPA" => Some(KeyPhrase::QueryMapSize),
+            "FALA AS CHAVES" => Some(KeyPhrase::ListMapKeys),
             "PARA AQUI" => Some(KeyPhrase::BreakScope),
             "VAI PRO PROXIMO" | "VAI PRO PRÓXIMO" => Some(KeyPhrase::SkipNextIteration),
+            "SAI DO LOOP" => Some(KeyPhrase::BreakLoop),
+            "TEM QUE SER" => Some(KeyPhrase::Assert),
+            "ESCOLHE" => Some(KeyPhrase::Switch),
+            "CASO" => Some(KeyPhrase::SwitchCase),
+            "QUALQUER OUTRO" => Some(KeyPhrase::SwitchDefault),
+            "OU SE FOR" => Some(KeyPhrase::ElseIf),
+            "SE NÃO" | "SE NAO" => Some(KeyPhrase::Else),
             _ => None,
         }
     }
@@ -115,20 +163,37 @@ pub enum MathOperator {
     Minus,
     Division,
     Multiplication,
+    /// The remainder of a division
+    Modulo,
+    Power,
     ParenthesisLeft,
     ParenthesisRight,
+    And,
+    Or,
+    Not,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    More,
+    MoreOrEqual,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum PunctuationKind {
     Colon,
     Comma,
+    /// The braces around a map, like `{ "CHAVE" : 1 }`
+    BraceLeft,
+    BraceRight,
 }
 
 #[derive(Debug, PartialEq)]
 pub enum Token {
     Command(KeyPhrase),
     Symbol(String),
+    /// A variable from a function up in the callstack, `depth` frames from the current function
+    CallerSymbol(usize, String),
     Text(String),
     Number(f64),
     Integer(IntegerType),
@@ -145,8 +210,25 @@ fn get_op(c : char) -> Option<MathOperator> {
         '-' => Some(MathOperator::Minus),
         '/' => Some(MathOperator::Division),
         '*' => Some(MathOperator::Multiplication),
+        '%' => Some(MathOperator::Modulo),
+        '^' => Some(MathOperator::Power),
         '(' => Some(MathOperator::ParenthesisLeft),
         ')' => Some(MathOperator::ParenthesisRight),
+        '!' => Some(MathOperator::Not),
+        '<' => Some(MathOperator::Less),
+        '>' => Some(MathOperator::More),
+        _ => None,
+    }
+}
+
+fn get_double_op(first : char, second : char) -> Option<MathOperator> {
+    match (first, second) {
+        ('&', '&') => Some(MathOperator::And),
+        ('|', '|') => Some(MathOperator::Or),
+        ('=', '=') => Some(MathOperator::Equal),
+        ('!', '=') => Some(MathOperator::NotEqual),
+        ('<', '=') => Some(MathOperator::LessOrEqual),
+        ('>', '=') => Some(MathOperator::MoreOrEqual),
         _ => None,
     }
 }
@@ -155,6 +237,8 @@ fn get_ponct(c : char) -> Option<PunctuationKind> {
     match c {
         ':' => Some(PunctuationKind::Colon),
         ',' => Some(PunctuationKind::Comma),
+        '{' => Some(PunctuationKind::BraceLeft),
+        '}' => Some(PunctuationKind::BraceRight),
         _ => None,
     }
 }
@@ -271,6 +355,28 @@ fn text_token(input : &[char], offset : &mut usize) -> Result<Token, String> {
     Ok(Token::Text(content))
 }
 
+/// Parse a reference to a variable of a caller, like @X (the caller's X) or @@X (X from the caller of the caller)
+fn caller_symbol_token(input : &[char], offset : &mut usize) -> Result<Token, String> {
+    let mut depth = 1usize;
+
+    while *offset < input.len() && input[*offset] == CALLER_FRAME_CHARACTER {
+        depth += 1;
+        *offset += 1;
+    }
+
+    if *offset >= input.len() {
+        return Err(format!("Esperado o nome de uma variável depois de {}", CALLER_FRAME_CHARACTER));
+    }
+
+    let first = input[*offset];
+    *offset += 1;
+
+    match symbol_token(input, offset, first)? {
+        Token::Symbol(s) => Ok(Token::CallerSymbol(depth, s)),
+        tok => Err(format!("Esperado o nome de uma variável depois de {}, encontrado {:?}", CALLER_FRAME_CHARACTER, tok)),
+    }
+}
+
 fn symbol_token(input : &[char], offset : &mut usize, first : char) -> Result<Token, String> {
     let mut result = String::new();
 
@@ -309,6 +415,10 @@ fn symbol_token(input : &[char], offset : &mut usize, first : char) -> Result<To
                 break;
             }
 
+            if *offset + 1 < input.len() && get_double_op(cur, input[*offset + 1]).is_some() {
+                break;
+            }
+
             if let Some(_) = get_digit(cur) {
                 if first_char {
                     break;
@@ -348,6 +458,11 @@ pub fn next_token(input : &[char], offset : &mut usize) -> Result<Token, String>
     }
 
     loop {
+        // Only spaces until the end of the line
+        if *offset >= input.len() {
+            return Ok(Token::None);
+        }
+
         if input[*offset] != ' ' && input[*offset] != '\t' {
             break;
         }
@@ -366,6 +481,16 @@ pub fn next_token(input : &[char], offset : &mut usize) -> Result<Token, String>
         return Ok(Token::NewLine);
     }
 
+    // Check the two character operators first, since some of them start with a valid single character operator
+
+    if *offset < input.len() {
+        if let Some(op) = get_double_op(first_char, input[*offset]) {
+            *offset += 1;
+
+            return Ok(Token::Operator(op));
+        }
+    }
+
     if let Some(op) = get_op(first_char) {
         return Ok(Token::Operator(op));
     }
@@ -386,6 +511,10 @@ pub fn next_token(input : &[char], offset : &mut usize) -> Result<Token, String>
         return text_token(input, offset);
     }
 
+    if first_char == CALLER_FRAME_CHARACTER {
+        return caller_symbol_token(input, offset);
+    }
+
     symbol_token(input, offset, first_char)
 }
 
@@ -395,6 +524,14 @@ pub enum TypeKind {
     Number,
     Text,
     List,
+    /// A lazy sequence. As a parameter, accepts anything that PRA CADA can go through
+    Sequence,
+    /// A file open for writing
+    File,
+    /// An open SQLite database
+    Database,
+    Bool,
+    Map,
     Null,
 }
 
@@ -405,11 +542,30 @@ impl TypeKind {
             KeyPhrase::TypeNum => Some(TypeKind::Number),
             KeyPhrase::TypeStr => Some(TypeKind::Text),
             KeyPhrase::TypeList => Some(TypeKind::List),
+            KeyPhrase::TypeBool => Some(TypeKind::Bool),
+            KeyPhrase::TypeMap => Some(TypeKind::Map),
             _ => None,
         }
     }
 }
 
+impl fmt::Display for TypeKind {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TypeKind::Integer => write!(f, "Inteiro"),
+            TypeKind::Number => write!(f, "Número"),
+            TypeKind::Text => write!(f, "Texto"),
+            TypeKind::List => write!(f, "Lista"),
+            TypeKind::Sequence => write!(f, "Sequência"),
+            TypeKind::File => write!(f, "Arquivo"),
+            TypeKind::Database => write!(f, "Banco"),
+            TypeKind::Bool => write!(f, "Booleano"),
+            TypeKind::Map => write!(f, "Mapa"),
+            TypeKind::Null => write!(f, "Null"),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct FunctionParameter {
     pub name : String,
@@ -425,39 +581,136 @@ impl FunctionParameter {
     }
 }
 
+/// Something written with FICHA above a function, changing how it's compiled or used
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Annotation {
+    /// Run by `birl test`, even if the name doesn't start with TESTE
+    Test,
+    /// Run when the program starts, in place of SHOW
+    EntryPoint,
+    /// Warnings in the function are only warnings, even in strict mode
+    NoStrict,
+    /// Calls to the function may be replaced by its code
+    Inline,
+}
+
+impl Annotation {
+    pub fn from_name(name : &str) -> Option<Annotation> {
+        match name {
+            "TESTE" => Some(Annotation::Test),
+            "PRINCIPAL" => Some(Annotation::EntryPoint),
+            "SEM MODO ESTRITO" => Some(Annotation::NoStrict),
+            "EM LINHA" => Some(Annotation::Inline),
+            _ => None,
+        }
+    }
+
+    /// How it's written after FICHA
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Annotation::Test => "TESTE",
+            Annotation::EntryPoint => "PRINCIPAL",
+            Annotation::NoStrict => "SEM MODO ESTRITO",
+            Annotation::Inline => "EM LINHA",
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct FunctionDeclaration {
     pub name : String,
     pub arguments : Vec<FunctionParameter>,
+    /// What was written with FICHA right before the function
+    pub annotations : Vec<Annotation>,
 }
 
 impl FunctionDeclaration {
     pub fn from(name : String) -> FunctionDeclaration {
         FunctionDeclaration {
             name,
-            arguments: vec![]
+            arguments: vec![],
+            annotations : vec![],
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ExpressionNode {
     Value(RawValue),
     Symbol(String),
-    Operator(MathOperator),
+    /// A read-only variable of a caller (depth, name). @X has depth 1, @@X has depth 2 and so on
+    CallerSymbol(usize, String),
+    /// An unary operation (- or !) on the node
+    Unary(MathOperator, Box<ExpressionNode>),
+    /// An arithmetic or comparision operation between the left and right nodes
+    Binary(MathOperator, Box<ExpressionNode>, Box<ExpressionNode>),
+    /// A logical operation (&& or ||). The right side is only evaluated if the left one doesn't decide the result
+    Logical(MathOperator, Box<ExpressionNode>, Box<ExpressionNode>),
+    /// Chained comparisions, like `1 < X <= 10`. Every operand is compared with the next one, and the result is only true
+    /// if all the comparisions are. There's always one more operand than operators
+    Chain(Vec<MathOperator>, Vec<ExpressionNode>),
+    /// A new map with the (key, value) pairs, in the order they were written
+    Map(Vec<(ExpressionNode, ExpressionNode)>),
 }
 
-#[derive(Debug, PartialEq)]
+impl ExpressionNode {
+    pub fn has_symbols(&self) -> bool {
+        match *self {
+            ExpressionNode::Value(_) => false,
+            ExpressionNode::Symbol(_) => true,
+            ExpressionNode::CallerSymbol(_, _) => true,
+            ExpressionNode::Unary(_, ref node) => node.has_symbols(),
+            ExpressionNode::Binary(_, ref left, ref right) |
+            ExpressionNode::Logical(_, ref left, ref right) => left.has_symbols() || right.has_symbols(),
+            ExpressionNode::Chain(_, ref operands) => operands.iter().any(|n| n.has_symbols()),
+            ExpressionNode::Map(ref entries) => entries.iter().any(|entry| entry.0.has_symbols() || entry.1.has_symbols()),
+        }
+    }
+
+    /// Add the name of every variable used in this node to `symbols`
+    pub fn collect_symbols(&self, symbols : &mut Vec<String>) {
+        match *self {
+            // Caller variables are resolved when the function runs, so they aren't listed
+            ExpressionNode::Value(_) | ExpressionNode::CallerSymbol(_, _) => {}
+            ExpressionNode::Symbol(ref s) => {
+                if ! symbols.contains(s) {
+                    symbols.push(s.clone());
+                }
+            }
+            ExpressionNode::Unary(_, ref node) => node.collect_symbols(symbols),
+            ExpressionNode::Binary(_, ref left, ref right) |
+            ExpressionNode::Logical(_, ref left, ref right) => {
+                left.collect_symbols(symbols);
+                right.collect_symbols(symbols);
+            }
+            ExpressionNode::Chain(_, ref operands) => {
+                for node in operands {
+                    node.collect_symbols(symbols);
+                }
+            }
+            ExpressionNode::Map(ref entries) => {
+                for entry in entries {
+                    entry.0.collect_symbols(symbols);
+                    entry.1.collect_symbols(symbols);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Expression {
-    pub nodes : Vec<ExpressionNode>,
+    pub root : ExpressionNode,
     pub has_symbols : bool,
 }
 
 impl Expression {
-    pub fn new() -> Expression {
+    pub fn from(root : ExpressionNode) -> Expression {
+        let has_symbols = root.has_symbols();
+
         Expression {
-            nodes: vec![],
-            has_symbols: false,
+            root,
+            has_symbols,
         }
     }
 }
@@ -480,6 +733,8 @@ pub enum CommandKind {
     ExecuteIfEqualOrGreater,
     ExecuteIfGreater,
     Call,
+    /// A call whose return values go to the variables before the colon
+    CallUnpack,
     GetStringInput,
     GetNumberInput,
     GetIntegerInput,
@@ -492,14 +747,36 @@ pub enum CommandKind {
     ExecuteWhileLess,
     ExecuteWhileGreater,
     ExecuteWhileEqualOrGreater,
+    /// Repeats the block while the condition is true, checked before each iteration
+    ExecuteWhile,
     RangeLoop,
+    ForEach,
     MakeNewList,
     QueryListSize,
     AddListElement,
     RemoveListElement,
     IndexList,
+    SortList,
+    MakeNewMap,
+    SetMapEntry,
+    IndexMap,
+    RemoveMapEntry,
+    QueryMapSize,
+    ListMapKeys,
     BreakScope,
     SkipNextIteration,
+    BreakLoop,
+    Assert,
+    /// Compares a value against the constants of each case that follows, and runs only the block of the first that
+    /// matches
+    Switch,
+    SwitchCase,
+    /// The block that runs when no case of the switch matched
+    SwitchDefault,
+    /// Ends the branch of an if and starts one that runs if no branch before it did and the condition is true
+    ElseIf,
+    /// Ends the branch of an if and starts one that runs if no branch before it did
+    Else,
 }
 
 impl CommandKind {
@@ -521,6 +798,7 @@ impl CommandKind {
             KeyPhrase::ExecuteIfEqualOrLess => Some(CommandKind::ExecuteIfEqualOrLess),
             KeyPhrase::ExecuteIfLess => Some(CommandKind::ExecuteIfLess),
             KeyPhrase::Call => Some(CommandKind::Call),
+            KeyPhrase::CallUnpack => Some(CommandKind::CallUnpack),
             KeyPhrase::GetStringInput => Some(CommandKind::GetStringInput),
             KeyPhrase::GetNumberInput => Some(CommandKind::GetNumberInput),
             KeyPhrase::IntoString => Some(CommandKind::IntoString),
@@ -533,14 +811,30 @@ impl CommandKind {
             KeyPhrase::ExecuteWhileLess => Some(CommandKind::ExecuteWhileLess),
             KeyPhrase::ExecuteWhileGreater => Some(CommandKind::ExecuteWhileGreater),
             KeyPhrase::ExecuteWhileEqualOrGreater => Some(CommandKind::ExecuteWhileEqualOrGreater),
+            KeyPhrase::ExecuteWhile => Some(CommandKind::ExecuteWhile),
             KeyPhrase::RangeLoop => Some(CommandKind::RangeLoop),
+            KeyPhrase::ForEach => Some(CommandKind::ForEach),
             KeyPhrase::MakeNewList => Some(CommandKind::MakeNewList),
             KeyPhrase::QueryListSize => Some(CommandKind::QueryListSize),
             KeyPhrase::AddListElement => Some(CommandKind::AddListElement),
             KeyPhrase::RemoveListElement => Some(CommandKind::RemoveListElement),
             KeyPhrase::IndexList => Some(CommandKind::IndexList),
+            KeyPhrase::SortList => Some(CommandKind::SortList),
+            KeyPhrase::MakeNewMap => Some(CommandKind::MakeNewMap),
+            KeyPhrase::SetMapEntry => Some(CommandKind::SetMapEntry),
+            KeyPhrase::IndexMap => Some(CommandKind::IndexMap),
+            KeyPhrase::RemoveMapEntry => Some(CommandKind::RemoveMapEntry),
+            KeyPhrase::QueryMapSize => Some(CommandKind::QueryMapSize),
+            KeyPhrase::ListMapKeys => Some(CommandKind::ListMapKeys),
             KeyPhrase::BreakScope => Some(CommandKind::BreakScope),
             KeyPhrase::SkipNextIteration => Some(CommandKind::SkipNextIteration),
+            KeyPhrase::BreakLoop => Some(CommandKind::BreakLoop),
+            KeyPhrase::Assert => Some(CommandKind::Assert),
+            KeyPhrase::Switch => Some(CommandKind::Switch),
+            KeyPhrase::SwitchCase => Some(CommandKind::SwitchCase),
+            KeyPhrase::SwitchDefault => Some(CommandKind::SwitchDefault),
+            KeyPhrase::ElseIf => Some(CommandKind::ElseIf),
+            KeyPhrase::Else => Some(CommandKind::Else),
             _ => None,
         }
     }
@@ -571,7 +865,7 @@ impl CommandInfo {
     fn from_kind(kind : CommandKind) -> CommandInfo {
         match kind {
             CommandKind::Quit => CommandInfo::from(0, 0, vec![]),
-            CommandKind::Return => CommandInfo::from(0, 1,
+            CommandKind::Return => CommandInfo::from(0, -1,
                                                      vec![CommandArgumentKind::Expression]),
             CommandKind::Print => CommandInfo::from(1, -1,
                                                     vec![CommandArgumentKind::Expression]),
@@ -588,7 +882,7 @@ impl CommandInfo {
                                              CommandArgumentKind::Expression])
             }
             CommandKind::Compare => {
-                CommandInfo::from(2, 2, vec![CommandArgumentKind::Expression,
+                CommandInfo::from(1, 2, vec![CommandArgumentKind::Expression,
                                              CommandArgumentKind::Expression])
             }
             CommandKind::EndSubScope => {
@@ -598,6 +892,11 @@ impl CommandInfo {
                 CommandInfo::from(1, -1, vec![CommandArgumentKind::Name,
                                               CommandArgumentKind::Expression])
             }
+            CommandKind::CallUnpack => {
+                // The variables come before these, up to the colon, then it's the same as a call
+                CommandInfo::from(2, -1, vec![CommandArgumentKind::Name,
+                                              CommandArgumentKind::Expression])
+            }
             CommandKind::ExecuteIfEqual |
             CommandKind::ExecuteIfNotEqual |
             CommandKind::ExecuteIfLess |
@@ -612,9 +911,10 @@ impl CommandInfo {
             CommandKind::ExecuteWhileGreater |
             CommandKind::ExecuteWhileEqualOrLess |
             CommandKind::ExecuteWhileEqualOrGreater => {
-                CommandInfo::from(2, 2, vec![CommandArgumentKind::Expression,
+                CommandInfo::from(1, 2, vec![CommandArgumentKind::Expression,
                                              CommandArgumentKind::Expression])
             }
+            CommandKind::ExecuteWhile => CommandInfo::from(1, 1, vec![CommandArgumentKind::Expression]),
             CommandKind::GetStringInput | CommandKind::GetNumberInput | CommandKind::IntoString |
             CommandKind::ConvertToNum | CommandKind::ConvertToInt | CommandKind::GetIntegerInput => {
                 CommandInfo::from(1, 1, vec![CommandArgumentKind::Name])
@@ -625,6 +925,10 @@ impl CommandInfo {
                                              CommandArgumentKind::Expression, CommandArgumentKind::Expression,
                                                 CommandArgumentKind::Expression])
             }
+            CommandKind::ForEach => {
+                // The variable and what it goes through
+                CommandInfo::from(2, 2, vec![CommandArgumentKind::Name, CommandArgumentKind::Expression])
+            }
             CommandKind::MakeNewList => {
                 CommandInfo::from(1, 1, vec![CommandArgumentKind::Name])
             }
@@ -642,7 +946,39 @@ impl CommandInfo {
                 CommandInfo::from(3, 3, vec![CommandArgumentKind::Name, CommandArgumentKind::Expression,
                     CommandArgumentKind::Name])
             }
-            CommandKind::BreakScope | CommandKind::SkipNextIteration => CommandInfo::from(0, 0, vec![]),
+            CommandKind::SortList => {
+                // The list and, optionally, the name of the function that compares the elements
+                CommandInfo::from(1, 2, vec![CommandArgumentKind::Name, CommandArgumentKind::Expression])
+            }
+            CommandKind::MakeNewMap => {
+                CommandInfo::from(1, 1, vec![CommandArgumentKind::Name])
+            }
+            CommandKind::SetMapEntry => {
+                // The map, the key and the value
+                CommandInfo::from(3, 3, vec![CommandArgumentKind::Name, CommandArgumentKind::Expression,
+                    CommandArgumentKind::Expression])
+            }
+            CommandKind::IndexMap => {
+                // The map, the key and where the value goes
+                CommandInfo::from(3, 3, vec![CommandArgumentKind::Name, CommandArgumentKind::Expression,
+                    CommandArgumentKind::Name])
+            }
+            CommandKind::RemoveMapEntry => {
+                CommandInfo::from(2, 2, vec![CommandArgumentKind::Name, CommandArgumentKind::Expression])
+            }
+            CommandKind::QueryMapSize | CommandKind::ListMapKeys => {
+                CommandInfo::from(2, 2, vec![CommandArgumentKind::Name, CommandArgumentKind::Name])
+            }
+            CommandKind::BreakScope | CommandKind::SkipNextIteration | CommandKind::BreakLoop => CommandInfo::from(0, 0, vec![]),
+            CommandKind::Assert => {
+                CommandInfo::from(2, 2, vec![CommandArgumentKind::Expression,
+                                             CommandArgumentKind::Expression])
+            }
+            CommandKind::Switch => CommandInfo::from(1, 1, vec![CommandArgumentKind::Expression]),
+            CommandKind::SwitchCase => CommandInfo::from(1, -1, vec![CommandArgumentKind::Expression]),
+            CommandKind::SwitchDefault => CommandInfo::from(0, 0, vec![]),
+            CommandKind::ElseIf => CommandInfo::from(1, 1, vec![CommandArgumentKind::Expression]),
+            CommandKind::Else => CommandInfo::from(0, 0, vec![]),
         }
     }
 }
@@ -651,6 +987,10 @@ impl CommandInfo {
 pub enum CommandArgument {
     Name(String),
     Expression(Expression),
+    /// A method of a variable (receiver, method), like `NOME.TAMANHO` in a call
+    Method(String, String),
+    /// The variables that get the values returned by a function, like `Q, R` in `PEGA DA JAULA: Q, R : DIVIDE, 7, 2`
+    Targets(Vec<String>),
 }
 
 #[derive(Debug)]
@@ -663,6 +1003,8 @@ pub struct Command {
 pub enum ParserResult {
     FunctionStart(FunctionDeclaration),
     FunctionEnd,
+    /// A FICHA line, for the function declared next
+    Annotations(Vec<Annotation>),
     Command(Command),
     Nothing,
 }
@@ -714,7 +1056,7 @@ fn parse_function(src : &[char], offset : &mut usize) -> Result<ParserResult, St
     match next_token(src, offset) {
         Ok(t) => {
            match t {
-               Token::NewLine | Token::None => {}
+               Token::NewLine | Token::None | Token::Comment => {}
                Token::Operator(MathOperator::ParenthesisLeft) => {
                    // Argument list
 
@@ -750,322 +1092,217 @@ fn parse_function(src : &[char], offset : &mut usize) -> Result<ParserResult, St
     Ok(ParserResult::FunctionStart(func))
 }
 
-fn parse_sub_expression(src : &[char], offset : &mut usize, expr : &mut Expression, root : bool) -> Result<(), String> {
-
-    let mut last_was_value;
-
-    let mut dummy_offset = *offset;
-
-    let first = match next_token(src, &mut dummy_offset) {
-        Ok(t) => t,
-        Err(e) => return Err(e),
-    };
-
-    let mut nodes = vec![];
-
-    let mut values : Vec<ExpressionNode> = vec![];
-    let mut operations : Vec<MathOperator> = vec![];
-    let mut last_was_important = false;
-
-    match first {
-        Token::Comment | Token::None => return Ok(()),
-        Token::Integer(i) => {
-            last_was_value = true;
-
-            if last_was_important {
-                last_was_important = false;
-
-                match values.pop() {
-                    Some(v) => nodes.push(v),
-                    None => {}
-                }
-
-                let op = match operations.pop() {
-                    Some(v) => v,
-                    None => return Err("Operations tá vazio".to_owned()),
-                };
-
-                nodes.push(ExpressionNode::Value(RawValue::Integer(i)));
-                nodes.push(ExpressionNode::Operator(op));
-            } else {
-                values.push(ExpressionNode::Value(RawValue::Integer(i)));
-            }
-        }
-        Token::Number(n) => {
-            last_was_value = true;
-
-            if last_was_important {
-                last_was_important = false;
+/// Parse the annotations after FICHA, separated by commas, like `FICHA: TESTE, SEM MODO ESTRITO`
+fn parse_annotations(src : &[char], offset : &mut usize) -> Result<ParserResult, String> {
+    match next_token(src, offset)? {
+        Token::Punctuation(PunctuationKind::Colon) => {}
+        t => return Err(format!("Esperado um : depois da FICHA, encontrado {:?}", t)),
+    }
 
-                match values.pop() {
-                    Some(v) => nodes.push(v),
-                    None => {}
-                }
+    let mut annotations = vec![];
 
-                let op = match operations.pop() {
-                    Some(v) => v,
-                    None => return Err("Operations tá vazio".to_owned()),
-                };
+    loop {
+        let name = match next_token(src, offset)? {
+            Token::Symbol(name) => name,
+            t => return Err(format!("Esperado o nome de uma anotação na FICHA, encontrado {:?}", t)),
+        };
 
-                nodes.push(ExpressionNode::Value(RawValue::Number(n)));
-                nodes.push(ExpressionNode::Operator(op));
-            } else {
-                values.push(ExpressionNode::Value(RawValue::Number(n)));
-            }
+        match Annotation::from_name(&name) {
+            Some(a) if annotations.contains(&a) => return Err(format!("A anotação {} tá repetida na FICHA", name)),
+            Some(a) => annotations.push(a),
+            None => return Err(format!("A anotação {} não existe. As que existem são TESTE, PRINCIPAL, SEM MODO ESTRITO \
+                                        e EM LINHA", name)),
         }
-        Token::Text(t) => {
-            last_was_value = true;
 
-            values.push(ExpressionNode::Value(RawValue::Text(t)));
+        match next_token(src, offset)? {
+            Token::Punctuation(PunctuationKind::Comma) => {}
+            Token::NewLine | Token::None | Token::Comment => break,
+            t => return Err(format!("Esperado uma vírgula ou o fim da FICHA, encontrado {:?}", t)),
         }
-        Token::NewLine => return Ok(()),
-        Token::Symbol(s) => {
-            last_was_value = true;
+    }
 
-            if !expr.has_symbols {
-                expr.has_symbols = true;
-            }
+    Ok(ParserResult::Annotations(annotations))
+}
 
-            if last_was_important {
-                last_was_important = false;
+/// Precedence of the binary operators, from the lowest to the highest. Unary operators (- and !) bind tighter than
+/// all of them, except for the power (so `-2 ^ 2` is -4, like in math)
+fn binary_precedence(op : MathOperator) -> Option<u32> {
+    match op {
+        MathOperator::Or => Some(1),
+        MathOperator::And => Some(2),
+        MathOperator::Equal | MathOperator::NotEqual |
+        MathOperator::Less | MathOperator::LessOrEqual |
+        MathOperator::More | MathOperator::MoreOrEqual => Some(COMPARISION_PRECEDENCE),
+        MathOperator::Plus | MathOperator::Minus => Some(4),
+        MathOperator::Multiplication | MathOperator::Division | MathOperator::Modulo => Some(5),
+        MathOperator::Power => Some(POWER_PRECEDENCE),
+        _ => None,
+    }
+}
 
-                match values.pop() {
-                    Some(v) => nodes.push(v),
-                    None => {}
-                }
+const COMPARISION_PRECEDENCE : u32 = 3;
+const UNARY_PRECEDENCE : u32 = 6;
+const POWER_PRECEDENCE : u32 = 7;
 
-                let op = match operations.pop() {
-                    Some(v) => v,
-                    None => return Err("Operations tá vazio".to_owned()),
-                };
+fn parse_operand(src : &[char], offset : &mut usize) -> Result<ExpressionNode, String> {
+    let tok = match next_token(src, offset) {
+        Ok(t) => t,
+        Err(e) => return Err(e),
+    };
 
-                nodes.push(ExpressionNode::Symbol(s));
-                nodes.push(ExpressionNode::Operator(op));
-            } else {
-                values.push(ExpressionNode::Symbol(s));
-            }
-        }
+    match tok {
+        Token::Integer(i) => Ok(ExpressionNode::Value(RawValue::Integer(i))),
+        Token::Number(n) => Ok(ExpressionNode::Value(RawValue::Number(n))),
+        Token::Text(t) => Ok(ExpressionNode::Value(RawValue::Text(t))),
+        Token::Symbol(ref s) if s == "VERDADE" => Ok(ExpressionNode::Value(RawValue::Bool(true))),
+        Token::Symbol(ref s) if s == "MENTIRA" => Ok(ExpressionNode::Value(RawValue::Bool(false))),
+        Token::Symbol(s) => Ok(ExpressionNode::Symbol(s)),
+        Token::CallerSymbol(depth, s) => Ok(ExpressionNode::CallerSymbol(depth, s)),
+        Token::Punctuation(PunctuationKind::BraceLeft) => parse_map(src, offset),
         Token::Operator(MathOperator::ParenthesisLeft) => {
-            last_was_value = true;
+            let node = parse_binary(src, offset, 0)?;
 
-            match parse_sub_expression(src, &mut dummy_offset, expr, false) {
-                Ok(_) => {}
-                Err(e) => return Err(e)
-            };
-        }
-        Token::Operator(MathOperator::ParenthesisRight) => {
-            *offset = dummy_offset;
-
-            return Ok(());
-        },
-        Token::Operator(o) => {
-            match o {
-                MathOperator::Plus | MathOperator::Minus => {
-                    // Add a zero before this
-                    values.push(ExpressionNode::Value(RawValue::Integer(0)));
-                    operations.push(o);
-                },
-                _ => return Err(format!("Scope ou expressão começa com o operator unário inválido {:?}", o)),
+            match next_token(src, offset) {
+                Ok(Token::Operator(MathOperator::ParenthesisRight)) => Ok(node),
+                Ok(t) => Err(format!("Esperado um ) pra fechar o parêntesis, encontrado {:?}", t)),
+                Err(e) => Err(e),
             }
-
-            last_was_value = false;
         }
-        Token::Punctuation(p) => {
-            match p {
-                PunctuationKind::Comma if root => {
-                    // Ok
+        Token::Operator(MathOperator::Plus) => parse_binary(src, offset, UNARY_PRECEDENCE),
+        Token::Operator(o @ MathOperator::Minus) |
+        Token::Operator(o @ MathOperator::Not) => {
+            let operand = parse_binary(src, offset, UNARY_PRECEDENCE)?;
 
-                    return Ok(());
-                }
-                _ => return Err(format!("A expressão deveria começar com um valor ou operador unário, mas começa com {:?}", p)),
-            }
+            Ok(ExpressionNode::Unary(o, Box::new(operand)))
         }
-        _ => return Err(format!("Esperado um valor ou operador na expressão, encontrado {:?}", first)),
+        Token::Operator(o) => Err(format!("Esperado um valor na expressão, mas foi encontrado o operador {:?}", o)),
+        Token::None | Token::NewLine | Token::Comment => Err("Expressão termina com um operador".to_owned()),
+        _ => Err(format!("Esperado um valor ou operador na expressão, encontrado {:?}", tok)),
     }
+}
 
-    *offset = dummy_offset;
-
-    loop {
-        if *offset >= src.len() {
-            break;
-        }
-
-        let current = match next_token(src, &mut dummy_offset) {
-            Ok(t) => t,
-            Err(e) => return Err(e)
-        };
-
-        match current {
-            Token::None | Token::Comment => return Ok(()),
-            Token::Integer(i) => {
-                if last_was_value {
-                    return Err("Dois valores seguidos na expressão".to_owned());
-                }
-
-                last_was_value = true;
-
-                if last_was_important {
-                    last_was_important = false;
-
-                    match values.pop() {
-                        Some(v) => nodes.push(v),
-                        None => {}
-                    }
-
-                    let op = match operations.pop() {
-                        Some(v) => v,
-                        None => return Err("Operations tá vazio".to_owned()),
-                    };
-
-                    nodes.push(ExpressionNode::Value(RawValue::Integer(i)));
-                    nodes.push(ExpressionNode::Operator(op));
-                } else {
-                    values.push(ExpressionNode::Value(RawValue::Integer(i)));
-                }
-            }
-            Token::Number(n) => {
-                if last_was_value {
-                    return Err("Dois valores seguidos na expressão".to_owned());
-                }
+/// Parse the entries of a map literal, after the opening brace : `"CHAVE" : valor` separated by commas, until the closing
+/// brace. Keys and values can be any expression
+fn parse_map(src : &[char], offset : &mut usize) -> Result<ExpressionNode, String> {
+    let mut entries = vec![];
 
-                last_was_value = true;
+    let mut peek_offset = *offset;
 
-                if last_was_important {
-                    last_was_important = false;
+    if next_token(src, &mut peek_offset)? == Token::Punctuation(PunctuationKind::BraceRight) {
+        *offset = peek_offset;
 
-                    match values.pop() {
-                        Some(v) => nodes.push(v),
-                        None => {}
-                    }
+        return Ok(ExpressionNode::Map(entries));
+    }
 
-                    let op = match operations.pop() {
-                        Some(v) => v,
-                        None => return Err("Operations tá vazio".to_owned()),
-                    };
+    loop {
+        let key = parse_binary(src, offset, 0)?;
 
-                    nodes.push(ExpressionNode::Value(RawValue::Number(n)));
-                    nodes.push(ExpressionNode::Operator(op));
-                } else {
-                    values.push(ExpressionNode::Value(RawValue::Number(n)));
-                }
-            }
-            Token::Text(t) => {
-                if last_was_value {
-                    return Err("Dois valores seguidos na expressão".to_owned());
-                }
+        match next_token(src, offset)? {
+            Token::Punctuation(PunctuationKind::Colon) => {}
+            t => return Err(format!("Esperado um : depois da chave do mapa, encontrado {:?}", t)),
+        }
 
-                last_was_value = true;
+        let value = parse_binary(src, offset, 0)?;
 
-                values.push(ExpressionNode::Value(RawValue::Text(t)));
-            }
-            Token::Symbol(s) => {
-                if last_was_value {
-                    return Err("Dois valores seguidos na expressão".to_owned());
-                }
+        entries.push((key, value));
 
-                last_was_value = true;
+        match next_token(src, offset)? {
+            Token::Punctuation(PunctuationKind::Comma) => {}
+            Token::Punctuation(PunctuationKind::BraceRight) => break,
+            t => return Err(format!("Esperado uma vírgula ou um }} pra fechar o mapa, encontrado {:?}", t)),
+        }
+    }
 
-                if !expr.has_symbols {
-                    expr.has_symbols = true;
-                }
+    Ok(ExpressionNode::Map(entries))
+}
 
-                if last_was_important {
-                    last_was_important = false;
+/// Parse a (sub) expression where only binary operators with a precedence of at least `min_precedence` are consumed
+fn parse_binary(src : &[char], offset : &mut usize, min_precedence : u32) -> Result<ExpressionNode, String> {
+    let mut left = parse_operand(src, offset)?;
+    // Whether `left` is a comparision made in this loop, so another comparision continues it instead of comparing its
+    // result (a comparision in parentheses is compared normally)
+    let mut left_is_comparision = false;
 
-                    match values.pop() {
-                        Some(v) => nodes.push(v),
-                        None => {}
-                    }
+    loop {
+        let mut peek_offset = *offset;
 
-                    let op = match operations.pop() {
-                        Some(v) => v,
-                        None => return Err("Operations tá vazio".to_owned()),
-                    };
+        let op = match next_token(src, &mut peek_offset) {
+            Ok(Token::Operator(o)) => o,
+            Ok(_) => break,
+            Err(e) => return Err(e),
+        };
 
-                    nodes.push(ExpressionNode::Symbol(s));
-                    nodes.push(ExpressionNode::Operator(op));
-                } else {
-                    values.push(ExpressionNode::Symbol(s));
-                }
-            }
-            Token::Operator(MathOperator::ParenthesisLeft) => {
-                last_was_value = true;
+        let precedence = match binary_precedence(op) {
+            Some(p) if p >= min_precedence => p,
+            // Either a parenthesis, which is for the caller to check, or an operator that binds looser
+            _ => break,
+        };
 
-                match parse_sub_expression(src, &mut dummy_offset, expr, false) {
-                    Ok(_) => {}
-                    Err(e) => return Err(e)
-                };
-            }
-            Token::Operator(MathOperator::ParenthesisRight) => {
-                *offset = dummy_offset;
-
-                break
-            },
-            Token::Operator(o) => {
-                if !last_was_value {
-                    return Err("Dois operadores seguidos na expressão".to_owned());
-                }
+        *offset = peek_offset;
 
-                last_was_value = false;
+        // Every binary operator but the power is left-associative, so the right side only takes operators that bind
+        // tighter. The power is right-associative (`2 ^ 3 ^ 2` is `2 ^ (3 ^ 2)`), so its right side takes other powers
+        let right = if precedence == POWER_PRECEDENCE {
+            parse_binary(src, offset, precedence)?
+        } else {
+            parse_binary(src, offset, precedence + 1)?
+        };
 
-                last_was_important = match o {
-                    MathOperator::Plus | MathOperator::Minus => false,
-                    _ => true,
-                };
+        let is_comparision = precedence == COMPARISION_PRECEDENCE;
 
-                operations.push(o);
+        left = match (op, left) {
+            (MathOperator::And, left) | (MathOperator::Or, left) => ExpressionNode::Logical(op, Box::new(left), Box::new(right)),
+            (_, ExpressionNode::Binary(first_op, first, middle)) if is_comparision && left_is_comparision => {
+                ExpressionNode::Chain(vec![first_op, op], vec![*first, *middle, right])
             }
-            Token::Punctuation(p) => {
-                match p {
-                    PunctuationKind::Comma if root => {
-                        // Ok. Do not set offset to dummy_offset, since we want the lower calls and the parser to see the comma
+            (_, ExpressionNode::Chain(mut ops, mut operands)) if is_comparision && left_is_comparision => {
+                ops.push(op);
+                operands.push(right);
 
-                        break;
-                    }
-                    _ => return Err(format!("Erro: {:?} no meio da expressão", p)),
-                }
+                ExpressionNode::Chain(ops, operands)
             }
-            Token::NewLine => break,
-            _ => return Err(format!("Esperado um valor ou operador na expressão, encontrado {:?}", current)),
-        }
+            (_, left) => ExpressionNode::Binary(op, Box::new(left), Box::new(right)),
+        };
 
-        *offset = dummy_offset;
+        left_is_comparision = is_comparision;
     }
 
-    if !last_was_value {
-        return Err("Expressão termina com um operador".to_owned());
-    }
+    Ok(left)
+}
 
-    for node in nodes {
-        expr.nodes.push(node);
-    }
+fn parse_expression(src : &[char], offset : &mut usize) -> Result<Expression, String> {
+    let root = parse_binary(src, offset, 0)?;
 
-    if values.len() == operations.len() {
-        // Ok
-    } else if operations.len() == values.len() - 1 {
-        let first = values.remove(0);
+    // Check if the expression really ended here, without consuming what comes next
 
-        expr.nodes.push(first);
-    } else {
-        return Err(format!("Formatação inválida da expressão resultado. Lenv : {}. lenO: {}", values.len(), operations.len()));
-    }
+    let mut peek_offset = *offset;
 
-    for _ in 0..values.len() {
-        let val = values.remove(0);
-        let op = operations.remove(0);
-        expr.nodes.push(val);
-        expr.nodes.push(ExpressionNode::Operator(op));
+    match next_token(src, &mut peek_offset) {
+        Ok(Token::None) | Ok(Token::NewLine) | Ok(Token::Comment) |
+        Ok(Token::Punctuation(PunctuationKind::Comma)) => Ok(Expression::from(root)),
+        Ok(Token::Operator(MathOperator::ParenthesisRight)) => Err("Parêntesis fechado sem ter sido aberto".to_owned()),
+        Ok(Token::Operator(o)) => Err(format!("Operador {:?} não pode ser usado entre dois valores", o)),
+        Ok(t) => Err(format!("Dois valores seguidos na expressão : {:?}", t)),
+        Err(e) => Err(e),
     }
-
-    Ok(())
 }
 
-fn parse_expression(src : &[char], offset : &mut usize) -> Result<Expression, String> {
-    let mut expr = Expression::new();
+/// The names separated by commas before the colon of a PEGA DA JAULA
+fn parse_targets(src : &[char], offset : &mut usize) -> Result<Vec<String>, String> {
+    let mut targets = vec![];
 
-    match parse_sub_expression(src, offset, &mut expr, true) {
-        Ok(_) => Ok(expr),
-        Err(e) => Err(e),
+    loop {
+        match next_token(src, offset)? {
+            Token::Symbol(name) => targets.push(name),
+            t => return Err(format!("Esperado o nome de uma variável pra receber um valor da função, mas {:?} foi \
+                                     encontrado", t)),
+        }
+
+        match next_token(src, offset)? {
+            Token::Punctuation(PunctuationKind::Comma) => {}
+            Token::Punctuation(PunctuationKind::Colon) => return Ok(targets),
+            t => return Err(format!("Esperado uma vírgula ou um : depois das variáveis, mas {:?} foi encontrado", t)),
+        }
     }
 }
 
@@ -1101,11 +1338,15 @@ fn parse_command(src : &[char], offset : &mut usize, kp : KeyPhrase) -> Result<P
     let mut dummy_offset = *offset;
 
     match next_token(src, &mut dummy_offset) {
-        Ok(Token::NewLine) | Ok(Token::None) => has_arguments = false,
+        Ok(Token::NewLine) | Ok(Token::None) | Ok(Token::Comment) => has_arguments = false,
         Ok(_) => {},
         Err(e) => return Err(e)
     }
 
+    if has_arguments && cmd_kind == CommandKind::CallUnpack {
+        cmd.arguments.push(CommandArgument::Targets(parse_targets(src, offset)?));
+    }
+
     if has_arguments {
         let mut arg_index = 0usize;
         let mut arg_count = 0usize;
@@ -1130,7 +1371,25 @@ fn parse_command(src : &[char], offset : &mut usize, kp : KeyPhrase) -> Result<P
                     match next_token(src, offset) {
                         Ok(t) => {
                             match t {
-                                Token::Symbol(s) => cmd.arguments.push(CommandArgument::Name(s)),
+                                Token::Symbol(s) => {
+                                    // A call can be to a method of a variable, like X.TAMANHO
+                                    if (cmd_kind == CommandKind::Call || cmd_kind == CommandKind::CallUnpack) && arg_index == 0 && *offset < src.len() && src[*offset] == METHOD_CHARACTER {
+                                        *offset += 1;
+
+                                        match next_token(src, offset)? {
+                                            Token::Symbol(method) => cmd.arguments.push(CommandArgument::Method(s, method)),
+                                            t => return Err(format!("Esperado o nome de um método depois de {}{}, mas {:?} foi encontrado", s, METHOD_CHARACTER, t)),
+                                        }
+                                    } else {
+                                        cmd.arguments.push(CommandArgument::Name(s));
+                                    }
+                                }
+                                Token::CallerSymbol(depth, s) => {
+                                    let prefix = CALLER_FRAME_CHARACTER.to_string().repeat(depth);
+
+                                    return Err(format!("O argumento espera que o argumento #{} seja um nome, mas {}{} foi encontrado",
+                                                       arg_count, prefix, s));
+                                }
                                 _ => return Err(format!("O argumento espera que o argumento #{} seja um nome, mas {:?} foi encontrado", arg_count, t)),
                             }
                         }
@@ -1154,7 +1413,7 @@ fn parse_command(src : &[char], offset : &mut usize, kp : KeyPhrase) -> Result<P
             match next_token(src, &mut peek_offset) {
                 Ok(t) => {
                     match t {
-                        Token::None | Token::NewLine => break,
+                        Token::None | Token::NewLine | Token::Comment => break,
                         Token::Punctuation(p) => {
                             match p {
                                 PunctuationKind::Comma => {
@@ -1181,18 +1440,36 @@ fn parse_command(src : &[char], offset : &mut usize, kp : KeyPhrase) -> Result<P
     Ok(ParserResult::Command(cmd))
 }
 
-pub fn parse_line(src : &str) -> Result<ParserResult, String> {
+/// Parse a line of source. Errors have the column where the parser stopped
+pub fn parse_line(src : &str) -> Result<ParserResult, BirlError> {
     if src.trim().is_empty() {
         return Ok(ParserResult::Nothing);
     }
 
     let chars = src.chars().collect::<Vec<char>>();
+    let mut offset = 0usize;
 
-    // try to infer what we're parsing from the first token
+    parse_chars(&chars, &mut offset).map_err(|message| {
+        let location = ErrorLocation { column : Some(offset.min(chars.len()) + 1), .. ErrorLocation::default() };
 
-    let mut offset = 0usize;
+        BirlError::Parse(message, Box::new(location))
+    })
+}
+
+/// Parse every line of a source that came as bytes, which don't have to be valid UTF-8 (what isn't is replaced), stopping
+/// at the first line that has an error. For fuzzing, or anything else that gets source it can't trust
+pub fn parse_bytes(bytes : &[u8]) -> Result<Vec<ParserResult>, BirlError> {
+    let source = String::from_utf8_lossy(bytes);
+
+    source.lines().enumerate()
+        .map(|(index, line)| parse_line(line).map_err(|e| e.at_line(index + 1)))
+        .collect()
+}
+
+fn parse_chars(chars : &[char], offset : &mut usize) -> Result<ParserResult, String> {
+    // try to infer what we're parsing from the first token
 
-    let first = match next_token(&chars, &mut offset) {
+    let first = match next_token(chars, offset) {
         Ok(t) => t,
         Err(e) => return Err(e),
     };
@@ -1202,22 +1479,24 @@ pub fn parse_line(src : &str) -> Result<ParserResult, String> {
         Token::Command(kp) => {
             match kp {
                 KeyPhrase::FunctionEnd => Ok(ParserResult::FunctionEnd),
-                KeyPhrase::FunctionStart => parse_function(&chars, &mut offset),
-                _ => parse_command(&chars, &mut offset, kp),
+                KeyPhrase::FunctionStart => parse_function(chars, offset),
+                KeyPhrase::Annotation => parse_annotations(chars, offset),
+                _ => parse_command(chars, offset, kp),
             }
         }
-        Token::Text(_) | Token::Number(_) | Token::Integer(_) | Token::Operator(MathOperator::ParenthesisLeft) => {
-            offset = 0;
-            parse_command(&chars, &mut offset, KeyPhrase::PrintDebug)
+        Token::Text(_) | Token::Number(_) | Token::Integer(_) | Token::Operator(MathOperator::ParenthesisLeft) |
+        Token::Operator(MathOperator::Minus) | Token::Operator(MathOperator::Not) => {
+            *offset = 0;
+            parse_command(chars, offset, KeyPhrase::PrintDebug)
         }
         Token::Symbol(sym) => {
-            match next_token(&chars, &mut offset) {
+            match next_token(chars, offset) {
                 Ok(Token::Punctuation(PunctuationKind::Colon)) => {
                     return Err(format!("O comando \"{}\" não existe.", sym));
                 }
                 Ok(_) => {
-                    offset = 0;
-                    parse_command(&chars, &mut offset, KeyPhrase::PrintDebug)
+                    *offset = 0;
+                    parse_command(chars, offset, KeyPhrase::PrintDebug)
                 }
                 Err(e) => return Err(e)
             }
@@ -1231,8 +1510,7 @@ mod tests {
     fn functions() {
         use parser::*;
 
-        {
-            let src = "JAULA TESTANDO";
+        for src in &["JAULA TESTANDO", "JAULA TESTANDO # Comentário"] {
 
             let got_func = match parse_line(src) {
                 Ok(ParserResult::FunctionStart(func)) => func,
@@ -1262,6 +1540,20 @@ mod tests {
         }
     }
 
+    #[test]
+    fn annotations() {
+        use parser::*;
+
+        match parse_line("FICHA: TESTE, SEM MODO ESTRITO # Comentário") {
+            Ok(ParserResult::Annotations(a)) => assert_eq!(a, vec![Annotation::Test, Annotation::NoStrict]),
+            other => panic!("Era esperada uma FICHA, recebido {:?}", other),
+        }
+
+        for src in &["FICHA", "FICHA:", "FICHA: TESTE,", "FICHA: TESTE PRINCIPAL", "FICHA: 1"] {
+            assert!(parse_line(src).is_err(), "{}", src);
+        }
+    }
+
     #[test]
     fn numeric_tokens() {
         use parser::*;
@@ -1353,4 +1645,179 @@ mod tests {
             assert_eq!(tok, expected);
         }
     }
+
+    #[test]
+    fn logical_operators() {
+        use parser::*;
+        use context::RawValue;
+
+        let src = "CE QUER VER: A && 2 || 3";
+
+        let cmd = match parse_line(src) {
+            Ok(ParserResult::Command(cmd)) => cmd,
+            Ok(res) => panic!("Era esperado um comando, recebido {:?}", res),
+            Err(e) => panic!("{}", e)
+        };
+
+        let expr = match cmd.arguments.into_iter().next() {
+            Some(CommandArgument::Expression(e)) => e,
+            arg => panic!("Era esperado uma expressão, recebido {:?}", arg),
+        };
+
+        // && has a higher precedence than ||, so this is (A && 2) || 3
+
+        let and = ExpressionNode::Logical(MathOperator::And,
+                                          Box::new(ExpressionNode::Symbol("A".to_owned())),
+                                          Box::new(ExpressionNode::Value(RawValue::Integer(2))));
+
+        let expected = ExpressionNode::Logical(MathOperator::Or, Box::new(and),
+                                               Box::new(ExpressionNode::Value(RawValue::Integer(3))));
+
+        assert_eq!(expr.root, expected);
+        assert!(expr.has_symbols);
+    }
+
+    #[test]
+    fn operator_precedence() {
+        use parser::*;
+        use context::RawValue;
+
+        let parse = |src : &str| {
+            let chars = src.chars().collect::<Vec<char>>();
+            let mut offset = 0usize;
+
+            match parse_expression(&chars, &mut offset) {
+                Ok(e) => e.root,
+                Err(e) => panic!("{} : {}", src, e),
+            }
+        };
+
+        let int = |i| Box::new(ExpressionNode::Value(RawValue::Integer(i)));
+        let bin = |op, l, r| Box::new(ExpressionNode::Binary(op, l, r));
+
+        // Multiplication binds tighter than addition
+        assert_eq!(parse("1 + 2 * 3"), *bin(MathOperator::Plus, int(1), bin(MathOperator::Multiplication, int(2), int(3))));
+        assert_eq!(parse("1 * 2 + 3"), *bin(MathOperator::Plus, bin(MathOperator::Multiplication, int(1), int(2)), int(3)));
+
+        // Operators with the same precedence are evaluated from left to right
+        assert_eq!(parse("10 - 2 - 3"), *bin(MathOperator::Minus, bin(MathOperator::Minus, int(10), int(2)), int(3)));
+        assert_eq!(parse("8 / 4 * 2"), *bin(MathOperator::Multiplication, bin(MathOperator::Division, int(8), int(4)), int(2)));
+        assert_eq!(parse("1 + 7 % 3"), *bin(MathOperator::Plus, int(1), bin(MathOperator::Modulo, int(7), int(3))));
+
+        // The power binds tighter than the unary operators, and is right-associative
+        assert_eq!(parse("2 * 3 ^ 2"), *bin(MathOperator::Multiplication, int(2), bin(MathOperator::Power, int(3), int(2))));
+        assert_eq!(parse("2 ^ 3 ^ 2"), *bin(MathOperator::Power, int(2), bin(MathOperator::Power, int(3), int(2))));
+        assert_eq!(parse("-2 ^ 2"),
+                   ExpressionNode::Unary(MathOperator::Minus, bin(MathOperator::Power, int(2), int(2))));
+        assert_eq!(parse("2 ^ -1"),
+                   *bin(MathOperator::Power, int(2), Box::new(ExpressionNode::Unary(MathOperator::Minus, int(1)))));
+
+        // Parentheses, including nested ones and ones on the right side
+        assert_eq!(parse("(1 + 2) * 3"), *bin(MathOperator::Multiplication, bin(MathOperator::Plus, int(1), int(2)), int(3)));
+        assert_eq!(parse("2 - (3 - (4 - 5))"),
+                   *bin(MathOperator::Minus, int(2), bin(MathOperator::Minus, int(3), bin(MathOperator::Minus, int(4), int(5)))));
+
+        // Unary operators bind tighter than everything
+        assert_eq!(parse("-2 * 3"),
+                   *bin(MathOperator::Multiplication, Box::new(ExpressionNode::Unary(MathOperator::Minus, int(2))), int(3)));
+        assert_eq!(parse("2 * -3"),
+                   *bin(MathOperator::Multiplication, int(2), Box::new(ExpressionNode::Unary(MathOperator::Minus, int(3)))));
+
+        // Comparisions come after arithmetic, and logical operators after comparisions
+        assert_eq!(parse("1 + 1 == 2"), *bin(MathOperator::Equal, bin(MathOperator::Plus, int(1), int(1)), int(2)));
+        assert_eq!(parse("1 < 2 && 3 >= 4"),
+                   ExpressionNode::Logical(MathOperator::And,
+                                           bin(MathOperator::Less, int(1), int(2)),
+                                           bin(MathOperator::MoreOrEqual, int(3), int(4))));
+
+        // Comparisions can be chained, but not when the first one is in parentheses
+        assert_eq!(parse("1 < 2 <= 3 == 4"),
+                   ExpressionNode::Chain(vec![MathOperator::Less, MathOperator::LessOrEqual, MathOperator::Equal],
+                                         vec![*int(1), *int(2), *int(3), *int(4)]));
+        assert_eq!(parse("1 < 2 + 3 < 4 && 5"),
+                   ExpressionNode::Logical(MathOperator::And,
+                                           Box::new(ExpressionNode::Chain(vec![MathOperator::Less, MathOperator::Less],
+                                                                          vec![*int(1), *bin(MathOperator::Plus, int(2), int(3)), *int(4)])),
+                                           int(5)));
+        assert_eq!(parse("(1 < 2) < 3"), *bin(MathOperator::Less, bin(MathOperator::Less, int(1), int(2)), int(3)));
+
+        // Invalid expressions
+        for src in &["1 2", "(1 + 2", "1 + 2)", "1 +", "* 2"] {
+            let chars = src.chars().collect::<Vec<char>>();
+            let mut offset = 0usize;
+
+            assert!(parse_expression(&chars, &mut offset).is_err(), "{} deveria ser inválida", src);
+        }
+    }
+
+    #[test]
+    fn expression_symbols() {
+        use parser::*;
+
+        let cmd = match parse_line("VEM: TOTAL, (A + B) * A - !C") {
+            Ok(ParserResult::Command(cmd)) => cmd,
+            Ok(res) => panic!("Era esperado um comando, recebido {:?}", res),
+            Err(e) => panic!("{}", e)
+        };
+
+        let expr = match cmd.arguments.into_iter().nth(1) {
+            Some(CommandArgument::Expression(e)) => e,
+            arg => panic!("Era esperado uma expressão, recebido {:?}", arg),
+        };
+
+        let mut symbols = vec![];
+        expr.root.collect_symbols(&mut symbols);
+
+        // Each symbol appears once, in the order they're used
+
+        assert_eq!(symbols, vec!["A".to_owned(), "B".to_owned(), "C".to_owned()]);
+    }
+
+    #[test]
+    fn caller_variables() {
+        use parser::*;
+
+        let cmd = match parse_line("CE QUER VER: @TOTAL + @@MEU NOME") {
+            Ok(ParserResult::Command(cmd)) => cmd,
+            Ok(res) => panic!("Era esperado um comando, recebido {:?}", res),
+            Err(e) => panic!("{}", e)
+        };
+
+        let expr = match cmd.arguments.into_iter().next() {
+            Some(CommandArgument::Expression(e)) => e,
+            arg => panic!("Era esperado uma expressão, recebido {:?}", arg),
+        };
+
+        let expected = ExpressionNode::Binary(MathOperator::Plus,
+                                              Box::new(ExpressionNode::CallerSymbol(1, "TOTAL".to_owned())),
+                                              Box::new(ExpressionNode::CallerSymbol(2, "MEU NOME".to_owned())));
+
+        assert_eq!(expr.root, expected);
+
+        // Caller variables can't be written to, so they aren't accepted as names
+
+        match parse_line("BORA: @TOTAL, 2") {
+            Err(e) => assert_eq!(e.message(), "O argumento espera que o argumento #0 seja um nome, mas @TOTAL foi encontrado"),
+            Ok(res) => panic!("Era esperado um erro, recebido {:?}", res),
+        }
+
+        assert!(parse_line("CE QUER VER: @").is_err());
+    }
+
+    #[test]
+    fn source_bytes() {
+        use parser::*;
+
+        // Lines ending in spaces, and bytes that aren't UTF-8
+        let results = parse_bytes(b"VEM: X, 1  \nJAULA SHOW \nCE QUER VER ISSO: \"\xff\" \t\n").unwrap();
+        assert_eq!(results.len(), 3);
+
+        assert!(parse_bytes(b"VEM: ").is_err());
+        assert!(parse_bytes(b"CE QUER VER ISSO: 1 + ").is_err());
+
+        match parse_bytes(b"VEM: X, 1\nVEM: X, )") {
+            Err(e) => assert_eq!(e.location().line, Some(2)),
+            Ok(res) => panic!("Era esperado um erro, recebido {:?}", res),
+        }
+    }
 }
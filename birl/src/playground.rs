@@ -0,0 +1,462 @@
+//! Runs untrusted code with limits on instructions, memory, time and output, for playgrounds, bots and anything else
+//! that runs code it didn't write. The result can be turned into JSON to be sent back to whoever asked for it
+
+use context::{ Context, BIRL_GLOBAL_FUNCTION_ID };
+use vm::{ VirtualMachineBuilder, ExecutionStatus };
+use vm_io::VmIo;
+use standard_lib::{ module_deterministic_standard_library, file_functions };
+use diagnostic::{ Diagnostic, push_json_string };
+use bytecode::CompiledProgram;
+use error::BirlError;
+
+use std::collections::VecDeque;
+use std::time::{ Duration, Instant };
+use std::rc::Rc;
+use std::cell::{ Cell, RefCell };
+
+/// How often (in instructions) the time limit is checked, since asking for the time isn't free
+const TIME_CHECK_INTERVAL : u64 = 1024;
+
+/// What the code being run is allowed to use
+#[derive(Debug, Clone)]
+pub struct Limits {
+    /// Maximum number of instructions executed
+    pub fuel : u64,
+    /// Maximum time running. None for no limit (the clock isn't available in every target)
+    pub time : Option<Duration>,
+    /// Maximum size of everything printed, in bytes
+    pub max_output : usize,
+    /// Maximum size of a single text, in bytes
+    pub max_text_size : usize,
+    /// Maximum number of elements of a single list
+    pub max_list_size : usize,
+    /// Maximum number of texts and lists allocated at the same time
+    pub max_special_items : usize,
+    /// Size of the stack of each function frame
+    pub stack_size : usize,
+    /// Maximum number of functions running at the same time
+    pub max_call_depth : usize,
+    /// Whether the standard library is available
+    pub standard_library : bool,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            fuel : 10_000_000,
+            time : Some(Duration::from_secs(5)),
+            max_output : 64 * 1024,
+            max_text_size : 1024 * 1024,
+            max_list_size : 64 * 1024,
+            max_special_items : 16 * 1024,
+            stack_size : 256,
+            max_call_depth : 1024,
+            standard_library : true,
+        }
+    }
+}
+
+/// How a run ended
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunStatus {
+    /// There was no more code to run
+    Finished,
+    /// The program asked to quit
+    Quit,
+    /// The code didn't compile
+    CompileError,
+    /// There was an error running the code
+    RuntimeError,
+    /// The instruction limit was reached
+    OutOfFuel,
+    /// The time limit was reached
+    TimedOut,
+    /// The output limit was reached
+    OutputLimit,
+}
+
+impl RunStatus {
+    /// Name used in the JSON
+    pub fn name(&self) -> &'static str {
+        match *self {
+            RunStatus::Finished => "finished",
+            RunStatus::Quit => "quit",
+            RunStatus::CompileError => "compile_error",
+            RunStatus::RuntimeError => "runtime_error",
+            RunStatus::OutOfFuel => "out_of_fuel",
+            RunStatus::TimedOut => "timed_out",
+            RunStatus::OutputLimit => "output_limit",
+        }
+    }
+
+    /// Whether the code ran to the end without any problems
+    pub fn is_success(&self) -> bool {
+        matches!(*self, RunStatus::Finished | RunStatus::Quit)
+    }
+}
+
+/// Everything that came out of running the code
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunResult {
+    pub status : RunStatus,
+    pub output : String,
+    pub diagnostics : Vec<Diagnostic>,
+    /// Number of instructions executed
+    pub instructions : u64,
+}
+
+impl RunResult {
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+
+        json.push_str("{\"status\":");
+        push_json_string(&mut json, self.status.name());
+        json.push_str(",\"success\":");
+        json.push_str(if self.status.is_success() { "true" } else { "false" });
+        json.push_str(",\"output\":");
+        push_json_string(&mut json, &self.output);
+        json.push_str(",\"instructions\":");
+        json.push_str(&self.instructions.to_string());
+        json.push_str(",\"diagnostics\":[");
+
+        for (index, diagnostic) in self.diagnostics.iter().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+
+            json.push_str(&diagnostic.to_json());
+        }
+
+        json.push_str("]}");
+
+        json
+    }
+}
+
+/// Input given beforehand and output kept in memory, up to a limit
+struct SandboxIo {
+    input : VecDeque<String>,
+    output : Rc<RefCell<String>>,
+    max_output : usize,
+    output_exceeded : Rc<Cell<bool>>,
+}
+
+impl VmIo for SandboxIo {
+    fn read_line(&mut self) -> Result<Option<String>, String> {
+        Ok(self.input.pop_front())
+    }
+
+    fn write(&mut self, text : &str) -> Result<(), String> {
+        let mut output = self.output.borrow_mut();
+
+        if output.len() + text.len() > self.max_output {
+            // Keep what fits, without cutting a character in half
+            let mut end = self.max_output - output.len();
+
+            while ! text.is_char_boundary(end) {
+                end -= 1;
+            }
+
+            output.push_str(&text[..end]);
+            self.output_exceeded.set(true);
+
+            return Err(format!("Limite de output atingido ({} bytes)", self.max_output));
+        }
+
+        output.push_str(text);
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn is_tty(&self) -> bool {
+        false
+    }
+}
+
+/// Why running stopped before the code ended
+enum Stop {
+    Error(BirlError),
+    OutOfFuel,
+    TimedOut,
+}
+
+struct Runner<'a> {
+    ctx : Context,
+    limits : &'a Limits,
+    start : Option<Instant>,
+}
+
+impl<'a> Runner<'a> {
+    /// Execute instructions until the code stops by itself or some limit is reached
+    fn run_until_stopped(&mut self) -> Result<ExecutionStatus, Stop> {
+        loop {
+            // The fuel is counted by the VM, so it also says how many instructions ran
+            if self.ctx.get_fuel().is_some_and(|fuel| fuel.is_multiple_of(TIME_CHECK_INTERVAL)) {
+                if let (Some(start), Some(time)) = (self.start, self.limits.time) {
+                    if start.elapsed() >= time {
+                        return Err(Stop::TimedOut);
+                    }
+                }
+            }
+
+            match ExecutionStatus::from(self.ctx.execute_next_instruction()) {
+                ExecutionStatus::Halt => return Ok(ExecutionStatus::Halt),
+                ExecutionStatus::Quit => return Ok(ExecutionStatus::Quit),
+                ExecutionStatus::OutOfFuel => return Err(Stop::OutOfFuel),
+                ExecutionStatus::Error(e) => return Err(Stop::Error(*e)),
+                ExecutionStatus::Normal | ExecutionStatus::Returned | ExecutionStatus::Breakpoint => {}
+                status => return Err(Stop::Error(BirlError::from(format!("Execução parou : {:?}", status)))),
+            }
+        }
+    }
+
+    /// Run the global code and then the main function, if there is one
+    fn run_program(&mut self) -> Result<ExecutionStatus, Stop> {
+        if self.run_until_stopped()? == ExecutionStatus::Quit {
+            return Ok(ExecutionStatus::Quit);
+        }
+
+        if ! self.ctx.has_main() {
+            return Ok(ExecutionStatus::Halt);
+        }
+
+        self.ctx.call_function_by_id(::context::BIRL_MAIN_FUNCTION_ID, vec![]).map_err(Stop::Error)?;
+
+        self.run_until_stopped()
+    }
+}
+
+/// Add the standard library, without anything that gives away information about the host or touches its files
+pub fn add_sandboxed_standard_library(ctx : &mut Context) -> Result<(), BirlError> {
+    let mut module = module_deterministic_standard_library();
+    let file_functions = file_functions();
+
+    module.plugin_functions.retain(|p| ! file_functions.contains(&p.name));
+
+    ctx.add_module(module)
+}
+
+/// Output of a sandboxed context, kept after the context is gone
+struct SandboxOutput {
+    text : Rc<RefCell<String>>,
+    exceeded : Rc<Cell<bool>>,
+}
+
+/// A context whose VM and IO follow `limits`, with the global code started and the standard library (if allowed) added
+fn sandboxed_context(input : &str, limits : &Limits, output : &SandboxOutput) -> Result<Context, RunResult> {
+    let builder = VirtualMachineBuilder::new()
+        .stack_size(limits.stack_size)
+        .max_text_size(Some(limits.max_text_size))
+        .max_list_size(Some(limits.max_list_size))
+        .max_special_items(Some(limits.max_special_items))
+        .max_call_depth(Some(limits.max_call_depth))
+        .fuel(Some(limits.fuel));
+
+    let mut ctx = match Context::with_vm(builder) {
+        Ok(c) => c,
+        Err(e) => return Err(RunResult {
+            status : RunStatus::RuntimeError,
+            output : String::new(),
+            diagnostics : vec![Diagnostic::error(e)],
+            instructions : 0,
+        }),
+    };
+
+    let _ = ctx.set_io(Box::new(SandboxIo {
+        input : input.lines().map(|l| l.to_owned()).collect(),
+        output : output.text.clone(),
+        max_output : limits.max_output,
+        output_exceeded : output.exceeded.clone(),
+    }));
+
+    let ready = ctx.call_function_by_id(BIRL_GLOBAL_FUNCTION_ID, vec![])
+        .and_then(|_| if limits.standard_library { add_sandboxed_standard_library(&mut ctx) } else { Ok(()) });
+
+    match ready {
+        Ok(_) => Ok(ctx),
+        Err(e) => Err(RunResult {
+            status : RunStatus::CompileError,
+            output : String::new(),
+            diagnostics : vec![Diagnostic::error(e.into())],
+            instructions : 0,
+        }),
+    }
+}
+
+/// Run the code already in the context until it ends or a limit is reached. `source` is used to point out where the
+/// errors are, when there is one
+fn run_loaded(ctx : Context, source : Option<&str>, limits : &Limits, output : SandboxOutput,
+              mut diagnostics : Vec<Diagnostic>) -> RunResult {
+    let mut runner = Runner {
+        ctx,
+        limits,
+        start : limits.time.map(|_| Instant::now()),
+    };
+
+    let status = match runner.run_program() {
+        Ok(ExecutionStatus::Quit) => RunStatus::Quit,
+        Ok(_) => RunStatus::Finished,
+        Err(Stop::Error(e)) => {
+            let diagnostic = Diagnostic::from_runtime_error(&e);
+
+            diagnostics.push(match source {
+                Some(source) => diagnostic.with_source(source),
+                None => diagnostic,
+            });
+
+            if output.exceeded.get() {
+                RunStatus::OutputLimit
+            } else {
+                RunStatus::RuntimeError
+            }
+        }
+        Err(Stop::OutOfFuel) => {
+            diagnostics.push(Diagnostic::error(format!("Limite de {} instruções atingido", limits.fuel)));
+            RunStatus::OutOfFuel
+        }
+        Err(Stop::TimedOut) => {
+            diagnostics.push(Diagnostic::error("Limite de tempo atingido".to_owned()));
+            RunStatus::TimedOut
+        }
+    };
+
+    let instructions = limits.fuel - runner.ctx.get_fuel().unwrap_or(0);
+
+    // The runner holds the context, which holds the IO, so it has to go before the output can be taken
+    drop(runner);
+
+    let output = output.text.borrow().clone();
+
+    RunResult {
+        status,
+        output,
+        diagnostics,
+        instructions,
+    }
+}
+
+/// Compile and run `source` within `limits`, giving each line of `input` to the program as a line of input
+pub fn run(source : &str, input : &str, limits : &Limits) -> RunResult {
+    let output = SandboxOutput { text : Rc::new(RefCell::new(String::new())), exceeded : Rc::new(Cell::new(false)) };
+
+    let mut ctx = match sandboxed_context(input, limits, &output) {
+        Ok(ctx) => ctx,
+        Err(result) => return result,
+    };
+
+    let mut diagnostics = vec![];
+
+    let compiled = ctx.add_source_string(source.to_owned());
+
+    for warning in ctx.take_warnings() {
+        diagnostics.push(Diagnostic::from_warning(&warning).with_source(source));
+    }
+
+    if let Err(e) = compiled {
+        let diagnostic = match ctx.last_compile_error() {
+            Some(error) => Diagnostic::from_compile_error(error).with_source(source),
+            None => Diagnostic::error(e.to_string()),
+        };
+
+        diagnostics.push(diagnostic);
+
+        return RunResult {
+            status : RunStatus::CompileError,
+            output : String::new(),
+            diagnostics,
+            instructions : 0,
+        };
+    }
+
+    run_loaded(ctx, Some(source), limits, output, diagnostics)
+}
+
+/// Load the bytes of a `.birlc` file and run it within `limits`, like `run`. The bytes may be anything, so a program that
+/// can't be read or loaded is a CompileError. It has to be compiled with the same plugins the playground has : the
+/// sandboxed standard library, or nothing if `limits` doesn't allow it
+pub fn run_bytecode(bytes : &[u8], input : &str, limits : &Limits) -> RunResult {
+    let output = SandboxOutput { text : Rc::new(RefCell::new(String::new())), exceeded : Rc::new(Cell::new(false)) };
+
+    let mut ctx = match sandboxed_context(input, limits, &output) {
+        Ok(ctx) => ctx,
+        Err(result) => return result,
+    };
+
+    if let Err(e) = CompiledProgram::from_bytes(bytes).and_then(|program| ctx.load_program(program)) {
+        return RunResult {
+            status : RunStatus::CompileError,
+            output : String::new(),
+            diagnostics : vec![Diagnostic::error(e)],
+            instructions : 0,
+        };
+    }
+
+    run_loaded(ctx, None, limits, output, vec![])
+}
+
+mod tests {
+    #[test]
+    fn playground_limits() {
+        use playground::{ run, Limits, RunStatus };
+
+        let limits = Limits::default();
+
+        let result = run("JAULA SHOW\nFALA AÍ: NOME\nCE QUER VER ISSO: \"BORA, \" + NOME\nSAINDO DA JAULA", "BAMBAM", &limits);
+
+        assert_eq!(result.status, RunStatus::Finished);
+        assert_eq!(result.output, "BORA, BAMBAM\n");
+        assert_eq!(result.to_json(), format!("{{\"status\":\"finished\",\"success\":true,\
+            \"output\":\"BORA, BAMBAM\\n\",\"instructions\":{},\"diagnostics\":[]}}", result.instructions));
+
+        let result = run("JAULA SHOW\nCE QUER VER ISSO: NADA_AQUI\nSAINDO DA JAULA", "", &limits);
+
+        assert_eq!(result.status, RunStatus::CompileError);
+        assert_eq!(result.diagnostics.len(), 1);
+
+        // The host's files are out of reach
+        let result = run("JAULA SHOW
+É HORA DO: ABRE PRA LER, \"/etc/passwd\"\nSAINDO DA JAULA", "", &limits);
+
+        assert_eq!(result.status, RunStatus::CompileError);
+
+        let endless = "JAULA SHOW\nENQUANTO É MEMO: 1, 1\nCE QUER VER ISSO: \"BIRL\"\nFIM\nSAINDO DA JAULA";
+        let few_instructions = Limits { fuel : 1000, max_output : 1024 * 1024, .. Limits::default() };
+
+        assert_eq!(run(endless, "", &few_instructions).status, RunStatus::OutOfFuel);
+
+        let little_output = Limits { max_output : 16, .. Limits::default() };
+        let result = run(endless, "", &little_output);
+
+        assert_eq!(result.status, RunStatus::OutputLimit);
+        assert_eq!(result.output.len(), 16);
+    }
+
+    #[test]
+    fn playground_bytecode() {
+        use context::{ Context, BIRL_GLOBAL_FUNCTION_ID };
+        use playground::{ run_bytecode, Limits, RunStatus };
+
+        let limits = Limits { standard_library : false, .. Limits::default() };
+
+        let mut ctx = Context::new();
+        ctx.call_function_by_id(BIRL_GLOBAL_FUNCTION_ID, vec![]).unwrap();
+        ctx.add_source_string("JAULA SHOW\nFALA AÍ: NOME\nCE QUER VER ISSO: \"BORA, \" + NOME\nSAINDO DA JAULA".to_owned()).unwrap();
+
+        let bytes = ctx.compiled_program().to_bytes();
+        let result = run_bytecode(&bytes, "BAMBAM", &limits);
+
+        assert_eq!(result.status, RunStatus::Finished);
+        assert_eq!(result.output, "BORA, BAMBAM\n");
+
+        // Anything that isn't a whole program doesn't get to run
+        assert_eq!(run_bytecode(b"BIRL", "", &limits).status, RunStatus::CompileError);
+        assert_eq!(run_bytecode(&bytes[..bytes.len() - 1], "", &limits).status, RunStatus::CompileError);
+
+        // Compiled without the standard library, so it doesn't load with it
+        assert_eq!(run_bytecode(&bytes, "", &Limits::default()).status, RunStatus::CompileError);
+    }
+}
@@ -0,0 +1,206 @@
+//! Helpers for writing plugins without going through the special storage by hand. A plugin keeps the usual signature
+//! and puts what it got in a PluginContext, which gives the arguments in the order of the parameters (the plugin gets
+//! them from the last to the first) and creates the texts and lists it returns :
+//!
+//! ```
+//! use birl::vm::{ DynamicValue, VirtualMachine };
+//! use birl::plugin::PluginContext;
+//!
+//! fn greet(arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+//!     let mut ctx = PluginContext::new(arguments, vm);
+//!     let name = ctx.arg_str(0)?;
+//!
+//!     Ok(Some(ctx.make_text(&format!("Fala, {}!", name))?))
+//! }
+//! ```
+
+use vm::{ DynamicValue, SpecialItemData, VirtualMachine };
+use parser::{ TypeKind, IntegerType };
+
+pub struct PluginContext<'a> {
+    /// In the order of the parameters
+    arguments : Vec<DynamicValue>,
+    vm : &'a mut VirtualMachine,
+}
+
+impl<'a> PluginContext<'a> {
+    /// Wrap the arguments and the VM a plugin was called with
+    pub fn new(mut arguments : Vec<DynamicValue>, vm : &'a mut VirtualMachine) -> PluginContext<'a> {
+        arguments.reverse();
+
+        PluginContext { arguments, vm }
+    }
+
+    /// Number of arguments the plugin got
+    pub fn len(&self) -> usize {
+        self.arguments.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.arguments.is_empty()
+    }
+
+    /// The argument for the parameter `index` (from 0), as it is
+    pub fn arg(&self, index : usize) -> Result<DynamicValue, String> {
+        match self.arguments.get(index) {
+            Some(&value) => Ok(value),
+            None => Err(format!("O plugin pediu o argumento {}, mas só recebeu {}", index + 1, self.arguments.len())),
+        }
+    }
+
+    fn wrong_type(index : usize, expected : TypeKind, value : DynamicValue) -> String {
+        format!("O argumento {} devia ser {}, mas é {}", index + 1, expected, value.get_kind())
+    }
+
+    /// The argument `index` as a text, copied out of the VM
+    pub fn arg_str(&self, index : usize) -> Result<String, String> {
+        match self.arg(index)? {
+            DynamicValue::Text(id) => match self.vm.get_special_storage_ref().get_data_ref(id) {
+                Some(SpecialItemData::Text(ref t)) => Ok(t.clone()),
+                _ => Err("Erro interno : Dado special com ID fornecido não é um texto".to_owned()),
+            },
+            other => Err(PluginContext::wrong_type(index, TypeKind::Text, other)),
+        }
+    }
+
+    pub fn arg_int(&self, index : usize) -> Result<IntegerType, String> {
+        match self.arg(index)? {
+            DynamicValue::Integer(i) => Ok(i),
+            other => Err(PluginContext::wrong_type(index, TypeKind::Integer, other)),
+        }
+    }
+
+    /// The argument `index` as a number. Integers are accepted too, like in the parameters of type number
+    pub fn arg_num(&self, index : usize) -> Result<f64, String> {
+        match self.arg(index)? {
+            DynamicValue::Number(n) => Ok(n),
+            DynamicValue::Integer(i) => Ok(i as f64),
+            other => Err(PluginContext::wrong_type(index, TypeKind::Number, other)),
+        }
+    }
+
+    pub fn arg_bool(&self, index : usize) -> Result<bool, String> {
+        match self.arg(index)? {
+            DynamicValue::Bool(b) => Ok(b),
+            other => Err(PluginContext::wrong_type(index, TypeKind::Bool, other)),
+        }
+    }
+
+    /// The elements of the list in the argument `index`. They're still owned by the list
+    pub fn arg_list(&self, index : usize) -> Result<Vec<DynamicValue>, String> {
+        match self.arg(index)? {
+            DynamicValue::List(id) => match self.vm.get_special_storage_ref().get_data_ref(id) {
+                Some(SpecialItemData::List(ref l)) => Ok(l.iter().map(|v| **v).collect()),
+                _ => Err("Erro interno : Dado special com ID fornecido não é uma lista".to_owned()),
+            },
+            other => Err(PluginContext::wrong_type(index, TypeKind::List, other)),
+        }
+    }
+
+    /// Create a text, within the limits of the VM. It belongs to no one until it's returned or put in a list
+    pub fn make_text(&mut self, text : &str) -> Result<DynamicValue, String> {
+        self.vm.check_text_size(text.len())?;
+
+        let id = self.vm.get_special_storage_mut().add(SpecialItemData::Text(text.to_owned()), 0);
+
+        Ok(DynamicValue::Text(id))
+    }
+
+    /// Create a list with the elements, within the limits of the VM. The list holds its own reference to each text or
+    /// list in it, so they can be arguments or made with `make_text` and `make_list`
+    pub fn make_list(&mut self, elements : Vec<DynamicValue>) -> Result<DynamicValue, String> {
+        self.vm.check_list_size(elements.len())?;
+
+        let storage = self.vm.get_special_storage_mut();
+
+        for id in elements.iter().filter_map(|v| v.special_id()) {
+            storage.increment_ref(id)?;
+        }
+
+        let id = storage.add(SpecialItemData::List(elements.into_iter().map(Box::new).collect()), 0);
+
+        Ok(DynamicValue::List(id))
+    }
+
+    /// The VM, for everything else (calling functions, reading input, etc)
+    pub fn vm(&mut self) -> &mut VirtualMachine {
+        self.vm
+    }
+}
+
+mod tests {
+    #[test]
+    fn plugin_context() {
+        use context::Context;
+        use error::BirlError;
+        use parser::TypeKind;
+        use plugin::PluginContext;
+        use testing::run_in_context;
+        use vm::{ DynamicValue, VirtualMachine };
+
+        fn repeat(arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+            let mut ctx = PluginContext::new(arguments, vm);
+            let (text, times) = (ctx.arg_str(0)?, ctx.arg_int(1)?);
+
+            let mut parts = vec![];
+
+            for _ in 0..times {
+                parts.push(ctx.make_text(&text)?);
+            }
+
+            Ok(Some(ctx.make_list(parts)?))
+        }
+
+        fn total(arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+            let ctx = PluginContext::new(arguments, vm);
+
+            let mut sum = 0.0;
+
+            for (index, value) in ctx.arg_list(0)?.into_iter().enumerate() {
+                sum += match value {
+                    DynamicValue::Integer(i) => i as f64,
+                    DynamicValue::Number(n) => n,
+                    other => return Err(format!("O elemento {} não é um número : {:?}", index, other)),
+                };
+            }
+
+            Ok(Some(DynamicValue::Number(sum + ctx.arg_num(1)?)))
+        }
+
+        fn wrong(arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+            let ctx = PluginContext::new(arguments, vm);
+
+            ctx.arg_int(0).map(|_| None)
+        }
+
+        let run = |src : &str| {
+            let mut ctx = Context::new();
+
+            let result = ctx.add_plugin("REPETIDO".to_owned(), vec![TypeKind::Text, TypeKind::Integer], repeat)
+                .and_then(|_| ctx.add_plugin("TOTAL".to_owned(), vec![TypeKind::List, TypeKind::Number], total))
+                .and_then(|_| ctx.add_plugin("ERRADO".to_owned(), vec![TypeKind::Text], wrong));
+
+            if let Err(e) = result {
+                panic!("{}", e);
+            }
+
+            run_in_context(&mut ctx, src)
+        };
+
+        let (result, output) = run("É HORA DO: REPETIDO, \"BIRL\", 3\n\
+                                    CE QUER VER ISSO: TREZE\n\
+                                    FAZ UMA LISTA: L\n\
+                                    PÕE ISSO AQUI: L, 1\n\
+                                    PÕE ISSO AQUI: L, 2.5\n\
+                                    É HORA DO: TOTAL, L, 1\n\
+                                    CE QUER VER ISSO: TREZE");
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "(Lista) [ \"BIRL\", \"BIRL\", \"BIRL\" ]\n4.5\n");
+
+        let (result, _) = run("É HORA DO: ERRADO, \"1\"");
+        assert!(matches!(result, Err(BirlError::Runtime(ref message, _))
+                         if message.starts_with("O argumento 1 devia ser Inteiro, mas é Texto")),
+                "{:?}", result);
+    }
+}
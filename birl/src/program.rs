@@ -0,0 +1,269 @@
+//! Compiling without running. A ProgramCompiler takes the source of one or more files and gives a Program : the code,
+//! the functions and global variables it declares and the diagnostics of the compilation. A Program can be turned into
+//! as many contexts as needed, each with its own VM, so tools (an editor, the playground, a test runner) can check or
+//! run code without setting up a Context by hand :
+//!
+//! ```
+//! use birl::program::ProgramCompiler;
+//! use birl::vm::VirtualMachineBuilder;
+//! use birl::vm_io::CaptureIo;
+//!
+//! let mut compiler = ProgramCompiler::new().unwrap();
+//!
+//! compiler.add_source("util.birl", "JAULA DOBRO(X : BATATA DOCE)\nBIRL: X * 2\nSAINDO DA JAULA").unwrap();
+//! compiler.add_source("main.birl", "JAULA SHOW\nÉ HORA DO: DOBRO, 21\nCE QUER VER ISSO: TREZE\nSAINDO DA JAULA").unwrap();
+//!
+//! let program = compiler.finish().unwrap();
+//!
+//! let io = CaptureIo::new("");
+//! let output = io.output();
+//!
+//! let mut ctx = program.instantiate(VirtualMachineBuilder::new()).unwrap();
+//! let _ = ctx.set_io(Box::new(io));
+//!
+//! ctx.start_program().unwrap();
+//!
+//! assert_eq!(*output.borrow(), "42\n");
+//! ```
+
+use std::fs;
+use std::rc::Rc;
+
+use context::{ Context, BIRL_GLOBAL_FUNCTION_ID };
+use bytecode::CompiledProgram;
+use diagnostic::Diagnostic;
+use compiler::SymbolTable;
+use parser::TypeKind;
+use vm::VirtualMachineBuilder;
+use error::BirlError;
+
+/// What is added to a context before the program (the standard library, plugins, modules), both to compile it and to
+/// run it. A program only runs with the same plugins, in the same order, it was compiled with
+pub type Environment = Rc<dyn Fn(&mut Context) -> Result<(), BirlError>>;
+
+pub struct ProgramCompiler {
+    ctx : Context,
+    environment : Environment,
+    files : Vec<String>,
+    diagnostics : Vec<Diagnostic>,
+    failed : bool,
+}
+
+impl ProgramCompiler {
+    /// A compiler for programs that use the standard library
+    pub fn new() -> Result<ProgramCompiler, String> {
+        ProgramCompiler::with_environment(Rc::new(|ctx : &mut Context| ctx.add_standard_library()))
+    }
+
+    /// A compiler for programs that only have what `environment` adds (nothing, if it does nothing)
+    pub fn with_environment(environment : Environment) -> Result<ProgramCompiler, String> {
+        let mut ctx = Context::new();
+
+        ctx.call_function_by_id(BIRL_GLOBAL_FUNCTION_ID, vec![])?;
+        environment(&mut ctx)?;
+
+        Ok(ProgramCompiler {
+            ctx,
+            environment,
+            files : vec![],
+            diagnostics : vec![],
+            failed : false,
+        })
+    }
+
+    /// Report warnings (like variables hiding others) as errors
+    pub fn set_strict_mode(&mut self, strict : bool) {
+        self.ctx.set_strict_mode(strict);
+    }
+
+    /// Compile the source of a file. The files are compiled in the order they're added, so a function can only be
+    /// called from the files after the one it's in. The warnings and the error, if it fails, go to the diagnostics
+    /// with the file they came from, and after an error no more files can be added
+    pub fn add_source(&mut self, file : &str, source : &str) -> Result<(), String> {
+        if self.failed {
+            return Err("A compilação já deu erro, não dá pra adicionar mais arquivos".to_owned());
+        }
+
+        let compiled = self.ctx.add_named_source(file, source.to_owned());
+
+        for warning in self.ctx.take_warnings() {
+            self.diagnostics.push(Diagnostic::from_warning(&warning).with_source(source).with_file(file));
+        }
+
+        self.files.push(file.to_owned());
+
+        if let Err(e) = compiled {
+            let diagnostic = match self.ctx.last_compile_error() {
+                Some(error) => Diagnostic::from_compile_error(error).with_source(source),
+                None => Diagnostic::error(e.to_string()),
+            };
+
+            self.diagnostics.push(diagnostic.with_file(file));
+            self.failed = true;
+
+            return Err(e.into());
+        }
+
+        Ok(())
+    }
+
+    /// Read a file and compile it, like `add_source`
+    pub fn add_file(&mut self, filename : &str) -> Result<(), String> {
+        match fs::read_to_string(filename) {
+            Ok(source) => self.add_source(filename, &source),
+            Err(e) => Err(format!("Não deu pra ler o arquivo {} : {}", filename, e)),
+        }
+    }
+
+    /// The warnings and errors so far
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// The program, or every diagnostic if the compilation failed
+    pub fn finish(self) -> Result<Program, Vec<Diagnostic>> {
+        if self.failed {
+            return Err(self.diagnostics);
+        }
+
+        Ok(Program {
+            compiled : self.ctx.compiled_program(),
+            symbols : self.ctx.symbol_table(),
+            has_main : self.ctx.has_main(),
+            monster_mode : self.ctx.is_monster_mode(),
+            environment : self.environment,
+            files : self.files,
+            diagnostics : self.diagnostics,
+        })
+    }
+}
+
+/// A compiled program, ready to be run. There's no table of constants : the values in the source are in the
+/// instructions that use them
+pub struct Program {
+    compiled : CompiledProgram,
+    symbols : SymbolTable,
+    has_main : bool,
+    monster_mode : bool,
+    environment : Environment,
+    files : Vec<String>,
+    diagnostics : Vec<Diagnostic>,
+}
+
+impl Program {
+    /// The code of every function, as saved in a `.birlc` file
+    pub fn compiled(&self) -> &CompiledProgram {
+        &self.compiled
+    }
+
+    /// The functions (plugins included), globals and methods the program was compiled with
+    pub fn symbol_table(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// The warnings from the compilation
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// The files the program was compiled from, in order
+    pub fn files(&self) -> &[String] {
+        &self.files
+    }
+
+    /// Whether a main function (SHOW) was declared
+    pub fn has_main(&self) -> bool {
+        self.has_main
+    }
+
+    /// ID, name and parameters of every function written in BIRL (including the ones from modules), in order of ID
+    pub fn functions(&self) -> Vec<(usize, &str, &[TypeKind])> {
+        self.compiled.functions.iter().enumerate()
+            .filter_map(|(id, f)| f.name.as_ref().map(|name| (id, name.as_str(), f.parameters.as_slice())))
+            .collect()
+    }
+
+    /// Name, address and whether they can be changed, for every global variable
+    pub fn globals(&self) -> &[(String, usize, bool)] {
+        &self.compiled.globals
+    }
+
+    /// A new context, with a VM made by the builder, that has the program loaded and ready for `start_program`
+    pub fn instantiate(&self, builder : VirtualMachineBuilder) -> Result<Context, String> {
+        let mut ctx = Context::with_vm(builder)?;
+
+        ctx.call_function_by_id(BIRL_GLOBAL_FUNCTION_ID, vec![])?;
+        (self.environment)(&mut ctx)?;
+
+        if self.monster_mode {
+            ctx.enable_monster_mode();
+        }
+
+        ctx.load_program(self.compiled.clone())?;
+
+        Ok(ctx)
+    }
+}
+
+mod tests {
+    #[test]
+    fn programs() {
+        use std::rc::Rc;
+
+        use context::Context;
+        use diagnostic::{ Severity, CODE_SHADOWING, CODE_COMPILE_ERROR };
+        use program::ProgramCompiler;
+        use vm::VirtualMachineBuilder;
+        use vm_io::CaptureIo;
+
+        let mut compiler = ProgramCompiler::with_environment(Rc::new(|_ : &mut Context| Ok(()))).unwrap();
+
+        let result = compiler.add_source("contador.birl", "VEM: TOTAL, 0\n\
+                                                           JAULA CONTA(X : BATATA DOCE)\n\
+                                                           VEM: X, X + 1\n\
+                                                           BORA: TOTAL, TOTAL + X\n\
+                                                           SAINDO DA JAULA")
+            .and_then(|_| compiler.add_source("show.birl", "JAULA SHOW\n\
+                                                            É HORA DO: CONTA, 1\n\
+                                                            É HORA DO: CONTA, 2\n\
+                                                            CE QUER VER ISSO: TOTAL\n\
+                                                            SAINDO DA JAULA"));
+
+        assert_eq!(result, Ok(()));
+
+        let program = compiler.finish().unwrap();
+
+        assert!(program.has_main());
+        assert_eq!(program.files(), ["contador.birl", "show.birl"]);
+        assert!(program.functions().iter().any(|&(_, name, parameters)| name == "CONTA" && parameters.len() == 1));
+        assert!(program.globals().iter().any(|global| global.0 == "TOTAL"));
+
+        let warning = &program.diagnostics()[0];
+        assert_eq!((warning.severity, warning.code, warning.file.as_deref()),
+                   (Severity::Warning, Some(CODE_SHADOWING), Some("contador.birl")));
+
+        // Each instance has its own VM, with its own globals
+        for _ in 0..2 {
+            let io = CaptureIo::new("");
+            let output = io.output();
+
+            let mut ctx = program.instantiate(VirtualMachineBuilder::new()).unwrap();
+            let _ = ctx.set_io(Box::new(io));
+
+            assert_eq!(ctx.start_program(), Ok(()));
+            assert_eq!(*output.borrow(), "5\n");
+        }
+
+        // Without the standard library, its functions aren't there
+        let mut compiler = ProgramCompiler::with_environment(Rc::new(|_ : &mut Context| Ok(()))).unwrap();
+
+        assert!(compiler.add_source("a.birl", "É HORA DO: TIRA ACENTOS, \"É\"").is_err());
+        assert!(compiler.add_source("b.birl", "VEM: X, 1").is_err());
+
+        let diagnostics = compiler.finish().err().unwrap();
+        let error = diagnostics.last().unwrap();
+
+        assert_eq!((error.code, error.file.as_deref(), error.span.map(|s| s.line)),
+                   (Some(CODE_COMPILE_ERROR), Some("a.birl"), Some(1)));
+    }
+}
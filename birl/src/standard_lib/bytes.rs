@@ -0,0 +1,143 @@
+//! Module for binary data. There's no type for bytes yet, so they're lists of integers from 0 to 255, which can be looked
+//! at with HEXDUMP and written to the output as they are, without being turned into a text
+
+use parser::TypeKind;
+use vm::{ DynamicValue, PluginFunction };
+
+/// Number of bytes in each line of a hexdump
+const HEXDUMP_LINE : usize = 16;
+
+/// The bytes like `hexdump -C` shows them : the offset, the bytes in hex, in two groups of 8, and the ones that are
+/// printable ASCII characters (the others as a .), one line for every 16 bytes
+pub fn hexdump(bytes : &[u8]) -> String {
+    let mut lines = vec![];
+
+    for (index, chunk) in bytes.chunks(HEXDUMP_LINE).enumerate() {
+        let mut line = format!("{:08x}  ", index * HEXDUMP_LINE);
+
+        for position in 0..HEXDUMP_LINE {
+            if position == HEXDUMP_LINE / 2 {
+                line.push(' ');
+            }
+
+            match chunk.get(position) {
+                Some(byte) => line.push_str(&format!("{:02x} ", byte)),
+                None => line.push_str("   "),
+            }
+        }
+
+        line.push_str(" |");
+        line.extend(chunk.iter().map(|&b| if b == b' ' || b.is_ascii_graphic() { b as char } else { '.' }));
+        line.push('|');
+
+        lines.push(line);
+    }
+
+    lines.join("\n")
+}
+
+/// The bytes in a list, which must all be integers from 0 to 255
+fn list_bytes(values : Vec<DynamicValue>) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::with_capacity(values.len());
+
+    for (index, value) in values.into_iter().enumerate() {
+        match value {
+            DynamicValue::Integer(i) if (0..=255).contains(&i) => bytes.push(i as u8),
+            other => return Err(format!("O elemento {} da lista não é um byte (um inteiro de 0 a 255) : {:?}",
+                                        index, other)),
+        }
+    }
+
+    Ok(bytes)
+}
+
+mod plugins
+{
+    use vm::{ DynamicValue, VirtualMachine };
+    use plugin::PluginContext;
+    use super::{ hexdump, list_bytes };
+
+    /// The bytes of the text, in UTF-8
+    /// Arguments : text : Text
+    pub fn text_bytes(arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let mut ctx = PluginContext::new(arguments, vm);
+        let text = ctx.arg_str(0)?;
+
+        let bytes = text.bytes().map(|b| DynamicValue::Integer(b as _)).collect();
+
+        Ok(Some(ctx.make_list(bytes)?))
+    }
+
+    /// A text showing the bytes of the list, like `hexdump -C`
+    /// Arguments : bytes : List
+    pub fn dump(arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let mut ctx = PluginContext::new(arguments, vm);
+        let bytes = list_bytes(ctx.arg_list(0)?)?;
+
+        Ok(Some(ctx.make_text(&hexdump(&bytes))?))
+    }
+
+    /// Write the bytes of the list to the output as they are
+    /// Arguments : bytes : List
+    pub fn write(arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let mut ctx = PluginContext::new(arguments, vm);
+        let bytes = list_bytes(ctx.arg_list(0)?)?;
+
+        ctx.vm().print_bytes(&bytes)?;
+
+        Ok(None)
+    }
+}
+
+pub fn get_plugins() -> Vec<(String, Vec<TypeKind>, PluginFunction)>
+{
+    vec!
+    [
+        ("BYTES DO TEXTO".to_owned(), vec![TypeKind::Text], plugins::text_bytes),
+        ("HEXDUMP".to_owned(), vec![TypeKind::List], plugins::dump),
+        ("ESCREVE BYTES".to_owned(), vec![TypeKind::List], plugins::write),
+    ]
+}
+
+mod tests {
+    #[test]
+    fn hexdumps() {
+        use standard_lib::bytes::hexdump;
+
+        assert_eq!(hexdump(&[]), "");
+        assert_eq!(hexdump(b"BIRL\n"),
+                   "00000000  42 49 52 4c 0a                                    |BIRL.|");
+        assert_eq!(hexdump(b"HORA DO SHOW, PORRA\x00\xff"),
+                   "00000000  48 4f 52 41 20 44 4f 20  53 48 4f 57 2c 20 50 4f  |HORA DO SHOW, PO|\n\
+                    00000010  52 52 41 00 ff                                    |RRA..|");
+    }
+
+    #[test]
+    fn byte_functions() {
+        use error::BirlError;
+        use testing::run_program;
+
+        let (result, output) = run_program("É HORA DO: BYTES DO TEXTO, \"É\"\n\
+                                            VEM: BYTES, TREZE\n\
+                                            CE QUER VER ISSO: BYTES\n\
+                                            É HORA DO: HEXDUMP, BYTES\n\
+                                            CE QUER VER ISSO: TREZE\n\
+                                            PÕE ISSO AQUI: BYTES, 10\n\
+                                            É HORA DO: ESCREVE BYTES, BYTES");
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "(Lista) [ 195, 137 ]\n\
+                            00000000  c3 89                                             |..|\n\
+                            É\n");
+
+        // Only what fits in a byte, and the captured output only takes valid text
+        let (result, _) = run_program("FAZ UMA LISTA: L\nPÕE ISSO AQUI: L, 256\nÉ HORA DO: HEXDUMP, L");
+        assert!(matches!(result, Err(BirlError::Runtime(ref message, _))
+                         if message.contains("O elemento 0 da lista não é um byte")),
+                "{:?}", result);
+
+        let (result, output) = run_program("FAZ UMA LISTA: L\nPÕE ISSO AQUI: L, 255\nÉ HORA DO: ESCREVE BYTES, L");
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "\u{fffd}");
+    }
+}
@@ -0,0 +1,88 @@
+//! Module for the clipboard of the desktop, with the `clipboard` feature : COPIA puts a text in it and COLA takes the
+//! text that's there. Only text is supported
+
+use parser::TypeKind;
+use vm::PluginFunction;
+
+mod plugins
+{
+    use vm::{ DynamicValue, SpecialItemData, VirtualMachine };
+
+    use arboard::Clipboard;
+
+    fn get_text(value : DynamicValue, vm : &VirtualMachine) -> Result<String, String> {
+        match value {
+            DynamicValue::Text(id) => match vm.get_special_storage_ref().get_data_ref(id) {
+                Some(SpecialItemData::Text(ref t)) => Ok(t.clone()),
+                _ => Err("Erro interno : Dado special com ID fornecido não é um texto".to_owned())
+            },
+            _ => unreachable!()
+        }
+    }
+
+    // A new one is opened on every call. When it's closed, what was copied is handed to the clipboard manager of the
+    // system (if there's one), so it stays there after the program ends
+    fn open() -> Result<Clipboard, String> {
+        Clipboard::new().map_err(|e| format!("Não deu pra abrir a área de transferência : {}", e))
+    }
+
+    /// Put a text in the clipboard
+    /// Arguments : text : Text
+    pub fn copy(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let text = get_text(arguments.remove(0), vm)?;
+
+        match open()?.set_text(text) {
+            Ok(_) => Ok(None),
+            Err(e) => Err(format!("Não deu pra copiar pra área de transferência : {}", e)),
+        }
+    }
+
+    /// The text in the clipboard. If it's empty or has something that isn't a text, gives Null
+    /// Arguments : none
+    pub fn paste(_ : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        use arboard::Error;
+
+        let text = match open()?.get_text() {
+            Ok(t) => t,
+            Err(Error::ContentNotAvailable) => return Ok(Some(DynamicValue::Null)),
+            Err(e) => return Err(format!("Não deu pra colar da área de transferência : {}", e)),
+        };
+
+        let id = vm.get_special_storage_mut().add(SpecialItemData::Text(text), 0);
+
+        Ok(Some(DynamicValue::Text(id)))
+    }
+}
+
+pub fn get_plugins() -> Vec<(String, Vec<TypeKind>, PluginFunction)>
+{
+    vec!
+    [
+        ("COPIA".to_owned(), vec![TypeKind::Text], plugins::copy),
+        ("COLA".to_owned(), vec![], plugins::paste),
+    ]
+}
+
+mod tests {
+    #[test]
+    #[cfg(feature = "clipboard")]
+    fn clipboard() {
+        use error::BirlError;
+        use testing::run_program;
+
+        let src = "JAULA SHOW\n\
+                   É HORA DO: COPIA, \"BIRL 🏋\"\n\
+                   É HORA DO: COLA\n\
+                   CE QUER VER ISSO: TREZE\n\
+                   SAINDO DA JAULA";
+
+        let (result, output) = run_program(src);
+
+        // Machines without a desktop (like most CI servers) have no clipboard
+        match result {
+            Ok(_) => assert_eq!(output, "BIRL 🏋\n"),
+            Err(BirlError::Runtime(message, _)) => assert!(message.contains("área de transferência"), "{}", message),
+            Err(e) => panic!("{:?}", e),
+        }
+    }
+}
@@ -0,0 +1,238 @@
+//! Module for reading config files : LÊ TOML (with the `toml` feature) and LÊ YAML (with the `yaml` feature) turn the
+//! text of a config into BIRL values. BIRL has no tables, so a table becomes a list of pairs, each a list with the key
+//! and the value, and ACHA CHAVE finds the value of a key in one
+
+use parser::TypeKind;
+use vm::PluginFunction;
+
+mod plugins
+{
+    use vm::{ DynamicValue, SpecialItemData, VirtualMachine };
+    #[cfg(any(feature = "toml", feature = "yaml"))]
+    use parser::IntegerType;
+
+    /// A value read from a config, before going to the special storage
+    #[cfg(any(feature = "toml", feature = "yaml"))]
+    enum ConfigValue {
+        Integer(IntegerType),
+        Number(f64),
+        Text(String),
+        Bool(bool),
+        List(Vec<ConfigValue>),
+        Table(Vec<(String, ConfigValue)>),
+        Null,
+    }
+
+    /// Put a value in the special storage. Texts and lists inside other lists start with the reference of the list
+    /// that holds them
+    #[cfg(any(feature = "toml", feature = "yaml"))]
+    fn store(value : ConfigValue, vm : &mut VirtualMachine, ref_count : u64) -> DynamicValue {
+        match value {
+            ConfigValue::Integer(i) => DynamicValue::Integer(i),
+            ConfigValue::Number(n) => DynamicValue::Number(n),
+            ConfigValue::Bool(b) => DynamicValue::Bool(b),
+            ConfigValue::Null => DynamicValue::Null,
+            ConfigValue::Text(t) => DynamicValue::Text(vm.get_special_storage_mut().add(SpecialItemData::Text(t), ref_count)),
+            ConfigValue::List(elements) => {
+                let elements = elements.into_iter()
+                    .map(|e| Box::new(store(e, vm, 1)))
+                    .collect::<Vec<Box<DynamicValue>>>();
+
+                DynamicValue::List(vm.get_special_storage_mut().add(SpecialItemData::List(elements), ref_count))
+            }
+            ConfigValue::Table(entries) => {
+                let pairs = entries.into_iter()
+                    .map(|(key, value)| ConfigValue::List(vec![ConfigValue::Text(key), value]))
+                    .collect();
+
+                store(ConfigValue::List(pairs), vm, ref_count)
+            }
+        }
+    }
+
+    fn get_text(value : DynamicValue, vm : &VirtualMachine) -> Result<String, String> {
+        match value {
+            DynamicValue::Text(id) => match vm.get_special_storage_ref().get_data_ref(id) {
+                Some(SpecialItemData::Text(ref t)) => Ok(t.clone()),
+                _ => Err("Erro interno : Dado special com ID fornecido não é um texto".to_owned())
+            },
+            _ => unreachable!()
+        }
+    }
+
+    #[cfg(feature = "toml")]
+    fn from_toml(value : ::toml::Value) -> ConfigValue {
+        use toml::Value;
+
+        match value {
+            Value::String(s) => ConfigValue::Text(s),
+            Value::Integer(i) => ConfigValue::Integer(i as IntegerType),
+            Value::Float(f) => ConfigValue::Number(f),
+            Value::Boolean(b) => ConfigValue::Bool(b),
+            Value::Datetime(d) => ConfigValue::Text(d.to_string()),
+            Value::Array(a) => ConfigValue::List(a.into_iter().map(from_toml).collect()),
+            Value::Table(t) => ConfigValue::Table(t.into_iter().map(|(k, v)| (k, from_toml(v))).collect()),
+        }
+    }
+
+    /// Read a config in TOML
+    /// Arguments : source : Text
+    #[cfg(feature = "toml")]
+    pub fn read_toml(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let source = get_text(arguments.remove(0), vm)?;
+
+        let value = match source.parse::<::toml::Value>() {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Erro lendo o TOML : {}", e)),
+        };
+
+        Ok(Some(store(from_toml(value), vm, 0)))
+    }
+
+    #[cfg(feature = "yaml")]
+    fn from_yaml(value : ::yaml_rust::Yaml) -> Result<ConfigValue, String> {
+        use yaml_rust::Yaml;
+
+        match value {
+            Yaml::String(s) => Ok(ConfigValue::Text(s)),
+            Yaml::Integer(i) => Ok(ConfigValue::Integer(i as IntegerType)),
+            Yaml::Real(r) => match r.parse::<f64>() {
+                Ok(n) => Ok(ConfigValue::Number(n)),
+                Err(_) => Err(format!("Erro lendo o YAML : Número inválido : {}", r)),
+            },
+            Yaml::Boolean(b) => Ok(ConfigValue::Bool(b)),
+            Yaml::Array(a) => Ok(ConfigValue::List(a.into_iter().map(from_yaml).collect::<Result<Vec<ConfigValue>, String>>()?)),
+            Yaml::Hash(h) => {
+                let mut entries = Vec::with_capacity(h.len());
+
+                for (key, value) in h {
+                    // Keys that aren't texts (like numbers) are turned into texts, since all keys in a table are
+                    let key = match key {
+                        Yaml::String(s) | Yaml::Real(s) => s,
+                        Yaml::Integer(i) => i.to_string(),
+                        Yaml::Boolean(b) => b.to_string(),
+                        _ => return Err("Erro lendo o YAML : Só texto e números podem ser chaves".to_owned()),
+                    };
+
+                    entries.push((key, from_yaml(value)?));
+                }
+
+                Ok(ConfigValue::Table(entries))
+            }
+            Yaml::Null => Ok(ConfigValue::Null),
+            Yaml::Alias(_) | Yaml::BadValue => Err("Erro lendo o YAML : Valor inválido".to_owned()),
+        }
+    }
+
+    /// Read a config in YAML. Only the first document is read, and an empty text gives Null
+    /// Arguments : source : Text
+    #[cfg(feature = "yaml")]
+    pub fn read_yaml(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        use yaml_rust::YamlLoader;
+
+        let source = get_text(arguments.remove(0), vm)?;
+
+        let mut documents = match YamlLoader::load_from_str(&source) {
+            Ok(d) => d,
+            Err(e) => return Err(format!("Erro lendo o YAML : {}", e)),
+        };
+
+        if documents.is_empty() {
+            return Ok(Some(DynamicValue::Null));
+        }
+
+        let value = from_yaml(documents.remove(0))?;
+
+        Ok(Some(store(value, vm, 0)))
+    }
+
+    /// The value of a key in a table (a list of pairs), or Null if it isn't there
+    /// Arguments : table : List, key : Text
+    pub fn find_key(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        // The arguments come in the reverse order
+        let key = get_text(arguments.remove(0), vm)?;
+
+        let table = match arguments.remove(0) {
+            DynamicValue::List(id) => match vm.get_special_storage_ref().get_data_ref(id) {
+                Some(SpecialItemData::List(ref l)) => l.clone(),
+                _ => return Err("Erro interno : Dado special com ID fornecido não é uma lista".to_owned())
+            },
+            _ => unreachable!()
+        };
+
+        for entry in table {
+            let pair = match *entry {
+                DynamicValue::List(id) => match vm.get_special_storage_ref().get_data_ref(id) {
+                    Some(SpecialItemData::List(ref l)) if l.len() == 2 => (*l[0], *l[1]),
+                    _ => continue,
+                },
+                _ => continue,
+            };
+
+            if let (DynamicValue::Text(id), value) = pair {
+                if let Some(SpecialItemData::Text(ref k)) = vm.get_special_storage_ref().get_data_ref(id) {
+                    if *k == key {
+                        return Ok(Some(value));
+                    }
+                }
+            }
+        }
+
+        Ok(Some(DynamicValue::Null))
+    }
+}
+
+pub fn get_plugins() -> Vec<(String, Vec<TypeKind>, PluginFunction)>
+{
+    #[allow(unused_mut)]
+    let mut functions : Vec<(String, Vec<TypeKind>, PluginFunction)> = vec!
+    [
+        ("ACHA CHAVE".to_owned(), vec![TypeKind::List, TypeKind::Text], plugins::find_key),
+    ];
+
+    #[cfg(feature = "toml")]
+    functions.push(("LÊ TOML".to_owned(), vec![TypeKind::Text], plugins::read_toml));
+
+    #[cfg(feature = "yaml")]
+    functions.push(("LÊ YAML".to_owned(), vec![TypeKind::Text], plugins::read_yaml));
+
+    functions
+}
+
+mod tests {
+    #[test]
+    #[cfg(all(feature = "toml", feature = "yaml"))]
+    fn read_config() {
+        use error::BirlError;
+        use testing::run_program;
+
+        let src = "JAULA SHOW\n\
+                   VEM: C, \"NOME = \\\"BIRL\\\"\\nSERIES = [12, 10]\\n[TREINO]\\nCARGA = 2.5\\nPESADO = true\\n\"\n\
+                   É HORA DO: LÊ TOML, C\n\
+                   VEM: CONFIG, TREZE\n\
+                   É HORA DO: ACHA CHAVE, CONFIG, \"SERIES\"\n\
+                   CE QUER VER ISSO: TREZE\n\
+                   É HORA DO: ACHA CHAVE, CONFIG, \"TREINO\"\n\
+                   CE QUER VER ISSO: TREZE\n\
+                   É HORA DO: ACHA CHAVE, CONFIG, \"NADA\"\n\
+                   CE QUER VER ISSO: TREZE\n\
+                   VEM: Y, \"NOME: BIRL\\nSERIES:\\n  - 12\\n  - 10\\nCARGA: 2.5\\n\"\n\
+                   É HORA DO: LÊ YAML, Y\n\
+                   CE QUER VER ISSO: TREZE\n\
+                   SAINDO DA JAULA";
+
+        let (result, output) = run_program(src);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "(Lista) [ 12, 10 ]\n(Lista) [ [ \"CARGA\", 2.5 ], [ \"PESADO\", VERDADE ] ]\n<Null>\n\
+                            (Lista) [ [ \"NOME\", \"BIRL\" ], [ \"SERIES\", [ 12, 10 ] ], [ \"CARGA\", 2.5 ] ]\n");
+
+        let (result, _) = run_program("JAULA SHOW\nÉ HORA DO: LÊ TOML, \"NOME = \"\nSAINDO DA JAULA");
+
+        match result {
+            Ok(_) => panic!("Leu um TOML inválido"),
+            Err(BirlError::Runtime(message, _)) => assert!(message.contains("Erro lendo o TOML"), "{}", message),
+            Err(e) => panic!("{:?}", e),
+        }
+    }
+}
@@ -0,0 +1,197 @@
+//! Module for SQLite databases, with the `sqlite` feature. A database stays open while it's in use, and the values of a
+//! command are given separately from it, in a list, so they're never mixed with the SQL. Like the tables of a config,
+//! each row of a query is a list of pairs, each a list with the name of the column and the value
+
+use parser::TypeKind;
+use vm::PluginFunction;
+
+mod plugins
+{
+    use vm::{ DynamicValue, SpecialItemData, VirtualMachine };
+    use parser::IntegerType;
+
+    use rusqlite::{ Connection, params_from_iter };
+    use rusqlite::types::Value;
+
+    fn get_text(value : DynamicValue, vm : &VirtualMachine) -> Result<String, String> {
+        match value {
+            DynamicValue::Text(id) => match vm.get_special_storage_ref().get_data_ref(id) {
+                Some(SpecialItemData::Text(ref t)) => Ok(t.clone()),
+                _ => Err("Erro interno : Dado special com ID fornecido não é um texto".to_owned())
+            },
+            _ => unreachable!()
+        }
+    }
+
+    fn get_connection(value : DynamicValue, vm : &VirtualMachine) -> Result<&Connection, String> {
+        match value {
+            DynamicValue::Database(id) => match vm.get_special_storage_ref().get_data_ref(id) {
+                Some(SpecialItemData::Database(ref connection)) => Ok(connection),
+                _ => Err("Erro interno : Dado special com ID fornecido não é um banco".to_owned())
+            },
+            _ => unreachable!()
+        }
+    }
+
+    /// The elements of the list, as values for the placeholders (?) of a command
+    // Depending on the IntegerType of the build, the cast is to the same type
+    #[allow(clippy::unnecessary_cast)]
+    fn get_parameters(value : DynamicValue, vm : &VirtualMachine) -> Result<Vec<Value>, String> {
+        let elements = match value {
+            DynamicValue::List(id) => match vm.get_special_storage_ref().get_data_ref(id) {
+                Some(SpecialItemData::List(ref l)) => l.clone(),
+                _ => return Err("Erro interno : Dado special com ID fornecido não é uma lista".to_owned())
+            },
+            _ => unreachable!()
+        };
+
+        elements.iter().map(|element| match **element {
+            DynamicValue::Integer(i) => Ok(Value::Integer(i as i64)),
+            DynamicValue::Number(n) => Ok(Value::Real(n)),
+            // SQLite has no booleans, they're kept as 1 and 0
+            DynamicValue::Bool(b) => Ok(Value::Integer(b as i64)),
+            DynamicValue::Text(_) => get_text(**element, vm).map(Value::Text),
+            DynamicValue::Null => Ok(Value::Null),
+            other => Err(format!("Um {} não pode ser guardado no banco", other.get_kind())),
+        }).collect()
+    }
+
+    /// Open a database, creating it if it doesn't exist. ":memory:" opens one that only exists in the memory
+    /// Arguments : path : Text
+    pub fn open(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let path = get_text(arguments.remove(0), vm)?;
+
+        let connection = match Connection::open(&path) {
+            Ok(c) => c,
+            Err(e) => return Err(format!("Não deu pra abrir o banco {} : {}", path, e)),
+        };
+
+        let id = vm.get_special_storage_mut().add(SpecialItemData::Database(connection), 0);
+
+        Ok(Some(DynamicValue::Database(id)))
+    }
+
+    /// Run a command that doesn't give rows (like CREATE, INSERT or UPDATE), returning how many rows it changed
+    /// Arguments : database : Database, sql : Text, parameters : List
+    pub fn execute(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        // The arguments come in the reverse order
+        let parameters = get_parameters(arguments.remove(0), vm)?;
+        let sql = get_text(arguments.remove(0), vm)?;
+        let connection = get_connection(arguments.remove(0), vm)?;
+
+        match connection.execute(&sql, params_from_iter(parameters)) {
+            Ok(changed) => Ok(Some(DynamicValue::Integer(changed as IntegerType))),
+            Err(e) => Err(format!("Erro no banco : {}", e)),
+        }
+    }
+
+    /// Run a query, returning a list with its rows
+    /// Arguments : database : Database, sql : Text, parameters : List
+    pub fn query(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let parameters = get_parameters(arguments.remove(0), vm)?;
+        let sql = get_text(arguments.remove(0), vm)?;
+
+        // Everything is read before going to the special storage, since the connection is in there
+        let (columns, rows) = {
+            let connection = get_connection(arguments.remove(0), vm)?;
+            let error = |e : ::rusqlite::Error| format!("Erro no banco : {}", e);
+
+            let mut statement = connection.prepare(&sql).map_err(error)?;
+            let columns = statement.column_names().into_iter().map(|c| c.to_owned()).collect::<Vec<String>>();
+            let mut result = statement.query(params_from_iter(parameters)).map_err(error)?;
+            let mut rows = vec![];
+
+            while let Some(row) = result.next().map_err(error)? {
+                let values = (0..columns.len()).map(|i| row.get::<_, Value>(i)).collect::<Result<Vec<Value>, _>>();
+
+                rows.push(values.map_err(error)?);
+            }
+
+            (columns, rows)
+        };
+
+        let storage = vm.get_special_storage_mut();
+        let mut list = Vec::with_capacity(rows.len());
+
+        for row in rows {
+            let mut pairs = Vec::with_capacity(columns.len());
+
+            for (column, value) in columns.iter().zip(row) {
+                // Each text and list is held by the list it's in
+                let value = match value {
+                    Value::Null => DynamicValue::Null,
+                    Value::Integer(i) => DynamicValue::Integer(i as IntegerType),
+                    Value::Real(n) => DynamicValue::Number(n),
+                    Value::Text(t) => DynamicValue::Text(storage.add(SpecialItemData::Text(t), 1)),
+                    Value::Blob(b) => DynamicValue::Text(storage.add(SpecialItemData::Text(String::from_utf8_lossy(&b).into_owned()), 1)),
+                };
+
+                let name = DynamicValue::Text(storage.add(SpecialItemData::Text(column.clone()), 1));
+                let pair = storage.add(SpecialItemData::List(vec![Box::new(name), Box::new(value)]), 1);
+
+                pairs.push(Box::new(DynamicValue::List(pair)));
+            }
+
+            list.push(Box::new(DynamicValue::List(storage.add(SpecialItemData::List(pairs), 1))));
+        }
+
+        let id = storage.add(SpecialItemData::List(list), 0);
+
+        Ok(Some(DynamicValue::List(id)))
+    }
+}
+
+pub fn get_plugins() -> Vec<(String, Vec<TypeKind>, PluginFunction)>
+{
+    vec!
+    [
+        ("ABRE BANCO".to_owned(), vec![TypeKind::Text], plugins::open),
+        ("BANCO EXECUTA".to_owned(), vec![TypeKind::Database, TypeKind::Text, TypeKind::List], plugins::execute),
+        ("BANCO CONSULTA".to_owned(), vec![TypeKind::Database, TypeKind::Text, TypeKind::List], plugins::query),
+    ]
+}
+
+mod tests {
+    #[test]
+    fn database() {
+        use error::BirlError;
+        use testing::run_program;
+
+        let src = "JAULA SHOW\n\
+                   É HORA DO: ABRE BANCO, \":memory:\"\n\
+                   VEM: BANCO, TREZE\n\
+                   FAZ UMA LISTA: NADA\n\
+                   É HORA DO: BANCO EXECUTA, BANCO, \"CREATE TABLE TREINO (NOME TEXT, CARGA REAL, SERIES INTEGER)\", NADA\n\
+                   FAZ UMA LISTA: VALORES\n\
+                   PÕE ISSO AQUI: VALORES, \"SUPINO\"\n\
+                   PÕE ISSO AQUI: VALORES, 120.5\n\
+                   PÕE ISSO AQUI: VALORES, 4\n\
+                   É HORA DO: BANCO EXECUTA, BANCO, \"INSERT INTO TREINO VALUES (?, ?, ?)\", VALORES\n\
+                   CE QUER VER ISSO: TREZE\n\
+                   FAZ UMA LISTA: OUTRO\n\
+                   PÕE ISSO AQUI: OUTRO, \"AGACHAMENTO'); DROP TABLE TREINO; --\"\n\
+                   PÕE ISSO AQUI: OUTRO, FRANGO\n\
+                   PÕE ISSO AQUI: OUTRO, 5\n\
+                   É HORA DO: BANCO EXECUTA, BANCO, \"INSERT INTO TREINO VALUES (?, ?, ?)\", OUTRO\n\
+                   FAZ UMA LISTA: MINIMO\n\
+                   PÕE ISSO AQUI: MINIMO, 3\n\
+                   É HORA DO: BANCO CONSULTA, BANCO, \"SELECT NOME, CARGA FROM TREINO WHERE SERIES > ? ORDER BY SERIES\", MINIMO\n\
+                   PRA CADA: LINHA, TREZE\n\
+                   CE QUER VER ISSO: LINHA\n\
+                   FIM\n\
+                   É HORA DO: BANCO EXECUTA, BANCO, \"ISSO NÃO É SQL\", NADA\n\
+                   SAINDO DA JAULA";
+
+        let (result, output) = run_program(src);
+
+        match result {
+            Ok(_) => panic!("Rodou um comando inválido"),
+            Err(BirlError::Runtime(message, _)) => assert!(message.contains("Erro no banco"), "{}", message),
+            Err(e) => panic!("{:?}", e),
+        }
+
+        assert_eq!(output, "1\n\
+                                              (Lista) [ [ \"NOME\", \"SUPINO\" ], [ \"CARGA\", 120.5 ] ]\n\
+                                              (Lista) [ [ \"NOME\", \"AGACHAMENTO'); DROP TABLE TREINO; --\" ], [ \"CARGA\", <Null> ] ]\n");
+    }
+}
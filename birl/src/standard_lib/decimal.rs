@@ -0,0 +1,344 @@
+//! Module for exact decimal arithmetic on texts like "19.90", for prices and money. Numbers are binary floats, so
+//! 0.1 + 0.2 gives 0.30000000000000004, which isn't something to show to someone learning. Here the digits are kept as an
+//! integer with the number of decimal places, so the results are the ones done by hand
+
+use parser::TypeKind;
+use vm::PluginFunction;
+
+use std::cmp::Ordering;
+use std::fmt;
+
+/// Most decimal places a value can have. 10^38 is the biggest power of 10 that fits in an i128
+const MAX_SCALE : u32 = 38;
+
+/// A decimal number : `digits` / 10^`scale`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Decimal {
+    digits : i128,
+    scale : u32,
+}
+
+fn overflow() -> String {
+    "O resultado não cabe num decimal".to_owned()
+}
+
+fn power_of_ten(exponent : u32) -> Result<i128, String> {
+    10i128.checked_pow(exponent).ok_or_else(overflow)
+}
+
+/// Divide, rounding halves away from zero (2.5 is 3 and -2.5 is -3)
+fn divide_rounded(dividend : i128, divisor : i128) -> Result<i128, String> {
+    if divisor == 0 {
+        return Err("Divisão por zero".to_owned());
+    }
+
+    let quotient = dividend / divisor;
+    let remainder = dividend % divisor;
+
+    // Comparing the remainder with the rest of the divisor instead of doubling it, which could overflow
+    if remainder.abs() >= divisor.abs() - remainder.abs() {
+        let away = if (dividend < 0) == (divisor < 0) { 1 } else { -1 };
+
+        quotient.checked_add(away).ok_or_else(overflow)
+    } else {
+        Ok(quotient)
+    }
+}
+
+impl Decimal {
+    /// Read a decimal like "-12.345". Both '.' and ',' are accepted for the decimal places, since prices are written
+    /// both ways
+    pub fn parse(text : &str) -> Option<Decimal> {
+        let text = text.trim();
+
+        let (negative, unsigned) = match text.chars().next()? {
+            '-' => (true, &text[1..]),
+            '+' => (false, &text[1..]),
+            _ => (false, text),
+        };
+
+        let (whole, fraction) = match unsigned.find(['.', ',']) {
+            Some(index) => (&unsigned[..index], &unsigned[index + 1..]),
+            None => (unsigned, ""),
+        };
+
+        if whole.is_empty() && fraction.is_empty() {
+            return None;
+        }
+
+        if fraction.len() > MAX_SCALE as usize {
+            return None;
+        }
+
+        let mut digits = 0i128;
+
+        for c in whole.chars().chain(fraction.chars()) {
+            let digit = c.to_digit(10)? as i128;
+
+            digits = digits.checked_mul(10)?.checked_add(digit)?;
+        }
+
+        Some(Decimal { digits : if negative { -digits } else { digits }, scale : fraction.len() as u32 })
+    }
+
+    /// The same value with another number of decimal places, rounded if it has less
+    pub fn with_scale(self, scale : u32) -> Result<Decimal, String> {
+        if scale > MAX_SCALE {
+            return Err(format!("Um decimal pode ter no máximo {} casas", MAX_SCALE));
+        }
+
+        let digits = match scale.cmp(&self.scale) {
+            Ordering::Equal => self.digits,
+            Ordering::Greater => self.digits.checked_mul(power_of_ten(scale - self.scale)?).ok_or_else(overflow)?,
+            Ordering::Less => divide_rounded(self.digits, power_of_ten(self.scale - scale)?)?,
+        };
+
+        Ok(Decimal { digits, scale })
+    }
+
+    /// Both values with the same number of decimal places (the biggest of the two)
+    fn aligned(self, other : Decimal) -> Result<(Decimal, Decimal), String> {
+        let scale = self.scale.max(other.scale);
+
+        Ok((self.with_scale(scale)?, other.with_scale(scale)?))
+    }
+
+    pub fn add(self, other : Decimal) -> Result<Decimal, String> {
+        let (a, b) = self.aligned(other)?;
+
+        Ok(Decimal { digits : a.digits.checked_add(b.digits).ok_or_else(overflow)?, scale : a.scale })
+    }
+
+    pub fn sub(self, other : Decimal) -> Result<Decimal, String> {
+        let (a, b) = self.aligned(other)?;
+
+        Ok(Decimal { digits : a.digits.checked_sub(b.digits).ok_or_else(overflow)?, scale : a.scale })
+    }
+
+    /// The exact product, with the decimal places of both
+    pub fn mul(self, other : Decimal) -> Result<Decimal, String> {
+        let scale = self.scale + other.scale;
+
+        if scale > MAX_SCALE {
+            return Err(format!("Um decimal pode ter no máximo {} casas", MAX_SCALE));
+        }
+
+        Ok(Decimal { digits : self.digits.checked_mul(other.digits).ok_or_else(overflow)?, scale })
+    }
+
+    /// The quotient rounded to `scale` decimal places
+    pub fn div(self, other : Decimal, scale : u32) -> Result<Decimal, String> {
+        if other.digits == 0 {
+            return Err("Divisão por zero".to_owned());
+        }
+
+        if scale > MAX_SCALE {
+            return Err(format!("Um decimal pode ter no máximo {} casas", MAX_SCALE));
+        }
+
+        // self / other = (self.digits * 10^other.scale) / (other.digits * 10^self.scale), times 10^scale to keep the places
+        let dividend = self.digits.checked_mul(power_of_ten(scale + other.scale)?).ok_or_else(overflow)?;
+        let divisor = other.digits.checked_mul(power_of_ten(self.scale)?).ok_or_else(overflow)?;
+
+        Ok(Decimal { digits : divide_rounded(dividend, divisor)?, scale })
+    }
+
+    pub fn compare(self, other : Decimal) -> Result<Ordering, String> {
+        let (a, b) = self.aligned(other)?;
+
+        Ok(a.digits.cmp(&b.digits))
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        let digits = self.digits.unsigned_abs().to_string();
+        let scale = self.scale as usize;
+
+        if self.digits < 0 {
+            f.write_str("-")?;
+        }
+
+        if scale == 0 {
+            return f.write_str(&digits);
+        }
+
+        // Zeros before, so there's always a digit before the point
+        let padded = format!("{:0>width$}", digits, width = scale + 1);
+        let point = padded.len() - scale;
+
+        write!(f, "{}.{}", &padded[..point], &padded[point..])
+    }
+}
+
+mod plugins
+{
+    use vm::{ DynamicValue, SpecialItemData, VirtualMachine };
+    use super::Decimal;
+
+    use std::cmp::Ordering;
+
+    fn get_decimal(value : DynamicValue, vm : &VirtualMachine) -> Result<Decimal, String> {
+        let text = match value {
+            DynamicValue::Text(id) => match vm.get_special_storage_ref().get_data_ref(id) {
+                Some(SpecialItemData::Text(ref t)) => t,
+                _ => return Err("Erro interno : Dado special com ID fornecido não é um texto".to_owned())
+            },
+            _ => unreachable!()
+        };
+
+        match Decimal::parse(text) {
+            Some(d) => Ok(d),
+            None => Err(format!("\"{}\" não é um número decimal", text)),
+        }
+    }
+
+    fn get_places(value : DynamicValue) -> Result<u32, String> {
+        match value {
+            // Anything past MAX_SCALE is an error later, it only can't wrap around
+            DynamicValue::Integer(n) if n >= 0 => Ok(n.min(u32::MAX as _) as u32),
+            DynamicValue::Integer(n) => Err(format!("O número de casas não pode ser negativo, mas foi {}", n)),
+            _ => unreachable!()
+        }
+    }
+
+    fn make_decimal(value : Decimal, vm : &mut VirtualMachine) -> Option<DynamicValue> {
+        Some(DynamicValue::Text(vm.get_special_storage_mut().add(SpecialItemData::Text(value.to_string()), 0)))
+    }
+
+    // The arguments come in the reverse order, so the second value comes first
+
+    /// Arguments : a : Text, b : Text
+    pub fn add(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let b = get_decimal(arguments.remove(0), vm)?;
+        let a = get_decimal(arguments.remove(0), vm)?;
+
+        Ok(make_decimal(a.add(b)?, vm))
+    }
+
+    /// Arguments : a : Text, b : Text
+    pub fn sub(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let b = get_decimal(arguments.remove(0), vm)?;
+        let a = get_decimal(arguments.remove(0), vm)?;
+
+        Ok(make_decimal(a.sub(b)?, vm))
+    }
+
+    /// Arguments : a : Text, b : Text
+    pub fn mul(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let b = get_decimal(arguments.remove(0), vm)?;
+        let a = get_decimal(arguments.remove(0), vm)?;
+
+        Ok(make_decimal(a.mul(b)?, vm))
+    }
+
+    /// Arguments : a : Text, b : Text, places : Integer
+    pub fn div(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let places = get_places(arguments.remove(0))?;
+        let b = get_decimal(arguments.remove(0), vm)?;
+        let a = get_decimal(arguments.remove(0), vm)?;
+
+        Ok(make_decimal(a.div(b, places)?, vm))
+    }
+
+    /// Arguments : value : Text, places : Integer
+    pub fn round(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let places = get_places(arguments.remove(0))?;
+        let value = get_decimal(arguments.remove(0), vm)?;
+
+        Ok(make_decimal(value.with_scale(places)?, vm))
+    }
+
+    /// -1 if a is smaller, 0 if they're equal and 1 if a is bigger
+    /// Arguments : a : Text, b : Text
+    pub fn compare(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let b = get_decimal(arguments.remove(0), vm)?;
+        let a = get_decimal(arguments.remove(0), vm)?;
+
+        let result = match a.compare(b)? {
+            Ordering::Less => -1,
+            Ordering::Equal => 0,
+            Ordering::Greater => 1,
+        };
+
+        Ok(Some(DynamicValue::Integer(result)))
+    }
+}
+
+pub fn get_plugins() -> Vec<(String, Vec<TypeKind>, PluginFunction)>
+{
+    vec!
+    [
+        ("SOMA DECIMAL".to_owned(), vec![TypeKind::Text, TypeKind::Text], plugins::add),
+        ("SUBTRAI DECIMAL".to_owned(), vec![TypeKind::Text, TypeKind::Text], plugins::sub),
+        ("MULTIPLICA DECIMAL".to_owned(), vec![TypeKind::Text, TypeKind::Text], plugins::mul),
+        ("DIVIDE DECIMAL".to_owned(), vec![TypeKind::Text, TypeKind::Text, TypeKind::Integer], plugins::div),
+        ("ARREDONDA DECIMAL".to_owned(), vec![TypeKind::Text, TypeKind::Integer], plugins::round),
+        ("COMPARA DECIMAL".to_owned(), vec![TypeKind::Text, TypeKind::Text], plugins::compare),
+    ]
+}
+
+mod tests {
+    #[test]
+    fn decimal_arithmetic() {
+        use standard_lib::decimal::Decimal;
+        use std::cmp::Ordering;
+
+        let d = |text : &str| match Decimal::parse(text) {
+            Some(d) => d,
+            None => panic!("\"{}\" não foi lido", text),
+        };
+
+        assert_eq!(d("0.1").add(d("0.2")).unwrap().to_string(), "0.3");
+        assert_eq!(d("19,90").add(d("5")).unwrap().to_string(), "24.90");
+        assert_eq!(d("1").sub(d("1.75")).unwrap().to_string(), "-0.75");
+        assert_eq!(d("19.90").mul(d("3")).unwrap().to_string(), "59.70");
+        assert_eq!(d("-.5").mul(d("0.5")).unwrap().to_string(), "-0.25");
+        assert_eq!(d("10").div(d("3"), 2).unwrap().to_string(), "3.33");
+        assert_eq!(d("2").div(d("3"), 2).unwrap().to_string(), "0.67");
+        assert_eq!(d("-2").div(d("3"), 0).unwrap().to_string(), "-1");
+        assert_eq!(d("1.005").with_scale(2).unwrap().to_string(), "1.01");
+        assert_eq!(d("-2.5").with_scale(0).unwrap().to_string(), "-3");
+        assert_eq!(d("7").with_scale(2).unwrap().to_string(), "7.00");
+        assert_eq!(d("0.30").compare(d("0.3")), Ok(Ordering::Equal));
+        assert_eq!(d("-1").compare(d("0.01")), Ok(Ordering::Less));
+
+        assert!(d("1").div(d("0.00"), 2).is_err());
+        assert!(d("170141183460469231731687303715884105727").add(d("1")).is_err());
+
+        for invalid in &["", "-", ".", "1.2.3", "1e5", "12a", "R$ 10"] {
+            assert_eq!(Decimal::parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn decimal_functions() {
+        use error::BirlError;
+        use testing::run_program;
+
+        let (result, output) = run_program("JAULA SHOW\n\
+                                            É HORA DO: MULTIPLICA DECIMAL, \"19.90\", \"3\"\n\
+                                            VEM: TOTAL, TREZE\n\
+                                            É HORA DO: SOMA DECIMAL, TOTAL, \"0.1\"\n\
+                                            CE QUER VER ISSO: TREZE\n\
+                                            É HORA DO: SUBTRAI DECIMAL, \"0.3\", \"0.1\"\n\
+                                            CE QUER VER ISSO: TREZE\n\
+                                            É HORA DO: DIVIDE DECIMAL, TOTAL, \"7\", 2\n\
+                                            CE QUER VER ISSO: TREZE\n\
+                                            É HORA DO: ARREDONDA DECIMAL, \"2.345\", 2\n\
+                                            CE QUER VER ISSO: TREZE\n\
+                                            É HORA DO: COMPARA DECIMAL, TOTAL, \"60\"\n\
+                                            CE QUER VER ISSO: TREZE\n\
+                                            É HORA DO: SOMA DECIMAL, \"1\", \"UM\"\n\
+                                            SAINDO DA JAULA");
+
+        match result {
+            Ok(_) => panic!("Somou um texto que não é um decimal"),
+            Err(BirlError::Runtime(message, _)) =>
+                assert!(message.contains("\"UM\" não é um número decimal"), "{}", message),
+            Err(e) => panic!("{:?}", e),
+        }
+
+        assert_eq!(output, "59.80\n0.2\n8.53\n2.35\n-1\n");
+    }
+}
@@ -0,0 +1,472 @@
+//! Module with functions for reading and writing files. A file open for reading is a sequence of its lines, read only as
+//! they're asked for, so even huge files are gone through with the memory of a single line. A file open for writing keeps
+//! what's written in a buffer, only going to the disk when it's full, flushed or the file is freed. For files that can't
+//! be left half written, there are temporary files and writing everything at once with an atomic rename
+
+use parser::TypeKind;
+use vm::PluginFunction;
+
+pub use self::plugins::replace_file;
+
+mod plugins
+{
+    use vm::{ DynamicValue, SpecialItemData, VirtualMachine };
+    use iteration::{ ValueIterator, make_sequence, next_in_sequence };
+    use standard_lib::text_manip::glob_matches;
+
+    use std::fs::{ self, File, OpenOptions };
+    use std::io::{ self, BufRead, BufReader, BufWriter, Write };
+    use std::path::{ Path, PathBuf };
+    use std::env;
+    use std::process;
+    use std::sync::atomic::{ AtomicUsize, Ordering };
+
+    /// Makes the names of temporary files made by this process different from each other
+    static NEXT_TEMPORARY : AtomicUsize = AtomicUsize::new(0);
+
+    /// Create a new, empty file in the folder, with a name no other file has
+    fn create_unique(folder : &Path, prefix : &str) -> io::Result<(PathBuf, File)> {
+        loop {
+            let name = format!("{}birl-{}-{}.tmp", prefix, process::id(), NEXT_TEMPORARY.fetch_add(1, Ordering::SeqCst));
+            let path = folder.join(name);
+
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => return Ok((path, file)),
+                // Left by another process with the same ID, long gone
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Gives the lines of a file, without the line break. The file is closed once the end is reached (or the sequence
+    /// is freed, whichever comes first)
+    #[derive(Debug)]
+    struct LineReader {
+        path : String,
+        reader : Option<BufReader<File>>,
+        /// Reused for every line
+        buffer : Vec<u8>,
+    }
+
+    impl ValueIterator for LineReader {
+        fn next(&mut self, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+            let read = match self.reader {
+                Some(ref mut reader) => {
+                    self.buffer.clear();
+
+                    reader.read_until(b'\n', &mut self.buffer)
+                }
+                None => return Ok(None),
+            };
+
+            match read {
+                Ok(0) => {
+                    self.reader = None;
+
+                    Ok(None)
+                }
+                Ok(_) => {
+                    if self.buffer.ends_with(b"\n") {
+                        self.buffer.pop();
+
+                        if self.buffer.ends_with(b"\r") {
+                            self.buffer.pop();
+                        }
+                    }
+
+                    let line = String::from_utf8_lossy(&self.buffer).into_owned();
+                    let id = vm.get_special_storage_mut().add(SpecialItemData::Text(line), 0);
+
+                    Ok(Some(DynamicValue::Text(id)))
+                }
+                Err(e) => {
+                    self.reader = None;
+
+                    Err(format!("Erro ao ler o arquivo {} : {}", self.path, e))
+                }
+            }
+        }
+    }
+
+    fn get_text(value : DynamicValue, vm : &VirtualMachine) -> Result<String, String> {
+        match value {
+            DynamicValue::Text(id) => match vm.get_special_storage_ref().get_data_ref(id) {
+                Some(SpecialItemData::Text(ref t)) => Ok(t.clone()),
+                _ => Err("Erro interno : Dado special com ID fornecido não é um texto".to_owned())
+            },
+            _ => unreachable!()
+        }
+    }
+
+    fn get_writer(value : DynamicValue, vm : &mut VirtualMachine) -> Result<&mut BufWriter<File>, String> {
+        match value {
+            DynamicValue::File(id) => match vm.get_special_storage_mut().get_data_mut(id) {
+                Some(SpecialItemData::File(ref mut writer)) => Ok(writer),
+                _ => Err("Erro interno : Dado special com ID fornecido não é um arquivo".to_owned())
+            },
+            _ => unreachable!()
+        }
+    }
+
+    fn open_for_writing(path : String, append : bool, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let file = match OpenOptions::new().write(true).create(true).append(append).truncate(! append).open(&path) {
+            Ok(f) => f,
+            Err(e) => return Err(format!("Não deu pra abrir o arquivo {} : {}", path, e)),
+        };
+
+        let id = vm.get_special_storage_mut().add(SpecialItemData::File(BufWriter::new(file)), 0);
+
+        Ok(Some(DynamicValue::File(id)))
+    }
+
+    /// Open a file for reading, giving a sequence with its lines
+    /// Arguments : path : Text
+    pub fn open_for_reading(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let path = get_text(arguments.remove(0), vm)?;
+
+        let file = match File::open(&path) {
+            Ok(f) => f,
+            Err(e) => return Err(format!("Não deu pra abrir o arquivo {} : {}", path, e)),
+        };
+
+        let reader = LineReader { path, reader : Some(BufReader::new(file)), buffer : vec![] };
+
+        Ok(Some(make_sequence(Box::new(reader), vm)))
+    }
+
+    /// The next line of a file (or the next value of any sequence), or null when there are no more
+    /// Arguments : file : Sequence
+    pub fn read_line(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        match arguments.remove(0) {
+            DynamicValue::Sequence(id) => Ok(Some(next_in_sequence(id, vm)?.unwrap_or(DynamicValue::Null))),
+            other => Err(format!("LÊ LINHA precisa de um arquivo aberto, mas recebeu um {}", other.get_kind())),
+        }
+    }
+
+    /// Open a file for writing, replacing what was in it
+    /// Arguments : path : Text
+    pub fn open_for_writing_over(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let path = get_text(arguments.remove(0), vm)?;
+
+        open_for_writing(path, false, vm)
+    }
+
+    /// Open a file for writing after what's already in it
+    /// Arguments : path : Text
+    pub fn open_for_appending(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let path = get_text(arguments.remove(0), vm)?;
+
+        open_for_writing(path, true, vm)
+    }
+
+    /// Write a text and a line break to a file
+    /// Arguments : file : File, line : Text
+    pub fn write_line(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let line = get_text(arguments.remove(0), vm)?;
+        let writer = get_writer(arguments.remove(0), vm)?;
+
+        match writer.write_all(line.as_bytes()).and_then(|_| writer.write_all(b"\n")) {
+            Ok(_) => Ok(None),
+            Err(e) => Err(format!("Erro ao escrever no arquivo : {}", e)),
+        }
+    }
+
+    /// Create an empty file in the system's temporary folder, giving its path
+    /// Arguments : none
+    pub fn create_temporary(_ : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let path = match create_unique(&env::temp_dir(), "") {
+            Ok((path, _)) => path.to_string_lossy().into_owned(),
+            Err(e) => return Err(format!("Não deu pra criar um arquivo temporário : {}", e)),
+        };
+
+        let id = vm.get_special_storage_mut().add(SpecialItemData::Text(path), 0);
+
+        Ok(Some(DynamicValue::Text(id)))
+    }
+
+    /// Replace what's in a file, all at once : it's written to a temporary file next to it, which then takes its place.
+    /// If the program is interrupted, the file has either the old or the new contents, never a part
+    pub fn replace_file(path : &Path, contents : &[u8]) -> Result<(), String> {
+        // The rename is only atomic inside the same file system, so the temporary file goes in the same folder
+        let folder = match path.parent() {
+            Some(p) if ! p.as_os_str().is_empty() => p.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+
+        let (temporary, mut file) = match create_unique(&folder, ".") {
+            Ok(t) => t,
+            Err(e) => return Err(format!("Não deu pra escrever o arquivo {} : {}", path.display(), e)),
+        };
+
+        let result = file.write_all(contents)
+            .and_then(|_| file.sync_all())
+            .and_then(|_| fs::rename(&temporary, path));
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                let _ = fs::remove_file(&temporary);
+
+                Err(format!("Não deu pra escrever o arquivo {} : {}", path.display(), e))
+            }
+        }
+    }
+
+    /// Replace what's in a file with the text, all at once (see `replace_file`)
+    /// Arguments : path : Text, contents : Text
+    pub fn write_atomically(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let contents = get_text(arguments.remove(0), vm)?;
+        let path = get_text(arguments.remove(0), vm)?;
+
+        replace_file(Path::new(&path), contents.as_bytes())?;
+
+        Ok(None)
+    }
+
+    /// Move (or rename) a file, replacing the destination if it exists. Inside the same file system, it's atomic
+    /// Arguments : from : Text, to : Text
+    pub fn move_file(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let to = get_text(arguments.remove(0), vm)?;
+        let from = get_text(arguments.remove(0), vm)?;
+
+        match fs::rename(&from, &to) {
+            Ok(_) => Ok(None),
+            Err(e) => Err(format!("Não deu pra mover {} pra {} : {}", from, to, e)),
+        }
+    }
+
+    /// The paths of the files in a folder whose names match a glob pattern, like `logs/*.txt`, in alphabetical order.
+    /// Only the name (the last part of the path) can have wildcards, and names starting with a dot are only matched by
+    /// patterns that start with one too
+    /// Arguments : pattern : Text
+    pub fn list_files(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let pattern = get_text(arguments.remove(0), vm)?;
+        let pattern_path = Path::new(&pattern);
+
+        let name_pattern = match pattern_path.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => return Err(format!("O padrão {} não tem um nome de arquivo", pattern)),
+        };
+
+        let folder = pattern_path.parent().filter(|p| ! p.as_os_str().is_empty());
+
+        let entries = match fs::read_dir(folder.unwrap_or_else(|| Path::new("."))) {
+            Ok(e) => e,
+            Err(e) => return Err(format!("Não deu pra listar os arquivos de {} : {}", pattern, e)),
+        };
+
+        let mut paths = vec![];
+
+        for entry in entries {
+            let name = match entry {
+                Ok(entry) => entry.file_name().to_string_lossy().into_owned(),
+                Err(e) => return Err(format!("Não deu pra listar os arquivos de {} : {}", pattern, e)),
+            };
+
+            if name.starts_with('.') && ! name_pattern.starts_with('.') {
+                continue;
+            }
+
+            if glob_matches(&name_pattern, &name) {
+                let path = match folder {
+                    Some(folder) => folder.join(&name).to_string_lossy().into_owned(),
+                    None => name,
+                };
+
+                paths.push(path);
+            }
+        }
+
+        paths.sort();
+
+        let storage = vm.get_special_storage_mut();
+
+        // Each path is held by the list, so it isn't freed when a variable that had it changes
+        let elements = paths.into_iter()
+            .map(|p| Box::new(DynamicValue::Text(storage.add(SpecialItemData::Text(p), 1))))
+            .collect::<Vec<Box<DynamicValue>>>();
+
+        let id = storage.add(SpecialItemData::List(elements), 0);
+
+        Ok(Some(DynamicValue::List(id)))
+    }
+
+    /// Write everything in the buffer of a file to the disk
+    /// Arguments : file : File
+    pub fn flush(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let writer = get_writer(arguments.remove(0), vm)?;
+
+        match writer.flush() {
+            Ok(_) => Ok(None),
+            Err(e) => Err(format!("Erro ao salvar o arquivo : {}", e)),
+        }
+    }
+}
+
+pub fn get_plugins() -> Vec<(String, Vec<TypeKind>, PluginFunction)>
+{
+    vec!
+    [
+        ("ABRE PRA LER".to_owned(), vec![TypeKind::Text], plugins::open_for_reading),
+        ("LÊ LINHA".to_owned(), vec![TypeKind::Sequence], plugins::read_line),
+        ("ABRE PRA ESCREVER".to_owned(), vec![TypeKind::Text], plugins::open_for_writing_over),
+        ("ABRE PRA ACRESCENTAR".to_owned(), vec![TypeKind::Text], plugins::open_for_appending),
+        ("ESCREVE LINHA".to_owned(), vec![TypeKind::File, TypeKind::Text], plugins::write_line),
+        ("SALVA ARQUIVO".to_owned(), vec![TypeKind::File], plugins::flush),
+        ("CRIA ARQUIVO TEMPORÁRIO".to_owned(), vec![], plugins::create_temporary),
+        ("ESCREVE ARQUIVO".to_owned(), vec![TypeKind::Text, TypeKind::Text], plugins::write_atomically),
+        ("MOVE ARQUIVO".to_owned(), vec![TypeKind::Text, TypeKind::Text], plugins::move_file),
+        ("ACHA ARQUIVOS".to_owned(), vec![TypeKind::Text], plugins::list_files),
+    ]
+}
+
+mod tests {
+    #[test]
+    fn read_file() {
+        use error::BirlError;
+        use testing::run_program;
+        use std::env;
+        use std::fs;
+
+        let path = env::temp_dir().join(format!("birl-ler-{}.txt", ::std::process::id()));
+
+        if let Err(e) = fs::write(&path, "PRIMEIRA\r\nSEGUNDA\nTERCEIRA\nQUARTA") {
+            panic!("{}", e);
+        }
+
+        let src = format!("JAULA SHOW\n\
+                           É HORA DO: ABRE PRA LER, \"{}\"\n\
+                           VEM: ARQUIVO, TREZE\n\
+                           É HORA DO: LÊ LINHA, ARQUIVO\n\
+                           CE QUER VER ISSO: TREZE\n\
+                           PRA CADA: LINHA, ARQUIVO\n\
+                           CE QUER VER ISSO: LINHA\n\
+                           FIM\n\
+                           É HORA DO: LÊ LINHA, ARQUIVO\n\
+                           CE QUER VER ISSO: TREZE\n\
+                           É HORA DO: ABRE PRA LER, \"{}.nada\"\n\
+                           SAINDO DA JAULA", path.display(), path.display());
+
+        let (result, output) = run_program(&src);
+
+        let _ = fs::remove_file(&path);
+
+        match result {
+            Ok(_) => panic!("O arquivo não devia existir"),
+            Err(BirlError::Runtime(message, _)) =>
+                assert!(message.contains("Não deu pra abrir o arquivo"), "{}", message),
+            Err(e) => panic!("{:?}", e),
+        }
+
+        assert_eq!(output, "PRIMEIRA\nSEGUNDA\nTERCEIRA\nQUARTA\n<Null>\n");
+    }
+
+    #[test]
+    fn write_file() {
+        use testing::run_program;
+        use std::env;
+        use std::fs;
+
+        let path = env::temp_dir().join(format!("birl-escrever-{}.txt", ::std::process::id()));
+
+        let src = format!("JAULA SHOW\n\
+                           É HORA DO: ABRE PRA ESCREVER, \"{0}\"\n\
+                           VEM: RELATÓRIO, TREZE\n\
+                           PRA CADA: LETRA, \"BIRL\"\n\
+                           É HORA DO: ESCREVE LINHA, RELATÓRIO, \"LETRA \" + LETRA\n\
+                           FIM\n\
+                           É HORA DO: SALVA ARQUIVO, RELATÓRIO\n\
+                           É HORA DO: ABRE PRA LER, \"{0}\"\n\
+                           É HORA DO: JUNTA, TREZE\n\
+                           CE QUER VER ISSO: TREZE\n\
+                           É HORA DO: ABRE PRA ACRESCENTAR, \"{0}\"\n\
+                           É HORA DO: ESCREVE LINHA, TREZE, \"FIM\"\n\
+                           SAINDO DA JAULA", path.display());
+
+        let (result, output) = run_program(&src);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "(Lista) [ \"LETRA B\", \"LETRA I\", \"LETRA R\", \"LETRA L\" ]\n");
+
+        // What's left in the buffer is written when the file is freed, with the VM, so it's all there by now
+        let contents = fs::read_to_string(&path);
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(contents.ok(), Some("LETRA B\nLETRA I\nLETRA R\nLETRA L\nFIM\n".to_owned()));
+    }
+
+    #[test]
+    fn atomic_write() {
+        use testing::run_program;
+        use std::env;
+        use std::fs;
+
+        let path = env::temp_dir().join(format!("birl-config-{}.txt", ::std::process::id()));
+        let moved = env::temp_dir().join(format!("birl-config-{}.old", ::std::process::id()));
+
+        if let Err(e) = fs::write(&path, "VERSÃO 1") {
+            panic!("{}", e);
+        }
+
+        let src = format!("JAULA SHOW\n\
+                           É HORA DO: CRIA ARQUIVO TEMPORÁRIO\n\
+                           VEM: TEMPORÁRIO, TREZE\n\
+                           É HORA DO: ESCREVE ARQUIVO, TEMPORÁRIO, \"VERSÃO 2\"\n\
+                           É HORA DO: MOVE ARQUIVO, \"{0}\", \"{1}\"\n\
+                           É HORA DO: MOVE ARQUIVO, TEMPORÁRIO, \"{0}\"\n\
+                           É HORA DO: ABRE PRA LER, \"{0}\"\n\
+                           É HORA DO: JUNTA, TREZE\n\
+                           CE QUER VER ISSO: TREZE\n\
+                           É HORA DO: ESCREVE ARQUIVO, \"{0}\", \"VERSÃO 3\"\n\
+                           SAINDO DA JAULA", path.display(), moved.display());
+
+        let (result, output) = run_program(&src);
+
+        let contents = fs::read_to_string(&path).ok();
+        let old = fs::read_to_string(&moved).ok();
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&moved);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "(Lista) [ \"VERSÃO 2\" ]\n");
+        assert_eq!(contents, Some("VERSÃO 3".to_owned()));
+        assert_eq!(old, Some("VERSÃO 1".to_owned()));
+    }
+
+    #[test]
+    fn list_files() {
+        use testing::run_program;
+        use std::env;
+        use std::fs;
+
+        let folder = env::temp_dir().join(format!("birl-lista-{}", ::std::process::id()));
+
+        let created = fs::create_dir_all(&folder)
+            .and_then(|_| fs::write(folder.join("b.txt"), ""))
+            .and_then(|_| fs::write(folder.join("a.txt"), ""))
+            .and_then(|_| fs::write(folder.join("c.log"), ""))
+            .and_then(|_| fs::write(folder.join(".escondido.txt"), ""));
+
+        if let Err(e) = created {
+            panic!("{}", e);
+        }
+
+        let src = format!("JAULA SHOW\n\
+                           É HORA DO: ACHA ARQUIVOS, \"{0}/*.txt\"\n\
+                           PRA CADA: CAMINHO, TREZE\n\
+                           CE QUER VER ISSO: CAMINHO\n\
+                           FIM\n\
+                           É HORA DO: COMBINA, \"relatório.log\", \"*.txt\"\n\
+                           CE QUER VER ISSO: TREZE\n\
+                           SAINDO DA JAULA", folder.display());
+
+        let (result, output) = run_program(&src);
+
+        let _ = fs::remove_dir_all(&folder);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, format!("{0}/a.txt\n{0}/b.txt\n0\n", folder.display()));
+    }
+}
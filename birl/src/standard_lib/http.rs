@@ -0,0 +1,343 @@
+//! Module for a simple HTTP server, with the `http` feature. SOBE SERVIDOR blocks the program, calling a BIRL function
+//! for each request with its path, method and body. The function returns the body of the response, or a list with the
+//! status, the body and (optionally) the content type. It's made for small programs and demos, so there's one request
+//! at a time, no keep-alive and only what's needed of HTTP/1.1 to talk to browsers and tools like curl
+
+use parser::TypeKind;
+use vm::PluginFunction;
+
+mod plugins
+{
+    use vm::{ DynamicValue, SpecialItemData, VirtualMachine };
+    use parser::IntegerType;
+
+    use std::io::{ self, Read, Write, ErrorKind };
+    use std::net::{ TcpListener, TcpStream };
+    use std::sync::atomic::Ordering;
+    use std::thread;
+    use std::time::Duration;
+
+    /// The most the head of a request (the request line and headers) can have, in bytes
+    const MAX_HEAD_SIZE : usize = 64 * 1024;
+    /// The most the body of a request can have, in bytes
+    const MAX_BODY_SIZE : usize = 16 * 1024 * 1024;
+    const DEFAULT_CONTENT_TYPE : &str = "text/plain; charset=utf-8";
+
+    struct Request {
+        method : String,
+        path : String,
+        body : String,
+    }
+
+    struct Response {
+        status : IntegerType,
+        body : String,
+        content_type : String,
+    }
+
+    fn get_text(value : DynamicValue, vm : &VirtualMachine) -> Result<String, String> {
+        match value {
+            DynamicValue::Text(id) => match vm.get_special_storage_ref().get_data_ref(id) {
+                Some(SpecialItemData::Text(ref t)) => Ok(t.clone()),
+                _ => Err("Erro interno : Dado special com ID fornecido não é um texto".to_owned())
+            },
+            _ => unreachable!()
+        }
+    }
+
+    fn find_head_end(data : &[u8]) -> Option<usize> {
+        data.windows(4).position(|w| w == b"\r\n\r\n")
+    }
+
+    /// Read a request from the connection. Gives None if it isn't valid HTTP
+    fn read_request(stream : &mut TcpStream) -> io::Result<Option<Request>> {
+        let mut data = vec![];
+        let mut buffer = [0u8; 4096];
+
+        let head_end = loop {
+            if let Some(end) = find_head_end(&data) {
+                break end;
+            }
+
+            if data.len() > MAX_HEAD_SIZE {
+                return Ok(None);
+            }
+
+            match stream.read(&mut buffer)? {
+                0 => return Ok(None),
+                n => data.extend_from_slice(&buffer[..n]),
+            }
+        };
+
+        let head = match String::from_utf8(data[..head_end].to_vec()) {
+            Ok(h) => h,
+            Err(_) => return Ok(None),
+        };
+
+        let mut lines = head.split("\r\n");
+        let mut request_line = lines.next().unwrap_or("").split(' ');
+
+        let (method, path) = match (request_line.next(), request_line.next(), request_line.next()) {
+            (Some(m), Some(p), Some(v)) if v.starts_with("HTTP/") => (m.to_owned(), p.to_owned()),
+            _ => return Ok(None),
+        };
+
+        let mut length = 0;
+
+        for line in lines {
+            let mut parts = line.splitn(2, ':');
+            let name = parts.next().unwrap_or("").trim();
+
+            if name.eq_ignore_ascii_case("content-length") {
+                length = match parts.next().unwrap_or("").trim().parse::<usize>() {
+                    Ok(l) if l <= MAX_BODY_SIZE => l,
+                    _ => return Ok(None),
+                };
+            }
+        }
+
+        let mut body = data.split_off(head_end + 4);
+
+        while body.len() < length {
+            match stream.read(&mut buffer)? {
+                0 => return Ok(None),
+                n => body.extend_from_slice(&buffer[..n]),
+            }
+        }
+
+        body.truncate(length);
+
+        let body = String::from_utf8_lossy(&body).into_owned();
+
+        Ok(Some(Request { method, path, body }))
+    }
+
+    fn reason(status : IntegerType) -> &'static str {
+        match status {
+            200 => "OK",
+            201 => "Created",
+            204 => "No Content",
+            301 => "Moved Permanently",
+            302 => "Found",
+            304 => "Not Modified",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            500 => "Internal Server Error",
+            _ => "",
+        }
+    }
+
+    fn write_response(stream : &mut TcpStream, response : &Response) -> io::Result<()> {
+        let head = format!("HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                           response.status, reason(response.status), response.content_type, response.body.len());
+
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(response.body.as_bytes())?;
+        stream.flush()
+    }
+
+    /// Turn what the handler returned into a response : a text is the body of a 200, and a list has the status, the
+    /// body and maybe the content type
+    fn make_response(value : DynamicValue, function : &str, vm : &VirtualMachine) -> Result<Response, String> {
+        let invalid = || format!("A função {} tem que retornar um texto, ou uma lista com o status, o texto e \
+                                  (se quiser) o tipo do conteúdo", function);
+
+        let elements = match value {
+            DynamicValue::Text(_) => return Ok(Response {
+                status : 200,
+                body : get_text(value, vm)?,
+                content_type : DEFAULT_CONTENT_TYPE.to_owned(),
+            }),
+            DynamicValue::List(id) => match vm.get_special_storage_ref().get_data_ref(id) {
+                Some(SpecialItemData::List(ref l)) => l.iter().map(|e| **e).collect::<Vec<DynamicValue>>(),
+                _ => return Err("Erro interno : Dado special com ID fornecido não é uma lista".to_owned()),
+            },
+            _ => return Err(invalid()),
+        };
+
+        match (elements.first(), elements.get(1), elements.get(2), elements.len()) {
+            (Some(&DynamicValue::Integer(status)), Some(&body @ DynamicValue::Text(_)), None, 2) => Ok(Response {
+                status,
+                body : get_text(body, vm)?,
+                content_type : DEFAULT_CONTENT_TYPE.to_owned(),
+            }),
+            (Some(&DynamicValue::Integer(status)), Some(&body @ DynamicValue::Text(_)),
+             Some(&content_type @ DynamicValue::Text(_)), 3) => Ok(Response {
+                status,
+                body : get_text(body, vm)?,
+                content_type : get_text(content_type, vm)?,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+
+    /// Call the handler for a request
+    fn handle(request : Request, function : &str, vm : &mut VirtualMachine) -> Result<Response, String> {
+        let arguments = [request.path, request.method, request.body].iter()
+            .map(|t| DynamicValue::Text(vm.get_special_storage_mut().add(SpecialItemData::Text(t.clone()), 0)))
+            .collect::<Vec<DynamicValue>>();
+
+        let result = vm.call_function(function, arguments)?;
+
+        make_response(result, function, vm)
+    }
+
+    /// Serve HTTP on the port, calling the function for each request, until the program is interrupted
+    /// Arguments : port : Integer, function name : Text
+    pub fn serve(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        // The arguments come in the reverse order
+        let function = get_text(arguments.remove(0), vm)?;
+
+        let port = match arguments.remove(0) {
+            DynamicValue::Integer(p) if (0..=65535).contains(&p) => p as u16,
+            DynamicValue::Integer(p) => return Err(format!("Porta inválida : {}", p)),
+            _ => unreachable!()
+        };
+
+        if ! vm.has_function(&function) {
+            return Err(format!("A função {} não existe", function));
+        }
+
+        // Only reachable from this machine, so a demo isn't exposed to the whole network by accident
+        let listener = match TcpListener::bind(("127.0.0.1", port)) {
+            Ok(l) => l,
+            Err(e) => return Err(format!("Não deu pra abrir o servidor na porta {} : {}", port, e)),
+        };
+
+        // Waiting without blocking, so an interruption from the host is seen
+        if let Err(e) = listener.set_nonblocking(true) {
+            return Err(format!("Não deu pra abrir o servidor na porta {} : {}", port, e));
+        }
+
+        let interrupt = vm.interrupt_handle();
+
+        loop {
+            // The flag is left set, so the VM stops at the next instruction like for any other interruption
+            if interrupt.load(Ordering::SeqCst) {
+                return Ok(None);
+            }
+
+            let mut stream = match listener.accept() {
+                Ok((s, _)) => s,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(10));
+                    continue;
+                }
+                Err(e) => return Err(format!("Erro no servidor : {}", e)),
+            };
+
+            let request = stream.set_nonblocking(false)
+                .and_then(|_| stream.set_read_timeout(Some(Duration::from_secs(5))))
+                .and_then(|_| read_request(&mut stream));
+
+            let error = |status| Response { status, body : String::new(), content_type : DEFAULT_CONTENT_TYPE.to_owned() };
+
+            // Problems with a connection are the client's, so the server goes on. An error in the function stops the
+            // program, like anywhere else, but the client still gets an answer
+            let response = match request {
+                Ok(Some(request)) => match handle(request, &function, vm) {
+                    Ok(r) => r,
+                    Err(e) => {
+                        let _ = write_response(&mut stream, &error(500));
+
+                        return Err(e);
+                    }
+                },
+                Ok(None) => error(400),
+                Err(_) => continue,
+            };
+
+            let _ = write_response(&mut stream, &response);
+        }
+    }
+}
+
+pub fn get_plugins() -> Vec<(String, Vec<TypeKind>, PluginFunction)>
+{
+    vec!
+    [
+        ("SOBE SERVIDOR".to_owned(), vec![TypeKind::Integer, TypeKind::Text], plugins::serve),
+    ]
+}
+
+mod tests {
+    #[test]
+    #[cfg(feature = "http")]
+    fn http_server() {
+        use context::Context;
+        use error::BirlError;
+        use std::io::{ Read, Write };
+        use std::net::{ TcpListener, TcpStream };
+        use std::sync::atomic::Ordering;
+        use std::thread;
+        use std::time::Duration;
+
+        let port = TcpListener::bind("127.0.0.1:0").and_then(|l| l.local_addr()).unwrap().port();
+
+        let src = format!("JAULA RESPONDE(CAMINHO : FIBRA, METODO : FIBRA, CORPO : FIBRA)\n\
+                           É ELE QUE A GENTE QUER: CAMINHO, \"/\"\n\
+                           É ELE MEMO:\n\
+                           BIRL: \"BIRL \" + METODO + \" \" + CORPO\n\
+                           FIM\n\
+                           FAZ UMA LISTA: RESPOSTA\n\
+                           PÕE ISSO AQUI: RESPOSTA, 404\n\
+                           PÕE ISSO AQUI: RESPOSTA, \"<h1>\" + CAMINHO + \"</h1>\"\n\
+                           PÕE ISSO AQUI: RESPOSTA, \"text/html\"\n\
+                           BIRL: RESPOSTA\n\
+                           SAINDO DA JAULA\n\
+                           JAULA SHOW\n\
+                           É HORA DO: SOBE SERVIDOR, {}, \"RESPONDE\"\n\
+                           SAINDO DA JAULA", port);
+
+        let mut ctx = Context::new();
+        let interrupt = ctx.interrupt_handle();
+
+        let client = thread::spawn(move || {
+            let request = |text : &str| {
+                // The server may not be up yet
+                let mut stream = loop {
+                    match TcpStream::connect(("127.0.0.1", port)) {
+                        Ok(s) => break s,
+                        Err(_) => thread::sleep(Duration::from_millis(10)),
+                    }
+                };
+
+                stream.write_all(text.as_bytes()).unwrap();
+
+                let mut response = String::new();
+                stream.read_to_string(&mut response).unwrap();
+
+                response
+            };
+
+            let responses = vec![
+                request("POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nHORA!"),
+                request("GET /FRANGO HTTP/1.1\r\nHost: localhost\r\n\r\n"),
+                request("ISSO NÃO É HTTP\r\n\r\n"),
+            ];
+
+            interrupt.store(true, Ordering::SeqCst);
+
+            responses
+        });
+
+        let result = ctx.call_function_by_id(0, vec![])
+            .and_then(|_| ctx.add_standard_library())
+            .and_then(|_| ctx.add_source_string(src))
+            .and_then(|_| ctx.start_program());
+
+        assert!(matches!(result, Err(BirlError::Runtime(ref message, _)) if message == "Execução interrompida"),
+                "{:?}", result);
+
+        let responses = client.join().unwrap();
+
+        assert_eq!(responses[0], "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: 15\r\n\
+                                  Connection: close\r\n\r\nBIRL POST HORA!");
+        assert_eq!(responses[1], "HTTP/1.1 404 Not Found\r\nContent-Type: text/html\r\nContent-Length: 16\r\n\
+                                  Connection: close\r\n\r\n<h1>/FRANGO</h1>");
+        assert!(responses[2].starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", responses[2]);
+    }
+}
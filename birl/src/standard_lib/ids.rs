@@ -0,0 +1,161 @@
+//! Module for random identifiers (for file names, tokens, keys in a database...). They use the random numbers of the VM,
+//! so with a seed they're the same on every run
+
+use parser::TypeKind;
+use vm::PluginFunction;
+
+/// Characters of the short IDs, the same as nanoid's : 64 of them, all safe in URLs and file names
+const SHORT_ID_ALPHABET : &[u8; 64] = b"useandom-26T198340PX75pxJACKVERYMINDBUSHWOLF_GQZbfghjklqvwyzrict";
+
+/// Longest short ID. Way past what's needed for them to never repeat, it's only to stop a mistake from filling the memory
+const SHORT_ID_MAX_SIZE : usize = 256;
+
+/// A random (version 4) UUID from 128 random bits, like "1b4e28ba-2fa1-4d2c-883f-0016d3cca427"
+pub fn uuid_v4(high : u64, low : u64) -> String {
+    let mut bytes = [0u8; 16];
+
+    bytes[..8].copy_from_slice(&high.to_be_bytes());
+    bytes[8..].copy_from_slice(&low.to_be_bytes());
+
+    // Version 4 and the RFC 4122 variant
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let mut uuid = String::with_capacity(36);
+
+    for (i, byte) in bytes.iter().enumerate() {
+        if i == 4 || i == 6 || i == 8 || i == 10 {
+            uuid.push('-');
+        }
+
+        uuid.push_str(&format!("{:02x}", byte));
+    }
+
+    uuid
+}
+
+/// A short ID with `size` characters, taking 6 bits of the random numbers for each one
+pub fn short_id(size : usize, mut next_random : impl FnMut() -> u64) -> String {
+    let mut id = String::with_capacity(size);
+    let mut bits = 0u64;
+    let mut available = 0;
+
+    for _ in 0..size {
+        if available < 6 {
+            bits = next_random();
+            available = 64;
+        }
+
+        id.push(SHORT_ID_ALPHABET[(bits & 0x3f) as usize] as char);
+
+        bits >>= 6;
+        available -= 6;
+    }
+
+    id
+}
+
+mod plugins
+{
+    use vm::{ DynamicValue, SpecialItemData, VirtualMachine };
+    use super::{ uuid_v4, short_id, SHORT_ID_MAX_SIZE };
+
+    fn make_text(text : String, vm : &mut VirtualMachine) -> DynamicValue {
+        DynamicValue::Text(vm.get_special_storage_mut().add(SpecialItemData::Text(text), 0))
+    }
+
+    /// A random UUID (version 4)
+    /// Arguments : none
+    pub fn uuid(_arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let high = vm.next_random();
+        let low = vm.next_random();
+
+        Ok(Some(make_text(uuid_v4(high, low), vm)))
+    }
+
+    /// A random ID with the given number of letters, digits, '-' and '_'
+    /// Arguments : size : Integer
+    pub fn short(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let size = match arguments.remove(0) {
+            DynamicValue::Integer(n) => n,
+            _ => unreachable!()
+        };
+
+        if size < 1 || size > SHORT_ID_MAX_SIZE as _ {
+            return Err(format!("O tamanho do ID tem que ser entre 1 e {}, mas foi {}", SHORT_ID_MAX_SIZE, size));
+        }
+
+        let id = short_id(size as usize, || vm.next_random());
+
+        Ok(Some(make_text(id, vm)))
+    }
+}
+
+pub fn get_plugins() -> Vec<(String, Vec<TypeKind>, PluginFunction)>
+{
+    vec!
+    [
+        ("UUID".to_owned(), vec![], plugins::uuid),
+        ("ID CURTO".to_owned(), vec![TypeKind::Integer], plugins::short),
+    ]
+}
+
+mod tests {
+    #[test]
+    fn uuid_format() {
+        use standard_lib::ids::uuid_v4;
+
+        assert_eq!(uuid_v4(0, 0), "00000000-0000-4000-8000-000000000000");
+        assert_eq!(uuid_v4(!0, !0), "ffffffff-ffff-4fff-bfff-ffffffffffff");
+        assert_eq!(uuid_v4(0x0123_4567_89ab_cdef, 0x0123_4567_89ab_cdef), "01234567-89ab-4def-8123-456789abcdef");
+    }
+
+    #[test]
+    fn random_ids() {
+        use context::Context;
+        use error::BirlError;
+        use testing::{ run_in_context, run_program };
+        use vm::VirtualMachineBuilder;
+
+        let run = |seed : Option<u64>| {
+            let mut ctx = match Context::with_vm(VirtualMachineBuilder::new().random_seed(seed)) {
+                Ok(c) => c,
+                Err(e) => panic!("{}", e),
+            };
+
+            run_in_context(&mut ctx, "JAULA SHOW\n\
+                                      É HORA DO: UUID\n\
+                                      CE QUER VER ISSO: TREZE\n\
+                                      É HORA DO: ID CURTO, 21\n\
+                                      CE QUER VER ISSO: TREZE\n\
+                                      É HORA DO: ID CURTO, 5\n\
+                                      CE QUER VER ISSO: TREZE\n\
+                                      SAINDO DA JAULA")
+        };
+
+        let (result, first) = run(Some(42));
+        assert_eq!(result, Ok(()));
+
+        let lines = first.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].len(), 36);
+        assert_eq!(lines[0].chars().nth(14), Some('4'));
+        assert_eq!(lines[1].len(), 21);
+        assert_eq!(lines[2].len(), 5);
+        assert!(lines[1].chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+
+        // The same seed gives the same IDs, and without one they're different every time
+        assert_eq!(run(Some(42)).1, first);
+        assert_ne!(run(Some(43)).1, first);
+        assert_ne!(run(None).1, run(None).1);
+
+        let (result, _) = run_program("É HORA DO: ID CURTO, 0");
+
+        match result {
+            Ok(_) => panic!("Fez um ID de tamanho 0"),
+            Err(BirlError::Runtime(message, _)) =>
+                assert!(message.contains("tem que ser entre 1 e 256"), "{}", message),
+            Err(e) => panic!("{:?}", e),
+        }
+    }
+}
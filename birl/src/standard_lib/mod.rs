@@ -6,6 +6,28 @@ use modules::*;
 use context::RawValue;
 
 mod text_manip;
+mod system_info;
+mod sequences;
+mod files;
+mod config;
+mod store;
+mod url;
+mod ids;
+mod decimal;
+mod statistics;
+mod normalization;
+mod similarity;
+mod bytes;
+mod width;
+#[cfg(feature = "sqlite")]
+mod database;
+#[cfg(feature = "clipboard")]
+mod clipboard;
+#[cfg(feature = "http")]
+mod http;
+
+// Used by the compiler to suggest names close to the ones it didn't find
+pub use self::similarity::edit_distance;
 
 fn get_global_vars() -> Vec<(String, RawValue)> {
     vec!
@@ -16,17 +38,66 @@ fn get_global_vars() -> Vec<(String, RawValue)> {
     ]
 }
 
+/// Names of the functions that touch the host's files (or its clipboard and network)
+pub fn file_functions() -> Vec<String> {
+    #[allow(unused_mut)]
+    let mut plugins = files::get_plugins();
+    plugins.extend(store::get_plugins());
+
+    #[cfg(feature = "sqlite")]
+    plugins.extend(database::get_plugins());
+
+    #[cfg(feature = "clipboard")]
+    plugins.extend(clipboard::get_plugins());
+
+    #[cfg(feature = "http")]
+    plugins.extend(http::get_plugins());
+
+    plugins.into_iter().map(|(name, _, _)| name).collect()
+}
+
 pub fn module_standard_library() -> Module {
     let mut module = Module::new("PADRÃO".to_owned());
 
-    let modules_plugins = vec!
+    #[allow(unused_mut)]
+    let mut modules_plugins = vec!
     [
-        text_manip::get_plugins()
+        text_manip::get_plugins(),
+        system_info::get_plugins(),
+        sequences::get_plugins(),
+        files::get_plugins(),
+        config::get_plugins(),
+        store::get_plugins(),
+        url::get_plugins(),
+        ids::get_plugins(),
+        decimal::get_plugins(),
+        statistics::get_plugins(),
+        normalization::get_plugins(),
+        similarity::get_plugins(),
+        bytes::get_plugins(),
+        width::get_plugins(),
     ];
 
+    #[cfg(feature = "sqlite")]
+    modules_plugins.push(database::get_plugins());
+
+    #[cfg(feature = "clipboard")]
+    modules_plugins.push(clipboard::get_plugins());
+
+    #[cfg(feature = "http")]
+    modules_plugins.push(http::get_plugins());
+
     let modules_vars = vec!
     [
-        get_global_vars()
+        get_global_vars(),
+        system_info::get_global_vars(),
+    ];
+
+    let modules_methods = vec!
+    [
+        text_manip::get_methods(),
+        normalization::get_methods(),
+        width::get_methods(),
     ];
 
     let modules_source_functions : Vec<Vec<SourceFunction>> = vec!
@@ -51,5 +122,25 @@ pub fn module_standard_library() -> Module {
         }
     }
 
+    for methods in modules_methods {
+        for (receiver, name, function) in methods {
+            module.methods.push(Method::new(receiver, name, function));
+        }
+    }
+
+    module
+}
+
+/// The standard library with what changes from one machine to another (the name of the user) fixed, so the same code
+/// prints the same thing anywhere
+pub fn module_deterministic_standard_library() -> Module {
+    let mut module = module_standard_library();
+
+    for var in &mut module.global_variables {
+        if var.name == "CUMPADE" {
+            var.value = RawValue::Text("CUMPADE".to_owned());
+        }
+    }
+
     module
 }
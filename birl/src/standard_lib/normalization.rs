@@ -0,0 +1,445 @@
+//! Module for Unicode normalization of texts, for comparing and searching them without caring about accents. There are
+//! no Unicode tables in the standard library, so only the Latin letters are covered (Latin-1, Latin Extended-A and B, and
+//! Latin Extended Additional, which has the Vietnamese letters) : everything else is kept as it is
+
+use parser::TypeKind;
+use vm::PluginFunction;
+
+/// The canonical combining class of a character, which says the order of the accents after a letter. Only the block
+/// of combining diacritical marks (U+0300 to U+036F) is known, anything else is taken as a letter (class 0)
+fn combining_class(c : char) -> u8 {
+    match c {
+        '\u{300}'..='\u{314}' => 230,
+        '\u{315}' => 232,
+        '\u{316}'..='\u{319}' => 220,
+        '\u{31A}' => 232,
+        '\u{31B}' => 216,
+        '\u{31C}'..='\u{320}' => 220,
+        '\u{321}'..='\u{322}' => 202,
+        '\u{323}'..='\u{326}' => 220,
+        '\u{327}'..='\u{328}' => 202,
+        '\u{329}'..='\u{333}' => 220,
+        '\u{334}'..='\u{338}' => 1,
+        '\u{339}'..='\u{33C}' => 220,
+        '\u{33D}'..='\u{344}' => 230,
+        '\u{345}' => 240,
+        '\u{346}' => 230,
+        '\u{347}'..='\u{349}' => 220,
+        '\u{34A}'..='\u{34C}' => 230,
+        '\u{34D}'..='\u{34E}' => 220,
+        '\u{350}'..='\u{352}' => 230,
+        '\u{353}'..='\u{356}' => 220,
+        '\u{357}' => 230,
+        '\u{358}' => 232,
+        '\u{359}'..='\u{35A}' => 220,
+        '\u{35B}' => 230,
+        '\u{35C}' => 233,
+        '\u{35D}'..='\u{35E}' => 234,
+        '\u{35F}' => 233,
+        '\u{360}'..='\u{361}' => 234,
+        '\u{362}' => 233,
+        '\u{363}'..='\u{36F}' => 230,
+        _ => 0,
+    }
+}
+
+fn is_combining_mark(c : char) -> bool {
+    ('\u{300}'..='\u{36F}').contains(&c)
+}
+
+/// Push the canonical decomposition of the character, decomposing the letter too when it also has an accent (like Ḉ,
+/// which is Ç with an acute accent)
+fn push_decomposed(c : char, result : &mut Vec<char>) {
+    match DECOMPOSITIONS.binary_search_by_key(&c, |&(composed, _, _)| composed) {
+        Ok(index) => {
+            let (_, letter, mark) = DECOMPOSITIONS[index];
+
+            push_decomposed(letter, result);
+            result.push(mark);
+        }
+        Err(_) => result.push(c),
+    }
+}
+
+/// The text decomposed, with each accent separated from its letter (Normalization Form D)
+pub fn nfd(text : &str) -> String {
+    let mut chars = Vec::with_capacity(text.len());
+
+    for c in text.chars() {
+        push_decomposed(c, &mut chars);
+    }
+
+    // The accents after each letter are put in the order of their classes. The sort is stable, so accents of the same
+    // class keep their order
+    let mut start = 0;
+
+    while start < chars.len() {
+        if combining_class(chars[start]) == 0 {
+            start += 1;
+            continue;
+        }
+
+        let end = chars[start..].iter().position(|&c| combining_class(c) == 0).map_or(chars.len(), |p| start + p);
+
+        chars[start..end].sort_by_key(|&c| combining_class(c));
+        start = end;
+    }
+
+    chars.into_iter().collect()
+}
+
+/// The text composed, with the accents joined to their letters whenever there's a character for both (Normalization
+/// Form C)
+pub fn nfc(text : &str) -> String {
+    let mut result : Vec<char> = vec![];
+    // The last letter, and the class of the last character after it, if any
+    let mut starter : Option<usize> = None;
+    let mut last_class : Option<u8> = None;
+
+    for c in nfd(text).chars() {
+        let class = combining_class(c);
+
+        if let Some(index) = starter {
+            // An accent can't join the letter if there's another one of the same class (or a letter) between them
+            let blocked = match last_class {
+                Some(last) => last == 0 || last >= class,
+                None => false,
+            };
+
+            if ! blocked {
+                let letter = result[index];
+
+                if let Some(&(composed, _, _)) = DECOMPOSITIONS.iter().find(|&&(_, l, m)| l == letter && m == c) {
+                    result[index] = composed;
+                    continue;
+                }
+            }
+        }
+
+        if class == 0 {
+            starter = Some(result.len());
+            last_class = None;
+        } else {
+            last_class = Some(class);
+        }
+
+        result.push(c);
+    }
+
+    result.into_iter().collect()
+}
+
+/// The text without accents, like "ação" to "acao". Letters with a stroke, which isn't an accent in Unicode, also lose
+/// it (ø, đ and ł)
+pub fn strip_accents(text : &str) -> String {
+    nfd(text).chars()
+        .filter(|&c| ! is_combining_mark(c))
+        .map(|c| match c {
+            'ø' => 'o',
+            'Ø' => 'O',
+            'đ' => 'd',
+            'Đ' => 'D',
+            'ł' => 'l',
+            'Ł' => 'L',
+            c => c,
+        })
+        .collect()
+}
+
+/// A text that can go in a URL or a file name, like "Ação Rápida!" to "acao-rapida" : without accents, in lower case,
+/// and with a '-' in place of anything other than letters and digits
+pub fn slug(text : &str) -> String {
+    let mut result = String::with_capacity(text.len());
+
+    for c in strip_accents(text).chars() {
+        if c.is_alphanumeric() {
+            result.extend(c.to_lowercase());
+        } else if ! result.is_empty() && ! result.ends_with('-') {
+            result.push('-');
+        }
+    }
+
+    if result.ends_with('-') {
+        result.pop();
+    }
+
+    result
+}
+
+mod plugins
+{
+    use vm::{ DynamicValue, SpecialItemData, VirtualMachine };
+
+    fn get_text(value : DynamicValue, vm : &VirtualMachine) -> Result<String, String> {
+        match value {
+            DynamicValue::Text(id) => match vm.get_special_storage_ref().get_data_ref(id) {
+                Some(SpecialItemData::Text(ref t)) => Ok(t.clone()),
+                _ => Err("Erro interno : Dado special com ID fornecido não é um texto".to_owned())
+            },
+            _ => unreachable!()
+        }
+    }
+
+    /// Apply `transform` to the text argument, giving the result as a new text
+    fn transform_text(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine, transform : fn(&str) -> String)
+        -> Result<Option<DynamicValue>, String> {
+        let text = get_text(arguments.remove(0), vm)?;
+        let result = transform(&text);
+
+        Ok(Some(DynamicValue::Text(vm.get_special_storage_mut().add(SpecialItemData::Text(result), 0))))
+    }
+
+    /// The text composed (Normalization Form C). Only Latin letters with diacritics are composed : the letters of other
+    /// scripts (like Greek or Hangul) and their accents come back as they are
+    /// Arguments : text : Text
+    pub fn nfc(arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        transform_text(arguments, vm, super::nfc)
+    }
+
+    /// Arguments : text : Text
+    pub fn nfd(arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        transform_text(arguments, vm, super::nfd)
+    }
+
+    /// Arguments : text : Text
+    pub fn strip_accents(arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        transform_text(arguments, vm, super::strip_accents)
+    }
+
+    /// Arguments : text : Text
+    pub fn slug(arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        transform_text(arguments, vm, super::slug)
+    }
+}
+
+pub fn get_plugins() -> Vec<(String, Vec<TypeKind>, PluginFunction)>
+{
+    vec!
+    [
+        ("NORMALIZA NFC".to_owned(), vec![TypeKind::Text], plugins::nfc),
+        ("NORMALIZA NFD".to_owned(), vec![TypeKind::Text], plugins::nfd),
+        ("TIRA ACENTOS".to_owned(), vec![TypeKind::Text], plugins::strip_accents),
+        ("SLUG".to_owned(), vec![TypeKind::Text], plugins::slug),
+    ]
+}
+
+/// Functions of this module that are also methods : (receiver, method, function)
+pub fn get_methods() -> Vec<(TypeKind, String, String)>
+{
+    vec!
+    [
+        (TypeKind::Text, "SEM ACENTOS".to_owned(), "TIRA ACENTOS".to_owned()),
+    ]
+}
+
+/// Each Latin letter with an accent, the character it's made of (sometimes another letter with an accent) and the
+/// accent, in the order of the letters. Taken from the canonical decompositions of UnicodeData.txt (Unicode 14)
+const DECOMPOSITIONS : &[(char, char, char)] = &[
+    ('\u{C0}', 'A', '\u{300}'), ('\u{C1}', 'A', '\u{301}'), ('\u{C2}', 'A', '\u{302}'), ('\u{C3}', 'A', '\u{303}'),
+    ('\u{C4}', 'A', '\u{308}'), ('\u{C5}', 'A', '\u{30A}'), ('\u{C7}', 'C', '\u{327}'), ('\u{C8}', 'E', '\u{300}'),
+    ('\u{C9}', 'E', '\u{301}'), ('\u{CA}', 'E', '\u{302}'), ('\u{CB}', 'E', '\u{308}'), ('\u{CC}', 'I', '\u{300}'),
+    ('\u{CD}', 'I', '\u{301}'), ('\u{CE}', 'I', '\u{302}'), ('\u{CF}', 'I', '\u{308}'), ('\u{D1}', 'N', '\u{303}'),
+    ('\u{D2}', 'O', '\u{300}'), ('\u{D3}', 'O', '\u{301}'), ('\u{D4}', 'O', '\u{302}'), ('\u{D5}', 'O', '\u{303}'),
+    ('\u{D6}', 'O', '\u{308}'), ('\u{D9}', 'U', '\u{300}'), ('\u{DA}', 'U', '\u{301}'), ('\u{DB}', 'U', '\u{302}'),
+    ('\u{DC}', 'U', '\u{308}'), ('\u{DD}', 'Y', '\u{301}'), ('\u{E0}', 'a', '\u{300}'), ('\u{E1}', 'a', '\u{301}'),
+    ('\u{E2}', 'a', '\u{302}'), ('\u{E3}', 'a', '\u{303}'), ('\u{E4}', 'a', '\u{308}'), ('\u{E5}', 'a', '\u{30A}'),
+    ('\u{E7}', 'c', '\u{327}'), ('\u{E8}', 'e', '\u{300}'), ('\u{E9}', 'e', '\u{301}'), ('\u{EA}', 'e', '\u{302}'),
+    ('\u{EB}', 'e', '\u{308}'), ('\u{EC}', 'i', '\u{300}'), ('\u{ED}', 'i', '\u{301}'), ('\u{EE}', 'i', '\u{302}'),
+    ('\u{EF}', 'i', '\u{308}'), ('\u{F1}', 'n', '\u{303}'), ('\u{F2}', 'o', '\u{300}'), ('\u{F3}', 'o', '\u{301}'),
+    ('\u{F4}', 'o', '\u{302}'), ('\u{F5}', 'o', '\u{303}'), ('\u{F6}', 'o', '\u{308}'), ('\u{F9}', 'u', '\u{300}'),
+    ('\u{FA}', 'u', '\u{301}'), ('\u{FB}', 'u', '\u{302}'), ('\u{FC}', 'u', '\u{308}'), ('\u{FD}', 'y', '\u{301}'),
+    ('\u{FF}', 'y', '\u{308}'), ('\u{100}', 'A', '\u{304}'), ('\u{101}', 'a', '\u{304}'), ('\u{102}', 'A', '\u{306}'),
+    ('\u{103}', 'a', '\u{306}'), ('\u{104}', 'A', '\u{328}'), ('\u{105}', 'a', '\u{328}'), ('\u{106}', 'C', '\u{301}'),
+    ('\u{107}', 'c', '\u{301}'), ('\u{108}', 'C', '\u{302}'), ('\u{109}', 'c', '\u{302}'), ('\u{10A}', 'C', '\u{307}'),
+    ('\u{10B}', 'c', '\u{307}'), ('\u{10C}', 'C', '\u{30C}'), ('\u{10D}', 'c', '\u{30C}'), ('\u{10E}', 'D', '\u{30C}'),
+    ('\u{10F}', 'd', '\u{30C}'), ('\u{112}', 'E', '\u{304}'), ('\u{113}', 'e', '\u{304}'), ('\u{114}', 'E', '\u{306}'),
+    ('\u{115}', 'e', '\u{306}'), ('\u{116}', 'E', '\u{307}'), ('\u{117}', 'e', '\u{307}'), ('\u{118}', 'E', '\u{328}'),
+    ('\u{119}', 'e', '\u{328}'), ('\u{11A}', 'E', '\u{30C}'), ('\u{11B}', 'e', '\u{30C}'), ('\u{11C}', 'G', '\u{302}'),
+    ('\u{11D}', 'g', '\u{302}'), ('\u{11E}', 'G', '\u{306}'), ('\u{11F}', 'g', '\u{306}'), ('\u{120}', 'G', '\u{307}'),
+    ('\u{121}', 'g', '\u{307}'), ('\u{122}', 'G', '\u{327}'), ('\u{123}', 'g', '\u{327}'), ('\u{124}', 'H', '\u{302}'),
+    ('\u{125}', 'h', '\u{302}'), ('\u{128}', 'I', '\u{303}'), ('\u{129}', 'i', '\u{303}'), ('\u{12A}', 'I', '\u{304}'),
+    ('\u{12B}', 'i', '\u{304}'), ('\u{12C}', 'I', '\u{306}'), ('\u{12D}', 'i', '\u{306}'), ('\u{12E}', 'I', '\u{328}'),
+    ('\u{12F}', 'i', '\u{328}'), ('\u{130}', 'I', '\u{307}'), ('\u{134}', 'J', '\u{302}'), ('\u{135}', 'j', '\u{302}'),
+    ('\u{136}', 'K', '\u{327}'), ('\u{137}', 'k', '\u{327}'), ('\u{139}', 'L', '\u{301}'), ('\u{13A}', 'l', '\u{301}'),
+    ('\u{13B}', 'L', '\u{327}'), ('\u{13C}', 'l', '\u{327}'), ('\u{13D}', 'L', '\u{30C}'), ('\u{13E}', 'l', '\u{30C}'),
+    ('\u{143}', 'N', '\u{301}'), ('\u{144}', 'n', '\u{301}'), ('\u{145}', 'N', '\u{327}'), ('\u{146}', 'n', '\u{327}'),
+    ('\u{147}', 'N', '\u{30C}'), ('\u{148}', 'n', '\u{30C}'), ('\u{14C}', 'O', '\u{304}'), ('\u{14D}', 'o', '\u{304}'),
+    ('\u{14E}', 'O', '\u{306}'), ('\u{14F}', 'o', '\u{306}'), ('\u{150}', 'O', '\u{30B}'), ('\u{151}', 'o', '\u{30B}'),
+    ('\u{154}', 'R', '\u{301}'), ('\u{155}', 'r', '\u{301}'), ('\u{156}', 'R', '\u{327}'), ('\u{157}', 'r', '\u{327}'),
+    ('\u{158}', 'R', '\u{30C}'), ('\u{159}', 'r', '\u{30C}'), ('\u{15A}', 'S', '\u{301}'), ('\u{15B}', 's', '\u{301}'),
+    ('\u{15C}', 'S', '\u{302}'), ('\u{15D}', 's', '\u{302}'), ('\u{15E}', 'S', '\u{327}'), ('\u{15F}', 's', '\u{327}'),
+    ('\u{160}', 'S', '\u{30C}'), ('\u{161}', 's', '\u{30C}'), ('\u{162}', 'T', '\u{327}'), ('\u{163}', 't', '\u{327}'),
+    ('\u{164}', 'T', '\u{30C}'), ('\u{165}', 't', '\u{30C}'), ('\u{168}', 'U', '\u{303}'), ('\u{169}', 'u', '\u{303}'),
+    ('\u{16A}', 'U', '\u{304}'), ('\u{16B}', 'u', '\u{304}'), ('\u{16C}', 'U', '\u{306}'), ('\u{16D}', 'u', '\u{306}'),
+    ('\u{16E}', 'U', '\u{30A}'), ('\u{16F}', 'u', '\u{30A}'), ('\u{170}', 'U', '\u{30B}'), ('\u{171}', 'u', '\u{30B}'),
+    ('\u{172}', 'U', '\u{328}'), ('\u{173}', 'u', '\u{328}'), ('\u{174}', 'W', '\u{302}'), ('\u{175}', 'w', '\u{302}'),
+    ('\u{176}', 'Y', '\u{302}'), ('\u{177}', 'y', '\u{302}'), ('\u{178}', 'Y', '\u{308}'), ('\u{179}', 'Z', '\u{301}'),
+    ('\u{17A}', 'z', '\u{301}'), ('\u{17B}', 'Z', '\u{307}'), ('\u{17C}', 'z', '\u{307}'), ('\u{17D}', 'Z', '\u{30C}'),
+    ('\u{17E}', 'z', '\u{30C}'), ('\u{1A0}', 'O', '\u{31B}'), ('\u{1A1}', 'o', '\u{31B}'), ('\u{1AF}', 'U', '\u{31B}'),
+    ('\u{1B0}', 'u', '\u{31B}'), ('\u{1CD}', 'A', '\u{30C}'), ('\u{1CE}', 'a', '\u{30C}'), ('\u{1CF}', 'I', '\u{30C}'),
+    ('\u{1D0}', 'i', '\u{30C}'), ('\u{1D1}', 'O', '\u{30C}'), ('\u{1D2}', 'o', '\u{30C}'), ('\u{1D3}', 'U', '\u{30C}'),
+    ('\u{1D4}', 'u', '\u{30C}'), ('\u{1D5}', '\u{DC}', '\u{304}'), ('\u{1D6}', '\u{FC}', '\u{304}'),
+    ('\u{1D7}', '\u{DC}', '\u{301}'), ('\u{1D8}', '\u{FC}', '\u{301}'), ('\u{1D9}', '\u{DC}', '\u{30C}'),
+    ('\u{1DA}', '\u{FC}', '\u{30C}'), ('\u{1DB}', '\u{DC}', '\u{300}'), ('\u{1DC}', '\u{FC}', '\u{300}'),
+    ('\u{1DE}', '\u{C4}', '\u{304}'), ('\u{1DF}', '\u{E4}', '\u{304}'), ('\u{1E0}', '\u{226}', '\u{304}'),
+    ('\u{1E1}', '\u{227}', '\u{304}'), ('\u{1E2}', '\u{C6}', '\u{304}'), ('\u{1E3}', '\u{E6}', '\u{304}'),
+    ('\u{1E6}', 'G', '\u{30C}'), ('\u{1E7}', 'g', '\u{30C}'), ('\u{1E8}', 'K', '\u{30C}'), ('\u{1E9}', 'k', '\u{30C}'),
+    ('\u{1EA}', 'O', '\u{328}'), ('\u{1EB}', 'o', '\u{328}'), ('\u{1EC}', '\u{1EA}', '\u{304}'),
+    ('\u{1ED}', '\u{1EB}', '\u{304}'), ('\u{1EE}', '\u{1B7}', '\u{30C}'), ('\u{1EF}', '\u{292}', '\u{30C}'),
+    ('\u{1F0}', 'j', '\u{30C}'), ('\u{1F4}', 'G', '\u{301}'), ('\u{1F5}', 'g', '\u{301}'), ('\u{1F8}', 'N', '\u{300}'),
+    ('\u{1F9}', 'n', '\u{300}'), ('\u{1FA}', '\u{C5}', '\u{301}'), ('\u{1FB}', '\u{E5}', '\u{301}'),
+    ('\u{1FC}', '\u{C6}', '\u{301}'), ('\u{1FD}', '\u{E6}', '\u{301}'), ('\u{1FE}', '\u{D8}', '\u{301}'),
+    ('\u{1FF}', '\u{F8}', '\u{301}'), ('\u{200}', 'A', '\u{30F}'), ('\u{201}', 'a', '\u{30F}'),
+    ('\u{202}', 'A', '\u{311}'), ('\u{203}', 'a', '\u{311}'), ('\u{204}', 'E', '\u{30F}'), ('\u{205}', 'e', '\u{30F}'),
+    ('\u{206}', 'E', '\u{311}'), ('\u{207}', 'e', '\u{311}'), ('\u{208}', 'I', '\u{30F}'), ('\u{209}', 'i', '\u{30F}'),
+    ('\u{20A}', 'I', '\u{311}'), ('\u{20B}', 'i', '\u{311}'), ('\u{20C}', 'O', '\u{30F}'), ('\u{20D}', 'o', '\u{30F}'),
+    ('\u{20E}', 'O', '\u{311}'), ('\u{20F}', 'o', '\u{311}'), ('\u{210}', 'R', '\u{30F}'), ('\u{211}', 'r', '\u{30F}'),
+    ('\u{212}', 'R', '\u{311}'), ('\u{213}', 'r', '\u{311}'), ('\u{214}', 'U', '\u{30F}'), ('\u{215}', 'u', '\u{30F}'),
+    ('\u{216}', 'U', '\u{311}'), ('\u{217}', 'u', '\u{311}'), ('\u{218}', 'S', '\u{326}'), ('\u{219}', 's', '\u{326}'),
+    ('\u{21A}', 'T', '\u{326}'), ('\u{21B}', 't', '\u{326}'), ('\u{21E}', 'H', '\u{30C}'), ('\u{21F}', 'h', '\u{30C}'),
+    ('\u{226}', 'A', '\u{307}'), ('\u{227}', 'a', '\u{307}'), ('\u{228}', 'E', '\u{327}'), ('\u{229}', 'e', '\u{327}'),
+    ('\u{22A}', '\u{D6}', '\u{304}'), ('\u{22B}', '\u{F6}', '\u{304}'), ('\u{22C}', '\u{D5}', '\u{304}'),
+    ('\u{22D}', '\u{F5}', '\u{304}'), ('\u{22E}', 'O', '\u{307}'), ('\u{22F}', 'o', '\u{307}'),
+    ('\u{230}', '\u{22E}', '\u{304}'), ('\u{231}', '\u{22F}', '\u{304}'), ('\u{232}', 'Y', '\u{304}'),
+    ('\u{233}', 'y', '\u{304}'), ('\u{1E00}', 'A', '\u{325}'), ('\u{1E01}', 'a', '\u{325}'),
+    ('\u{1E02}', 'B', '\u{307}'), ('\u{1E03}', 'b', '\u{307}'), ('\u{1E04}', 'B', '\u{323}'),
+    ('\u{1E05}', 'b', '\u{323}'), ('\u{1E06}', 'B', '\u{331}'), ('\u{1E07}', 'b', '\u{331}'),
+    ('\u{1E08}', '\u{C7}', '\u{301}'), ('\u{1E09}', '\u{E7}', '\u{301}'), ('\u{1E0A}', 'D', '\u{307}'),
+    ('\u{1E0B}', 'd', '\u{307}'), ('\u{1E0C}', 'D', '\u{323}'), ('\u{1E0D}', 'd', '\u{323}'),
+    ('\u{1E0E}', 'D', '\u{331}'), ('\u{1E0F}', 'd', '\u{331}'), ('\u{1E10}', 'D', '\u{327}'),
+    ('\u{1E11}', 'd', '\u{327}'), ('\u{1E12}', 'D', '\u{32D}'), ('\u{1E13}', 'd', '\u{32D}'),
+    ('\u{1E14}', '\u{112}', '\u{300}'), ('\u{1E15}', '\u{113}', '\u{300}'), ('\u{1E16}', '\u{112}', '\u{301}'),
+    ('\u{1E17}', '\u{113}', '\u{301}'), ('\u{1E18}', 'E', '\u{32D}'), ('\u{1E19}', 'e', '\u{32D}'),
+    ('\u{1E1A}', 'E', '\u{330}'), ('\u{1E1B}', 'e', '\u{330}'), ('\u{1E1C}', '\u{228}', '\u{306}'),
+    ('\u{1E1D}', '\u{229}', '\u{306}'), ('\u{1E1E}', 'F', '\u{307}'), ('\u{1E1F}', 'f', '\u{307}'),
+    ('\u{1E20}', 'G', '\u{304}'), ('\u{1E21}', 'g', '\u{304}'), ('\u{1E22}', 'H', '\u{307}'),
+    ('\u{1E23}', 'h', '\u{307}'), ('\u{1E24}', 'H', '\u{323}'), ('\u{1E25}', 'h', '\u{323}'),
+    ('\u{1E26}', 'H', '\u{308}'), ('\u{1E27}', 'h', '\u{308}'), ('\u{1E28}', 'H', '\u{327}'),
+    ('\u{1E29}', 'h', '\u{327}'), ('\u{1E2A}', 'H', '\u{32E}'), ('\u{1E2B}', 'h', '\u{32E}'),
+    ('\u{1E2C}', 'I', '\u{330}'), ('\u{1E2D}', 'i', '\u{330}'), ('\u{1E2E}', '\u{CF}', '\u{301}'),
+    ('\u{1E2F}', '\u{EF}', '\u{301}'), ('\u{1E30}', 'K', '\u{301}'), ('\u{1E31}', 'k', '\u{301}'),
+    ('\u{1E32}', 'K', '\u{323}'), ('\u{1E33}', 'k', '\u{323}'), ('\u{1E34}', 'K', '\u{331}'),
+    ('\u{1E35}', 'k', '\u{331}'), ('\u{1E36}', 'L', '\u{323}'), ('\u{1E37}', 'l', '\u{323}'),
+    ('\u{1E38}', '\u{1E36}', '\u{304}'), ('\u{1E39}', '\u{1E37}', '\u{304}'), ('\u{1E3A}', 'L', '\u{331}'),
+    ('\u{1E3B}', 'l', '\u{331}'), ('\u{1E3C}', 'L', '\u{32D}'), ('\u{1E3D}', 'l', '\u{32D}'),
+    ('\u{1E3E}', 'M', '\u{301}'), ('\u{1E3F}', 'm', '\u{301}'), ('\u{1E40}', 'M', '\u{307}'),
+    ('\u{1E41}', 'm', '\u{307}'), ('\u{1E42}', 'M', '\u{323}'), ('\u{1E43}', 'm', '\u{323}'),
+    ('\u{1E44}', 'N', '\u{307}'), ('\u{1E45}', 'n', '\u{307}'), ('\u{1E46}', 'N', '\u{323}'),
+    ('\u{1E47}', 'n', '\u{323}'), ('\u{1E48}', 'N', '\u{331}'), ('\u{1E49}', 'n', '\u{331}'),
+    ('\u{1E4A}', 'N', '\u{32D}'), ('\u{1E4B}', 'n', '\u{32D}'), ('\u{1E4C}', '\u{D5}', '\u{301}'),
+    ('\u{1E4D}', '\u{F5}', '\u{301}'), ('\u{1E4E}', '\u{D5}', '\u{308}'), ('\u{1E4F}', '\u{F5}', '\u{308}'),
+    ('\u{1E50}', '\u{14C}', '\u{300}'), ('\u{1E51}', '\u{14D}', '\u{300}'), ('\u{1E52}', '\u{14C}', '\u{301}'),
+    ('\u{1E53}', '\u{14D}', '\u{301}'), ('\u{1E54}', 'P', '\u{301}'), ('\u{1E55}', 'p', '\u{301}'),
+    ('\u{1E56}', 'P', '\u{307}'), ('\u{1E57}', 'p', '\u{307}'), ('\u{1E58}', 'R', '\u{307}'),
+    ('\u{1E59}', 'r', '\u{307}'), ('\u{1E5A}', 'R', '\u{323}'), ('\u{1E5B}', 'r', '\u{323}'),
+    ('\u{1E5C}', '\u{1E5A}', '\u{304}'), ('\u{1E5D}', '\u{1E5B}', '\u{304}'), ('\u{1E5E}', 'R', '\u{331}'),
+    ('\u{1E5F}', 'r', '\u{331}'), ('\u{1E60}', 'S', '\u{307}'), ('\u{1E61}', 's', '\u{307}'),
+    ('\u{1E62}', 'S', '\u{323}'), ('\u{1E63}', 's', '\u{323}'), ('\u{1E64}', '\u{15A}', '\u{307}'),
+    ('\u{1E65}', '\u{15B}', '\u{307}'), ('\u{1E66}', '\u{160}', '\u{307}'), ('\u{1E67}', '\u{161}', '\u{307}'),
+    ('\u{1E68}', '\u{1E62}', '\u{307}'), ('\u{1E69}', '\u{1E63}', '\u{307}'), ('\u{1E6A}', 'T', '\u{307}'),
+    ('\u{1E6B}', 't', '\u{307}'), ('\u{1E6C}', 'T', '\u{323}'), ('\u{1E6D}', 't', '\u{323}'),
+    ('\u{1E6E}', 'T', '\u{331}'), ('\u{1E6F}', 't', '\u{331}'), ('\u{1E70}', 'T', '\u{32D}'),
+    ('\u{1E71}', 't', '\u{32D}'), ('\u{1E72}', 'U', '\u{324}'), ('\u{1E73}', 'u', '\u{324}'),
+    ('\u{1E74}', 'U', '\u{330}'), ('\u{1E75}', 'u', '\u{330}'), ('\u{1E76}', 'U', '\u{32D}'),
+    ('\u{1E77}', 'u', '\u{32D}'), ('\u{1E78}', '\u{168}', '\u{301}'), ('\u{1E79}', '\u{169}', '\u{301}'),
+    ('\u{1E7A}', '\u{16A}', '\u{308}'), ('\u{1E7B}', '\u{16B}', '\u{308}'), ('\u{1E7C}', 'V', '\u{303}'),
+    ('\u{1E7D}', 'v', '\u{303}'), ('\u{1E7E}', 'V', '\u{323}'), ('\u{1E7F}', 'v', '\u{323}'),
+    ('\u{1E80}', 'W', '\u{300}'), ('\u{1E81}', 'w', '\u{300}'), ('\u{1E82}', 'W', '\u{301}'),
+    ('\u{1E83}', 'w', '\u{301}'), ('\u{1E84}', 'W', '\u{308}'), ('\u{1E85}', 'w', '\u{308}'),
+    ('\u{1E86}', 'W', '\u{307}'), ('\u{1E87}', 'w', '\u{307}'), ('\u{1E88}', 'W', '\u{323}'),
+    ('\u{1E89}', 'w', '\u{323}'), ('\u{1E8A}', 'X', '\u{307}'), ('\u{1E8B}', 'x', '\u{307}'),
+    ('\u{1E8C}', 'X', '\u{308}'), ('\u{1E8D}', 'x', '\u{308}'), ('\u{1E8E}', 'Y', '\u{307}'),
+    ('\u{1E8F}', 'y', '\u{307}'), ('\u{1E90}', 'Z', '\u{302}'), ('\u{1E91}', 'z', '\u{302}'),
+    ('\u{1E92}', 'Z', '\u{323}'), ('\u{1E93}', 'z', '\u{323}'), ('\u{1E94}', 'Z', '\u{331}'),
+    ('\u{1E95}', 'z', '\u{331}'), ('\u{1E96}', 'h', '\u{331}'), ('\u{1E97}', 't', '\u{308}'),
+    ('\u{1E98}', 'w', '\u{30A}'), ('\u{1E99}', 'y', '\u{30A}'), ('\u{1E9B}', '\u{17F}', '\u{307}'),
+    ('\u{1EA0}', 'A', '\u{323}'), ('\u{1EA1}', 'a', '\u{323}'), ('\u{1EA2}', 'A', '\u{309}'),
+    ('\u{1EA3}', 'a', '\u{309}'), ('\u{1EA4}', '\u{C2}', '\u{301}'), ('\u{1EA5}', '\u{E2}', '\u{301}'),
+    ('\u{1EA6}', '\u{C2}', '\u{300}'), ('\u{1EA7}', '\u{E2}', '\u{300}'), ('\u{1EA8}', '\u{C2}', '\u{309}'),
+    ('\u{1EA9}', '\u{E2}', '\u{309}'), ('\u{1EAA}', '\u{C2}', '\u{303}'), ('\u{1EAB}', '\u{E2}', '\u{303}'),
+    ('\u{1EAC}', '\u{1EA0}', '\u{302}'), ('\u{1EAD}', '\u{1EA1}', '\u{302}'), ('\u{1EAE}', '\u{102}', '\u{301}'),
+    ('\u{1EAF}', '\u{103}', '\u{301}'), ('\u{1EB0}', '\u{102}', '\u{300}'), ('\u{1EB1}', '\u{103}', '\u{300}'),
+    ('\u{1EB2}', '\u{102}', '\u{309}'), ('\u{1EB3}', '\u{103}', '\u{309}'), ('\u{1EB4}', '\u{102}', '\u{303}'),
+    ('\u{1EB5}', '\u{103}', '\u{303}'), ('\u{1EB6}', '\u{1EA0}', '\u{306}'), ('\u{1EB7}', '\u{1EA1}', '\u{306}'),
+    ('\u{1EB8}', 'E', '\u{323}'), ('\u{1EB9}', 'e', '\u{323}'), ('\u{1EBA}', 'E', '\u{309}'),
+    ('\u{1EBB}', 'e', '\u{309}'), ('\u{1EBC}', 'E', '\u{303}'), ('\u{1EBD}', 'e', '\u{303}'),
+    ('\u{1EBE}', '\u{CA}', '\u{301}'), ('\u{1EBF}', '\u{EA}', '\u{301}'), ('\u{1EC0}', '\u{CA}', '\u{300}'),
+    ('\u{1EC1}', '\u{EA}', '\u{300}'), ('\u{1EC2}', '\u{CA}', '\u{309}'), ('\u{1EC3}', '\u{EA}', '\u{309}'),
+    ('\u{1EC4}', '\u{CA}', '\u{303}'), ('\u{1EC5}', '\u{EA}', '\u{303}'), ('\u{1EC6}', '\u{1EB8}', '\u{302}'),
+    ('\u{1EC7}', '\u{1EB9}', '\u{302}'), ('\u{1EC8}', 'I', '\u{309}'), ('\u{1EC9}', 'i', '\u{309}'),
+    ('\u{1ECA}', 'I', '\u{323}'), ('\u{1ECB}', 'i', '\u{323}'), ('\u{1ECC}', 'O', '\u{323}'),
+    ('\u{1ECD}', 'o', '\u{323}'), ('\u{1ECE}', 'O', '\u{309}'), ('\u{1ECF}', 'o', '\u{309}'),
+    ('\u{1ED0}', '\u{D4}', '\u{301}'), ('\u{1ED1}', '\u{F4}', '\u{301}'), ('\u{1ED2}', '\u{D4}', '\u{300}'),
+    ('\u{1ED3}', '\u{F4}', '\u{300}'), ('\u{1ED4}', '\u{D4}', '\u{309}'), ('\u{1ED5}', '\u{F4}', '\u{309}'),
+    ('\u{1ED6}', '\u{D4}', '\u{303}'), ('\u{1ED7}', '\u{F4}', '\u{303}'), ('\u{1ED8}', '\u{1ECC}', '\u{302}'),
+    ('\u{1ED9}', '\u{1ECD}', '\u{302}'), ('\u{1EDA}', '\u{1A0}', '\u{301}'), ('\u{1EDB}', '\u{1A1}', '\u{301}'),
+    ('\u{1EDC}', '\u{1A0}', '\u{300}'), ('\u{1EDD}', '\u{1A1}', '\u{300}'), ('\u{1EDE}', '\u{1A0}', '\u{309}'),
+    ('\u{1EDF}', '\u{1A1}', '\u{309}'), ('\u{1EE0}', '\u{1A0}', '\u{303}'), ('\u{1EE1}', '\u{1A1}', '\u{303}'),
+    ('\u{1EE2}', '\u{1A0}', '\u{323}'), ('\u{1EE3}', '\u{1A1}', '\u{323}'), ('\u{1EE4}', 'U', '\u{323}'),
+    ('\u{1EE5}', 'u', '\u{323}'), ('\u{1EE6}', 'U', '\u{309}'), ('\u{1EE7}', 'u', '\u{309}'),
+    ('\u{1EE8}', '\u{1AF}', '\u{301}'), ('\u{1EE9}', '\u{1B0}', '\u{301}'), ('\u{1EEA}', '\u{1AF}', '\u{300}'),
+    ('\u{1EEB}', '\u{1B0}', '\u{300}'), ('\u{1EEC}', '\u{1AF}', '\u{309}'), ('\u{1EED}', '\u{1B0}', '\u{309}'),
+    ('\u{1EEE}', '\u{1AF}', '\u{303}'), ('\u{1EEF}', '\u{1B0}', '\u{303}'), ('\u{1EF0}', '\u{1AF}', '\u{323}'),
+    ('\u{1EF1}', '\u{1B0}', '\u{323}'), ('\u{1EF2}', 'Y', '\u{300}'), ('\u{1EF3}', 'y', '\u{300}'),
+    ('\u{1EF4}', 'Y', '\u{323}'), ('\u{1EF5}', 'y', '\u{323}'), ('\u{1EF6}', 'Y', '\u{309}'),
+    ('\u{1EF7}', 'y', '\u{309}'), ('\u{1EF8}', 'Y', '\u{303}'), ('\u{1EF9}', 'y', '\u{303}'),
+];
+
+mod tests {
+    #[test]
+    fn normalization() {
+        use standard_lib::normalization::{ nfc, nfd, strip_accents, slug };
+
+        let composed = "A\u{E7}\u{E3}o";
+        let decomposed = "Ac\u{327}a\u{303}o";
+
+        assert_eq!(nfd(composed), decomposed);
+        assert_eq!(nfc(decomposed), composed);
+        assert_eq!(nfc(composed), composed);
+        assert_eq!(nfd(decomposed), decomposed);
+
+        // Ḉ is Ç with an acute accent, and the cedilla comes first since its class is lower
+        assert_eq!(nfd("\u{1E08}"), "C\u{327}\u{301}");
+        assert_eq!(nfd("C\u{301}\u{327}"), "C\u{327}\u{301}");
+        assert_eq!(nfc("C\u{301}\u{327}"), "\u{1E08}");
+
+        // Vietnamese has letters with two accents
+        assert_eq!(nfc("e\u{302}\u{301}"), "\u{1EBF}");
+        assert_eq!(nfc("Vie\u{323}\u{302}t"), "Vi\u{1EC7}t");
+
+        // An accent that has no character with the letter stays separate
+        assert_eq!(nfc("q\u{301}"), "q\u{301}");
+        assert_eq!(nfc("\u{3B1}\u{301}"), "\u{3B1}\u{301}");
+
+        assert_eq!(strip_accents("Ação, pão e café à vontade"), "Acao, pao e cafe a vontade");
+        assert_eq!(strip_accents("Crème Brûlée, Ørsted, Łódź"), "Creme Brulee, Orsted, Lodz");
+        assert_eq!(strip_accents(decomposed), "Acao");
+
+        assert_eq!(slug("  Ação Rápida!! É HORA DO SHOW  "), "acao-rapida-e-hora-do-show");
+        assert_eq!(slug("--"), "");
+    }
+
+    #[test]
+    fn normalization_functions() {
+        use testing::run_program;
+
+        let src = "VEM: NOME, \"São João\"\n\
+                   É HORA DO: TIRA ACENTOS, NOME\n\
+                   CE QUER VER ISSO: TREZE\n\
+                   É HORA DO: NOME.SEM ACENTOS\n\
+                   CE QUER VER ISSO: TREZE\n\
+                   É HORA DO: SLUG, NOME\n\
+                   CE QUER VER ISSO: TREZE\n\
+                   É HORA DO: NORMALIZA NFD, NOME\n\
+                   É HORA DO: TAMANHO DO TEXTO, TREZE\n\
+                   CE QUER VER ISSO: TREZE\n\
+                   É HORA DO: NORMALIZA NFC, \"\u{3B1}\u{301} \u{1100}\u{1161}\"\n\
+                   CE QUER VER ISSO: TREZE";
+
+        let (result, output) = run_program(src);
+
+        assert_eq!(result, Ok(()));
+        // Greek and Hangul aren't composed
+        assert_eq!(output, "Sao Joao\nSao Joao\nsao-joao\n12\n\u{3B1}\u{301} \u{1100}\u{1161}\n");
+    }
+}
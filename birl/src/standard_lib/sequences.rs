@@ -0,0 +1,157 @@
+//! Module with functions over sequences : they work on anything PRA CADA can go through and are lazy, only taking
+//! values from the source as the result is gone through, so no intermediate lists are made
+
+use parser::TypeKind;
+use vm::PluginFunction;
+
+mod plugins
+{
+    use vm::{ DynamicValue, SpecialItemData, VirtualMachine };
+    use iteration::{ iterate, make_sequence, TakeIterator, SkipIterator, MapIterator, FilterIterator };
+    use parser::IntegerType;
+
+    // The arguments come in the reverse order, so the sequence is the last one
+
+    fn get_count(value : DynamicValue) -> IntegerType {
+        match value {
+            DynamicValue::Integer(n) => n,
+            _ => unreachable!()
+        }
+    }
+
+    fn get_function(value : DynamicValue, vm : &VirtualMachine) -> Result<String, String> {
+        let name = match value {
+            DynamicValue::Text(id) => match vm.get_special_storage_ref().get_data_ref(id) {
+                Some(SpecialItemData::Text(ref t)) => t.clone(),
+                _ => return Err("Erro interno : Dado special com ID fornecido não é um texto".to_owned())
+            },
+            _ => unreachable!()
+        };
+
+        if ! vm.has_function(&name) {
+            return Err(format!("A função {} não existe", name));
+        }
+
+        Ok(name)
+    }
+
+    /// The first values of a sequence
+    /// Arguments : sequence : Sequence, count : Integer
+    pub fn take(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let count = get_count(arguments.remove(0));
+        let source = iterate(arguments.remove(0), vm)?;
+
+        Ok(Some(make_sequence(Box::new(TakeIterator::new(source, count)), vm)))
+    }
+
+    /// A sequence without its first values
+    /// Arguments : sequence : Sequence, count : Integer
+    pub fn skip(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let count = get_count(arguments.remove(0));
+        let source = iterate(arguments.remove(0), vm)?;
+
+        Ok(Some(make_sequence(Box::new(SkipIterator::new(source, count)), vm)))
+    }
+
+    /// What the function returns for each value of a sequence
+    /// Arguments : sequence : Sequence, function name : Text
+    pub fn map(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let function = get_function(arguments.remove(0), vm)?;
+        let source = iterate(arguments.remove(0), vm)?;
+
+        Ok(Some(make_sequence(Box::new(MapIterator::new(source, function)), vm)))
+    }
+
+    /// The values of a sequence for which the function returns something true
+    /// Arguments : sequence : Sequence, function name : Text
+    pub fn filter(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let function = get_function(arguments.remove(0), vm)?;
+        let source = iterate(arguments.remove(0), vm)?;
+
+        Ok(Some(make_sequence(Box::new(FilterIterator::new(source, function)), vm)))
+    }
+
+    /// Go through a sequence, putting its values in a new list
+    /// Arguments : sequence : Sequence
+    pub fn collect(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let mut source = iterate(arguments.remove(0), vm)?;
+        let mut elements = vec![];
+
+        while let Some(value) = source.next(vm)? {
+            // Held by the list from now on, so calling the functions of the sequence again doesn't free it
+            if let DynamicValue::Text(id) | DynamicValue::List(id) | DynamicValue::Sequence(id) = value {
+                vm.get_special_storage_mut().increment_ref(id)?;
+            }
+
+            elements.push(Box::new(value));
+        }
+
+        let id = vm.get_special_storage_mut().add(SpecialItemData::List(elements), 0);
+
+        Ok(Some(DynamicValue::List(id)))
+    }
+}
+
+pub fn get_plugins() -> Vec<(String, Vec<TypeKind>, PluginFunction)>
+{
+    vec!
+    [
+        ("PEGA".to_owned(), vec![TypeKind::Sequence, TypeKind::Integer], plugins::take),
+        ("PULA".to_owned(), vec![TypeKind::Sequence, TypeKind::Integer], plugins::skip),
+        ("MAPEIA".to_owned(), vec![TypeKind::Sequence, TypeKind::Text], plugins::map),
+        ("FILTRA".to_owned(), vec![TypeKind::Sequence, TypeKind::Text], plugins::filter),
+        ("JUNTA".to_owned(), vec![TypeKind::Sequence], plugins::collect),
+    ]
+}
+
+mod tests {
+    #[test]
+    fn sequences() {
+        use error::BirlError;
+        use testing::run_program;
+
+        let src = "JAULA DOBRO (X : BATATA DOCE)\n\
+                   BIRL: X * 2\n\
+                   SAINDO DA JAULA\n\
+                   JAULA GRANDE (X : BATATA DOCE)\n\
+                   BIRL: X > 10\n\
+                   SAINDO DA JAULA\n\
+                   JAULA SHOW\n\
+                   É HORA DO: MAPEIA, 1000000000, \"DOBRO\"\n\
+                   VEM: S, TREZE\n\
+                   É HORA DO: S.FILTRA, \"GRANDE\"\n\
+                   É HORA DO: PULA, TREZE, 1\n\
+                   É HORA DO: PEGA, TREZE, 3\n\
+                   PRA CADA: N, TREZE\n\
+                   CE QUER VER ISSO: N\n\
+                   FIM\n\
+                   É HORA DO: S.PEGA, 2\n\
+                   É HORA DO: JUNTA, TREZE\n\
+                   CE QUER VER ISSO: TREZE\n\
+                   FAZ UMA LISTA: L\n\
+                   PÕE ISSO AQUI: L, \"A\"\n\
+                   PÕE ISSO AQUI: L, \"B\"\n\
+                   PÕE ISSO AQUI: L, \"C\"\n\
+                   É HORA DO: L.PULA, 1\n\
+                   É HORA DO: JUNTA, TREZE\n\
+                   CE QUER VER ISSO: TREZE\n\
+                   É HORA DO: PEGA, \"BIRL\", 2\n\
+                   É HORA DO: JUNTA, TREZE\n\
+                   CE QUER VER ISSO: TREZE\n\
+                   É HORA DO: MAPEIA, L, \"NADA\"\n\
+                   SAINDO DA JAULA";
+
+        let (result, output) = run_program(src);
+
+        // A billion values are never made : only the ones that were needed are taken from the source
+        match result {
+            Ok(_) => panic!("NADA não devia existir"),
+            Err(BirlError::Runtime(message, _)) => assert!(message.contains("A função NADA não existe"), "{}", message),
+            Err(e) => panic!("{:?}", e),
+        }
+
+        // S was already partly gone through by the first loop, so it continues from where it stopped
+        assert_eq!(output, "14\n16\n18\n(Lista) [ 20, 22 ]\n(Lista) [ \"B\", \"C\" ]\n\
+                            (Lista) [ \"B\", \"I\" ]\n");
+    }
+}
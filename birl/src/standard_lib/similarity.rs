@@ -0,0 +1,148 @@
+//! Module for finding texts that are almost the same, like a word typed wrong. The distances count characters, not bytes,
+//! so a letter with an accent is one character like any other
+
+use parser::TypeKind;
+use vm::PluginFunction;
+use std::mem;
+
+/// The Levenshtein distance : how many characters have to be added, removed or swapped for another to turn `a` into `b`
+pub fn edit_distance(a : &str, b : &str) -> usize {
+    let b = b.chars().collect::<Vec<char>>();
+
+    // Only the previous row of the table is needed
+    let mut previous = (0..=b.len()).collect::<Vec<usize>>();
+    let mut current = vec![0; b.len() + 1];
+
+    for (i, ca) in a.chars().enumerate() {
+        current[0] = i + 1;
+
+        for (j, &cb) in b.iter().enumerate() {
+            let swap = previous[j] + if ca == cb { 0 } else { 1 };
+
+            current[j + 1] = swap.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+
+        mem::swap(&mut previous, &mut current);
+    }
+
+    previous[b.len()]
+}
+
+/// Whether some part of `text` is at most `max_distance` edits away from `pattern`. With 0, it's the same as looking for
+/// `pattern` in `text`
+pub fn fuzzy_contains(text : &str, pattern : &str, max_distance : usize) -> bool {
+    let pattern = pattern.chars().collect::<Vec<char>>();
+
+    // Like the edit distance, but the match can start anywhere in the text (the first column is always 0) and end
+    // anywhere (any row with the last column small enough is a match)
+    let mut previous = (0..=pattern.len()).collect::<Vec<usize>>();
+    let mut current = vec![0; pattern.len() + 1];
+
+    if previous[pattern.len()] <= max_distance {
+        return true;
+    }
+
+    for ct in text.chars() {
+        current[0] = 0;
+
+        for (j, &cp) in pattern.iter().enumerate() {
+            let swap = previous[j] + if ct == cp { 0 } else { 1 };
+
+            current[j + 1] = swap.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+
+        if current[pattern.len()] <= max_distance {
+            return true;
+        }
+
+        mem::swap(&mut previous, &mut current);
+    }
+
+    false
+}
+
+mod plugins
+{
+    use vm::{ DynamicValue, VirtualMachine };
+    use plugin::PluginContext;
+    use super::{ edit_distance, fuzzy_contains };
+    use std::convert::TryFrom;
+
+    /// How many characters have to change for one text to become the other
+    /// Arguments : a : Text, b : Text
+    pub fn distance(arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let ctx = PluginContext::new(arguments, vm);
+        let (a, b) = (ctx.arg_str(0)?, ctx.arg_str(1)?);
+
+        Ok(Some(DynamicValue::Integer(edit_distance(&a, &b) as _)))
+    }
+
+    /// Whether the text has something close to the pattern, with at most the given number of characters changed
+    /// Arguments : text : Text, pattern : Text, max_distance : Integer
+    pub fn contains(arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let ctx = PluginContext::new(arguments, vm);
+        let max_distance = ctx.arg_int(2)?;
+
+        if max_distance < 0 {
+            return Err(format!("O número de diferenças não pode ser negativo, mas foi {}", max_distance));
+        }
+
+        let (text, pattern) = (ctx.arg_str(0)?, ctx.arg_str(1)?);
+        let max_distance = usize::try_from(max_distance).unwrap_or(usize::MAX);
+
+        Ok(Some(DynamicValue::Bool(fuzzy_contains(&text, &pattern, max_distance))))
+    }
+}
+
+pub fn get_plugins() -> Vec<(String, Vec<TypeKind>, PluginFunction)>
+{
+    vec!
+    [
+        ("DISTÂNCIA DE EDIÇÃO".to_owned(), vec![TypeKind::Text, TypeKind::Text], plugins::distance),
+        ("CONTÉM PARECIDO".to_owned(), vec![TypeKind::Text, TypeKind::Text, TypeKind::Integer], plugins::contains),
+    ]
+}
+
+mod tests {
+    #[test]
+    fn edit_distances() {
+        use standard_lib::similarity::{ edit_distance, fuzzy_contains };
+
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("BIRL", ""), 4);
+        assert_eq!(edit_distance("", "BIRL"), 4);
+        assert_eq!(edit_distance("BIRL", "BIRL"), 0);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("FRANGO", "FRANG"), 1);
+        assert_eq!(edit_distance("MÉDIA", "MEDIA"), 1);
+        assert_eq!(edit_distance("JAULA", "JALUA"), 2);
+
+        assert!(fuzzy_contains("BORA CUMPADE", "CUMPADE", 0));
+        assert!(fuzzy_contains("BORA CUMPADI", "CUMPADE", 1));
+        assert!(fuzzy_contains("BORA CUMPDE", "CUMPADE", 1));
+        assert!(! fuzzy_contains("BORA CMPDI", "CUMPADE", 2));
+        assert!(fuzzy_contains("qualquer coisa", "", 0));
+        assert!(fuzzy_contains("", "AB", 2));
+        assert!(! fuzzy_contains("", "AB", 1));
+    }
+
+    #[test]
+    fn similarity_functions() {
+        use error::BirlError;
+        use testing::run_program;
+
+        let (result, output) = run_program("É HORA DO: DISTÂNCIA DE EDIÇÃO, \"FRANGO\", \"FRANCO\"\n\
+                                            CE QUER VER ISSO: TREZE\n\
+                                            É HORA DO: CONTÉM PARECIDO, \"HORA DO SHOW\", \"SHOU\", 1\n\
+                                            CE QUER VER ISSO: TREZE\n\
+                                            É HORA DO: CONTÉM PARECIDO, \"HORA DO SHOW\", \"SHOU\", 0\n\
+                                            CE QUER VER ISSO: TREZE");
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "1\nVERDADE\nMENTIRA\n");
+
+        let (result, _) = run_program("É HORA DO: CONTÉM PARECIDO, \"A\", \"B\", -1");
+        assert!(matches!(result, Err(BirlError::Runtime(ref message, _)) if message.contains("negativo")),
+                "{:?}", result);
+    }
+}
@@ -0,0 +1,184 @@
+//! Module for statistics over lists of numbers (integers and numbers can be mixed). The results are always numbers, even
+//! when every element is an integer
+
+use parser::TypeKind;
+use vm::PluginFunction;
+
+/// The arithmetic mean
+pub fn mean(values : &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// The variance of the population (dividing by the number of elements, not by one less)
+pub fn variance(values : &[f64]) -> f64 {
+    let mean = mean(values);
+
+    values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / values.len() as f64
+}
+
+/// The value below which `percent` percent of the values are, interpolating between the two closest ones when it falls
+/// between them (like PERCENTIL in spreadsheets). The values have to be sorted
+pub fn percentile(sorted : &[f64], percent : f64) -> f64 {
+    let position = percent / 100.0 * (sorted.len() - 1) as f64;
+    let below = position.floor() as usize;
+    let above = position.ceil() as usize;
+
+    sorted[below] + (sorted[above] - sorted[below]) * (position - below as f64)
+}
+
+mod plugins
+{
+    use vm::{ DynamicValue, SpecialItemData, VirtualMachine };
+    use super::{ mean, variance, percentile };
+
+    /// The elements of the list as numbers, failing if it's empty or has anything that isn't a number
+    fn get_numbers(value : DynamicValue, vm : &VirtualMachine) -> Result<Vec<f64>, String> {
+        let elements = match value {
+            DynamicValue::List(id) => match vm.get_special_storage_ref().get_data_ref(id) {
+                Some(SpecialItemData::List(ref l)) => l,
+                _ => return Err("Erro interno : Dado special com ID fornecido não é uma lista".to_owned())
+            },
+            _ => unreachable!()
+        };
+
+        if elements.is_empty() {
+            return Err("A lista está vazia, não tem como calcular nada com ela".to_owned());
+        }
+
+        elements.iter().enumerate().map(|(index, element)| match **element {
+            DynamicValue::Integer(i) => Ok(i as f64),
+            DynamicValue::Number(n) => Ok(n),
+            ref other => Err(format!("O elemento {} da lista é um {}, não um número", index, other.get_kind())),
+        }).collect()
+    }
+
+    fn get_sorted_numbers(value : DynamicValue, vm : &VirtualMachine) -> Result<Vec<f64>, String> {
+        let mut numbers = get_numbers(value, vm)?;
+
+        // Infinities are numbers too, but NaN can't be sorted
+        if numbers.iter().any(|n| n.is_nan()) {
+            return Err("A lista tem um valor que não é um número (NaN)".to_owned());
+        }
+
+        numbers.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        Ok(numbers)
+    }
+
+    /// The mean of the elements
+    /// Arguments : list : List
+    pub fn mean_of(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let numbers = get_numbers(arguments.remove(0), vm)?;
+
+        Ok(Some(DynamicValue::Number(mean(&numbers))))
+    }
+
+    /// The variance of the elements, as a population
+    /// Arguments : list : List
+    pub fn variance_of(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let numbers = get_numbers(arguments.remove(0), vm)?;
+
+        Ok(Some(DynamicValue::Number(variance(&numbers))))
+    }
+
+    /// The standard deviation of the elements, as a population
+    /// Arguments : list : List
+    pub fn standard_deviation(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let numbers = get_numbers(arguments.remove(0), vm)?;
+
+        Ok(Some(DynamicValue::Number(variance(&numbers).sqrt())))
+    }
+
+    /// The element in the middle, or the mean of the two in the middle if there's an even number of them
+    /// Arguments : list : List
+    pub fn median(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let numbers = get_sorted_numbers(arguments.remove(0), vm)?;
+
+        Ok(Some(DynamicValue::Number(percentile(&numbers, 50.0))))
+    }
+
+    /// The percentile of the elements, from 0 (the smallest) to 100 (the biggest)
+    /// Arguments : list : List, percent : Number
+    pub fn percentile_of(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        // The arguments come in the reverse order
+        let percent = match arguments.remove(0) {
+            DynamicValue::Integer(i) => i as f64,
+            DynamicValue::Number(n) => n,
+            _ => unreachable!()
+        };
+
+        if !(0.0..=100.0).contains(&percent) {
+            return Err(format!("O percentil tem que ser entre 0 e 100, mas foi {}", percent));
+        }
+
+        let numbers = get_sorted_numbers(arguments.remove(0), vm)?;
+
+        Ok(Some(DynamicValue::Number(percentile(&numbers, percent))))
+    }
+}
+
+pub fn get_plugins() -> Vec<(String, Vec<TypeKind>, PluginFunction)>
+{
+    vec!
+    [
+        ("MÉDIA".to_owned(), vec![TypeKind::List], plugins::mean_of),
+        ("VARIÂNCIA".to_owned(), vec![TypeKind::List], plugins::variance_of),
+        ("DESVIO PADRÃO".to_owned(), vec![TypeKind::List], plugins::standard_deviation),
+        ("MEDIANA".to_owned(), vec![TypeKind::List], plugins::median),
+        ("PERCENTIL".to_owned(), vec![TypeKind::List, TypeKind::Number], plugins::percentile_of),
+    ]
+}
+
+mod tests {
+    #[test]
+    fn statistics_of_lists() {
+        use standard_lib::statistics::{ mean, variance, percentile };
+
+        let values = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+
+        assert_eq!(mean(&values), 5.0);
+        assert_eq!(variance(&values), 4.0);
+        assert_eq!(percentile(&values, 0.0), 2.0);
+        assert_eq!(percentile(&values, 50.0), 4.5);
+        assert_eq!(percentile(&values, 100.0), 9.0);
+        assert_eq!(percentile(&[1.0, 2.0, 3.0, 4.0, 5.0], 25.0), 2.0);
+        assert_eq!(percentile(&[10.0, 20.0], 30.0), 13.0);
+        assert_eq!(percentile(&[3.0], 90.0), 3.0);
+    }
+
+    #[test]
+    fn statistics_functions() {
+        use error::BirlError;
+        use testing::run_program;
+
+        let (result, output) = run_program("FAZ UMA LISTA: NOTAS\n\
+                                            PÕE ISSO AQUI: NOTAS, 7\n\
+                                            PÕE ISSO AQUI: NOTAS, 3\n\
+                                            PÕE ISSO AQUI: NOTAS, 9.5\n\
+                                            PÕE ISSO AQUI: NOTAS, 5.5\n\
+                                            É HORA DO: MÉDIA, NOTAS\n\
+                                            CE QUER VER ISSO: TREZE\n\
+                                            É HORA DO: MEDIANA, NOTAS\n\
+                                            CE QUER VER ISSO: TREZE\n\
+                                            É HORA DO: VARIÂNCIA, NOTAS\n\
+                                            CE QUER VER ISSO: TREZE\n\
+                                            É HORA DO: DESVIO PADRÃO, NOTAS\n\
+                                            CE QUER VER ISSO: TREZE\n\
+                                            É HORA DO: PERCENTIL, NOTAS, 100\n\
+                                            CE QUER VER ISSO: TREZE");
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "6.25\n6.25\n5.5625\n2.358495283014151\n9.5\n");
+
+        let (result, _) = run_program("FAZ UMA LISTA: L\nÉ HORA DO: MEDIANA, L");
+        assert!(matches!(result, Err(BirlError::Runtime(ref message, _)) if message.contains("vazia")), "{:?}", result);
+
+        let (result, _) = run_program("FAZ UMA LISTA: L\nPÕE ISSO AQUI: L, 1\nPÕE ISSO AQUI: L, \"2\"\nÉ HORA DO: MÉDIA, L");
+        assert!(matches!(result, Err(BirlError::Runtime(ref message, _)) if message.contains("O elemento 1 da lista")),
+                "{:?}", result);
+
+        let (result, _) = run_program("FAZ UMA LISTA: L\nPÕE ISSO AQUI: L, 1\nÉ HORA DO: PERCENTIL, L, 101");
+        assert!(matches!(result, Err(BirlError::Runtime(ref message, _)) if message.contains("entre 0 e 100")),
+                "{:?}", result);
+    }
+}
@@ -0,0 +1,330 @@
+//! Module for a simple persistent store : a file with texts saved by key, so a program can remember things between
+//! runs without a database. The file is a JSON object (of texts only), and is replaced all at once on every change, like
+//! ESCREVE ARQUIVO does
+
+use parser::TypeKind;
+use vm::PluginFunction;
+
+mod plugins
+{
+    use vm::{ DynamicValue, SpecialItemData, VirtualMachine };
+    use diagnostic::push_json_string;
+    use standard_lib::files::replace_file;
+
+    use std::fs;
+    use std::io::ErrorKind;
+    use std::path::Path;
+    use std::str::Chars;
+    use std::iter::Peekable;
+
+    fn get_text(value : DynamicValue, vm : &VirtualMachine) -> Result<String, String> {
+        match value {
+            DynamicValue::Text(id) => match vm.get_special_storage_ref().get_data_ref(id) {
+                Some(SpecialItemData::Text(ref t)) => Ok(t.clone()),
+                _ => Err("Erro interno : Dado special com ID fornecido não é um texto".to_owned())
+            },
+            _ => unreachable!()
+        }
+    }
+
+    /// Read a JSON string, after the opening quote
+    fn read_string(chars : &mut Peekable<Chars>) -> Option<String> {
+        let mut result = String::new();
+
+        loop {
+            match chars.next()? {
+                '"' => return Some(result),
+                '\\' => match chars.next()? {
+                    '"' => result.push('"'),
+                    '\\' => result.push('\\'),
+                    '/' => result.push('/'),
+                    'b' => result.push('\u{8}'),
+                    'f' => result.push('\u{c}'),
+                    'n' => result.push('\n'),
+                    'r' => result.push('\r'),
+                    't' => result.push('\t'),
+                    'u' => {
+                        let high = read_hex(chars)?;
+
+                        // Characters outside the BMP are written as two escapes (a surrogate pair)
+                        let code = if (0xD800..0xDC00).contains(&high) {
+                            if chars.next()? != '\\' || chars.next()? != 'u' {
+                                return None;
+                            }
+
+                            let low = read_hex(chars)?;
+
+                            if ! (0xDC00..0xE000).contains(&low) {
+                                return None;
+                            }
+
+                            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+                        } else {
+                            high
+                        };
+
+                        result.push(::std::char::from_u32(code)?);
+                    }
+                    _ => return None,
+                },
+                c => result.push(c),
+            }
+        }
+    }
+
+    fn read_hex(chars : &mut Peekable<Chars>) -> Option<u32> {
+        let mut code = 0;
+
+        for _ in 0..4 {
+            code = code * 16 + chars.next()?.to_digit(16)?;
+        }
+
+        Some(code)
+    }
+
+    fn skip_spaces(chars : &mut Peekable<Chars>) {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    /// Read an object with only texts, keeping the order of the keys
+    fn parse_store(source : &str) -> Option<Vec<(String, String)>> {
+        let mut chars = source.chars().peekable();
+        let mut entries = vec![];
+
+        skip_spaces(&mut chars);
+
+        if chars.next()? != '{' {
+            return None;
+        }
+
+        skip_spaces(&mut chars);
+
+        if chars.peek() == Some(&'}') {
+            chars.next();
+        } else {
+            loop {
+                skip_spaces(&mut chars);
+
+                if chars.next()? != '"' {
+                    return None;
+                }
+
+                let key = read_string(&mut chars)?;
+
+                skip_spaces(&mut chars);
+
+                if chars.next()? != ':' {
+                    return None;
+                }
+
+                skip_spaces(&mut chars);
+
+                if chars.next()? != '"' {
+                    return None;
+                }
+
+                let value = read_string(&mut chars)?;
+
+                entries.retain(|entry : &(String, String)| entry.0 != key);
+                entries.push((key, value));
+
+                skip_spaces(&mut chars);
+
+                match chars.next()? {
+                    ',' => continue,
+                    '}' => break,
+                    _ => return None,
+                }
+            }
+        }
+
+        skip_spaces(&mut chars);
+
+        if chars.next().is_some() {
+            return None;
+        }
+
+        Some(entries)
+    }
+
+    /// The entries in the store at the path. A store that doesn't exist yet is empty
+    fn load(path : &str) -> Result<Vec<(String, String)>, String> {
+        let source = match fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(format!("Não deu pra ler o arquivo {} : {}", path, e)),
+        };
+
+        match parse_store(&source) {
+            Some(entries) => Ok(entries),
+            None => Err(format!("O arquivo {} não é um armazenamento válido", path)),
+        }
+    }
+
+    fn save(path : &str, entries : &[(String, String)]) -> Result<(), String> {
+        let mut json = String::from("{");
+
+        for (i, entry) in entries.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+
+            json.push_str("\n  ");
+            push_json_string(&mut json, &entry.0);
+            json.push_str(" : ");
+            push_json_string(&mut json, &entry.1);
+        }
+
+        json.push_str("\n}\n");
+
+        replace_file(Path::new(path), json.as_bytes())
+    }
+
+    /// Save a text with a key, replacing what was there before
+    /// Arguments : path : Text, key : Text, value : Text
+    pub fn set(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        // The arguments come in the reverse order
+        let value = get_text(arguments.remove(0), vm)?;
+        let key = get_text(arguments.remove(0), vm)?;
+        let path = get_text(arguments.remove(0), vm)?;
+
+        let mut entries = load(&path)?;
+
+        match entries.iter_mut().find(|entry| entry.0 == key) {
+            Some(entry) => entry.1 = value,
+            None => entries.push((key, value)),
+        }
+
+        save(&path, &entries)?;
+
+        Ok(None)
+    }
+
+    /// The text saved with a key, or Null if there's none
+    /// Arguments : path : Text, key : Text
+    pub fn get(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let key = get_text(arguments.remove(0), vm)?;
+        let path = get_text(arguments.remove(0), vm)?;
+
+        let value = load(&path)?.into_iter().find(|entry| entry.0 == key);
+
+        match value {
+            Some((_, v)) => {
+                let id = vm.get_special_storage_mut().add(SpecialItemData::Text(v), 0);
+
+                Ok(Some(DynamicValue::Text(id)))
+            }
+            None => Ok(Some(DynamicValue::Null)),
+        }
+    }
+
+    /// Remove a key, returning 1 if it was there and 0 if not
+    /// Arguments : path : Text, key : Text
+    pub fn delete(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let key = get_text(arguments.remove(0), vm)?;
+        let path = get_text(arguments.remove(0), vm)?;
+
+        let mut entries = load(&path)?;
+        let before = entries.len();
+
+        entries.retain(|entry| entry.0 != key);
+
+        if entries.len() == before {
+            return Ok(Some(DynamicValue::Integer(0)));
+        }
+
+        save(&path, &entries)?;
+
+        Ok(Some(DynamicValue::Integer(1)))
+    }
+
+    /// A list with the keys in the store, in the order they were first saved
+    /// Arguments : path : Text
+    pub fn keys(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let path = get_text(arguments.remove(0), vm)?;
+
+        let entries = load(&path)?;
+        let storage = vm.get_special_storage_mut();
+
+        // Each key is held by the list
+        let keys = entries.into_iter()
+            .map(|(k, _)| Box::new(DynamicValue::Text(storage.add(SpecialItemData::Text(k), 1))))
+            .collect::<Vec<Box<DynamicValue>>>();
+
+        let id = storage.add(SpecialItemData::List(keys), 0);
+
+        Ok(Some(DynamicValue::List(id)))
+    }
+}
+
+pub fn get_plugins() -> Vec<(String, Vec<TypeKind>, PluginFunction)>
+{
+    vec!
+    [
+        ("GUARDA".to_owned(), vec![TypeKind::Text, TypeKind::Text, TypeKind::Text], plugins::set),
+        ("LEMBRA".to_owned(), vec![TypeKind::Text, TypeKind::Text], plugins::get),
+        ("ESQUECE".to_owned(), vec![TypeKind::Text, TypeKind::Text], plugins::delete),
+        ("CHAVES GUARDADAS".to_owned(), vec![TypeKind::Text], plugins::keys),
+    ]
+}
+
+mod tests {
+    #[test]
+    fn key_value_store() {
+        use error::BirlError;
+        use testing::run_program;
+        use std::env;
+        use std::fs;
+        use std::process;
+
+        let path = env::temp_dir().join(format!("birl_armazenamento_{}.json", process::id()));
+        let _ = fs::remove_file(&path);
+
+        let first = format!("JAULA SHOW\n\
+                             VEM: A, \"{}\"\n\
+                             É HORA DO: GUARDA, A, \"TREINO\", \"SUPINO\"\n\
+                             É HORA DO: GUARDA, A, \"FRASE\", \"BIRL \\\"É\\\" 🏋\\n\"\n\
+                             É HORA DO: GUARDA, A, \"CARGA\", \"100\"\n\
+                             É HORA DO: GUARDA, A, \"TREINO\", \"AGACHAMENTO\"\n\
+                             É HORA DO: ESQUECE, A, \"CARGA\"\n\
+                             CE QUER VER ISSO: TREZE\n\
+                             É HORA DO: ESQUECE, A, \"CARGA\"\n\
+                             CE QUER VER ISSO: TREZE\n\
+                             SAINDO DA JAULA", path.display());
+
+        let (result, output) = run_program(&first);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "1\n0\n");
+
+        // Another run sees what the first one saved
+        let second = format!("JAULA SHOW\n\
+                              VEM: A, \"{}\"\n\
+                              É HORA DO: LEMBRA, A, \"TREINO\"\n\
+                              CE QUER VER ISSO: TREZE\n\
+                              É HORA DO: LEMBRA, A, \"FRASE\"\n\
+                              CE QUER VER ISSO: TREZE\n\
+                              É HORA DO: LEMBRA, A, \"CARGA\"\n\
+                              CE QUER VER ISSO: TREZE\n\
+                              É HORA DO: CHAVES GUARDADAS, A\n\
+                              CE QUER VER ISSO: TREZE\n\
+                              SAINDO DA JAULA", path.display());
+
+        let (result, output) = run_program(&second);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "AGACHAMENTO\nBIRL \"É\" 🏋\n\n<Null>\n(Lista) [ \"TREINO\", \"FRASE\" ]\n");
+
+        fs::write(&path, "{ \"TREINO\" : 1 }").unwrap();
+
+        let (result, _) = run_program(&second);
+        let _ = fs::remove_file(&path);
+
+        match result {
+            Ok(_) => panic!("Leu um armazenamento inválido"),
+            Err(BirlError::Runtime(message, _)) =>
+                assert!(message.contains("não é um armazenamento válido"), "{}", message),
+            Err(e) => panic!("{:?}", e),
+        }
+    }
+}
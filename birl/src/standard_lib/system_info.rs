@@ -0,0 +1,72 @@
+//! Module with information about the interpreter and where it's running, so programs can check if something is
+//! available before using it
+
+use std::env::consts;
+
+use parser::TypeKind;
+use vm::PluginFunction;
+use context::{ RawValue, enabled_features };
+
+mod plugins
+{
+    use vm::{ DynamicValue, SpecialItemData, VirtualMachine };
+    use context::enabled_features;
+
+    /// Returns 1 if the interpreter was built with the given feature, 0 if not
+    /// Arguments : feature : Text
+    pub fn has_feature(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let enabled = match arguments.remove(0) {
+            DynamicValue::Text(id) => {
+                match vm.get_special_storage_ref().get_data_ref(id) {
+                    Some(SpecialItemData::Text(name)) => enabled_features().iter().any(|f| f.eq_ignore_ascii_case(name)),
+                    Some(_) => return Err("Erro interno : Dado special não é um texto".to_owned()),
+                    None => return Err("ID special inválida".to_owned()),
+                }
+            }
+            _ => unreachable!()
+        };
+
+        Ok(Some(DynamicValue::Integer(if enabled { 1 } else { 0 })))
+    }
+}
+
+pub fn get_global_vars() -> Vec<(String, RawValue)> {
+    vec!
+    [
+        ("VERSÃO".to_owned(), RawValue::Text(env!("CARGO_PKG_VERSION").to_owned())),
+        ("PLATAFORMA".to_owned(), RawValue::Text(consts::OS.to_owned())),
+        ("ARQUITETURA".to_owned(), RawValue::Text(consts::ARCH.to_owned())),
+        ("RECURSOS".to_owned(), RawValue::Text(enabled_features().join(", "))),
+    ]
+}
+
+pub fn get_plugins() -> Vec<(String, Vec<TypeKind>, PluginFunction)>
+{
+    vec!
+    [
+        ("TEM RECURSO".to_owned(), vec![TypeKind::Text], plugins::has_feature),
+    ]
+}
+
+mod tests {
+    #[test]
+    fn system_info() {
+        use context::enabled_features;
+        use testing::run_program;
+
+        let src = "JAULA SHOW\n\
+                   CE QUER VER ISSO: VERSÃO\n\
+                   CE QUER VER ISSO: RECURSOS\n\
+                   É HORA DO: TEM RECURSO, \"i128\"\n\
+                   CE QUER VER ISSO: TREZE\n\
+                   É HORA DO: TEM RECURSO, \"teletransporte\"\n\
+                   CE QUER VER ISSO: TREZE\n\
+                   SAINDO DA JAULA";
+
+        let (result, output) = run_program(src);
+        let has_i128 = if cfg!(feature = "i128") { 1 } else { 0 };
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, format!("{}\n{}\n{}\n0\n", env!("CARGO_PKG_VERSION"), enabled_features().join(", "), has_i128));
+    }
+}
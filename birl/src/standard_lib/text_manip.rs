@@ -3,9 +3,119 @@
 use parser::TypeKind;
 use vm::PluginFunction;
 
+/// Whether the text matches the pattern, where `*` is any sequence of characters (including none), `?` is any single
+/// character and `[abc]` or `[a-z]` is one of the characters in the brackets (`[!abc]` for any other)
+pub fn glob_matches(pattern : &str, text : &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<char>>();
+    let text = text.chars().collect::<Vec<char>>();
+
+    let (mut p, mut t) = (0, 0);
+    // Where the last * is in the pattern, and where in the text what it matches ends. When the rest doesn't match, the
+    // * takes one more character and the rest is tried again from there
+    let mut star : Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() {
+            let matched = match pattern[p] {
+                '*' => {
+                    star = Some((p, t));
+                    p += 1;
+
+                    continue;
+                }
+                '?' => Some(p + 1),
+                '[' => match match_class(&pattern, p, text[t]) {
+                    Some((true, next)) => Some(next),
+                    Some((false, _)) => None,
+                    // Without the closing bracket, it's just a character
+                    None => if text[t] == '[' { Some(p + 1) } else { None },
+                },
+                c => if c == text[t] { Some(p + 1) } else { None },
+            };
+
+            if let Some(next) = matched {
+                p = next;
+                t += 1;
+
+                continue;
+            }
+        }
+
+        match star {
+            Some((star_p, star_t)) => {
+                p = star_p + 1;
+                t = star_t + 1;
+                star = Some((star_p, star_t + 1));
+            }
+            None => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Match a character against the class starting at `start` (a `[`), giving whether it matched and where the pattern
+/// continues after the class. None if the class isn't closed
+fn match_class(pattern : &[char], start : usize, c : char) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+    let negated = pattern.get(i) == Some(&'!');
+
+    if negated {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+
+    while i < pattern.len() {
+        // A ] right at the start is part of the class
+        if pattern[i] == ']' && ! first {
+            return Some((matched != negated, i + 1));
+        }
+
+        if i + 2 < pattern.len() && pattern[i + 1] == '-' && pattern[i + 2] != ']' {
+            matched |= pattern[i] <= c && c <= pattern[i + 2];
+            i += 3;
+        } else {
+            matched |= pattern[i] == c;
+            i += 1;
+        }
+
+        first = false;
+    }
+
+    None
+}
+
+/// The lines of the text that have `part` somewhere in them, without the line break
+fn lines_containing<'a>(text : &'a str, part : &str) -> Vec<&'a str> {
+    text.lines().filter(|line| line.contains(part)).collect()
+}
+
+/// Every piece of the text between a `start` and the first `end` after it. The search goes on after the `end`, so the
+/// pieces don't overlap, and a `start` without an `end` after it is ignored
+fn extract_between<'a>(text : &'a str, start : &str, end : &str) -> Vec<&'a str> {
+    let mut pieces = vec![];
+    let mut rest = text;
+
+    while let Some(s) = rest.find(start) {
+        rest = &rest[s + start.len()..];
+
+        match rest.find(end) {
+            Some(e) => {
+                pieces.push(&rest[..e]);
+                rest = &rest[e + end.len()..];
+            }
+            None => break,
+        }
+    }
+
+    pieces
+}
+
 mod plugins
 {
-    use vm::{ DynamicValue, SpecialItemData, VirtualMachine };
+    use vm::{ DynamicValue, SpecialItemData, VirtualMachine, DecimalSeparator, parse_number, push_number_with };
     use parser::IntegerType;
 
     /// Split an string into multiple parts based on another string
@@ -21,8 +131,8 @@ mod plugins
                             match vm.get_special_storage_ref().get_data_ref(id)
                                 {
                                     Some(data) => match data {
-                                        SpecialItemData::List(_) => unreachable!(),
                                         SpecialItemData::Text(s) => Ok(s),
+                                        _ => unreachable!(),
                                     }
                                     None => Err("Erro interno : Dado special com ID fornecido não existe".to_owned())
                                 }
@@ -41,7 +151,8 @@ mod plugins
         let result_id = {
             let storage = vm.get_special_storage_mut();
 
-            let elements = result.into_iter().map(|e| Box::new(DynamicValue::Text(storage.add(SpecialItemData::Text(e), 0u64)))).collect::<Vec<Box<DynamicValue>>>();
+            // Each part is held by the list
+            let elements = result.into_iter().map(|e| Box::new(DynamicValue::Text(storage.add(SpecialItemData::Text(e), 1u64)))).collect::<Vec<Box<DynamicValue>>>();
 
             storage.add(SpecialItemData::List(elements), 0u64)
         };
@@ -69,6 +180,129 @@ mod plugins
 
         Ok(Some(DynamicValue::Integer(length as IntegerType)))
     }
+
+    /// Check if a text matches a glob pattern (like `*.txt`), giving 1 if it does and 0 if not
+    /// Arguments : text : Text, pattern : Text
+    pub fn matches_pattern(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let matched = {
+            let mut get_str_arg = || {
+                match arguments.remove(0) {
+                    DynamicValue::Text(id) => match vm.get_special_storage_ref().get_data_ref(id) {
+                        Some(SpecialItemData::Text(s)) => Ok(s),
+                        _ => Err("Erro interno : Dado special com ID fornecido não é um texto".to_owned()),
+                    },
+                    _ => unreachable!()
+                }
+            };
+
+            let pattern = get_str_arg()?;
+            let text = get_str_arg()?;
+
+            super::glob_matches(pattern, text)
+        };
+
+        Ok(Some(DynamicValue::Integer(if matched { 1 } else { 0 })))
+    }
+
+    fn get_text(value : DynamicValue, vm : &VirtualMachine) -> Result<String, String> {
+        match value {
+            DynamicValue::Text(id) => match vm.get_special_storage_ref().get_data_ref(id) {
+                Some(SpecialItemData::Text(ref t)) => Ok(t.clone()),
+                _ => Err("Erro interno : Dado special com ID fornecido não é um texto".to_owned())
+            },
+            _ => unreachable!()
+        }
+    }
+
+    /// Make a new list with each text in it
+    fn make_text_list(texts : Vec<&str>, vm : &mut VirtualMachine) -> DynamicValue {
+        let storage = vm.get_special_storage_mut();
+
+        // Each text is held by the list
+        let elements = texts.into_iter()
+            .map(|t| Box::new(DynamicValue::Text(storage.add(SpecialItemData::Text(t.to_owned()), 1u64))))
+            .collect::<Vec<Box<DynamicValue>>>();
+
+        DynamicValue::List(storage.add(SpecialItemData::List(elements), 0u64))
+    }
+
+    /// Find the lines of a text that have another one in them, giving a list with them
+    /// Arguments : text : Text, part : Text
+    pub fn lines_containing(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let part = get_text(arguments.remove(0), vm)?;
+        let text = get_text(arguments.remove(0), vm)?;
+
+        let lines = super::lines_containing(&text, &part);
+
+        Ok(Some(make_text_list(lines, vm)))
+    }
+
+    /// Take every piece of a text that's between two delimiters, giving a list with them
+    /// Arguments : text : Text, start : Text, end : Text
+    pub fn extract_between(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let end = get_text(arguments.remove(0), vm)?;
+        let start = get_text(arguments.remove(0), vm)?;
+        let text = get_text(arguments.remove(0), vm)?;
+
+        if start.is_empty() || end.is_empty() {
+            return Err("Os delimitadores do PEGA ENTRE não podem ser vazios".to_owned());
+        }
+
+        let pieces = super::extract_between(&text, &start, &end);
+
+        Ok(Some(make_text_list(pieces, vm)))
+    }
+
+    /// Count how many times a text shows up in another, without overlapping
+    /// Arguments : text : Text, part : Text
+    pub fn count_occurrences(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let part = get_text(arguments.remove(0), vm)?;
+        let text = get_text(arguments.remove(0), vm)?;
+
+        if part.is_empty() {
+            return Err("Não dá pra contar um texto vazio".to_owned());
+        }
+
+        Ok(Some(DynamicValue::Integer(text.matches(part.as_str()).count() as IntegerType)))
+    }
+
+    /// The separator given as a text, "," or "."
+    fn get_separator(value : DynamicValue, vm : &VirtualMachine) -> Result<DecimalSeparator, String> {
+        match get_text(value, vm)?.as_str() {
+            "," => Ok(DecimalSeparator::Comma),
+            "." => Ok(DecimalSeparator::Point),
+            other => Err(format!("O separador decimal tem que ser \",\" ou \".\", mas foi \"{}\"", other)),
+        }
+    }
+
+    /// Read a number from a text with the given decimal separator, whatever the separator of the VM is
+    /// Arguments : text : Text, separator : Text
+    pub fn parse_number_with(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        // The arguments come in the reverse order
+        let separator = get_separator(arguments.remove(0), vm)?;
+        let text = get_text(arguments.remove(0), vm)?;
+
+        match parse_number(&text, separator) {
+            Some(n) => Ok(Some(DynamicValue::Number(n))),
+            None => Err(format!("Não foi possível converter \"{}\" pra Num", text)),
+        }
+    }
+
+    /// Write a number in a text with the given decimal separator, whatever the separator of the VM is
+    /// Arguments : number : Number, separator : Text
+    pub fn format_number_with(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let separator = get_separator(arguments.remove(0), vm)?;
+        let n = match arguments.remove(0) {
+            DynamicValue::Integer(i) => i as f64,
+            DynamicValue::Number(n) => n,
+            _ => unreachable!()
+        };
+
+        let mut text = String::new();
+        push_number_with(&mut text, n, separator);
+
+        Ok(Some(DynamicValue::Text(vm.get_special_storage_mut().add(SpecialItemData::Text(text), 0))))
+    }
 }
 
 pub fn get_plugins() -> Vec<(String, Vec<TypeKind>, PluginFunction)>
@@ -77,5 +311,64 @@ pub fn get_plugins() -> Vec<(String, Vec<TypeKind>, PluginFunction)>
     [
         ("DIVIDE TEXTO".to_owned(), vec![TypeKind::Text, TypeKind::Text], plugins::split_string),
         ("TAMANHO DO TEXTO".to_owned(), vec![TypeKind::Text], plugins::get_string_length),
+        ("COMBINA".to_owned(), vec![TypeKind::Text, TypeKind::Text], plugins::matches_pattern),
+        ("NÚMERO DO TEXTO".to_owned(), vec![TypeKind::Text, TypeKind::Text], plugins::parse_number_with),
+        ("TEXTO DO NÚMERO".to_owned(), vec![TypeKind::Number, TypeKind::Text], plugins::format_number_with),
+        ("LINHAS COM".to_owned(), vec![TypeKind::Text, TypeKind::Text], plugins::lines_containing),
+        ("PEGA ENTRE".to_owned(), vec![TypeKind::Text, TypeKind::Text, TypeKind::Text], plugins::extract_between),
+        ("CONTA NO TEXTO".to_owned(), vec![TypeKind::Text, TypeKind::Text], plugins::count_occurrences),
+    ]
+}
+
+/// Functions of this module that are also methods : (receiver, method, function)
+pub fn get_methods() -> Vec<(TypeKind, String, String)>
+{
+    vec!
+    [
+        (TypeKind::Text, "DIVIDE".to_owned(), "DIVIDE TEXTO".to_owned()),
+        (TypeKind::Text, "TAMANHO".to_owned(), "TAMANHO DO TEXTO".to_owned()),
+        (TypeKind::Text, "LINHAS".to_owned(), "LINHAS COM".to_owned()),
+        (TypeKind::Text, "ENTRE".to_owned(), "PEGA ENTRE".to_owned()),
+        (TypeKind::Text, "CONTA".to_owned(), "CONTA NO TEXTO".to_owned()),
     ]
+}
+
+mod tests {
+    #[test]
+    fn glob_patterns() {
+        use standard_lib::text_manip::glob_matches;
+
+        assert!(glob_matches("*.txt", "notas.txt"));
+        assert!(glob_matches("*.txt", ".txt"));
+        assert!(! glob_matches("*.txt", "notas.txt.bak"));
+        assert!(glob_matches("a*b*c", "aXXbYYbZc"));
+        assert!(! glob_matches("a*b*c", "aXXbYY"));
+        assert!(glob_matches("BIRL?", "BIRL!"));
+        assert!(! glob_matches("BIRL?", "BIRL"));
+        assert!(glob_matches("log-[0-9][0-9].txt", "log-42.txt"));
+        assert!(! glob_matches("log-[0-9].txt", "log-x.txt"));
+        assert!(glob_matches("[!a]*", "BAMBAM"));
+        assert!(! glob_matches("[!B]*", "BAMBAM"));
+        assert!(glob_matches("[]]", "]"));
+        assert!(glob_matches("[aberto", "[aberto"));
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("FRANGÃO*", "FRANGÃO!"));
+    }
+
+    #[test]
+    fn search_and_extract() {
+        use standard_lib::text_manip::{ lines_containing, extract_between };
+
+        let log = "INFO inicio\nERRO [disco] cheio\nINFO meio\r\nERRO [rede] caiu\n";
+
+        assert_eq!(lines_containing(log, "ERRO"), vec!["ERRO [disco] cheio", "ERRO [rede] caiu"]);
+        assert_eq!(lines_containing(log, "INFO"), vec!["INFO inicio", "INFO meio"]);
+        assert!(lines_containing(log, "AVISO").is_empty());
+
+        assert_eq!(extract_between(log, "[", "]"), vec!["disco", "rede"]);
+        assert_eq!(extract_between("<a><b>", "<", ">"), vec!["a", "b"]);
+        assert_eq!(extract_between("x=1; y=2; z=", "=", ";"), vec!["1", "2"]);
+        assert_eq!(extract_between("(((a)", "(", ")"), vec!["((a"]);
+        assert!(extract_between("[sem fim", "[", "]").is_empty());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,218 @@
+//! Module with the width texts take in a terminal, for lining up tables and columns. An accent over a letter takes no
+//! space, while Chinese, Japanese and Korean characters and most emoji take two columns. The ranges below are the wide
+//! and fullwidth blocks of East Asian Width and the emoji with emoji presentation, written by hand, so they cover the
+//! common cases and not every character
+
+use parser::TypeKind;
+use vm::PluginFunction;
+
+const ZERO_WIDTH_JOINER : char = '\u{200D}';
+const EMOJI_PRESENTATION : char = '\u{FE0F}';
+
+/// Characters that take no space : accents and other combining marks, invisible formatting and control characters
+fn is_zero_width(c : char) -> bool {
+    match c {
+        '\u{0}'..='\u{1F}' | '\u{7F}'..='\u{9F}' => true,
+        '\u{300}'..='\u{36F}' | '\u{483}'..='\u{489}' | '\u{591}'..='\u{5BD}' | '\u{610}'..='\u{61A}' |
+        '\u{64B}'..='\u{65F}' | '\u{E31}' | '\u{E34}'..='\u{E3A}' | '\u{E47}'..='\u{E4E}' |
+        '\u{1AB0}'..='\u{1AFF}' | '\u{1DC0}'..='\u{1DFF}' | '\u{20D0}'..='\u{20FF}' | '\u{FE20}'..='\u{FE2F}' => true,
+        '\u{200B}'..='\u{200F}' | '\u{2028}'..='\u{202E}' | '\u{2060}'..='\u{2064}' | '\u{FEFF}' => true,
+        // Variation selectors and the tags of subdivision flags
+        '\u{FE00}'..='\u{FE0F}' | '\u{E0020}'..='\u{E007F}' | '\u{E0100}'..='\u{E01EF}' => true,
+        // The second part of a Hangul syllable written in jamo
+        '\u{1160}'..='\u{11FF}' => true,
+        _ => false,
+    }
+}
+
+/// Characters that take two columns
+fn is_wide(c : char) -> bool {
+    match c {
+        '\u{1100}'..='\u{115F}' | '\u{2E80}'..='\u{303E}' | '\u{3041}'..='\u{33FF}' | '\u{3400}'..='\u{4DBF}' |
+        '\u{4E00}'..='\u{9FFF}' | '\u{A000}'..='\u{A4CF}' | '\u{A960}'..='\u{A97F}' | '\u{AC00}'..='\u{D7A3}' |
+        '\u{F900}'..='\u{FAFF}' | '\u{FE30}'..='\u{FE4F}' | '\u{FF00}'..='\u{FF60}' | '\u{FFE0}'..='\u{FFE6}' |
+        '\u{20000}'..='\u{2FFFD}' | '\u{30000}'..='\u{3FFFD}' => true,
+        // Emoji that are shown as emoji even without U+FE0F after them
+        '\u{231A}'..='\u{231B}' | '\u{23E9}'..='\u{23EC}' | '\u{23F0}' | '\u{23F3}' | '\u{25FD}'..='\u{25FE}' |
+        '\u{2614}'..='\u{2615}' | '\u{2648}'..='\u{2653}' | '\u{267F}' | '\u{2693}' | '\u{26A1}' |
+        '\u{26AA}'..='\u{26AB}' | '\u{26BD}'..='\u{26BE}' | '\u{26C4}'..='\u{26C5}' | '\u{26CE}' | '\u{26D4}' |
+        '\u{26EA}' | '\u{26F2}'..='\u{26F3}' | '\u{26F5}' | '\u{26FA}' | '\u{26FD}' | '\u{2705}' |
+        '\u{270A}'..='\u{270B}' | '\u{2728}' | '\u{274C}' | '\u{274E}' | '\u{2753}'..='\u{2755}' | '\u{2757}' |
+        '\u{2795}'..='\u{2797}' | '\u{27B0}' | '\u{27BF}' | '\u{2B1B}'..='\u{2B1C}' | '\u{2B50}' | '\u{2B55}' |
+        '\u{1F004}' | '\u{1F0CF}' | '\u{1F18E}' | '\u{1F191}'..='\u{1F19A}' | '\u{1F200}'..='\u{1F2FF}' |
+        '\u{1F300}'..='\u{1F64F}' | '\u{1F680}'..='\u{1F6FF}' | '\u{1F7E0}'..='\u{1F7EB}' |
+        '\u{1F90C}'..='\u{1F9FF}' | '\u{1FA70}'..='\u{1FAFF}' => true,
+        _ => false,
+    }
+}
+
+fn is_regional_indicator(c : char) -> bool {
+    ('\u{1F1E6}'..='\u{1F1FF}').contains(&c)
+}
+
+fn is_skin_tone(c : char) -> bool {
+    ('\u{1F3FB}'..='\u{1F3FF}').contains(&c)
+}
+
+/// How many columns the text takes in a terminal. Everything that is shown as a single character counts once : a letter
+/// with its accents, an emoji joined to others (like a family), a flag (two regional indicators) and an emoji with a
+/// skin tone. A character followed by U+FE0F is shown as an emoji, taking two columns
+pub fn display_width(text : &str) -> usize {
+    let mut width = 0;
+    // Width of the last character that took space, which is what U+FE0F and skin tones change
+    let mut last = 0;
+    let mut joined = false;
+    let mut half_flag = false;
+
+    for c in text.chars() {
+        if c == ZERO_WIDTH_JOINER {
+            joined = true;
+
+            continue;
+        }
+
+        // What comes after a joiner is drawn together with what came before it
+        if joined {
+            joined = false;
+
+            if ! is_zero_width(c) {
+                continue;
+            }
+        }
+
+        if is_regional_indicator(c) {
+            // The first of a pair takes the two columns of the flag
+            if ! half_flag {
+                width += 2;
+                last = 2;
+            }
+
+            half_flag = ! half_flag;
+
+            continue;
+        }
+
+        half_flag = false;
+
+        if c == EMOJI_PRESENTATION && last == 1 {
+            width += 1;
+            last = 2;
+        } else if is_skin_tone(c) && last == 2 {
+            // Changes the emoji before it
+        } else if is_wide(c) {
+            width += 2;
+            last = 2;
+        } else if ! is_zero_width(c) {
+            width += 1;
+            last = 1;
+        }
+    }
+
+    width
+}
+
+/// Fill the text with spaces until it takes `width` columns, on the right if `left` (lining it up on the left) or on the
+/// left otherwise. A text that's already as wide is kept as it is
+pub fn align(text : &str, width : usize, left : bool) -> String {
+    let padding = " ".repeat(width.saturating_sub(display_width(text)));
+
+    if left {
+        format!("{}{}", text, padding)
+    } else {
+        format!("{}{}", padding, text)
+    }
+}
+
+mod plugins
+{
+    use vm::{ DynamicValue, SpecialItemData, VirtualMachine };
+    use parser::IntegerType;
+
+    fn get_text(value : DynamicValue, vm : &VirtualMachine) -> Result<String, String> {
+        match value {
+            DynamicValue::Text(id) => match vm.get_special_storage_ref().get_data_ref(id) {
+                Some(SpecialItemData::Text(ref t)) => Ok(t.clone()),
+                _ => Err("Erro interno : Dado special com ID fornecido não é um texto".to_owned())
+            },
+            _ => unreachable!()
+        }
+    }
+
+    /// How many columns the text takes in a terminal
+    /// Arguments : text : Text
+    pub fn display_width(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        let text = get_text(arguments.remove(0), vm)?;
+
+        Ok(Some(DynamicValue::Integer(super::display_width(&text) as IntegerType)))
+    }
+
+    fn align(mut arguments : Vec<DynamicValue>, vm : &mut VirtualMachine, left : bool) -> Result<Option<DynamicValue>, String> {
+        // The arguments come in the reverse order
+        let width = match arguments.remove(0) {
+            DynamicValue::Integer(i) if i >= 0 => i as usize,
+            DynamicValue::Integer(i) => return Err(format!("A largura pra alinhar não pode ser negativa, mas foi {}", i)),
+            _ => unreachable!()
+        };
+
+        let text = get_text(arguments.remove(0), vm)?;
+        let result = super::align(&text, width, left);
+
+        Ok(Some(DynamicValue::Text(vm.get_special_storage_mut().add(SpecialItemData::Text(result), 0))))
+    }
+
+    /// Arguments : text : Text, width : Integer
+    pub fn align_left(arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        align(arguments, vm, true)
+    }
+
+    /// Arguments : text : Text, width : Integer
+    pub fn align_right(arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+        align(arguments, vm, false)
+    }
+}
+
+pub fn get_plugins() -> Vec<(String, Vec<TypeKind>, PluginFunction)>
+{
+    vec!
+    [
+        ("LARGURA DO TEXTO".to_owned(), vec![TypeKind::Text], plugins::display_width),
+        ("ALINHA À ESQUERDA".to_owned(), vec![TypeKind::Text, TypeKind::Integer], plugins::align_left),
+        ("ALINHA À DIREITA".to_owned(), vec![TypeKind::Text, TypeKind::Integer], plugins::align_right),
+    ]
+}
+
+/// Functions of this module that are also methods : (receiver, method, function)
+pub fn get_methods() -> Vec<(TypeKind, String, String)>
+{
+    vec!
+    [
+        (TypeKind::Text, "LARGURA".to_owned(), "LARGURA DO TEXTO".to_owned()),
+    ]
+}
+
+mod tests {
+    #[test]
+    fn display_width() {
+        use standard_lib::width::{ display_width, align };
+
+        assert_eq!(display_width("BIRL"), 4);
+        assert_eq!(display_width("Ação"), 4);
+        // The same, with the accents as separate characters
+        assert_eq!(display_width("Ac\u{327}a\u{303}o"), 4);
+        assert_eq!(display_width("日本語"), 6);
+        assert_eq!(display_width("ＢＩＲＬ"), 8);
+        assert_eq!(display_width("💪"), 2);
+        assert_eq!(display_width("💪🏽"), 2);
+        // A family is a man, a woman and a girl joined together
+        assert_eq!(display_width("👨\u{200D}👩\u{200D}👧"), 2);
+        assert_eq!(display_width("🇧🇷🇵🇹"), 4);
+        assert_eq!(display_width("❤"), 1);
+        assert_eq!(display_width("❤\u{FE0F}"), 2);
+        assert_eq!(display_width("\u{200B}"), 0);
+        assert_eq!(display_width(""), 0);
+
+        assert_eq!(align("Ação", 6, true), "Ação  ");
+        assert_eq!(align("💪", 4, false), "  💪");
+        assert_eq!(align("BAMBAM", 3, true), "BAMBAM");
+    }
+}
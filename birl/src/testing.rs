@@ -0,0 +1,359 @@
+//! Support for tests written in BIRL : functions whose name starts with TESTE (or marked with `FICHA: TESTE`) are run by
+//! `birl test`, and the TEM QUE SER command checks a value. When a check fails, what was expected and what was found are
+//! kept in a structured form, so the runner can show the difference between them instead of just a message
+
+use std::fmt;
+use std::fs;
+use std::path::{ Path, PathBuf };
+
+use context::{ Context, BIRL_GLOBAL_FUNCTION_ID };
+use compiler::{ FunctionSymbol, FunctionKind };
+#[cfg(test)]
+use error::BirlError;
+use parser::Annotation;
+use standard_lib::module_deterministic_standard_library;
+use vm::VirtualMachineBuilder;
+use vm_io::CaptureIo;
+
+/// Functions with a name starting with this are tests
+pub const TEST_FUNCTION_PREFIX : &str = "TESTE";
+
+pub fn is_test_function(name : &str) -> bool {
+    name.starts_with(TEST_FUNCTION_PREFIX)
+}
+
+/// Whether `birl test` runs the function : by the name or by a `FICHA: TESTE` above it
+pub fn is_test(function : &FunctionSymbol) -> bool {
+    function.kind == FunctionKind::Source && (is_test_function(&function.name) || function.has_annotation(Annotation::Test))
+}
+
+/// Where the snapshot of a test's output is kept : in a folder named after the source file, next to it, like
+/// `exemplos/contas.snapshots/TESTE_SOMA.snap` for the test TESTE SOMA in `exemplos/contas.birl`
+pub fn snapshot_path(source_file : &Path, test_name : &str) -> PathBuf {
+    let stem = source_file.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let folder = source_file.with_file_name(format!("{}.snapshots", stem));
+
+    folder.join(format!("{}.snap", test_name.replace(' ', "_")))
+}
+
+/// Compare the output of a test with its snapshot. Returns the difference, line by line, if they aren't the same
+pub fn compare_snapshot(snapshot : &str, output : &str) -> Option<Vec<DiffLine>> {
+    if snapshot == output {
+        return None;
+    }
+
+    let expected = snapshot.lines().map(|l| l.to_owned()).collect::<Vec<String>>();
+    let actual = output.lines().map(|l| l.to_owned()).collect::<Vec<String>>();
+
+    Some(diff(&expected, &actual))
+}
+
+/// Extension of the file with what an example has to print, next to it : `exemplos/fatorial.saida` for
+/// `exemplos/fatorial.birl`
+pub const EXPECTED_OUTPUT_EXTENSION : &str = "saida";
+
+/// Extension of the file with the input given to an example, if it reads any : `exemplos/fatorial.entrada`
+pub const SCRIPTED_INPUT_EXTENSION : &str = "entrada";
+
+/// Seed of the random numbers of the examples, so they print the same thing every time
+const EXAMPLE_RANDOM_SEED : u64 = 13;
+
+/// How running an example compared with what it had to print
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExampleOutcome {
+    Passed,
+    /// The output was different. Has what was printed and the difference to what was expected, line by line
+    Differs { output : String, diff : Vec<DiffLine> },
+    /// There's no file with the expected output. Has what was printed, to be saved as the expected output
+    NoExpectedOutput(String),
+    /// The example didn't compile or stopped with an error
+    Failed(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExampleResult {
+    pub file : PathBuf,
+    pub outcome : ExampleOutcome,
+}
+
+/// Run a file with the given input and give back everything it printed. The standard library is the deterministic one
+/// and the random numbers always start from the same seed, so the output only changes when the language does
+pub fn run_example(source_file : &Path, input : &str) -> Result<String, String> {
+    let io = CaptureIo::new(input);
+    let output = io.output();
+
+    let mut ctx = Context::with_vm(VirtualMachineBuilder::new().random_seed(Some(EXAMPLE_RANDOM_SEED)))?;
+    let _ = ctx.set_io(Box::new(io));
+
+    ctx.call_function_by_id(BIRL_GLOBAL_FUNCTION_ID, vec![])?;
+    ctx.add_module(module_deterministic_standard_library())?;
+    ctx.add_file(&source_file.to_string_lossy())?;
+    ctx.start_program()?;
+
+    drop(ctx);
+
+    let printed = output.borrow().clone();
+
+    Ok(printed)
+}
+
+/// Run a file with the input in its `.entrada` and compare what it printed with its `.saida`
+pub fn check_example(source_file : &Path) -> ExampleResult {
+    let input = fs::read_to_string(source_file.with_extension(SCRIPTED_INPUT_EXTENSION)).unwrap_or_default();
+
+    let outcome = match run_example(source_file, &input) {
+        Err(e) => ExampleOutcome::Failed(e),
+        Ok(output) => match fs::read_to_string(source_file.with_extension(EXPECTED_OUTPUT_EXTENSION)) {
+            Err(_) => ExampleOutcome::NoExpectedOutput(output),
+            Ok(expected) => match compare_snapshot(&expected, &output) {
+                None => ExampleOutcome::Passed,
+                Some(diff) => ExampleOutcome::Differs { output, diff },
+            },
+        },
+    };
+
+    ExampleResult { file : source_file.to_owned(), outcome }
+}
+
+/// Check every `.birl` file in the folder, in the order of their names
+pub fn check_examples(folder : &Path) -> Result<Vec<ExampleResult>, String> {
+    let entries = fs::read_dir(folder).map_err(|e| format!("Não deu pra ler a pasta {} : {}", folder.display(), e))?;
+
+    let mut files = entries.filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().map(|e| e == "birl").unwrap_or(false))
+        .collect::<Vec<PathBuf>>();
+
+    files.sort();
+
+    Ok(files.iter().map(|file| check_example(file)).collect())
+}
+
+/// Run a program in the context, with the standard library, and give back how it ended and everything it printed. For
+/// the tests of this crate, so each of them doesn't have to set up the context again
+#[cfg(test)]
+pub fn run_in_context(ctx : &mut Context, src : &str) -> (Result<(), BirlError>, String) {
+    let io = CaptureIo::new("");
+    let output = io.output();
+
+    let _ = ctx.set_io(Box::new(io));
+
+    let result = ctx.call_function_by_id(BIRL_GLOBAL_FUNCTION_ID, vec![])
+        .and_then(|_| ctx.add_standard_library())
+        .and_then(|_| ctx.add_source_string(src.to_owned()))
+        .and_then(|_| ctx.start_program());
+
+    let printed = output.borrow().clone();
+
+    (result, printed)
+}
+
+/// Run a program in a new context. See `run_in_context`
+#[cfg(test)]
+pub fn run_program(src : &str) -> (Result<(), BirlError>, String) {
+    run_in_context(&mut Context::new(), src)
+}
+
+/// A value that was checked, already converted to text
+#[derive(Debug, Clone, PartialEq)]
+pub enum AssertedValue {
+    Text(String),
+    /// The elements of a list, each as they'd be shown inside the list (texts with quotes)
+    List(Vec<String>),
+    /// Anything else (numbers, null), as it would be printed
+    Other(String),
+}
+
+impl fmt::Display for AssertedValue {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AssertedValue::Text(ref t) => write!(f, "\"{}\"", t),
+            AssertedValue::List(ref elements) => write!(f, "[ {} ]", elements.join(", ")),
+            AssertedValue::Other(ref s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// A failed TEM QUE SER
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssertionFailure {
+    pub expected : AssertedValue,
+    pub actual : AssertedValue,
+}
+
+impl AssertionFailure {
+    pub fn message(&self) -> String {
+        format!("Era esperado {}, mas veio {}", self.expected, self.actual)
+    }
+
+    /// The difference between the values, line by line for texts with more than one line and element by element for
+    /// lists. Other values are short enough to just be shown side by side, so they have no diff
+    pub fn diff(&self) -> Option<Vec<DiffLine>> {
+        match (&self.expected, &self.actual) {
+            (AssertedValue::Text(expected), AssertedValue::Text(actual))
+                if expected.contains('\n') || actual.contains('\n') => {
+                let expected = expected.lines().map(|l| l.to_owned()).collect::<Vec<String>>();
+                let actual = actual.lines().map(|l| l.to_owned()).collect::<Vec<String>>();
+
+                Some(diff(&expected, &actual))
+            }
+            (AssertedValue::List(expected), AssertedValue::List(actual)) => Some(diff(expected, actual)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiffLine {
+    /// In both sides
+    Same(String),
+    /// Only in what was expected
+    Expected(String),
+    /// Only in what was found
+    Actual(String),
+}
+
+/// Difference between two sequences of lines, keeping as many lines in common as possible. When a line was changed,
+/// the expected version comes before the actual one
+pub fn diff(expected : &[String], actual : &[String]) -> Vec<DiffLine> {
+    // common[i][j] is the size of the longest common subsequence of expected[i..] and actual[j..]
+    let mut common = vec![vec![0usize; actual.len() + 1]; expected.len() + 1];
+
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            common[i][j] = if expected[i] == actual[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut result = vec![];
+    let (mut i, mut j) = (0, 0);
+
+    while i < expected.len() && j < actual.len() {
+        if expected[i] == actual[j] {
+            result.push(DiffLine::Same(expected[i].clone()));
+            i += 1;
+            j += 1;
+        } else if common[i + 1][j] >= common[i][j + 1] {
+            result.push(DiffLine::Expected(expected[i].clone()));
+            i += 1;
+        } else {
+            result.push(DiffLine::Actual(actual[j].clone()));
+            j += 1;
+        }
+    }
+
+    result.extend(expected[i..].iter().map(|l| DiffLine::Expected(l.clone())));
+    result.extend(actual[j..].iter().map(|l| DiffLine::Actual(l.clone())));
+
+    result
+}
+
+mod tests {
+    #[test]
+    fn assertions() {
+        use context::Context;
+        use testing::{ AssertedValue, DiffLine };
+
+        let run = |src : &str| {
+            let mut ctx = Context::new();
+
+            if let Err(e) = ctx.add_source_string(src.to_owned()) {
+                panic!("{}", e);
+            }
+
+            if let Err(e) = ctx.call_function_by_id(0, vec![]) {
+                panic!("{}", e);
+            }
+
+            let result = ctx.start_program();
+
+            (result, ctx.take_assertion_failure())
+        };
+
+        let (result, failure) = run("JAULA SHOW\nTEM QUE SER: 2 + 2, 4\nTEM QUE SER: \"BIRL\", \"BI\" + \"RL\"\nSAINDO DA JAULA");
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(failure, None);
+
+        let (result, failure) = run("JAULA SHOW\nTEM QUE SER: \"A\\nB\\nC\", \"A\\nX\\nC\"\nSAINDO DA JAULA");
+        let failure = failure.expect("A conferência devia ter falhado");
+
+        assert!(result.is_err());
+        assert_eq!(failure.actual, AssertedValue::Text("A\nB\nC".to_owned()));
+        assert_eq!(failure.diff(), Some(vec![DiffLine::Same("A".to_owned()), DiffLine::Expected("X".to_owned()),
+                                             DiffLine::Actual("B".to_owned()), DiffLine::Same("C".to_owned())]));
+
+        let src = "JAULA SHOW\n\
+                   FAZ UMA LISTA: L\n\
+                   PÕE ISSO AQUI: L, 1\n\
+                   PÕE ISSO AQUI: L, \"DOIS\"\n\
+                   FAZ UMA LISTA: M\n\
+                   PÕE ISSO AQUI: M, \"DOIS\"\n\
+                   TEM QUE SER: L, M\n\
+                   SAINDO DA JAULA";
+
+        let (_, failure) = run(src);
+        let failure = failure.expect("A conferência devia ter falhado");
+
+        assert_eq!(failure.expected, AssertedValue::List(vec!["\"DOIS\"".to_owned()]));
+        assert_eq!(failure.diff(), Some(vec![DiffLine::Actual("1".to_owned()), DiffLine::Same("\"DOIS\"".to_owned())]));
+
+        let (_, failure) = run("JAULA SHOW\nTEM QUE SER: 1, 2\nSAINDO DA JAULA");
+        let failure = failure.expect("A conferência devia ter falhado");
+
+        assert_eq!(failure.message(), "Era esperado 2, mas veio 1");
+        assert_eq!(failure.diff(), None);
+    }
+
+    #[test]
+    fn snapshots() {
+        use testing::{ snapshot_path, compare_snapshot, DiffLine };
+        use std::path::Path;
+
+        assert_eq!(snapshot_path(Path::new("exemplos/contas.birl"), "TESTE SOMA"),
+                   Path::new("exemplos/contas.snapshots/TESTE_SOMA.snap"));
+
+        assert_eq!(compare_snapshot("1\n2\n", "1\n2\n"), None);
+        assert_eq!(compare_snapshot("1\n2\n", "1\n3\n"),
+                   Some(vec![DiffLine::Same("1".to_owned()), DiffLine::Expected("2".to_owned()), DiffLine::Actual("3".to_owned())]));
+    }
+
+    #[test]
+    fn examples_with_input() {
+        use testing::{ check_example, check_examples, ExampleOutcome, DiffLine };
+        use std::env;
+        use std::fs;
+
+        let folder = env::temp_dir().join(format!("birl_exemplos_{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+
+        let file = folder.join("oi.birl");
+        fs::write(&file, "FALA AÍ: NOME\nCE QUER VER ISSO: \"BORA, \" + NOME + \", \" + CUMPADE\n").unwrap();
+        fs::write(folder.join("oi.entrada"), "BAMBAM\n").unwrap();
+
+        match check_example(&file).outcome {
+            ExampleOutcome::NoExpectedOutput(output) => assert_eq!(output, "BORA, BAMBAM, CUMPADE\n"),
+            outcome => panic!("Era esperado que não tivesse saída, recebido {:?}", outcome),
+        }
+
+        fs::write(folder.join("oi.saida"), "BORA, BAMBAM, CUMPADE\n").unwrap();
+        assert_eq!(check_example(&file).outcome, ExampleOutcome::Passed);
+
+        fs::write(folder.join("oi.saida"), "BORA, BIRL, CUMPADE\n").unwrap();
+        assert_eq!(check_example(&file).outcome, ExampleOutcome::Differs {
+            output : "BORA, BAMBAM, CUMPADE\n".to_owned(),
+            diff : vec![DiffLine::Expected("BORA, BIRL, CUMPADE".to_owned()), DiffLine::Actual("BORA, BAMBAM, CUMPADE".to_owned())],
+        });
+
+        fs::write(folder.join("erro.birl"), "CE QUER VER ISSO: NADA\n").unwrap();
+
+        let results = check_examples(&folder).unwrap();
+        let _ = fs::remove_dir_all(&folder);
+
+        assert_eq!(results.len(), 2);
+        assert!(matches!(results[0].outcome, ExampleOutcome::Failed(_)));
+    }
+}
@@ -1,11 +1,36 @@
 use parser::{ TypeKind, IntegerType };
 use context::RawValue;
 
-use std::io::{ Write, BufRead };
+use vm_io::{ VmIo, NullIo };
+use testing::{ AssertedValue, AssertionFailure };
+use iteration::{ ValueIterator, iterate };
+use error::{ BirlError, ErrorLocation, TraceEntry };
 use std::fmt::{ Display, self };
+use std::collections::{ HashMap, HashSet, VecDeque };
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::BufWriter;
 
 const STACK_DEFAULT_SIZE : usize = 128;
-
+/// Default maximum size of a single text, in bytes (64 MiB)
+const MAX_TEXT_SIZE_DEFAULT : usize = 64 * 1024 * 1024;
+/// Default maximum number of elements in a single list
+const MAX_LIST_SIZE_DEFAULT : usize = 8 * 1024 * 1024;
+/// Default number of items alive that makes the garbage collector run
+const GC_THRESHOLD_DEFAULT : usize = 100_000;
+/// Default maximum number of functions running at the same time (frames in the callstack, the global code included)
+const MAX_CALL_DEPTH_DEFAULT : usize = 10_000;
+
+/// Version of the instruction set. Compiled code (like .birlc files or code given through the C API) is stamped with it
+/// and can only be loaded by a VM with the same version, so this must be incremented every time an instruction is added,
+/// removed or changes meaning
+pub const INSTRUCTION_SET_VERSION : u32 = 15;
+
+/// A function implemented in Rust, callable from BIRL. Texts and lists in the arguments are only guaranteed to exist
+/// until the plugin returns; to keep one for later, use `vm.retain`, which gives a SpecialRef that keeps it alive
 pub type PluginFunction = fn (arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String>;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -41,13 +66,55 @@ pub enum DynamicValue {
     Number(f64),
     Text(u64),
     List(u64),
+    /// A lazy sequence of values, computed as it's gone through
+    Sequence(u64),
+    /// A file open for writing
+    File(u64),
+    /// An open SQLite database
+    Database(u64),
+    Bool(bool),
+    /// Values by text keys
+    Map(u64),
     Null,
 }
 
+impl DynamicValue {
+    /// The ID of the item in the special storage, for the values that are kept there
+    pub fn special_id(&self) -> Option<u64> {
+        match *self {
+            DynamicValue::Text(id) | DynamicValue::List(id) | DynamicValue::Sequence(id) | DynamicValue::File(id) | DynamicValue::Database(id) | DynamicValue::Map(id) => Some(id),
+            _ => None,
+        }
+    }
+
+    pub fn get_kind(&self) -> TypeKind {
+        match *self {
+            DynamicValue::Integer(_) => TypeKind::Integer,
+            DynamicValue::Number(_) => TypeKind::Number,
+            DynamicValue::Text(_) => TypeKind::Text,
+            DynamicValue::List(_) => TypeKind::List,
+            DynamicValue::Sequence(_) => TypeKind::Sequence,
+            DynamicValue::File(_) => TypeKind::File,
+            DynamicValue::Database(_) => TypeKind::Database,
+            DynamicValue::Bool(_) => TypeKind::Bool,
+            DynamicValue::Map(_) => TypeKind::Map,
+            DynamicValue::Null => TypeKind::Null,
+        }
+    }
+}
+
+#[doc(hidden)]
 #[derive(Debug)]
 pub enum SpecialItemData {
     Text(String),
-    List(Vec<Box<DynamicValue>>)
+    List(Vec<Box<DynamicValue>>),
+    /// The iterator that computes the values of a sequence. It's only None while the sequence is being advanced
+    Sequence(Option<Box<dyn ValueIterator>>),
+    /// What's written goes to the buffer first, and to the file when it's flushed or freed
+    File(BufWriter<File>),
+    #[cfg(feature = "sqlite")]
+    Database(::rusqlite::Connection),
+    Map(HashMap<String, DynamicValue>),
 }
 
 impl SpecialItemData {
@@ -78,8 +145,25 @@ impl SpecialItemData {
             _ => None
         }
     }
+
+    pub fn try_into_map(&self) -> Option<&HashMap<String, DynamicValue>> {
+        match *self {
+            SpecialItemData::Map(ref m) => Some(m),
+            _ => None
+        }
+    }
+
+    /// The values that this item holds a reference to : the elements of a list or the values of a map
+    fn held_values(&self) -> Vec<DynamicValue> {
+        match *self {
+            SpecialItemData::List(ref l) => l.iter().map(|v| **v).collect(),
+            SpecialItemData::Map(ref m) => m.values().cloned().collect(),
+            _ => vec![],
+        }
+    }
 }
 
+#[doc(hidden)]
 #[derive(Debug)]
 pub struct SpecialItem {
     data : SpecialItemData,
@@ -87,20 +171,103 @@ pub struct SpecialItem {
     ref_count : u64,
 }
 
+#[doc(hidden)]
 #[derive(Debug)]
 pub struct SpecialStorage {
-    items : Vec<SpecialItem>,
+    /// By ID, so finding an item doesn't depend on how many others are alive
+    items : HashMap<u64, SpecialItem>,
     next_item_id : u64,
+    /// In arena mode, items are only added and never freed (until the storage is dropped), so there's no reference
+    /// counting
+    arena : bool,
+    /// Every change to the ref count of each item, kept even after the item is freed
+    #[cfg(feature = "refcount-debug")]
+    log : HashMap<u64, Vec<RefOperation>>,
+    /// What the VM is running, written in each operation of the log
+    #[cfg(feature = "refcount-debug")]
+    location : String,
+}
+
+/// A change to the ref count of an item, recorded with the refcount-debug feature
+#[cfg(feature = "refcount-debug")]
+#[derive(Debug, Clone)]
+pub struct RefOperation {
+    /// "criado", "incrementado", "decrementado" or "coletado"
+    pub operation : &'static str,
+    /// The ref count after the operation
+    pub ref_count : u64,
+    /// The instruction being run when it happened
+    pub location : String,
+    /// Only captured when RUST_BACKTRACE is set, since it's slow
+    pub backtrace : String,
+}
+
+#[cfg(feature = "refcount-debug")]
+impl Display for RefOperation {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (agora {}) em {}", self.operation, self.ref_count, self.location)?;
+
+        if ! self.backtrace.is_empty() {
+            write!(f, "\n{}", self.backtrace)?;
+        }
+
+        Ok(())
+    }
 }
 
 impl SpecialStorage {
-    fn new() -> SpecialStorage {
+    fn new(arena : bool) -> SpecialStorage {
         SpecialStorage {
-            items : vec![],
+            items : HashMap::new(),
             next_item_id : 0,
+            arena,
+            #[cfg(feature = "refcount-debug")]
+            log : HashMap::new(),
+            #[cfg(feature = "refcount-debug")]
+            location : String::new(),
         }
     }
 
+    #[cfg(feature = "refcount-debug")]
+    fn record(&mut self, id : u64, operation : &'static str, ref_count : u64) {
+        use std::backtrace::{ Backtrace, BacktraceStatus };
+
+        let backtrace = Backtrace::capture();
+        let backtrace = match backtrace.status() {
+            BacktraceStatus::Captured => backtrace.to_string(),
+            _ => String::new(),
+        };
+
+        let location = self.location.clone();
+
+        self.log.entry(id).or_default().push(RefOperation { operation, ref_count, location, backtrace });
+    }
+
+    /// Set what the VM is running, to be written in the operations recorded from now on
+    #[cfg(feature = "refcount-debug")]
+    pub fn set_location(&mut self, location : String) {
+        self.location = location;
+    }
+
+    /// Every change to the ref count of an item, in order
+    #[cfg(feature = "refcount-debug")]
+    pub fn operation_log(&self, id : u64) -> &[RefOperation] {
+        self.log.get(&id).map(|log| log.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn is_arena(&self) -> bool {
+        self.arena
+    }
+
+    /// Number of items currently allocated
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
     pub fn add(&mut self, data : SpecialItemData, ref_count : u64) -> u64 {
         let item_id = self.next_item_id;
         self.next_item_id += 1;
@@ -111,22 +278,44 @@ impl SpecialStorage {
             ref_count
         };
 
-        self.items.push(item);
+        self.items.insert(item_id, item);
+
+        #[cfg(feature = "refcount-debug")]
+        self.record(item_id, "criado", ref_count);
 
         item_id
     }
 
     pub fn decrement_ref(&mut self, id : u64) -> Result<(), String>
     {
-        for i in 0..self.items.len() {
-            if self.items[i].item_id == id {
-                if self.items[i].ref_count <= 1 {
-                    self.items.remove(i);
-                } else {
-                    self.items[i].ref_count -= 1;
+        if self.arena {
+            return Ok(());
+        }
+
+        // A list or map holds a reference to each of its values, given back when it's freed (which may free them too)
+        let mut pending = vec![id];
+
+        while let Some(id) = pending.pop() {
+            let freed = match self.items.get_mut(&id) {
+                Some(item) if item.ref_count <= 1 => true,
+                Some(item) => {
+                    item.ref_count -= 1;
+
+                    false
                 }
+                None => continue,
+            };
 
-                break;
+            if freed {
+                if let Some(item) = self.items.remove(&id) {
+                    pending.extend(item.data.held_values().iter().filter_map(|v| v.special_id()));
+                }
+            }
+
+            #[cfg(feature = "refcount-debug")]
+            {
+                let ref_count = self.get_ref(id).map(|item| item.ref_count).unwrap_or(0);
+                self.record(id, "decrementado", ref_count);
             }
         }
 
@@ -135,11 +324,21 @@ impl SpecialStorage {
 
     pub fn increment_ref(&mut self, id : u64) -> Result<(), String>
     {
+        if self.arena {
+            return Ok(());
+        }
+
         match self.get_mut(id) {
             Some(item) => item.ref_count += 1,
             None => return Err("Invalid item ID".to_owned())
         };
 
+        #[cfg(feature = "refcount-debug")]
+        {
+            let ref_count = self.get_ref(id).map(|item| item.ref_count).unwrap_or(0);
+            self.record(id, "incrementado", ref_count);
+        }
+
         Ok(())
     }
 
@@ -152,23 +351,128 @@ impl SpecialStorage {
     }
 
     pub fn get_ref(&self, id : u64) -> Option<&SpecialItem> {
-        for e in &self.items {
-            if e.item_id == id {
-                return Some(e);
+        self.items.get(&id)
+    }
+
+    pub fn get_mut(&mut self, id : u64) -> Option<&mut SpecialItem> {
+        self.items.get_mut(&id)
+    }
+
+    /// Free every item that isn't marked, returning how many were freed
+    fn sweep(&mut self, marked : &HashSet<u64>) -> usize {
+        let garbage = self.items.keys().filter(|id| ! marked.contains(id)).cloned().collect::<Vec<u64>>();
+
+        // The references that the freed lists and maps had to the values that stay are given back
+        let mut given_back = vec![];
+
+        for id in &garbage {
+            if let Some(data) = self.get_data_ref(*id) {
+                given_back.extend(data.held_values().iter().filter_map(|v| v.special_id()).filter(|e| marked.contains(e)));
             }
         }
 
-        None
-    }
+        for id in &garbage {
+            self.items.remove(id);
 
-    pub fn get_mut(&mut self, id : u64) -> Option<&mut SpecialItem> {
-        for e in &mut self.items {
-            if e.item_id == id {
-                return Some(e);
+            #[cfg(feature = "refcount-debug")]
+            self.record(*id, "coletado", 0);
+        }
+
+        for id in given_back {
+            if let Some(item) = self.items.get_mut(&id) {
+                item.ref_count = item.ref_count.saturating_sub(1);
             }
         }
 
-        None
+        garbage.len()
+    }
+
+    /// Every item alive, in the order they were created
+    fn sorted_items(&self) -> Vec<&SpecialItem> {
+        let mut items = self.items.values().collect::<Vec<&SpecialItem>>();
+
+        items.sort_by_key(|item| item.item_id);
+
+        items
+    }
+}
+
+/// Information about an item alive in the special storage, for debugging
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeapItem {
+    pub id : u64,
+    /// "Texto", "Lista", "Mapa"...
+    pub kind : &'static str,
+    /// Characters in a text, elements in a list or keys in a map
+    pub size : usize,
+    pub ref_count : u64,
+    /// Who has a reference to the item (a variable, a frame, a list or a map), if anyone
+    pub owners : Vec<String>,
+}
+
+impl Display for HeapItem {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        let unit = if self.kind == "Texto" { "caracteres" } else { "elementos" };
+
+        write!(f, "#{} {} ({} {}), {} referência(s)", self.id, self.kind, self.size, unit, self.ref_count)?;
+
+        if self.owners.is_empty() {
+            write!(f, ", sem dono")
+        } else {
+            write!(f, ", em {}", self.owners.join(", "))
+        }
+    }
+}
+
+/// A reference to a text or list kept outside of the program's variables, usually by a plugin that needs the item
+/// after it returns. The item isn't freed while the SpecialRef exists, and the reference is given back when it's
+/// dropped (the VM only decrements the count at the next plugin call, since it isn't reachable from here)
+#[derive(Debug)]
+pub struct SpecialRef {
+    value : DynamicValue,
+    released : Rc<RefCell<Vec<u64>>>,
+}
+
+impl SpecialRef {
+    pub fn value(&self) -> DynamicValue {
+        self.value
+    }
+}
+
+impl Drop for SpecialRef {
+    fn drop(&mut self) {
+        match self.value {
+            DynamicValue::Text(id) | DynamicValue::List(id) | DynamicValue::Sequence(id) | DynamicValue::File(id) | DynamicValue::Database(id) | DynamicValue::Map(id) => self.released.borrow_mut().push(id),
+            _ => {}
+        }
+    }
+}
+
+/// The execution of a VM as an iterator : each item is the status of one instruction executed, so a host can run a
+/// program a few instructions at a time (like some every frame of a game, with `take`) without its own loop around
+/// `execute_next_instruction`. Breakpoints, interruptions, running out of fuel and waiting for input are given like any
+/// other status and the iteration can go on after them. It ends after the program halts or quits, or after an error
+pub struct Steps<'a> {
+    vm : &'a mut VirtualMachine,
+    finished : bool,
+}
+
+impl<'a> Iterator for Steps<'a> {
+    type Item = Result<ExecutionStatus, BirlError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        let result = self.vm.step();
+
+        match result {
+            Ok(ExecutionStatus::Halt) | Ok(ExecutionStatus::Quit) | Err(_) => self.finished = true,
+            _ => {}
+        }
+
+        Some(result)
     }
 }
 
@@ -177,6 +481,8 @@ struct LoopLabel {
     start_pc : usize,
     index_address : Option<usize>,
     stepping : DynamicValue,
+    /// What a PRA CADA is going through
+    iterator : Option<Box<dyn ValueIterator>>,
 }
 
 impl LoopLabel {
@@ -185,27 +491,31 @@ impl LoopLabel {
             start_pc,
             index_address : None,
             stepping : DynamicValue::Null,
+            iterator : None,
         }
     }
 }
 
 #[derive(Debug)]
-pub struct FunctionFrame {
+struct FunctionFrame {
     id : usize,
     stack : Vec<DynamicValue>,
     program_counter : usize,
     last_comparision : Option<Comparision>,
     next_address : usize,
     ready : bool,
-    skip_level : u32,
     stack_size : usize,
     // Number of special items allocated
     num_special_items : usize,
     label_stack : Vec<LoopLabel>,
+    /// Name of the function, for the trace of errors. None for the global code
+    name : Option<Rc<str>>,
+    /// The values of a BIRL with more than one, until it returns
+    return_values : Vec<DynamicValue>,
 }
 
 impl FunctionFrame {
-    pub fn new(id : usize, stack_size : usize) -> FunctionFrame {
+    fn new(id : usize, stack_size : usize) -> FunctionFrame {
         FunctionFrame {
             id,
             stack : vec![DynamicValue::Null; stack_size],
@@ -213,1596 +523,6291 @@ impl FunctionFrame {
             last_comparision : None,
             next_address : 0usize,
             ready : false,
-            skip_level : 0,
             stack_size,
             label_stack : vec![],
             num_special_items : 0,
+            name : None,
+            return_values : vec![],
+        }
+    }
+
+    /// Where the code of the frame is, for messages
+    fn describe(&self) -> String {
+        match self.name {
+            Some(ref name) => format!("na JAULA {}", name),
+            None => "no código global".to_owned(),
         }
     }
 }
 
-#[derive(Clone, Debug)]
+/// Result of executing an instruction. Everything other than Normal and Returned stops `resume`, and can be queried
+/// later with `stop_reason`
+#[derive(Clone, Debug, PartialEq)]
 pub enum ExecutionStatus {
     Normal,
     Quit,
     Returned,
+    /// There's no more code to run for now. In interactive mode, more code can be added and execution resumed
     Halt,
+    /// Execution reached a breakpoint
+    Breakpoint,
+    /// The VM ran out of fuel (the limit of instructions it's allowed to execute)
+    OutOfFuel,
+    /// The host asked for the execution to stop, through the interrupt handle
+    Interrupted,
+    /// The program is waiting for a line of input that wasn't given yet
+    AwaitingInput,
+    /// Every instruction `run_steps` was allowed to run ran, and nothing else stopped the execution. The next call (or
+    /// a resume) goes on from there
+    Paused,
+    /// The execution stopped with an error, with the message, the function and instruction that failed and the trace
+    /// in its location. The functions that run code give the error as their Err, so this is what `stop_reason` says
+    /// after one and what `ExecutionStatus::from` makes of their result, for hosts that want a single value to match
+    Error(Box<BirlError>),
+}
+
+impl ExecutionStatus {
+    /// The error, if the execution stopped with one
+    pub fn error(&self) -> Option<&BirlError> {
+        match *self {
+            ExecutionStatus::Error(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// The status of running code and its error as one value : `match ExecutionStatus::from(vm.resume())`
+impl From<Result<ExecutionStatus, BirlError>> for ExecutionStatus {
+    fn from(result : Result<ExecutionStatus, BirlError>) -> ExecutionStatus {
+        match result {
+            Ok(status) => status,
+            Err(e) => ExecutionStatus::Error(Box::new(e)),
+        }
+    }
+}
+
+/// Width of the integers in a VM. Results of arithmetic are wrapped to it, like the integer of that size would. It can't
+/// be wider than the IntegerType the crate was built with (enable the "i128" feature for 128 bits)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IntegerWidth {
+    Bits32,
+    Bits64,
+    Bits128,
+}
+
+impl IntegerWidth {
+    pub fn bits(&self) -> usize {
+        match *self {
+            IntegerWidth::Bits32 => 32,
+            IntegerWidth::Bits64 => 64,
+            IntegerWidth::Bits128 => 128,
+        }
+    }
+
+    /// Width of IntegerType, the widest available
+    pub fn native() -> IntegerWidth {
+        match ::std::mem::size_of::<IntegerType>() * 8 {
+            32 => IntegerWidth::Bits32,
+            64 => IntegerWidth::Bits64,
+            _ => IntegerWidth::Bits128,
+        }
+    }
+
+    /// Wrap the integer around to fit in this width
+    // Depending on the IntegerType of the build, one of the casts is to the same type
+    #[allow(clippy::unnecessary_cast)]
+    fn wrap(&self, i : IntegerType) -> IntegerType {
+        match *self {
+            IntegerWidth::Bits32 => i as i32 as IntegerType,
+            IntegerWidth::Bits64 => i as i64 as IntegerType,
+            IntegerWidth::Bits128 => i,
+        }
+    }
+}
+
+/// What happens when the result of an operation on integers doesn't fit in the width of the VM's integers
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OverflowPolicy {
+    /// Stop with an error
+    Error,
+    /// Wrap around, like the integer of that size would. The default
+    Wrap,
+    /// Give the result as a number instead
+    Float,
 }
 
+/// How numbers are written and read from texts. Only changes texts : in the source code, numbers always use a point
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DecimalSeparator {
+    /// 3.14, the default
+    Point,
+    /// 3,14, like it's written in Brazil. Reading also accepts 3.14, and points separating the thousands when there's a
+    /// comma (1.234,5)
+    Comma,
+}
+
+#[doc(hidden)]
 pub struct Registers {
     math_a : DynamicValue,
     math_b : DynamicValue,
     intermediate : DynamicValue,
-    first_operation : bool,
     secondary : DynamicValue,
     default_stack_size : usize,
+    /// Maximum size of a text, in bytes. None for no limit
+    max_text_size : Option<usize>,
+    /// Maximum number of elements in a list. None for no limit
+    max_list_size : Option<usize>,
+    /// Maximum number of texts and lists allocated at the same time. None for no limit
+    max_special_items : Option<usize>,
+    /// Maximum number of frames in the callstack. None for no limit
+    max_call_depth : Option<usize>,
+    /// Number of items alive that makes the garbage collector run. None to only run it when asked
+    gc_threshold : Option<usize>,
+    /// Number of items alive that makes the garbage collector run next. Grows with the number of items that survive a
+    /// collection, so a program with lots of items in use doesn't collect all the time
+    next_collection : usize,
+    integer_width : IntegerWidth,
+    overflow_policy : OverflowPolicy,
+    /// If set, comparing values of different types (other than integers and numbers) is an error, instead of them
+    /// being just different
+    strict_comparisons : bool,
+    decimal_separator : DecimalSeparator,
+    /// Whether the output is flushed after each print. None to do it only when the output is a terminal
+    auto_flush : Option<bool>,
+    /// Number of instructions the VM can still execute. None for no limit
+    fuel : Option<u64>,
     has_quit : bool,
     is_interactive : bool,
+    /// If set, reading input when there's none available suspends the execution with AwaitingInput, instead of giving Null
+    await_input : bool,
     next_code_index : usize,
     next_plugin_index : usize,
 }
 
 impl Registers {
+    pub fn math_a(&self) -> DynamicValue {
+        self.math_a
+    }
+
+    pub fn math_b(&self) -> DynamicValue {
+        self.math_b
+    }
+
+    pub fn intermediate(&self) -> DynamicValue {
+        self.intermediate
+    }
+
+    pub fn secondary(&self) -> DynamicValue {
+        self.secondary
+    }
+
     fn default() -> Registers {
         Registers {
             math_a : DynamicValue::Null,
             math_b : DynamicValue::Null,
             secondary : DynamicValue::Null,
             intermediate : DynamicValue::Null,
-            first_operation : false,
             default_stack_size : STACK_DEFAULT_SIZE,
+            max_text_size : Some(MAX_TEXT_SIZE_DEFAULT),
+            max_list_size : Some(MAX_LIST_SIZE_DEFAULT),
+            max_special_items : None,
+            max_call_depth : Some(MAX_CALL_DEPTH_DEFAULT),
+            gc_threshold : Some(GC_THRESHOLD_DEFAULT),
+            next_collection : GC_THRESHOLD_DEFAULT,
+            integer_width : IntegerWidth::native(),
+            overflow_policy : OverflowPolicy::Wrap,
+            strict_comparisons : false,
+            decimal_separator : DecimalSeparator::Point,
+            auto_flush : None,
+            fuel : None,
             has_quit : false,
             is_interactive : false,
+            await_input : false,
             next_code_index : 0,
             next_plugin_index : 0,
         }
     }
 }
 
-pub struct VirtualMachine {
-    registers : Registers,
-    callstack : Vec<FunctionFrame>,
-    stdout: Option<Box<Write>>,
-    stdin:  Option<Box<BufRead>>,
-    code : Vec<Vec<Instruction>>,
-    plugins : Vec<PluginFunction>,
-    special_storage : SpecialStorage,
-    plugin_argument_stack : Vec<DynamicValue>,
+/// Configures and creates a VirtualMachine
+pub struct VirtualMachineBuilder {
+    stack_size : usize,
+    max_text_size : Option<usize>,
+    max_list_size : Option<usize>,
+    max_special_items : Option<usize>,
+    max_call_depth : Option<usize>,
+    gc_threshold : Option<usize>,
+    integer_width : IntegerWidth,
+    overflow_policy : OverflowPolicy,
+    strict_comparisons : bool,
+    decimal_separator : DecimalSeparator,
+    auto_flush : Option<bool>,
+    fuel : Option<u64>,
+    arena : bool,
+    random_seed : Option<u64>,
 }
 
-macro_rules! vm_write{
-    ($out:expr,$($arg:tt)*) => ({
-        if let Some(output) = $out.as_mut(){
-            write!(output, $($arg)*)
-                .map_err(|what| format!("Deu pra escrever não cumpade: {:?}", what))
-        }else{
-            Ok(())
+impl VirtualMachineBuilder {
+    pub fn new() -> VirtualMachineBuilder {
+        VirtualMachineBuilder {
+            stack_size : STACK_DEFAULT_SIZE,
+            max_text_size : Some(MAX_TEXT_SIZE_DEFAULT),
+            max_list_size : Some(MAX_LIST_SIZE_DEFAULT),
+            max_special_items : None,
+            max_call_depth : Some(MAX_CALL_DEPTH_DEFAULT),
+            gc_threshold : Some(GC_THRESHOLD_DEFAULT),
+            integer_width : IntegerWidth::native(),
+            overflow_policy : OverflowPolicy::Wrap,
+            strict_comparisons : false,
+            decimal_separator : DecimalSeparator::Point,
+            auto_flush : None,
+            fuel : None,
+            arena : false,
+            random_seed : None,
         }
-    })
-}
+    }
 
-impl VirtualMachine {
-    pub fn new() -> VirtualMachine {
-        VirtualMachine {
-            registers : Registers::default(),
-            callstack : vec![],
-            stdout: None,
-            stdin: None,
-            code : vec![],
-            plugins : vec![],
-            special_storage : SpecialStorage::new(),
-            plugin_argument_stack : vec![]
-        }
+    /// Size of the stack of each function frame
+    pub fn stack_size(mut self, size : usize) -> VirtualMachineBuilder {
+        self.stack_size = size;
+        self
     }
 
-    fn add_special_item(&mut self, frame_index : usize, data : SpecialItemData) -> Result<u64, String> {
-        if self.callstack.len() <= frame_index {
-            return Err("add_special_item : Index é inválido".to_owned());
-        }
+    /// Maximum size of a single text, in bytes. None for no limit
+    pub fn max_text_size(mut self, size : Option<usize>) -> VirtualMachineBuilder {
+        self.max_text_size = size;
+        self
+    }
 
-        self.callstack[frame_index].num_special_items += 1;
+    /// Maximum number of elements of a single list. None for no limit
+    pub fn max_list_size(mut self, size : Option<usize>) -> VirtualMachineBuilder {
+        self.max_list_size = size;
+        self
+    }
 
-        Ok(self.special_storage.add(data, 0u64))
+    /// Maximum number of texts and lists allocated at the same time. None (the default) for no limit
+    pub fn max_special_items(mut self, count : Option<usize>) -> VirtualMachineBuilder {
+        self.max_special_items = count;
+        self
     }
 
-    fn raw_to_dynamic(&mut self, val : RawValue) -> Result<DynamicValue, String> {
-        match val {
-            RawValue::Text(t) => {
-                let parent_index = match self.get_last_ready_index() {
-                    Some(s) => s,
-                    None => 0,
-                };
+    /// Maximum number of functions running at the same time, the global code included, so a recursion that never ends
+    /// is an error instead of taking all the memory. None for no limit
+    pub fn max_call_depth(mut self, depth : Option<usize>) -> VirtualMachineBuilder {
+        self.max_call_depth = depth;
+        self
+    }
 
-                let id = match self.add_special_item(parent_index, SpecialItemData::Text(t)) {
-                    Ok(id) => id,
-                    Err(e) => return Err(e)
-                };
+    /// Number of texts and lists alive that makes the garbage collector run. None to only run it when asked, with
+    /// `collect_garbage`
+    pub fn gc_threshold(mut self, count : Option<usize>) -> VirtualMachineBuilder {
+        self.gc_threshold = count;
+        self
+    }
 
-                Ok(DynamicValue::Text(id))
-            },
-            RawValue::Number(n) => Ok(DynamicValue::Number(n)),
-            RawValue::Integer(i) => Ok(DynamicValue::Integer(i)),
-            RawValue::Null => Ok(DynamicValue::Null),
-        }
+    /// Width of the integers. By default it's the width of IntegerType
+    pub fn integer_width(mut self, width : IntegerWidth) -> VirtualMachineBuilder {
+        self.integer_width = width;
+        self
     }
 
-    pub fn set_interactive_mode(&mut self) {
-        self.registers.is_interactive = true;
+    /// What happens when an integer result doesn't fit in the width. By default it wraps around
+    pub fn overflow_policy(mut self, policy : OverflowPolicy) -> VirtualMachineBuilder {
+        self.overflow_policy = policy;
+        self
     }
 
-    pub fn execute_next_instruction(&mut self) -> Result<ExecutionStatus, String> {
-        if self.callstack.is_empty() {
-            return Err("Nenhuma função em execução".to_owned());
-        }
+    /// Make comparing values of different types an error. By default they're just different
+    pub fn strict_comparisons(mut self, strict : bool) -> VirtualMachineBuilder {
+        self.strict_comparisons = strict;
+        self
+    }
 
-        let pc = match self.get_current_pc() {
-            Some(p) => p,
-            None => return Err("Nenhuma função em execução".to_owned()),
-        };
+    /// How numbers are written in texts and read from them. By default with a point
+    pub fn decimal_separator(mut self, separator : DecimalSeparator) -> VirtualMachineBuilder {
+        self.decimal_separator = separator;
+        self
+    }
 
-        let id = match self.get_current_id() {
-            Some(i) => i,
-            None => return Err("Nenhuma função em execução".to_owned())
-        };
+    /// Whether the output is flushed after each print, so what's printed shows up right away. None (the default) to do
+    /// it only when the output is a terminal
+    pub fn auto_flush(mut self, auto_flush : Option<bool>) -> VirtualMachineBuilder {
+        self.auto_flush = auto_flush;
+        self
+    }
 
-        if self.code.len() <= id {
-            return Err("ID atual pra função é inválida".to_owned());
-        }
+    /// Maximum number of instructions executed, for code that can't be trusted to end. None (the default) for no limit
+    pub fn fuel(mut self, fuel : Option<u64>) -> VirtualMachineBuilder {
+        self.fuel = fuel;
+        self
+    }
 
-        match self.increment_pc() {
-            Ok(_) => {}
-            Err(e) => return Err(e),
+    /// Allocate texts and lists from an arena that is only freed when the VM is dropped, skipping all the reference
+    /// counting. Faster for short scripts, but memory only grows, so it's not meant for long running programs
+    pub fn arena_allocation(mut self, arena : bool) -> VirtualMachineBuilder {
+        self.arena = arena;
+        self
+    }
+
+    /// Seed of the random numbers, so a program gets the same ones on every run (for tests, or reproducing a bug). None
+    /// (the default) for different ones every time
+    pub fn random_seed(mut self, seed : Option<u64>) -> VirtualMachineBuilder {
+        self.random_seed = seed;
+        self
+    }
+
+    /// Create the VM. Fails if the integer width is wider than IntegerType
+    pub fn build(self) -> Result<VirtualMachine, String> {
+        let mut vm = VirtualMachine::new();
+
+        vm.set_stack_size(self.stack_size);
+        vm.set_max_text_size(self.max_text_size);
+        vm.set_max_list_size(self.max_list_size);
+        vm.set_max_special_items(self.max_special_items);
+        vm.set_max_call_depth(self.max_call_depth);
+        vm.set_gc_threshold(self.gc_threshold);
+        vm.set_integer_width(self.integer_width)?;
+        vm.set_overflow_policy(self.overflow_policy);
+        vm.set_strict_comparisons(self.strict_comparisons);
+        vm.set_decimal_separator(self.decimal_separator);
+        vm.set_auto_flush(self.auto_flush);
+
+        if let Some(fuel) = self.fuel {
+            vm.set_fuel(fuel);
         }
+        vm.special_storage = SpecialStorage::new(self.arena);
 
-        // The case above doesn't happen anymore and we can just execute it directly
-        // if self.code[id].len() <= pc {}
+        if let Some(seed) = self.random_seed {
+            vm.set_random_seed(seed);
+        }
 
-        let instruction = self.code[id][pc].clone();
+        Ok(vm)
+    }
+}
 
-        self.run(instruction)
+impl Default for VirtualMachineBuilder {
+    fn default() -> VirtualMachineBuilder {
+        VirtualMachineBuilder::new()
     }
+}
 
-    pub fn set_stdout(&mut self, write: Option<Box<Write>>) -> Option<Box<Write>>{
-        use std::mem;
-        mem::replace(&mut self.stdout, write)
+pub struct VirtualMachine {
+    registers : Registers,
+    callstack : Vec<FunctionFrame>,
+    io : Box<dyn VmIo>,
+    /// Whether the io is a terminal, asked once when it's set instead of on every print
+    io_is_tty : bool,
+    /// Reused by the print instructions to format values
+    format_buffer : String,
+    code : Vec<Vec<Instruction>>,
+    /// Name and address of the variables in the function scope of each code, used to read variables from callers
+    variable_names : Vec<HashMap<String, usize>>,
+    /// For each code, the first instruction compiled from each line of the source and the number of that line, in
+    /// order. Used to say where runtime errors happened
+    source_lines : Vec<Vec<(usize, usize)>>,
+    /// Address and parameter types of each function in the source, so they can be called by name
+    functions : HashMap<String, (usize, Vec<TypeKind>)>,
+    /// Name of the function of each code, if it's one
+    function_names : Vec<Option<Rc<str>>>,
+    /// The last error that had where it happened put in it. An error in a function called by a plugin comes back to the
+    /// instruction that called the plugin as a text, and this is how it gets its location (and trace) back
+    located_error : Option<BirlError>,
+    plugins : Vec<PluginFunction>,
+    /// Plugins added with a name, with their ID and parameters, so they can be called by name like the functions
+    plugin_names : HashMap<String, (usize, Vec<TypeKind>)>,
+    special_storage : SpecialStorage,
+    plugin_argument_stack : Vec<DynamicValue>,
+    /// Arguments of the plugins running right now. They're still held until the plugin returns, and a plugin can call
+    /// functions in the meantime
+    lent_plugin_arguments : Vec<DynamicValue>,
+    expression_stack : Vec<DynamicValue>,
+    /// Every value returned by the last function (or plugin) that returned, for PEGA DA JAULA
+    return_values : Vec<DynamicValue>,
+    /// Set by the host (possibly from another thread) to stop the execution at the next instruction
+    interrupt : Arc<AtomicBool>,
+    /// Why the last call to resume stopped
+    stop_reason : Option<ExecutionStatus>,
+    /// Lines given by the host with provide_input, read before the ones from the IO
+    provided_input : VecDeque<String>,
+    /// What the last failed TEM QUE SER checked, until the host takes it
+    assertion_failure : Option<AssertionFailure>,
+    /// IDs of the items whose SpecialRef was dropped, waiting to have their ref count decremented
+    released_refs : Rc<RefCell<Vec<u64>>>,
+    /// IDs of the items with a SpecialRef alive. They're held by Rust code, so the garbage collector can't free them
+    retained_refs : Vec<u64>,
+    /// State of the random number generator (a SplitMix64)
+    random_state : u64,
+    /// Instructions (code id, index) that stop the execution with ExecutionStatus::Breakpoint before running
+    breakpoints : HashSet<(usize, usize)>,
+    /// Set when the execution stopped at a breakpoint, so the next step runs the instruction instead of stopping again
+    on_breakpoint : bool,
+}
+
+/// A seed that's different every time, for when the host doesn't give one. Taken from the random keys the standard
+/// library uses for HashMaps, so it doesn't need a clock (which not every target has)
+fn entropy_seed() -> u64 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{ BuildHasher, Hasher };
+
+    RandomState::new().build_hasher().finish()
+}
+
+/// Write a number as text, with the shortest digits that read back as the same number (using ryu), so a program
+/// prints the same numbers on every platform. Whole numbers are written without the ".0", and very big or very small
+/// ones with an exponent (like 1e21 and 1e-7)
+pub fn push_number(text : &mut String, n : f64) {
+    if n.is_nan() {
+        text.push_str("NaN");
+    } else if n.is_infinite() {
+        text.push_str(if n > 0.0 { "inf" } else { "-inf" });
+    } else {
+        let mut buffer = ryu::Buffer::new();
+        let formatted = buffer.format_finite(n);
+
+        text.push_str(formatted.strip_suffix(".0").unwrap_or(formatted));
     }
+}
 
-    pub fn set_stdin(&mut self, read: Option<Box<BufRead>>) -> Option<Box<BufRead>>{
-        use std::mem;
-        mem::replace(&mut self.stdin, read)
-    } 
+/// Like push_number, but with the given decimal separator
+pub fn push_number_with(text : &mut String, n : f64, separator : DecimalSeparator) {
+    let start = text.len();
 
-    pub fn get_current_skip_level(&self) -> u32 {
-        match self.get_last_ready_ref() {
-            Some(f) => f.skip_level,
-            None => 0,
+    push_number(text, n);
+
+    if separator == DecimalSeparator::Comma {
+        if let Some(point) = text[start..].find('.') {
+            text.replace_range(start + point..start + point + 1, ",");
         }
     }
+}
 
-    fn get_last_ready_ref(&self) -> Option<&FunctionFrame> {
-        let callstack = &self.callstack;
-        for frame in callstack.into_iter().rev() {
-            if frame.ready {
-                return Some(frame);
-            }
-        }
-        None
+/// Read a number from a text, with the given decimal separator. None if it isn't a number
+pub fn parse_number(text : &str, separator : DecimalSeparator) -> Option<f64> {
+    let text = text.trim();
+
+    let comma = match text.find(',') {
+        Some(comma) if separator == DecimalSeparator::Comma => comma,
+        _ => return text.parse::<f64>().ok(),
+    };
+
+    let (whole, fraction) = (&text[..comma], &text[comma + 1..]);
+
+    // The points can only be separating groups of 3 digits, so "1.23,4" isn't taken as 123,4
+    let mut groups = whole.split('.');
+    let first = groups.next().unwrap_or("");
+    let first_digits = first.trim_start_matches(['-', '+']);
+
+    if whole.contains('.') && (first_digits.is_empty() || first_digits.len() > 3 ||
+                               groups.any(|g| g.len() != 3 || ! g.bytes().all(|b| b.is_ascii_digit()))) {
+        return None;
     }
 
-    pub fn get_last_ready_mut(&mut self) -> Option<&mut FunctionFrame> {
-        let callstack = &mut self.callstack;
-        for frame in callstack.into_iter().rev() {
-            if frame.ready {
-                return Some(frame);
-            }
-        }
-        None
+    if fraction.contains(['.', ',']) {
+        return None;
     }
 
-    fn get_current_id(&self) -> Option<usize> {
-        if self.callstack.is_empty() {
-            None
-        } else {
-            match self.get_last_ready_ref() {
-                Some(f) => Some(f.id),
-                None => None,
-            }
+    format!("{}.{}", whole.replace('.', ""), fraction).parse::<f64>().ok()
+}
+
+/// Raise a base to a non negative power by squaring, using `multiply` for every step. Gives None as soon as a step does
+fn integer_power(base : IntegerType, exponent : IntegerType,
+                 multiply : fn(IntegerType, IntegerType) -> Option<IntegerType>) -> Option<IntegerType> {
+    let (mut result, mut base, mut exponent) = (1, base, exponent);
+
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = multiply(result, base)?;
         }
-    }
 
-    pub fn get_next_code_id(&self) -> usize {
-        self.registers.next_code_index
-    }
+        exponent >>= 1;
 
-    pub fn get_next_plugin_id(&self) -> usize {
-        self.registers.next_plugin_index
+        if exponent > 0 {
+            base = multiply(base, base)?;
+        }
     }
 
-    pub fn get_code_for(&mut self, id : usize) -> Option<&mut Vec<Instruction>> {
-        if self.code.len() <= id {
-            None
-        } else {
-            Some(&mut self.code[id])
+    Some(result)
+}
+
+pub(crate) fn checked_pow(base : IntegerType, exponent : IntegerType) -> Option<IntegerType> {
+    integer_power(base, exponent, IntegerType::checked_mul)
+}
+
+fn wrapping_pow(base : IntegerType, exponent : IntegerType) -> IntegerType {
+    integer_power(base, exponent, |l, r| Some(l.wrapping_mul(r))).unwrap_or(0)
+}
+
+/// How a boolean is written, the same as its literal in the source
+pub fn bool_name(b : bool) -> &'static str {
+    if b { "VERDADE" } else { "MENTIRA" }
+}
+
+/// Format into the VM's formatting buffer and write it to the output. The buffer is reused, so printing numbers in a
+/// loop doesn't allocate a new string every time
+macro_rules! vm_write{
+    ($vm:expr,$($arg:tt)*) => ({
+        use std::fmt::Write as FmtWrite;
+
+        $vm.format_buffer.clear();
+
+        // Writing to a String never fails
+        let _ = write!($vm.format_buffer, $($arg)*);
+
+        $vm.io.write(&$vm.format_buffer)
+    })
+}
+
+impl VirtualMachine {
+    pub fn new() -> VirtualMachine {
+        VirtualMachine {
+            registers : Registers::default(),
+            callstack : vec![],
+            io : Box::new(NullIo),
+            io_is_tty : false,
+            format_buffer : String::new(),
+            code : vec![],
+            variable_names : vec![],
+            source_lines : vec![],
+            functions : HashMap::new(),
+            function_names : vec![],
+            located_error : None,
+            plugins : vec![],
+            plugin_names : HashMap::new(),
+            special_storage : SpecialStorage::new(false),
+            plugin_argument_stack : vec![],
+            lent_plugin_arguments : vec![],
+            expression_stack : vec![],
+            return_values : vec![],
+            interrupt : Arc::new(AtomicBool::new(false)),
+            stop_reason : None,
+            provided_input : VecDeque::new(),
+            assertion_failure : None,
+            released_refs : Rc::new(RefCell::new(vec![])),
+            retained_refs : vec![],
+            random_state : entropy_seed(),
+            breakpoints : HashSet::new(),
+            on_breakpoint : false,
         }
     }
 
-    pub fn add_new_code(&mut self) -> usize {
-        let id = self.registers.next_code_index;
-        self.registers.next_code_index += 1;
-        self.code.push(vec![]);
+    /// When set, reading input when none is available (the IO has no more lines and none was provided) suspends the
+    /// execution with ExecutionStatus::AwaitingInput. The host can then give a line with `provide_input` and resume
+    pub fn set_await_input(&mut self, await_input : bool) {
+        self.registers.await_input = await_input;
+    }
 
-        id
+    /// Give a line of input to the program. Lines given this way are read before the ones from the IO
+    pub fn provide_input(&mut self, line : String) {
+        self.provided_input.push_back(line);
     }
 
-    pub fn add_new_plugin(&mut self, plugin : PluginFunction) -> usize {
-        let id = self.get_next_plugin_id();
-        self.registers.next_plugin_index += 1;
-        self.plugins.push(plugin);
+    /// Keep a text or list alive after a plugin returns. Other values don't need to be kept, so there's no SpecialRef
+    /// for them
+    pub fn retain(&mut self, value : DynamicValue) -> Result<Option<SpecialRef>, String> {
+        match value {
+            DynamicValue::Text(id) | DynamicValue::List(id) | DynamicValue::Sequence(id) | DynamicValue::File(id) | DynamicValue::Database(id) | DynamicValue::Map(id) => {
+                self.special_storage.increment_ref(id)?;
+                self.retained_refs.push(id);
 
-        id
-    }
-    pub fn get_registers(&self) -> &Registers {
-        &self.registers
+                Ok(Some(SpecialRef { value, released : self.released_refs.clone() }))
+            }
+            _ => Ok(None),
+        }
     }
 
-    pub fn get_special_storage_ref(&self) -> &SpecialStorage {
-        &self.special_storage
+    /// Give back a reference to a text or list, freeing it if it was the last one
+    fn release(&mut self, value : DynamicValue) -> Result<(), String> {
+        match value {
+            DynamicValue::Text(id) | DynamicValue::List(id) | DynamicValue::Sequence(id) | DynamicValue::File(id) | DynamicValue::Database(id) | DynamicValue::Map(id) => self.special_storage.decrement_ref(id),
+            _ => Ok(()),
+        }
     }
 
-    pub fn get_special_storage_mut(&mut self) -> &mut SpecialStorage {
-        &mut self.special_storage
-    }
+    /// Decrement the ref count of the items whose SpecialRef was dropped
+    fn release_dropped_refs(&mut self) -> Result<(), String> {
+        let released = std::mem::take(&mut *self.released_refs.borrow_mut());
 
-    pub fn flush_stdout(&mut self) {
-        if let Some(ref mut out) = self.stdout.as_mut(){
-            match out.flush() {
-                Ok(_) => {}
-                Err(_) => {}
+        for id in released {
+            if let Some(index) = self.retained_refs.iter().position(|&i| i == id) {
+                self.retained_refs.remove(index);
             }
+
+            self.special_storage.decrement_ref(id)?;
         }
+
+        Ok(())
     }
 
-    fn is_compatible(left : DynamicValue, right : DynamicValue) -> bool {
-        match left {
-            DynamicValue::Text(_) => {
-                if let DynamicValue::Text(_) = right {
-                    true
-                } else {
-                    false
-                }
-            }
-            DynamicValue::Integer(_) | DynamicValue::Number(_) => {
-                match right {
-                    DynamicValue::Integer(_) | DynamicValue::Number(_) => true,
-                    _ => false,
-                }
-            }
-            _ => false,
+    /// End the loops of a function that returned in the middle of them. Otherwise what a PRA CADA was going through would
+    /// be kept alive until the next plugin call
+    fn drop_loop_labels(&mut self, labels : Vec<LoopLabel>) -> Result<(), String> {
+        let had_iterator = labels.iter().any(|l| l.iterator.is_some());
+
+        drop(labels);
+
+        if had_iterator {
+            self.release_dropped_refs()?;
         }
+
+        Ok(())
     }
 
-    fn add_values(&mut self, left : DynamicValue, right : DynamicValue) -> Result<DynamicValue, String> {
-        if ! VirtualMachine::is_compatible(left, right) {
-            return Err(format!("Add : Os valores não são compatíveis : {:?} e {:?}", left, right));
+    /// Free every text, list, sequence and file that the program can't reach anymore. The ref counts free most items as
+    /// soon as they're not used, but not lists that contain each other (directly or not), or items that lost track of
+    /// their count. What can be reached is found from the variables of every frame, the registers, the values waiting
+    /// in the stacks and the items held by Rust code through a SpecialRef, going into the values of lists and maps. Returns
+    /// how many items were freed. Does nothing in arena mode
+    pub fn collect_garbage(&mut self) -> Result<usize, String> {
+        if self.special_storage.is_arena() {
+            return Ok(0);
         }
 
-        match left {
-            DynamicValue::Integer(l_i) => {
-                match right {
-                    DynamicValue::Integer(r_i) => Ok(DynamicValue::Integer(l_i + r_i)),
-                    DynamicValue::Number(r_n) => Ok(DynamicValue::Number((l_i as f64) + r_n)),
-                    _ => return Err("Incompatível. Não deveria chegar aqui.".to_owned()),
-                }
+        // So the items whose SpecialRef is gone aren't taken as still held
+        self.release_dropped_refs()?;
+
+        let mut pending = vec![self.registers.math_a, self.registers.math_b, self.registers.intermediate,
+                               self.registers.secondary];
+
+        for frame in &self.callstack {
+            pending.extend(frame.stack.iter().cloned());
+            pending.extend(frame.label_stack.iter().map(|label| label.stepping));
+            pending.extend(frame.return_values.iter().cloned());
+        }
+
+        pending.extend(self.plugin_argument_stack.iter().cloned());
+        pending.extend(self.lent_plugin_arguments.iter().cloned());
+        pending.extend(self.expression_stack.iter().cloned());
+        pending.extend(self.return_values.iter().cloned());
+
+        let mut pending = pending.iter().filter_map(|v| v.special_id()).collect::<Vec<u64>>();
+        pending.extend(self.retained_refs.iter().cloned());
+
+        let mut marked = HashSet::new();
+
+        while let Some(id) = pending.pop() {
+            if ! marked.insert(id) {
+                continue;
             }
-            DynamicValue::Number(l_n) => {
-                match right {
-                    DynamicValue::Integer(r_i) => Ok(DynamicValue::Number(l_n + (r_i as f64))),
-                    DynamicValue::Number(r_n) => Ok(DynamicValue::Number(l_n + r_n)),
-                    _ => return Err("Incompatível. Não deveria chegar aqui.".to_owned()),
-                }
+
+            if let Some(data) = self.special_storage.get_data_ref(id) {
+                pending.extend(data.held_values().iter().filter_map(|v| v.special_id()));
             }
-            DynamicValue::Text(l_t) => {
-                match right {
-                    DynamicValue::Text(r_t) => {
-                        // Add right value to left node
+        }
 
-                        let mut result = String::new();
+        let freed = self.special_storage.sweep(&marked);
 
-                        {
-                            let left_v = match self.special_storage.get_data_ref(r_t) {
-                                Some(s) => match s {
-                                    &SpecialItemData::Text(ref s) => s,
-                                    _ => return Err(format!("Erro interno : DynamicValue é texto, mas o id aponta pra outra coisa"))
-                                },
-                                None => return Err(format!("Add w/ Text : Id {} não encontrada.", r_t))
-                            };
+        // Sequences that were freed may have been holding other items
+        self.release_dropped_refs()?;
 
-                            // remove right node
-                            let right_v = match self.special_storage.get_data_ref(l_t) {
-                                Some(s) => match s {
-                                    &SpecialItemData::Text(ref s) => s,
-                                    _ => return Err(format!("Erro interno : DynamicValue é texto, mas o id aponta pra outra coisa"))
-                                },
-                                None => return Err(format!("Add w/ Text : Id {} não encontrada.", l_t))
-                            };
+        Ok(freed)
+    }
+
+    /// Run the garbage collector if there are more items alive than the threshold
+    fn collect_garbage_if_needed(&mut self) -> Result<(), String> {
+        let threshold = match self.registers.gc_threshold {
+            Some(t) => t,
+            None => return Ok(()),
+        };
 
-                            if self.registers.first_operation {
-                                result.push_str(right_v);
-                                result.push_str(left_v);
+        if self.special_storage.len() >= self.registers.next_collection {
+            self.collect_garbage()?;
 
-                                self.registers.first_operation = false;
-                            } else {
-                                result.push_str(left_v);
-                                result.push_str(right_v);
+            self.registers.next_collection = threshold.max(self.special_storage.len() * 2);
+        }
 
-                            }
-                        }
+        Ok(())
+    }
 
-                        let parent_index = match self.get_last_ready_index() {
-                            Some(idx) => idx,
-                            None => return Err("Nenhuma função em execução".to_owned())
-                        };
+    /// Give back the references to the values returned by the last function, before the next one's are kept
+    fn clear_return_values(&mut self) -> Result<(), String> {
+        while let Some(value) = self.return_values.pop() {
+            self.release(value)?;
+        }
 
-                        let id = match self.add_special_item(parent_index, SpecialItemData::Text(result)) {
-                            Ok(id) => id,
-                            Err(e) => return Err(e)
-                        };
+        Ok(())
+    }
 
-                        Ok(DynamicValue::Text(id))
-                    }
-                    _ => return Err("Incompatível. Não deveria chegar aqui.".to_owned()),
-                }
-            }
-            DynamicValue::List(left_id) => {
-                match right {
-                    DynamicValue::List(right_id) => {
-                        // We must create a new list, add elements from left, then right, then return it
+    /// Write the value returned by a plugin to TREZE (and show it, in interactive mode)
+    fn store_plugin_result(&mut self, result : Option<DynamicValue>) -> Result<(), String> {
+        if let Some(id) = result.and_then(|v| v.special_id()) {
+            self.special_storage.increment_ref(id)?;
+        }
 
-                        let mut data = vec![];
+        self.clear_return_values()?;
+        self.return_values.extend(result);
 
-                        match self.special_storage.get_data_ref(left_id) {
-                            Some(SpecialItemData::List(ref contents)) => {
-                                for item in contents {
-                                    data.push(item.clone());
-                                }
-                            }
-                            Some(_) => return Err("Erro interno : DynamicValue é uma lista, mas o valor guardado não".to_owned()),
-                            None => return Err("Erro interno : ID inválida pra lista".to_owned())
-                        }
+        if let Some(value) = result {
+            let index = self.last_frame_index()?;
+            self.write_to(value, index, 0)?;
 
-                        match self.special_storage.get_data_ref(right_id) {
-                            Some(SpecialItemData::List(ref contents)) => {
-                                for item in contents {
-                                    data.push(item.clone());
-                                }
-                            }
-                            Some(_) => return Err("Erro interno : DynamicValue é uma lista, mas o valor guardado não".to_owned()),
-                            None => return Err("Erro interno : ID inválida pra lista".to_owned())
-                        }
+            if self.registers.is_interactive && self.callstack.len() == 1 {
+                let tmp = self.registers.math_b;
 
-                        let index = match self.get_last_ready_index() {
-                            Some(i) => i,
-                            None => return Err("Nenhuma função em execução".to_owned())
-                        };
+                self.registers.math_b = value;
 
-                        let id = self.add_special_item(index, SpecialItemData::List(data))?;
+                self.run(Instruction::PrintMathBDebug)?;
 
-                        Ok(DynamicValue::List(id))
-                    }
-                    _ => return Err("Operação não suportada entre Listas e outros valores".to_owned())
-                }
+                self.registers.math_b = tmp;
             }
-            DynamicValue::Null => Ok(DynamicValue::Null),
         }
+
+        Ok(())
     }
 
-    fn sub_values(&mut self, left : DynamicValue, right : DynamicValue) -> Result<DynamicValue, String> {
-        if ! VirtualMachine::is_compatible(left, right) {
-            return Err(format!("Add : Os valores não são compatíveis : {:?} e {:?}", left, right));
+    /// Every item alive in the special storage, with who references it
+    pub fn heap_items(&self) -> Vec<HeapItem> {
+        let mut items = vec![];
+
+        for item in self.special_storage.sorted_items() {
+            let (kind, size) = match item.data {
+                SpecialItemData::Text(ref t) => ("Texto", t.chars().count()),
+                SpecialItemData::List(ref l) => ("Lista", l.len()),
+                SpecialItemData::Sequence(_) => ("Sequência", 0),
+                SpecialItemData::File(_) => ("Arquivo", 0),
+                #[cfg(feature = "sqlite")]
+                SpecialItemData::Database(_) => ("Banco", 0),
+                SpecialItemData::Map(ref m) => ("Mapa", m.len()),
+            };
+
+            items.push(HeapItem { id : item.item_id, kind, size, ref_count : item.ref_count, owners : self.owners_of(item.item_id) });
         }
 
-        match left {
-            DynamicValue::Integer(l_i) => {
-                match right {
-                    DynamicValue::Integer(r_i) => Ok(DynamicValue::Integer(l_i - r_i)),
-                    DynamicValue::Number(r_n) => Ok(DynamicValue::Number((l_i as f64) - r_n)),
-                    _ => return Err("Incompatível. Não deveria chegar aqui.".to_owned()),
+        items
+    }
+
+    /// Where the item with the given id is referenced : variables in the frames and values of lists and maps
+    fn owners_of(&self, id : u64) -> Vec<String> {
+        let refers = |value : &DynamicValue| match *value {
+            DynamicValue::Text(i) | DynamicValue::List(i) | DynamicValue::Sequence(i) | DynamicValue::File(i) | DynamicValue::Database(i) | DynamicValue::Map(i) => i == id,
+            _ => false,
+        };
+
+        let mut owners = vec![];
+
+        for (index, frame) in self.callstack.iter().enumerate() {
+            for (address, value) in frame.stack.iter().enumerate() {
+                if ! refers(value) {
+                    continue;
+                }
+
+                let name = self.variable_names.get(frame.id)
+                    .and_then(|names| names.iter().find(|&(_, &a)| a == address).map(|(n, _)| n.clone()));
+
+                match name {
+                    Some(name) => owners.push(format!("variável {} (frame {})", name, index)),
+                    None => owners.push(format!("endereço {} (frame {})", address, index)),
                 }
             }
-            DynamicValue::Number(l_n) => {
-                match right {
-                    DynamicValue::Integer(r_i) => Ok(DynamicValue::Number(l_n - (r_i as f64))),
-                    DynamicValue::Number(r_n) => Ok(DynamicValue::Number(l_n - r_n)),
-                    _ => return Err("Incompatível. Não deveria chegar aqui.".to_owned()),
+        }
+
+        for item in self.special_storage.sorted_items() {
+            match item.data {
+                SpecialItemData::List(ref list) if list.iter().any(|v| refers(v)) => {
+                    owners.push(format!("lista #{}", item.item_id));
+                }
+                SpecialItemData::Map(ref map) if map.values().any(&refers) => {
+                    owners.push(format!("mapa #{}", item.item_id));
                 }
+                _ => {}
             }
-            DynamicValue::Text(_) => return Err("Operação inválida em texto : -".to_owned()),
-            DynamicValue::Null => Ok(DynamicValue::Null),
-            DynamicValue::List(_) => return Err("Operação não suportada em listas".to_owned())
         }
+
+        owners
     }
 
-    fn mul_values(&mut self, left : DynamicValue, right : DynamicValue) -> Result<DynamicValue, String> {
-        if ! VirtualMachine::is_compatible(left, right) {
-            return Err(format!("Add : Os valores não são compatíveis : {:?} e {:?}", left, right));
+    /// A report of every item alive in the special storage, one per line
+    pub fn dump_heap(&self) -> String {
+        let items = self.heap_items();
+        let mut report = format!("Itens vivos : {}\n", items.len());
+
+        for item in items {
+            report.push_str(&format!("  {}\n", item));
         }
 
-        match left {
-            DynamicValue::Integer(l_i) => {
-                match right {
-                    DynamicValue::Integer(r_i) => Ok(DynamicValue::Integer(l_i * r_i)),
-                    DynamicValue::Number(r_n) => Ok(DynamicValue::Number((l_i as f64) * r_n)),
-                    _ => return Err("Incompatível. Não deveria chegar aqui.".to_owned()),
-                }
+        report
+    }
+
+    /// Items alive that can't be reached from the global variables (directly or through lists and maps). Once the program
+    /// ended, these should have been freed, so they're leaks
+    pub fn find_leaks(&self) -> Vec<HeapItem> {
+        let mut reachable = vec![];
+        let mut pending = vec![];
+
+        if let Some(global) = self.callstack.first() {
+            pending.extend(global.stack.iter().cloned());
+        }
+
+        while let Some(value) = pending.pop() {
+            let id = match value {
+                DynamicValue::Text(id) | DynamicValue::List(id) | DynamicValue::Sequence(id) | DynamicValue::File(id) | DynamicValue::Database(id) | DynamicValue::Map(id) => id,
+                _ => continue,
+            };
+
+            if reachable.contains(&id) {
+                continue;
             }
-            DynamicValue::Number(l_n) => {
-                match right {
-                    DynamicValue::Integer(r_i) => Ok(DynamicValue::Number(l_n * (r_i as f64))),
-                    DynamicValue::Number(r_n) => Ok(DynamicValue::Number(l_n * r_n)),
-                    _ => return Err("Incompatível. Não deveria chegar aqui.".to_owned()),
-                }
+
+            reachable.push(id);
+
+            if let Some(data) = self.special_storage.get_data_ref(id) {
+                pending.extend(data.held_values());
             }
-            DynamicValue::Text(_) => return Err("Operação inválida em texto : *".to_owned()),
-            DynamicValue::Null => Ok(DynamicValue::Null),
-            DynamicValue::List(_) => return Err("Operação não suportada em listas".to_owned())
         }
+
+        self.heap_items().into_iter().filter(|item| ! reachable.contains(&item.id)).collect()
     }
 
-    fn div_values(&mut self, left : DynamicValue, right : DynamicValue) -> Result<DynamicValue, String> {
-        if ! VirtualMachine::is_compatible(left, right) {
-            return Err(format!("Add : Os valores não são compatíveis : {:?} e {:?}", left, right));
+    /// Check that the ref count of every item matches the number of references to it (in the variables of every frame,
+    /// in lists and maps, in the arguments waiting for (or lent to) a plugin and in SpecialRefs), panicking with the history of the items
+    /// that don't. Run every time a function returns
+    #[cfg(feature = "refcount-debug")]
+    fn check_ref_counts(&self) {
+        if self.special_storage.is_arena() {
+            return;
         }
 
-        match left {
-            DynamicValue::Integer(l_i) => {
-                match right {
-                    DynamicValue::Integer(r_i) => Ok(DynamicValue::Integer(l_i / r_i)),
-                    DynamicValue::Number(r_n) => Ok(DynamicValue::Number((l_i as f64) / r_n)),
-                    _ => return Err("Incompatível. Não deveria chegar aqui.".to_owned()),
+        let mut references : HashMap<u64, u64> = HashMap::new();
+
+        {
+            let mut count = |value : &DynamicValue| {
+                if let DynamicValue::Text(id) | DynamicValue::List(id) | DynamicValue::Sequence(id) | DynamicValue::File(id) | DynamicValue::Database(id) | DynamicValue::Map(id) = *value {
+                    *references.entry(id).or_insert(0) += 1;
                 }
+            };
+
+            self.callstack.iter().flat_map(|frame| frame.stack.iter().chain(frame.return_values.iter())).for_each(&mut count);
+            self.plugin_argument_stack.iter().for_each(&mut count);
+            self.lent_plugin_arguments.iter().for_each(&mut count);
+            self.return_values.iter().for_each(&mut count);
+
+            for item in self.special_storage.items.values() {
+                item.data.held_values().iter().for_each(&mut count);
             }
-            DynamicValue::Number(l_n) => {
-                match right {
-                    DynamicValue::Integer(r_i) => Ok(DynamicValue::Number(l_n / (r_i as f64))),
-                    DynamicValue::Number(r_n) => Ok(DynamicValue::Number(l_n / r_n)),
-                    _ => return Err("Incompatível. Não deveria chegar aqui.".to_owned()),
-                }
-            }
-            DynamicValue::Text(_) => return Err("Operação inválida em texto : /".to_owned()),
-            DynamicValue::Null => Ok(DynamicValue::Null),
-            DynamicValue::List(_) => return Err("Operação não suportada em listas".to_owned())
         }
-    }
 
-    fn get_last_comparision(&self) -> Result<Comparision, String> {
-        if self.callstack.is_empty() {
-            return Err("Callstack vazia".to_owned());
+        for &id in &self.retained_refs {
+            *references.entry(id).or_insert(0) += 1;
         }
 
-        match self.callstack.last().unwrap().last_comparision {
-            Some(c) => Ok(c),
-            None => Err("Nenhuma comparação na função atual".to_owned())
-        }
-    }
+        let mut report = String::new();
 
-    fn compare(&self, left : DynamicValue, right : DynamicValue) -> Result<Comparision, String> {
-        let comp_numbers: fn(f64, f64) -> Comparision = | l, r | {
-            if l == r {
-                Comparision::Equal
-            } else if l < r {
-                Comparision::LessThan
-            } else {
-                Comparision::MoreThan
-            }
-        };
+        for item in self.heap_items() {
+            let found = references.remove(&item.id).unwrap_or(0);
 
-        let comp = match left {
-            DynamicValue::Integer(l_i) => {
-                match right {
-                    DynamicValue::Integer(r_i) => {
-                        if l_i == r_i {
-                            Comparision::Equal
-                        } else if l_i < r_i {
-                            Comparision::LessThan
-                        } else {
-                            Comparision::MoreThan
-                        }
-                    }
-                    DynamicValue::Number(r_n) => comp_numbers(l_i as f64, r_n),
-                    _ => Comparision::NotEqual
-                }
-            }
-            DynamicValue::Number(l_n) => {
-                match right {
-                    DynamicValue::Number(r_n) => {
-                        comp_numbers(l_n, r_n)
-                    }
-                    DynamicValue::Integer(r_i) => {
-                        comp_numbers(l_n, r_i as f64)
-                    }
-                    _ => Comparision::NotEqual,
-                }
+            if found != item.ref_count {
+                report.push_str(&format!("{}, mas {} referência(s) encontrada(s)\n", item, found));
+                self.push_operation_log(&mut report, item.id);
             }
-            DynamicValue::Text(l_t) => {
-                match right {
-                    DynamicValue::Text(r_t) => {
-                        let ltext = match self.special_storage.get_data_ref(l_t) {
-                            Some(s) => match s {
-                                &SpecialItemData::Text(ref s) => s,
-                                _ => return Err(format!("Erro interno : DynamicValue é texto, mas o id aponta pra outra coisa"))
-                            },
-                            None => return Err(format!("Erro : TextID não encontrada : {}", l_t)),
-                        };
+        }
 
-                        let rtext = match self.special_storage.get_data_ref(r_t) {
-                            Some(s) => match s {
-                                &SpecialItemData::Text(ref s) => s,
-                                _ => return Err(format!("Erro interno : DynamicValue é texto, mas o id aponta pra outra coisa"))
-                            },
-                            None => return Err(format!("Erro : TextID não encontrada : {}", r_t)),
-                        };
+        // What's left references items that were already freed
+        for (id, found) in references {
+            report.push_str(&format!("#{} já foi liberado, mas ainda tem {} referência(s) : {}\n", id, found,
+                                     self.owners_of(id).join(", ")));
+            self.push_operation_log(&mut report, id);
+        }
 
-                        let llen = ltext.len();
-                        let rlen = rtext.len();
+        if ! report.is_empty() {
+            panic!("Contagem de referências errada ao sair de uma função :\n{}", report);
+        }
+    }
 
-                        if llen > rlen {
-                            Comparision::MoreThan
-                        } else if llen < rlen {
-                            Comparision::LessThan
-                        } else {
-                            if ltext == rtext {
-                                Comparision::Equal
-                            } else {
-                                Comparision::NotEqual
-                            }
-                        }
-                    }
-                    _ => Comparision::NotEqual
-                }
+    #[cfg(feature = "refcount-debug")]
+    fn push_operation_log(&self, report : &mut String, id : u64) {
+        for operation in self.special_storage.operation_log(id) {
+            for line in operation.to_string().lines() {
+                report.push_str(&format!("    {}\n", line));
             }
-            DynamicValue::List(left_id) => {
-                match right {
-                    DynamicValue::List(right_id) => {
-                        let left_list = match self.special_storage.get_data_ref(left_id) {
-                            Some(SpecialItemData::List(ref list)) => list.clone(),
-                            Some(_) => return Err("Erro interno : DynamicValue é uma lista mas o item guardado não".to_owned()),
-                            None => return Err("ID não existe".to_owned())
-                        };
+        }
+    }
 
-                        let right_list = match self.special_storage.get_data_ref(right_id) {
-                            Some(SpecialItemData::List(ref list)) => list.clone(),
-                            Some(_) => return Err("Erro interno : DynamicValue é uma lista mas o item guardado não".to_owned()),
-                            None => return Err("ID não existe".to_owned())
-                        };
+    /// The last failed TEM QUE SER, if there was one since the last call
+    pub fn take_assertion_failure(&mut self) -> Option<AssertionFailure> {
+        self.assertion_failure.take()
+    }
 
-                        if left_list.len() != right_list.len() {
-                            Comparision::NotEqual
-                        } else {
+    /// Convert a value to the form kept in an assertion failure
+    fn asserted_value(&mut self, val : DynamicValue) -> Result<AssertedValue, String> {
+        match val {
+            DynamicValue::Text(_) => Ok(AssertedValue::Text(self.conv_to_string(val)?)),
+            DynamicValue::List(id) => {
+                let list = match self.special_storage.get_data_ref(id) {
+                    Some(SpecialItemData::List(ref list)) => list.clone(),
+                    Some(_) => return Err("Erro interno : DynamicValue é uma lista, item interno não".to_owned()),
+                    None => return Err("ID inválida pra lista".to_owned())
+                };
 
-                            for i in 0..left_list.len() {
-                                match self.compare(*left_list[i], *right_list[i]) {
-                                    Ok(Comparision::Equal) => {},
-                                    Ok(_) => return Ok(Comparision::NotEqual),
-                                    Err(e) => return Err(e)
-                                }
-                            }
+                let mut elements = vec![];
 
-                            Comparision::Equal
-                        }
-                    }
-                    _ => Comparision::NotEqual,
-                }
-            }
-            DynamicValue::Null => {
-                match right {
-                    DynamicValue::Null => Comparision::Equal,
-                    _ => Comparision::NotEqual,
+                for item in list {
+                    let element = match *item {
+                        DynamicValue::Text(_) => format!("\"{}\"", self.conv_to_string(*item)?),
+                        _ => self.conv_to_string(*item)?,
+                    };
+
+                    elements.push(element);
                 }
+
+                Ok(AssertedValue::List(elements))
             }
-        };
+            _ => Ok(AssertedValue::Other(self.conv_to_string(val)?)),
+        }
+    }
 
-        Ok(comp)
+    /// Execute instructions until something stops the execution (a Halt, Quit, breakpoint, interruption...) and return
+    /// why it stopped. Calling it again continues from where it stopped
+    pub fn resume(&mut self) -> Result<ExecutionStatus, BirlError> {
+        loop {
+            match self.step()? {
+                ExecutionStatus::Normal | ExecutionStatus::Returned => {}
+                status => return Ok(status),
+            }
+        }
     }
 
-    fn set_last_comparision(&mut self, comp : Comparision) -> Result<(), String> {
-        if self.callstack.is_empty() {
-            return Err("Callstack tá vazia. Provavelmente é erro interno".to_owned());
+    /// Like resume, but execute at most `count` instructions. If they all ran and nothing stopped the execution, returns
+    /// Paused, and the next call (or resume) goes on from there. For hosts that run many programs taking turns, like
+    /// a game engine running some instructions of each script every frame
+    pub fn run_steps(&mut self, count : usize) -> Result<ExecutionStatus, BirlError> {
+        for _ in 0..count {
+            match self.step()? {
+                ExecutionStatus::Normal | ExecutionStatus::Returned => {}
+                status => return Ok(status),
+            }
         }
 
-        self.callstack.last_mut().unwrap().last_comparision = Some(comp);
+        self.stop_reason = Some(ExecutionStatus::Paused);
 
-        Ok(())
+        Ok(ExecutionStatus::Paused)
     }
 
-    // This function doesn't search all the callstack, just the first frame
-    fn get_last_ready_index(&self) -> Option<usize> {
-        if self.callstack.is_empty() {
-            None
-        }
-        else if self.callstack.len() < 2 {
-            if self.callstack[0].ready {
-                Some(0)
-            } else {
-                None
-            }
-        } else {
-            let last = self.callstack.len() - 1;
+    /// run_steps with the error as a status, for hosts that match a single value every turn
+    pub fn run_for(&mut self, count : usize) -> ExecutionStatus {
+        ExecutionStatus::from(self.run_steps(count))
+    }
 
-            if self.callstack[last].ready {
-                Some(last)
-            } else {
-                Some(last - 1)
-            }
-        }
+    /// Continue after stopping at a breakpoint, until the next one (or anything else that stops `resume`). The same as
+    /// resume, for debuggers
+    pub fn continue_run(&mut self) -> Result<ExecutionStatus, BirlError> {
+        self.resume()
     }
 
-    fn write_to(&mut self, val : DynamicValue, stack_index : usize, address : usize) -> Result<(), String> {
-        if self.callstack.len() <= stack_index {
-            return Err(format!("Index de frame inválido : {}", stack_index));
+    /// Execute a single instruction, unless the execution was interrupted or is at a breakpoint, keeping the reason if
+    /// it stopped. After stopping at a breakpoint, the next step runs the instruction
+    pub fn step(&mut self) -> Result<ExecutionStatus, BirlError> {
+        if self.interrupt.swap(false, Ordering::SeqCst) {
+            self.stop_reason = Some(ExecutionStatus::Interrupted);
+
+            return Ok(ExecutionStatus::Interrupted);
         }
 
-        let frame = &mut self.callstack[stack_index];
+        if ! self.on_breakpoint && ! self.breakpoints.is_empty() {
+            let at_breakpoint = match self.current_location() {
+                Some(location) => self.breakpoints.contains(&location),
+                None => false,
+            };
 
-        if frame.stack.len() <= address {
-            return Err("Endereço out-of-bounds".to_owned());
+            if at_breakpoint {
+                self.on_breakpoint = true;
+                self.stop_reason = Some(ExecutionStatus::Breakpoint);
+
+                return Ok(ExecutionStatus::Breakpoint);
+            }
         }
 
-        // Check if the value we're writing to is a special item
-        // if it is, we need to decrement it first
+        self.on_breakpoint = false;
 
-        match frame.stack[address] {
-            DynamicValue::List(id) => self.special_storage.decrement_ref(id)?,
-            DynamicValue::Text(id) => self.special_storage.decrement_ref(id)?,
-            _ => {}
-        };
+        // Only between instructions, when every value in use is somewhere the collector can find it
+        let result = self.execute_next_instruction()
+            .and_then(|status| self.collect_garbage_if_needed().map(|_| status).map_err(BirlError::from));
 
-        // If the value we're writing is a special item, increment its ref count
+        match result {
+            Ok(ExecutionStatus::Normal) | Ok(ExecutionStatus::Returned) => {}
+            Ok(ref status) => self.stop_reason = Some(status.clone()),
+            Err(ref e) => self.stop_reason = Some(ExecutionStatus::Error(Box::new(e.clone()))),
+        }
 
-        match val {
-            DynamicValue::List(id) => self.special_storage.increment_ref(id)?,
-            DynamicValue::Text(id) => self.special_storage.increment_ref(id)?,
-            _ => {}
-        };
+        result
+    }
 
-        frame.stack[address] = val;
+    /// Stop the execution before the instruction with the given index of a code runs, with ExecutionStatus::Breakpoint.
+    /// The ids of the functions are in `Context::compiled_functions`, and the first instruction of a line can be found
+    /// with `source_line_at`
+    pub fn set_breakpoint(&mut self, id : usize, instruction : usize) {
+        self.breakpoints.insert((id, instruction));
+    }
 
-        Ok(())
+    /// Remove a breakpoint, returning whether there was one there
+    pub fn remove_breakpoint(&mut self, id : usize, instruction : usize) -> bool {
+        self.breakpoints.remove(&(id, instruction))
     }
 
-    fn increase_skip_level(&mut self) -> Result<(), String> {
-        match self.get_last_ready_mut() {
-            Some(f) => f.skip_level += 1,
-            None => return Err("Nenhuma função ready em execução".to_owned())
-        }
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
 
-        Ok(())
+    /// The id of the code running and the index of the next instruction in it, or None if nothing is running
+    pub fn current_location(&self) -> Option<(usize, usize)> {
+        self.get_last_ready_ref().map(|frame| (frame.id, frame.program_counter))
     }
 
-    fn decrease_skip_level(&mut self) -> Result<(), String> {
-        match self.get_last_ready_mut() {
-            Some(f) => f.skip_level -= 1,
-            None => return Err("Nenhuma função ready em execução".to_owned())
-        }
+    /// The stack of the function running, each slot being the value at that address
+    pub fn current_frame_stack(&self) -> Option<&[DynamicValue]> {
+        self.get_last_ready_ref().map(|frame| frame.stack.as_slice())
+    }
 
-        Ok(())
+    /// Value of the global variable at `address`
+    pub fn global_variable(&self, address : usize) -> Option<DynamicValue> {
+        self.callstack.first().and_then(|frame| frame.stack.get(address).cloned())
     }
 
-    fn read_from_id(&mut self, index : usize, address : usize) -> Result<DynamicValue, String> {
-        if self.callstack.len() < index {
-            return Err(format!("Index out of bounds for read : {}", index));
-        }
+    /// The variables of the function running, with their values, in the order of their addresses
+    pub fn current_frame_variables(&self) -> Vec<(String, DynamicValue)> {
+        let frame = match self.get_last_ready_ref() {
+            Some(f) => f,
+            None => return vec![],
+        };
 
-        let val = {
+        let mut variables = match self.variable_names.get(frame.id) {
+            Some(names) => names.iter()
+                .filter_map(|(name, &address)| frame.stack.get(address).map(|value| (address, name.clone(), *value)))
+                .collect::<Vec<(usize, String, DynamicValue)>>(),
+            None => vec![],
+        };
 
-            let frame = &mut self.callstack[index];
+        variables.sort_by_key(|&(address, _, _)| address);
 
-            if frame.stack.len() <= address {
-                return Err("Erro : Endereço pra variável é inválido".to_owned());
-            }
+        variables.into_iter().map(|(_, name, value)| (name, value)).collect()
+    }
 
-            frame.stack[address]
-        };
+    /// The value as a text, like MUDA PRA TEXTO gives, so a debugger can show it
+    pub fn value_to_string(&mut self, value : DynamicValue) -> Result<String, String> {
+        self.conv_to_string(value)
+    }
 
-        Ok(val)
+    /// Iterate over the execution, one instruction at a time. See Steps
+    pub fn steps(&mut self) -> Steps<'_> {
+        Steps { vm : self, finished : false }
     }
 
-    pub fn unset_quit(&mut self) {
-        self.registers.has_quit = false;
+    /// Why the last call to resume stopped, or None if it was never called
+    pub fn stop_reason(&self) -> Option<ExecutionStatus> {
+        self.stop_reason.clone()
     }
 
-    pub fn has_quit(&self) -> bool {
-        self.registers.has_quit
+    /// Handle that can be used to interrupt the execution (even from another thread). When set, `resume` stops before
+    /// the next instruction with ExecutionStatus::Interrupted, and the flag is cleared
+    pub fn interrupt_handle(&self) -> Arc<AtomicBool> {
+        self.interrupt.clone()
     }
 
-    pub fn get_current_pc(&self) -> Option<usize> {
-        match self.get_last_ready_ref() {
-            Some(f) => Some(f.program_counter),
-            None => None
+    /// Check if a text with `size` bytes is within the limit
+    pub fn check_text_size(&self, size : usize) -> Result<(), String> {
+        match self.registers.max_text_size {
+            Some(max) if size > max => Err(format!("O texto ficaria com {} bytes, mas o máximo permitido é {}. \
+                                                    Tá crescendo sem parar, cumpade?", size, max)),
+            _ => Ok(()),
         }
     }
 
-    pub fn increment_pc(&mut self) -> Result<(), String> {
-        match self.get_last_ready_mut() {
-            Some(f) => f.program_counter += 1,
-            None => return Err("Nenhuma função em execução".to_owned())
+    /// Check if a list with `size` elements is within the limit
+    pub fn check_list_size(&self, size : usize) -> Result<(), String> {
+        match self.registers.max_list_size {
+            Some(max) if size > max => Err(format!("A lista ficaria com {} elementos, mas o máximo permitido é {}. \
+                                                    Tá crescendo sem parar, cumpade?", size, max)),
+            _ => Ok(()),
         }
+    }
 
-        Ok(())
+    fn add_special_item(&mut self, frame_index : usize, data : SpecialItemData) -> Result<u64, String> {
+        if self.callstack.len() <= frame_index {
+            return Err("add_special_item : Index é inválido".to_owned());
+        }
+
+        match data {
+            SpecialItemData::Text(ref t) => self.check_text_size(t.len())?,
+            SpecialItemData::List(ref l) => self.check_list_size(l.len())?,
+            _ => {}
+        }
+
+        if let Some(max) = self.registers.max_special_items {
+            if self.special_storage.len() >= max {
+                return Err(format!("Limite de textos e listas alocados ao mesmo tempo atingido ({})", max));
+            }
+        }
+
+        self.callstack[frame_index].num_special_items += 1;
+
+        Ok(self.special_storage.add(data, 0u64))
     }
 
-    pub fn decrement_pc(&mut self) -> Result<(), String> {
-        match self.get_last_ready_mut() {
-            Some(f) => f.program_counter -= 1,
-            None => return Err("Nenhuma função em execução".to_owned())
+    /// The order of two elements of a list being sorted without a function : numbers (integers or not) by value, and
+    /// texts by their characters, one by one
+    fn natural_order(&self, left : DynamicValue, right : DynamicValue) -> Result<std::cmp::Ordering, String> {
+        let number = |value : DynamicValue| match value {
+            DynamicValue::Integer(i) => Some(i as f64),
+            DynamicValue::Number(n) => Some(n),
+            _ => None,
+        };
+
+        if let (DynamicValue::Integer(l), DynamicValue::Integer(r)) = (left, right) {
+            return Ok(l.cmp(&r));
         }
 
-        Ok(())
+        if let (Some(l), Some(r)) = (number(left), number(right)) {
+            return l.partial_cmp(&r).ok_or_else(|| "ORDENA não sabe onde colocar um NaN".to_owned());
+        }
+
+        let text = |id : u64| match self.special_storage.get_data_ref(id) {
+            Some(SpecialItemData::Text(ref t)) => Ok(t),
+            Some(_) => Err("Erro interno : DynamicValue é texto, mas o id aponta pra outra coisa".to_owned()),
+            None => Err(format!("Erro : TextID não encontrada : {}", id)),
+        };
+
+        match (left, right) {
+            (DynamicValue::Text(l), DynamicValue::Text(r)) => Ok(text(l)?.cmp(text(r)?)),
+            _ => Err(format!("Sem uma função pra comparar, ORDENA só ordena números com números e textos com textos, \
+                              mas a lista tem {} e {}", left.get_kind(), right.get_kind())),
+        }
     }
 
-    fn conv_to_string(&mut self, val : DynamicValue) -> Result<String, String> {
-        match val {
-            DynamicValue::Text(t) => {
-                let s = match self.special_storage.get_data_ref(t) {
-                    Some(s) => match s {
-                        &SpecialItemData::Text(ref s) => s,
-                        _ => return Err(format!("Erro interno : DynamicValue é texto, mas o id aponta pra outra coisa"))
-                    },
-                    None => return Err("Invalid string ID".to_owned()),
-                };
-
-                Ok(s.clone())
-            }
-            DynamicValue::Integer(i) => Ok(format!("{}", i)),
-            DynamicValue::Number(n) => Ok(format!("{}", n)),
-            DynamicValue::Null => Ok(String::from("<Null>")),
-            DynamicValue::List(id) => {
-                let list = match self.special_storage.get_data_ref(id) {
-                    Some(SpecialItemData::List(ref list)) => list.clone(),
-                    Some(_) => return Err("Erro interno : DynamicValue é uma lista, item interno não".to_owned()),
-                    None => return Err("ID inválida pra lista".to_owned())
-                };
-                
-                let mut result = String::from("[ ");
-                let mut first = true;
-
-                for item in list {
-                    if !first {
-                        result.push_str(", ");
-                    } else {
-                        first = false;
-                    }
+    /// The order given by a BIRL function that compares two elements, returning a negative number if the first goes
+    /// before the second, a positive one if it goes after, or 0 if either way is fine
+    fn comparator_order(&mut self, function : &str, left : DynamicValue, right : DynamicValue)
+            -> Result<std::cmp::Ordering, String> {
+        let result = self.call_function(function, vec![left, right])?;
 
-                    // kek
-                    let is_str = if let DynamicValue::Text(_) = *item {
-                        true
-                    } else {
-                        false
-                    };
+        let order = match result {
+            DynamicValue::Integer(i) => Some(i.cmp(&0)),
+            DynamicValue::Number(n) => n.partial_cmp(&0.0),
+            _ => None,
+        };
 
-                    let s = self.conv_to_string(*item)?;
+        order.ok_or_else(|| format!("A função {} tem que retornar um número (negativo, 0 ou positivo) pra ORDENA, mas \
+                                     retornou {}", function, result.get_kind()))
+    }
 
-                    if is_str {
-                        result.push_str("\"");
-                    }
+    /// Merge sort, which keeps equal elements in the order they were and can stop at an error in the comparision
+    fn merge_sort(&mut self, mut values : Vec<DynamicValue>, comparator : Option<&str>)
+            -> Result<Vec<DynamicValue>, String> {
+        if values.len() <= 1 {
+            return Ok(values);
+        }
 
-                    result.push_str(s.as_str());
+        let right = values.split_off(values.len() / 2);
+        let left = self.merge_sort(values, comparator)?;
+        let right = self.merge_sort(right, comparator)?;
 
-                    if is_str {
-                        result.push_str("\"");
-                    }
-                }
+        let mut merged = Vec::with_capacity(left.len() + right.len());
+        let (mut left, mut right) = (left.into_iter().peekable(), right.into_iter().peekable());
 
-                result.push_str(" ]");
+        while let (Some(&l), Some(&r)) = (left.peek(), right.peek()) {
+            let order = match comparator {
+                Some(function) => self.comparator_order(function, l, r)?,
+                None => self.natural_order(l, r)?,
+            };
 
-                Ok(result)
+            if order == std::cmp::Ordering::Greater {
+                merged.push(r);
+                right.next();
+            } else {
+                merged.push(l);
+                left.next();
             }
         }
+
+        merged.extend(left);
+        merged.extend(right);
+
+        Ok(merged)
     }
 
-    fn conv_to_int(&mut self, val : DynamicValue) -> Result<IntegerType, String> {
-        match val {
-            DynamicValue::Text(t) => {
-                let text = match self.special_storage.get_data_ref(t) {
-                    Some(s) => match s {
-                        &SpecialItemData::Text(ref s) => s,
-                        _ => return Err(format!("Erro interno : DynamicValue é texto, mas o id aponta pra outra coisa"))
-                    },
-                    None => return Err("Invalid text id".to_owned())
-                };
+    /// Sort the list in place. The list is held while the comparator runs, and it can't be changed by it
+    fn sort_list(&mut self, id : u64, comparator : Option<&str>) -> Result<(), String> {
+        let original = match self.special_storage.get_data_ref(id) {
+            Some(SpecialItemData::List(ref list)) => list.iter().map(|v| **v).collect::<Vec<DynamicValue>>(),
+            Some(_) => return Err("Erro interno : DynamicValue é uma lista mas o valor na memória não".to_owned()),
+            None => return Err("Erro interno : ID não encontrada".to_owned()),
+        };
 
-                let i = match text.parse::<IntegerType>() {
-                    Ok(i) => i,
-                    Err(_) => return Err(format!("Não foi possível converter \"{}\" pra Int", text))
-                };
+        let held = self.retain(DynamicValue::List(id))?;
 
-                Ok(i)
-            }
-            DynamicValue::Number(n) => Ok(n as IntegerType),
-            DynamicValue::Integer(i) => Ok(i),
-            DynamicValue::Null => return Err("Convert : <Null>".to_owned()),
-            DynamicValue::List(_) => return Err("Não é possível converter uma lista pra inteiro".to_owned())
-        }
-    }
+        let sorted = self.merge_sort(original.clone(), comparator);
 
-    fn conv_to_num(&mut self, val : DynamicValue) -> Result<f64, String> {
-        match val {
-            DynamicValue::Text(t) => {
-                let text = match self.special_storage.get_data_ref(t) {
-                    Some(s) => match s {
-                        &SpecialItemData::Text(ref s) => s,
-                        _ => return Err(format!("Erro interno : DynamicValue é texto, mas o id aponta pra outra coisa"))
-                    },
-                    None => return Err("Invalid text id".to_owned())
-                };
+        let same = |a : DynamicValue, b : DynamicValue| match (a, b) {
+            (DynamicValue::Integer(x), DynamicValue::Integer(y)) => x == y,
+            (DynamicValue::Number(x), DynamicValue::Number(y)) => x.to_bits() == y.to_bits(),
+            (DynamicValue::Bool(x), DynamicValue::Bool(y)) => x == y,
+            (DynamicValue::Null, DynamicValue::Null) => true,
+            (x, y) => x.special_id().is_some() && x.special_id() == y.special_id(),
+        };
 
-                let n = match text.parse::<f64>() {
-                    Ok(n) => n,
-                    Err(_) => return Err(format!("Não foi possível converter \"{}\" pra Num", text))
-                };
+        let result = match (sorted, self.special_storage.get_data_mut(id)) {
+            (Err(e), _) => Err(e),
+            (Ok(sorted), Some(SpecialItemData::List(ref mut list))) => {
+                if list.len() == original.len() && original.iter().zip(list.iter()).all(|(&a, b)| same(a, **b)) {
+                    *list = sorted.into_iter().map(Box::new).collect();
 
-                Ok(n)
+                    Ok(())
+                } else {
+                    Err(format!("A lista mudou enquanto era ordenada pela função {}", comparator.unwrap_or_default()))
+                }
             }
-            DynamicValue::Number(n) => Ok(n),
-            DynamicValue::Integer(i) => Ok(i as f64),
-            DynamicValue::Null => return Err("Convert : <Null>".to_owned()),
-            DynamicValue::List(_) => return Err("Não é possível converter uma lista pra número".to_owned())
-        }
-    }
-
-    fn last_comparision_matches(&self, req : ComparisionRequest) -> Result<bool, String> {
-        let last = match self.get_last_comparision() {
-            Ok(c) => c,
-            Err(e) => return Err(e)
+            (Ok(_), _) => Err("Erro interno : A lista sumiu enquanto era ordenada".to_owned()),
         };
 
-        match req {
-            ComparisionRequest::Equal => Ok(last == Comparision::Equal),
-            ComparisionRequest::NotEqual => Ok(last != Comparision::Equal),
-            ComparisionRequest::Less => Ok(last == Comparision::LessThan),
-            ComparisionRequest::LessOrEqual => Ok(last == Comparision::LessThan || last == Comparision::Equal),
-            ComparisionRequest::More => Ok(last == Comparision::MoreThan),
-            ComparisionRequest::MoreOrEqual => Ok(last == Comparision::MoreThan || last == Comparision::Equal),
-        }
-    }
+        drop(held);
+        self.release_dropped_refs()?;
 
-    pub fn set_stack_size(&mut self, size : usize) {
-        self.registers.default_stack_size = size;
+        result
     }
 
-    fn set_current_pc(&mut self, pc : usize) -> Result<(), String> {
-        match self.get_last_ready_mut() {
-            Some(f) => f.program_counter = pc,
-            None => return Err("Nenhuma função em execução".to_owned())
-        };
-
-        Ok(())
+    /// The key of a map, which has to be a text
+    fn map_key(&self, value : DynamicValue) -> Result<String, String> {
+        match value {
+            DynamicValue::Text(id) => match self.special_storage.get_data_ref(id) {
+                Some(SpecialItemData::Text(ref t)) => Ok(t.clone()),
+                Some(_) => Err("Erro interno : DynamicValue é texto, mas o id aponta pra outra coisa".to_owned()),
+                None => Err(format!("Erro : TextID não encontrada : {}", id)),
+            },
+            _ => Err(format!("A chave de um mapa tem que ser um texto, mas foi usado um valor do tipo {}", value.get_kind())),
+        }
     }
 
-    pub fn print_string(&mut self, s : &str) -> Result<(), String> {
-        vm_write!(self.stdout, "{}", s)
+    fn map_in_intermediate(&self) -> Result<u64, String> {
+        match self.registers.intermediate {
+            DynamicValue::Map(id) => Ok(id),
+            _ => Err("A variável não é um mapa".to_owned()),
+        }
     }
 
-    pub fn print_value(&mut self, val : DynamicValue) -> Result<(), String> {
+    fn raw_to_dynamic(&mut self, val : RawValue) -> Result<DynamicValue, String> {
         match val {
-            DynamicValue::Integer(i) => vm_write!(self.stdout, "{}", i)?,
-            DynamicValue::Number(n) => vm_write!(self.stdout, "{}", n)?,
-            DynamicValue::Text(t) => {
-                let t = match self.special_storage.get_data_ref(t) {
-                    Some(s) => match s {
-                        &SpecialItemData::Text(ref s) => s,
-                        _ => return Err(format!("Erro interno : DynamicValue é texto, mas o id aponta pra outra coisa"))
-                    },
-                    None => return Err(format!("MainPrint : Não foi encontrado text com ID {}", t)),
+            RawValue::Text(t) => {
+                let parent_index = match self.get_last_ready_index() {
+                    Some(s) => s,
+                    None => 0,
                 };
 
-                vm_write!(self.stdout, "{}", t)?
-            }
-            DynamicValue::List(id) => {
-                let string = match self.conv_to_string(DynamicValue::List(id)) {
-                    Ok(s) => s,
+                let id = match self.add_special_item(parent_index, SpecialItemData::Text(t)) {
+                    Ok(id) => id,
                     Err(e) => return Err(e)
                 };
-                vm_write!(self.stdout, "(Lista) {}", string)?;
-            }
-            DynamicValue::Null => vm_write!(self.stdout, "<Null>")?,
-        }
 
-        Ok(())
+                Ok(DynamicValue::Text(id))
+            },
+            RawValue::Number(n) => Ok(DynamicValue::Number(n)),
+            RawValue::Integer(i) => Ok(DynamicValue::Integer(self.registers.integer_width.wrap(i))),
+            RawValue::Bool(b) => Ok(DynamicValue::Bool(b)),
+            RawValue::Null => Ok(DynamicValue::Null),
+        }
     }
 
-    pub fn run(&mut self, inst : Instruction) -> Result<ExecutionStatus, String> {
-        if self.get_current_skip_level() > 0 {
-            if let Instruction::EndConditionalBlock = inst {
-                self.decrease_skip_level()?;
-            }
+    pub fn set_interactive_mode(&mut self) {
+        self.registers.is_interactive = true;
+    }
 
-            return Ok(ExecutionStatus::Normal);
+    /// Execute the instruction at the PC. Errors have the line and the index of the instruction that failed
+    pub(crate) fn execute_next_instruction(&mut self) -> Result<ExecutionStatus, BirlError> {
+        if self.callstack.is_empty() {
+            return Err(BirlError::from("Nenhuma função em execução".to_owned()));
         }
 
-        match inst {
-            Instruction::EndConditionalBlock => {},
-            Instruction::PrintMathBDebug => {
-                match self.registers.math_b {
-                    DynamicValue::Integer(i) => vm_write!(self.stdout, "(Integer) {}\n", i)?,
-                    DynamicValue::Number(n) => vm_write!(self.stdout, "(Number) {}\n", n)?,
-                    DynamicValue::Text(t) => {
-                        let t = match self.special_storage.get_data_ref(t) {
-                            Some(s) => match s {
-                                &SpecialItemData::Text(ref s) => s,
-                                _ => return Err(format!("Erro interno : DynamicValue é texto, mas o id aponta pra outra coisa"))
-                            },
-                            None => return Err(format!("MainPrint : Não foi encontrado text com ID {}", t)),
-                        };
-
-                        vm_write!(self.stdout, "(Text) \"{}\"\n", t)?
-                    }
-                    DynamicValue::Null => vm_write!(self.stdout, "<Null>\n")?,
-                    DynamicValue::List(id) => {
-                        let string = match self.conv_to_string(DynamicValue::List(id)) {
-                            Ok(s) => s,
-                            Err(e) => return Err(e)
-                        };
-                        vm_write!(self.stdout, "{}\n", string)?;
-                    }
-                }
+        let pc = match self.get_current_pc() {
+            Some(p) => p,
+            None => return Err(BirlError::from("Nenhuma função em execução".to_owned())),
+        };
 
-                self.flush_stdout();
-            }
-            Instruction::PrintMathB => {
-                let val = self.registers.math_b;
+        let id = match self.get_current_id() {
+            Some(i) => i,
+            None => return Err(BirlError::from("Nenhuma função em execução".to_owned()))
+        };
 
-                self.print_value(val)?;
-            }
-            Instruction::PrintNewLine => {
-                vm_write!(self.stdout, "\n")?
-            }
-            Instruction::Quit => {
-                self.registers.has_quit = true;
+        if self.code.len() <= id {
+            return Err(BirlError::from("ID atual pra função é inválida".to_owned()));
+        }
 
-                return Ok(ExecutionStatus::Quit);
-            }
-            Instruction::FlushStdout => {
-                self.flush_stdout();
-            }
-            Instruction::Compare => {
-                let result = match self.compare(self.registers.math_a, self.registers.math_b) {
-                    Ok(c) => c,
-                    Err(e) => return Err(e),
-                };
+        // Ran past the end of the code (the global function has no return), so there's nothing left to do
+        if self.code[id].len() <= pc {
+            return Ok(ExecutionStatus::Halt);
+        }
 
-                match self.set_last_comparision(result) {
-                    Ok(_) => {}
-                    Err(e) => return Err(e)
-                }
+        if let Some(fuel) = self.registers.fuel {
+            if fuel == 0 {
+                return Ok(ExecutionStatus::OutOfFuel);
             }
-            Instruction::Return => {
 
-                if self.callstack.len() == 1 {
-                    self.registers.has_quit = true;
+            self.registers.fuel = Some(fuel - 1);
+        }
 
-                    return Ok(ExecutionStatus::Quit);
-                }
+        match self.increment_pc() {
+            Ok(_) => {}
+            Err(e) => return Err(BirlError::from(e)),
+        }
 
-                match self.callstack.pop() {
-                    Some(_) => {}
-                    None => return Err("Erro no return : Nenhuma função em execução".to_owned())
-                }
+        let instruction = self.code[id][pc].clone();
 
-                let index = self.callstack.len() - 1;
-                let val = self.registers.math_b;
-                match self.write_to(val, index, 0) {
-                    Ok(_) => {}
-                    Err(e) => return Err(e)
-                }
+        #[cfg(feature = "refcount-debug")]
+        self.special_storage.set_location(format!("função {}, instrução {} ({:?})", id, pc, instruction));
 
-                // If this is the global function and we're in interactive mode, print the return value
+        match self.run(instruction) {
+            Ok(status) => Ok(status),
+            Err(e) => Err(self.locate_error(e, id, pc)),
+        }
+    }
 
-                if self.callstack.len() == 1 && self.registers.is_interactive {
-                    self.run(Instruction::PrintMathBDebug)?; // Return val is in math_b already
-                }
+    /// Check if compiled code stamped with `version` can be loaded by this VM
+    pub fn check_instruction_set_version(version : u32) -> Result<(), String> {
+        if version == INSTRUCTION_SET_VERSION {
+            Ok(())
+        } else {
+            Err(format!("O código foi compilado pra versão {} do conjunto de instruções, mas essa VM roda a versão {}. \
+                         Compila de novo, cumpade", version, INSTRUCTION_SET_VERSION))
+        }
+    }
 
-                return Ok(ExecutionStatus::Returned);
-            }
-            Instruction::ExecuteIf(req) => {
-                if self.get_current_skip_level() > 0 {
-                    self.increase_skip_level()?;
-                } else {
-                    if ! self.last_comparision_matches(req)? {
-                        self.increase_skip_level()?;
-                    }
-                }
-            }
-            Instruction::MakeNewFrame(id) => {
-                // Add a new, not ready frame to the callstack
+    /// Replace the input and output of the VM, returning the previous one
+    pub fn set_io(&mut self, io : Box<dyn VmIo>) -> Box<dyn VmIo> {
+        use std::mem;
+        self.io_is_tty = io.is_tty();
+        mem::replace(&mut self.io, io)
+    }
 
-                let frame = FunctionFrame::new(id, self.registers.default_stack_size);
+    pub fn is_tty(&self) -> bool {
+        self.io.is_tty()
+    }
 
-                self.callstack.push(frame);
+    fn get_last_ready_ref(&self) -> Option<&FunctionFrame> {
+        let callstack = &self.callstack;
+        for frame in callstack.into_iter().rev() {
+            if frame.ready {
+                return Some(frame);
             }
-            Instruction::SetLastFrameReady => {
-                // Set the last frame to ready
+        }
+        None
+    }
 
-                if ! self.callstack.is_empty() {
-                    self.callstack.last_mut().unwrap().ready = true;
-                } else {
-                    return Err("Callstack vazia".to_owned());
-                }
+    fn get_last_ready_mut(&mut self) -> Option<&mut FunctionFrame> {
+        let callstack = &mut self.callstack;
+        for frame in callstack.into_iter().rev() {
+            if frame.ready {
+                return Some(frame);
             }
-            Instruction::AssertMathBCompatible(kind) => {
-                let v = self.registers.math_b;
+        }
+        None
+    }
 
-                match v {
-                    DynamicValue::Null => return Err("Tipo incompatível : Null".to_owned()),
-                    DynamicValue::Text(_) => {
-                        if kind == TypeKind::Text {
-                            // Ok
-                        } else {
-                            return Err("Tipo incompatível : Texto".to_owned());
-                        }
-                    }
-                    DynamicValue::Integer(_) => {
-                        if kind == TypeKind::Integer || kind == TypeKind::Number {
-                            // Ok
-                        } else {
-                            return Err("Tipo incompatível : Int ou Num".to_owned());
-                        }
-                    }
-                    DynamicValue::Number(_) => {
-                        if kind == TypeKind::Number {
-                            // Ok
-                        } else {
-                            return Err("Tipo incompatível : Number".to_owned());
-                        }
-                    }
-                    DynamicValue::List(_) => {
-                        if kind == TypeKind::List {
-                            // Ok
-                        } else {
-                            return Err("Tipo incompatível : Lista".to_owned());
+    fn get_current_id(&self) -> Option<usize> {
+        if self.callstack.is_empty() {
+            None
+        } else {
+            match self.get_last_ready_ref() {
+                Some(f) => Some(f.id),
+                None => None,
+            }
+        }
+    }
+
+    pub(crate) fn get_next_code_id(&self) -> usize {
+        self.registers.next_code_index
+    }
+
+    fn get_next_plugin_id(&self) -> usize {
+        self.registers.next_plugin_index
+    }
+
+    pub(crate) fn get_code_for(&mut self, id : usize) -> Option<&mut Vec<Instruction>> {
+        if self.code.len() <= id {
+            None
+        } else {
+            Some(&mut self.code[id])
+        }
+    }
+
+    pub(crate) fn get_code_ref(&self, id : usize) -> Option<&Vec<Instruction>> {
+        self.code.get(id)
+    }
+
+    pub(crate) fn add_new_code(&mut self) -> usize {
+        let id = self.registers.next_code_index;
+        self.registers.next_code_index += 1;
+        self.code.push(vec![]);
+        self.variable_names.push(HashMap::new());
+        self.source_lines.push(vec![]);
+        self.function_names.push(None);
+
+        id
+    }
+
+    /// Record that the instructions of the code starting at `first_instruction` came from the given line of the source
+    pub(crate) fn add_source_line(&mut self, id : usize, first_instruction : usize, line : usize) -> Result<(), String> {
+        match self.source_lines.get_mut(id) {
+            Some(lines) => lines.push((first_instruction, line)),
+            None => return Err(format!("ID inválido pra código : {}", id)),
+        }
+
+        Ok(())
+    }
+
+    /// Line of the source of the instruction being run, if known
+    pub fn current_source_line(&self) -> Option<usize> {
+        let id = self.get_current_id()?;
+        // The PC already points to the next instruction
+        let pc = self.get_current_pc()?.checked_sub(1)?;
+
+        self.source_line_at(id, pc)
+    }
+
+    /// Line of the source of an instruction of the code with the given id, if known
+    pub fn source_line_at(&self, id : usize, instruction : usize) -> Option<usize> {
+        self.source_lines.get(id)?.iter().rev().find(|&&(first, _)| first <= instruction).map(|&(_, line)| line)
+    }
+
+    /// Put where an error from an instruction happened in it, using the lines recorded when the code was compiled : the
+    /// line, the index of the instruction and the trace of the functions running (walking the callstack) in the location,
+    /// and the line and the function at the end of the message, like " (linha 3, na JAULA SHOW)". Code without lines
+    /// (typed in the interactive console, for example) only gets the instruction and the trace
+    fn locate_error(&mut self, mut error : BirlError, id : usize, pc : usize) -> BirlError {
+        let frames = self.callstack.iter().filter(|f| f.ready).collect::<Vec<&FunctionFrame>>();
+        // The PC points to the instruction after the one running
+        let failed = frames.iter().rposition(|f| f.id == id && f.program_counter == pc + 1);
+
+        // Frames above the one that failed are from a function called by Rust code (like a plugin), where the error
+        // really happened
+        if let Some(located) = self.located_error.take() {
+            if failed.map(|index| index + 1 < frames.len()).unwrap_or(false) && located.message() == error.message() {
+                return located;
+            }
+        }
+
+        let line = self.source_line_at(id, pc);
+        let function = self.function_names.get(id).cloned().unwrap_or_default();
+
+        // The functions that were running, ending with the one that failed. The frames that ran to the end of their code
+        // aren't calling anything, like the global code when SHOW is called by the host
+        let mut trace = frames[..failed.unwrap_or(frames.len())].iter()
+            .filter(|f| f.program_counter > 0 && self.code.get(f.id).map(|c| f.program_counter < c.len()).unwrap_or(false))
+            .map(|f| TraceEntry {
+                function : f.name.as_ref().map(|n| n.to_string()),
+                line : self.source_line_at(f.id, f.program_counter - 1),
+            })
+            .collect::<Vec<TraceEntry>>();
+
+        trace.push(TraceEntry { function : function.as_ref().map(|n| n.to_string()), line });
+
+        {
+            let location = error.location_mut();
+
+            location.function_id = Some(id);
+            location.instruction = Some(pc);
+            location.line = line;
+            location.trace = trace;
+        }
+
+        match (line, function) {
+            (Some(line), Some(name)) => error.message_mut().push_str(&format!(" (linha {}, na JAULA {})", line, name)),
+            (Some(line), None) => error.message_mut().push_str(&format!(" (linha {})", line)),
+            (None, _) => {}
+        }
+
+        self.located_error = Some(error.clone());
+
+        error
+    }
+
+    pub(crate) fn set_variable_names(&mut self, id : usize, names : HashMap<String, usize>) -> Result<(), String> {
+        if self.variable_names.len() <= id {
+            return Err(format!("ID inválido pra código : {}", id));
+        }
+
+        self.variable_names[id] = names;
+
+        Ok(())
+    }
+
+    /// Variables of the code with the given id and their addresses, as given to `set_variable_names`
+    pub fn variable_names(&self, id : usize) -> Option<&HashMap<String, usize>> {
+        self.variable_names.get(id)
+    }
+
+    /// Lines of the source recorded for the code with the given id, as pairs of the first instruction and the line
+    pub fn source_lines(&self, id : usize) -> Option<&[(usize, usize)]> {
+        self.source_lines.get(id).map(|lines| lines.as_slice())
+    }
+
+    /// Replace the lines of the source recorded for the code with the given id
+    pub(crate) fn set_source_lines(&mut self, id : usize, lines : Vec<(usize, usize)>) -> Result<(), String> {
+        match self.source_lines.get_mut(id) {
+            Some(l) => *l = lines,
+            None => return Err(format!("ID inválido pra código : {}", id)),
+        }
+
+        Ok(())
+    }
+
+    /// Name of the function with the given id, if it was made callable with `set_function`
+    pub fn function_name(&self, id : usize) -> Option<&str> {
+        self.function_names.get(id)?.as_ref().map(|name| &**name)
+    }
+
+    /// Parameters of the function with the given name
+    pub fn function_parameters(&self, name : &str) -> Option<&[TypeKind]> {
+        self.functions.get(name).map(|(_, parameters)| parameters.as_slice())
+    }
+
+    /// Make a function of the source callable by name, with `call_function`
+    pub(crate) fn set_function(&mut self, name : String, address : usize, parameters : Vec<TypeKind>) {
+        if let Some(slot) = self.function_names.get_mut(address) {
+            *slot = Some(Rc::from(name.as_str()));
+        }
+
+        self.functions.insert(name, (address, parameters));
+    }
+
+    /// Whether there's a function of the source or a plugin with a name with the given name
+    pub fn has_function(&self, name : &str) -> bool {
+        self.functions.contains_key(name) || self.plugin_names.contains_key(name)
+    }
+
+    /// Call a function of the source (or a plugin with a name) and run it until it returns, giving back what it
+    /// returned. This is how plugins call functions given to them by name (like MAPEIA). The value in TREZE is replaced
+    /// by the returned one
+    pub fn call_function(&mut self, name : &str, arguments : Vec<DynamicValue>) -> Result<DynamicValue, String> {
+        let (address, parameters) = match self.functions.get(name) {
+            Some(f) => f.clone(),
+            None if self.plugin_names.contains_key(name) => return self.call_plugin(name, arguments),
+            None => return Err(format!("A função {} não existe", name)),
+        };
+
+        if arguments.len() != parameters.len() {
+            return Err(format!("A função {} recebe {} argumentos, mas foram passados {}", name, parameters.len(), arguments.len()));
+        }
+
+        // The function may be called in the middle of an instruction, so the registers it uses are kept
+        let (math_a, intermediate, secondary) = (self.registers.math_a, self.registers.intermediate, self.registers.secondary);
+        let depth = self.callstack.len();
+
+        self.run(Instruction::MakeNewFrame(address))?;
+
+        for (index, (value, kind)) in arguments.into_iter().zip(parameters).enumerate() {
+            self.registers.math_b = value;
+
+            self.run(Instruction::AssertMathBCompatible(kind))?;
+            self.run(Instruction::WriteVarToLast(index + 1))?;
+        }
+
+        self.run(Instruction::SetLastFrameReady)?;
+
+        while self.callstack.len() > depth {
+            match self.execute_next_instruction()? {
+                ExecutionStatus::Normal | ExecutionStatus::Returned => {}
+                ExecutionStatus::OutOfFuel => return Err(format!("Acabou o combustível da VM na função {}", name)),
+                status => return Err(format!("A função {} parou antes de retornar ({:?})", name, status)),
+            }
+        }
+
+        self.registers.math_a = math_a;
+        self.registers.intermediate = intermediate;
+        self.registers.secondary = secondary;
+
+        Ok(self.registers.math_b)
+    }
+
+    /// Call a plugin added with a name, checking the arguments like CallPlugin does, and give back what it returned (or
+    /// Null). The arguments are only lent to it, like in CallPlugin, and go in the order of the parameters
+    pub fn call_plugin(&mut self, name : &str, arguments : Vec<DynamicValue>) -> Result<DynamicValue, String> {
+        let (address, parameters) = match self.plugin_names.get(name) {
+            Some(p) => p.clone(),
+            None => return Err(format!("O plugin {} não existe", name)),
+        };
+
+        if arguments.len() != parameters.len() {
+            return Err(format!("A função {} recebe {} argumentos, mas foram passados {}", name, parameters.len(), arguments.len()));
+        }
+
+        let (math_a, math_b, intermediate, secondary) =
+            (self.registers.math_a, self.registers.math_b, self.registers.intermediate, self.registers.secondary);
+
+        for (&value, &kind) in arguments.iter().zip(&parameters) {
+            self.registers.math_b = value;
+            self.run(Instruction::AssertMathBCompatible(kind))?;
+        }
+
+        self.registers.math_b = math_b;
+
+        // Plugins get the arguments from the last to the first, since they come from a stack
+        let plugin = self.plugins[address];
+        let result = plugin(arguments.into_iter().rev().collect(), self)?.unwrap_or(DynamicValue::Null);
+
+        if let Some(index) = self.callstack.len().checked_sub(1) {
+            self.write_to(result, index, 0)?;
+        }
+
+        self.release_dropped_refs()?;
+
+        self.registers.math_a = math_a;
+        self.registers.intermediate = intermediate;
+        self.registers.secondary = secondary;
+
+        Ok(result)
+    }
+
+    fn add_new_plugin(&mut self, plugin : PluginFunction) -> usize {
+        let id = self.get_next_plugin_id();
+        self.registers.next_plugin_index += 1;
+        self.plugins.push(plugin);
+
+        id
+    }
+
+    /// Add a plugin that can be called by name with `call_function` and `call_plugin` (and so by MAPEIA and the like),
+    /// with the parameters it takes. Returns its ID, for CallPlugin
+    pub fn add_named_plugin(&mut self, name : String, parameters : Vec<TypeKind>, plugin : PluginFunction) -> Result<usize, String> {
+        if self.plugin_names.contains_key(&name) {
+            return Err(format!("Já existe um plugin chamado {}", name));
+        }
+
+        let id = self.add_new_plugin(plugin);
+        self.plugin_names.insert(name, (id, parameters));
+
+        Ok(id)
+    }
+
+    /// The ID and the parameters of the plugin with the given name
+    pub fn get_plugin_by_name(&self, name : &str) -> Option<(usize, &[TypeKind])> {
+        self.plugin_names.get(name).map(|(id, parameters)| (*id, parameters.as_slice()))
+    }
+
+    // The registers and the special storage are how the VM works now, not something to depend on : they're public for
+    // the standard library, and plugins outside of it should use PluginContext (see the api module)
+    #[doc(hidden)]
+    pub fn get_registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    #[doc(hidden)]
+    pub fn get_special_storage_ref(&self) -> &SpecialStorage {
+        &self.special_storage
+    }
+
+    #[doc(hidden)]
+    pub fn get_special_storage_mut(&mut self) -> &mut SpecialStorage {
+        &mut self.special_storage
+    }
+
+    pub fn flush_stdout(&mut self) {
+        match self.io.flush() {
+            Ok(_) => {}
+            Err(_) => {}
+        }
+    }
+
+    /// Flush after a print, if the output is flushed automatically
+    fn auto_flush_stdout(&mut self) {
+        if self.registers.auto_flush.unwrap_or(self.io_is_tty) {
+            self.flush_stdout();
+        }
+    }
+
+    fn is_compatible(left : DynamicValue, right : DynamicValue) -> bool {
+        match left {
+            DynamicValue::Text(_) => {
+                if let DynamicValue::Text(_) = right {
+                    true
+                } else {
+                    false
+                }
+            }
+            DynamicValue::Integer(_) | DynamicValue::Number(_) => {
+                match right {
+                    DynamicValue::Integer(_) | DynamicValue::Number(_) => true,
+                    _ => false,
+                }
+            }
+            _ => false,
+        }
+    }
+
+    fn add_values(&mut self, left : DynamicValue, right : DynamicValue) -> Result<DynamicValue, String> {
+        if ! VirtualMachine::is_compatible(left, right) {
+            return Err(format!("Add : Os valores não são compatíveis : {:?} e {:?}", left, right));
+        }
+
+        match left {
+            DynamicValue::Integer(l_i) => {
+                match right {
+                    DynamicValue::Integer(r_i) => self.integer_operation(l_i, r_i, "+", IntegerType::checked_add,
+                                                                         IntegerType::wrapping_add, |l, r| l + r),
+                    DynamicValue::Number(r_n) => Ok(DynamicValue::Number((l_i as f64) + r_n)),
+                    _ => return Err("Incompatível. Não deveria chegar aqui.".to_owned()),
+                }
+            }
+            DynamicValue::Number(l_n) => {
+                match right {
+                    DynamicValue::Integer(r_i) => Ok(DynamicValue::Number(l_n + (r_i as f64))),
+                    DynamicValue::Number(r_n) => Ok(DynamicValue::Number(l_n + r_n)),
+                    _ => return Err("Incompatível. Não deveria chegar aqui.".to_owned()),
+                }
+            }
+            DynamicValue::Text(l_t) => {
+                match right {
+                    DynamicValue::Text(r_t) => {
+                        // Add right value to left node
+
+                        let mut result = String::new();
+
+                        {
+                            let left_v = match self.special_storage.get_data_ref(l_t) {
+                                Some(s) => match s {
+                                    &SpecialItemData::Text(ref s) => s,
+                                    _ => return Err(format!("Erro interno : DynamicValue é texto, mas o id aponta pra outra coisa"))
+                                },
+                                None => return Err(format!("Add w/ Text : Id {} não encontrada.", l_t))
+                            };
+
+                            let right_v = match self.special_storage.get_data_ref(r_t) {
+                                Some(s) => match s {
+                                    &SpecialItemData::Text(ref s) => s,
+                                    _ => return Err(format!("Erro interno : DynamicValue é texto, mas o id aponta pra outra coisa"))
+                                },
+                                None => return Err(format!("Add w/ Text : Id {} não encontrada.", r_t))
+                            };
+
+                            // Check before allocating anything, so a text that doubles every iteration fails fast
+                            self.check_text_size(left_v.len() + right_v.len())?;
+
+                            result.push_str(left_v);
+                            result.push_str(right_v);
                         }
+
+                        let parent_index = match self.get_last_ready_index() {
+                            Some(idx) => idx,
+                            None => return Err("Nenhuma função em execução".to_owned())
+                        };
+
+                        let id = match self.add_special_item(parent_index, SpecialItemData::Text(result)) {
+                            Ok(id) => id,
+                            Err(e) => return Err(e)
+                        };
+
+                        Ok(DynamicValue::Text(id))
                     }
+                    _ => return Err("Incompatível. Não deveria chegar aqui.".to_owned()),
                 }
             }
-            Instruction::ReadInput => {
-                let line = if let Some(ref mut input) = self.stdin.as_mut(){
-                    let mut line = String::new();
-                    match input.read_line(&mut line) {
-                        Ok(_) => {}
-                        Err(e) => return Err(format!("Erro lendo input : {:?}", e))
-                    };
+            DynamicValue::List(left_id) => {
+                match right {
+                    DynamicValue::List(right_id) => {
+                        // We must create a new list, add elements from left, then right, then return it
+
+                        let left_len = match self.special_storage.get_data_ref(left_id) {
+                            Some(SpecialItemData::List(ref contents)) => contents.len(),
+                            _ => 0,
+                        };
+
+                        let right_len = match self.special_storage.get_data_ref(right_id) {
+                            Some(SpecialItemData::List(ref contents)) => contents.len(),
+                            _ => 0,
+                        };
+
+                        self.check_list_size(left_len + right_len)?;
+
+                        let mut data = vec![];
+
+                        match self.special_storage.get_data_ref(left_id) {
+                            Some(SpecialItemData::List(ref contents)) => {
+                                for item in contents {
+                                    data.push(item.clone());
+                                }
+                            }
+                            Some(_) => return Err("Erro interno : DynamicValue é uma lista, mas o valor guardado não".to_owned()),
+                            None => return Err("Erro interno : ID inválida pra lista".to_owned())
+                        }
+
+                        match self.special_storage.get_data_ref(right_id) {
+                            Some(SpecialItemData::List(ref contents)) => {
+                                for item in contents {
+                                    data.push(item.clone());
+                                }
+                            }
+                            Some(_) => return Err("Erro interno : DynamicValue é uma lista, mas o valor guardado não".to_owned()),
+                            None => return Err("Erro interno : ID inválida pra lista".to_owned())
+                        }
+
+                        let index = match self.get_last_ready_index() {
+                            Some(i) => i,
+                            None => return Err("Nenhuma função em execução".to_owned())
+                        };
+
+                        // The new list holds its own reference to each element
+                        for id in data.iter().filter_map(|v| v.special_id()) {
+                            self.special_storage.increment_ref(id)?;
+                        }
+
+                        let id = self.add_special_item(index, SpecialItemData::List(data))?;
+
+                        Ok(DynamicValue::List(id))
+                    }
+                    _ => return Err("Operação não suportada entre Listas e outros valores".to_owned())
+                }
+            }
+            DynamicValue::Sequence(_) => Err("Operação não suportada em sequências".to_owned()),
+            DynamicValue::File(_) => Err("Operação não suportada em arquivos".to_owned()),
+            DynamicValue::Database(_) => Err("Operação não suportada em bancos".to_owned()),
+            DynamicValue::Bool(_) => Err("Operação não suportada em booleanos".to_owned()),
+            DynamicValue::Map(_) => Err("Operação não suportada em mapas".to_owned()),
+            DynamicValue::Null => Ok(DynamicValue::Null),
+        }
+    }
+
+    fn sub_values(&mut self, left : DynamicValue, right : DynamicValue) -> Result<DynamicValue, String> {
+        if ! VirtualMachine::is_compatible(left, right) {
+            return Err(format!("Add : Os valores não são compatíveis : {:?} e {:?}", left, right));
+        }
+
+        match left {
+            DynamicValue::Integer(l_i) => {
+                match right {
+                    DynamicValue::Integer(r_i) => self.integer_operation(l_i, r_i, "-", IntegerType::checked_sub,
+                                                                         IntegerType::wrapping_sub, |l, r| l - r),
+                    DynamicValue::Number(r_n) => Ok(DynamicValue::Number((l_i as f64) - r_n)),
+                    _ => return Err("Incompatível. Não deveria chegar aqui.".to_owned()),
+                }
+            }
+            DynamicValue::Number(l_n) => {
+                match right {
+                    DynamicValue::Integer(r_i) => Ok(DynamicValue::Number(l_n - (r_i as f64))),
+                    DynamicValue::Number(r_n) => Ok(DynamicValue::Number(l_n - r_n)),
+                    _ => return Err("Incompatível. Não deveria chegar aqui.".to_owned()),
+                }
+            }
+            DynamicValue::Text(_) => return Err("Operação inválida em texto : -".to_owned()),
+            DynamicValue::Null => Ok(DynamicValue::Null),
+            DynamicValue::List(_) => return Err("Operação não suportada em listas".to_owned()),
+            DynamicValue::Sequence(_) => Err("Operação não suportada em sequências".to_owned()),
+            DynamicValue::File(_) => Err("Operação não suportada em arquivos".to_owned()),
+            DynamicValue::Database(_) => Err("Operação não suportada em bancos".to_owned()),
+            DynamicValue::Bool(_) => Err("Operação não suportada em booleanos".to_owned()),
+            DynamicValue::Map(_) => Err("Operação não suportada em mapas".to_owned()),
+        }
+    }
+
+    fn mul_values(&mut self, left : DynamicValue, right : DynamicValue) -> Result<DynamicValue, String> {
+        if ! VirtualMachine::is_compatible(left, right) {
+            return Err(format!("Add : Os valores não são compatíveis : {:?} e {:?}", left, right));
+        }
+
+        match left {
+            DynamicValue::Integer(l_i) => {
+                match right {
+                    DynamicValue::Integer(r_i) => self.integer_operation(l_i, r_i, "*", IntegerType::checked_mul,
+                                                                         IntegerType::wrapping_mul, |l, r| l * r),
+                    DynamicValue::Number(r_n) => Ok(DynamicValue::Number((l_i as f64) * r_n)),
+                    _ => return Err("Incompatível. Não deveria chegar aqui.".to_owned()),
+                }
+            }
+            DynamicValue::Number(l_n) => {
+                match right {
+                    DynamicValue::Integer(r_i) => Ok(DynamicValue::Number(l_n * (r_i as f64))),
+                    DynamicValue::Number(r_n) => Ok(DynamicValue::Number(l_n * r_n)),
+                    _ => return Err("Incompatível. Não deveria chegar aqui.".to_owned()),
+                }
+            }
+            DynamicValue::Text(_) => return Err("Operação inválida em texto : *".to_owned()),
+            DynamicValue::Null => Ok(DynamicValue::Null),
+            DynamicValue::List(_) => return Err("Operação não suportada em listas".to_owned()),
+            DynamicValue::Sequence(_) => Err("Operação não suportada em sequências".to_owned()),
+            DynamicValue::File(_) => Err("Operação não suportada em arquivos".to_owned()),
+            DynamicValue::Database(_) => Err("Operação não suportada em bancos".to_owned()),
+            DynamicValue::Bool(_) => Err("Operação não suportada em booleanos".to_owned()),
+            DynamicValue::Map(_) => Err("Operação não suportada em mapas".to_owned()),
+        }
+    }
+
+    fn div_values(&mut self, left : DynamicValue, right : DynamicValue) -> Result<DynamicValue, String> {
+        if ! VirtualMachine::is_compatible(left, right) {
+            return Err(format!("Add : Os valores não são compatíveis : {:?} e {:?}", left, right));
+        }
+
+        match left {
+            DynamicValue::Integer(l_i) => {
+                match right {
+                    DynamicValue::Integer(0) => Err("Divisão por zero".to_owned()),
+                    // The minimum divided by -1 doesn't fit
+                    DynamicValue::Integer(r_i) => self.integer_operation(l_i, r_i, "/", IntegerType::checked_div,
+                                                                         IntegerType::wrapping_div, |l, r| l / r),
+                    DynamicValue::Number(r_n) => Ok(DynamicValue::Number((l_i as f64) / r_n)),
+                    _ => return Err("Incompatível. Não deveria chegar aqui.".to_owned()),
+                }
+            }
+            DynamicValue::Number(l_n) => {
+                match right {
+                    DynamicValue::Integer(r_i) => Ok(DynamicValue::Number(l_n / (r_i as f64))),
+                    DynamicValue::Number(r_n) => Ok(DynamicValue::Number(l_n / r_n)),
+                    _ => return Err("Incompatível. Não deveria chegar aqui.".to_owned()),
+                }
+            }
+            DynamicValue::Text(_) => return Err("Operação inválida em texto : /".to_owned()),
+            DynamicValue::Null => Ok(DynamicValue::Null),
+            DynamicValue::List(_) => return Err("Operação não suportada em listas".to_owned()),
+            DynamicValue::Sequence(_) => Err("Operação não suportada em sequências".to_owned()),
+            DynamicValue::File(_) => Err("Operação não suportada em arquivos".to_owned()),
+            DynamicValue::Database(_) => Err("Operação não suportada em bancos".to_owned()),
+            DynamicValue::Bool(_) => Err("Operação não suportada em booleanos".to_owned()),
+            DynamicValue::Map(_) => Err("Operação não suportada em mapas".to_owned()),
+        }
+    }
+
+    /// Raise left to the power of right. An integer to a negative power gives a number, since it's a fraction
+    fn pow_values(&mut self, left : DynamicValue, right : DynamicValue) -> Result<DynamicValue, String> {
+        if ! VirtualMachine::is_compatible(left, right) {
+            return Err(format!("Pow : Os valores não são compatíveis : {:?} e {:?}", left, right));
+        }
+
+        match left {
+            DynamicValue::Integer(l_i) => {
+                match right {
+                    DynamicValue::Integer(r_i) if r_i < 0 => Ok(DynamicValue::Number((l_i as f64).powf(r_i as f64))),
+                    DynamicValue::Integer(r_i) => self.integer_operation(l_i, r_i, "^", checked_pow, wrapping_pow,
+                                                                         f64::powf),
+                    DynamicValue::Number(r_n) => Ok(DynamicValue::Number((l_i as f64).powf(r_n))),
+                    _ => Err("Incompatível. Não deveria chegar aqui.".to_owned()),
+                }
+            }
+            DynamicValue::Number(l_n) => {
+                match right {
+                    DynamicValue::Integer(r_i) => Ok(DynamicValue::Number(l_n.powf(r_i as f64))),
+                    DynamicValue::Number(r_n) => Ok(DynamicValue::Number(l_n.powf(r_n))),
+                    _ => Err("Incompatível. Não deveria chegar aqui.".to_owned()),
+                }
+            }
+            DynamicValue::Text(_) => Err("Operação inválida em texto : ^".to_owned()),
+            DynamicValue::Null => Ok(DynamicValue::Null),
+            DynamicValue::List(_) => Err("Operação não suportada em listas".to_owned()),
+            DynamicValue::Sequence(_) => Err("Operação não suportada em sequências".to_owned()),
+            DynamicValue::File(_) => Err("Operação não suportada em arquivos".to_owned()),
+            DynamicValue::Database(_) => Err("Operação não suportada em bancos".to_owned()),
+            DynamicValue::Bool(_) => Err("Operação não suportada em booleanos".to_owned()),
+            DynamicValue::Map(_) => Err("Operação não suportada em mapas".to_owned()),
+        }
+    }
+
+    /// The remainder of the division, like the division truncated towards zero : `-7 % 3` is -1
+    fn rem_values(&mut self, left : DynamicValue, right : DynamicValue) -> Result<DynamicValue, String> {
+        if ! VirtualMachine::is_compatible(left, right) {
+            return Err(format!("Mod : Os valores não são compatíveis : {:?} e {:?}", left, right));
+        }
+
+        match left {
+            DynamicValue::Integer(l_i) => {
+                match right {
+                    DynamicValue::Integer(0) => Err("Resto de divisão por zero".to_owned()),
+                    DynamicValue::Integer(r_i) => self.integer_operation(l_i, r_i, "%", IntegerType::checked_rem,
+                                                                         IntegerType::wrapping_rem, |l, r| l % r),
+                    DynamicValue::Number(r_n) => Ok(DynamicValue::Number((l_i as f64) % r_n)),
+                    _ => Err("Incompatível. Não deveria chegar aqui.".to_owned()),
+                }
+            }
+            DynamicValue::Number(l_n) => {
+                match right {
+                    DynamicValue::Integer(r_i) => Ok(DynamicValue::Number(l_n % (r_i as f64))),
+                    DynamicValue::Number(r_n) => Ok(DynamicValue::Number(l_n % r_n)),
+                    _ => Err("Incompatível. Não deveria chegar aqui.".to_owned()),
+                }
+            }
+            DynamicValue::Text(_) => Err("Operação inválida em texto : %".to_owned()),
+            DynamicValue::Null => Ok(DynamicValue::Null),
+            DynamicValue::List(_) => Err("Operação não suportada em listas".to_owned()),
+            DynamicValue::Sequence(_) => Err("Operação não suportada em sequências".to_owned()),
+            DynamicValue::File(_) => Err("Operação não suportada em arquivos".to_owned()),
+            DynamicValue::Database(_) => Err("Operação não suportada em bancos".to_owned()),
+            DynamicValue::Bool(_) => Err("Operação não suportada em booleanos".to_owned()),
+            DynamicValue::Map(_) => Err("Operação não suportada em mapas".to_owned()),
+        }
+    }
+
+    /// Null, MENTIRA, zeroes and empty texts, lists or maps are false. Everything else (including sequences) is true
+    pub fn is_truthy(&self, val : DynamicValue) -> Result<bool, String> {
+        match val {
+            DynamicValue::Null => Ok(false),
+            DynamicValue::Bool(b) => Ok(b),
+            DynamicValue::Integer(i) => Ok(i != 0),
+            DynamicValue::Number(n) => Ok(n != 0.0),
+            DynamicValue::Text(id) => match self.special_storage.get_data_ref(id) {
+                Some(SpecialItemData::Text(ref t)) => Ok(! t.is_empty()),
+                Some(_) => Err("Erro interno : DynamicValue é texto, mas o id aponta pra outra coisa".to_owned()),
+                None => Err(format!("Erro : TextID não encontrada : {}", id)),
+            },
+            DynamicValue::List(id) => match self.special_storage.get_data_ref(id) {
+                Some(SpecialItemData::List(ref l)) => Ok(! l.is_empty()),
+                Some(_) => Err("Erro interno : DynamicValue é uma lista, mas o item guardado não".to_owned()),
+                None => Err("Erro interno : ID inválida pra lista".to_owned()),
+            },
+            DynamicValue::Map(id) => match self.special_storage.get_data_ref(id) {
+                Some(SpecialItemData::Map(ref m)) => Ok(! m.is_empty()),
+                Some(_) => Err("Erro interno : DynamicValue é um mapa, mas o item guardado não".to_owned()),
+                None => Err("Erro interno : ID inválida pra mapa".to_owned()),
+            },
+            // Whether it has values left can't be known without computing the next one
+            DynamicValue::Sequence(_) | DynamicValue::File(_) | DynamicValue::Database(_) => Ok(true),
+        }
+    }
+
+    fn skip_instructions(&mut self, count : usize) -> Result<(), String> {
+        let pc = match self.get_current_pc() {
+            Some(p) => p,
+            None => return Err("Nenhuma função em execução".to_owned())
+        };
+
+        self.set_current_pc(pc + count)
+    }
+
+    fn get_last_comparision(&self) -> Result<Comparision, String> {
+        if self.callstack.is_empty() {
+            return Err("Callstack vazia".to_owned());
+        }
+
+        match self.callstack.last().unwrap().last_comparision {
+            Some(c) => Ok(c),
+            None => Err("Nenhuma comparação na função atual".to_owned())
+        }
+    }
+
+    /// With strict comparisons, fail if the values have types that can't be compared, like a text and a number. Only
+    /// the values compared by the program are checked, not the elements of lists being compared
+    fn check_comparable(&self, left : DynamicValue, right : DynamicValue) -> Result<(), String> {
+        if ! self.registers.strict_comparisons {
+            return Ok(());
+        }
+
+        let (left_kind, right_kind) = (left.get_kind(), right.get_kind());
+
+        let is_number = |kind : TypeKind| kind == TypeKind::Integer || kind == TypeKind::Number;
+
+        if left_kind == right_kind || is_number(left_kind) && is_number(right_kind) {
+            return Ok(());
+        }
+
+        Err(format!("Comparação entre {} e {} : No modo monstro só dá pra comparar valores do mesmo tipo, cumpade",
+                    left_kind, right_kind))
+    }
+
+    fn compare(&self, left : DynamicValue, right : DynamicValue) -> Result<Comparision, String> {
+        self.compare_visiting(left, right, &mut HashSet::new())
+    }
+
+    // A list or map can contain itself, so the pairs of IDs already being compared are kept in
+    // visiting. Meeting one of them again means nothing different was found down that path
+    fn compare_visiting(&self, left : DynamicValue, right : DynamicValue, visiting : &mut HashSet<(u64, u64)>) -> Result<Comparision, String> {
+        let comp_numbers: fn(f64, f64) -> Comparision = | l, r | {
+            if l == r {
+                Comparision::Equal
+            } else if l < r {
+                Comparision::LessThan
+            } else {
+                Comparision::MoreThan
+            }
+        };
+
+        let comp = match left {
+            DynamicValue::Integer(l_i) => {
+                match right {
+                    DynamicValue::Integer(r_i) => {
+                        if l_i == r_i {
+                            Comparision::Equal
+                        } else if l_i < r_i {
+                            Comparision::LessThan
+                        } else {
+                            Comparision::MoreThan
+                        }
+                    }
+                    DynamicValue::Number(r_n) => comp_numbers(l_i as f64, r_n),
+                    _ => Comparision::NotEqual
+                }
+            }
+            DynamicValue::Number(l_n) => {
+                match right {
+                    DynamicValue::Number(r_n) => {
+                        comp_numbers(l_n, r_n)
+                    }
+                    DynamicValue::Integer(r_i) => {
+                        comp_numbers(l_n, r_i as f64)
+                    }
+                    _ => Comparision::NotEqual,
+                }
+            }
+            DynamicValue::Text(l_t) => {
+                match right {
+                    DynamicValue::Text(r_t) => {
+                        let ltext = match self.special_storage.get_data_ref(l_t) {
+                            Some(s) => match s {
+                                &SpecialItemData::Text(ref s) => s,
+                                _ => return Err(format!("Erro interno : DynamicValue é texto, mas o id aponta pra outra coisa"))
+                            },
+                            None => return Err(format!("Erro : TextID não encontrada : {}", l_t)),
+                        };
+
+                        let rtext = match self.special_storage.get_data_ref(r_t) {
+                            Some(s) => match s {
+                                &SpecialItemData::Text(ref s) => s,
+                                _ => return Err(format!("Erro interno : DynamicValue é texto, mas o id aponta pra outra coisa"))
+                            },
+                            None => return Err(format!("Erro : TextID não encontrada : {}", r_t)),
+                        };
+
+                        let llen = ltext.len();
+                        let rlen = rtext.len();
+
+                        if llen > rlen {
+                            Comparision::MoreThan
+                        } else if llen < rlen {
+                            Comparision::LessThan
+                        } else {
+                            if ltext == rtext {
+                                Comparision::Equal
+                            } else {
+                                Comparision::NotEqual
+                            }
+                        }
+                    }
+                    _ => Comparision::NotEqual
+                }
+            }
+            DynamicValue::List(left_id) => {
+                match right {
+                    DynamicValue::List(right_id) => {
+                        if !visiting.insert((left_id, right_id)) {
+                            return Ok(Comparision::Equal);
+                        }
+
+                        let left_list = match self.special_storage.get_data_ref(left_id) {
+                            Some(SpecialItemData::List(ref list)) => list.clone(),
+                            Some(_) => return Err("Erro interno : DynamicValue é uma lista mas o item guardado não".to_owned()),
+                            None => return Err("ID não existe".to_owned())
+                        };
+
+                        let right_list = match self.special_storage.get_data_ref(right_id) {
+                            Some(SpecialItemData::List(ref list)) => list.clone(),
+                            Some(_) => return Err("Erro interno : DynamicValue é uma lista mas o item guardado não".to_owned()),
+                            None => return Err("ID não existe".to_owned())
+                        };
+
+                        if left_list.len() != right_list.len() {
+                            Comparision::NotEqual
+                        } else {
+
+                            for i in 0..left_list.len() {
+                                match self.compare_visiting(*left_list[i], *right_list[i], visiting) {
+                                    Ok(Comparision::Equal) => {},
+                                    Ok(_) => return Ok(Comparision::NotEqual),
+                                    Err(e) => return Err(e)
+                                }
+                            }
+
+                            Comparision::Equal
+                        }
+                    }
+                    _ => Comparision::NotEqual,
+                }
+            }
+            DynamicValue::Sequence(left_id) => {
+                // Going through a sequence consumes it, so only the same sequence is equal to it
+                match right {
+                    DynamicValue::Sequence(right_id) if left_id == right_id => Comparision::Equal,
+                    _ => Comparision::NotEqual,
+                }
+            }
+            DynamicValue::File(left_id) => {
+                match right {
+                    DynamicValue::File(right_id) if left_id == right_id => Comparision::Equal,
+                    _ => Comparision::NotEqual,
+                }
+            }
+            DynamicValue::Database(left_id) => {
+                match right {
+                    DynamicValue::Database(right_id) if left_id == right_id => Comparision::Equal,
+                    _ => Comparision::NotEqual,
+                }
+            }
+            DynamicValue::Null => {
+                match right {
+                    DynamicValue::Null => Comparision::Equal,
+                    _ => Comparision::NotEqual,
+                }
+            }
+            DynamicValue::Bool(l_b) => {
+                match right {
+                    DynamicValue::Bool(r_b) if l_b == r_b => Comparision::Equal,
+                    _ => Comparision::NotEqual,
+                }
+            }
+            DynamicValue::Map(left_id) => {
+                match right {
+                    DynamicValue::Map(right_id) => {
+                        if !visiting.insert((left_id, right_id)) {
+                            return Ok(Comparision::Equal);
+                        }
+
+                        let left_map = match self.special_storage.get_data_ref(left_id) {
+                            Some(SpecialItemData::Map(ref map)) => map.clone(),
+                            Some(_) => return Err("Erro interno : DynamicValue é um mapa mas o item guardado não".to_owned()),
+                            None => return Err("ID não existe".to_owned())
+                        };
+
+                        let right_map = match self.special_storage.get_data_ref(right_id) {
+                            Some(SpecialItemData::Map(ref map)) => map.clone(),
+                            Some(_) => return Err("Erro interno : DynamicValue é um mapa mas o item guardado não".to_owned()),
+                            None => return Err("ID não existe".to_owned())
+                        };
+
+                        if left_map.len() != right_map.len() {
+                            return Ok(Comparision::NotEqual);
+                        }
+
+                        // Equal when both have the same keys, with equal values
+                        for (key, left_value) in left_map {
+                            let right_value = match right_map.get(&key) {
+                                Some(v) => *v,
+                                None => return Ok(Comparision::NotEqual),
+                            };
+
+                            if self.compare_visiting(left_value, right_value, visiting)? != Comparision::Equal {
+                                return Ok(Comparision::NotEqual);
+                            }
+                        }
+
+                        Comparision::Equal
+                    }
+                    _ => Comparision::NotEqual,
+                }
+            }
+        };
+
+        Ok(comp)
+    }
+
+    fn set_last_comparision(&mut self, comp : Comparision) -> Result<(), String> {
+        if self.callstack.is_empty() {
+            return Err("Callstack tá vazia. Provavelmente é erro interno".to_owned());
+        }
+
+        self.callstack.last_mut().unwrap().last_comparision = Some(comp);
+
+        Ok(())
+    }
+
+    /// Index of the frame on top of the callstack, ready or not
+    fn last_frame_index(&self) -> Result<usize, String> {
+        self.callstack.len().checked_sub(1).ok_or_else(|| "Erro interno : Não tem nenhuma função na callstack".to_owned())
+    }
+
+    // This function doesn't search all the callstack, just the first frame
+    fn get_last_ready_index(&self) -> Option<usize> {
+        if self.callstack.is_empty() {
+            None
+        }
+        else if self.callstack.len() < 2 {
+            if self.callstack[0].ready {
+                Some(0)
+            } else {
+                None
+            }
+        } else {
+            let last = self.callstack.len() - 1;
+
+            if self.callstack[last].ready {
+                Some(last)
+            } else {
+                Some(last - 1)
+            }
+        }
+    }
+
+    fn write_to(&mut self, val : DynamicValue, stack_index : usize, address : usize) -> Result<(), String> {
+        if self.callstack.len() <= stack_index {
+            return Err(format!("Index de frame inválido : {}", stack_index));
+        }
+
+        let frame = &mut self.callstack[stack_index];
+
+        if frame.stack.len() <= address {
+            return Err("Endereço out-of-bounds".to_owned());
+        }
+
+        // In arena mode nothing is ever freed, so there's no need to keep the ref counts
+
+        if self.special_storage.is_arena() {
+            frame.stack[address] = val;
+
+            return Ok(());
+        }
+
+        // Check if the value we're writing to is a special item
+        // if it is, we need to decrement it first
+
+        match frame.stack[address] {
+            DynamicValue::List(id) => self.special_storage.decrement_ref(id)?,
+            DynamicValue::Text(id) => self.special_storage.decrement_ref(id)?,
+            DynamicValue::Sequence(id) => self.special_storage.decrement_ref(id)?,
+            DynamicValue::File(id) => self.special_storage.decrement_ref(id)?,
+            DynamicValue::Database(id) => self.special_storage.decrement_ref(id)?,
+            DynamicValue::Map(id) => self.special_storage.decrement_ref(id)?,
+            _ => {}
+        };
+
+        // If the value we're writing is a special item, increment its ref count
+
+        match val {
+            DynamicValue::List(id) => self.special_storage.increment_ref(id)?,
+            DynamicValue::Text(id) => self.special_storage.increment_ref(id)?,
+            DynamicValue::Sequence(id) => self.special_storage.increment_ref(id)?,
+            DynamicValue::File(id) => self.special_storage.increment_ref(id)?,
+            DynamicValue::Database(id) => self.special_storage.increment_ref(id)?,
+            DynamicValue::Map(id) => self.special_storage.increment_ref(id)?,
+            _ => {}
+        };
+
+        frame.stack[address] = val;
+
+        Ok(())
+    }
+
+    fn read_from_id(&mut self, index : usize, address : usize) -> Result<DynamicValue, String> {
+        if self.callstack.len() < index {
+            return Err(format!("Index out of bounds for read : {}", index));
+        }
+
+        let val = {
+
+            let frame = &mut self.callstack[index];
+
+            if frame.stack.len() <= address {
+                return Err("Erro : Endereço pra variável é inválido".to_owned());
+            }
+
+            frame.stack[address]
+        };
+
+        Ok(val)
+    }
+
+    pub(crate) fn unset_quit(&mut self) {
+        self.registers.has_quit = false;
+    }
+
+    /// Drop every function that's still running but the global code, like the ones left by a quit or an error, so
+    /// the next call doesn't go back to them
+    pub(crate) fn unwind_to_global(&mut self) -> Result<(), String> {
+        while self.callstack.len() > 1 {
+            let frame = match self.callstack.pop() {
+                Some(f) => f,
+                None => break,
+            };
+
+            for id in frame.stack.iter().chain(frame.return_values.iter()).filter_map(|v| v.special_id()) {
+                self.special_storage.decrement_ref(id)?;
+            }
+
+            self.drop_loop_labels(frame.label_stack)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn has_quit(&self) -> bool {
+        self.registers.has_quit
+    }
+
+    fn get_current_pc(&self) -> Option<usize> {
+        match self.get_last_ready_ref() {
+            Some(f) => Some(f.program_counter),
+            None => None
+        }
+    }
+
+    fn increment_pc(&mut self) -> Result<(), String> {
+        match self.get_last_ready_mut() {
+            Some(f) => f.program_counter += 1,
+            None => return Err("Nenhuma função em execução".to_owned())
+        }
+
+        Ok(())
+    }
+
+    fn decrement_pc(&mut self) -> Result<(), String> {
+        match self.get_last_ready_mut() {
+            Some(f) => f.program_counter -= 1,
+            None => return Err("Nenhuma função em execução".to_owned())
+        }
+
+        Ok(())
+    }
+
+    fn conv_to_string(&mut self, val : DynamicValue) -> Result<String, String> {
+        self.conv_to_string_visiting(val, &mut HashSet::new())
+    }
+
+    // visiting has the IDs of the lists and maps being printed right now, so one that contains
+    // itself shows up as [...] or {...} instead of going on forever
+    fn conv_to_string_visiting(&mut self, val : DynamicValue, visiting : &mut HashSet<u64>) -> Result<String, String> {
+        match val {
+            DynamicValue::Text(t) => {
+                let s = match self.special_storage.get_data_ref(t) {
+                    Some(s) => match s {
+                        &SpecialItemData::Text(ref s) => s,
+                        _ => return Err(format!("Erro interno : DynamicValue é texto, mas o id aponta pra outra coisa"))
+                    },
+                    None => return Err("Invalid string ID".to_owned()),
+                };
+
+                Ok(s.clone())
+            }
+            DynamicValue::Integer(i) => Ok(format!("{}", i)),
+            DynamicValue::Number(n) => {
+                let mut s = String::new();
+                push_number_with(&mut s, n, self.registers.decimal_separator);
+
+                Ok(s)
+            }
+            DynamicValue::Null => Ok(String::from("<Null>")),
+            DynamicValue::Sequence(_) => Ok(String::from("<Sequência>")),
+            DynamicValue::File(_) => Ok(String::from("<Arquivo>")),
+            DynamicValue::Database(_) => Ok(String::from("<Banco>")),
+            DynamicValue::Bool(b) => Ok(String::from(bool_name(b))),
+            DynamicValue::Map(id) => {
+                if visiting.contains(&id) {
+                    return Ok(String::from("{...}"));
+                }
+
+                let mut entries = match self.special_storage.get_data_ref(id) {
+                    Some(SpecialItemData::Map(ref map)) => map.iter().map(|(k, v)| (k.clone(), *v)).collect::<Vec<(String, DynamicValue)>>(),
+                    Some(_) => return Err("Erro interno : DynamicValue é um mapa, item interno não".to_owned()),
+                    None => return Err("ID inválida pra mapa".to_owned())
+                };
+
+                // The order of a HashMap changes from run to run, so the keys are sorted
+                entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+                let mut result = String::from("{ ");
+                visiting.insert(id);
+
+                for (i, (key, value)) in entries.into_iter().enumerate() {
+                    if i > 0 {
+                        result.push_str(", ");
+                    }
+
+                    result.push_str(&format!("\"{}\" : ", key));
+
+                    let s = self.conv_to_string_visiting(value, visiting)?;
+
+                    if let DynamicValue::Text(_) = value {
+                        result.push_str(&format!("\"{}\"", s));
+                    } else {
+                        result.push_str(&s);
+                    }
+                }
+
+                visiting.remove(&id);
+                result.push_str(" }");
+
+                Ok(result)
+            }
+            DynamicValue::List(id) => {
+                if visiting.contains(&id) {
+                    return Ok(String::from("[...]"));
+                }
+
+                let list = match self.special_storage.get_data_ref(id) {
+                    Some(SpecialItemData::List(ref list)) => list.clone(),
+                    Some(_) => return Err("Erro interno : DynamicValue é uma lista, item interno não".to_owned()),
+                    None => return Err("ID inválida pra lista".to_owned())
+                };
+                
+                let mut result = String::from("[ ");
+                let mut first = true;
+                visiting.insert(id);
+
+                for item in list {
+                    if !first {
+                        result.push_str(", ");
+                    } else {
+                        first = false;
+                    }
+
+                    // kek
+                    let is_str = if let DynamicValue::Text(_) = *item {
+                        true
+                    } else {
+                        false
+                    };
+
+                    let s = self.conv_to_string_visiting(*item, visiting)?;
+
+                    if is_str {
+                        result.push_str("\"");
+                    }
+
+                    result.push_str(s.as_str());
+
+                    if is_str {
+                        result.push_str("\"");
+                    }
+                }
+
+                visiting.remove(&id);
+                result.push_str(" ]");
+
+                Ok(result)
+            }
+        }
+    }
+
+    fn conv_to_int(&mut self, val : DynamicValue) -> Result<IntegerType, String> {
+        match val {
+            DynamicValue::Text(t) => {
+                let text = match self.special_storage.get_data_ref(t) {
+                    Some(s) => match s {
+                        &SpecialItemData::Text(ref s) => s,
+                        _ => return Err(format!("Erro interno : DynamicValue é texto, mas o id aponta pra outra coisa"))
+                    },
+                    None => return Err("Invalid text id".to_owned())
+                };
+
+                let i = match text.parse::<IntegerType>() {
+                    Ok(i) => i,
+                    Err(_) => return Err(format!("Não foi possível converter \"{}\" pra Int", text))
+                };
+
+                Ok(self.registers.integer_width.wrap(i))
+            }
+            DynamicValue::Number(n) => Ok(self.registers.integer_width.wrap(n as IntegerType)),
+            DynamicValue::Integer(i) => Ok(i),
+            DynamicValue::Bool(b) => Ok(b as IntegerType),
+            DynamicValue::Null => return Err("Convert : <Null>".to_owned()),
+            DynamicValue::List(_) => return Err("Não é possível converter uma lista pra inteiro".to_owned()),
+            DynamicValue::Sequence(_) => Err("Não é possível converter uma sequência pra inteiro".to_owned()),
+            DynamicValue::File(_) => Err("Não é possível converter um arquivo pra inteiro".to_owned()),
+            DynamicValue::Database(_) => Err("Não é possível converter um banco pra inteiro".to_owned()),
+            DynamicValue::Map(_) => Err("Não é possível converter um mapa pra inteiro".to_owned()),
+        }
+    }
+
+    fn conv_to_num(&mut self, val : DynamicValue) -> Result<f64, String> {
+        match val {
+            DynamicValue::Text(t) => {
+                let text = match self.special_storage.get_data_ref(t) {
+                    Some(s) => match s {
+                        &SpecialItemData::Text(ref s) => s,
+                        _ => return Err(format!("Erro interno : DynamicValue é texto, mas o id aponta pra outra coisa"))
+                    },
+                    None => return Err("Invalid text id".to_owned())
+                };
+
+                let n = match parse_number(text, self.registers.decimal_separator) {
+                    Some(n) => n,
+                    None => return Err(format!("Não foi possível converter \"{}\" pra Num", text))
+                };
+
+                Ok(n)
+            }
+            DynamicValue::Number(n) => Ok(n),
+            DynamicValue::Integer(i) => Ok(i as f64),
+            DynamicValue::Bool(b) => Ok(if b { 1.0 } else { 0.0 }),
+            DynamicValue::Null => return Err("Convert : <Null>".to_owned()),
+            DynamicValue::List(_) => return Err("Não é possível converter uma lista pra número".to_owned()),
+            DynamicValue::Sequence(_) => Err("Não é possível converter uma sequência pra número".to_owned()),
+            DynamicValue::File(_) => Err("Não é possível converter um arquivo pra número".to_owned()),
+            DynamicValue::Database(_) => Err("Não é possível converter um banco pra número".to_owned()),
+            DynamicValue::Map(_) => Err("Não é possível converter um mapa pra número".to_owned()),
+        }
+    }
+
+    fn comparision_matches(comp : Comparision, req : ComparisionRequest) -> bool {
+        match req {
+            ComparisionRequest::Equal => comp == Comparision::Equal,
+            ComparisionRequest::NotEqual => comp != Comparision::Equal,
+            ComparisionRequest::Less => comp == Comparision::LessThan,
+            ComparisionRequest::LessOrEqual => comp == Comparision::LessThan || comp == Comparision::Equal,
+            ComparisionRequest::More => comp == Comparision::MoreThan,
+            ComparisionRequest::MoreOrEqual => comp == Comparision::MoreThan || comp == Comparision::Equal,
+        }
+    }
+
+    fn last_comparision_matches(&self, req : ComparisionRequest) -> Result<bool, String> {
+        let last = match self.get_last_comparision() {
+            Ok(c) => c,
+            Err(e) => return Err(e)
+        };
+
+        Ok(VirtualMachine::comparision_matches(last, req))
+    }
+
+    pub fn set_stack_size(&mut self, size : usize) {
+        self.registers.default_stack_size = size;
+    }
+
+    /// Set the maximum size of a single text, in bytes. None removes the limit
+    pub fn set_max_text_size(&mut self, size : Option<usize>) {
+        self.registers.max_text_size = size;
+    }
+
+    /// Set the maximum number of elements of a single list. None removes the limit
+    pub fn set_max_list_size(&mut self, size : Option<usize>) {
+        self.registers.max_list_size = size;
+    }
+
+    /// Set the width of the integers. Fails if it's wider than IntegerType
+    pub fn set_integer_width(&mut self, width : IntegerWidth) -> Result<(), String> {
+        if width.bits() > IntegerWidth::native().bits() {
+            return Err(format!("Inteiros de {} bits não tão disponíveis, o máximo é {} (use a feature \"i128\" pra ter 128)",
+                               width.bits(), IntegerWidth::native().bits()));
+        }
+
+        self.registers.integer_width = width;
+
+        Ok(())
+    }
+
+    pub fn get_integer_width(&self) -> IntegerWidth {
+        self.registers.integer_width
+    }
+
+    /// Restart the random numbers from a seed. The same seed always gives the same numbers
+    pub fn set_random_seed(&mut self, seed : u64) {
+        self.random_state = seed;
+    }
+
+    /// The next random number. Used by everything random in the standard library, so a seed makes all of it repeat
+    pub fn next_random(&mut self) -> u64 {
+        self.random_state = self.random_state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+
+        let mut z = self.random_state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+
+        z ^ (z >> 31)
+    }
+
+    pub fn set_overflow_policy(&mut self, policy : OverflowPolicy) {
+        self.registers.overflow_policy = policy;
+    }
+
+    pub fn get_overflow_policy(&self) -> OverflowPolicy {
+        self.registers.overflow_policy
+    }
+
+    pub fn set_strict_comparisons(&mut self, strict : bool) {
+        self.registers.strict_comparisons = strict;
+    }
+
+    pub fn get_strict_comparisons(&self) -> bool {
+        self.registers.strict_comparisons
+    }
+
+    pub fn set_auto_flush(&mut self, auto_flush : Option<bool>) {
+        self.registers.auto_flush = auto_flush;
+    }
+
+    pub fn get_auto_flush(&self) -> Option<bool> {
+        self.registers.auto_flush
+    }
+
+    pub fn set_decimal_separator(&mut self, separator : DecimalSeparator) {
+        self.registers.decimal_separator = separator;
+    }
+
+    pub fn get_decimal_separator(&self) -> DecimalSeparator {
+        self.registers.decimal_separator
+    }
+
+    /// Let the VM execute `fuel` more instructions. After that, the execution stops with ExecutionStatus::OutOfFuel,
+    /// and can only continue after more is given
+    pub fn set_fuel(&mut self, fuel : u64) {
+        self.registers.fuel = Some(fuel);
+    }
+
+    /// How many instructions the VM can still execute, or None if there's no limit
+    pub fn get_fuel(&self) -> Option<u64> {
+        self.registers.fuel
+    }
+
+    pub fn remove_fuel_limit(&mut self) {
+        self.registers.fuel = None;
+    }
+
+    /// Do an operation on two integers. When the result doesn't fit in the width of the VM's integers, what happens
+    /// depends on the overflow policy
+    fn integer_operation(&self, left : IntegerType, right : IntegerType, symbol : &str,
+                         checked : fn(IntegerType, IntegerType) -> Option<IntegerType>,
+                         wrapping : fn(IntegerType, IntegerType) -> IntegerType,
+                         float : fn(f64, f64) -> f64) -> Result<DynamicValue, String> {
+        let width = self.registers.integer_width;
+
+        match checked(left, right) {
+            Some(result) if width.wrap(result) == result => return Ok(DynamicValue::Integer(result)),
+            _ => {}
+        }
+
+        match self.registers.overflow_policy {
+            OverflowPolicy::Wrap => Ok(DynamicValue::Integer(width.wrap(wrapping(left, right)))),
+            OverflowPolicy::Float => Ok(DynamicValue::Number(float(left as f64, right as f64))),
+            OverflowPolicy::Error => Err(format!("Estouro : O resultado de {} {} {} não cabe num inteiro de {} bits",
+                                                 left, symbol, right, width.bits())),
+        }
+    }
+
+    /// Wrap an integer result to the width of the VM's integers
+    fn fit_integer(&self, val : DynamicValue) -> DynamicValue {
+        match val {
+            DynamicValue::Integer(i) => DynamicValue::Integer(self.registers.integer_width.wrap(i)),
+            other => other,
+        }
+    }
+
+    /// Set the maximum number of texts and lists allocated at the same time. None removes the limit
+    pub fn set_max_special_items(&mut self, count : Option<usize>) {
+        self.registers.max_special_items = count;
+    }
+
+    /// Set the maximum number of frames in the callstack. None removes the limit
+    pub fn set_max_call_depth(&mut self, depth : Option<usize>) {
+        self.registers.max_call_depth = depth;
+    }
+
+    pub fn get_max_call_depth(&self) -> Option<usize> {
+        self.registers.max_call_depth
+    }
+
+    /// Set the number of texts and lists alive that makes the garbage collector run. None to only run it when asked
+    pub fn set_gc_threshold(&mut self, count : Option<usize>) {
+        self.registers.gc_threshold = count;
+        self.registers.next_collection = count.unwrap_or(0);
+    }
+
+    fn set_current_pc(&mut self, pc : usize) -> Result<(), String> {
+        match self.get_last_ready_mut() {
+            Some(f) => f.program_counter = pc,
+            None => return Err("Nenhuma função em execução".to_owned())
+        };
+
+        Ok(())
+    }
+
+    pub fn print_string(&mut self, s : &str) -> Result<(), String> {
+        self.io.write(s)
+    }
+
+    /// Write bytes to the output without turning them into a text first, for binary data
+    pub fn print_bytes(&mut self, bytes : &[u8]) -> Result<(), String> {
+        self.io.write_bytes(bytes)?;
+        self.auto_flush_stdout();
+
+        Ok(())
+    }
+
+    pub fn print_value(&mut self, val : DynamicValue) -> Result<(), String> {
+        match val {
+            DynamicValue::Integer(i) => vm_write!(self, "{}", i)?,
+            DynamicValue::Number(n) => {
+                self.format_buffer.clear();
+                push_number_with(&mut self.format_buffer, n, self.registers.decimal_separator);
+
+                self.io.write(&self.format_buffer)?
+            }
+            DynamicValue::Text(t) => {
+                let t = match self.special_storage.get_data_ref(t) {
+                    Some(s) => match s {
+                        &SpecialItemData::Text(ref s) => s,
+                        _ => return Err(format!("Erro interno : DynamicValue é texto, mas o id aponta pra outra coisa"))
+                    },
+                    None => return Err(format!("MainPrint : Não foi encontrado text com ID {}", t)),
+                };
+
+                // Texts are already strings, so they don't need to go through the formatting buffer
+                self.io.write(t)?
+            }
+            DynamicValue::List(id) => {
+                let string = match self.conv_to_string(DynamicValue::List(id)) {
+                    Ok(s) => s,
+                    Err(e) => return Err(e)
+                };
+                vm_write!(self, "(Lista) {}", string)?;
+            }
+            DynamicValue::Null => vm_write!(self, "<Null>")?,
+            DynamicValue::Sequence(_) => vm_write!(self, "<Sequência>")?,
+            DynamicValue::File(_) => vm_write!(self, "<Arquivo>")?,
+            DynamicValue::Database(_) => vm_write!(self, "<Banco>")?,
+            DynamicValue::Bool(b) => self.io.write(bool_name(b))?,
+            DynamicValue::Map(id) => {
+                let string = self.conv_to_string(DynamicValue::Map(id))?;
+
+                vm_write!(self, "(Mapa) {}", string)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run a single instruction. Errors say what kind of problem it was, but not where : that's up to the caller, since
+    /// the instruction might not be part of any code (like the ones the context runs to call a function)
+    pub(crate) fn run(&mut self, inst : Instruction) -> Result<ExecutionStatus, BirlError> {
+        let kind = self.error_kind_for(&inst);
+
+        self.run_instruction(inst).map_err(|message| kind(message, Box::default()))
+    }
+
+    /// How errors from running the instruction are reported. Arithmetic only fails for numbers when the result can't be
+    /// represented (division by zero, overflow...), any other failure there is about the types of the values
+    fn error_kind_for(&self, inst : &Instruction) -> fn(String, Box<ErrorLocation>) -> BirlError {
+        let is_number = |value : &DynamicValue| matches!(*value, DynamicValue::Integer(_) | DynamicValue::Number(_));
+
+        match *inst {
+            Instruction::AssertMathBCompatible(_) => BirlError::Type,
+            Instruction::Add | Instruction::Sub | Instruction::Mul | Instruction::Div | Instruction::Mod |
+            Instruction::Pow => {
+                if is_number(&self.registers.math_a) && is_number(&self.registers.math_b) {
+                    BirlError::Runtime
+                } else {
+                    BirlError::Type
+                }
+            }
+            Instruction::ReadInput | Instruction::PrintMathB | Instruction::PrintNewLine | Instruction::FlushStdout =>
+                BirlError::Io,
+            _ => BirlError::Runtime,
+        }
+    }
+
+    fn run_instruction(&mut self, inst : Instruction) -> Result<ExecutionStatus, String> {
+        match inst {
+            Instruction::PrintMathBDebug => {
+                match self.registers.math_b {
+                    DynamicValue::Integer(i) => vm_write!(self, "(Integer) {}\n", i)?,
+                    DynamicValue::Number(n) => {
+                        self.format_buffer.clear();
+                        self.format_buffer.push_str("(Number) ");
+                        push_number_with(&mut self.format_buffer, n, self.registers.decimal_separator);
+                        self.format_buffer.push('\n');
+
+                        self.io.write(&self.format_buffer)?
+                    }
+                    DynamicValue::Text(t) => {
+                        let t = match self.special_storage.get_data_ref(t) {
+                            Some(s) => match s {
+                                &SpecialItemData::Text(ref s) => s,
+                                _ => return Err(format!("Erro interno : DynamicValue é texto, mas o id aponta pra outra coisa"))
+                            },
+                            None => return Err(format!("MainPrint : Não foi encontrado text com ID {}", t)),
+                        };
+
+                        vm_write!(self, "(Text) \"{}\"\n", t)?
+                    }
+                    DynamicValue::Null => vm_write!(self, "<Null>\n")?,
+                    DynamicValue::Sequence(_) => vm_write!(self, "<Sequência>\n")?,
+                    DynamicValue::File(_) => vm_write!(self, "<Arquivo>\n")?,
+                    DynamicValue::Database(_) => vm_write!(self, "<Banco>\n")?,
+                    DynamicValue::Bool(b) => vm_write!(self, "(Bool) {}\n", bool_name(b))?,
+                    DynamicValue::List(id) => {
+                        let string = match self.conv_to_string(DynamicValue::List(id)) {
+                            Ok(s) => s,
+                            Err(e) => return Err(e)
+                        };
+                        vm_write!(self, "{}\n", string)?;
+                    }
+                    DynamicValue::Map(id) => {
+                        let string = self.conv_to_string(DynamicValue::Map(id))?;
+
+                        vm_write!(self, "{}\n", string)?;
+                    }
+                }
+
+                self.auto_flush_stdout();
+            }
+            Instruction::PrintMathB => {
+                let val = self.registers.math_b;
+
+                self.print_value(val)?;
+                self.auto_flush_stdout();
+            }
+            Instruction::PrintNewLine => {
+                vm_write!(self, "\n")?;
+                self.auto_flush_stdout();
+            }
+            Instruction::Quit => {
+                self.registers.has_quit = true;
+
+                return Ok(ExecutionStatus::Quit);
+            }
+            Instruction::FlushStdout => {
+                self.flush_stdout();
+            }
+            Instruction::Compare => {
+                self.check_comparable(self.registers.math_a, self.registers.math_b)?;
+
+                let result = match self.compare(self.registers.math_a, self.registers.math_b) {
+                    Ok(c) => c,
+                    Err(e) => return Err(e),
+                };
+
+                match self.set_last_comparision(result) {
+                    Ok(_) => {}
+                    Err(e) => return Err(e)
+                }
+            }
+            Instruction::Return => {
+
+                if self.callstack.len() == 1 {
+                    self.registers.has_quit = true;
+
+                    return Ok(ExecutionStatus::Quit);
+                }
+
+                let mut frame = match self.callstack.pop() {
+                    Some(f) => f,
+                    None => return Err("Erro no return : Nenhuma função em execução".to_owned())
+                };
+
+                // With more than one value, the first one is the one that goes to TREZE
+                let values = std::mem::take(&mut frame.return_values);
+
+                if let Some(&first) = values.first() {
+                    self.registers.math_b = first;
+                } else if let Some(id) = self.registers.math_b.special_id() {
+                    self.special_storage.increment_ref(id)?;
+                }
+
+                let index = self.last_frame_index()?;
+                let val = self.registers.math_b;
+                match self.write_to(val, index, 0) {
+                    Ok(_) => {}
+                    Err(e) => return Err(e)
+                }
+
+                // Each value kept for PEGA DA JAULA already has its own reference
+                self.clear_return_values()?;
+
+                if values.is_empty() {
+                    self.return_values.push(val);
+                } else {
+                    self.return_values = values;
+                }
+
+                // The variables of the function are gone, so are their references. Only after the value returned was
+                // written, since it may be one of them
+                for id in frame.stack.iter().filter_map(|v| v.special_id()) {
+                    self.special_storage.decrement_ref(id)?;
+                }
+
+                // A return from inside a loop leaves its label
+                self.drop_loop_labels(std::mem::take(&mut frame.label_stack))?;
+
+                #[cfg(feature = "refcount-debug")]
+                self.check_ref_counts();
+
+                // If this is the global function and we're in interactive mode, print the return value
+
+                if self.callstack.len() == 1 && self.registers.is_interactive {
+                    self.run_instruction(Instruction::PrintMathBDebug)?; // Return val is in math_b already
+                }
+
+                return Ok(ExecutionStatus::Returned);
+            }
+            Instruction::Jump(target) => self.set_current_pc(target)?,
+            Instruction::JumpIfNot(req, target) => {
+                if ! self.last_comparision_matches(req)? {
+                    self.set_current_pc(target)?;
+                }
+            }
+            Instruction::MakeNewFrame(id) => {
+                // Add a new, not ready frame to the callstack
+
+                if let Some(max) = self.registers.max_call_depth {
+                    if self.callstack.len() >= max {
+                        return Err(format!("A stack de chamadas estourou : mais de {} funções rodando ao mesmo tempo. \
+                                            Tem uma recursão sem fim aí?", max));
+                    }
+                }
+
+                let mut frame = FunctionFrame::new(id, self.registers.default_stack_size);
+                frame.name = self.function_names.get(id).cloned().unwrap_or_default();
+
+                self.callstack.push(frame);
+            }
+            Instruction::TailCall(id) => {
+                let mut frame = match self.callstack.pop() {
+                    Some(f) if f.id == id && ! f.ready => f,
+                    _ => return Err("Erro no tail call : A última frame não é da função chamada".to_owned()),
+                };
+
+                let index = self.get_last_ready_index().ok_or_else(|| "Erro no tail call : Nenhuma função em execução".to_owned())?;
+
+                frame.ready = true;
+
+                // The frame running has nothing left to do, so it goes away like in a return, but without giving a
+                // value : the new one will return to where it would
+                let old = std::mem::replace(&mut self.callstack[index], frame);
+
+                for id in old.stack.iter().chain(old.return_values.iter()).filter_map(|v| v.special_id()) {
+                    self.special_storage.decrement_ref(id)?;
+                }
+
+                self.drop_loop_labels(old.label_stack)?;
+
+                #[cfg(feature = "refcount-debug")]
+                self.check_ref_counts();
+            }
+            Instruction::SetLastFrameReady => {
+                // Set the last frame to ready
+
+                if ! self.callstack.is_empty() {
+                    self.callstack.last_mut().unwrap().ready = true;
+                } else {
+                    return Err("Callstack vazia".to_owned());
+                }
+            }
+            Instruction::AssertMathBCompatible(kind) => {
+                let v = self.registers.math_b;
+
+                match v {
+                    // Anything that PRA CADA can go through is accepted as a sequence
+                    DynamicValue::Text(_) | DynamicValue::List(_) | DynamicValue::Integer(_)
+                        if kind == TypeKind::Sequence => {}
+                    DynamicValue::Null => return Err("Tipo incompatível : Null".to_owned()),
+                    DynamicValue::Text(_) => {
+                        if kind == TypeKind::Text {
+                            // Ok
+                        } else {
+                            return Err("Tipo incompatível : Texto".to_owned());
+                        }
+                    }
+                    DynamicValue::Integer(_) => {
+                        if kind == TypeKind::Integer || kind == TypeKind::Number {
+                            // Ok
+                        } else {
+                            return Err("Tipo incompatível : Int ou Num".to_owned());
+                        }
+                    }
+                    DynamicValue::Number(_) => {
+                        if kind == TypeKind::Number {
+                            // Ok
+                        } else {
+                            return Err("Tipo incompatível : Number".to_owned());
+                        }
+                    }
+                    DynamicValue::List(_) => {
+                        if kind == TypeKind::List {
+                            // Ok
+                        } else {
+                            return Err("Tipo incompatível : Lista".to_owned());
+                        }
+                    }
+                    DynamicValue::Sequence(_) => {
+                        if kind == TypeKind::Sequence {
+                            // Ok
+                        } else {
+                            return Err("Tipo incompatível : Sequência".to_owned());
+                        }
+                    }
+                    DynamicValue::File(_) => {
+                        if kind == TypeKind::File {
+                            // Ok
+                        } else {
+                            return Err("Tipo incompatível : Arquivo".to_owned());
+                        }
+                    }
+                    DynamicValue::Database(_) => {
+                        if kind == TypeKind::Database {
+                            // Ok
+                        } else {
+                            return Err("Tipo incompatível : Banco".to_owned());
+                        }
+                    }
+                    DynamicValue::Bool(_) => {
+                        if kind == TypeKind::Bool {
+                            // Ok
+                        } else {
+                            return Err("Tipo incompatível : Booleano".to_owned());
+                        }
+                    }
+                    DynamicValue::Map(_) => {
+                        if kind == TypeKind::Map {
+                            // Ok
+                        } else {
+                            return Err("Tipo incompatível : Mapa".to_owned());
+                        }
+                    }
+                }
+            }
+            Instruction::ReadInput => {
+                // Whatever was asked has to show up before the answer is waited for
+                self.flush_stdout();
+
+                let line = match self.provided_input.pop_front() {
+                    Some(l) => Some(l),
+                    None => self.io.read_line()?,
+                };
+
+                if line.is_none() && self.registers.await_input {
+                    // Go back so this instruction runs again when resumed, hopefully with some input
+                    self.decrement_pc()?;
+
+                    return Ok(ExecutionStatus::AwaitingInput);
+                }
+
+                let parent_index = match self.get_last_ready_index() {
+                    Some(s) => s,
+                    None => return Err("Nenhuma função em execução".to_owned())
+                };
+
+                if let Some(line) = line {
+                    let id = match self.add_special_item(parent_index, SpecialItemData::Text(line)) {
+                        Ok(id) => id,
+                        Err(e) => return Err(e)
+                    };
+
+                    self.registers.intermediate = DynamicValue::Text(id);
+                } else {
+                    // Input ended
+                    self.registers.intermediate = DynamicValue::Null;
+                }
+            }
+            Instruction::ConvertToNum => {
+                let val = self.registers.math_b;
+
+                let v = match self.conv_to_num(val) {
+                    Ok(v) => v,
+                    Err(e) => return Err(e)
+                };
+
+                self.registers.math_b = DynamicValue::Number(v);
+            }
+            Instruction::ConvertToInt => {
+                let val = self.registers.math_b;
+
+                let v = match self.conv_to_int(val) {
+                    Ok(v) => v,
+                    Err(e) => return Err(e)
+                };
+
+                self.registers.math_b = DynamicValue::Integer(v);
+            }
+            Instruction::ConvertToString => {
+                let val = self.registers.math_b;
+
+                let id = if let DynamicValue::Text(id) = val {
+                    id
+                } else {
+                    let v = match self.conv_to_string(val) {
+                        Ok(v) => v,
+                        Err(e) => return Err(e)
+                    };
+
+                    let parent_index = match self.get_last_ready_index() {
+                        Some(s) => s,
+                        None => return Err("Nenhuma função em execução".to_owned())
+                    };
+
+                    match self.add_special_item(parent_index, SpecialItemData::Text(v)) {
+                        Ok(id) => id,
+                        Err(e) => return Err(e)
+                    }
+                };
+
+                self.registers.math_b = DynamicValue::Text(id);
+            }
+            Instruction::PushValMathA(val) => {
+                match self.raw_to_dynamic(val) {
+                    Ok(v) => self.registers.math_a = v,
+                    Err(e) => return Err(e)
+                }
+            }
+            Instruction::PushValMathB(val) => {
+                match self.raw_to_dynamic(val) {
+                    Ok(v) => self.registers.math_b = v,
+                    Err(e) => return Err(e)
+                }
+            }
+            Instruction::PushIntermediateToA => {
+                self.registers.math_a = self.registers.intermediate;
+            }
+            Instruction::PushIntermediateToB => {
+                self.registers.math_b = self.registers.intermediate;
+            }
+            Instruction::ReadGlobalVarFrom(addr) => {
+                let val = match self.read_from_id(0, addr) {
+                    Ok(v) => v,
+                    Err(e) => return Err(e)
+                };
+
+                self.registers.intermediate = val;
+            }
+            Instruction::WriteGlobalVarTo(addr) => {
+                let index = 0;
+                let val = self.registers.math_b;
+
+                match self.write_to(val, index, addr) {
+                    Ok(_) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            Instruction::ReadVarFrom(addr) => {
+                let index = match self.get_last_ready_index() {
+                    Some(i) => i,
+                    None => return Err("Nenhuma função pronta em execução".to_owned()),
+                };
+
+                let val = match self.read_from_id(index, addr) {
+                    Ok(v) => v,
+                    Err(e) => return Err(e)
+                };
+
+                self.registers.intermediate = val;
+            }
+            Instruction::ReadVarFromFrame(depth, name) => {
+                let index = match self.get_last_ready_index() {
+                    Some(i) => i,
+                    None => return Err("Nenhuma função pronta em execução".to_owned()),
+                };
+
+                // The global function (index 0) doesn't count, since its variables are accessed as globals
+
+                if index <= depth {
+                    return Err(format!("Não tem nenhuma função {} chamadas acima dessa pra ler a variável {}", depth, name));
+                }
+
+                let frame_index = index - depth;
+                let id = self.callstack[frame_index].id;
+
+                let address = match self.variable_names.get(id).and_then(|names| names.get(name.as_str())) {
+                    Some(a) => *a,
+                    None => return Err(format!("A função que chamou essa não tem uma variável chamada {}", name)),
+                };
+
+                let val = self.read_from_id(frame_index, address)?;
+
+                self.registers.intermediate = val;
+            }
+            Instruction::WriteVarTo(addr) => {
+                let index = match self.get_last_ready_index() {
+                    Some(i) => i,
+                    None => return Err("Nenhuma função pronta em execução".to_owned()),
+                };
+
+                let val = self.registers.math_b;
+
+                match self.write_to(val, index, addr) {
+                    Ok(_) => {}
+                    Err(e) => return Err(e)
+                }
+            }
+            Instruction::WriteVarToLast(addr) => {
+                let index = self.last_frame_index()?;
+                let val = self.registers.math_b;
+
+                match self.write_to(val, index, addr) {
+                    Ok(_) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            Instruction::Add => {
+                let left = self.registers.math_a;
+                let right = self.registers.math_b;
+                let res = match self.add_values(left, right) {
+                    Ok(v) => v,
+                    Err(e) => return Err(e)
+                };
+
+                self.registers.math_b = self.fit_integer(res);
+            }
+            Instruction::Mul => {
+                let left = self.registers.math_a;
+                let right = self.registers.math_b;
+                let res = match self.mul_values(left, right) {
+                    Ok(v) => v,
+                    Err(e) => return Err(e)
+                };
+
+                self.registers.math_b = self.fit_integer(res);
+            }
+            Instruction::Div => {
+                let left = self.registers.math_a;
+                let right = self.registers.math_b;
+                let res = match self.div_values(left, right) {
+                    Ok(v) => v,
+                    Err(e) => return Err(e)
+                };
+
+                self.registers.math_b = self.fit_integer(res);
+            }
+            Instruction::Mod => {
+                let left = self.registers.math_a;
+                let right = self.registers.math_b;
+                let res = self.rem_values(left, right)?;
+
+                self.registers.math_b = self.fit_integer(res);
+            }
+            Instruction::Pow => {
+                let left = self.registers.math_a;
+                let right = self.registers.math_b;
+                let res = self.pow_values(left, right)?;
+
+                self.registers.math_b = self.fit_integer(res);
+            }
+            Instruction::Sub => {
+                let left = self.registers.math_a;
+                let right = self.registers.math_b;
+                let res = match self.sub_values(left, right) {
+                    Ok(v) => v,
+                    Err(e) => return Err(e)
+                };
+
+                self.registers.math_b = self.fit_integer(res);
+            }
+            Instruction::SwapMath => {
+                let tmp = self.registers.math_b;
+                self.registers.math_b = self.registers.math_a;
+                self.registers.math_a = tmp;
+            }
+            Instruction::ClearMath => {
+                self.registers.math_a = DynamicValue::Null;
+                self.registers.math_b = DynamicValue::Null;
+                self.registers.intermediate = DynamicValue::Null;
+            }
+            Instruction::AddLoopLabel => {
+                let next_pc = match self.get_current_pc() {
+                    Some(p) => p,
+                    None => return Err("Nenhuma função em execução".to_owned())
+                };
+
+                match self.get_last_ready_mut() {
+                    Some(f) => f.label_stack.push(LoopLabel::new(next_pc)),
+                    None => return Err("Nenhuma função em execução".to_owned())
+                }
+            }
+            Instruction::RestoreLoopLabel => {
+                let (mut address, mut step) = (None, DynamicValue::Null);
+
+                let pc = match self.get_last_ready_ref() {
+                    Some(f) => {
+                        let label = match f.label_stack.last() {
+                            Some(l) => l,
+                            None => return Err(format!("Restore : Nenhuma label disponível {}", f.describe()))
+                        };
+
+                        if let Some(addr) = label.index_address {
+                            address = Some(addr);
+                            step = label.stepping;
+                        }
+
+                        label.start_pc
+                    }
+                    None => return Err("Nenhuma função em execução".to_owned())
+                };
+
+                self.set_current_pc(pc)?;
+
+                if let Some(address) = address {
+                    let index = match self.get_last_ready_index() {
+                        Some(i) => i,
+                        None => return Err("Nenhuma função pronta em execução".to_owned()),
+                    };
+
+                    let current = self.read_from_id(index, address)?;
+
+                    let result = self.add_values(current, step)?;
+
+                    match self.write_to(result, index, address) {
+                        Ok(_) => {}
+                        Err(e) => return Err(e)
+                    }
+                }
+            }
+            Instruction::PopLoopLabel => {
+                let label = match self.get_last_ready_mut() {
+                    Some(f) => {
+                        match f.label_stack.pop() {
+                            Some(l) => l,
+                            None => return Err(format!("Não havia nenhuma label pra remover {}", f.describe()))
+                        }
+                    }
+                    None => return Err("Nenhuma função em execução".to_owned())
+                };
+
+                // Give back what the iterator was keeping alive now, instead of waiting for the next plugin call
+                if label.iterator.is_some() {
+                    drop(label);
+                    self.release_dropped_refs()?;
+                }
+            }
+            Instruction::IterateMathB => {
+                // Like RegisterIncrementOnRestore, this is right after AddLoopLabel and must only run once
+
+                let iterator = iterate(self.registers.math_b, self)?;
+
+                match self.get_last_ready_mut().and_then(|f| f.label_stack.last_mut()) {
+                    Some(l) => {
+                        l.iterator = Some(iterator);
+                        l.start_pc += 1;
+                    }
+                    None => return Err("Função atual não tem nenhuma label".to_owned()),
+                }
+            }
+            Instruction::NextFromIterator => {
+                // Taken out of the label while it runs, since it needs the VM
+                let mut iterator = match self.get_last_ready_mut().and_then(|f| f.label_stack.last_mut()) {
+                    Some(l) => match l.iterator.take() {
+                        Some(i) => i,
+                        None => return Err("A label atual não tem nada pra percorrer".to_owned()),
+                    },
+                    None => return Err("Função atual não tem nenhuma label".to_owned()),
+                };
+
+                let next = iterator.next(self);
+
+                if let Some(l) = self.get_last_ready_mut().and_then(|f| f.label_stack.last_mut()) {
+                    l.iterator = Some(iterator);
+                }
+
+                let comparision = match next? {
+                    Some(value) => {
+                        self.registers.math_b = value;
+                        Comparision::Equal
+                    }
+                    None => {
+                        self.registers.math_b = DynamicValue::Null;
+                        Comparision::NotEqual
+                    }
+                };
+
+                self.set_last_comparision(comparision)?;
+            }
+            Instruction::RegisterIncrementOnRestore(address) => {
+                // Since this instruction is right after AddLabel, this is going to be executed each iteration
+                // and since we don't want that, we'll also increment the PC on the label
+
+                let stepping = self.registers.math_b;
+
+                // With no step the loop would never end, and with NaN it'd never start
+                match stepping {
+                    DynamicValue::Integer(0) => {
+                        return Err("O passo do REPETE não pode ser zero, senão o loop nunca acaba".to_owned());
+                    }
+                    DynamicValue::Number(n) if n == 0.0 || n.is_nan() => {
+                        return Err(format!("O passo do REPETE não pode ser {}, senão o loop nunca acaba", n));
+                    }
+                    DynamicValue::Integer(_) | DynamicValue::Number(_) => {}
+                    other => return Err(format!("O passo do REPETE tem que ser um número, mas é {}", other.get_kind())),
+                }
+
+                match self.get_last_ready_mut() {
+                    Some(s) => match s.label_stack.last_mut() {
+                        Some(l) => {
+                            l.stepping = stepping;
+                            l.index_address = Some(address);
+                            // As explained above
+                            l.start_pc += 1;
+                        }
+                        None => return Err("Função atual não tem nenhuma label".to_owned()),
+                    }
+                    None => return Err("Nenhuma função em execução".to_owned())
+                };
+            }
+            Instruction::PushReturnValue => {
+                let val = self.registers.math_b;
+
+                // Held until the function returns, since it may be a variable that is gone by then
+                if let Some(id) = val.special_id() {
+                    self.special_storage.increment_ref(id)?;
+                }
+
+                match self.get_last_ready_mut() {
+                    Some(f) => f.return_values.push(val),
+                    None => return Err("Nenhuma função em execução".to_owned())
+                }
+            }
+            Instruction::ReadReturnValue(index) => {
+                match self.return_values.get(index) {
+                    Some(&value) => self.registers.math_b = value,
+                    None => return Err(format!("A função retornou {} valor(es), mas o valor {} foi pedido",
+                                               self.return_values.len(), index + 1)),
+                }
+            }
+            Instruction::CompareLoopIndex => {
+                let stepping = match self.get_last_ready_ref().and_then(|f| f.label_stack.last()) {
+                    Some(l) => l.stepping,
+                    None => return Err("Função atual não tem nenhuma label".to_owned()),
+                };
+
+                self.check_comparable(self.registers.math_a, self.registers.math_b)?;
+
+                let index = self.compare(self.registers.math_a, self.registers.math_b)?;
+
+                // Going up it stops at the end or past it, and going down the same. So a step that doesn't divide the
+                // range never skips the end, and a range in the other direction doesn't run at all
+                let comparision = match (self.compare(stepping, DynamicValue::Integer(0))?, index) {
+                    (Comparision::MoreThan, Comparision::LessThan) | (Comparision::LessThan, Comparision::MoreThan) => {
+                        Comparision::Equal
+                    }
+                    _ => Comparision::NotEqual,
+                };
+
+                self.set_last_comparision(comparision)?;
+            }
+            Instruction::MakeNewList => {
+                let index = match self.get_last_ready_index() {
+                    Some(i) => i,
+                    None => return Err("Nenhuma função em execução".to_owned())
+                };
+
+                let data = match self.add_special_item(index, SpecialItemData::List(vec![])) {
+                    Ok(d) => d,
+                    Err(e) => return Err(e)
+                };
+
+                self.registers.math_b = DynamicValue::List(data);
+            }
+            Instruction::IndexList => {
+                let index = if let DynamicValue::Integer(i) = self.registers.math_b {
+                    i
+                } else {
+                    return Err(format!("Esperado um índice na forma de um inteiro, encontrado {:?}", self.registers.math_b))
+                };
+
+                let value = {
+                    if let DynamicValue::List(id) = self.registers.intermediate {
+                        match self.special_storage.get_data_ref(id) {
+                            Some(SpecialItemData::List(ref d)) => {
+                                if index as usize >= d.len() {
+                                    return Err(format!("Erro : Index depois do final da lista. Tamanho da lista : {}", d.len()));
+                                }
+
+                                *d[index as usize]
+                            }
+                            Some(_) => return Err(format!("Erro interno : DynamicValue é uma lista, mas o item na memória não")),
+                            None => return Err("Erro interno : ID inválida".to_owned())
+                        }
+                    } else {
+                        return Err(format!("Variável passada não é uma lista"));
+                    }
+                };
+
+                self.registers.math_b = value;
+            }
+            Instruction::AddToListAtIndex => {
+                let index = if let DynamicValue::Integer(val) = self.registers.secondary {
+                    Some(val)
+                } else {
+                    None
+                };
+
+                let value = self.registers.math_b;
+
+                let list_id = if let DynamicValue::List(id) = self.registers.intermediate {
+                    id
+                } else {
+                    return Err(format!("AddListToIndex : A variável não é uma lista"));
+                };
+
+                let len = match self.special_storage.get_data_ref(list_id) {
+                    Some(SpecialItemData::List(ref list)) => list.len(),
+                    _ => 0,
+                };
+
+                self.check_list_size(len + 1)?;
+
+                let list = match self.special_storage.get_data_mut(list_id) {
+                    Some(l) => match l {
+                        SpecialItemData::List(ref mut list) => list,
+                        _ => return Err("Item especial com a ID passada não é uma lista".to_owned())
+                    }
+                    None => return Err("ID da lista não encontrada".to_owned())
+                };
+
+                if let Some(i) = index {
+                    if i as usize >= list.len() {
+                        list.push(Box::new(value));
+                    } else {
+                        list.insert(i as usize, Box::new(value));
+                    }
+                } else {
+                    list.push(Box::new(value));
+                }
+
+                // The list holds a reference to its elements
+                if let Some(id) = value.special_id() {
+                    self.special_storage.increment_ref(id)?;
+                }
+            }
+            Instruction::ClearSecondary => {
+                self.registers.secondary = DynamicValue::Null;
+            }
+            Instruction::PushMathBToSeconday => {
+                let val = self.registers.math_b;
+                self.registers.secondary = val;
+            }
+            Instruction::RemoveFromListAtIndex => {
+                let index = if let DynamicValue::Integer(i) = self.registers.math_b {
+                    i
+                } else {
+                    return Err(format!("Esperado um inteiro como índice pra lista, encontrado {:?}", self.registers.math_b));
+                };
+
+                let id = if let DynamicValue::List(id) = self.registers.intermediate {
+                    id
+                } else {
+                    return Err("A variável não é uma lista".to_owned());
+                };
+
+                let removed = match self.special_storage.get_data_mut(id) {
+                    Some(SpecialItemData::List(ref mut list)) => {
+                        if index as usize >= list.len() {
+                            return Err(format!("Erro : Index maior que a lista. Tamanho da lista : {}", list.len()));
+                        }
+
+                        list.remove(index as usize)
+                    }
+                    Some(_) => return Err("Erro interno : DynamicValue é uma lista mas o valor na memória não".to_owned()),
+                    None => return Err("Erro interno : ID não encontrada".to_owned())
+                };
+
+                if let Some(id) = removed.special_id() {
+                    self.special_storage.decrement_ref(id)?;
+                }
+            }
+            Instruction::SortList => {
+                let id = match self.registers.intermediate {
+                    DynamicValue::List(id) => id,
+                    other => return Err(format!("ORDENA : A variável não é uma lista, é {}", other.get_kind())),
+                };
+
+                let comparator = match self.registers.math_b {
+                    DynamicValue::Null => None,
+                    DynamicValue::Text(_) => Some(self.map_key(self.registers.math_b)?),
+                    other => return Err(format!("ORDENA recebe o nome da função que compara os elementos, mas recebeu {}",
+                                                other.get_kind())),
+                };
+
+                self.sort_list(id, comparator.as_deref())?;
+            }
+            Instruction::QueryListSize => {
+                let id = if let DynamicValue::List(id) = self.registers.intermediate {
+                    id
+                } else {
+                    return Err("QueryListSize : Variável não é uma lista".to_owned());
+                };
+
+                let list = match self.special_storage.get_data_ref(id) {
+                    Some(l) => match l {
+                        SpecialItemData::List(l) => l,
+                        _ => return Err("Erro interno : ID não aponta pra uma lista".to_owned())
+                    }
+                    None => return Err("Não encontrado item com a ID passada".to_owned())
+                };
+
+                let val = DynamicValue::Integer(list.len() as IntegerType);
+
+                self.registers.math_b = val;
+            }
+            Instruction::MakeNewMap => {
+                let index = match self.get_last_ready_index() {
+                    Some(i) => i,
+                    None => return Err("Nenhuma função em execução".to_owned())
+                };
+
+                let id = self.add_special_item(index, SpecialItemData::Map(HashMap::new()))?;
+
+                self.registers.math_b = DynamicValue::Map(id);
+            }
+            Instruction::SetMapEntry => {
+                let key = match self.expression_stack.pop() {
+                    Some(k) => self.map_key(k)?,
+                    None => return Err("Erro interno : A pilha de expressões está vazia".to_owned())
+                };
+
+                let id = match self.expression_stack.last() {
+                    Some(&DynamicValue::Map(id)) => id,
+                    _ => return Err("A variável não é um mapa".to_owned())
+                };
+
+                let value = self.registers.math_b;
+
+                // The map holds a reference to its values. The new one is counted first, in case it's the one replaced
+                if let Some(id) = value.special_id() {
+                    self.special_storage.increment_ref(id)?;
+                }
+
+                let replaced = match self.special_storage.get_data_mut(id) {
+                    Some(SpecialItemData::Map(ref mut map)) => map.insert(key, value),
+                    Some(_) => return Err("Erro interno : DynamicValue é um mapa mas o valor na memória não".to_owned()),
+                    None => return Err("Erro interno : ID não encontrada".to_owned())
+                };
+
+                if let Some(id) = replaced.and_then(|v| v.special_id()) {
+                    self.special_storage.decrement_ref(id)?;
+                }
+            }
+            Instruction::IndexMap => {
+                let key = self.map_key(self.registers.math_b)?;
+                let id = self.map_in_intermediate()?;
+
+                let value = match self.special_storage.get_data_ref(id) {
+                    Some(SpecialItemData::Map(ref map)) => map.get(&key).cloned().unwrap_or(DynamicValue::Null),
+                    Some(_) => return Err("Erro interno : DynamicValue é um mapa mas o valor na memória não".to_owned()),
+                    None => return Err("Erro interno : ID não encontrada".to_owned())
+                };
+
+                self.registers.math_b = value;
+            }
+            Instruction::RemoveFromMap => {
+                let key = self.map_key(self.registers.math_b)?;
+                let id = self.map_in_intermediate()?;
+
+                let removed = match self.special_storage.get_data_mut(id) {
+                    Some(SpecialItemData::Map(ref mut map)) => map.remove(&key),
+                    Some(_) => return Err("Erro interno : DynamicValue é um mapa mas o valor na memória não".to_owned()),
+                    None => return Err("Erro interno : ID não encontrada".to_owned())
+                };
+
+                if let Some(id) = removed.and_then(|v| v.special_id()) {
+                    self.special_storage.decrement_ref(id)?;
+                }
+            }
+            Instruction::QueryMapSize => {
+                let id = self.map_in_intermediate()?;
+
+                let size = match self.special_storage.get_data_ref(id) {
+                    Some(SpecialItemData::Map(ref map)) => map.len(),
+                    Some(_) => return Err("Erro interno : DynamicValue é um mapa mas o valor na memória não".to_owned()),
+                    None => return Err("Erro interno : ID não encontrada".to_owned())
+                };
+
+                self.registers.math_b = DynamicValue::Integer(size as IntegerType);
+            }
+            Instruction::ListMapKeys => {
+                let id = self.map_in_intermediate()?;
+
+                let mut keys = match self.special_storage.get_data_ref(id) {
+                    Some(SpecialItemData::Map(ref map)) => map.keys().cloned().collect::<Vec<String>>(),
+                    Some(_) => return Err("Erro interno : DynamicValue é um mapa mas o valor na memória não".to_owned()),
+                    None => return Err("Erro interno : ID não encontrada".to_owned())
+                };
+
+                keys.sort();
+
+                let index = match self.get_last_ready_index() {
+                    Some(i) => i,
+                    None => return Err("Nenhuma função em execução".to_owned())
+                };
+
+                let mut list = vec![];
+
+                // Each key is held by the list
+                for key in keys {
+                    let id = self.add_special_item(index, SpecialItemData::Text(key))?;
+                    self.special_storage.increment_ref(id)?;
+
+                    list.push(Box::new(DynamicValue::Text(id)));
+                }
+
+                let id = self.add_special_item(index, SpecialItemData::List(list))?;
+
+                self.registers.math_b = DynamicValue::List(id);
+            }
+            Instruction::CallPlugin(address, num) => {
+                if address >= self.plugins.len() {
+                    return Err("CallPlugin : Endereço inválido".to_owned());
+                }
+
+                let plugin = self.plugins[address];
+
+                if num > self.plugin_argument_stack.len() {
+                    return Err(format!("CallPlugin : Número de argumentos maior que a quantidade de argumentos disponíveis"));
+                }
+
+                let mut args = Vec::with_capacity(num);
+
+                for _ in 0..num {
+                    let val = match self.plugin_argument_stack.pop() {
+                        Some(v) => v,
+                        None => unreachable!()
+                    };
+
+                    args.push(val);
+                }
+
+                // The arguments are only lent to the plugin : the references taken when they were pushed are given
+                // back after it returns, so a plugin that needs an item later has to retain it
+                let lent_start = self.lent_plugin_arguments.len();
+                self.lent_plugin_arguments.extend(args.iter().cloned());
+
+                let result = plugin(args, self).and_then(|r| self.store_plugin_result(r));
+                let lent = self.lent_plugin_arguments.split_off(lent_start);
+
+                for value in lent {
+                    self.release(value)?;
+                }
+
+                self.release_dropped_refs()?;
+
+                result?;
+            }
+            Instruction::PushMathBPluginArgument => {
+                let val = self.registers.math_b;
+
+                // Held by the argument stack, so it isn't freed if the variable changes before the call
+                match val {
+                    DynamicValue::Text(id) | DynamicValue::List(id) | DynamicValue::Sequence(id) | DynamicValue::File(id) | DynamicValue::Database(id) | DynamicValue::Map(id) => self.special_storage.increment_ref(id)?,
+                    _ => {}
+                }
+
+                self.plugin_argument_stack.push(val);
+            }
+            Instruction::Halt => {
+                return Ok(ExecutionStatus::Halt);
+            }
+            Instruction::PushMathBToStack => {
+                let val = self.registers.math_b;
+                self.expression_stack.push(val);
+            }
+            Instruction::PopStackToMathA => {
+                match self.expression_stack.pop() {
+                    Some(v) => self.registers.math_a = v,
+                    None => return Err("Erro interno : A pilha de expressões está vazia".to_owned())
+                }
+            }
+            Instruction::CompareToMathB(req) => {
+                self.check_comparable(self.registers.math_a, self.registers.math_b)?;
+
+                let result = self.compare(self.registers.math_a, self.registers.math_b)?;
+
+                let matches = VirtualMachine::comparision_matches(result, req);
+
+                self.registers.math_b = DynamicValue::Bool(matches);
+            }
+            Instruction::Not => {
+                let val = self.registers.math_b;
+
+                let truthy = self.is_truthy(val)?;
+
+                self.registers.math_b = DynamicValue::Bool(! truthy);
+            }
+            Instruction::CompareTruthiness => {
+                let val = self.registers.math_b;
+
+                let result = if self.is_truthy(val)? { Comparision::Equal } else { Comparision::NotEqual };
+
+                self.set_last_comparision(result)?;
+            }
+            Instruction::SkipIfMathBFalsy(count) => {
+                let val = self.registers.math_b;
+
+                if ! self.is_truthy(val)? {
+                    self.skip_instructions(count)?;
+                }
+            }
+            Instruction::DispatchMethod(method, table) => {
+                let kind = self.registers.math_b.get_kind();
+
+                // Integers can also be used where numbers are expected
+                let found = table.iter().find(|&&(k, _)| k == kind)
+                    .or_else(|| if kind == TypeKind::Integer {
+                        table.iter().find(|&&(k, _)| k == TypeKind::Number)
+                    } else {
+                        None
+                    });
+
+                match found {
+                    Some(&(_, count)) => self.skip_instructions(count)?,
+                    None => return Err(format!("{} não é um método de {}", method, kind)),
+                }
+            }
+            Instruction::Skip(count) => self.skip_instructions(count)?,
+            Instruction::AssertMathEqual => {
+                let (actual, expected) = (self.registers.math_a, self.registers.math_b);
+
+                if self.compare(actual, expected)? != Comparision::Equal {
+                    let failure = AssertionFailure {
+                        expected : self.asserted_value(expected)?,
+                        actual : self.asserted_value(actual)?,
+                    };
+
+                    let message = failure.message();
+                    self.assertion_failure = Some(failure);
+
+                    return Err(message);
+                }
+            }
+            Instruction::SkipIfMathBTruthy(count) => {
+                let val = self.registers.math_b;
+
+                if self.is_truthy(val)? {
+                    self.skip_instructions(count)?;
+                }
+            }
+            Instruction::TryDecrementRefAt(address) => {
+                let index = match self.get_last_ready_index() {
+                    Some(i) => i,
+                    None => return Err("".to_owned()),
+                };
+
+                match self.read_from_id(index, address) {
+                    Ok(v) => match v {
+                        DynamicValue::List(id) => self.special_storage.decrement_ref(id)?,
+                        DynamicValue::Text(id) => self.special_storage.decrement_ref(id)?,
+                        DynamicValue::Sequence(id) => self.special_storage.decrement_ref(id)?,
+                        DynamicValue::File(id) => self.special_storage.decrement_ref(id)?,
+                        DynamicValue::Database(id) => self.special_storage.decrement_ref(id)?,
+                        DynamicValue::Map(id) => self.special_storage.decrement_ref(id)?,
+                        _ => {}
+                    }
+                    Err(e) => return Err(e),
+                }
+
+                // The variable is gone, so the slot can't keep the ID around. Otherwise the next write there (or the
+                // return of the function) would decrement it again
+                self.callstack[index].stack[address] = DynamicValue::Null;
+            }
+        }
+
+        Ok(ExecutionStatus::Normal)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Instruction {
+    PrintMathB,
+    PrintMathBDebug,
+    PrintNewLine,
+    /// Flush the output, even if it's not flushed automatically
+    FlushStdout,
+    Quit,
+    Compare,
+    Return,
+    /// Continue the execution from the instruction with the index given, in the code of the current function
+    Jump(usize),
+    /// Jump to the instruction with the index given if the last comparision doesn't match the request. The start of
+    /// every conditional block and loop, with the index of the instruction after its end
+    JumpIfNot(ComparisionRequest, usize),
+    MakeNewFrame(usize),
+    SetLastFrameReady,
+    /// Like SetLastFrameReady, for a call of a function to itself that is the last thing it does : the new frame (with
+    /// the ID given) takes the place of the one running, so the recursion doesn't grow the callstack
+    TailCall(usize),
+    // For use when pushing arguments for a function. Check if the value on the top of the main stack
+    // has a compatible type
+    AssertMathBCompatible(TypeKind),
+    // Get a line of input and put it at the top of the main stack
+    ReadInput,
+    // Turn the main stack top into string
+    ConvertToString,
+    // Turn the main stack top into num
+    ConvertToNum,
+    // Turn the main stack top into int
+    ConvertToInt,
+    PushValMathA(RawValue),
+    PushValMathB(RawValue),
+    PushIntermediateToA,
+    PushIntermediateToB,
+    PushMathBToSeconday,
+    ClearSecondary,
+    /// Read a global var to the intermediary register
+    ReadGlobalVarFrom(usize),
+    /// When writing, values are read from the math b register
+    WriteGlobalVarTo(usize),
+    ReadVarFrom(usize),
+    /// Read the variable with the name from the function `depth` calls above the current one, into intermediate.
+    /// Only variables in the function scope of the caller can be read
+    ReadVarFromFrame(usize, String),
+    WriteVarTo(usize),
+    WriteVarToLast(usize),
+    SwapMath,
+    ClearMath,
+    Add,
+    Mul,
+    Div,
+    /// The remainder of dividing math a by math b, with the sign of math a
+    Mod,
+    /// Math a raised to the power of math b
+    Pow,
+    Sub,
+    /// Saves the current PC so when the loop ends it can return to it's beginning
+    AddLoopLabel,
+    /// Return to a previous saved loop label
+    RestoreLoopLabel,
+    /// Remove a previously saved label
+    PopLoopLabel,
+    /// Retrieve the increment value from MathB and write it on every Restore to the specified address
+    RegisterIncrementOnRestore(usize),
+    /// Create a new list and put the result at MathB
+    MakeNewList,
+    /// Index a list with the ID from the intermediate register and the index from MathB, and put the result in MathB
+    IndexList,
+    /// Add the result in MathB to the list in the intermediate register, using the index at the secondary register
+    /// if the secondary register is Null, the element is placed on the back of the list
+    AddToListAtIndex,
+    /// Remove the element at the index located in MathB from the list in the intermediate register
+    RemoveFromListAtIndex,
+    /// Sort the list in the intermediate register in place. If MathB has a text, it's the name of the function that
+    /// compares two elements, otherwise numbers and texts are sorted in their natural order
+    SortList,
+    /// Query the list from the intermediate address and write its size to the MathB
+    QueryListSize,
+    /// Create a new map and put it at MathB
+    MakeNewMap,
+    /// Pop a key from the expression stack and set it to the value in MathB, in the map left at the top of the stack
+    SetMapEntry,
+    /// Put the value of the key in MathB, from the map in the intermediate register, in MathB. If the map doesn't have
+    /// the key, the result is Null
+    IndexMap,
+    /// Remove the key in MathB from the map in the intermediate register, if it's there
+    RemoveFromMap,
+    /// Write the number of keys of the map in the intermediate register to MathB
+    QueryMapSize,
+    /// Write a list with the keys of the map in the intermediate register, in order, to MathB
+    ListMapKeys,
+    /// Call a plugin function with a number of arguments to pop from the stack
+    CallPlugin(usize, usize),
+    /// Push the value in MathB to the Plugin Argument stack
+    PushMathBPluginArgument,
+    /// Halt the execution
+    Halt,
+    /// Try decrementing the ref count of the object in the specified location in the current frame (if special item),
+    /// leaving null in its place
+    TryDecrementRefAt(usize),
+    /// Push the value in MathB to the expression stack, saving it while the other side of an operation is evaluated
+    PushMathBToStack,
+    /// Pop the last value saved on the expression stack into MathA
+    PopStackToMathA,
+    /// Compare MathA with MathB and write to MathB whether the comparision matches the request
+    CompareToMathB(ComparisionRequest),
+    /// Write to MathB whether the value in it is falsy
+    Not,
+    /// Set the last comparision to Equal if the value in MathB is truthy, or NotEqual if not, so a single condition
+    /// works with JumpIfNot
+    CompareTruthiness,
+    /// Skip the next n instructions if the value in MathB is falsy. Used to short-circuit &&
+    SkipIfMathBFalsy(usize),
+    /// Skip the next n instructions if the value in MathB is truthy. Used to short-circuit ||
+    SkipIfMathBTruthy(usize),
+    /// Fail if the value in MathA (the actual one) isn't equal to MathB (the expected one)
+    AssertMathEqual,
+    /// Skip the number of instructions given for the type of the value in MathB, to call the function bound to a
+    /// method (named in the text) for that type. Fails if no function was bound for the type
+    DispatchMethod(String, Vec<(TypeKind, usize)>),
+    /// Skip the next n instructions
+    Skip(usize),
+    /// Make an iterator that goes through the value in MathB and attach it to the last loop label. Like
+    /// RegisterIncrementOnRestore, it's right after AddLoopLabel and only runs once
+    IterateMathB,
+    /// Put the next value of the iterator of the last loop label in MathB and set the last comparision to Equal, or, if
+    /// there are no more values, put Null in MathB and set it to NotEqual
+    NextFromIterator,
+    /// Set the last comparision to Equal if the index in MathA hasn't reached the end in MathB yet, going in the
+    /// direction of the stepping of the last loop label, or NotEqual if it has
+    CompareLoopIndex,
+    /// Add the value in MathB to the values the current function is going to return
+    PushReturnValue,
+    /// Put the value number n (from 0) returned by the last function that was called in MathB. Fails if it returned less
+    /// than that
+    ReadReturnValue(usize),
+}
+
+mod tests {
+    #[test]
+    fn text_and_list_limits() {
+        use context::Context;
+        use error::BirlError;
+        use vm_io::CaptureIo;
+
+        // Without the standard library, since its globals would count for the limits too
+        let run = |src : &str| {
+            let mut ctx = Context::new();
+
+            let _ = ctx.set_io(Box::new(CaptureIo::new("")));
+            ctx.set_max_text_size(Some(16));
+            ctx.set_max_list_size(Some(3));
+
+            ctx.add_source_string(src.to_owned())
+                .and_then(|_| ctx.call_function_by_id(0, vec![]))
+                .and_then(|_| ctx.start_program())
+        };
+
+        let doubling = "JAULA SHOW\n\
+                        VEM: T, \"BIRL\"\n\
+                        REPETE: I, 0, 10\n\
+                        BORA: T, T + T\n\
+                        FIM\n\
+                        SAINDO DA JAULA";
+
+        match run(doubling) {
+            Ok(_) => panic!("O texto passou do limite sem erro"),
+            Err(BirlError::Type(message, _)) => assert!(message.contains("32 bytes"), "{}", message),
+            Err(e) => panic!("{:?}", e),
+        }
+
+        let growing = "JAULA SHOW\n\
+                       FAZ UMA LISTA: L\n\
+                       REPETE: I, 0, 10\n\
+                       PÕE ISSO AQUI: L, I\n\
+                       FIM\n\
+                       SAINDO DA JAULA";
+
+        match run(growing) {
+            Ok(_) => panic!("A lista passou do limite sem erro"),
+            Err(BirlError::Runtime(message, _)) => assert!(message.contains("4 elementos"), "{}", message),
+            Err(e) => panic!("{:?}", e),
+        }
+
+        // Within the limits, nothing fails
+
+        if let Err(e) = run("JAULA SHOW\nVEM: T, \"BIRL\" + \"BIRL\"\nSAINDO DA JAULA") {
+            panic!("{}", e);
+        }
+    }
+
+    #[test]
+    fn arena_allocation() {
+        use context::Context;
+        use testing::run_in_context;
+        use vm::VirtualMachineBuilder;
+
+        let mut ctx = match Context::with_vm(VirtualMachineBuilder::new().arena_allocation(true)) {
+            Ok(c) => c,
+            Err(e) => panic!("{}", e),
+        };
+
+        let src = "JAULA SHOW\n\
+                   VEM: T, \"\"\n\
+                   FAZ UMA LISTA: L\n\
+                   REPETE: I, 0, 3\n\
+                   BORA: T, T + \"BIRL\"\n\
+                   PÕE ISSO AQUI: L, T\n\
+                   FIM\n\
+                   CE QUER VER ISSO: L\n\
+                   SAINDO DA JAULA";
+
+        let (result, output) = run_in_context(&mut ctx, src);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "(Lista) [ \"BIRL\", \"BIRLBIRL\", \"BIRLBIRLBIRL\" ]\n");
+    }
+
+    #[test]
+    fn resume_and_interrupt() {
+        use context::Context;
+        use vm::ExecutionStatus;
+        use vm_io::CaptureIo;
+        use std::sync::atomic::Ordering;
+
+        let io = CaptureIo::new("");
+        let output = io.output();
+
+        let mut ctx = Context::new();
+        let _ = ctx.set_io(Box::new(io));
+
+        if let Err(e) = ctx.add_source_string("CE QUER VER ISSO: 1\nCE QUER VER ISSO: 2".to_owned()) {
+            panic!("{}", e);
+        }
+
+        if let Err(e) = ctx.interactive_prepare_resume() {
+            panic!("{}", e);
+        }
+
+        if let Err(e) = ctx.call_function_by_id(0, vec![]) {
+            panic!("{}", e);
+        }
+
+        assert_eq!(ctx.stop_reason(), None);
+
+        // Interrupting stops before running anything
+
+        ctx.interrupt_handle().store(true, Ordering::SeqCst);
+
+        assert_eq!(ctx.resume(), Ok(ExecutionStatus::Interrupted));
+        assert_eq!(ctx.stop_reason(), Some(ExecutionStatus::Interrupted));
+        assert_eq!(output.borrow().as_str(), "");
+
+        // Resuming continues from where it stopped, until the Halt
+
+        assert_eq!(ctx.resume(), Ok(ExecutionStatus::Halt));
+        assert_eq!(ctx.stop_reason(), Some(ExecutionStatus::Halt));
+        assert_eq!(output.borrow().as_str(), "1\n2\n");
+    }
+
+    #[test]
+    fn error_status() {
+        use context::{ Context, BIRL_MAIN_FUNCTION_ID };
+        use vm::ExecutionStatus;
+        use vm_io::CaptureIo;
+
+        let mut ctx = Context::new();
+        let _ = ctx.set_io(Box::new(CaptureIo::new("")));
+
+        ctx.call_function_by_id(0, vec![]).unwrap();
+        ctx.add_source_string("JAULA SHOW\nVEM: X, 1\nCE QUER VER ISSO: X / 0\nSAINDO DA JAULA".to_owned()).unwrap();
+
+        assert_eq!(ExecutionStatus::from(ctx.resume()), ExecutionStatus::Halt);
+
+        ctx.call_function_by_id(BIRL_MAIN_FUNCTION_ID, vec![]).unwrap();
+
+        // The error is both the Err of resume and the reason it stopped, with where it happened
+        let status = ExecutionStatus::from(ctx.resume());
+
+        assert_eq!(ctx.stop_reason(), Some(status.clone()));
+
+        let location = status.error().unwrap().location();
+
+        assert_eq!((location.function_id, location.line), (Some(BIRL_MAIN_FUNCTION_ID), Some(3)));
+        assert!(location.instruction.is_some());
+        assert_eq!(location.trace.len(), 1);
+    }
+
+    #[test]
+    fn breakpoints() {
+        use context::Context;
+        use vm::{ DynamicValue, ExecutionStatus };
+        use vm_io::CaptureIo;
+
+        let io = CaptureIo::new("");
+        let output = io.output();
+
+        let mut ctx = Context::new();
+        let _ = ctx.set_io(Box::new(io));
+
+        // The breakpoint is in the code of CONTA, so the calls have to run it
+        ctx.set_inlining(false);
+
+        let src = "JAULA CONTA (N : BATATA DOCE)\n\
+                   VEM: DOBRO, N * 2\n\
+                   CE QUER VER ISSO: DOBRO\n\
+                   SAINDO DA JAULA\n\
+                   É HORA DO: CONTA, 5\n\
+                   É HORA DO: CONTA, 7";
+
+        let result = ctx.call_function_by_id(0, vec![])
+            .and_then(|_| ctx.add_source_string(src.to_owned()))
+            .and_then(|_| ctx.interactive_prepare_resume());
+
+        assert_eq!(result, Ok(()));
+
+        let id = match ctx.compiled_functions().into_iter().find(|&(_, ref name, _)| name == "CONTA") {
+            Some((id, _, _)) => id,
+            None => panic!("A função CONTA não foi compilada"),
+        };
+
+        // Stop at the CE QUER VER ISSO
+        let instruction = match (0..100).find(|&i| ctx.source_line_at(id, i) == Some(3)) {
+            Some(i) => i,
+            None => panic!("Nenhuma instrução na linha 3"),
+        };
+
+        ctx.set_breakpoint(id, instruction);
+
+        assert_eq!(ctx.resume(), Ok(ExecutionStatus::Breakpoint));
+        assert_eq!(ctx.stop_reason(), Some(ExecutionStatus::Breakpoint));
+        assert_eq!(ctx.current_location(), Some((id, instruction)));
+        assert_eq!(output.borrow().as_str(), "");
+
+        let variables = ctx.current_frame_variables();
+        let names = variables.iter().map(|&(ref name, _)| name.as_str()).collect::<Vec<&str>>();
+
+        // TREZE, where the function returns its result, is always the first
+        assert_eq!(names, vec!["TREZE", "N", "DOBRO"]);
+        assert!(matches!(variables[1].1, DynamicValue::Integer(5)));
+        assert!(matches!(variables[2].1, DynamicValue::Integer(10)));
+
+        match ctx.current_frame_stack() {
+            Some(stack) => assert!(stack.iter().any(|v| matches!(*v, DynamicValue::Integer(10)))),
+            None => panic!("Nenhuma função em execução"),
+        }
+
+        // Stepping runs the instruction the breakpoint is at, instead of stopping there again
+        assert_eq!(ctx.step(), Ok(ExecutionStatus::Normal));
+        assert_ne!(ctx.current_location(), Some((id, instruction)));
+
+        assert_eq!(ctx.continue_run(), Ok(ExecutionStatus::Breakpoint));
+        assert_eq!(output.borrow().as_str(), "10\n");
+        assert!(matches!(ctx.current_frame_variables()[1].1, DynamicValue::Integer(7)));
+
+        let value = ctx.current_frame_variables()[2].1;
+        assert_eq!(ctx.value_to_string(value), Ok("14".to_owned()));
+
+        assert!(ctx.remove_breakpoint(id, instruction));
+        assert!(! ctx.remove_breakpoint(id, instruction));
+
+        assert_eq!(ctx.continue_run(), Ok(ExecutionStatus::Halt));
+        assert_eq!(output.borrow().as_str(), "10\n14\n");
+    }
+
+    #[test]
+    fn fuel() {
+        use context::Context;
+        use error::BirlError;
+        use vm::{ ExecutionStatus, VirtualMachineBuilder };
+        use vm_io::CaptureIo;
+
+        let io = CaptureIo::new("");
+        let output = io.output();
+
+        let mut ctx = match Context::with_vm(VirtualMachineBuilder::new().fuel(Some(0))) {
+            Ok(c) => c,
+            Err(e) => panic!("{}", e),
+        };
+
+        let _ = ctx.set_io(Box::new(io));
+
+        let src = "VEM: I, 0\n\
+                   ENQUANTO É MENOR: I, 10000\n\
+                   BORA: I, I + 1\n\
+                   FIM\n\
+                   CE QUER VER ISSO: I";
+
+        let result = ctx.call_function_by_id(0, vec![])
+            .and_then(|_| ctx.add_source_string(src.to_owned()))
+            .and_then(|_| ctx.interactive_prepare_resume());
+
+        assert_eq!(result, Ok(()));
+
+        // Nothing runs without fuel
+        assert_eq!(ctx.resume(), Ok(ExecutionStatus::OutOfFuel));
+        assert_eq!(ctx.current_location(), Some((0, 0)));
+
+        ctx.set_fuel(1000);
+
+        assert_eq!(ctx.resume(), Ok(ExecutionStatus::OutOfFuel));
+        assert_eq!(ctx.stop_reason(), Some(ExecutionStatus::OutOfFuel));
+        assert_eq!(ctx.get_fuel(), Some(0));
+        assert_eq!(output.borrow().as_str(), "");
+
+        // Giving more continues from where it stopped
+        ctx.set_fuel(1_000_000);
+
+        assert_eq!(ctx.resume(), Ok(ExecutionStatus::Halt));
+        assert_eq!(output.borrow().as_str(), "10000\n");
+
+        // Context::start_program has no one to give more fuel, so running out is an error
+        let mut ctx = match Context::with_vm(VirtualMachineBuilder::new().fuel(Some(100))) {
+            Ok(c) => c,
+            Err(e) => panic!("{}", e),
+        };
+
+        let result = ctx.call_function_by_id(0, vec![])
+            .and_then(|_| ctx.add_source_string(src.to_owned()))
+            .and_then(|_| ctx.start_program());
+
+        assert!(matches!(result, Err(BirlError::Runtime(ref message, _)) if message == "Acabou o combustível da VM"),
+                "{:?}", result);
+    }
+
+    #[test]
+    fn execution_steps() {
+        use context::Context;
+        use vm::ExecutionStatus;
+        use error::BirlError;
+        use vm_io::CaptureIo;
+
+        let io = CaptureIo::new("");
+        let output = io.output();
+
+        let mut ctx = Context::new();
+        let _ = ctx.set_io(Box::new(io));
+
+        if let Err(e) = ctx.add_source_string("CE QUER VER ISSO: 1\nCE QUER VER ISSO: 2".to_owned()) {
+            panic!("{}", e);
+        }
+
+        if let Err(e) = ctx.call_function_by_id(0, vec![]) {
+            panic!("{}", e);
+        }
+
+        // A few instructions at a time, like a host running some every tick
+        let mut ticks = 0;
+
+        loop {
+            let statuses = ctx.steps().take(2).collect::<Result<Vec<ExecutionStatus>, BirlError>>();
+            let statuses = match statuses {
+                Ok(s) => s,
+                Err(e) => panic!("{}", e),
+            };
+
+            ticks += 1;
+
+            if statuses.is_empty() || statuses.last() == Some(&ExecutionStatus::Halt) {
+                break;
+            }
+
+            assert!(statuses.iter().all(|s| *s == ExecutionStatus::Normal));
+        }
+
+        assert!(ticks > 1);
+        assert_eq!(output.borrow().as_str(), "1\n2\n");
+        assert_eq!(ctx.stop_reason(), Some(ExecutionStatus::Halt));
+
+        // The iteration ends after the Halt
+        let mut steps = ctx.steps();
+
+        assert_eq!(steps.next(), Some(Ok(ExecutionStatus::Halt)));
+        assert_eq!(steps.next(), None);
+    }
+
+    #[test]
+    fn method_calls() {
+        use context::Context;
+        use parser::TypeKind;
+        use vm_io::CaptureIo;
+
+        let io = CaptureIo::new("");
+        let output = io.output();
+
+        let mut ctx = Context::new();
+        let _ = ctx.set_io(Box::new(io));
+
+        let functions = "JAULA TAMANHO DA LISTA (L : LISTA)\n\
+                         FALA O TAMANHO: L, TAMANHO\n\
+                         BIRL: TAMANHO\n\
+                         SAINDO DA JAULA\n\
+                         JAULA DOBRO (X : TRAPÉZIO DESCENDENTE)\n\
+                         BIRL: X * 2\n\
+                         SAINDO DA JAULA";
+
+        let program = "JAULA SHOW\n\
+                       VEM: T, \"BIRL\"\n\
+                       FAZ UMA LISTA: L\n\
+                       PÕE ISSO AQUI: L, 1\n\
+                       É HORA DO: T.TAMANHO\n\
+                       CE QUER VER ISSO: TREZE\n\
+                       É HORA DO: L.TAMANHO\n\
+                       CE QUER VER ISSO: TREZE\n\
+                       É HORA DO: T.DIVIDE, \"R\"\n\
+                       CE QUER VER ISSO: TREZE\n\
+                       VEM: N, 21\n\
+                       É HORA DO: N.DOBRO\n\
+                       CE QUER VER ISSO: TREZE\n\
+                       É HORA DO: N.TAMANHO\n\
+                       SAINDO DA JAULA";
+
+        let result = ctx.call_function_by_id(0, vec![])
+            .and_then(|_| ctx.add_standard_library())
+            .and_then(|_| ctx.add_source_string(functions.to_owned()))
+            .and_then(|_| ctx.add_method(TypeKind::List, "TAMANHO".to_owned(), "TAMANHO DA LISTA".to_owned()))
+            .and_then(|_| ctx.add_source_string(program.to_owned()));
+
+        if let Err(e) = result {
+            panic!("{}", e);
+        }
+
+        // The method depends on the type of the variable, and DOBRO isn't bound to any, so it's just called with N
+        match ctx.start_program() {
+            Ok(_) => panic!("TAMANHO não devia existir pra inteiros"),
+            Err(e) => assert!(e.message().contains("TAMANHO não é um método de Inteiro"), "{}", e),
+        }
+
+        assert_eq!(output.borrow().as_str(), "4\n1\n(Lista) [ \"BI\", \"L\" ]\n42\n");
+
+        // Binding a function that doesn't take the type as the first argument
+        assert!(ctx.add_method(TypeKind::Text, "DOBRO".to_owned(), "DOBRO".to_owned()).is_err());
+    }
+
+    #[test]
+    fn awaiting_input() {
+        use context::Context;
+        use vm::ExecutionStatus;
+        use vm_io::CaptureIo;
+
+        let io = CaptureIo::new("");
+        let output = io.output();
+
+        let mut ctx = Context::new();
+        let _ = ctx.set_io(Box::new(io));
+        ctx.set_await_input(true);
+
+        let src = "FALA AÍ: NOME\nCE QUER VER ISSO: \"BORA, \" + NOME";
+
+        if let Err(e) = ctx.add_source_string(src.to_owned()) {
+            panic!("{}", e);
+        }
+
+        if let Err(e) = ctx.interactive_prepare_resume() {
+            panic!("{}", e);
+        }
+
+        if let Err(e) = ctx.call_function_by_id(0, vec![]) {
+            panic!("{}", e);
+        }
+
+        // No input yet, so it waits, and keeps waiting until some is given
+
+        assert_eq!(ctx.resume(), Ok(ExecutionStatus::AwaitingInput));
+        assert_eq!(ctx.resume(), Ok(ExecutionStatus::AwaitingInput));
+
+        ctx.provide_input("BAMBAM".to_owned());
+
+        assert_eq!(ctx.resume(), Ok(ExecutionStatus::Halt));
+        assert_eq!(output.borrow().as_str(), "BORA, BAMBAM\n");
+    }
+
+    #[test]
+    fn number_formatting() {
+        use testing::run_program;
+
+        let src = "JAULA SHOW\n\
+                   CE QUER VER ISSO: 0.1 + 0.2\n\
+                   CE QUER VER ISSO: 2.5 * 2.0\n\
+                   CE QUER VER ISSO: 1.0 / 3.0\n\
+                   CE QUER VER ISSO: 1000000.0 * 1000000.0 * 1000000000.0\n\
+                   CE QUER VER ISSO: 1.0 / 10000000.0\n\
+                   VEM: N, 0.5\n\
+                   MUDA PRA TEXTO: N\n\
+                   CE QUER VER ISSO: \"N = \" + N\n\
+                   SAINDO DA JAULA";
+
+        let (result, output) = run_program(src);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "0.30000000000000004\n5\n0.3333333333333333\n1e21\n1e-7\nN = 0.5\n");
+    }
+
+    #[test]
+    fn integer_width() {
+        use context::Context;
+        use testing::run_in_context;
+        use vm::{ VirtualMachineBuilder, IntegerWidth };
+
+        let mut ctx = match Context::with_vm(VirtualMachineBuilder::new().integer_width(IntegerWidth::Bits32)) {
+            Ok(c) => c,
+            Err(e) => panic!("{}", e),
+        };
+
+        let src = "JAULA SHOW\n\
+                   CE QUER VER ISSO: 2147483647 + 1\n\
+                   CE QUER VER ISSO: 65536 * 65536\n\
+                   VEM: X, \"4294967297\"\n\
+                   MUDA PRA INTEIRO: X\n\
+                   CE QUER VER ISSO: X\n\
+                   SAINDO DA JAULA";
+
+        let (result, output) = run_in_context(&mut ctx, src);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "-2147483648\n0\n1\n");
+
+        // Wider than the integers the crate was built with
+        let too_wide = VirtualMachineBuilder::new().integer_width(IntegerWidth::Bits128);
+
+        assert_eq!(Context::with_vm(too_wide).is_ok(), IntegerWidth::native() == IntegerWidth::Bits128);
+    }
+
+    #[test]
+    fn runtime_error_lines() {
+        use context::Context;
+        use error::BirlError;
+        use playground::{ run, Limits };
+
+        let run_source = |src : &str| {
+            let mut ctx = Context::new();
+
+            if let Err(e) = ctx.add_source_string(src.to_owned()) {
+                panic!("{}", e);
+            }
+
+            ctx.call_function_by_id(0, vec![]).and_then(|_| ctx.start_program())
+        };
+
+        let src = "JAULA SHOW\n\
+                   VEM: A, \"BIRL\"\n\
+                   \n\
+                   VEM: B, A * 2\n\
+                   SAINDO DA JAULA";
+
+        match run_source(src) {
+            Err(BirlError::Type(ref message, ref location)) if location.line == Some(4) =>
+                assert!(message.ends_with("não são compatíveis : Text(0) e Integer(2) (linha 4, na JAULA SHOW)"), "{}", message),
+            result => panic!("Era esperado um erro na linha 4, recebido {:?}", result),
+        }
+
+        // Every runtime error has it, not only the ones about numbers
+        let src = "JAULA SHOW\n\
+                   FAZ UMA LISTA: A\n\
+                   ME DA ESSE: A, 3, B\n\
+                   SAINDO DA JAULA";
+
+        match run_source(src) {
+            Err(BirlError::Runtime(ref message, ref location)) if location.line == Some(3) =>
+                assert!(message.ends_with(" (linha 3, na JAULA SHOW)"), "{}", message),
+            result => panic!("Era esperado um erro na linha 3, recebido {:?}", result),
+        }
+
+        // The playground points to the line too
+        let result = run("\nVEM: A, 1 / 0", "", &Limits::default());
+
+        match result.diagnostics.first().and_then(|d| d.span) {
+            Some(span) => assert_eq!((span.line, span.column_start, span.column_end), (2, 1, 14)),
+            None => panic!("O erro não tem onde aconteceu : {:?}", result.diagnostics),
+        }
+    }
+
+    #[test]
+    fn error_traces() {
+        use context::Context;
+        use error::{ BirlError, TraceEntry };
+        use testing::run_in_context;
+
+        let run = |src : &str| -> BirlError {
+            let mut ctx = Context::new();
+            ctx.set_inlining(false);
+
+            match run_in_context(&mut ctx, src).0 {
+                Err(e) => e,
+                result => panic!("Era esperado um erro, recebido {:?}", result),
+            }
+        };
+
+        let entry = |function : Option<&str>, line : usize| TraceEntry {
+            function : function.map(|f| f.to_owned()),
+            line : Some(line),
+        };
+
+        let src = "JAULA DIVIDE(A : BATATA DOCE)\n\
+                   VEM: X, 10 / A\n\
+                   SAINDO DA JAULA\n\
+                   JAULA CONTA(A : BATATA DOCE)\n\
+                   É ELE QUE A GENTE QUER: A, 0\n\
+                   É MAIOR\n\
+                   É HORA DO: CONTA, A - 1\n\
+                   FIM\n\
+                   É HORA DO: DIVIDE, A\n\
+                   SAINDO DA JAULA\n\
+                   JAULA SHOW\n\
+                   É HORA DO: CONTA, 1\n\
+                   SAINDO DA JAULA";
+
+        let error = run(src);
+
+        assert_eq!(error.location().trace, vec![entry(Some("SHOW"), 12), entry(Some("CONTA"), 7), entry(Some("CONTA"), 9),
+                                                entry(Some("DIVIDE"), 2)]);
+        assert_eq!(error.location().format_trace(), "Rastro (a chamada mais recente por último) :\n  \
+                                                     na JAULA SHOW, linha 12\n  \
+                                                     na JAULA CONTA, linha 7\n  \
+                                                     na JAULA CONTA, linha 9\n  \
+                                                     na JAULA DIVIDE, linha 2");
+
+        // A function called by a plugin has the call to the plugin before it, and the message only says where the error
+        // really happened
+        let src = "JAULA DOBRA(A : BATATA DOCE)\n\
+                   VEM: X, 10 / A\n\
+                   SAINDO DA JAULA\n\
+                   É HORA DO: MAPEIA, 2, \"DOBRA\"\n\
+                   PRA CADA: V, TREZE\n\
+                   FIM";
+
+        let error = run(src);
+
+        assert_eq!(error.message(), "Divisão por zero (linha 2, na JAULA DOBRA)");
+        assert_eq!(error.location().trace, vec![entry(None, 5), entry(Some("DOBRA"), 2)]);
+
+        // An error right where the program is doesn't need a trace
+        let error = run("JAULA SHOW\nVEM: X, 1 / 0\nSAINDO DA JAULA");
+
+        assert_eq!(error.location().trace, vec![entry(Some("SHOW"), 2)]);
+        assert_eq!(error.location().format_trace(), "");
+    }
+
+    #[test]
+    fn division_by_zero() {
+        use context::Context;
+        use error::BirlError;
+        use testing::run_in_context;
+
+        let run = |src : &str| {
+            let mut ctx = Context::new();
+            ctx.set_inlining(false);
+
+            run_in_context(&mut ctx, src).0
+        };
+
+        let src = "JAULA DIVIDE(A : BATATA DOCE)\n\
+                   VEM: X, 10 / A\n\
+                   SAINDO DA JAULA\n\
+                   JAULA SHOW\n\
+                   VEM: Y, 7 / 2\n\
+                   É HORA DO: DIVIDE, 0\n\
+                   SAINDO DA JAULA";
+
+        let result = run(src);
+        assert!(matches!(result, Err(BirlError::Runtime(ref message, _))
+                         if message == "Divisão por zero (linha 2, na JAULA DIVIDE)"),
+                "{:?}", result);
+
+        // Numbers follow the floating point rules instead
+        assert_eq!(run("JAULA SHOW\nVEM: X, 1.0 / 0\nSAINDO DA JAULA"), Ok(()));
+    }
+
+    #[test]
+    fn modulo() {
+        use error::BirlError;
+        use testing::run_program;
+
+        let src = "JAULA SHOW\n\
+                   CE QUER VER ISSO: 7 % 3, \" \", -7 % 3, \" \", 7 % -3, \" \", 7.5 % 2, \" \", 1 + 10 % 4 * 2\n\
+                   PRA CADA: I, 16\n\
+                   É ELE QUE A GENTE QUER: I % 15, 0\n\
+                   É ELE MEMO:\n\
+                   CE QUER VER ISSO: \"FIZZBUZZ\"\n\
+                   FIM\n\
+                   FIM\n\
+                   SAINDO DA JAULA";
+
+        let (result, output) = run_program(src);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "1 -1 1 1.5 5\nFIZZBUZZ\nFIZZBUZZ\n");
+
+        let (result, _) = run_program("JAULA SHOW\nVEM: X, 0\nVEM: Y, 10 % X\nSAINDO DA JAULA");
+
+        assert!(matches!(result, Err(BirlError::Runtime(ref message, _))
+                         if message == "Resto de divisão por zero (linha 3, na JAULA SHOW)"),
+                "{:?}", result);
+    }
+
+    #[test]
+    fn booleans() {
+        use error::BirlError;
+        use testing::run_program;
+
+        let src = "JAULA INVERTE (B: SIM OU NÃO)\n\
+                   BIRL: !B\n\
+                   SAINDO DA JAULA\n\
+                   JAULA SHOW\n\
+                   VEM: X, 0\n\
+                   ENQUANTO É MEMO: X < 3\n\
+                   BORA: X, X + 1\n\
+                   FIM\n\
+                   VEM: PRONTO, X > 2\n\
+                   CE QUER VER ISSO: PRONTO, \" \", MENTIRA, \" \", PRONTO == 1\n\
+                   É HORA DO: INVERTE, PRONTO\n\
+                   É ELE QUE A GENTE QUER: TREZE\n\
+                   NUM É ELE:\n\
+                   CE QUER VER ISSO: \"INVERTEU\"\n\
+                   FIM\n\
+                   MUDA PRA INTEIRO: PRONTO\n\
+                   CE QUER VER ISSO: PRONTO\n\
+                   SAINDO DA JAULA";
+
+        let (result, output) = run_program(src);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "VERDADE MENTIRA MENTIRA\nINVERTEU\n1\n");
+
+        let (result, _) = run_program("JAULA SHOW\nVEM: X, VERDADE + 1\nSAINDO DA JAULA");
+
+        match result {
+            Ok(_) => panic!("Somou um booleano"),
+            Err(BirlError::Type(message, _)) => assert!(message.contains("não são compatíveis"), "{}", message),
+            Err(e) => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn power() {
+        use context::Context;
+        use error::BirlError;
+        use testing::run_in_context;
+        use vm::{ VirtualMachineBuilder, IntegerWidth, OverflowPolicy };
+
+        let run = |src : &str, policy : OverflowPolicy| {
+            let builder = VirtualMachineBuilder::new().integer_width(IntegerWidth::Bits32).overflow_policy(policy);
+
+            let mut ctx = match Context::with_vm(builder) {
+                Ok(c) => c,
+                Err(e) => panic!("{}", e),
+            };
+
+            run_in_context(&mut ctx, src)
+        };
+
+        let src = "JAULA SHOW\n\
+                   CE QUER VER ISSO: 2 ^ 10, \" \", 2 ^ 3 ^ 2, \" \", -2 ^ 2, \" \", (-2) ^ 3, \" \", 5 ^ 0\n\
+                   CE QUER VER ISSO: 2 ^ -1, \" \", 4 ^ 0.5, \" \", 1.5 ^ 2, \" \", 1 ^ 100000000000, \" \", -1 ^ 3\n\
+                   SAINDO DA JAULA";
+
+        let (result, output) = run(src, OverflowPolicy::Error);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "1024 512 -4 -8 1\n0.5 2 2.25 1 -1\n");
+
+        let src = "JAULA SHOW\nCE QUER VER ISSO: 2 ^ 31\nSAINDO DA JAULA";
+
+        assert_eq!(run(src, OverflowPolicy::Wrap), (Ok(()), "-2147483648\n".to_owned()));
+        assert_eq!(run(src, OverflowPolicy::Float), (Ok(()), "2147483648\n".to_owned()));
+
+        let (result, _) = run(src, OverflowPolicy::Error);
+        assert!(matches!(result, Err(BirlError::Runtime(ref message, _))
+                         if message == "Estouro : O resultado de 2 ^ 31 não cabe num inteiro de 32 bits (linha 2, na JAULA SHOW)"),
+                "{:?}", result);
+    }
+
+    #[test]
+    fn overflow_policy() {
+        use context::Context;
+        use error::BirlError;
+        use testing::run_in_context;
+        use vm::{ VirtualMachineBuilder, IntegerWidth, OverflowPolicy };
+
+        let run = |policy : OverflowPolicy| {
+            let builder = VirtualMachineBuilder::new().integer_width(IntegerWidth::Bits32).overflow_policy(policy);
+
+            let mut ctx = match Context::with_vm(builder) {
+                Ok(c) => c,
+                Err(e) => panic!("{}", e),
+            };
+
+            let src = "JAULA SHOW\n\
+                       CE QUER VER ISSO: 2147483000 + 600\n\
+                       CE QUER VER ISSO: 2147483647 + 1\n\
+                       CE QUER VER ISSO: -2147483647 - 2\n\
+                       CE QUER VER ISSO: 65536 * 65536\n\
+                       VEM: MINIMO, -2147483647 - 1\n\
+                       CE QUER VER ISSO: MINIMO / -1\n\
+                       CE QUER VER ISSO: -MINIMO\n\
+                       SAINDO DA JAULA";
+
+            run_in_context(&mut ctx, src)
+        };
+
+        let (result, output) = run(OverflowPolicy::Wrap);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "2147483600\n-2147483648\n2147483647\n0\n-2147483648\n-2147483648\n");
+
+        let (result, output) = run(OverflowPolicy::Float);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "2147483600\n2147483648\n-2147483649\n4294967296\n2147483648\n2147483648\n");
+
+        let (result, output) = run(OverflowPolicy::Error);
+
+        assert!(matches!(result, Err(BirlError::Runtime(ref message, _))
+                         if message == "Estouro : O resultado de 2147483647 + 1 não cabe num inteiro de 32 bits (linha 3, na JAULA SHOW)"),
+                "{:?}", result);
+        assert_eq!(output, "2147483600\n");
+    }
+
+    #[test]
+    fn monster_mode() {
+        use context::Context;
+        use error::BirlError;
+        use testing::run_in_context;
+        use vm::{ VirtualMachineBuilder, IntegerWidth };
+
+        let run = |src : &str, monster : bool| {
+            let builder = VirtualMachineBuilder::new().integer_width(IntegerWidth::Bits32);
+
+            let mut ctx = match Context::with_vm(builder) {
+                Ok(c) => c,
+                Err(e) => panic!("{}", e),
+            };
+
+            if monster {
+                ctx.enable_monster_mode();
+            }
+
+            run_in_context(&mut ctx, src)
+        };
+
+        let compare = "JAULA SHOW\n\
+                       CE QUER VER ISSO: 1 == 1.0\n\
+                       CE QUER VER ISSO: \"1\" == 1\n\
+                       SAINDO DA JAULA";
+
+        assert_eq!(run(compare, false), (Ok(()), "VERDADE\nMENTIRA\n".to_owned()));
+
+        let (result, output) = run(compare, true);
+
+        assert!(matches!(result, Err(BirlError::Runtime(ref message, _))
+                         if message.starts_with("Comparação entre Texto e Inteiro")),
+                "{:?}", result);
+        assert_eq!(output, "VERDADE\n");
+
+        // The pragma turns it on too, and declarations need a value
+        let (result, _) = run("# USE MODO MONSTRO\nJAULA SHOW\nVEM: X\nSAINDO DA JAULA", false);
+        assert!(matches!(result, Err(BirlError::Compile(ref message, _))
+                         if message.contains("X foi declarada sem valor")),
+                "{:?}", result);
+
+        let (result, _) = run("#use modo monstro\nJAULA SHOW\nCE QUER VER ISSO: 2147483647 + 1\nSAINDO DA JAULA", false);
+        assert!(matches!(result, Err(BirlError::Runtime(ref message, _)) if message.starts_with("Estouro")),
+                "{:?}", result);
+
+        // Comparing with the same type still works
+        let (result, _) = run("#USE MODO MONSTRO\nJAULA SHOW\nE ELE QUE A GENTE QUER: \"A\", \"B\"\nSAINDO DA JAULA", false);
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test]
+    fn range_loop_steps() {
+        use error::BirlError;
+        use testing::run_program;
+
+        let run = |args : &str| {
+            let src = format!("JAULA SHOW\nREPETE: I, {}\nCE QUER VER: I, \" \"\nFIM\nSAINDO DA JAULA", args);
+            let (result, output) = run_program(&src);
+
+            result.map(|_| output)
+        };
+
+        assert_eq!(run("0, 3"), Ok("0 1 2 ".to_owned()));
+        assert_eq!(run("3, 0, (-1)"), Ok("3 2 1 ".to_owned()));
+
+        // A step that doesn't divide the range stops before passing the end
+        assert_eq!(run("0, 5, 2"), Ok("0 2 4 ".to_owned()));
+        assert_eq!(run("5, 0, (-2)"), Ok("5 3 1 ".to_owned()));
+        assert_eq!(run("0, 1, 0.25"), Ok("0 0.25 0.5 0.75 ".to_owned()));
+
+        // Empty ranges, and ranges going the other way, don't run
+        assert_eq!(run("0, 0"), Ok("".to_owned()));
+        assert_eq!(run("3, 0"), Ok("".to_owned()));
+        assert_eq!(run("0, 3, (-1)"), Ok("".to_owned()));
+
+        // A step that would never get to the end
+        assert!(matches!(run("0, 3, 0"), Err(BirlError::Runtime(ref message, _))
+                         if message.contains("não pode ser zero")));
+        assert!(matches!(run("0, 3, \"1\""), Err(BirlError::Runtime(ref message, _))
+                         if message.contains("tem que ser um número, mas é Texto")));
+    }
+
+    #[test]
+    fn loop_labels_left_behind() {
+        use context::Context;
+        use vm::{ VirtualMachine, Instruction };
+        use vm_io::CaptureIo;
+
+        let run = |src : &str| -> (String, usize) {
+            let io = CaptureIo::new("");
+            let output = io.output();
+            let mut ctx = Context::new();
+
+            let _ = ctx.set_io(Box::new(io));
+
+            let result = ctx.add_source_string(src.to_owned())
+                .and_then(|_| ctx.call_function_by_id(0, vec![]))
+                .and_then(|_| ctx.start_program());
+
+            if let Err(e) = result {
+                panic!("{}", e);
+            }
+
+            let output = output.borrow().clone();
+
+            (output, ctx.find_leaks().len())
+        };
+
+        // Returning from inside loops ends them, and lets go of the list the PRA CADA was going through
+        let (output, leaks) = run("JAULA PRIMEIRO\n\
+                                   FAZ UMA LISTA: L\n\
+                                   PÕE ISSO AQUI: L, \"BIRL\"\n\
+                                   PRA CADA: X, L\n\
+                                   REPETE: I, 0, 3\n\
+                                   BIRL: I\n\
+                                   FIM\n\
+                                   FIM\n\
+                                   SAINDO DA JAULA\n\
+                                   JAULA SHOW\n\
+                                   É HORA DO: PRIMEIRO\n\
+                                   CE QUER VER ISSO: TREZE\n\
+                                   SAINDO DA JAULA");
+
+        assert_eq!(output, "0\n");
+        assert_eq!(leaks, 0);
+
+        // Going back to a loop that isn't there says where
+        let mut vm = VirtualMachine::new();
+        vm.add_new_code();
+
+        let result = vm.run_instruction(Instruction::MakeNewFrame(0))
+            .and_then(|_| vm.run_instruction(Instruction::SetLastFrameReady));
+
+        if let Err(e) = result {
+            panic!("{}", e);
+        }
+
+        vm.callstack[0].name = Some("SHOW".into());
+
+        assert_eq!(vm.run_instruction(Instruction::RestoreLoopLabel).unwrap_err(),
+                   "Restore : Nenhuma label disponível na JAULA SHOW");
+        assert_eq!(vm.run_instruction(Instruction::PopLoopLabel).unwrap_err(),
+                   "Não havia nenhuma label pra remover na JAULA SHOW");
+    }
+
+    #[test]
+    fn auto_flush() {
+        use context::Context;
+        use vm_io::VmIo;
+        use std::rc::Rc;
+        use std::cell::RefCell;
+
+        // Writes what's printed and a | on each flush
+        struct FlushLog {
+            log : Rc<RefCell<String>>,
+            tty : bool,
+        }
+
+        impl VmIo for FlushLog {
+            fn read_line(&mut self) -> Result<Option<String>, String> {
+                Ok(None)
+            }
+
+            fn write(&mut self, text : &str) -> Result<(), String> {
+                self.log.borrow_mut().push_str(text);
+                Ok(())
+            }
+
+            fn flush(&mut self) -> Result<(), String> {
+                self.log.borrow_mut().push('|');
+                Ok(())
+            }
+
+            fn is_tty(&self) -> bool {
+                self.tty
+            }
+        }
+
+        let run = |src : &str, tty : bool, auto_flush : Option<bool>| -> String {
+            let log = Rc::new(RefCell::new(String::new()));
+            let mut ctx = Context::new();
+
+            let _ = ctx.set_io(Box::new(FlushLog { log : log.clone(), tty }));
+            ctx.set_auto_flush(auto_flush);
+
+            let result = ctx.add_source_string(src.to_owned())
+                .and_then(|_| ctx.call_function_by_id(0, vec![]))
+                .and_then(|_| ctx.start_program());
+
+            if let Err(e) = result {
+                panic!("{}", e);
+            }
+
+            let log = log.borrow().clone();
+
+            log
+        };
+
+        let print = "CE QUER VER ISSO: \"BIRL\"";
+
+        // By default, only a terminal is flushed on each print
+        assert_eq!(run(print, true, None), "BIRL|\n|");
+        assert_eq!(run(print, false, None), "BIRL\n");
+        assert_eq!(run(print, false, Some(true)), "BIRL|\n|");
+        assert_eq!(run(print, true, Some(false)), "BIRL\n");
+
+        // What was asked always shows up before the input is read
+        assert_eq!(run("CE QUER VER: \"NOME? \"\nFALA AÍ: NOME", false, Some(false)), "NOME? |");
+    }
+
+    #[test]
+    fn run_steps() {
+        use context::Context;
+        use vm::ExecutionStatus;
+        use vm_io::CaptureIo;
+        use std::rc::Rc;
+        use std::cell::RefCell;
+
+        let start = |src : &str| -> (Context, Rc<RefCell<String>>) {
+            let io = CaptureIo::new("");
+            let output = io.output();
+            let mut ctx = Context::new();
+
+            let _ = ctx.set_io(Box::new(io));
+
+            if let Err(e) = ctx.add_source_string(src.to_owned()).and_then(|_| ctx.call_function_by_id(0, vec![])) {
+                panic!("{}", e);
+            }
+
+            (ctx, output)
+        };
+
+        let (mut ctx, output) = start("REPETE: I, 0, 5\nCE QUER VER ISSO: I\nFIM");
+
+        // Nothing runs without steps
+        assert_eq!(ctx.run_steps(0), Ok(ExecutionStatus::Paused));
+        assert_eq!(output.borrow().as_str(), "");
+
+        // A few at a time, going on from where it stopped
+        let mut turns = 0;
+
+        loop {
+            turns += 1;
+
+            match ctx.run_steps(3) {
+                Ok(ExecutionStatus::Paused) => assert_eq!(ctx.stop_reason(), Some(ExecutionStatus::Paused)),
+                Ok(ExecutionStatus::Halt) => break,
+                other => panic!("{:?}", other),
+            }
+        }
+
+        assert!(turns > 1);
+        assert_eq!(output.borrow().as_str(), "0\n1\n2\n3\n4\n");
+
+        // The same with the error as a status
+        let (mut ctx, output) = start("REPETE: I, 0, 3\nCE QUER VER ISSO: 10 / (1 - I)\nFIM");
+        let mut statuses = vec![];
+
+        loop {
+            match ctx.run_for(2) {
+                ExecutionStatus::Paused => statuses.push("Paused"),
+                ExecutionStatus::Error(e) => {
+                    assert!(e.message().contains("Divisão por zero"), "{}", e.message());
+                    break;
+                }
+                other => panic!("{:?}", other),
+            }
+        }
+
+        assert!(statuses.len() > 1);
+        assert_eq!(output.borrow().as_str(), "10\n");
+
+        // Anything else that stops the execution comes before the end of the steps
+        let (mut ctx, output) = start("FALA AÍ: NOME\nCE QUER VER ISSO: NOME");
+        ctx.set_await_input(true);
+
+        assert_eq!(ctx.run_steps(1000), Ok(ExecutionStatus::AwaitingInput));
+
+        ctx.provide_input("BAMBAM".to_owned());
+
+        assert_eq!(ctx.run_steps(1000), Ok(ExecutionStatus::Halt));
+        assert_eq!(output.borrow().as_str(), "BAMBAM\n");
+    }
+
+    #[test]
+    fn decimal_separator() {
+        use context::Context;
+        use error::BirlError;
+        use vm::{ VirtualMachineBuilder, DecimalSeparator, parse_number };
+        use vm_io::CaptureIo;
+
+        assert_eq!(parse_number("3.25", DecimalSeparator::Point), Some(3.25));
+        assert_eq!(parse_number("3,25", DecimalSeparator::Point), None);
+        assert_eq!(parse_number(" 3,25 ", DecimalSeparator::Comma), Some(3.25));
+        assert_eq!(parse_number("3.25", DecimalSeparator::Comma), Some(3.25));
+        assert_eq!(parse_number("-1.234.567,5", DecimalSeparator::Comma), Some(-1234567.5));
+        assert_eq!(parse_number("1.23,4", DecimalSeparator::Comma), None);
+        assert_eq!(parse_number("1234.567,8", DecimalSeparator::Comma), None);
+        assert_eq!(parse_number("1,2,3", DecimalSeparator::Comma), None);
+
+        let run = |separator : DecimalSeparator| -> (Result<(), BirlError>, String) {
+            let io = CaptureIo::new("3,5\n1.234,5\n");
+            let output = io.output();
+
+            let mut ctx = match Context::with_vm(VirtualMachineBuilder::new().decimal_separator(separator)) {
+                Ok(c) => c,
+                Err(e) => panic!("{}", e),
+            };
+
+            let _ = ctx.set_io(Box::new(io));
+
+            let src = "JAULA SHOW\n\
+                       FALA UM NÚMERO: A\n\
+                       FALA UM NÚMERO: B\n\
+                       CE QUER VER ISSO: A * 2\n\
+                       CE QUER VER ISSO: B + 0.25\n\
+                       VEM: C, 1.5\n\
+                       MUDA PRA TEXTO: C\n\
+                       CE QUER VER ISSO: C\n\
+                       É HORA DO: TEXTO DO NÚMERO, 2.5, \".\"\n\
+                       CE QUER VER ISSO: TREZE\n\
+                       É HORA DO: NÚMERO DO TEXTO, \"7,25\", \",\"\n\
+                       CE QUER VER ISSO: TREZE\n\
+                       SAINDO DA JAULA";
+
+            let result = ctx.call_function_by_id(0, vec![])
+                .and_then(|_| ctx.add_standard_library())
+                .and_then(|_| ctx.add_source_string(src.to_owned()))
+                .and_then(|_| ctx.start_program());
+
+            let output = output.borrow().clone();
+
+            (result, output)
+        };
+
+        let (result, output) = run(DecimalSeparator::Comma);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "7\n1234,75\n1,5\n2.5\n7,25\n");
+
+        // With a point, the comma isn't accepted
+        let (result, output) = run(DecimalSeparator::Point);
+
+        assert!(matches!(result, Err(BirlError::Runtime(ref message, _))
+                         if message.starts_with("Não foi possível converter \"3,5\" pra Num")),
+                "{:?}", result);
+        assert_eq!(output, "");
+    }
+
+    #[test]
+    fn chained_comparisions() {
+        use testing::run_program;
+
+        // The division by zero is never evaluated, since the first comparision is already false
+        let src = "JAULA SHOW\n\
+                   VEM: X, 5\n\
+                   CE QUER VER ISSO: 1 < X <= 10\n\
+                   CE QUER VER ISSO: 1 < X < 3\n\
+                   CE QUER VER ISSO: 1 < 2 < 3 < 4 == 4 != 5\n\
+                   CE QUER VER ISSO: (X > 1 > 0) == VERDADE\n\
+                   CE QUER VER ISSO: (3 > 2) > 1\n\
+                   CE QUER VER ISSO: X < 3 < 1 / 0\n\
+                   SAINDO DA JAULA";
+
+        let (result, output) = run_program(src);
+
+        assert_eq!(result, Ok(()));
+
+        assert_eq!(output, "VERDADE\nMENTIRA\nVERDADE\nVERDADE\nMENTIRA\nMENTIRA\n");
+    }
+
+    #[test]
+    fn comparision_operands() {
+        use testing::run_program;
+
+        // The second value uses both math registers, and the first one can't be lost while it's calculated
+        let src = "JAULA SHOW\n\
+                   VEM: X, 5\n\
+                   É ELE QUE A GENTE QUER: 121, 0 + 0\n\
+                   É ELE MEMO:\n\
+                   CE QUER VER ISSO: \"errado\"\n\
+                   FIM\n\
+                   É ELE QUE A GENTE QUER: X * 2, (X - X) * (3 + 1)\n\
+                   É MAIOR:\n\
+                   CE QUER VER ISSO: \"certo\"\n\
+                   FIM\n\
+                   SAINDO DA JAULA";
+
+        let (result, output) = run_program(src);
+
+        assert_eq!(result, Ok(()));
+
+        assert_eq!(output, "certo\n");
+    }
+
+    #[test]
+    fn plugins_by_name() {
+        use context::Context;
+        use parser::TypeKind;
+        use vm::{ DynamicValue, VirtualMachine };
+        use vm_io::CaptureIo;
+
+        fn subtract(mut arguments : Vec<DynamicValue>, _vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
 
-                    let last_index = line.len() - 1;
-                    line.remove(last_index);
+            // The last argument comes first
+            match (arguments.remove(1), arguments.remove(0)) {
+                (DynamicValue::Integer(a), DynamicValue::Integer(b)) => Ok(Some(DynamicValue::Integer(a - b))),
+                _ => Err("Esperado dois inteiros".to_owned()),
+            }
+        }
 
-                    Some(line)
-                } else { None };
+        let io = CaptureIo::new("");
+        let output = io.output();
 
-                let parent_index = match self.get_last_ready_index() {
-                    Some(s) => s,
-                    None => return Err("Nenhuma função em execução".to_owned())
-                };
+        let mut ctx = Context::new();
+        let _ = ctx.set_io(Box::new(io));
 
-                if let Some(line) = line {
-                    let id = match self.add_special_item(parent_index, SpecialItemData::Text(line)) {
-                        Ok(id) => id,
-                        Err(e) => return Err(e)
-                    };
+        let result = ctx.call_function_by_id(0, vec![])
+            .and_then(|_| ctx.add_standard_library())
+            .and_then(|_| ctx.add_plugin("SUBTRAI".to_owned(), vec![TypeKind::Integer, TypeKind::Integer], subtract));
 
-                    self.registers.intermediate = DynamicValue::Text(id);
-                }
-            }
-            Instruction::ConvertToNum => {
-                let val = self.registers.math_b;
+        if let Err(e) = result {
+            panic!("{}", e);
+        }
 
-                let v = match self.conv_to_num(val) {
-                    Ok(v) => v,
-                    Err(e) => return Err(e)
-                };
+        // The name can't be used again, by a plugin or a function
+        match ctx.add_plugin("SUBTRAI".to_owned(), vec![], subtract) {
+            Ok(_) => panic!("Adicionou dois plugins com o mesmo nome"),
+            Err(e) => assert!(e.message().contains("SUBTRAI"), "{}", e),
+        }
 
-                self.registers.math_b = DynamicValue::Number(v);
-            }
-            Instruction::ConvertToInt => {
-                let val = self.registers.math_b;
+        match ctx.add_plugin("SHOW".to_owned(), vec![], subtract) {
+            Ok(_) => panic!("Adicionou um plugin com o nome de uma função"),
+            Err(e) => assert!(e.message().contains("SHOW"), "{}", e),
+        }
 
-                let v = match self.conv_to_int(val) {
-                    Ok(v) => v,
-                    Err(e) => return Err(e)
-                };
+        // Plugins are called like functions, and can be given by name to the ones that call functions
+        let src = "JAULA SHOW\n\
+                   É HORA DO: SUBTRAI, 10, 3\n\
+                   CE QUER VER ISSO: TREZE\n\
+                   FAZ UMA LISTA: L\n\
+                   PÕE ISSO AQUI: L, \"BIRL\"\n\
+                   PÕE ISSO AQUI: L, \"CUMPADE\"\n\
+                   É HORA DO: MAPEIA, L, \"TAMANHO DO TEXTO\"\n\
+                   É HORA DO: JUNTA, TREZE\n\
+                   CE QUER VER ISSO: TREZE\n\
+                   É HORA DO: MAPEIA, L, \"SUBTRAI\"\n\
+                   É HORA DO: JUNTA, TREZE\n\
+                   SAINDO DA JAULA";
+
+        if let Err(e) = ctx.add_source_string(src.to_owned()) {
+            panic!("{}", e);
+        }
 
-                self.registers.math_b = DynamicValue::Integer(v);
-            }
-            Instruction::ConvertToString => {
-                let val = self.registers.math_b;
+        match ctx.start_program() {
+            Ok(_) => panic!("SUBTRAI recebeu um argumento só"),
+            Err(e) => assert!(e.message().contains("A função SUBTRAI recebe 2 argumentos, mas foram passados 1"), "{}", e),
+        }
 
-                let id = if let DynamicValue::Text(id) = val {
-                    id
-                } else {
-                    let v = match self.conv_to_string(val) {
-                        Ok(v) => v,
-                        Err(e) => return Err(e)
-                    };
+        assert_eq!(output.borrow().as_str(), "7\n(Lista) [ 4, 7 ]\n");
+    }
 
-                    let parent_index = match self.get_last_ready_index() {
-                        Some(s) => s,
-                        None => return Err("Nenhuma função em execução".to_owned())
-                    };
+    #[test]
+    fn plugin_argument_ownership() {
+        use context::Context;
+        use parser::TypeKind;
+        use parser::IntegerType;
+        use vm::{ DynamicValue, SpecialItemData, SpecialRef, VirtualMachine, PluginFunction };
+        use vm_io::CaptureIo;
+        use std::cell::RefCell;
+
+        thread_local! {
+            static KEPT : RefCell<Option<SpecialRef>> = const { RefCell::new(None) };
+        }
 
-                    match self.add_special_item(parent_index, SpecialItemData::Text(v)) {
-                        Ok(id) => id,
-                        Err(e) => return Err(e)
-                    }
-                };
+        fn keep(mut args : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+            let kept = vm.retain(args.remove(0))?;
 
-                self.registers.math_b = DynamicValue::Text(id);
-            }
-            Instruction::PushValMathA(val) => {
-                match self.raw_to_dynamic(val) {
-                    Ok(v) => self.registers.math_a = v,
-                    Err(e) => return Err(e)
-                }
-            }
-            Instruction::PushValMathB(val) => {
-                match self.raw_to_dynamic(val) {
-                    Ok(v) => self.registers.math_b = v,
-                    Err(e) => return Err(e)
-                }
-            }
-            Instruction::PushIntermediateToA => {
-                self.registers.math_a = self.registers.intermediate;
-            }
-            Instruction::PushIntermediateToB => {
-                self.registers.math_b = self.registers.intermediate;
-            }
-            Instruction::ReadGlobalVarFrom(addr) => {
-                let val = match self.read_from_id(0, addr) {
-                    Ok(v) => v,
-                    Err(e) => return Err(e)
-                };
+            KEPT.with(|k| *k.borrow_mut() = kept);
 
-                self.registers.intermediate = val;
-            }
-            Instruction::WriteGlobalVarTo(addr) => {
-                let index = 0;
-                let val = self.registers.math_b;
+            Ok(None)
+        }
 
-                match self.write_to(val, index, addr) {
-                    Ok(_) => {}
-                    Err(e) => return Err(e),
-                }
-            }
-            Instruction::ReadVarFrom(addr) => {
-                let index = match self.get_last_ready_index() {
-                    Some(i) => i,
-                    None => return Err("Nenhuma função pronta em execução".to_owned()),
-                };
+        fn read_kept(_ : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+            let id = match KEPT.with(|k| k.borrow().as_ref().map(|r| r.value())) {
+                Some(DynamicValue::Text(id)) => id,
+                _ => return Ok(Some(DynamicValue::Null)),
+            };
 
-                let val = match self.read_from_id(index, addr) {
-                    Ok(v) => v,
-                    Err(e) => return Err(e)
-                };
+            let text = match vm.get_special_storage_ref().get_data_ref(id) {
+                Some(SpecialItemData::Text(t)) => t.clone(),
+                _ => return Err("O texto guardado foi liberado".to_owned()),
+            };
 
-                self.registers.intermediate = val;
-            }
-            Instruction::WriteVarTo(addr) => {
-                let index = match self.get_last_ready_index() {
-                    Some(i) => i,
-                    None => return Err("Nenhuma função pronta em execução".to_owned()),
-                };
+            let id = vm.get_special_storage_mut().add(SpecialItemData::Text(text), 0);
 
-                let val = self.registers.math_b;
+            Ok(Some(DynamicValue::Text(id)))
+        }
 
-                match self.write_to(val, index, addr) {
-                    Ok(_) => {}
-                    Err(e) => return Err(e)
-                }
-            }
-            Instruction::WriteVarToLast(addr) => {
-                let index = self.callstack.len() - 1;
-                let val = self.registers.math_b;
+        fn drop_kept(_ : Vec<DynamicValue>, _ : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+            KEPT.with(|k| *k.borrow_mut() = None);
 
-                match self.write_to(val, index, addr) {
-                    Ok(_) => {}
-                    Err(e) => return Err(e),
-                }
-            }
-            Instruction::Add => {
-                let left = self.registers.math_a;
-                let right = self.registers.math_b;
-                let res = match self.add_values(left, right) {
-                    Ok(v) => v,
-                    Err(e) => return Err(e)
-                };
+            Ok(None)
+        }
 
-                self.registers.math_b = res;
-            }
-            Instruction::Mul => {
-                let left = self.registers.math_a;
-                let right = self.registers.math_b;
-                let res = match self.mul_values(left, right) {
-                    Ok(v) => v,
-                    Err(e) => return Err(e)
-                };
+        fn count_items(_ : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+            Ok(Some(DynamicValue::Integer(vm.get_special_storage_ref().len() as IntegerType)))
+        }
 
-                self.registers.math_b = res;
-            }
-            Instruction::Div => {
-                let left = self.registers.math_a;
-                let right = self.registers.math_b;
-                let res = match self.div_values(left, right) {
-                    Ok(v) => v,
-                    Err(e) => return Err(e)
-                };
+        let io = CaptureIo::new("");
+        let output = io.output();
 
-                self.registers.math_b = res;
-            }
-            Instruction::Sub => {
-                let left = self.registers.math_a;
-                let right = self.registers.math_b;
-                let res = match self.sub_values(left, right) {
-                    Ok(v) => v,
-                    Err(e) => return Err(e)
-                };
+        let mut ctx = Context::new();
+        let _ = ctx.set_io(Box::new(io));
 
-                self.registers.math_b = res;
-            }
-            Instruction::SwapMath => {
-                let tmp = self.registers.math_b;
-                self.registers.math_b = self.registers.math_a;
-                self.registers.math_a = tmp;
-            }
-            Instruction::ClearMath => {
-                self.registers.math_a = DynamicValue::Null;
-                self.registers.math_b = DynamicValue::Null;
-                self.registers.intermediate = DynamicValue::Null;
-            }
-            Instruction::AddLoopLabel => {
-                let next_pc = match self.get_current_pc() {
-                    Some(p) => p,
-                    None => return Err("Nenhuma função em execução".to_owned())
-                };
+        let plugins : Vec<(&str, Vec<TypeKind>, PluginFunction)> = vec![
+            ("GUARDA", vec![TypeKind::Text], keep),
+            ("LE", vec![], read_kept),
+            ("SOLTA", vec![], drop_kept),
+            ("CONTA", vec![], count_items),
+        ];
 
-                match self.get_last_ready_mut() {
-                    Some(f) => f.label_stack.push(LoopLabel::new(next_pc)),
-                    None => return Err("Nenhuma função em execução".to_owned())
-                }
+        for (name, params, func) in plugins {
+            if let Err(e) = ctx.add_plugin(name.to_owned(), params, func) {
+                panic!("{}", e);
             }
-            Instruction::RestoreLoopLabel => {
-                let (mut address, mut step) = (None, DynamicValue::Null);
+        }
 
-                let pc = match self.get_last_ready_ref() {
-                    Some(f) => {
-                        let label = match f.label_stack.last() {
-                            Some(l) => l,
-                            None => return Err("Restore : Nenhuma label disponível".to_owned())
-                        };
+        // The text is kept by the plugin after the variable that had it changes, and freed when the plugin lets it go
+        let src = "JAULA SHOW\n\
+                   VEM: T, \"BIRL\" + \"!\"\n\
+                   É HORA DO: GUARDA, T\n\
+                   BORA: T, 0\n\
+                   É HORA DO: LE\n\
+                   CE QUER VER ISSO: TREZE\n\
+                   É HORA DO: CONTA\n\
+                   É HORA DO: CONTA\n\
+                   CE QUER VER ISSO: TREZE\n\
+                   É HORA DO: SOLTA\n\
+                   É HORA DO: CONTA\n\
+                   CE QUER VER ISSO: TREZE\n\
+                   SAINDO DA JAULA";
+
+        if let Err(e) = ctx.add_source_string(src.to_owned()) {
+            panic!("{}", e);
+        }
 
-                        if let Some(addr) = label.index_address {
-                            address = Some(addr);
-                            step = label.stepping;
-                        }
+        if let Err(e) = ctx.call_function_by_id(0, vec![]) {
+            panic!("{}", e);
+        }
 
-                        label.start_pc
-                    }
-                    None => return Err("Nenhuma função em execução".to_owned())
-                };
+        if let Err(e) = ctx.start_program() {
+            panic!("{}", e);
+        }
 
-                self.set_current_pc(pc)?;
+        let lines = output.borrow().lines().map(|l| l.to_owned()).collect::<Vec<String>>();
 
-                if let Some(address) = address {
-                    let index = match self.get_last_ready_index() {
-                        Some(i) => i,
-                        None => return Err("Nenhuma função pronta em execução".to_owned()),
-                    };
+        assert_eq!(lines[0], "BIRL!");
 
-                    let current = self.read_from_id(index, address)?;
+        let (kept, released) = (lines[1].parse::<usize>().unwrap(), lines[2].parse::<usize>().unwrap());
 
-                    let result = self.add_values(current, step)?;
+        assert_eq!(released, kept - 1);
+    }
 
-                    match self.write_to(result, index, address) {
-                        Ok(_) => {}
-                        Err(e) => return Err(e)
-                    }
-                }
-            }
-            Instruction::PopLoopLabel => {
-                match self.get_last_ready_mut() {
-                    Some(f) => {
-                        match f.label_stack.pop() {
-                            Some(_) => {}
-                            None => return Err("Não havia nenhuma label pra remover".to_owned())
-                        }
-                    }
-                    None => return Err("Nenhuma função em execução".to_owned())
-                }
-            }
-            Instruction::RegisterIncrementOnRestore(address) => {
-                // Since this instruction is right after AddLabel, this is going to be executed each iteration
-                // and since we don't want that, we'll also increment the PC on the label
+    #[test]
+    fn maps() {
+        use context::Context;
+        use testing::run_in_context;
+        use vm_io::CaptureIo;
+
+        let mut ctx = Context::new();
+        let src = "JAULA ANIVERSARIO (PESSOA: MAPA)\n\
+                   PEGA DO MAPA: PESSOA, \"IDADE\", IDADE\n\
+                   BOTA NO MAPA: PESSOA, \"IDADE\", IDADE + 1\n\
+                   SAINDO DA JAULA\n\
+                   JAULA SHOW\n\
+                   VEM: PESSOA, { \"NOME\" : \"BAMBAM\", \"IDADE\" : 40 + 2, \"TREINO\" : { \"SEGUNDA\" : \"PEITO\" } }\n\
+                   CE QUER VER ISSO: PESSOA\n\
+                   É HORA DO: ANIVERSARIO, PESSOA\n\
+                   BOTA NO MAPA: PESSOA, \"CIDADE\", \"SP\"\n\
+                   TIRA DO MAPA: PESSOA, \"TREINO\"\n\
+                   PEGA DO MAPA: PESSOA, \"IDADE\", IDADE\n\
+                   PEGA DO MAPA: PESSOA, \"PESO\", PESO\n\
+                   FALA O TAMANHO DO MAPA: PESSOA, TAMANHO\n\
+                   FALA AS CHAVES: PESSOA, CHAVES\n\
+                   CE QUER VER ISSO: IDADE, \" \", PESO, \" \", TAMANHO, \" \", CHAVES\n\
+                   CE QUER VER ISSO: {} == {}, \" \", { \"A\" : 1 } == { \"A\" : 2 }, \" \", !{}\n\
+                   SAINDO DA JAULA";
+
+        let (result, output) = run_in_context(&mut ctx, src);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "(Mapa) { \"IDADE\" : 42, \"NOME\" : \"BAMBAM\", \"TREINO\" : { \"SEGUNDA\" : \"PEITO\" } }\n\
+                                               43 <Null> 3 (Lista) [ \"CIDADE\", \"IDADE\", \"NOME\" ]\n\
+                                               VERDADE MENTIRA VERDADE\n");
+
+        // The references that the maps had to what was removed or replaced were given back, so nothing is held anymore
+        // (the temporary values are only freed by the garbage collector)
+        let leaks = ctx.find_leaks();
+
+        assert!(leaks.iter().all(|item| item.ref_count == 0), "{:?}", leaks);
+
+        let mut ctx = Context::new();
+        let _ = ctx.set_io(Box::new(CaptureIo::new("")));
+
+        let result = ctx.add_source_string("JAULA SHOW\nVEM: M, { 1 : 2 }\nSAINDO DA JAULA".to_owned())
+            .and_then(|_| ctx.call_function_by_id(0, vec![]))
+            .and_then(|_| ctx.start_program());
+
+        match result {
+            Ok(_) => panic!("Usou um inteiro como chave"),
+            Err(e) => assert!(e.message().contains("A chave de um mapa tem que ser um texto"), "{}", e),
+        }
+    }
 
-                let stepping = self.registers.math_b;
+    // The leak is on purpose, and check_ref_counts would panic on it
+    #[test]
+    #[cfg(not(feature = "refcount-debug"))]
+    fn heap_dump_and_leaks() {
+        use context::Context;
+        use vm::{ DynamicValue, SpecialItemData, VirtualMachine };
+        use vm_io::CaptureIo;
 
-                match self.get_last_ready_mut() {
-                    Some(s) => match s.label_stack.last_mut() {
-                        Some(l) => {
-                            l.stepping = stepping;
-                            l.index_address = Some(address);
-                            // As explained above
-                            l.start_pc += 1;
-                        }
-                        None => return Err("Função atual não tem nenhuma label".to_owned()),
-                    }
-                    None => return Err("Nenhuma função em execução".to_owned())
-                };
-            }
-            Instruction::SetFirstExpressionOperation => {
-                self.registers.first_operation = true;
-            }
-            Instruction::MakeNewList => {
-                let index = match self.get_last_ready_index() {
-                    Some(i) => i,
-                    None => return Err("Nenhuma função em execução".to_owned())
-                };
+        // Allocates a text that nothing references, the kind of bug the leak detection is for
+        fn leak(_ : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+            vm.get_special_storage_mut().add(SpecialItemData::Text("VAZOU".to_owned()), 1);
+
+            Ok(None)
+        }
+
+        let mut ctx = Context::new();
+        let _ = ctx.set_io(Box::new(CaptureIo::new("")));
+
+        if let Err(e) = ctx.add_plugin("VAZA".to_owned(), vec![], leak) {
+            panic!("{}", e);
+        }
+
+        let src = "VEM: GLOBAL, \"BIRL\"\n\
+                   FAZ UMA LISTA: NOMES\n\
+                   PÕE ISSO AQUI: NOMES, \"ELEMENTO\"\n\
+                   JAULA SHOW\n\
+                   É HORA DO: VAZA\n\
+                   SAINDO DA JAULA";
+
+        if let Err(e) = ctx.add_source_string(src.to_owned()) {
+            panic!("{}", e);
+        }
+
+        if let Err(e) = ctx.call_function_by_id(0, vec![]) {
+            panic!("{}", e);
+        }
+
+        if let Err(e) = ctx.start_program() {
+            panic!("{}", e);
+        }
+
+        let dump = ctx.dump_heap();
+        let leaks = ctx.find_leaks();
+
+        assert!(dump.contains("Texto (5 caracteres), 1 referência(s), sem dono"), "{}", dump);
+        assert!(dump.lines().any(|l| l.contains("Texto (8 caracteres)") && l.contains("lista #")), "{}", dump);
 
-                let data = match self.add_special_item(index, SpecialItemData::List(vec![])) {
-                    Ok(d) => d,
-                    Err(e) => return Err(e)
-                };
+        // Globals, and what's in lists that are in globals, are still in use
+        assert!(leaks.iter().any(|item| item.kind == "Texto" && item.size == 5 && item.owners.is_empty()));
+        assert!(! leaks.iter().any(|item| item.size == 4 || item.size == 8 || item.kind == "Lista"));
+    }
 
-                self.registers.math_b = DynamicValue::List(data);
+    #[test]
+    #[cfg(feature = "refcount-debug")]
+    #[should_panic(expected = "Contagem de referências errada")]
+    fn refcount_debug_check() {
+        use context::Context;
+        use vm::{ DynamicValue, VirtualMachine };
+        use parser::TypeKind;
+
+        // Counts a reference that nothing holds
+        fn extra_ref(arguments : Vec<DynamicValue>, vm : &mut VirtualMachine) -> Result<Option<DynamicValue>, String> {
+            if let DynamicValue::Text(id) = arguments[0] {
+                vm.get_special_storage_mut().increment_ref(id)?;
             }
-            Instruction::IndexList => {
-                let index = if let DynamicValue::Integer(i) = self.registers.math_b {
-                    i
-                } else {
-                    return Err(format!("Esperado um índice na forma de um inteiro, encontrado {:?}", self.registers.math_b))
-                };
 
-                let value = {
-                    if let DynamicValue::List(id) = self.registers.intermediate {
-                        match self.special_storage.get_data_ref(id) {
-                            Some(SpecialItemData::List(ref d)) => {
-                                if index as usize >= d.len() {
-                                    return Err(format!("Erro : Index depois do final da lista. Tamanho da lista : {}", d.len()));
-                                }
+            Ok(None)
+        }
 
-                                *d[index as usize]
-                            }
-                            Some(_) => return Err(format!("Erro interno : DynamicValue é uma lista, mas o item na memória não")),
-                            None => return Err("Erro interno : ID inválida".to_owned())
-                        }
-                    } else {
-                        return Err(format!("Variável passada não é uma lista"));
-                    }
-                };
+        let mut ctx = Context::new();
 
-                self.registers.math_b = value;
-            }
-            Instruction::AddToListAtIndex => {
-                let index = if let DynamicValue::Integer(val) = self.registers.secondary {
-                    Some(val)
-                } else {
-                    None
-                };
+        if let Err(e) = ctx.add_plugin("REFERENCIA".to_owned(), vec![TypeKind::Text], extra_ref) {
+            panic!("{}", e);
+        }
 
-                let value = self.registers.math_b;
+        let src = "VEM: GLOBAL, \"BIRL\"\n\
+                   JAULA DOBRO (X : BATATA DOCE)\n\
+                   BIRL: X * 2\n\
+                   SAINDO DA JAULA\n\
+                   JAULA ERRADA\n\
+                   É HORA DO: REFERENCIA, GLOBAL\n\
+                   SAINDO DA JAULA\n\
+                   JAULA SHOW\n\
+                   É HORA DO: DOBRO, 2\n\
+                   É HORA DO: ERRADA\n\
+                   SAINDO DA JAULA";
+
+        if let Err(e) = ctx.add_source_string(src.to_owned()) {
+            panic!("{}", e);
+        }
 
-                let list_id = if let DynamicValue::List(id) = self.registers.intermediate {
-                    id
-                } else {
-                    return Err(format!("AddListToIndex : A variável não é uma lista"));
-                };
+        if let Err(e) = ctx.call_function_by_id(0, vec![]) {
+            panic!("{}", e);
+        }
 
-                let list = match self.special_storage.get_data_mut(list_id) {
-                    Some(l) => match l {
-                        SpecialItemData::List(ref mut list) => list,
-                        _ => return Err("Item especial com a ID passada não é uma lista".to_owned())
-                    }
-                    None => return Err("ID da lista não encontrada".to_owned())
-                };
+        // DOBRO returns with the counts right, ERRADA doesn't
+        let _ = ctx.start_program();
+    }
 
-                if let Some(i) = index {
-                    if i as usize >= list.len() {
-                        list.push(Box::new(value));
-                    } else {
-                        list.insert(i as usize, Box::new(value));
-                    }
-                } else {
-                    list.push(Box::new(value));
-                }
+    #[test]
+    fn garbage_collection() {
+        use context::Context;
+        use vm_io::CaptureIo;
+
+        let run = |src : &str, threshold : Option<usize>| {
+            let io = CaptureIo::new("");
+            let output = io.output();
+
+            let mut ctx = Context::new();
+            let _ = ctx.set_io(Box::new(io));
+            ctx.set_gc_threshold(threshold);
+
+            if let Err(e) = ctx.add_source_string(src.to_owned()) {
+                panic!("{}", e);
             }
-            Instruction::ClearSecondary => {
-                self.registers.secondary = DynamicValue::Null;
+
+            if let Err(e) = ctx.call_function_by_id(0, vec![]) {
+                panic!("{}", e);
             }
-            Instruction::PushMathBToSeconday => {
-                let val = self.registers.math_b;
-                self.registers.secondary = val;
+
+            if let Err(e) = ctx.start_program() {
+                panic!("{}", e);
             }
-            Instruction::RemoveFromListAtIndex => {
-                let index = if let DynamicValue::Integer(i) = self.registers.math_b {
-                    i
-                } else {
-                    return Err(format!("Esperado um inteiro como índice pra lista, encontrado {:?}", self.registers.math_b));
-                };
 
-                let id = if let DynamicValue::List(id) = self.registers.intermediate {
-                    id
-                } else {
-                    return Err("A variável não é uma lista".to_owned());
-                };
+            let output = output.borrow().clone();
 
-                match self.special_storage.get_data_mut(id) {
-                    Some(SpecialItemData::List(ref mut list)) => {
-                        if index as usize >= list.len() {
-                            return Err(format!("Erro : Index maior que a lista. Tamanho da lista : {}", list.len()));
-                        }
+            (ctx, output)
+        };
 
-                        list.remove(index as usize);
-                    }
-                    Some(_) => return Err("Erro interno : DynamicValue é uma lista mas o valor na memória não".to_owned()),
-                    None => return Err("Erro interno : ID não encontrada".to_owned())
-                }
-            }
-            Instruction::QueryListSize => {
-                let id = if let DynamicValue::List(id) = self.registers.intermediate {
-                    id
-                } else {
-                    return Err("QueryListSize : Variável não é uma lista".to_owned());
-                };
+        // Two lists that contain each other are never freed by the ref counts alone
+        let src = "JAULA CICLO\n\
+                   FAZ UMA LISTA: A\n\
+                   FAZ UMA LISTA: B\n\
+                   PÕE ISSO AQUI: A, B\n\
+                   PÕE ISSO AQUI: B, A\n\
+                   PÕE ISSO AQUI: B, \"BIRL\"\n\
+                   SAINDO DA JAULA\n\
+                   JAULA SHOW\n\
+                   É HORA DO: CICLO\n\
+                   FAZ UMA LISTA: C\n\
+                   PÕE ISSO AQUI: C, 1\n\
+                   SAINDO DA JAULA";
+
+        let (mut ctx, _) = run(src, None);
+
+        assert!(ctx.dump_heap().starts_with("Itens vivos : 3\n"), "{}", ctx.dump_heap());
+        assert_eq!(ctx.collect_garbage(), Ok(3));
+        assert!(ctx.dump_heap().starts_with("Itens vivos : 0\n"), "{}", ctx.dump_heap());
+
+        // Without a collection, there'd be one list alive for each time the loop ran
+        let src = "JAULA SHOW\n\
+                   PRA CADA: I, 100\n\
+                   FAZ UMA LISTA: L\n\
+                   PÕE ISSO AQUI: L, L\n\
+                   FIM\n\
+                   SAINDO DA JAULA";
+
+        let (ctx, _) = run(src, Some(10));
+        let dump = ctx.dump_heap();
+
+        assert!(dump.lines().count() <= 21, "{}", dump);
+
+        // A list keeps its elements alive, even when the variable they came from changes
+        let src = "JAULA SHOW\n\
+                   FAZ UMA LISTA: L\n\
+                   VEM: NOME, \"BAMBAM\"\n\
+                   PÕE ISSO AQUI: L, NOME\n\
+                   BORA: NOME, \"BIRL\"\n\
+                   CE QUER VER ISSO: L\n\
+                   TIRA ESSE: L, 0\n\
+                   CE QUER VER ISSO: NOME\n\
+                   SAINDO DA JAULA";
+
+        let (ctx, output) = run(src, None);
+
+        assert!(output.contains("[ \"BAMBAM\" ]"), "{}", output);
+        assert!(output.contains("BIRL"), "{}", output);
+        assert!(ctx.dump_heap().starts_with("Itens vivos : 0\n"), "{}", ctx.dump_heap());
+    }
 
-                let list = match self.special_storage.get_data_ref(id) {
-                    Some(l) => match l {
-                        SpecialItemData::List(l) => l,
-                        _ => return Err("Erro interno : ID não aponta pra uma lista".to_owned())
-                    }
-                    None => return Err("Não encontrado item com a ID passada".to_owned())
-                };
+    #[test]
+    fn multiple_return_values() {
+        use context::Context;
+        use error::BirlError;
+        use testing::run_in_context;
 
-                let val = DynamicValue::Integer(list.len() as IntegerType);
+        let run = |src : &str| {
+            let mut ctx = Context::new();
+            let (result, output) = run_in_context(&mut ctx, src);
 
-                self.registers.math_b = val;
-            }
-            Instruction::CallPlugin(address, num) => {
-                if address > self.plugins.len() {
-                    return Err("CallPlugin : Endereço inválido".to_owned());
-                }
+            // Texts that were only printed stay without references, so only the ones still referenced count
+            let leaks = ctx.find_leaks().iter().filter(|item| item.ref_count > 0).count();
 
-                let plugin = self.plugins[address];
+            (result, output, leaks)
+        };
 
-                if num > self.plugin_argument_stack.len() {
-                    return Err(format!("CallPlugin : Número de argumentos maior que a quantidade de argumentos disponíveis"));
-                }
+        // The first value goes to TREZE too, and the texts made in the function outlive it
+        let (result, output, leaks) = run("JAULA DIVIDE(A : BATATA DOCE, B : BATATA DOCE)\n\
+                                           VEM: RESTO, A % B\n\
+                                           MUDA PRA TEXTO: RESTO\n\
+                                           BIRL: A / B, A % B, \"RESTO \" + RESTO\n\
+                                           SAINDO DA JAULA\n\
+                                           JAULA SHOW\n\
+                                           PEGA DA JAULA: Q, R, T : DIVIDE, 7, 2\n\
+                                           CE QUER VER ISSO: Q, \" \", R, \" \", T, \" \", TREZE\n\
+                                           PEGA DA JAULA: S : TIRA ACENTOS, \"AÇÃO\"\n\
+                                           CE QUER VER ISSO: S\n\
+                                           PEGA DA JAULA: Q : DIVIDE, 9, 3\n\
+                                           CE QUER VER ISSO: Q\n\
+                                           SAINDO DA JAULA");
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "3 1 RESTO 1 3\nACAO\n3\n");
+        assert_eq!(leaks, 0);
+
+        let (result, _, _) = run("JAULA UM\nBIRL: 1\nSAINDO DA JAULA\n\
+                                  JAULA SHOW\nPEGA DA JAULA: A, B : UM\nSAINDO DA JAULA");
+
+        assert!(matches!(result, Err(BirlError::Runtime(ref message, _))
+                         if message.contains("A função retornou 1 valor(es), mas o valor 2 foi pedido")),
+                "{:?}", result);
+    }
 
-                let mut args = Vec::with_capacity(num);
+    #[test]
+    fn sort_list() {
+        use error::BirlError;
+        use testing::run_program;
+
+        // Integers and numbers together, then backwards with a function
+        let (result, output) = run_program("JAULA DECRESCENTE(A : TRAPEZIO DESCENDENTE, B : TRAPEZIO DESCENDENTE)\n\
+                                            BIRL: B - A\n\
+                                            SAINDO DA JAULA\n\
+                                            JAULA SHOW\n\
+                                            FAZ UMA LISTA: L\n\
+                                            PÕE ISSO AQUI: L, 3\n\
+                                            PÕE ISSO AQUI: L, 1.5\n\
+                                            PÕE ISSO AQUI: L, -2\n\
+                                            PÕE ISSO AQUI: L, 1\n\
+                                            ORDENA: L\n\
+                                            CE QUER VER ISSO: L\n\
+                                            ORDENA: L, \"DECRESCENTE\"\n\
+                                            CE QUER VER ISSO: L\n\
+                                            SAINDO DA JAULA");
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "(Lista) [ -2, 1, 1.5, 3 ]\n(Lista) [ 3, 1.5, 1, -2 ]\n");
+
+        // Texts, and a function that keeps the ones of the same size in the order they were
+        let (result, output) = run_program("JAULA TAMANHO(A : FIBRA, B : FIBRA)\n\
+                                            É HORA DO: TAMANHO DO TEXTO, A\n\
+                                            VEM: TA, TREZE\n\
+                                            É HORA DO: TAMANHO DO TEXTO, B\n\
+                                            BIRL: TA - TREZE\n\
+                                            SAINDO DA JAULA\n\
+                                            JAULA SHOW\n\
+                                            FAZ UMA LISTA: L\n\
+                                            PÕE ISSO AQUI: L, \"FRANGO\"\n\
+                                            PÕE ISSO AQUI: L, \"BIRL\"\n\
+                                            PÕE ISSO AQUI: L, \"AGUA\"\n\
+                                            ORDENA: L\n\
+                                            CE QUER VER ISSO: L\n\
+                                            PÕE ISSO AQUI: L, \"OVO\"\n\
+                                            ORDENA: L, \"TAMANHO\"\n\
+                                            CE QUER VER ISSO: L\n\
+                                            SAINDO DA JAULA");
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "(Lista) [ \"AGUA\", \"BIRL\", \"FRANGO\" ]\n(Lista) [ \"OVO\", \"AGUA\", \"BIRL\", \"FRANGO\" ]\n");
+
+        let (result, _) = run_program("FAZ UMA LISTA: L\nPÕE ISSO AQUI: L, 1\nPÕE ISSO AQUI: L, \"1\"\nORDENA: L");
+        assert!(matches!(result, Err(BirlError::Runtime(ref message, _))
+                         if message.contains("ORDENA só ordena números com números e textos com textos")),
+                "{:?}", result);
+
+        let (result, _) = run_program("JAULA NADA(A : BATATA DOCE, B : BATATA DOCE)\nBIRL: \"A\"\nSAINDO DA JAULA\n\
+                                       FAZ UMA LISTA: L\nPÕE ISSO AQUI: L, 1\nPÕE ISSO AQUI: L, 2\nORDENA: L, \"NADA\"");
+        assert!(matches!(result, Err(BirlError::Runtime(ref message, _))
+                         if message.contains("A função NADA tem que retornar um número")),
+                "{:?}", result);
+
+        let (result, _) = run_program("VEM: L, 1\nORDENA: L");
+        assert!(matches!(result, Err(BirlError::Runtime(ref message, _))
+                         if message.contains("ORDENA : A variável não é uma lista")),
+                "{:?}", result);
+    }
 
-                for _ in 0..num {
-                    let val = match self.plugin_argument_stack.pop() {
-                        Some(v) => v,
-                        None => unreachable!()
-                    };
+    #[test]
+    fn call_depth_limit() {
+        use context::Context;
+        use vm::VirtualMachineBuilder;
+
+        let src = "JAULA DESCE(N : BATATA DOCE)\n\
+                   E ELE QUE A GENTE QUER: N, 0\n\
+                   É MAIOR:\n\
+                   É HORA DO: DESCE, N - 1\n\
+                   FIM\n\
+                   SAINDO DA JAULA\n\
+                   JAULA SHOW\n\
+                   É HORA DO: DESCE, PROFUNDIDADE\n\
+                   SAINDO DA JAULA";
+
+        // The global code, SHOW and DESCE from PROFUNDIDADE to 0
+        let run = |depth : usize, limit : Option<usize>| {
+            let mut ctx = Context::with_vm(VirtualMachineBuilder::new().max_call_depth(limit)).unwrap();
+
+            ctx.call_function_by_id(0, vec![])
+                .and_then(|_| ctx.add_source_string(src.replace("PROFUNDIDADE", &depth.to_string())))
+                .and_then(|_| ctx.start_program())
+        };
 
-                    args.push(val);
-                }
+        assert_eq!(run(47, Some(50)), Ok(()));
 
-                let result = plugin(args, self)?;
+        let error = run(48, Some(50)).unwrap_err();
+        assert!(error.message().contains("A stack de chamadas estourou : mais de 50 funções"), "{}", error);
 
-                if let Some(value) = result {
-                    let index = self.callstack.len() - 1;
-                    self.write_to(value, index, 0)?;
+        // The trace only shows the first few of the repeated calls
+        let trace = error.location().format_trace();
+        assert_eq!(trace.lines().filter(|l| l.contains("na JAULA DESCE")).count(), 3, "{}", trace);
+        assert!(trace.ends_with("[a mesma chamada se repete mais 45 vez(es)]"), "{}", trace);
 
-                    if self.registers.is_interactive && self.callstack.len() == 1 {
-                        let tmp = self.registers.math_b;
+        assert_eq!(run(20_000, None), Ok(()));
+    }
 
-                        self.registers.math_b = value;
+    #[test]
+    fn tail_calls() {
+        use context::Context;
+        use error::BirlError;
+        use testing::run_in_context;
+        use vm::{ VirtualMachineBuilder, Instruction };
+
+        // CONTA returns the result of calling itself, even from inside a loop, so it never has more than one frame.
+        // SOMA does something with the result, so it can't
+        let src = "JAULA CONTA(N : BATATA DOCE, TEXTO : FIBRA)\n\
+                   É ELE QUE A GENTE QUER: N, 0\n\
+                   É ELE MEMO:\n\
+                   BIRL: TEXTO\n\
+                   FIM\n\
+                   PRA CADA: LETRA, \"ab\"\n\
+                   É HORA DO: CONTA, N - 1, TEXTO + LETRA\n\
+                   BIRL: TREZE\n\
+                   FIM\n\
+                   SAINDO DA JAULA\n\
+                   JAULA SOMA(N : BATATA DOCE)\n\
+                   É ELE QUE A GENTE QUER: N, 0\n\
+                   É ELE MEMO:\n\
+                   BIRL: 0\n\
+                   FIM\n\
+                   É HORA DO: SOMA, N - 1\n\
+                   BIRL: TREZE + N\n\
+                   SAINDO DA JAULA\n\
+                   JAULA SHOW\n\
+                   É HORA DO: CONTA, 100, \"\"\n\
+                   CE QUER VER ISSO: TREZE\n\
+                   É HORA DO: SOMA, 10\n\
+                   CE QUER VER ISSO: TREZE\n\
+                   É HORA DO: SOMA, 1000\n\
+                   SAINDO DA JAULA";
+
+        let mut ctx = Context::with_vm(VirtualMachineBuilder::new().max_call_depth(Some(50))).unwrap();
+        let (result, output) = run_in_context(&mut ctx, src);
+
+        assert_eq!(output, format!("{}\n55\n", "a".repeat(100)));
+        assert!(matches!(result, Err(BirlError::Runtime(ref message, _))
+                         if message.contains("A stack de chamadas estourou")),
+                "{:?}", result);
+        assert!(ctx.find_leaks().is_empty(), "{:?}", ctx.find_leaks());
+
+        let calls = ctx.compiled_functions().into_iter()
+            .map(|(_, name, code)| (name, code.iter().filter(|i| matches!(i, Instruction::TailCall(_))).count()))
+            .collect::<Vec<(String, usize)>>();
+
+        assert!(calls.contains(&("CONTA".to_owned(), 1)) && calls.contains(&("SOMA".to_owned(), 0)), "{:?}", calls);
+    }
 
-                        self.run(Instruction::PrintMathBDebug)?;
+    #[test]
+    fn reserved_globals() {
+        use context::{ Context, RawValue };
+        use vm_io::CaptureIo;
 
-                        self.registers.math_b = tmp;
-                    }
-                }
-            }
-            Instruction::PushMathBPluginArgument => {
-                let val = self.registers.math_b;
-                self.plugin_argument_stack.push(val);
-            }
-            Instruction::IncreaseSkippingLevel => {
-                self.increase_skip_level()?;
-            }
-            Instruction::Halt => {
-                return Ok(ExecutionStatus::Halt);
-            }
-            Instruction::TryDecrementRefAt(address) => {
-                let index = match self.get_last_ready_index() {
-                    Some(i) => i,
-                    None => return Err("".to_owned()),
-                };
+        let io = CaptureIo::new("");
+        let output = io.output();
 
-                match self.read_from_id(index, address) {
-                    Ok(v) => match v {
-                        DynamicValue::List(id) => self.special_storage.decrement_ref(id)?,
-                        DynamicValue::Text(id) => self.special_storage.decrement_ref(id)?,
-                        _ => {}
-                    }
-                    Err(e) => return Err(e),
+        let mut ctx = Context::new();
+        let _ = ctx.set_io(Box::new(io));
+
+        ctx.call_function_by_id(0, vec![]).unwrap();
+        ctx.reserve_global("ENTRADA".to_owned(), RawValue::Null).unwrap();
+        ctx.reserve_global("SAIDA".to_owned(), RawValue::Text(String::new())).unwrap();
+
+        assert!(ctx.reserve_global("SAIDA".to_owned(), RawValue::Null).is_err());
+
+        ctx.add_source_string("VEM: VEZES, 0\n\
+                               VEM: SAUDACAO, \"OLÁ, \"\n\
+                               JAULA SHOW\n\
+                               BORA: VEZES, VEZES + 1\n\
+                               BORA: SAIDA, SAUDACAO + ENTRADA\n\
+                               CE QUER VER ISSO: VEZES\n\
+                               SAINDO DA JAULA".to_owned()).unwrap();
+
+        // The same VM answers more than once, with the globals changed by the host in between
+        for (name, answer) in [("BAMBAM", "OLÁ, BAMBAM"), ("JORGE", "OLÁ, JORGE")] {
+            ctx.set_global("ENTRADA", RawValue::Text(name.to_owned())).unwrap();
+            ctx.start_program().unwrap();
+
+            let value = ctx.global_value("SAIDA").unwrap();
+            assert_eq!(ctx.value_to_string(value), Ok(answer.to_owned()));
+        }
+
+        assert_eq!(*output.borrow(), "1\n2\n");
+        assert!(ctx.find_leaks().is_empty(), "{:?}", ctx.find_leaks());
+
+        assert!(ctx.set_global("NADA", RawValue::Null).is_err());
+        assert!(ctx.global_value("NADA").is_err());
+
+        // Reserved globals can be hidden in functions, but not declared again
+        assert!(ctx.add_source_string("JAULA OUTRA\nVEM: ENTRADA, 1\nSAINDO DA JAULA".to_owned()).is_ok());
+        assert!(ctx.add_source_string("VEM: ENTRADA, 1".to_owned()).unwrap_err().message().contains("reservada"));
+    }
+
+    #[test]
+    fn while_condition() {
+        use context::Context;
+        use testing::run_program;
+
+        // The condition is checked before each iteration (VAI PRO PRÓXIMO included), so a false one never runs the
+        // block
+        let src = "JAULA SHOW\n\
+                   VEM: X, 0\n\
+                   ENQUANTO FOR: X < 10 && X != 7\n\
+                   BORA: X, X + 1\n\
+                   É ELE QUE A GENTE QUER: X % 2 == 0\n\
+                   É ELE MEMO:\n\
+                   VAI PRO PRÓXIMO\n\
+                   FIM\n\
+                   CE QUER VER ISSO: X\n\
+                   FIM\n\
+                   ENQUANTO FOR: X > 100\n\
+                   CE QUER VER ISSO: \"NUNCA\"\n\
+                   FIM\n\
+                   ENQUANTO FOR: X > 3\n\
+                   BORA: X, X - 1\n\
+                   FIM\n\
+                   CE QUER VER ISSO: X\n\
+                   SAINDO DA JAULA";
+
+        let (result, output) = run_program(src);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "1\n3\n5\n7\n3\n");
+
+        // The condition is a single value
+        let mut ctx = Context::new();
+        ctx.call_function_by_id(0, vec![]).unwrap();
+
+        assert!(ctx.add_source_string("JAULA SHOW\nENQUANTO FOR: 1, 2\nFIM\nSAINDO DA JAULA".to_owned()).is_err());
+    }
+
+    #[test]
+    fn run_function_many_times() {
+        use context::{ Context, RawValue };
+        use vm::ExecutionStatus;
+        use vm_io::CaptureIo;
+
+        let src = "VEM: TOTAL, 0\n\
+                   JAULA SOMA(LINHA : FIBRA, VEZES : BATATA DOCE)\n\
+                   MUDA PRA INTEIRO: LINHA\n\
+                   É ELE QUE A GENTE QUER: LINHA, 0\n\
+                   É ELE MEMO:\n\
+                   NUM VAI DÁ NÃO\n\
+                   FIM\n\
+                   BORA: TOTAL, TOTAL + LINHA * VEZES\n\
+                   CE QUER VER ISSO: TOTAL\n\
+                   SAINDO DA JAULA";
+
+        let io = CaptureIo::new("");
+        let output = io.output();
+
+        let mut ctx = Context::new();
+        let _ = ctx.set_io(Box::new(io));
+
+        ctx.call_function_by_id(0, vec![]).unwrap();
+        ctx.add_source_string(src.to_owned()).unwrap();
+
+        let id = ctx.symbol_table().function("SOMA").unwrap().id;
+
+        // The arguments go to the parameters, in order, and the globals stay from one call to the next
+        for line in &["1", "2", "3"] {
+            assert_eq!(ctx.run_function(id, vec![RawValue::Text(line.to_string()), RawValue::Integer(10)]),
+                       Ok(ExecutionStatus::Halt));
+        }
+
+        assert_eq!(ctx.run_function(id, vec![RawValue::Text("0".to_owned()), RawValue::Integer(1)]),
+                   Ok(ExecutionStatus::Quit));
+        assert!(ctx.run_function(id, vec![RawValue::Integer(1), RawValue::Integer(1)]).is_err());
+        assert!(ctx.run_function(id, vec![RawValue::Text("x".to_owned()), RawValue::Integer(1)]).is_err());
+
+        // What quit or failed doesn't go on in the next call
+        assert_eq!(ctx.run_function(id, vec![RawValue::Text("4".to_owned()), RawValue::Integer(1)]),
+                   Ok(ExecutionStatus::Halt));
+
+        assert_eq!(*output.borrow(), "10\n30\n60\n64\n");
+        assert!(ctx.find_leaks().is_empty(), "{:?}", ctx.find_leaks());
+    }
+
+    #[test]
+    fn break_loop() {
+        use context::Context;
+        use testing::run_in_context;
+
+        // SAI DO LOOP leaves only the innermost loop, even from inside conditionals, and PARA AQUI only the conditional
+        let src = "JAULA SHOW\n\
+                   PRA CADA: LETRA, \"BIRLL\"\n\
+                   REPETE: I, 0, 10\n\
+                   É ELE QUE A GENTE QUER: I, 2\n\
+                   É ELE MEMO:\n\
+                   É ELE QUE A GENTE QUER: LETRA, \"R\"\n\
+                   NUM É ELE:\n\
+                   SAI DO LOOP\n\
+                   FIM\n\
+                   PARA AQUI\n\
+                   FIM\n\
+                   CE QUER VER: LETRA, I, \" \"\n\
+                   FIM\n\
+                   É ELE QUE A GENTE QUER: LETRA, \"R\"\n\
+                   É ELE MEMO:\n\
+                   SAI DO LOOP\n\
+                   FIM\n\
+                   FIM\n\
+                   VEM: X, 0\n\
+                   ENQUANTO FOR: VERDADE\n\
+                   BORA: X, X + 1\n\
+                   É ELE QUE A GENTE QUER: X, 3\n\
+                   MAIOR OU É MEMO:\n\
+                   SAI DO LOOP\n\
+                   FIM\n\
+                   FIM\n\
+                   CE QUER VER ISSO: X\n\
+                   SAINDO DA JAULA";
+
+        let mut ctx = Context::new();
+        let (result, output) = run_in_context(&mut ctx, src);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "B0 B1 I0 I1 R0 R1 R2 R3 R4 R5 R6 R7 R8 R9 3\n");
+
+        // The iterators of the loops that were left gave back the text (the temporary values are left to the collector)
+        assert!(ctx.find_leaks().iter().all(|item| item.ref_count == 0), "{:?}", ctx.find_leaks());
+
+        // Outside of a loop, there's nothing to leave or go on with
+        let mut ctx = Context::new();
+        ctx.call_function_by_id(0, vec![]).unwrap();
+
+        let error = ctx.add_source_string("JAULA SHOW\nÉ ELE QUE A GENTE QUER: 1\nÉ ELE MEMO:\nSAI DO LOOP\nFIM\nSAINDO DA JAULA".to_owned());
+        assert!(error.unwrap_err().message().contains("fora de um loop"));
+        assert!(ctx.add_source_string("JAULA OUTRA\nVAI PRO PRÓXIMO\nSAINDO DA JAULA".to_owned()).is_err());
+    }
+
+    #[test]
+    fn switch_case() {
+        use context::Context;
+        use testing::run_in_context;
+
+        // Only the first case that matches runs, and PARA AQUI leaves the whole switch
+        let src = "JAULA SHOW\n\
+                   REPETE: I, 0, 7\n\
+                   ESCOLHE: I\n\
+                   CASO: 0\n\
+                   CE QUER VER: \"Z\"\n\
+                   CASO: 1, 2\n\
+                   VEM: T, \"P\"\n\
+                   CE QUER VER: T\n\
+                   CASO: 3\n\
+                   É ELE QUE A GENTE QUER: I, 3\n\
+                   É ELE MEMO:\n\
+                   CE QUER VER: \"T\"\n\
+                   PARA AQUI\n\
+                   FIM\n\
+                   CE QUER VER: \"!\"\n\
+                   PARA AQUI\n\
+                   CASO: 5\n\
+                   SAI DO LOOP\n\
+                   QUALQUER OUTRO\n\
+                   CE QUER VER: \"D\"\n\
+                   FIM\n\
+                   CE QUER VER: I, \" \"\n\
+                   FIM\n\
+                   É ELE QUE A GENTE QUER: 1, 2\n\
+                   É ELE MEMO:\n\
+                   ESCOLHE: 1\n\
+                   CASO: 1\n\
+                   CE QUER VER: \"X\"\n\
+                   QUALQUER OUTRO\n\
+                   CE QUER VER: \"Y\"\n\
+                   FIM\n\
+                   FIM\n\
+                   ESCOLHE: \"BIRL\"\n\
+                   CASO: \"BORA\"\n\
+                   CE QUER VER ISSO: \"NÃO\"\n\
+                   CASO: \"BIRL\"\n\
+                   CE QUER VER ISSO: \"SIM\"\n\
+                   FIM\n\
+                   SAINDO DA JAULA";
+
+        let mut ctx = Context::new();
+        let (result, output) = run_in_context(&mut ctx, src);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "Z0 P1 P2 T!3 D4 SIM\n");
+        assert!(ctx.find_leaks().iter().all(|item| item.ref_count == 0), "{:?}", ctx.find_leaks());
+
+        let errors = [
+            ("ESCOLHE: 1\nCASO: 1\nCASO: 2, 1\nFIM", "já tem um CASO"),
+            ("VEM: X, 1\nESCOLHE: 1\nCASO: X\nFIM", "constantes"),
+            ("ESCOLHE: 1\nCE QUER VER: 1\nFIM", "num CASO"),
+            ("ESCOLHE: 1\nQUALQUER OUTRO\nCASO: 1\nFIM", "tem que ser o último"),
+            ("CASO: 1", "fora de um ESCOLHE"),
+        ];
+
+        for &(body, message) in errors.iter() {
+            let mut ctx = Context::new();
+            ctx.call_function_by_id(0, vec![]).unwrap();
+
+            let error = ctx.add_source_string(format!("JAULA OUTRA\n{}\nSAINDO DA JAULA", body)).unwrap_err();
+            assert!(error.message().contains(message), "{}", error);
+        }
+    }
+
+    #[test]
+    fn else_branches() {
+        use context::Context;
+        use testing::run_in_context;
+
+        // Exactly one branch runs, also with ifs inside the branches and PARA AQUI leaving the whole chain
+        let src = "JAULA SHOW\n\
+                   REPETE: I, 0, 5\n\
+                   É ELE QUE A GENTE QUER: I, 1\n\
+                   É MENOR:\n\
+                   CE QUER VER: \"A\"\n\
+                   OU SE FOR: I == 1\n\
+                   É ELE QUE A GENTE QUER: I, 1\n\
+                   É ELE MEMO:\n\
+                   CE QUER VER: \"B\"\n\
+                   SE NÃO\n\
+                   CE QUER VER: \"?\"\n\
+                   FIM\n\
+                   OU SE FOR: I == 2\n\
+                   VEM: T, \"C\"\n\
+                   CE QUER VER: T\n\
+                   PARA AQUI\n\
+                   SE NÃO\n\
+                   É ELE QUE A GENTE QUER: I, 3\n\
+                   É ELE MEMO:\n\
+                   VAI PRO PRÓXIMO\n\
+                   FIM\n\
+                   CE QUER VER: \"D\"\n\
+                   FIM\n\
+                   CE QUER VER: I, \" \"\n\
+                   FIM\n\
+                   É ELE QUE A GENTE QUER: 1, 2\n\
+                   É ELE MEMO:\n\
+                   É ELE QUE A GENTE QUER: 1, 1\n\
+                   É ELE MEMO:\n\
+                   CE QUER VER: \"X\"\n\
+                   SE NÃO\n\
+                   CE QUER VER: \"Y\"\n\
+                   FIM\n\
+                   SE NÃO\n\
+                   CE QUER VER ISSO: \"FIM\"\n\
+                   FIM\n\
+                   SAINDO DA JAULA";
+
+        let mut ctx = Context::new();
+        let (result, output) = run_in_context(&mut ctx, src);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "A0 B1 C2 D4 FIM\n");
+        assert!(ctx.find_leaks().iter().all(|item| item.ref_count == 0), "{:?}", ctx.find_leaks());
+
+        let errors = [
+            ("SE NÃO\nFIM", "sem um bloco condicional"),
+            ("REPETE: I, 0, 2\nSE NÃO\nFIM", "sem um bloco condicional"),
+            ("É ELE QUE A GENTE QUER: 1\nÉ ELE MEMO:\nSE NÃO\nOU SE FOR: 1\nFIM", "tem que ser o último"),
+        ];
+
+        for &(body, message) in errors.iter() {
+            let mut ctx = Context::new();
+            ctx.call_function_by_id(0, vec![]).unwrap();
+
+            let error = ctx.add_source_string(format!("JAULA OUTRA\n{}\nSAINDO DA JAULA", body)).unwrap_err();
+            assert!(error.message().contains(message), "{}", error);
+        }
+    }
+
+    #[test]
+    fn jumps() {
+        use context::Context;
+        use testing::run_in_context;
+        use vm::Instruction;
+
+        // PARA AQUI in a loop leaves it, and in a conditional inside a loop only the conditional
+        let src = "JAULA SHOW\n\
+                   REPETE: I, 0, 3\n\
+                   REPETE: J, 0, 5\n\
+                   É ELE QUE A GENTE QUER: J, 1\n\
+                   É ELE MEMO:\n\
+                   VEM: TEXTO, \"-\"\n\
+                   CE QUER VER: TEXTO\n\
+                   PARA AQUI\n\
+                   CE QUER VER: \"NUNCA\"\n\
+                   FIM\n\
+                   CE QUER VER: I, J, \" \"\n\
+                   É ELE QUE A GENTE QUER: J, 2\n\
+                   É MENOR:\n\
+                   VAI PRO PRÓXIMO\n\
+                   FIM\n\
+                   PARA AQUI\n\
+                   FIM\n\
+                   FIM\n\
+                   CE QUER VER ISSO: \"FIM\"\n\
+                   SAINDO DA JAULA";
+
+        let mut ctx = Context::new();
+        let (result, output) = run_in_context(&mut ctx, src);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "00 -01 02 10 -11 12 20 -21 22 FIM\n");
+        assert!(ctx.find_leaks().iter().all(|item| item.ref_count == 0), "{:?}", ctx.find_leaks());
+
+        // Every jump goes to an instruction of the function, or right after the last one
+        for (_, name, code) in ctx.compiled_functions() {
+            for instruction in code {
+                match *instruction {
+                    Instruction::Jump(target) | Instruction::JumpIfNot(_, target) =>
+                        assert!(target <= code.len(), "{} : {:?}", name, instruction),
+                    _ => {}
                 }
             }
         }
 
-        Ok(ExecutionStatus::Normal)
+        // Without a block, there's nothing for PARA AQUI to leave
+        let mut ctx = Context::new();
+        ctx.call_function_by_id(0, vec![]).unwrap();
+
+        let error = ctx.add_source_string("JAULA OUTRA\nPARA AQUI\nSAINDO DA JAULA".to_owned()).unwrap_err();
+        assert!(error.message().contains("fora de um bloco"), "{}", error);
     }
-}
 
-#[derive(Clone, Debug)]
-pub enum Instruction {
-    PrintMathB,
-    PrintMathBDebug,
-    PrintNewLine,
-    FlushStdout,
-    Quit,
-    Compare,
-    Return,
-    EndConditionalBlock,
-    ExecuteIf(ComparisionRequest),
-    MakeNewFrame(usize),
-    SetLastFrameReady,
-    // For use when pushing arguments for a function. Check if the value on the top of the main stack
-    // has a compatible type
-    AssertMathBCompatible(TypeKind),
-    // Get a line of input and put it at the top of the main stack
-    ReadInput,
-    // Turn the main stack top into string
-    ConvertToString,
-    // Turn the main stack top into num
-    ConvertToNum,
-    // Turn the main stack top into int
-    ConvertToInt,
-    PushValMathA(RawValue),
-    PushValMathB(RawValue),
-    PushIntermediateToA,
-    PushIntermediateToB,
-    PushMathBToSeconday,
-    ClearSecondary,
-    /// Read a global var to the intermediary register
-    ReadGlobalVarFrom(usize),
-    /// When writing, values are read from the math b register
-    WriteGlobalVarTo(usize),
-    ReadVarFrom(usize),
-    WriteVarTo(usize),
-    WriteVarToLast(usize),
-    SwapMath,
-    ClearMath,
-    Add,
-    Mul,
-    Div,
-    Sub,
-    /// Saves the current PC so when the loop ends it can return to it's beginning
-    AddLoopLabel,
-    /// Return to a previous saved loop label
-    RestoreLoopLabel,
-    /// Remove a previously saved label
-    PopLoopLabel,
-    /// Retrieve the increment value from MathB and write it on every Restore to the specified address
-    RegisterIncrementOnRestore(usize),
-    /// Set the register to denote this is the first operation on the expression
-    SetFirstExpressionOperation,
-    /// Create a new list and put the result at MathB
-    MakeNewList,
-    /// Index a list with the ID from the intermediate register and the index from MathB, and put the result in MathB
-    IndexList,
-    /// Add the result in MathB to the list in the intermediate register, using the index at the secondary register
-    /// if the secondary register is Null, the element is placed on the back of the list
-    AddToListAtIndex,
-    /// Remove the element at the index located in MathB from the list in the intermediate register
-    RemoveFromListAtIndex,
-    /// Query the list from the intermediate address and write its size to the MathB
-    QueryListSize,
-    /// Call a plugin function with a number of arguments to pop from the stack
-    CallPlugin(usize, usize),
-    /// Push the value in MathB to the Plugin Argument stack
-    PushMathBPluginArgument,
-    /// Increase the skipping level
-    IncreaseSkippingLevel,
-    /// Halt the execution
-    Halt,
-    /// Try decrementing the ref count of the object in the specified location in the current frame (if special item)
-    TryDecrementRefAt(usize),
+    #[test]
+    fn block_variables_released_once() {
+        use context::Context;
+        use testing::run_in_context;
+
+        // The text in T is released at the end of the block, and the return of the function can't release it again
+        let src = "JAULA GUARDA(X : FIBRA)\n\
+                   É ELE QUE A GENTE QUER: 1\n\
+                   É ELE MEMO:\n\
+                   VEM: T, X\n\
+                   FIM\n\
+                   SAINDO DA JAULA\n\
+                   JAULA SHOW\n\
+                   VEM: S, \"BIRL\"\n\
+                   É HORA DO: GUARDA, S\n\
+                   É HORA DO: GUARDA, S\n\
+                   CE QUER VER ISSO: S\n\
+                   SAINDO DA JAULA";
+
+        let mut ctx = Context::new();
+        ctx.set_inlining(false);
+
+        let (result, output) = run_in_context(&mut ctx, src);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "BIRL\n");
+    }
+
+    #[test]
+    fn self_containing_values() {
+        use testing::run_program;
+
+        // A list or map inside itself is printed as [...] or {...}, and comparing it ends
+        let src = "JAULA SHOW\n\
+                   FAZ UMA LISTA: L\n\
+                   PÕE ISSO AQUI: L, 1\n\
+                   PÕE ISSO AQUI: L, L\n\
+                   CE QUER VER ISSO: L\n\
+                   FAZ UM MAPA: M\n\
+                   BOTA NO MAPA: M, \"EU\", M\n\
+                   CE QUER VER ISSO: M\n\
+                   É ELE QUE A GENTE QUER: L, L\n\
+                   É ELE MEMO\n\
+                   CE QUER VER ISSO: \"IGUAL\"\n\
+                   FIM\n\
+                   É ELE QUE A GENTE QUER: M, M\n\
+                   É ELE MEMO\n\
+                   CE QUER VER ISSO: \"IGUAL\"\n\
+                   FIM\n\
+                   SAINDO DA JAULA";
+
+        let (result, output) = run_program(src);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "(Lista) [ 1, [...] ]\n(Mapa) { \"EU\" : {...} }\nIGUAL\nIGUAL\n");
+    }
 }
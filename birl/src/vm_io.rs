@@ -0,0 +1,202 @@
+//! Input and output used by the virtual machine
+
+use std::io::{ self, Write, BufRead, IsTerminal };
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+/// Where the VM reads input from and writes output to
+pub trait VmIo {
+    /// Read a line from the input, without the line ending. Returns None if the input ended
+    fn read_line(&mut self) -> Result<Option<String>, String>;
+
+    /// Write to the output
+    fn write(&mut self, text : &str) -> Result<(), String>;
+
+    /// Write bytes to the output as they are, even if they aren't a valid text (UTF-8). By default what isn't valid is
+    /// written as �, for outputs that only take text
+    fn write_bytes(&mut self, bytes : &[u8]) -> Result<(), String> {
+        self.write(&String::from_utf8_lossy(bytes))
+    }
+
+    /// Write to the error output. By default it goes to the same place as the normal output
+    fn write_error(&mut self, text : &str) -> Result<(), String> {
+        self.write(text)
+    }
+
+    fn flush(&mut self) -> Result<(), String>;
+
+    /// Whether the output is a terminal (and so can be colored, flushed on every line, etc)
+    fn is_tty(&self) -> bool;
+}
+
+/// The process' standard input, output and error
+pub struct StdIo {
+    stdin : io::Stdin,
+    stdout : io::Stdout,
+    stderr : io::Stderr,
+}
+
+impl StdIo {
+    pub fn new() -> StdIo {
+        StdIo {
+            stdin : io::stdin(),
+            stdout : io::stdout(),
+            stderr : io::stderr(),
+        }
+    }
+}
+
+impl Default for StdIo {
+    fn default() -> StdIo {
+        StdIo::new()
+    }
+}
+
+impl VmIo for StdIo {
+    fn read_line(&mut self) -> Result<Option<String>, String> {
+        let mut line = String::new();
+
+        match self.stdin.lock().read_line(&mut line) {
+            Ok(0) => Ok(None),
+            Ok(_) => {
+                // Remove the line ending (\n or \r\n)
+
+                if line.ends_with('\n') {
+                    line.pop();
+
+                    if line.ends_with('\r') {
+                        line.pop();
+                    }
+                }
+
+                Ok(Some(line))
+            }
+            Err(e) => Err(format!("Erro lendo input : {:?}", e)),
+        }
+    }
+
+    fn write(&mut self, text : &str) -> Result<(), String> {
+        self.write_bytes(text.as_bytes())
+    }
+
+    fn write_bytes(&mut self, bytes : &[u8]) -> Result<(), String> {
+        self.stdout.write_all(bytes)
+            .map_err(|what| format!("Deu pra escrever não cumpade: {:?}", what))
+    }
+
+    fn write_error(&mut self, text : &str) -> Result<(), String> {
+        self.stderr.write_all(text.as_bytes())
+            .map_err(|what| format!("Deu pra escrever não cumpade: {:?}", what))
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        self.stdout.flush()
+            .map_err(|what| format!("Deu pra dar flush não cumpade: {:?}", what))
+    }
+
+    fn is_tty(&self) -> bool {
+        self.stdout.is_terminal()
+    }
+}
+
+/// Reads input from a string given beforehand and keeps everything written in memory. Useful for tests and for
+/// running code where there's no terminal
+pub struct CaptureIo {
+    input : VecDeque<String>,
+    output : Rc<RefCell<String>>,
+}
+
+impl CaptureIo {
+    /// Create a CaptureIo where each line of `input` is given to the program as a line of input
+    pub fn new(input : &str) -> CaptureIo {
+        CaptureIo {
+            input : input.lines().map(|l| l.to_owned()).collect(),
+            output : Rc::new(RefCell::new(String::new())),
+        }
+    }
+
+    /// Handle to the captured output, still valid after the CaptureIo is given to the VM
+    pub fn output(&self) -> Rc<RefCell<String>> {
+        self.output.clone()
+    }
+}
+
+impl VmIo for CaptureIo {
+    fn read_line(&mut self) -> Result<Option<String>, String> {
+        Ok(self.input.pop_front())
+    }
+
+    fn write(&mut self, text : &str) -> Result<(), String> {
+        self.output.borrow_mut().push_str(text);
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn is_tty(&self) -> bool {
+        false
+    }
+}
+
+/// Has no input and throws away all output
+pub struct NullIo;
+
+impl VmIo for NullIo {
+    fn read_line(&mut self) -> Result<Option<String>, String> {
+        Ok(None)
+    }
+
+    fn write(&mut self, _text : &str) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn is_tty(&self) -> bool {
+        false
+    }
+}
+
+mod tests {
+    #[test]
+    fn capture_io() {
+        use vm_io::CaptureIo;
+        use context::Context;
+
+        let io = CaptureIo::new("BAMBAM\n");
+        let output = io.output();
+
+        let mut ctx = Context::new();
+
+        let _ = ctx.set_io(Box::new(io));
+
+        let src = "JAULA SHOW\n\
+                   FALA AÍ: NOME\n\
+                   CE QUER VER ISSO: \"BORA, \" + NOME\n\
+                   FALA AÍ: NADA\n\
+                   CE QUER VER ISSO: NADA\n\
+                   SAINDO DA JAULA";
+
+        if let Err(e) = ctx.add_source_string(src.to_owned()) {
+            panic!("{}", e);
+        }
+
+        if let Err(e) = ctx.call_function_by_id(0, vec![]) {
+            panic!("{}", e);
+        }
+
+        if let Err(e) = ctx.start_program() {
+            panic!("{}", e);
+        }
+
+        // After the input ends, reading gives Null
+
+        assert_eq!(output.borrow().as_str(), "BORA, BAMBAM\n<Null>\n");
+    }
+}
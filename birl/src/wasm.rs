@@ -0,0 +1,193 @@
+//! The Birl type for JavaScript, made with wasm-bindgen, to run code in the browser (like in the online playground).
+//! What the program prints goes to a JS callback as soon as it's printed, and the code runs a number of instructions at
+//! a time, so the page doesn't freeze while a long (or endless) program runs :
+//!
+//! ```js
+//! const birl = new Birl(text => terminal.write(text));
+//! birl.compile(source); // Throws the error as a diagnostic, in JSON
+//!
+//! function tick() {
+//!     switch (birl.run(10000)) {
+//!         case "running": requestAnimationFrame(tick); break;
+//!         case "awaiting_input": askForLine(line => { birl.provideInput(line); tick(); }); break;
+//!     }
+//! }
+//!
+//! tick();
+//! ```
+//!
+//! For running everything at once, with the limits of the playground, there's `runPlayground`
+
+use wasm_bindgen::prelude::*;
+use js_sys::Function;
+
+use context::{ Context, BIRL_GLOBAL_FUNCTION_ID, BIRL_MAIN_FUNCTION_ID };
+use vm::{ VirtualMachineBuilder, ExecutionStatus };
+use vm_io::VmIo;
+use playground::{ self, Limits, add_sandboxed_standard_library };
+use diagnostic::Diagnostic;
+use error::BirlError;
+
+/// Sends the output to a JS function. There's no input to read, so the VM waits for `provideInput`
+struct JsIo {
+    on_output : Function,
+}
+
+impl VmIo for JsIo {
+    fn read_line(&mut self) -> Result<Option<String>, String> {
+        Ok(None)
+    }
+
+    fn write(&mut self, text : &str) -> Result<(), String> {
+        self.on_output.call1(&JsValue::NULL, &JsValue::from_str(text))
+            .map(|_| ())
+            .map_err(|e| format!("Erro no callback do output : {:?}", e))
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn is_tty(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stage {
+    /// Nothing was compiled yet
+    Empty,
+    /// Running the global code, before the main function
+    Global,
+    Main,
+    /// Ended, with the status that is returned by every run after that
+    Ended(&'static str),
+}
+
+#[wasm_bindgen]
+pub struct Birl {
+    ctx : Context,
+    source : String,
+    stage : Stage,
+}
+
+#[wasm_bindgen]
+impl Birl {
+    /// Create a VM with the sandboxed standard library (the same one as the playground), that calls `on_output` with
+    /// everything the program prints
+    #[wasm_bindgen(constructor)]
+    pub fn new(on_output : Function) -> Result<Birl, JsValue> {
+        let limits = Limits::default();
+
+        let builder = VirtualMachineBuilder::new()
+            .stack_size(limits.stack_size)
+            .max_text_size(Some(limits.max_text_size))
+            .max_list_size(Some(limits.max_list_size))
+            .max_special_items(Some(limits.max_special_items))
+            .max_call_depth(Some(limits.max_call_depth));
+
+        let mut ctx = Context::with_vm(builder).map_err(|e| Birl::error(Diagnostic::error(e)))?;
+
+        let _ = ctx.set_io(Box::new(JsIo { on_output }));
+        ctx.set_await_input(true);
+
+        ctx.call_function_by_id(BIRL_GLOBAL_FUNCTION_ID, vec![])
+            .and_then(|_| add_sandboxed_standard_library(&mut ctx))
+            .map_err(|e| Birl::error(Diagnostic::error(e.into())))?;
+
+        Ok(Birl { ctx, source : String::new(), stage : Stage::Empty })
+    }
+
+    /// Compile the program. Returns the warnings, as a JSON array of diagnostics, or throws the error. A Birl only
+    /// runs one program, so for another one a new Birl has to be made
+    pub fn compile(&mut self, source : &str) -> Result<String, JsValue> {
+        if self.stage != Stage::Empty {
+            return Err(Birl::error(Diagnostic::error("Esse Birl já tem um programa, cumpade. Cria outro pra rodar \
+                                                      outro código".to_owned())));
+        }
+
+        self.source = source.to_owned();
+
+        let compiled = self.ctx.add_source_string(source.to_owned());
+
+        let warnings = self.ctx.take_warnings().iter()
+            .map(|w| Diagnostic::from_warning(w).with_source(source).to_json())
+            .collect::<Vec<String>>();
+
+        if let Err(e) = compiled {
+            let diagnostic = match self.ctx.last_compile_error() {
+                Some(error) => Diagnostic::from_compile_error(error).with_source(source),
+                None => Diagnostic::error(e.to_string()),
+            };
+
+            self.stage = Stage::Ended("error");
+
+            return Err(Birl::error(diagnostic));
+        }
+
+        self.stage = Stage::Global;
+
+        Ok(format!("[{}]", warnings.join(",")))
+    }
+
+    /// Execute up to `max_instructions` instructions. Returns "running" if there's still more to run, "awaiting_input"
+    /// if the program is waiting for `provideInput`, or "finished" or "quit" when it ends. Errors are thrown as a
+    /// diagnostic in JSON, and after one it only returns "error"
+    pub fn run(&mut self, max_instructions : u32) -> Result<String, JsValue> {
+        match self.stage {
+            Stage::Empty => return Err(Birl::error(Diagnostic::error("Nenhum programa foi compilado".to_owned()))),
+            Stage::Ended(status) => return Ok(status.to_owned()),
+            Stage::Global | Stage::Main => {}
+        }
+
+        match ExecutionStatus::from(self.ctx.run_steps(max_instructions as usize)) {
+            ExecutionStatus::Error(e) => Err(self.runtime_error(&e)),
+            ExecutionStatus::Paused | ExecutionStatus::Breakpoint => Ok("running".to_owned()),
+            ExecutionStatus::AwaitingInput => Ok("awaiting_input".to_owned()),
+            ExecutionStatus::Quit => Ok(self.end("quit")),
+            ExecutionStatus::Halt if self.stage == Stage::Global && self.ctx.has_main() => {
+                self.ctx.call_function_by_id(BIRL_MAIN_FUNCTION_ID, vec![])
+                    .map_err(|e| self.runtime_error(&e))?;
+
+                self.stage = Stage::Main;
+
+                Ok("running".to_owned())
+            }
+            ExecutionStatus::Halt => Ok(self.end("finished")),
+            other => Err(self.runtime_error(&BirlError::from(format!("Execução parou : {:?}", other)))),
+        }
+    }
+
+    /// Give a line of input to the program, for when it asks for one
+    #[wasm_bindgen(js_name = provideInput)]
+    pub fn provide_input(&mut self, line : String) {
+        self.ctx.provide_input(line);
+    }
+}
+
+impl Birl {
+    fn error(diagnostic : Diagnostic) -> JsValue {
+        JsValue::from_str(&diagnostic.to_json())
+    }
+
+    fn runtime_error(&mut self, error : &BirlError) -> JsValue {
+        self.stage = Stage::Ended("error");
+
+        Birl::error(Diagnostic::from_runtime_error(error).with_source(&self.source))
+    }
+
+    fn end(&mut self, status : &'static str) -> String {
+        self.stage = Stage::Ended(status);
+
+        status.to_owned()
+    }
+}
+
+/// Compile and run `source` all at once with the playground limits, returning the result in JSON (the same as
+/// `RunResult::to_json`). There's no clock in wasm, so there's no time limit, only the one on instructions
+#[wasm_bindgen(js_name = runPlayground)]
+pub fn run_playground(source : &str, input : &str) -> String {
+    let limits = Limits { time : None, .. Limits::default() };
+
+    playground::run(source, input, &limits).to_json()
+}
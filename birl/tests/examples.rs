@@ -0,0 +1,70 @@
+//! Runs every example in `exemplos` and compares what it printed with its `.saida` file, so a change in the language
+//! that changes what the examples do doesn't go unnoticed. With ATUALIZA_EXEMPLOS set, the `.saida` files are written
+//! with the current output instead. They also have to do the same thing on every engine of the differential module
+
+extern crate birl;
+
+use birl::testing::{ check_examples, ExampleOutcome, DiffLine, EXPECTED_OUTPUT_EXTENSION, SCRIPTED_INPUT_EXTENSION };
+use birl::differential::check_source;
+
+use std::env;
+use std::fs;
+use std::path::{ Path, PathBuf };
+
+fn examples_folder() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("..").join("exemplos")
+}
+
+#[test]
+fn examples() {
+    let update = env::var_os("ATUALIZA_EXEMPLOS").is_some();
+    let folder = examples_folder();
+
+    let results = check_examples(&folder).unwrap();
+    assert!(! results.is_empty(), "Nenhum exemplo em {}", folder.display());
+
+    let mut failures = vec![];
+
+    for result in results {
+        let name = result.file.display();
+
+        match result.outcome {
+            ExampleOutcome::Passed => {}
+            ExampleOutcome::Failed(e) => failures.push(format!("{} : {}", name, e)),
+            ExampleOutcome::NoExpectedOutput(ref output) | ExampleOutcome::Differs { ref output, .. } if update => {
+                fs::write(result.file.with_extension(EXPECTED_OUTPUT_EXTENSION), output).unwrap();
+            }
+            ExampleOutcome::NoExpectedOutput(output) => {
+                failures.push(format!("{} não tem um .{}, o output foi :\n{}", name, EXPECTED_OUTPUT_EXTENSION, output));
+            }
+            ExampleOutcome::Differs { diff, .. } => {
+                let diff = diff.iter()
+                    .map(|line| match *line {
+                        DiffLine::Same(ref l) => format!("  {}", l),
+                        DiffLine::Expected(ref l) => format!("- {}", l),
+                        DiffLine::Actual(ref l) => format!("+ {}", l),
+                    })
+                    .collect::<Vec<String>>();
+
+                failures.push(format!("{} mudou :\n{}", name, diff.join("\n")));
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n\n"));
+}
+
+#[test]
+fn examples_on_every_engine() {
+    for result in check_examples(&examples_folder()).unwrap() {
+        let source = fs::read_to_string(&result.file).unwrap();
+        let input = fs::read_to_string(result.file.with_extension(SCRIPTED_INPUT_EXTENSION)).unwrap_or_default();
+
+        let mismatches = check_source(&source, &input).unwrap();
+
+        if let Some(mismatch) = mismatches.first() {
+            panic!("{} deu diferente em {:?} :\nEsperado {:?}\nEncontrado {:?}", result.file.display(),
+                   mismatch.engine, mismatch.expected, mismatch.found);
+        }
+    }
+}
@@ -1,10 +1,24 @@
 extern crate birl;
 
-use std::env::args;
+mod render;
+mod test_runner;
+
+use std::env::{ self, args };
+use std::path::PathBuf;
 use std::process::exit;
-use birl::context::Context;
-use birl::compiler::CompilerHint;
-use birl::context::BIRL_GLOBAL_FUNCTION_ID;
+use std::fs;
+use birl::context::{ Context, RawValue };
+use birl::compiler::{ CompilerHint, FunctionKind };
+use birl::context::{ BIRL_GLOBAL_FUNCTION_ID, BIRL_MAIN_FUNCTION_ID };
+use birl::vm_io::StdIo;
+use birl::vm::{ VirtualMachineBuilder, IntegerWidth, OverflowPolicy, DecimalSeparator, ExecutionStatus };
+use birl::parser::{ parse_line, ParserResult, CommandKind, CommandArgument, TypeKind };
+use birl::compiler::Warning;
+use birl::diagnostic::{ Diagnostic, Span, CODE_PARSE_ERROR };
+use birl::error::{ BirlError, ErrorLocation };
+use birl::bytecode::BYTECODE_EXTENSION;
+use test_runner::{ run_tests, TestOptions };
+use render::{ Style, render_error, render_warning, highlight_source, highlight_debug };
 
 pub const SHELL_COPYRIGHT : &'static str
 = "© 2019 Rafael Rodrigues Nakano, Matheus Branco Borella";
@@ -19,19 +33,12 @@ fn start_interactive_console(c: &mut Context) {
     c.set_interactive_mode();
 
 	/* Bind the Context interpreter to standard IO */
-	let _ = c.set_stdin({
-		use std::io;
-		let reader = io::BufReader::new(io::stdin());
-		Some(Box::new(reader))
-	});
-	let _ = c.set_stdout({
-		use std::io;
-		Some(Box::new(io::stdout()))
-	});
+	let _ = c.set_io(Box::new(StdIo::new()));
 
 	/* Enter interactive loop */
-	use std::io::{stdin, BufReader, BufRead};
-	let mut prompt = BufReader::new(stdin());
+	/* Shares the buffer of stdin with the VM, so lines read by the program aren't lost */
+	use std::io::stdin;
+	let prompt = stdin();
     let mut scope_level = 0usize;
 	loop{
         if scope_level == 0 {
@@ -56,7 +63,26 @@ fn start_interactive_console(c: &mut Context) {
 			}
 		}
 
-        match c.process_line(&line) {
+        // A ; at the end keeps the value of an assignment from being shown
+        let quiet = line.trim_end().ends_with(';');
+        let line = if quiet { line.trim_end().trim_end_matches(';').to_owned() } else { line };
+
+        let echo = if scope_level == 0 && ! quiet { assignment_target(&line) } else { None };
+
+        let mut result = c.process_line(&line);
+
+        // Assignments show the value that was stored, the same way as typing just the name of the variable
+        if let (Ok(None), Some(name)) = (&result, echo) {
+            result = c.process_line(&name);
+        }
+
+        let style = Style::for_stderr();
+
+        for warning in c.take_warnings() {
+            eprintln!("{} : {}", style.warning("Aviso"), warning.message);
+        }
+
+        match result {
             Ok(None) => {}
             Ok(Some(hint)) => {
                 match hint {
@@ -64,7 +90,7 @@ fn start_interactive_console(c: &mut Context) {
                     CompilerHint::ScopeEnd => scope_level -= 1,
                 }
             }
-            Err(e) => eprintln!("{}", e)
+            Err(e) => eprintln!("{} : {}", style.error("Erro"), e)
         };
 
         if scope_level == 0 {
@@ -75,15 +101,20 @@ fn start_interactive_console(c: &mut Context) {
 
             use birl::vm::ExecutionStatus as Es;
             loop {
-                match c.execute_next_instruction() {
-                    Ok(Es::Quit) => {
+                match Es::from(c.resume()) {
+                    Es::Quit => {
                         eprintln!("Saindo...");
                         return;
                     }
-                    Ok(Es::Halt) => break,
-                    Ok(_) => {}
-                    Err(e) => {
+                    Es::Halt => break,
+                    Es::Breakpoint => {}
+                    Es::Error(e) => {
                         eprintln!("{}", e);
+                        report_trace(e.location());
+                    }
+                    status => {
+                        eprintln!("Execução parou : {:?}", status);
+                        break;
                     }
                 }
             }
@@ -91,8 +122,84 @@ fn start_interactive_console(c: &mut Context) {
 	}
 
 	/* Make sure the output is flushed */
-	c.set_stdout(None).unwrap().flush()
-		.expect("Could not flush io::stdout().");
+	c.flush_output();
+}
+
+/// Name of the file with definitions loaded at the start of the interactive console, in the home directory
+const RC_FILE_NAME : &str = ".birlrc.birl";
+
+/// The rc file, if the home directory is known and the file exists
+fn rc_file() -> Option<PathBuf> {
+    let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"))?;
+    let path = PathBuf::from(home).join(RC_FILE_NAME);
+
+    if path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Find a file given in the command line. If it isn't in the current directory, it's looked for in each directory of
+/// BIRL_PATH, in order
+fn resolve_file(file : String) -> String {
+    if fs::metadata(&file).is_ok() {
+        return file;
+    }
+
+    if let Some(paths) = env::var_os("BIRL_PATH") {
+        for dir in env::split_paths(&paths) {
+            let candidate = dir.join(&file);
+
+            if candidate.is_file() {
+                return candidate.to_string_lossy().into_owned();
+            }
+        }
+    }
+
+    file
+}
+
+/// Print the items alive at the end of the program that can't be reached from the global variables
+fn report_leaks(ctx : &Context, arena : bool) {
+    let style = Style::for_stderr();
+
+    // The arena never frees anything, so everything would look like a leak
+    if arena {
+        eprintln!("{} : --detect-leaks não funciona junto com --arena", style.warning("aviso"));
+        return;
+    }
+
+    let leaks = ctx.find_leaks();
+
+    if leaks.is_empty() {
+        eprintln!("{} : nenhum vazamento", style.bold("memória"));
+        return;
+    }
+
+    eprintln!("{} : {} item(ns) vivo(s) que não dá pra alcançar pelas variáveis globais", style.warning("vazamentos"), leaks.len());
+
+    for item in leaks {
+        eprintln!("  {}", item);
+    }
+}
+
+/// Name of the variable the line assigns to (with VEM or BORA), if it's an assignment
+fn assignment_target(line : &str) -> Option<String> {
+    let cmd = match parse_line(line) {
+        Ok(ParserResult::Command(cmd)) => cmd,
+        _ => return None,
+    };
+
+    match cmd.kind {
+        CommandKind::Declare | CommandKind::Set => {}
+        _ => return None,
+    }
+
+    match cmd.arguments.into_iter().next() {
+        Some(CommandArgument::Name(name)) => Some(name),
+        _ => None,
+    }
 }
 
 fn print_help() {
@@ -100,6 +207,9 @@ fn print_help() {
 
 	println!("Ta querendo ajuda, cumpade?");
 	println!("O uso é o seguinte: birl [opções] [arquivo ou arquivos]");
+	println!("Pra rodar os testes (funções que começam com TESTE ou com FICHA: TESTE) : birl test [opções] [arquivo ou arquivos]");
+	println!("\tCom --snapshot, a saída de cada teste é comparada com a guardada num arquivo .snap (e --update \
+              substitui as que mudaram)");
 	println!("Cê pode passar mais de um arquivo, só que apenas um pode ter a seção \"SHOW\", que \
               é o ponto de partida do teu programa.");
 	println!("As opções são as seguintes:");
@@ -109,6 +219,36 @@ fn print_help() {
               um arquivo.");
 	println!("\t-i ou --interativo\t\t\t\t: Inicia um console interativo pra rodar códigos");
     println!("\t-p ou --sem-padrão\t\t\t\t: Não adiciona as definições da biblioteca padrão");
+    println!("\t-e ou --estrito ou --strict\t\t: Liga o modo monstro (o mesmo que # USE MODO MONSTRO no código) : avisos \
+              viram erros, variáveis precisam de valor, comparar tipos diferentes e estouro de inteiros dão erro");
+    println!("\t--arena\t\t\t\t\t: Nunca libera memória enquanto o programa roda (mais rápido pra programas curtos)");
+    println!("\t--sem-em-linha\t\t\t\t: Não troca as chamadas de funções pequenas pelo código delas, pra que os erros \
+              dentro delas mostrem a função e a linha onde aconteceram");
+    println!("\t--opt\t\t\t\t\t: Tira do código das funções instruções que não fazem diferença (como ler uma \
+              variável logo depois de escrever nela)");
+    println!("\t--emit-ast ou --emit ast\t\t: Mostra a árvore de cada linha do código, sem rodar");
+    println!("\t--emit-bytecode ou --emit bytecode\t: Mostra as instruções de cada função compilada, com os nomes das \
+              variáveis e funções e pra onde vão os pulos, sem rodar");
+    println!("\t--compila\t\t\t\t: Guarda o programa compilado num arquivo .{} com o nome do primeiro arquivo, sem \
+              rodar. Depois é só passar o .{} no lugar do código", BYTECODE_EXTENSION, BYTECODE_EXTENSION);
+    println!("\t--message-format=json\t\t\t: Mostra os erros e avisos como JSON, um por linha");
+    println!("\t--inteiros=32, 64 ou 128\t\t: Tamanho dos inteiros em bits (o padrão é o maior disponível)");
+    println!("\t--estouro=volta, erro ou número\t\t: O que acontece quando uma conta com inteiros não cabe no tamanho \
+              (o padrão é volta)");
+    println!("\t--decimal=vírgula ou ponto\t\t: Separador decimal dos números em textos (no que é mostrado e no que é \
+              lido, como em FALA UM NÚMERO). O padrão é ponto");
+    println!("\t--flush=sempre ou nunca\t\t\t: Se o output aparece logo depois de cada print. O padrão é só quando \
+              ele é um terminal");
+    println!("\t--profundidade=N\t\t\t: Máximo de funções rodando ao mesmo tempo, contando o código global, antes \
+              de dar erro (o padrão é 10000, e 0 é sem limite)");
+    println!("\t--semente=N\t\t\t\t: Começa os números aleatórios (de UUID, ID CURTO...) da semente N, pra serem os \
+              mesmos toda vez");
+    println!("\t--detect-leaks\t\t\t\t: No fim, mostra os textos e listas que ficaram na memória sem ninguém usar");
+    println!("\t--cada-linha=FUNÇÃO\t\t\t: Chama a FUNÇÃO (que recebe uma FIBRA) pra cada linha da entrada, na mesma \
+              VM. O código global roda antes da primeira linha e o SHOW, se tiver, depois da última");
+    println!("\t--sem-birlrc\t\t\t\t: Não carrega o ~/{} no console interativo", RC_FILE_NAME);
+    println!();
+    println!("Arquivos que não estão na pasta atual são procurados nas pastas da variável de ambiente BIRL_PATH.");
 }
 
 /// Parameters passed through the command line
@@ -123,14 +263,60 @@ enum Param {
 	Interactive,
     /// Do not add the standard library to the code
     WithoutStdLib,
+    /// Treat warnings as errors
+    Strict,
+    /// Use arena allocation in the VM
+    Arena,
+    /// Keep the calls to small functions instead of replacing them by their code
+    WithoutInlining,
+    /// Pass the code of the functions through the peephole optimizer
+    Optimize,
+    /// Print the parsed AST of the sources instead of running them
+    EmitAst,
+    /// Print the compiled instructions instead of running the program
+    EmitBytecode,
+    /// Save the compiled program in a .birlc file instead of running it
+    Compile,
+    /// How to print errors and warnings
+    MessageFormat(MessageFormat),
+    /// Width of the integers in the VM
+    IntegerWidth(IntegerWidth),
+    /// What happens when an integer result doesn't fit in the width
+    OverflowPolicy(OverflowPolicy),
+    /// How numbers are written in texts and read from them
+    DecimalSeparator(DecimalSeparator),
+    /// Seed of the random numbers
+    RandomSeed(u64),
+    /// Maximum number of functions running at the same time, 0 for no limit
+    MaxCallDepth(usize),
+    /// Whether the output is flushed after each print
+    AutoFlush(bool),
+    /// Do not load the rc file in the interactive console
+    WithoutRcFile,
+    /// Run the tests in the files instead of the program
+    Test,
+    /// Compare the output of the tests with snapshots
+    Snapshot,
+    /// Replace the snapshots that changed
+    UpdateSnapshots,
+    /// Show the items still alive at the end that can't be reached from the globals
+    DetectLeaks,
+    /// Call the function with this name for each line of the input
+    EachLine(String),
 }
 
 fn get_params() -> Vec<Param> {
-	let mut arguments = args();
+	let mut arguments = args().peekable();
 	let mut result: Vec<Param> = vec![];
 
 	let _ = arguments.next().unwrap(); // Dispose of the first argument
 
+    // `birl test` is a subcommand, so it's only recognized as the first argument
+    if let Some("test") | Some("teste") = arguments.peek().map(|a| a.as_str()) {
+        let _ = arguments.next();
+        result.push(Param::Test);
+    }
+
 	loop {
 		if let Some(arg) = arguments.next() {
 			match arg.as_str() {
@@ -146,6 +332,43 @@ fn get_params() -> Vec<Param> {
 					}
 				}
                 "-p" | "--sem-padrao" | "--sem-padrão" => result.push(Param::WithoutStdLib),
+                "-e" | "--estrito" | "--strict" => result.push(Param::Strict),
+                "--arena" => result.push(Param::Arena),
+                "--sem-em-linha" => result.push(Param::WithoutInlining),
+                "--opt" => result.push(Param::Optimize),
+                "--emit-ast" => result.push(Param::EmitAst),
+                "--emit-bytecode" => result.push(Param::EmitBytecode),
+                "--emit" => match arguments.next().as_deref() {
+                    Some("ast") => result.push(Param::EmitAst),
+                    Some("bytecode") => result.push(Param::EmitBytecode),
+                    _ => println!("Erro: O argumento --emit precisa de ast ou bytecode logo em seguida, bixo."),
+                },
+                "--compila" => result.push(Param::Compile),
+                "--message-format=human" => result.push(Param::MessageFormat(MessageFormat::Human)),
+                "--message-format=json" => result.push(Param::MessageFormat(MessageFormat::Json)),
+                "--inteiros=32" => result.push(Param::IntegerWidth(IntegerWidth::Bits32)),
+                "--inteiros=64" => result.push(Param::IntegerWidth(IntegerWidth::Bits64)),
+                "--inteiros=128" => result.push(Param::IntegerWidth(IntegerWidth::Bits128)),
+                "--estouro=erro" => result.push(Param::OverflowPolicy(OverflowPolicy::Error)),
+                "--estouro=volta" => result.push(Param::OverflowPolicy(OverflowPolicy::Wrap)),
+                "--estouro=número" | "--estouro=numero" => result.push(Param::OverflowPolicy(OverflowPolicy::Float)),
+                "--decimal=vírgula" | "--decimal=virgula" => result.push(Param::DecimalSeparator(DecimalSeparator::Comma)),
+                "--decimal=ponto" => result.push(Param::DecimalSeparator(DecimalSeparator::Point)),
+                "--flush=sempre" => result.push(Param::AutoFlush(true)),
+                "--flush=nunca" => result.push(Param::AutoFlush(false)),
+                seed if seed.starts_with("--semente=") => match seed["--semente=".len()..].parse::<u64>() {
+                    Ok(s) => result.push(Param::RandomSeed(s)),
+                    Err(_) => println!("Erro: A semente tem que ser um inteiro positivo, bixo."),
+                },
+                depth if depth.starts_with("--profundidade=") => match depth["--profundidade=".len()..].parse::<usize>() {
+                    Ok(d) => result.push(Param::MaxCallDepth(d)),
+                    Err(_) => println!("Erro: A profundidade tem que ser um inteiro positivo, bixo."),
+                },
+                "--sem-birlrc" => result.push(Param::WithoutRcFile),
+                "--snapshot" => result.push(Param::Snapshot),
+                "--update" | "--atualiza" => result.push(Param::UpdateSnapshots),
+                "--detect-leaks" => result.push(Param::DetectLeaks),
+                each if each.starts_with("--cada-linha=") => result.push(Param::EachLine(each["--cada-linha=".len()..].to_owned())),
 				// Push the file to the result stack
 				_ => result.push(Param::InputFile(arg))
 			}
@@ -157,24 +380,224 @@ fn get_params() -> Vec<Param> {
 	result
 }
 
-fn main() {
-	let args = get_params();
-	let mut interactive = false;
-    let mut with_stdlib = true;
-    let mut files = vec![];
-    let mut strings = vec![];
+/// Line `number` (starting at 1) of the source, with the number, if it exists
+fn source_line(source : &str, number : Option<usize>) -> Option<(usize, &str)> {
+    let number = number?;
 
-	let mut ctx = Context::new();
+    source.lines().nth(number.checked_sub(1)?).map(|line| (number, line))
+}
 
-    match ctx.call_function_by_id(BIRL_GLOBAL_FUNCTION_ID, vec![]) {
-        Ok(_) => {}
-        Err(e) => {
-            println!("Erro iniciando o contexto : {}", e);
+/// How errors and warnings are printed
+#[derive(Clone, Copy, PartialEq)]
+enum MessageFormat {
+    /// Text for people to read, with colors if it's a terminal
+    Human,
+    /// A line of JSON for each message, for editors and other tools
+    Json,
+}
+
+fn report_warnings(warnings : Vec<Warning>, origin : &str, source : &str, format : MessageFormat) {
+    let style = Style::for_stderr();
+
+    for warning in warnings {
+        let span = Diagnostic::from_warning(&warning).with_source(source).span;
+        let columns = span.map(|s| (s.column_start, s.column_end));
+
+        match format {
+            MessageFormat::Human => eprintln!("{}\n", render_warning(&style, origin, source_line(source, warning.line),
+                                                                     columns, &warning.message)),
+            MessageFormat::Json => eprintln!("{}", Diagnostic::from_warning(&warning).with_file(origin)
+                                                       .with_source(source).to_json()),
+        }
+    }
+}
+
+fn report_compile_error(ctx : &Context, origin : &str, source : &str, error : BirlError, format : MessageFormat) {
+    let style = Style::for_stderr();
+
+    match (format, ctx.last_compile_error()) {
+        (MessageFormat::Human, Some(e)) =>
+            eprintln!("{}\n", render_error(&style, origin, source_line(source, Some(e.line)), &e.message)),
+        (MessageFormat::Human, None) => eprintln!("{}\n", render_error(&style, origin, None, error.message())),
+        (MessageFormat::Json, Some(e)) =>
+            eprintln!("{}", Diagnostic::from_compile_error(e).with_file(origin).with_source(source).to_json()),
+        (MessageFormat::Json, None) => eprintln!("{}", Diagnostic::error(error.into()).with_file(origin).to_json()),
+    }
+}
+
+/// Show the functions that were running when an error happened, if there was more than one
+fn report_trace(location : &ErrorLocation) {
+    let trace = location.format_trace();
+
+    if ! trace.is_empty() {
+        eprintln!("{}", Style::for_stderr().gutter(&trace));
+    }
+}
+
+/// Report an error from running the program, with the line of the source where it happened if it's known
+fn report_runtime_error(error : BirlError, format : MessageFormat) {
+    let location = error.location();
+    // Only read again to show where the problem is
+    let source = location.file.as_ref().and_then(|f| fs::read_to_string(f).ok()).unwrap_or_default();
+    let line = source_line(&source, location.line);
+
+    match format {
+        MessageFormat::Human => {
+            match (location.file.as_ref(), line) {
+                (Some(file), Some(line)) => eprintln!("{}", render_error(&Style::for_stderr(), file, Some(line), error.message())),
+                _ => println!("{} : {}", Style::for_stdout().error("Erro de execução"), error),
+            }
 
+            report_trace(location);
+        }
+        MessageFormat::Json => eprintln!("{}", Diagnostic::from_runtime_error(&error).with_source(&source).to_json()),
+    }
+}
+
+/// Report an error that isn't tied to a line of the source
+fn report_error(origin : &str, error : &str, format : MessageFormat) {
+    match format {
+        MessageFormat::Human => eprintln!("{}", render_error(&Style::for_stderr(), origin, None, error)),
+        MessageFormat::Json => eprintln!("{}", Diagnostic::error(error.to_owned()).with_file(origin).to_json()),
+    }
+}
+
+/// Print the result of parsing each line of the sources, without compiling them
+fn print_ast(files : &[String], strings : &[String], format : MessageFormat) {
+    let style = Style::for_stdout();
+    let mut sources = vec![];
+
+    for file in files {
+        match fs::read_to_string(file) {
+            Ok(source) => sources.push((file.clone(), source)),
+            Err(e) => report_error(file, &format!("{:?}", e), format),
+        }
+    }
+
+    for source in strings {
+        sources.push(("<string>".to_owned(), source.clone()));
+    }
+
+    for (origin, source) in sources {
+        println!("{}", style.bold(&format!("== {} ==", origin)));
+
+        for (index, line) in source.lines().enumerate() {
+            match parse_line(line) {
+                Ok(ParserResult::Nothing) => {}
+                Ok(result) => {
+                    println!("{} {} {}", style.gutter(&format!("{:>4}", index + 1)), style.gutter("|"),
+                             highlight_source(&style, line.trim()));
+                    println!("{}", highlight_debug(&style, &format!("{:#?}", result)));
+                }
+                Err(e) => match format {
+                    MessageFormat::Human =>
+                        eprintln!("{}", render_error(&Style::for_stderr(), &origin, Some((index + 1, line)), e.message())),
+                    MessageFormat::Json => {
+                        let mut diagnostic = Diagnostic::error(e.into()).with_code(CODE_PARSE_ERROR).with_file(&origin);
+                        diagnostic.span = Some(Span::whole_line(index + 1, line));
+
+                        eprintln!("{}", diagnostic.to_json());
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// Call the function for each line of stdin, awk style, on the same VM, so the globals are kept from one line to the
+/// next. The global code runs before the first line and SHOW after the last one, unless the code quits
+fn run_each_line(ctx : &mut Context, function : &str, format : MessageFormat) -> Result<(), BirlError> {
+    let id = match ctx.symbol_table().function(function) {
+        Some(f) if f.kind == FunctionKind::Source && f.parameters == [TypeKind::Text] => f.id,
+        Some(_) => {
+            report_error("--cada-linha", &format!("A função {} tem que ser escrita em BIRL e receber só uma FIBRA", function), format);
+            exit(-1);
+        }
+        None => {
+            report_error("--cada-linha", &format!("Função {} não encontrada", function), format);
             exit(-1);
         }
+    };
+
+    // Not locked, so the code can read from stdin too, taking the next lines
+    let input = std::io::stdin();
+
+    loop {
+        let mut line = String::new();
+
+        match input.read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) => return Err(format!("Erro lendo a entrada : {}", e).into()),
+        }
+
+        let line = line.trim_end_matches('\n').trim_end_matches('\r').to_owned();
+
+        if let ExecutionStatus::Quit = ctx.run_function(id, vec![RawValue::Text(line)])? {
+            return Ok(());
+        }
+    }
+
+    if ctx.has_main() {
+        ctx.run_function(BIRL_MAIN_FUNCTION_ID, vec![])?;
     }
 
+    Ok(())
+}
+
+/// Print the instructions of every compiled function
+fn print_bytecode(ctx : &Context) {
+    let style = Style::for_stdout();
+
+    for function in ctx.disassemble() {
+        println!("{}", style.bold(&format!("== {} ({}) ==", function.name, function.id)));
+
+        for instruction in function.instructions {
+            // The line of the source the next instructions came from, when it changes
+            if let Some(line) = instruction.line {
+                println!("{}", style.gutter(&format!("     ; linha {}", line)));
+            }
+
+            let text = highlight_debug(&style, &instruction.text);
+
+            match instruction.note {
+                // The padding is counted without the colors
+                Some(note) => println!("{} {}{} {}", style.gutter(&format!("{:>4}", instruction.index)), text,
+                                       " ".repeat(32usize.saturating_sub(instruction.text.chars().count())),
+                                       style.gutter(&format!("; {}", note))),
+                None => println!("{} {}", style.gutter(&format!("{:>4}", instruction.index)), text),
+            }
+        }
+    }
+}
+
+fn main() {
+	let args = get_params();
+	let mut interactive = false;
+    let mut with_stdlib = true;
+    let mut files = vec![];
+    let mut strings = vec![];
+    let mut strict = false;
+    let mut arena = false;
+    let mut inlining = true;
+    let mut optimize = false;
+    let mut emit_ast = false;
+    let mut emit_bytecode = false;
+    let mut compile = false;
+    let mut message_format = MessageFormat::Human;
+    let mut integer_width = IntegerWidth::native();
+    let mut overflow_policy = OverflowPolicy::Wrap;
+    let mut decimal_separator = DecimalSeparator::Point;
+    let mut random_seed = None;
+    let mut max_call_depth = None;
+    let mut auto_flush = None;
+    let mut with_rc_file = true;
+    let mut test = false;
+    let mut snapshot = false;
+    let mut update_snapshots = false;
+    let mut detect_leaks = false;
+    let mut each_line = None;
+
 	if args.len() > 0 {
 		for arg in args {
 			match arg {
@@ -182,7 +605,27 @@ fn main() {
 				Param::Interactive => interactive = true,
 				Param::PrintVersion => Context::print_version(),
                 Param::WithoutStdLib => with_stdlib = false,
-				Param::InputFile(file) => files.push(file),
+                Param::Strict => strict = true,
+                Param::Arena => arena = true,
+                Param::WithoutInlining => inlining = false,
+                Param::Optimize => optimize = true,
+                Param::EmitAst => emit_ast = true,
+                Param::EmitBytecode => emit_bytecode = true,
+                Param::Compile => compile = true,
+                Param::MessageFormat(format) => message_format = format,
+                Param::IntegerWidth(width) => integer_width = width,
+                Param::OverflowPolicy(policy) => overflow_policy = policy,
+                Param::DecimalSeparator(separator) => decimal_separator = separator,
+                Param::RandomSeed(seed) => random_seed = Some(seed),
+                Param::MaxCallDepth(depth) => max_call_depth = Some(depth),
+                Param::AutoFlush(flush) => auto_flush = Some(flush),
+                Param::WithoutRcFile => with_rc_file = false,
+                Param::Test => test = true,
+                Param::Snapshot => snapshot = true,
+                Param::UpdateSnapshots => update_snapshots = true,
+                Param::DetectLeaks => detect_leaks = true,
+                Param::EachLine(function) => each_line = Some(function),
+				Param::InputFile(file) => files.push(resolve_file(file)),
 				Param::StringSource(source) => strings.push(source),
 			}
 		}
@@ -190,6 +633,63 @@ fn main() {
 		interactive = true;
 	}
 
+    if test {
+        // Updating the snapshots only makes sense when they're used
+        let options = TestOptions { with_stdlib, integer_width, overflow_policy, decimal_separator, snapshot : snapshot || update_snapshots, update_snapshots };
+
+        if ! run_tests(&files, &options) {
+            exit(1);
+        }
+
+        return;
+    }
+
+    if emit_ast {
+        print_ast(&files, &strings, message_format);
+
+        if ! emit_bytecode {
+            return;
+        }
+    }
+
+    // The arena never frees memory, so it's only used for programs that run once, not the interactive console
+    let mut builder = VirtualMachineBuilder::new()
+        .arena_allocation(arena && ! interactive)
+        .integer_width(integer_width)
+        .overflow_policy(overflow_policy)
+        .decimal_separator(decimal_separator)
+        .auto_flush(auto_flush)
+        .random_seed(random_seed);
+
+    if let Some(depth) = max_call_depth {
+        builder = builder.max_call_depth(Some(depth).filter(|&d| d > 0));
+    }
+
+	let mut ctx = match Context::with_vm(builder) {
+        Ok(c) => c,
+        Err(e) => {
+            println!("Erro iniciando o contexto : {}", e);
+
+            exit(-1);
+        }
+    };
+
+    if strict {
+        ctx.enable_monster_mode();
+    }
+
+    ctx.set_inlining(inlining);
+    ctx.set_peephole(optimize);
+
+    match ctx.call_function_by_id(BIRL_GLOBAL_FUNCTION_ID, vec![]) {
+        Ok(_) => {}
+        Err(e) => {
+            println!("Erro iniciando o contexto : {}", e);
+
+            exit(-1);
+        }
+    }
+
     if with_stdlib {
         match ctx.add_standard_library() {
             Ok(_) => {}
@@ -200,43 +700,99 @@ fn main() {
         }
     }
 
+    // Errors in the rc file are shown, but don't stop the console from starting
+    if let Some(path) = rc_file().filter(|_| interactive && with_rc_file) {
+        let origin = path.to_string_lossy().into_owned();
+        let result = ctx.add_file(origin.as_str());
+        let source = fs::read_to_string(&path).unwrap_or_default();
+
+        report_warnings(ctx.take_warnings(), origin.as_str(), &source, message_format);
+
+        if let Err(e) = result {
+            report_compile_error(&ctx, origin.as_str(), &source, e, message_format);
+        }
+    }
+
+    // The name of the compiled file comes from the first one
+    let compiled_file = files.first().map(|f| PathBuf::from(f).with_extension(BYTECODE_EXTENSION));
+
     for file in files {
-        match ctx.add_file(file.as_str()) {
+        // Compiled files are loaded as they are, there's no source to show
+        if PathBuf::from(&file).extension().map(|e| e == BYTECODE_EXTENSION).unwrap_or(false) {
+            if let Err(e) = ctx.add_bytecode_file(file.as_str()) {
+                println!("Erro carregando {} : {}", file, e);
+                exit(-1);
+            }
+
+            continue;
+        }
+
+        let result = ctx.add_file(file.as_str());
+        // Only read again to show where the problem is
+        let source = fs::read_to_string(file.as_str()).unwrap_or_default();
+
+        report_warnings(ctx.take_warnings(), file.as_str(), &source, message_format);
+
+        match result {
             Ok(_) => {}
             Err(e) => {
-                println!("Ocorreu um erro ao adicionar o arquivo \"{}\" pro contexto : {}",
-                         file.as_str(), e);
+                report_compile_error(&ctx, file.as_str(), &source, e, message_format);
                 exit(-1);
             }
         }
     }
 
     for source in strings {
-        match ctx.add_source_string(source) {
+        let result = ctx.add_source_string(source.clone());
+
+        report_warnings(ctx.take_warnings(), "<string>", &source, message_format);
+
+        match result {
             Ok(_) => {}
-            Err(e) => {
-                println!("Erro ao adicionar string de código ao contexto : {}", e);
+            Err(e) => report_compile_error(&ctx, "<string>", &source, e, message_format),
+        }
+    }
+
+    if emit_bytecode {
+        print_bytecode(&ctx);
+        return;
+    }
+
+    if compile {
+        let path = match compiled_file {
+            Some(p) => p.to_string_lossy().into_owned(),
+            None => {
+                println!("Passa um arquivo pra compilar, cumpade");
+                exit(-1);
             }
+        };
+
+        if let Err(e) = ctx.save_bytecode(path.as_str()) {
+            println!("{}", e);
+            exit(-1);
         }
+
+        return;
     }
 
 	if interactive {
 		start_interactive_console(&mut ctx);
 	} else {
         /* Bind the Context interpreter to standard IO */
-        let _ = ctx.set_stdin({
-            use std::io;
-            let reader = io::BufReader::new(io::stdin());
-            Some(Box::new(reader))
-        });
-        let _ = ctx.set_stdout({
-            use std::io;
-            Some(Box::new(io::stdout()))
-        });
-
-		match ctx.start_program() {
+        let _ = ctx.set_io(Box::new(StdIo::new()));
+
+        let result = match each_line {
+            Some(function) => run_each_line(&mut ctx, &function, message_format),
+            None => ctx.start_program(),
+        };
+
+		match result {
 			Ok(_) => {}
-			Err(e) => println!("Erro de execução : {}", e),
+			Err(e) => report_runtime_error(e, message_format),
 		}
+
+        if detect_leaks {
+            report_leaks(&ctx, arena);
+        }
 	}
 }
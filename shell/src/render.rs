@@ -0,0 +1,321 @@
+//! Colors and highlighting for what the shell prints on the terminal
+
+use std::env;
+use std::io::{ stdout, stderr, IsTerminal };
+
+use birl::parser::{ parse_line, ParserResult };
+use birl::testing::{ AssertionFailure, DiffLine };
+
+const RESET : &str = "\x1b[0m";
+const BOLD : &str = "\x1b[1m";
+const UNDERLINE : &str = "\x1b[4m";
+const RED : &str = "\x1b[31m";
+const GREEN : &str = "\x1b[32m";
+const YELLOW : &str = "\x1b[33m";
+const BLUE : &str = "\x1b[34m";
+const CYAN : &str = "\x1b[36m";
+
+/// Wraps text in escape codes, or not, if colors are disabled
+#[derive(Clone, Copy)]
+pub struct Style {
+    enabled : bool,
+}
+
+impl Style {
+    /// Colors are only used when the output is a terminal, and NO_COLOR isn't set
+    fn detect(is_terminal : bool) -> Style {
+        Style { enabled : is_terminal && env::var_os("NO_COLOR").is_none() }
+    }
+
+    pub fn for_stdout() -> Style {
+        Style::detect(stdout().is_terminal())
+    }
+
+    pub fn for_stderr() -> Style {
+        Style::detect(stderr().is_terminal())
+    }
+
+    fn paint(&self, codes : &[&str], text : &str) -> String {
+        if ! self.enabled {
+            return text.to_owned();
+        }
+
+        format!("{}{}{}", codes.concat(), text, RESET)
+    }
+
+    pub fn bold(&self, text : &str) -> String {
+        self.paint(&[BOLD], text)
+    }
+
+    pub fn error(&self, text : &str) -> String {
+        self.paint(&[BOLD, RED], text)
+    }
+
+    pub fn warning(&self, text : &str) -> String {
+        self.paint(&[BOLD, YELLOW], text)
+    }
+
+    pub fn gutter(&self, text : &str) -> String {
+        self.paint(&[BOLD, BLUE], text)
+    }
+
+    pub fn string(&self, text : &str) -> String {
+        self.paint(&[GREEN], text)
+    }
+
+    pub fn number(&self, text : &str) -> String {
+        self.paint(&[CYAN], text)
+    }
+
+    pub fn success(&self, text : &str) -> String {
+        self.paint(&[BOLD, GREEN], text)
+    }
+
+    pub fn removed(&self, text : &str) -> String {
+        self.paint(&[RED], text)
+    }
+
+    pub fn added(&self, text : &str) -> String {
+        self.paint(&[GREEN], text)
+    }
+
+    /// Underline text that may already have colors in it
+    pub fn underline(&self, text : &str) -> String {
+        if ! self.enabled {
+            return text.to_owned();
+        }
+
+        // Every reset inside would end the underline too, so it's started again after them
+        let restarted = text.replace(RESET, &format!("{}{}", RESET, UNDERLINE));
+
+        self.paint(&[UNDERLINE], &restarted)
+    }
+}
+
+/// Split a line of source in code and strings (including the quotes), so each can be highlighted differently
+fn split_strings(line : &str) -> Vec<(bool, &str)> {
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (index, c) in line.char_indices() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                parts.push((true, &line[start..index + 1]));
+                start = index + 1;
+                in_string = false;
+            }
+        } else if c == '"' {
+            if start < index {
+                parts.push((false, &line[start..index]));
+            }
+
+            start = index;
+            in_string = true;
+        }
+    }
+
+    if start < line.len() {
+        parts.push((in_string, &line[start..]));
+    }
+
+    parts
+}
+
+/// Highlight numbers in a piece of code that has no strings
+fn highlight_numbers(style : &Style, code : &str) -> String {
+    let mut result = String::new();
+    let mut number = String::new();
+    let mut previous = ' ';
+
+    for c in code.chars() {
+        // Digits that are part of a name (like X1) aren't numbers
+        let starts_number = c.is_ascii_digit() && ! (previous.is_alphanumeric() || previous == '_');
+        let continues_number = ! number.is_empty() && (c.is_ascii_digit() || c == '.');
+
+        if starts_number || continues_number {
+            number.push(c);
+        } else {
+            if ! number.is_empty() {
+                result.push_str(&style.number(&number));
+                number.clear();
+            }
+
+            result.push(c);
+        }
+
+        previous = c;
+    }
+
+    if ! number.is_empty() {
+        result.push_str(&style.number(&number));
+    }
+
+    result
+}
+
+/// Highlight a line of BIRL : the command in bold, strings and numbers colored
+pub fn highlight_source(style : &Style, line : &str) -> String {
+    let is_code = match parse_line(line) {
+        Ok(ParserResult::Nothing) | Err(_) => false,
+        Ok(_) => true,
+    };
+
+    let mut result = String::new();
+    let mut command_done = ! is_code;
+
+    for (is_string, part) in split_strings(line) {
+        if is_string {
+            result.push_str(&style.string(part));
+            continue;
+        }
+
+        if command_done {
+            result.push_str(&highlight_numbers(style, part));
+            continue;
+        }
+
+        // The command goes until the :, or is the whole line if it has no arguments
+        command_done = true;
+
+        match part.find(':') {
+            Some(colon) => {
+                result.push_str(&style.bold(&part[..colon]));
+                result.push_str(&highlight_numbers(style, &part[colon..]));
+            }
+            None => result.push_str(&style.bold(part)),
+        }
+    }
+
+    result
+}
+
+/// Highlight the debug output of the AST or the bytecode : names of variants and types in bold, strings and numbers
+/// colored
+pub fn highlight_debug(style : &Style, text : &str) -> String {
+    let mut result = String::new();
+
+    for (is_string, part) in split_strings(text) {
+        if is_string {
+            result.push_str(&style.string(part));
+            continue;
+        }
+
+        let mut word = String::new();
+
+        let flush_word = |word : &mut String, result : &mut String| {
+            if word.chars().next().map(|c| c.is_uppercase()).unwrap_or(false) {
+                result.push_str(&style.bold(word));
+            } else {
+                result.push_str(&highlight_numbers(style, word));
+            }
+
+            word.clear();
+        };
+
+        for c in part.chars() {
+            if c.is_alphanumeric() || c == '_' || (c == '.' && word.chars().all(|c| c.is_ascii_digit() || c == '.')) {
+                word.push(c);
+            } else {
+                flush_word(&mut word, &mut result);
+                result.push(c);
+            }
+        }
+
+        flush_word(&mut word, &mut result);
+    }
+
+    result
+}
+
+/// Render a message pointing to a line of the source, like
+///
+/// ```text
+/// erro: Variável não encontrada : X
+///  --> arquivo.birl:3
+///   |
+/// 3 | CE QUER VER ISSO: X
+///   | ^^^^^^^^^^^^^^^^^^^
+/// ```
+///
+/// The columns (from 1, the end not included) are the part of the line that's marked, or the whole line if not given
+fn render_at_line(style : &Style, label : &str, origin : &str, line : Option<(usize, &str)>,
+                  columns : Option<(usize, usize)>, message : &str) -> String {
+    let mut result = format!("{}: {}\n", label, style.bold(message));
+
+    match line {
+        Some((number, source)) => {
+            let number = number.to_string();
+            let padding = " ".repeat(number.len());
+            let trimmed = source.trim_end();
+            let indent = trimmed.len() - trimmed.trim_start().len();
+
+            result.push_str(&format!("{}{} {}:{}\n", padding, style.gutter("-->"), origin, number));
+            result.push_str(&format!("{} {}\n", padding, style.gutter("|")));
+            result.push_str(&format!("{} {} {}{}\n", style.gutter(&number), style.gutter("|"), &trimmed[..indent],
+                                     style.underline(&highlight_source(style, &trimmed[indent..]))));
+            let (marker_indent, marker_length) = match columns {
+                // Tabs are kept, so the marker lines up with the line above it
+                Some((start, end)) => (trimmed.chars().take(start.saturating_sub(1))
+                                           .map(|c| if c == '\t' { '\t' } else { ' ' }).collect::<String>(),
+                                       end.saturating_sub(start)),
+                None => (trimmed[..indent].to_owned(), trimmed.trim_start().chars().count()),
+            };
+
+            result.push_str(&format!("{} {} {}{}", padding, style.gutter("|"), marker_indent,
+                                     style.error(&"^".repeat(marker_length.max(1)))));
+        }
+        None => result.push_str(&format!("{} {}", style.gutter("-->"), origin)),
+    }
+
+    result
+}
+
+pub fn render_error(style : &Style, origin : &str, line : Option<(usize, &str)>, message : &str) -> String {
+    render_at_line(style, &style.error("erro"), origin, line, None, message)
+}
+
+pub fn render_warning(style : &Style, origin : &str, line : Option<(usize, &str)>, columns : Option<(usize, usize)>,
+                      message : &str) -> String {
+    render_at_line(style, &style.warning("aviso"), origin, line, columns, message)
+}
+
+/// Lines of a diff, what was expected marked with - and what was found marked with +
+fn render_diff_lines(style : &Style, diff : Vec<DiffLine>) -> String {
+    let mut result = String::new();
+
+    for line in diff {
+        result.push('\n');
+
+        match line {
+            DiffLine::Same(l) => result.push_str(&format!("    {}", l)),
+            DiffLine::Expected(l) => result.push_str(&style.removed(&format!("  - {}", l))),
+            DiffLine::Actual(l) => result.push_str(&style.added(&format!("  + {}", l))),
+        }
+    }
+
+    result
+}
+
+/// Render a failed TEM QUE SER. Values with a diff (texts with many lines and lists) are shown line by line
+pub fn render_assertion_failure(style : &Style, failure : &AssertionFailure) -> String {
+    let diff = match failure.diff() {
+        Some(d) => d,
+        None => return format!("{}\n  {} {}\n  {} {}", style.bold("O valor não é o esperado"),
+                               style.removed("esperado :"), failure.expected, style.added("recebido :"), failure.actual),
+    };
+
+    format!("{} ({}, {}){}", style.bold("O valor não é o esperado"), style.removed("- esperado"),
+            style.added("+ recebido"), render_diff_lines(style, diff))
+}
+
+/// Render the difference between the output of a test and its snapshot
+pub fn render_snapshot_diff(style : &Style, snapshot : &str, diff : Vec<DiffLine>) -> String {
+    format!("{} {} ({}, {}){}", style.bold("A saída mudou desde o snapshot"), snapshot, style.removed("- snapshot"),
+            style.added("+ saída"), render_diff_lines(style, diff))
+}
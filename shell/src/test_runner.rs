@@ -0,0 +1,177 @@
+//! The `birl test` subcommand : runs every function whose name starts with TESTE (or that has a `FICHA: TESTE`) in the
+//! given files, each one in a new context, and shows which ones failed and why. With --snapshot, what each test prints
+//! is also compared with what it printed before, kept in a .snap file
+
+use birl::context::{ Context, BIRL_GLOBAL_FUNCTION_ID };
+use birl::testing::{ is_test, snapshot_path, compare_snapshot };
+use birl::vm::{ VirtualMachineBuilder, IntegerWidth, OverflowPolicy, DecimalSeparator };
+use birl::vm_io::CaptureIo;
+
+use render::{ Style, render_assertion_failure, render_snapshot_diff };
+
+use std::fs;
+use std::path::Path;
+
+pub struct TestOptions {
+    pub with_stdlib : bool,
+    pub integer_width : IntegerWidth,
+    pub overflow_policy : OverflowPolicy,
+    pub decimal_separator : DecimalSeparator,
+    /// Compare the output of each test with its snapshot (and record it, if there's none)
+    pub snapshot : bool,
+    /// Replace the snapshots that don't match instead of failing
+    pub update_snapshots : bool,
+}
+
+enum TestOutcome {
+    /// With a note about the snapshot, if one was written
+    Passed(Option<&'static str>),
+    /// Why it failed, already rendered, and what the test printed before failing
+    Failed(String, String),
+}
+
+fn new_context(file : &str, options : &TestOptions) -> Result<Context, String> {
+    let builder = VirtualMachineBuilder::new()
+        .integer_width(options.integer_width)
+        .overflow_policy(options.overflow_policy)
+        .decimal_separator(options.decimal_separator);
+    let mut ctx = Context::with_vm(builder)?;
+
+    ctx.call_function_by_id(BIRL_GLOBAL_FUNCTION_ID, vec![])?;
+
+    if options.with_stdlib {
+        ctx.add_standard_library()?;
+    }
+
+    ctx.add_file(file).map_err(|e| e.to_string())?;
+
+    Ok(ctx)
+}
+
+/// Compare the output of a test that passed with its snapshot
+fn check_snapshot(file : &str, name : &str, output : String, options : &TestOptions, style : &Style) -> TestOutcome {
+    let path = snapshot_path(Path::new(file), name);
+
+    let write = |note| {
+        let written = match path.parent() {
+            Some(folder) => fs::create_dir_all(folder).and_then(|_| fs::write(&path, &output)),
+            None => fs::write(&path, &output),
+        };
+
+        match written {
+            Ok(_) => TestOutcome::Passed(Some(note)),
+            Err(e) => TestOutcome::Failed(format!("Não deu pra escrever o snapshot {} : {}", path.display(), e), String::new()),
+        }
+    };
+
+    let snapshot = match fs::read_to_string(&path) {
+        Ok(s) => s,
+        Err(_) => return write("snapshot novo"),
+    };
+
+    match compare_snapshot(&snapshot, &output) {
+        None => TestOutcome::Passed(None),
+        Some(_) if options.update_snapshots => write("snapshot atualizado"),
+        Some(diff) => TestOutcome::Failed(render_snapshot_diff(style, &path.display().to_string(), diff), String::new()),
+    }
+}
+
+fn run_test(file : &str, id : usize, name : &str, options : &TestOptions, style : &Style) -> TestOutcome {
+    let mut ctx = match new_context(file, options) {
+        Ok(c) => c,
+        Err(e) => return TestOutcome::Failed(e, String::new()),
+    };
+
+    // What the test prints isn't shown unless it fails
+    let io = CaptureIo::new("");
+    let output = io.output();
+    let _ = ctx.set_io(Box::new(io));
+
+    let result = ctx.start_function(id);
+    let output = output.borrow().clone();
+
+    match result {
+        Ok(_) if options.snapshot => check_snapshot(file, name, output, options, style),
+        Ok(_) => TestOutcome::Passed(None),
+        Err(e) => match ctx.take_assertion_failure() {
+            Some(failure) => TestOutcome::Failed(render_assertion_failure(style, &failure), output),
+            None => TestOutcome::Failed(format!("{} : {}", style.error("Erro de execução"), e), output),
+        },
+    }
+}
+
+/// Run the tests in every file and print the results. Returns whether all of them passed
+pub fn run_tests(files : &[String], options : &TestOptions) -> bool {
+    let style = Style::for_stdout();
+    let mut passed = 0usize;
+    let mut failed = 0usize;
+
+    for file in files {
+        // Compiled once just to find the tests. Each test is compiled again so they don't share variables
+        let tests = match new_context(file, options) {
+            Ok(ctx) => {
+                let mut tests = ctx.symbol_table().functions.into_iter()
+                    .filter(is_test)
+                    .map(|f| (f.id, f.name))
+                    .collect::<Vec<(usize, String)>>();
+
+                // In the order they were written
+                tests.sort();
+
+                tests
+            }
+            Err(e) => {
+                println!("{} {} : {}", style.error("erro"), file, e);
+                failed += 1;
+                continue;
+            }
+        };
+
+        if tests.is_empty() {
+            println!("{} : nenhum teste (funções que começam com TESTE ou com FICHA: TESTE)", file);
+            continue;
+        }
+
+        println!("{} : {} teste(s)", style.bold(file), tests.len());
+
+        for (id, name) in tests {
+            match run_test(file, id, &name, options, &style) {
+                TestOutcome::Passed(None) => {
+                    println!("  {} ... {}", name, style.success("ok"));
+                    passed += 1;
+                }
+                TestOutcome::Passed(Some(note)) => {
+                    println!("  {} ... {} ({})", name, style.success("ok"), note);
+                    passed += 1;
+                }
+                TestOutcome::Failed(reason, output) => {
+                    println!("  {} ... {}", name, style.error("FALHOU"));
+
+                    for line in reason.lines() {
+                        println!("    {}", line);
+                    }
+
+                    if ! output.is_empty() {
+                        println!("    {}", style.bold("Saída do teste :"));
+
+                        for line in output.lines() {
+                            println!("    | {}", line);
+                        }
+                    }
+
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    let summary = format!("{} passaram, {} falharam", passed, failed);
+
+    if failed == 0 {
+        println!("\n{} : {}", style.success("resultado"), summary);
+    } else {
+        println!("\n{} : {}", style.error("resultado"), summary);
+    }
+
+    failed == 0
+}